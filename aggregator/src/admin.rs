@@ -0,0 +1,260 @@
+//! Local admin socket
+//!
+//! A running aggregator listens on a Unix socket for routine operations, so
+//! they don't need psql or redis-cli: persisting now, flushing flows out of
+//! memory, reading state statistics, purging a device and changing the log
+//! filter. Each connection carries one JSON request line and gets one JSON
+//! response line back; the `force-persist`, `flush-flows`, `show-stats`,
+//! `purge-device` and `log-level` subcommands are the client side. Access
+//! is limited to the socket owner.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::config::AdminConfig;
+use crate::logging::LogFilter;
+use crate::pipeline::PersistRequest;
+use crate::state::{AggregatorState, MacAddr};
+
+/// Longest request line accepted
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+/// Operation asked of a running instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// Persist the whole state now
+    ForcePersist,
+    /// Persist, then evict the persisted flows from memory
+    FlushFlows,
+    /// State sizes and counters
+    ShowStats,
+    /// Forget a MAC address on every site, in memory and in the database
+    PurgeDevice { mac: String },
+    /// Print the log filter, or apply directives on top of the configured one
+    LogLevel {
+        #[serde(default)]
+        filter: Option<String>,
+    },
+}
+
+/// Outcome of a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminResponse {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+/// Serves admin requests on the local socket
+pub struct AdminServer {
+    config: AdminConfig,
+    state: Arc<AggregatorState>,
+    persister: mpsc::Sender<PersistRequest>,
+    log_filter: Option<LogFilter>,
+}
+
+impl AdminServer {
+    /// Create a server handing persist, flush and purge requests to `persister`
+    pub fn new(config: AdminConfig, state: Arc<AggregatorState>, persister: mpsc::Sender<PersistRequest>) -> Self {
+        Self { config, state, persister, log_filter: None }
+    }
+
+    /// Serve log filter changes through `log_filter`
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Accept connections until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        let path = &self.config.socket;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        // A socket left behind by an instance that did not stop cleanly
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                anyhow::bail!("Admin socket {:?} is in use by another instance", path);
+            }
+            std::fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {:?}", path))?;
+        }
+
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind admin socket {:?}", path))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict admin socket {:?}", path))?;
+        info!("Admin socket listening on {:?}", path);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                accepted = listener.accept() => {
+                    let (stream, _) = match accepted {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            warn!("Admin socket accept failed: {}", e);
+                            continue;
+                        }
+                    };
+                    let state = Arc::clone(&self.state);
+                    let persister = self.persister.clone();
+                    let log_filter = self.log_filter.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &state, &persister, log_filter.as_ref()).await {
+                            debug!("Admin connection failed: {:#}", e);
+                        }
+                    });
+                }
+            }
+        }
+
+        let _ = std::fs::remove_file(path);
+        info!("Admin socket stopped");
+        Ok(())
+    }
+}
+
+/// Read one request from `stream` and write its response
+async fn handle(
+    stream: UnixStream,
+    state: &AggregatorState,
+    persister: &mpsc::Sender<PersistRequest>,
+    log_filter: Option<&LogFilter>,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read.take(MAX_REQUEST_BYTES)).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<AdminRequest>(&line) {
+        Ok(request) => {
+            info!("Admin request: {:?}", request);
+            match answer(request, state, persister, log_filter).await {
+                Ok(value) => AdminResponse::Ok(value),
+                Err(e) => AdminResponse::Error(format!("{:#}", e)),
+            }
+        }
+        Err(e) => AdminResponse::Error(format!("Invalid request: {}", e)),
+    };
+
+    let mut body = serde_json::to_vec(&response)?;
+    body.push(b'\n');
+    write.write_all(&body).await?;
+    Ok(())
+}
+
+/// Carry out `request`
+async fn answer(
+    request: AdminRequest,
+    state: &AggregatorState,
+    persister: &mpsc::Sender<PersistRequest>,
+    log_filter: Option<&LogFilter>,
+) -> Result<serde_json::Value> {
+    match request {
+        AdminRequest::ShowStats => Ok(serde_json::to_value(state.stats_snapshot())?),
+        AdminRequest::ForcePersist => {
+            let report = ask(persister, PersistRequest::Persist).await?;
+            let mut value = serde_json::to_value(&report)?;
+            value["elapsed_ms"] = (report.elapsed.as_millis() as u64).into();
+            Ok(value)
+        }
+        AdminRequest::FlushFlows => {
+            let evicted = ask(persister, PersistRequest::FlushFlows).await?;
+            Ok(serde_json::json!({ "evicted_flows": evicted }))
+        }
+        AdminRequest::PurgeDevice { mac } => {
+            let mac = MacAddr::from_string(&mac).with_context(|| format!("Invalid MAC address '{}'", mac))?;
+            let report = ask(persister, |reply| PersistRequest::PurgeDevice(mac, reply)).await?;
+            Ok(serde_json::to_value(report)?)
+        }
+        AdminRequest::LogLevel { filter } => {
+            let log_filter = log_filter.context("The log filter cannot be changed in this process")?;
+            let current = match filter {
+                Some(directives) => log_filter.set(&directives)?,
+                None => log_filter.current()?,
+            };
+            Ok(serde_json::json!({ "filter": current }))
+        }
+    }
+}
+
+/// Send a request to the persister and wait for its outcome
+async fn ask<T>(
+    persister: &mpsc::Sender<PersistRequest>,
+    request: impl FnOnce(oneshot::Sender<Result<T>>) -> PersistRequest,
+) -> Result<T> {
+    let (reply, outcome) = oneshot::channel();
+    persister.send(request(reply)).await.ok().context("The persister is not running")?;
+    outcome.await.context("The persister stopped before answering")?
+}
+
+/// Send `request` to the instance listening on `socket`
+pub async fn send(socket: &Path, request: &AdminRequest) -> Result<serde_json::Value> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("Failed to connect to {:?}; is the aggregator running with [admin] enabled?", socket))?;
+    let (read, mut write) = stream.into_split();
+
+    let mut body = serde_json::to_vec(request)?;
+    body.push(b'\n');
+    write.write_all(&body).await?;
+
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+    match serde_json::from_str(&line).with_context(|| "Invalid response from the admin socket")? {
+        AdminResponse::Ok(value) => Ok(value),
+        AdminResponse::Error(message) => anyhow::bail!(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!("netsentinel-admin-{}", std::process::id()));
+        let config = AdminConfig { enabled: true, socket: dir.join("admin.sock") };
+        let state = Arc::new(AggregatorState::new());
+        let (persister, mut requests) = mpsc::channel(1);
+        let (shutdown_tx, shutdown) = broadcast::channel(1);
+        let server = tokio::spawn(AdminServer::new(config.clone(), state, persister).run(shutdown));
+
+        // A stand-in persister answering flushes
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                if let PersistRequest::FlushFlows(reply) = request {
+                    let _ = reply.send(Ok(3));
+                }
+            }
+        });
+
+        let mut stats = Err(anyhow::anyhow!("not started"));
+        for _ in 0..50 {
+            stats = send(&config.socket, &AdminRequest::ShowStats).await;
+            if stats.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(stats.unwrap()["total_devices"], 0);
+
+        let flushed = send(&config.socket, &AdminRequest::FlushFlows).await.unwrap();
+        assert_eq!(flushed["evicted_flows"], 3);
+
+        let purge = AdminRequest::PurgeDevice { mac: "not-a-mac".to_string() };
+        let error = send(&config.socket, &purge).await.unwrap_err();
+        assert!(error.to_string().contains("Invalid MAC address"));
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!config.socket.exists());
+        let _ = std::fs::remove_dir(&dir);
+    }
+}
@@ -0,0 +1,342 @@
+//! Alert lifecycle
+//!
+//! Every alert, whichever subsystem raised it, goes through the
+//! [`AlertManager`] before reaching the notification sinks. Alerts are
+//! identified by a fingerprint (name, site, MAC and IP), so one that keeps firing
+//! while unresolved is recorded as a further occurrence of the same row in
+//! the `alerts` table rather than a new one, and is only notified again once
+//! the re-notify interval has passed. Acknowledged alerts are recorded but
+//! no longer notified, and alerts firing inside a maintenance window, or
+//! about a device under maintenance through the API, are recorded without
+//! being notified at all.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Uuid;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::{AlertsConfig, MaintenanceWindow};
+use crate::db::Database;
+use crate::events::{self, Event, EventSender};
+use crate::state::{AggregatorState, DeviceKey, MacAddr, SiteId, DEFAULT_SITE};
+
+/// How often stale alerts are checked for auto-resolution
+const AUTO_RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Alert lifecycle state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Open,
+    Acknowledged,
+    Resolved,
+}
+
+impl AlertStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertStatus::Open => "open",
+            AlertStatus::Acknowledged => "acknowledged",
+            AlertStatus::Resolved => "resolved",
+        }
+    }
+}
+
+impl FromStr for AlertStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "open" => Ok(AlertStatus::Open),
+            "acknowledged" => Ok(AlertStatus::Acknowledged),
+            "resolved" => Ok(AlertStatus::Resolved),
+            other => anyhow::bail!("Unknown alert status '{}'", other),
+        }
+    }
+}
+
+/// State of an alert row after recording an occurrence
+#[derive(Debug, Clone)]
+pub struct AlertRecord {
+    pub id: Uuid,
+    pub status: AlertStatus,
+    pub occurrences: u64,
+    pub last_notified: Option<DateTime<Utc>>,
+}
+
+/// Identity of an alert: the same name for the same device is one alert
+///
+/// The default site is left out of the hash so alerts raised before sites
+/// existed keep their fingerprint; likewise the sensor is only hashed for
+/// alerts about a sensor.
+pub fn fingerprint(event: &Event) -> Option<String> {
+    let Event::Alert { name, site, sensor, mac, ip, .. } = event else {
+        return None;
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    hasher.update([0]);
+    if let Some(site) = site.as_deref().filter(|site| *site != DEFAULT_SITE) {
+        hasher.update(site.as_bytes());
+        hasher.update([0]);
+    }
+    if let Some(sensor) = sensor {
+        hasher.update(b"sensor:");
+        hasher.update(sensor.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(mac.as_deref().unwrap_or_default().to_ascii_lowercase().as_bytes());
+    hasher.update([0]);
+    hasher.update(ip.map(|ip| ip.octets()).unwrap_or_default());
+    Some(hasher.finalize().iter().take(16).map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Whether an occurrence of `record` at `now` should be notified
+fn should_notify(record: &AlertRecord, now: DateTime<Utc>, renotify_interval_secs: u64) -> bool {
+    if record.status != AlertStatus::Open {
+        return false;
+    }
+    match record.last_notified {
+        None => true,
+        Some(_) if renotify_interval_secs == 0 => false,
+        Some(at) => (now - at).num_seconds() >= renotify_interval_secs as i64,
+    }
+}
+
+/// Whether `window` is in effect at `at`
+fn window_active(window: &MaintenanceWindow, at: DateTime<Utc>) -> bool {
+    if let (Some(start), Some(end)) = (window.start, window.end) {
+        return start <= at && at < end;
+    }
+    let (Some(from), Some(to)) = (window.from, window.to) else {
+        return false;
+    };
+
+    daily_window_active(&window.days, from, to, at)
+}
+
+/// Whether the daily `from`..`to` window (UTC, starting on `days`, every day
+/// when empty) is open at `at`; a window whose `to` is before its `from`
+/// runs past midnight
+pub fn daily_window_active(days: &[Weekday], from: NaiveTime, to: NaiveTime, at: DateTime<Utc>) -> bool {
+    let time = at.time();
+    let starts_on = |day| days.is_empty() || days.contains(&day);
+    if from <= to {
+        starts_on(at.weekday()) && from <= time && time < to
+    } else {
+        // Runs past midnight: either today's window or the one that started yesterday
+        (starts_on(at.weekday()) && time >= from) || (starts_on(at.weekday().pred()) && time < to)
+    }
+}
+
+/// Whether `window` covers `event`
+fn window_covers(window: &MaintenanceWindow, event: &Event, at: DateTime<Utc>) -> bool {
+    let Event::Alert { name, mac, .. } = event else {
+        return false;
+    };
+    if !window.alerts.is_empty() && !window.alerts.contains(name) {
+        return false;
+    }
+    if !window.mac.is_empty() {
+        let Some(mac) = mac.as_deref().map(str::to_ascii_lowercase) else {
+            return false;
+        };
+        if !window.mac.iter().any(|prefix| mac.starts_with(&prefix.to_ascii_lowercase().replace('-', ":"))) {
+            return false;
+        }
+    }
+
+    window_active(window, at)
+}
+
+/// Deduplicates, records and gates alerts on their way to the sinks
+pub struct AlertManager {
+    config: AlertsConfig,
+    db: Arc<Database>,
+    state: Option<Arc<AggregatorState>>,
+}
+
+impl AlertManager {
+    pub fn new(config: AlertsConfig, db: Arc<Database>) -> Self {
+        Self { config, db, state: None }
+    }
+
+    /// Hold back the alerts about the devices under maintenance in `state`
+    pub fn with_state(mut self, state: Arc<AggregatorState>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Maintenance window covering `event` at `at`, if any
+    fn maintenance(&self, event: &Event, at: DateTime<Utc>) -> Option<&MaintenanceWindow> {
+        self.config.maintenance.iter().find(|w| window_covers(w, event, at))
+    }
+
+    /// Name of the device maintenance covering `event` at `at`, if any
+    fn device_maintenance(&self, event: &Event, at: DateTime<Utc>) -> Option<String> {
+        let (state, Event::Alert { site, mac, .. }) = (self.state.as_ref()?, event) else {
+            return None;
+        };
+        let site = SiteId::new(site.as_deref().unwrap_or(DEFAULT_SITE))?;
+        let mac = mac.as_deref().and_then(MacAddr::from_string)?;
+        state.maintenance_of(DeviceKey::new(site, mac), at).map(|entry| format!("maintenance {}", entry.id))
+    }
+
+    /// Record `event` and forward it to `output` if it is due a notification
+    async fn handle(&self, event: Event, output: &EventSender) {
+        let Some(fingerprint) = fingerprint(&event) else {
+            return;
+        };
+        let now = Utc::now();
+        let window = self.maintenance(&event, now)
+            .map(|w| w.name.clone())
+            .or_else(|| self.device_maintenance(&event, now));
+        let window = window.as_deref();
+
+        let record = match self.db.record_alert(&event, &fingerprint, window).await {
+            Ok(record) => record,
+            Err(e) => {
+                // Better a duplicate notification than a lost alert
+                warn!("{:#}", e);
+                None
+            }
+        };
+
+        if let Some(window) = window {
+            debug!("{} suppressed by maintenance '{}'", event.summary(), window);
+            return;
+        }
+        if let Some(record) = &record {
+            if !should_notify(record, now, self.config.renotify_interval_secs) {
+                debug!("{} already notified ({} occurrences, {:?})", event.summary(), record.occurrences, record.status);
+                return;
+            }
+            if let Err(e) = self.db.mark_alert_notified(record.id, now).await {
+                warn!("{:#}", e);
+            }
+        }
+
+        let _ = output.send(event);
+    }
+
+    async fn auto_resolve(&self) {
+        match self.db.resolve_stale_alerts(self.config.auto_resolve_secs).await {
+            Ok(0) => {}
+            Ok(n) => info!("Resolved {} alerts idle for {}s", n, self.config.auto_resolve_secs),
+            Err(e) => warn!("{:#}", e),
+        }
+    }
+
+    /// Handle alerts until shutdown, then those still queued
+    pub async fn run(self, mut alerts: broadcast::Receiver<Event>, output: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        let mut ticker = tokio::time::interval(AUTO_RESOLVE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let auto_resolve = self.config.auto_resolve_secs > 0;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick(), if auto_resolve => self.auto_resolve().await,
+                event = events::recv(&mut alerts, "Alert manager") => {
+                    let Some(event) = event else { break };
+                    self.handle(event, &output).await;
+                }
+            }
+        }
+
+        for event in events::drain(&mut alerts) {
+            self.handle(event, &output).await;
+        }
+
+        debug!("Alert manager stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use chrono::TimeZone;
+
+    fn alert(name: &str, mac: &str) -> Event {
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: Severity::High,
+            name: name.to_string(),
+            message: "test".to_string(),
+            site: None,
+            sensor: None,
+            mac: Some(mac.to_string()),
+            ip: None,
+            channels: Vec::new(),
+            details: None,
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_and_renotify() {
+        assert_eq!(fingerprint(&alert("scan", "00:11:22:33:44:55")), fingerprint(&alert("scan", "00:11:22:33:44:55")));
+        assert_ne!(fingerprint(&alert("scan", "00:11:22:33:44:55")), fingerprint(&alert("scan", "00:11:22:33:44:56")));
+        assert_ne!(fingerprint(&alert("scan", "00:11:22:33:44:55")), fingerprint(&alert("flood", "00:11:22:33:44:55")));
+
+        let at_site = |site: &str| {
+            let mut event = alert("scan", "00:11:22:33:44:55");
+            if let Event::Alert { site: s, .. } = &mut event {
+                *s = Some(site.to_string());
+            }
+            fingerprint(&event)
+        };
+        assert_eq!(at_site(DEFAULT_SITE), fingerprint(&alert("scan", "00:11:22:33:44:55")));
+        assert_ne!(at_site("lyon"), at_site(DEFAULT_SITE));
+
+        let now = Utc::now();
+        let mut record = AlertRecord { id: Uuid::new_v4(), status: AlertStatus::Open, occurrences: 1, last_notified: None };
+        assert!(should_notify(&record, now, 3600));
+
+        record.last_notified = Some(now - chrono::Duration::minutes(10));
+        assert!(!should_notify(&record, now, 3600));
+        assert!(should_notify(&record, now, 600));
+        assert!(!should_notify(&record, now, 0));
+
+        record.status = AlertStatus::Acknowledged;
+        assert!(!should_notify(&record, now, 600));
+    }
+
+    #[test]
+    fn test_maintenance_windows() {
+        let config: AlertsConfig = toml::from_str(r#"
+[[maintenance]]
+name = "upgrade"
+start = "2026-03-01T20:00:00Z"
+end = "2026-03-01T22:00:00Z"
+mac = ["00-11-22"]
+
+[[maintenance]]
+name = "nightly-backup"
+days = ["sat"]
+from = "23:00"
+to = "02:00"
+alerts = ["bulk-transfer"]
+"#).unwrap();
+        let [upgrade, backup] = &config.maintenance[..] else { panic!() };
+        let at = |d, h, m| Utc.with_ymd_and_hms(2026, 3, d, h, m, 0).unwrap();
+
+        // 2026-03-01 is a Sunday
+        assert!(window_covers(upgrade, &alert("scan", "00:11:22:33:44:55"), at(1, 21, 0)));
+        assert!(!window_covers(upgrade, &alert("scan", "00:11:22:33:44:55"), at(1, 22, 0)));
+        assert!(!window_covers(upgrade, &alert("scan", "aa:11:22:33:44:55"), at(1, 21, 0)));
+
+        // Saturday night into Sunday morning
+        assert!(window_covers(backup, &alert("bulk-transfer", "aa:bb:cc:00:00:01"), at(7, 23, 30)));
+        assert!(window_covers(backup, &alert("bulk-transfer", "aa:bb:cc:00:00:01"), at(1, 1, 30)));
+        assert!(!window_covers(backup, &alert("bulk-transfer", "aa:bb:cc:00:00:01"), at(2, 1, 30)));
+        assert!(!window_covers(backup, &alert("scan", "aa:bb:cc:00:00:01"), at(7, 23, 30)));
+    }
+}
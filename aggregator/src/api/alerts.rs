@@ -0,0 +1,102 @@
+//! Alert endpoints
+
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::alerts::AlertStatus;
+use crate::db::{AlertFilter, StoredAlert};
+
+/// Body of the acknowledge and resolve requests
+#[derive(Debug, Default, Deserialize)]
+pub struct StatusChange {
+    /// Who made the change
+    pub by: Option<String>,
+    pub note: Option<String>,
+}
+
+/// `GET /api/alerts`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<AlertFilter>,
+) -> Result<Json<Page<StoredAlert>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (alerts, total) = api.db
+        .list_alerts(&filter, pagination.limit(), pagination.cursor()?)
+        .await?;
+    Ok(Json(pagination.wrap_keyed(alerts, total)))
+}
+
+/// `GET /api/alerts/{id}`
+pub async fn get(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(id): Path<String>,
+) -> Result<Json<StoredAlert>, ApiError> {
+    let id = parse_id(&id)?;
+    find(&api, scope, id).await.map(Json)
+}
+
+/// `POST /api/alerts/{id}/acknowledge`
+///
+/// Acknowledged alerts keep being recorded but are no longer notified.
+pub async fn acknowledge(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(id): Path<String>,
+    body: Option<Json<StatusChange>>,
+) -> Result<Json<StoredAlert>, ApiError> {
+    change_status(api, scope, &id, AlertStatus::Acknowledged, body).await
+}
+
+/// `POST /api/alerts/{id}/resolve`
+///
+/// The next occurrence of a resolved alert opens a new one.
+pub async fn resolve(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(id): Path<String>,
+    body: Option<Json<StatusChange>>,
+) -> Result<Json<StoredAlert>, ApiError> {
+    change_status(api, scope, &id, AlertStatus::Resolved, body).await
+}
+
+async fn change_status(
+    api: ApiState,
+    scope: Scope,
+    id: &str,
+    status: AlertStatus,
+    body: Option<Json<StatusChange>>,
+) -> Result<Json<StoredAlert>, ApiError> {
+    let id = parse_id(id)?;
+    let change = body.map(|Json(change)| change).unwrap_or_default();
+    find(&api, scope, id).await?;
+
+    let changed = api.db
+        .set_alert_status(id, status, change.by.as_deref(), change.note.as_deref())
+        .await?;
+    let alert = api.db.get_alert(id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Alert {} not found", id)))?;
+    if !changed {
+        return Err(ApiError::BadRequest(format!("Alert {} is already {}", id, alert.status.as_str())));
+    }
+
+    Ok(Json(alert))
+}
+
+/// Alert `id`, not found unless visible in `scope`
+async fn find(api: &ApiState, scope: Scope, id: Uuid) -> Result<StoredAlert, ApiError> {
+    api.db.get_alert(id)
+        .await?
+        .filter(|alert| scope.sees(&api.state.tenants, alert.site.as_deref()))
+        .ok_or_else(|| ApiError::NotFound(format!("Alert {} not found", id)))
+}
+
+fn parse_id(id: &str) -> Result<Uuid, ApiError> {
+    id.parse().map_err(|_| ApiError::BadRequest(format!("Invalid alert ID '{}'", id)))
+}
@@ -0,0 +1,246 @@
+//! API authentication and authorization
+//!
+//! Once any token or OIDC provider is configured, requests need an
+//! `Authorization: Bearer` header with a configured token or an access token
+//! of the provider. Each grants a scope and a role. Admin-wide tokens see
+//! every tenant; a tenant's tokens see the tenant's sites only: lists are
+//! narrowed to them, objects of other tenants are not found, and
+//! aggregator-wide endpoints (external scanners, stream position, purges)
+//! are refused. Read-only tokens may only read: alert changes, annotations,
+//! maintenance and purges are refused. Without tokens the API is open, with full
+//! access to every tenant.
+
+use anyhow::Result;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::debug;
+
+use super::limit::RateLimiter;
+use super::oidc::Oidc;
+use super::{ApiError, ApiState};
+use crate::config::{ApiConfig, TenantConfig};
+use crate::state::{TenantId, Tenants};
+
+/// What a request may do within its scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Read the inventory
+    Read,
+    /// Also acknowledge and resolve alerts, annotate, purge and put devices
+    /// under maintenance
+    Admin,
+}
+
+/// What a request has access to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Every tenant
+    All,
+    /// The sites of one tenant
+    Tenant(TenantId),
+}
+
+impl Scope {
+    /// Name of the tenant the request is restricted to, `None` for every tenant
+    pub fn tenant(&self) -> Option<String> {
+        match self {
+            Scope::All => None,
+            Scope::Tenant(tenant) => Some(tenant.to_string()),
+        }
+    }
+
+    /// Whether what is kept for `tenant` is visible
+    pub fn includes(&self, tenant: TenantId) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Tenant(scope) => *scope == tenant,
+        }
+    }
+
+    /// Whether what is kept for `site` (no site: aggregator-wide) is visible
+    pub fn sees(&self, tenants: &Tenants, site: Option<&str>) -> bool {
+        self.includes(site.map_or_else(TenantId::default, |site| tenants.tenant_of_name(site)))
+    }
+
+    /// Refuse tenant-restricted requests to aggregator-wide endpoints
+    pub fn require_all(&self) -> Result<(), ApiError> {
+        match self {
+            Scope::All => Ok(()),
+            Scope::Tenant(_) => Err(ApiError::Forbidden("not available to tenant tokens".to_string())),
+        }
+    }
+}
+
+/// Access granted by each API token
+#[derive(Clone, Default)]
+pub struct ApiTokens {
+    tokens: HashMap<String, (Scope, Role)>,
+    oidc: Option<Arc<Oidc>>,
+}
+
+impl ApiTokens {
+    /// Tokens of `config` and of each tenant, and the OIDC provider of
+    /// `config`
+    pub fn new(config: &ApiConfig, tenants: &[TenantConfig]) -> Result<Self> {
+        let mut tokens: HashMap<String, (Scope, Role)> = HashMap::new();
+        tokens.extend(config.admin_tokens.iter().map(|token| (token.clone(), (Scope::All, Role::Admin))));
+        tokens.extend(config.read_tokens.iter().map(|token| (token.clone(), (Scope::All, Role::Read))));
+        for tenant in tenants {
+            let Some(id) = TenantId::new(&tenant.name) else { continue };
+            tokens.extend(tenant.api_tokens.iter().map(|token| (token.clone(), (Scope::Tenant(id), Role::Admin))));
+            tokens.extend(tenant.read_tokens.iter().map(|token| (token.clone(), (Scope::Tenant(id), Role::Read))));
+        }
+        let oidc = config.oidc.clone().map(|oidc| Oidc::new(oidc, tenants)).transpose()?.map(Arc::new);
+
+        Ok(Self { tokens, oidc })
+    }
+
+    /// Whether requests go unauthenticated
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty() && self.oidc.is_none()
+    }
+
+    /// Access of the request bearing `authorization`
+    pub async fn access(&self, authorization: Option<&str>) -> Result<(Scope, Role), ApiError> {
+        if self.is_empty() {
+            return Ok((Scope::All, Role::Admin));
+        }
+        let token = authorization
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(ApiError::Unauthorized)?;
+        if let Some(access) = self.tokens.get(token) {
+            return Ok(*access);
+        }
+
+        let oidc = self.oidc.as_ref().ok_or(ApiError::Unauthorized)?;
+        let claims = oidc.verify(token).await.map_err(|e| {
+            debug!("Refused bearer token: {:#}", e);
+            ApiError::Unauthorized
+        })?;
+        oidc.access(&claims)
+    }
+}
+
+/// Access of a request from `address` bearing `authorization`, within the
+/// rates of its address and token
+///
+/// On an open API, clients are limited by address. Otherwise the address
+/// must have requests left before the token is resolved (an OIDC token
+/// costs a signature check, maybe a key fetch), and a refused token takes
+/// one; accepted requests are counted against their token.
+async fn admit(
+    tokens: &ApiTokens,
+    limiter: &RateLimiter,
+    authorization: Option<&str>,
+    address: &str,
+    now: Instant,
+) -> Result<(Scope, Role), ApiError> {
+    if tokens.is_empty() {
+        limiter.check(address, now).map_err(ApiError::TooManyRequests)?;
+        return tokens.access(authorization).await;
+    }
+
+    limiter.peek(address, now).map_err(ApiError::TooManyRequests)?;
+    let access = tokens.access(authorization).await.inspect_err(|_| {
+        let _ = limiter.check(address, now);
+    })?;
+    limiter.check(authorization.unwrap_or_default(), now).map_err(ApiError::TooManyRequests)?;
+    Ok(access)
+}
+
+/// Resolve the access of a request, refusing changes to read-only tokens
+/// and clients over their rate; the scope is passed on to the handlers as
+/// an extension
+pub async fn authenticate(State(api): State<ApiState>, mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let authorization = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let address = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or_else(String::new, |ConnectInfo(address)| address.ip().to_string());
+    let (scope, role) = admit(&api.tokens, &api.limiter, authorization, &address, Instant::now()).await?;
+
+    if role == Role::Read && !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return Err(ApiError::Forbidden("read-only token".to_string()));
+    }
+
+    request.extensions_mut().insert(scope);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_access() {
+        assert_eq!(ApiTokens::default().access(None).await.unwrap(), (Scope::All, Role::Admin));
+
+        let config: ApiConfig = toml::from_str(
+            r#"
+admin_tokens = ["root-token"]
+read_tokens = ["audit-token"]
+"#,
+        )
+        .unwrap();
+        let tenants: Vec<TenantConfig> = vec![toml::from_str(
+            r#"
+name = "acme"
+sites = ["paris"]
+api_tokens = ["acme-token"]
+read_tokens = ["acme-audit"]
+"#,
+        )
+        .unwrap()];
+        let tokens = ApiTokens::new(&config, &tenants).unwrap();
+        let acme = Scope::Tenant(TenantId::new("acme").unwrap());
+
+        assert_eq!(tokens.access(Some("Bearer root-token")).await.unwrap(), (Scope::All, Role::Admin));
+        assert_eq!(tokens.access(Some("Bearer audit-token")).await.unwrap(), (Scope::All, Role::Read));
+        assert_eq!(tokens.access(Some("Bearer acme-token")).await.unwrap(), (acme, Role::Admin));
+        assert_eq!(tokens.access(Some("Bearer acme-audit")).await.unwrap(), (acme, Role::Read));
+        for refused in [Some("Bearer other"), Some("acme-token"), None] {
+            assert!(matches!(tokens.access(refused).await, Err(ApiError::Unauthorized)));
+        }
+
+        let sites = Tenants::new([("acme", &tenants[0].sites[..])]);
+        assert!(acme.sees(&sites, Some("paris")));
+        assert!(!acme.sees(&sites, Some("lyon")));
+        assert!(!acme.sees(&sites, None));
+        assert!(Scope::All.sees(&sites, Some("lyon")));
+        assert_eq!(acme.tenant().as_deref(), Some("acme"));
+        assert!(acme.require_all().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_guessed_tokens_limited() {
+        let config: ApiConfig = toml::from_str(r#"admin_tokens = ["root-token"]"#).unwrap();
+        let tokens = ApiTokens::new(&config, &[]).unwrap();
+        let limiter = RateLimiter::new(60, 3);
+        let now = Instant::now();
+
+        // Accepted requests count against their token, not their address
+        for _ in 0..2 {
+            assert!(admit(&tokens, &limiter, Some("Bearer root-token"), "192.0.2.1", now).await.is_ok());
+        }
+
+        // Bad tokens drain their address, then are refused before being resolved
+        for _ in 0..3 {
+            let refused = admit(&tokens, &limiter, Some("Bearer guess"), "192.0.2.9", now).await;
+            assert!(matches!(refused, Err(ApiError::Unauthorized)));
+        }
+        let limited = admit(&tokens, &limiter, Some("Bearer guess"), "192.0.2.9", now).await;
+        assert!(matches!(limited, Err(ApiError::TooManyRequests(_))));
+        // Even with a valid token, whose bucket has a request left
+        let limited = admit(&tokens, &limiter, Some("Bearer root-token"), "192.0.2.9", now).await;
+        assert!(matches!(limited, Err(ApiError::TooManyRequests(_))));
+        assert!(admit(&tokens, &limiter, Some("Bearer root-token"), "192.0.2.1", now).await.is_ok());
+    }
+}
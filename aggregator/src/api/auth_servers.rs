@@ -0,0 +1,40 @@
+//! Authentication server endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::auth_servers::AuthServer;
+use crate::db::{AuthClient, AuthClientFilter, AuthServerFilter};
+
+/// `GET /api/auth/servers`
+///
+/// Domain controllers, Kerberos, LDAP and SMB servers, most clients first.
+pub async fn servers(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<AuthServerFilter>,
+) -> Result<Json<Page<AuthServer>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (servers, total) = api.db
+        .list_auth_servers(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(servers, total)))
+}
+
+/// `GET /api/auth/clients`
+///
+/// Which clients depend on which authentication servers, busiest first.
+pub async fn clients(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<AuthClientFilter>,
+) -> Result<Json<Page<AuthClient>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (clients, total) = api.db
+        .list_auth_clients(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(clients, total)))
+}
@@ -0,0 +1,21 @@
+//! Service dependency map endpoint
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{DependencyFilter, StoredDependency};
+
+/// `GET /api/dependencies`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<DependencyFilter>,
+) -> Result<Json<Page<StoredDependency>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (dependencies, total) = api.db
+        .list_dependencies(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(dependencies, total)))
+}
@@ -0,0 +1,368 @@
+//! Device endpoints
+
+use anyhow::Context;
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+use super::{ApiError, ApiState, Page, Pagination, Scope, Source};
+use crate::db::{DeviceFilter, HourlyAnomaly, HourlyComposition, HourlyDnsStats, HourlyQosStats};
+use crate::pipeline::{PersistRequest, PurgeReport};
+use crate::state::anomaly::slope_per_day;
+use crate::state::{merge_sites, port_of, DeviceSnapshot, MacAddr, TenantId, DEFAULT_SITE};
+
+/// Hours of traffic composition, QoS classes, DNS activity and anomaly scores returned by default and at most
+const DEFAULT_HOURS: i64 = 24;
+const MAX_HOURS: i64 = 24 * 90;
+
+/// Query parameters of the traffic composition, QoS, DNS and anomaly endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct TrafficQuery {
+    /// Site to report on (default: the site the device was last seen on)
+    pub site: Option<String>,
+    pub hours: Option<i64>,
+}
+
+/// Anomaly scores of a device by one detector over a window
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyTrend {
+    pub detector: String,
+    /// Mean of the hourly mean scores
+    pub mean_score: f64,
+    pub max_score: f64,
+    /// Change of the hourly mean score per day: positive when the device
+    /// drifts away above its baseline
+    pub slope_per_day: f64,
+    pub hours: Vec<HourlyAnomaly>,
+}
+
+/// Body of the annotation requests
+#[derive(Debug, Deserialize)]
+pub struct Annotation {
+    /// New notes, `null` to clear them
+    pub notes: Option<String>,
+}
+
+/// `GET /api/devices`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<DeviceFilter>,
+) -> Result<Json<Page<DeviceSnapshot>>, ApiError> {
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let (devices, total) = api.db
+            .list_devices(&filter, api.inactivity_timeout, pagination.limit(), pagination.cursor()?)
+            .await?;
+        return Ok(Json(pagination.wrap_keyed(devices, total)));
+    }
+
+    let oui = filter.oui.as_ref().map(|o| o.to_uppercase());
+    let devices: Vec<DeviceSnapshot> = visible(&api, scope, None)
+        .into_iter()
+        .filter(|device| matches(device, &filter, oui.as_deref(), api.inactivity_timeout))
+        .collect();
+
+    Ok(Json(pagination.page_keyed(devices)))
+}
+
+/// `GET /api/devices/{mac}`
+///
+/// Looks in memory first and falls back to the database for devices that
+/// have been evicted since they were last seen. A MAC address seen on
+/// several sites is returned as one device with its presence on each.
+pub async fn get(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+) -> Result<Json<DeviceSnapshot>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+
+    let mut device = lookup(&api, scope, mac)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Device {} not found", mac)))?;
+    if device.notes.is_none() {
+        device.notes = api.db.device_notes(&device.mac_address, scope.tenant().as_deref()).await?;
+    }
+    Ok(Json(device))
+}
+
+/// `PUT /api/devices/{mac}/notes`
+///
+/// Annotates the persisted device, of every tenant for admin-wide tokens.
+pub async fn annotate(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+    Json(annotation): Json<Annotation>,
+) -> Result<Json<DeviceSnapshot>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+    let tenant = scope.tenant();
+    let notes = annotation.notes.filter(|notes| !notes.is_empty());
+
+    if api.db.set_device_notes(&mac, tenant.as_deref(), notes.as_deref()).await? == 0 {
+        return Err(ApiError::NotFound(format!("Device {} not persisted yet", mac)));
+    }
+    let mut device = lookup(&api, scope, mac)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Device {} not found", mac)))?;
+    device.notes = notes;
+    Ok(Json(device))
+}
+
+/// `DELETE /api/devices/{mac}`
+///
+/// Forgets the MAC address on every site, in memory and in the database,
+/// as `netsentinel-aggregator purge-device` does; for admin-wide tokens.
+pub async fn purge(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+) -> Result<Json<PurgeReport>, ApiError> {
+    scope.require_all()?;
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+
+    let (reply, outcome) = oneshot::channel();
+    api.persister
+        .send(PersistRequest::PurgeDevice(mac, reply))
+        .await
+        .ok()
+        .context("The persister is not running")?;
+    let report = outcome.await.context("The persister stopped before answering")??;
+    Ok(Json(report))
+}
+
+/// `GET /api/devices/{mac}/traffic`
+///
+/// Bytes sent and received per hour and service over the last `hours`
+/// hours (24 by default), services named after their port.
+pub async fn traffic(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+    Query(query): Query<TrafficQuery>,
+) -> Result<Json<Vec<HourlyComposition>>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+    let site = device_site(&api, scope, mac, query.site).await?;
+    let hours = query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let since = Utc::now() - Duration::hours(hours);
+
+    let mut composition = api.db.device_hourly_traffic(&site, &mac.to_string(), since).await?;
+    for service in composition.iter_mut().flat_map(|hour| hour.services.iter_mut()) {
+        service.name = port_of(&service.service).and_then(|port| api.state.services.name(port)).map(str::to_string);
+    }
+    Ok(Json(composition))
+}
+
+/// `GET /api/devices/{mac}/qos`
+///
+/// Bytes sent per hour, VLAN and DSCP value over the last `hours` hours
+/// (24 by default).
+pub async fn qos(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+    Query(query): Query<TrafficQuery>,
+) -> Result<Json<Vec<HourlyQosStats>>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+    let site = device_site(&api, scope, mac, query.site).await?;
+    let hours = query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let since = Utc::now() - Duration::hours(hours);
+
+    Ok(Json(api.db.device_hourly_qos(&site, &mac.to_string(), since).await?))
+}
+
+/// `GET /api/devices/{mac}/dns`
+///
+/// Queries, NXDOMAIN responses, DGA-like names and most queried names per
+/// hour over the last `hours` hours (24 by default).
+pub async fn dns(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+    Query(query): Query<TrafficQuery>,
+) -> Result<Json<Vec<HourlyDnsStats>>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+    let site = device_site(&api, scope, mac, query.site).await?;
+    let hours = query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let since = Utc::now() - Duration::hours(hours);
+
+    Ok(Json(api.db.device_hourly_dns(&site, &mac.to_string(), since).await?))
+}
+
+/// `GET /api/devices/{mac}/anomalies`
+///
+/// Hourly scores of the baseline detectors (outbound volume, NXDOMAIN
+/// responses) over the last `hours` hours (24 by default), with their trend.
+pub async fn anomalies(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(mac): Path<String>,
+    Query(query): Query<TrafficQuery>,
+) -> Result<Json<Vec<AnomalyTrend>>, ApiError> {
+    let mac = MacAddr::from_string(&mac)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?;
+    let site = device_site(&api, scope, mac, query.site).await?;
+    let hours = query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let since = Utc::now() - Duration::hours(hours);
+
+    Ok(Json(trends(api.db.device_hourly_anomalies(&site, &mac.to_string(), since).await?)))
+}
+
+/// Trend of each detector over `hours`, ordered by detector then hour
+fn trends(hours: Vec<HourlyAnomaly>) -> Vec<AnomalyTrend> {
+    let mut trends: Vec<AnomalyTrend> = Vec::new();
+    for hour in hours {
+        match trends.last_mut() {
+            Some(trend) if trend.detector == hour.detector => trend.hours.push(hour),
+            _ => trends.push(AnomalyTrend {
+                detector: hour.detector.clone(),
+                mean_score: 0.0,
+                max_score: 0.0,
+                slope_per_day: 0.0,
+                hours: vec![hour],
+            }),
+        }
+    }
+
+    for trend in &mut trends {
+        let scores: Vec<_> = trend.hours.iter().map(|hour| (hour.hour, hour.mean_score)).collect();
+        trend.mean_score = scores.iter().map(|(_, score)| score).sum::<f64>() / scores.len() as f64;
+        trend.max_score = trend.hours.iter().map(|hour| hour.max_score).fold(f64::MIN, f64::max);
+        trend.slope_per_day = slope_per_day(&scores);
+    }
+    trends
+}
+
+/// In-memory devices visible in `scope` (with MAC address `mac` when set),
+/// merged over the sites of each tenant
+pub(super) fn visible(api: &ApiState, scope: Scope, mac: Option<MacAddr>) -> Vec<DeviceSnapshot> {
+    let mut tenants: HashMap<TenantId, Vec<DeviceSnapshot>> = HashMap::new();
+    for entry in api.state.devices.iter().filter(|entry| mac.is_none_or(|mac| entry.key().mac == mac)) {
+        let tenant = api.state.tenants.tenant_of(entry.key().site);
+        if scope.includes(tenant) {
+            tenants.entry(tenant).or_default().push(entry.value().snapshot());
+        }
+    }
+    tenants.into_values().flat_map(merge_sites).collect()
+}
+
+/// Device with MAC address `mac` visible in `scope`, merged over the sites
+/// it is in memory on, or from the database when evicted; the last seen
+/// when several tenants have one
+async fn lookup(api: &ApiState, scope: Scope, mac: MacAddr) -> Result<Option<DeviceSnapshot>, ApiError> {
+    if let Some(device) = visible(api, scope, Some(mac)).into_iter().max_by_key(|device| device.last_seen) {
+        return Ok(Some(device));
+    }
+
+    Ok(api.db.get_device(&mac.to_string(), scope.tenant().as_deref()).await?)
+}
+
+/// Site to look up the history of `mac` on: `site` when given, otherwise the
+/// site the device was last seen on
+pub(super) async fn device_site(api: &ApiState, scope: Scope, mac: MacAddr, site: Option<String>) -> Result<String, ApiError> {
+    let site = match site {
+        Some(site) => site,
+        None => lookup(api, scope, mac).await?.map_or_else(|| DEFAULT_SITE.to_string(), |device| device.site),
+    };
+    if !scope.sees(&api.state.tenants, Some(&site)) {
+        return Err(ApiError::NotFound(format!("Device {} not found", mac)));
+    }
+
+    Ok(site)
+}
+
+/// Whether a device matches `filter`
+pub(super) fn matches(device: &DeviceSnapshot, filter: &DeviceFilter, oui: Option<&str>, inactivity_timeout: u64) -> bool {
+    if filter.site.as_ref().is_some_and(|site| !device.sites.iter().any(|s| &s.site == site)) {
+        return false;
+    }
+    if filter.vlan.is_some_and(|vlan| !device.vlans.contains(&vlan)) {
+        return false;
+    }
+    if filter.ip.is_some_and(|ip| !device.ip_addresses.iter().any(|i| i.ip_address == ip)) {
+        return false;
+    }
+    if oui.is_some_and(|oui| !device.mac_address.to_uppercase().starts_with(oui)) {
+        return false;
+    }
+    if filter.gateway.is_some_and(|gateway| device.is_gateway != gateway) {
+        return false;
+    }
+    if filter.min_confidence.is_some_and(|min| device.confidence < min) {
+        return false;
+    }
+    let inactive = Utc::now() - device.last_seen > Duration::seconds(inactivity_timeout as i64);
+    if filter.active.is_some_and(|active| inactive == active) {
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, DeviceState, SiteId};
+
+    #[test]
+    fn test_device_filter() {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let device = DeviceState::new(DeviceKey::new(SiteId::default(), mac), Utc::now());
+        device.update(Some("10.0.0.5".parse().unwrap()), Some(20), 64, true, Utc::now().timestamp() as u64);
+        let device = device.snapshot();
+
+        let filter = DeviceFilter { vlan: Some(20), ..Default::default() };
+        assert!(matches(&device, &filter, None, 300));
+
+        let filter = DeviceFilter { vlan: Some(30), ..Default::default() };
+        assert!(!matches(&device, &filter, None, 300));
+
+        let filter = DeviceFilter { site: Some("lyon".to_string()), ..Default::default() };
+        assert!(!matches(&device, &filter, None, 300));
+
+        let filter = DeviceFilter { ip: Some("10.0.0.5".parse().unwrap()), active: Some(true), ..Default::default() };
+        assert!(matches(&device, &filter, Some("00:11:22"), 300));
+        assert!(!matches(&device, &filter, Some("AA:BB:CC"), 300));
+
+        let filter = DeviceFilter { min_confidence: Some(10), ..Default::default() };
+        assert!(!matches(&device, &filter, None, 300));
+    }
+
+    #[test]
+    fn test_anomaly_trends() {
+        let start = Utc::now() - Duration::hours(12);
+        let hour = |detector: &str, h: i64, score: f64| HourlyAnomaly {
+            hour: start + Duration::hours(h),
+            detector: detector.to_string(),
+            samples: 60,
+            mean_score: score,
+            max_score: score + 1.0,
+            mean_value: 1000.0,
+            baseline: 800.0,
+        };
+
+        let trends = trends(vec![
+            hour("exfiltration", 0, 0.5),
+            hour("exfiltration", 12, 1.5),
+            hour("nxdomain", 0, -0.2),
+        ]);
+        assert_eq!(trends.len(), 2);
+        assert_eq!(trends[0].detector, "exfiltration");
+        assert_eq!(trends[0].mean_score, 1.0);
+        assert_eq!(trends[0].max_score, 2.5);
+        assert!((trends[0].slope_per_day - 2.0).abs() < 1e-9);
+        assert_eq!(trends[1].hours.len(), 1);
+        assert_eq!(trends[1].slope_per_day, 0.0);
+    }
+}
@@ -0,0 +1,21 @@
+//! DHCP lease endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{LeaseFilter, StoredLease};
+
+/// `GET /api/dhcp/leases`
+pub async fn leases(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<LeaseFilter>,
+) -> Result<Json<Page<StoredLease>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (leases, total) = api.db
+        .list_leases(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(leases, total)))
+}
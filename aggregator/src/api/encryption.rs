@@ -0,0 +1,30 @@
+//! Encrypted traffic endpoint
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use chrono::{Duration, Utc};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{DeviceEncryption, EncryptionFilter};
+
+/// Period covered when the request does not give `since`
+const DEFAULT_HOURS: i64 = 24;
+
+/// `GET /api/encryption`
+///
+/// Encrypted, plaintext and unclassified bytes of each device since `since`
+/// (the last 24 hours by default), with the plaintext protocols it used,
+/// most plaintext bytes first.
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<EncryptionFilter>,
+) -> Result<Json<Page<DeviceEncryption>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let since = filter.since.unwrap_or_else(|| Utc::now() - Duration::hours(DEFAULT_HOURS));
+    let (devices, total) = api.db
+        .list_device_encryption(&filter, since, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(devices, total)))
+}
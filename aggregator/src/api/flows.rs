@@ -0,0 +1,129 @@
+//! Flow endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use chrono::{Duration, Utc};
+use std::sync::atomic::Ordering;
+
+use super::{ApiError, ApiState, Page, Pagination, Scope, Source};
+use crate::db::{FlowFilter, FlowHistory, FlowHistoryFilter};
+use crate::state::{FlowKey, FlowSnapshot, MacAddr, ServiceNames};
+
+/// Window of the flow history returned when the request sets none
+const DEFAULT_HOURS: i64 = 24;
+const MAX_HOURS: i64 = 24 * 90;
+
+/// `GET /api/flows`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<FlowFilter>,
+) -> Result<Json<Page<FlowSnapshot>>, ApiError> {
+    let mac = match &filter.mac {
+        Some(mac) => Some(MacAddr::from_string(mac)
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac)))?),
+        None => None,
+    };
+
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let (flows, total) = api.db.list_flows(&filter, pagination.limit(), pagination.cursor()?).await?;
+        return Ok(Json(pagination.wrap_keyed(flows, total)));
+    }
+
+    let now_ts = Utc::now().timestamp() as u64;
+    let flows: Vec<FlowSnapshot> = api.state.flows.iter()
+        .filter(|entry| scope.includes(api.state.tenants.tenant_of(entry.key().site)))
+        .filter(|entry| matches(entry.key(), entry.value().last_seen.load(Ordering::Relaxed), &filter, mac, &api.state.services))
+        .filter(|entry| filter.tcp_outcome.is_none_or(|outcome| entry.value().tcp_outcome(now_ts) == Some(outcome)))
+        .map(|entry| entry.value().snapshot(entry.key().ethertype(), &api.state.services))
+        .collect();
+
+    Ok(Json(pagination.page_keyed(flows)))
+}
+
+/// `GET /api/flows/history`
+///
+/// Traffic between `device` and `peer` (any peer when unset) over the
+/// window (the last 24 hours by default), per flow, or summed per hour
+/// and/or service with `by`. Read from the hourly flow history, so it
+/// covers flows evicted long ago, up to the last persist.
+pub async fn history(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<FlowHistoryFilter>,
+) -> Result<Json<Page<FlowHistory>>, ApiError> {
+    let end = filter.until.unwrap_or_else(Utc::now);
+    let start = match filter.since {
+        Some(since) => since,
+        None => end - Duration::hours(filter.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS)),
+    };
+    if start >= end {
+        return Err(ApiError::BadRequest("`since` must be before `until`".to_string()));
+    }
+
+    filter.tenant = scope.tenant();
+    let (history, total) = api.db.flow_history(&filter, start, end, pagination.limit(), pagination.offset()?).await?;
+    Ok(Json(pagination.wrap(history, total)))
+}
+
+/// Whether an in-memory flow, last seen at `last_seen` (Unix seconds), matches `filter`
+fn matches(key: &FlowKey, last_seen: u64, filter: &FlowFilter, mac: Option<MacAddr>, services: &ServiceNames) -> bool {
+    if filter.site.as_ref().is_some_and(|site| key.site.as_str() != site) {
+        return false;
+    }
+    if filter.sensor.as_ref().is_some_and(|sensor| key.sensor.as_str() != sensor) {
+        return false;
+    }
+    if mac.is_some_and(|mac| key.src_mac != mac && key.dst_mac != mac) {
+        return false;
+    }
+    if filter.ip.is_some_and(|ip| key.src_ip != Some(ip) && key.dst_ip != Some(ip)) {
+        return false;
+    }
+    if filter.src_ip.is_some_and(|ip| key.src_ip != Some(ip)) {
+        return false;
+    }
+    if filter.dst_ip.is_some_and(|ip| key.dst_ip != Some(ip)) {
+        return false;
+    }
+    if filter.port.is_some_and(|port| key.src_port != Some(port) && key.dst_port != Some(port)) {
+        return false;
+    }
+    if filter.service.as_ref().is_some_and(|service| !key.service(services).is_some_and(|s| s.eq_ignore_ascii_case(service))) {
+        return false;
+    }
+    if filter.protocol.is_some_and(|protocol| key.protocol != Some(protocol)) {
+        return false;
+    }
+    if filter.vlan.is_some_and(|vlan| key.vlan_id != Some(vlan)) {
+        return false;
+    }
+    if filter.since.is_some_and(|since| (last_seen as i64) < since.timestamp()) {
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{FlowEndpoint, FlowGrouping};
+
+    #[test]
+    fn test_flow_history_parameters() {
+        let mac: FlowEndpoint = "00:11:22:33:44:55".parse().unwrap();
+        assert!(matches!(mac, FlowEndpoint::Mac(_)));
+        let Ok(FlowEndpoint::Network(host)) = "10.0.0.5".parse() else { panic!("not an address") };
+        assert_eq!(host.to_string(), "10.0.0.5/32");
+        let Ok(FlowEndpoint::Network(subnet)) = "10.0.20.7/24".parse() else { panic!("not a network") };
+        assert_eq!(subnet.to_string(), "10.0.20.0/24");
+        assert!("printer".parse::<FlowEndpoint>().is_err());
+
+        assert_eq!("".parse::<FlowGrouping>().unwrap(), FlowGrouping::default());
+        assert_eq!("hour, service".parse::<FlowGrouping>().unwrap(), FlowGrouping { hour: true, service: true });
+        assert!("day".parse::<FlowGrouping>().is_err());
+    }
+}
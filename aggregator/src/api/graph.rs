@@ -0,0 +1,49 @@
+//! Network graph export endpoint
+
+use axum::extract::{Query, State};
+use axum::Extension;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use super::{ApiError, ApiState, Scope};
+use crate::graph::{GraphFormat, NetworkGraph};
+
+/// Window drawn when the request sets none
+const DEFAULT_HOURS: i64 = 24;
+const MAX_HOURS: i64 = 24 * 90;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GraphQuery {
+    #[serde(default)]
+    pub format: GraphFormat,
+    pub site: Option<String>,
+    /// Window ending now, unless `since` is set
+    pub hours: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// `GET /api/graph`
+///
+/// Devices and the traffic between them over the flows active during the
+/// window (the last 24 hours by default), as `json`, `graphml` or `dot`.
+pub async fn export(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(query): Query<GraphQuery>,
+) -> Result<Response, ApiError> {
+    let end = query.until.unwrap_or_else(Utc::now);
+    let start = match query.since {
+        Some(since) => since,
+        None => end - Duration::hours(query.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS)),
+    };
+    if start >= end {
+        return Err(ApiError::BadRequest("`since` must be before `until`".to_string()));
+    }
+
+    let graph = NetworkGraph::load(&api.db, query.site.as_deref(), scope.tenant().as_deref(), start, end).await?;
+    let body = graph.render(query.format)?;
+    Ok(([(header::CONTENT_TYPE, query.format.content_type())], body).into_response())
+}
@@ -0,0 +1,110 @@
+//! Per-client request rate limits
+//!
+//! Each client, known by its bearer token or, on an open API, by its
+//! address, has a token bucket holding `burst` requests and refilled at
+//! `requests_per_minute`. Requests finding it empty are refused with the
+//! wait before the next one would pass. Buckets of clients gone quiet are
+//! dropped once full again.
+//!
+//! With authentication, the address of a client also has a bucket, checked
+//! before its token is resolved and drained by the requests refused, so
+//! guessing tokens is held to the same rate.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Clients kept before the full buckets are dropped
+const PRUNE_ABOVE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets of the API clients
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Limit each client to `requests_per_minute`, `burst` at once; no
+    /// limit when `requests_per_minute` is 0
+    pub fn new(requests_per_minute: u32, burst: u32) -> Self {
+        Self {
+            rate: f64::from(requests_per_minute) / 60.0,
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a request out of `client`'s bucket, or tell how long to wait
+    /// for one
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, 1.0)
+    }
+
+    /// Whether `client`'s bucket holds a request, without taking it
+    pub fn peek(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, 0.0)
+    }
+
+    /// Take `cost` requests out of `client`'s bucket if it holds one
+    fn take(&self, client: &str, now: Instant, cost: f64) -> Result<(), Duration> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > PRUNE_ABOVE {
+            buckets.retain(|_, bucket| self.refilled(*bucket, now) < self.burst);
+        }
+        let bucket = buckets
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = self.refilled(*bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Tokens of `bucket` at `now`
+    fn refilled(&self, bucket: Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(60, 3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check("dashboard", now).is_ok());
+        }
+        let wait = limiter.check("dashboard", now).unwrap_err();
+        assert_eq!(wait.as_secs(), 1);
+
+        // Other clients have their own bucket, and buckets refill
+        assert!(limiter.check("cli", now).is_ok());
+        assert!(limiter.check("dashboard", now + Duration::from_secs(1)).is_ok());
+        assert!(limiter.check("dashboard", now + Duration::from_secs(1)).is_err());
+
+        let unlimited = RateLimiter::new(0, 1);
+        assert!((0..100).all(|_| unlimited.check("dashboard", now).is_ok()));
+    }
+}
@@ -0,0 +1,187 @@
+//! Maintenance endpoints
+//!
+//! Puts a device, or a whole VLAN of a site, under maintenance until a set
+//! time: rules raise no alert about it, other alerts are recorded without
+//! being notified and the change report leaves out what changed on it
+//! meanwhile (see `state::maintenance`).
+
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+
+use super::{devices, ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::MaintenanceFilter;
+use crate::state::{MacAddr, MaintenanceEntry, MaintenanceTarget, DEFAULT_SITE};
+
+/// Longest maintenance that can be set
+const MAX_DURATION_DAYS: i64 = 90;
+
+/// Body of the maintenance requests
+#[derive(Debug, Default, Deserialize)]
+pub struct MaintenanceRequest {
+    /// Device to put under maintenance
+    pub mac: Option<String>,
+    /// Or VLAN to put under maintenance
+    pub vlan: Option<u16>,
+    /// Site of the device (default: the site it was last seen on) or of the
+    /// VLAN (default: `default`)
+    pub site: Option<String>,
+    /// End of the maintenance, or its length in seconds
+    pub until: Option<DateTime<Utc>>,
+    pub duration_secs: Option<u64>,
+    pub reason: Option<String>,
+    /// Who set the maintenance
+    pub by: Option<String>,
+}
+
+impl MaintenanceRequest {
+    /// What the request puts under maintenance
+    fn target(&self) -> Result<MaintenanceTarget, ApiError> {
+        match (&self.mac, self.vlan) {
+            (Some(mac), None) => MacAddr::from_string(mac)
+                .map(MaintenanceTarget::Device)
+                .ok_or_else(|| ApiError::BadRequest(format!("Invalid MAC address '{}'", mac))),
+            (None, Some(vlan)) if (1..4095).contains(&vlan) => Ok(MaintenanceTarget::Vlan(vlan)),
+            (None, Some(vlan)) => Err(ApiError::BadRequest(format!("Invalid VLAN ID {}", vlan))),
+            _ => Err(ApiError::BadRequest("Either `mac` or `vlan` is required".to_string())),
+        }
+    }
+
+    /// End of a maintenance starting at `now`
+    fn until(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, ApiError> {
+        let until = match (self.until, self.duration_secs) {
+            (Some(until), None) => until,
+            // Past the limit either way, without overflowing
+            (None, Some(secs)) => now + Duration::seconds(secs.min(MAX_DURATION_DAYS as u64 * 86_400 + 1) as i64),
+            _ => return Err(ApiError::BadRequest("Either `until` or `duration_secs` is required".to_string())),
+        };
+        if until <= now {
+            return Err(ApiError::BadRequest(format!("Maintenance end {} is already past", until)));
+        }
+        if until > now + Duration::days(MAX_DURATION_DAYS) {
+            return Err(ApiError::BadRequest(format!("Maintenance is limited to {} days", MAX_DURATION_DAYS)));
+        }
+        Ok(until)
+    }
+}
+
+/// `GET /api/maintenance`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<MaintenanceFilter>,
+) -> Result<Json<Page<MaintenanceEntry>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (entries, total) = api.db
+        .list_maintenance(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(entries, total)))
+}
+
+/// `POST /api/maintenance`
+///
+/// Puts a device or a VLAN of a site under maintenance from now on.
+pub async fn create(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceEntry>, ApiError> {
+    let target = request.target()?;
+    let now = Utc::now();
+    let until = request.until(now)?;
+    let site = match (target, request.site) {
+        (_, Some(site)) => site,
+        (MaintenanceTarget::Device(mac), None) => devices::device_site(&api, scope, mac, None).await?,
+        (MaintenanceTarget::Vlan(_), None) => DEFAULT_SITE.to_string(),
+    };
+    if !scope.sees(&api.state.tenants, Some(&site)) {
+        return Err(ApiError::Forbidden(format!("Site '{}' is not available", site)));
+    }
+
+    let (mac_address, vlan_id) = match target {
+        MaintenanceTarget::Device(mac) => (Some(mac.to_string()), None),
+        MaintenanceTarget::Vlan(vlan_id) => (None, Some(vlan_id)),
+    };
+    let entry = MaintenanceEntry {
+        id: Uuid::new_v4(),
+        site,
+        mac_address,
+        vlan_id,
+        reason: request.reason.filter(|reason| !reason.is_empty()),
+        created_by: request.by,
+        starts_at: now,
+        until,
+    };
+    api.db.insert_maintenance(&entry).await?;
+    api.state.maintenance.prune(now);
+    api.state.maintenance.insert(entry.clone());
+
+    Ok(Json(entry))
+}
+
+/// `DELETE /api/maintenance/{id}`
+///
+/// Ends a maintenance now; what changed while it ran stays out of the
+/// change report.
+pub async fn end(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Path(id): Path<String>,
+) -> Result<Json<MaintenanceEntry>, ApiError> {
+    let id: Uuid = id.parse().map_err(|_| ApiError::BadRequest(format!("Invalid maintenance ID '{}'", id)))?;
+    let now = Utc::now();
+    api.db.get_maintenance(id)
+        .await?
+        .filter(|(_, tenant)| scope.tenant().is_none_or(|scope| scope == *tenant))
+        .ok_or_else(|| ApiError::NotFound(format!("Maintenance {} not found", id)))?;
+
+    if !api.db.end_maintenance(id, now).await? {
+        return Err(ApiError::BadRequest(format!("Maintenance {} is already over", id)));
+    }
+    api.state.maintenance.end(id, now);
+    let (entry, _) = api.db.get_maintenance(id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Maintenance {} not found", id)))?;
+
+    Ok(Json(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_request() {
+        let now = Utc::now();
+        let request = MaintenanceRequest {
+            mac: Some("00:11:22:33:44:55".to_string()),
+            duration_secs: Some(3600),
+            ..Default::default()
+        };
+        assert!(matches!(request.target(), Ok(MaintenanceTarget::Device(_))));
+        assert_eq!(request.until(now).unwrap(), now + Duration::hours(1));
+
+        let request = MaintenanceRequest { vlan: Some(30), until: Some(now + Duration::days(1)), ..Default::default() };
+        assert!(matches!(request.target(), Ok(MaintenanceTarget::Vlan(30))));
+        assert!(request.until(now).is_ok());
+
+        for request in [
+            MaintenanceRequest { mac: Some("printer".to_string()), ..Default::default() },
+            MaintenanceRequest { mac: Some("00:11:22:33:44:55".to_string()), vlan: Some(30), ..Default::default() },
+            MaintenanceRequest { vlan: Some(4095), ..Default::default() },
+            MaintenanceRequest::default(),
+        ] {
+            assert!(request.target().is_err());
+        }
+        for request in [
+            MaintenanceRequest { until: Some(now - Duration::minutes(1)), ..Default::default() },
+            MaintenanceRequest { duration_secs: Some(91 * 86_400), ..Default::default() },
+            MaintenanceRequest { until: Some(now), duration_secs: Some(60), ..Default::default() },
+        ] {
+            assert!(request.until(now).is_err());
+        }
+    }
+}
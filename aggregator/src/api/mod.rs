@@ -0,0 +1,425 @@
+//! HTTP API for querying aggregated inventory
+//!
+//! Serves devices, flows and VLANs as JSON, either straight from the live
+//! in-memory state (`source=memory`, the default) or from PostgreSQL
+//! (`source=db`), which also covers entries already evicted from memory.
+//! Alerts are always read from PostgreSQL, where they can also be
+//! acknowledged and resolved; devices can be annotated, purged and put under
+//! maintenance. Tokens decide what each client sees and may change (see
+//! `auth`).
+
+use anyhow::{Context, Result};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::middleware;
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::cmp::Reverse;
+use std::str::FromStr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info};
+
+use crate::config::{ApiConfig, RedisConfig};
+use crate::db::{Cursor, Database, StoredAlert};
+use crate::pipeline::PersistRequest;
+use crate::state::{AggregatorState, DeviceSnapshot, FlowSnapshot};
+
+pub use auth::{ApiTokens, Role, Scope};
+pub use limit::RateLimiter;
+
+mod alerts;
+mod auth;
+mod auth_servers;
+mod dependencies;
+mod devices;
+mod dhcp;
+mod encryption;
+mod flows;
+mod graph;
+mod limit;
+mod maintenance;
+mod multicast;
+mod ntp;
+mod oidc;
+mod ot;
+mod scanners;
+mod search;
+mod stream;
+mod tls;
+mod top;
+mod topology;
+mod vlans;
+mod voip;
+
+/// Page size used when the request does not specify one
+const DEFAULT_LIMIT: usize = 100;
+
+/// Largest page size a request may ask for
+const MAX_LIMIT: usize = 1000;
+
+/// Deepest offset of a database list, which makes the database skip every
+/// row before it; lists sorted by time page deeper with their cursors
+const MAX_OFFSET: usize = 10_000;
+
+/// Shared state of the API handlers
+#[derive(Clone)]
+pub struct ApiState {
+    pub state: Arc<AggregatorState>,
+    pub db: Arc<Database>,
+    /// Seconds without traffic after which a device counts as inactive
+    pub inactivity_timeout: u64,
+    /// Prefix length of the subnets inferred on each VLAN
+    pub vlan_subnet_prefix: u8,
+    /// Frames stream consumed, `None` when frames are handed over in memory
+    pub redis: Option<RedisConfig>,
+    /// Access granted by each API token, none to leave the API open
+    pub tokens: ApiTokens,
+    /// Persister serving device purges
+    pub persister: mpsc::Sender<PersistRequest>,
+    /// Request rate of each client
+    pub limiter: Arc<RateLimiter>,
+}
+
+/// API error, rendered as `{"error": "..."}`
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("missing or invalid API token")]
+    Unauthorized,
+
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("too many requests, retry in {} seconds", .0.as_secs().max(1))]
+    TooManyRequests(Duration),
+
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(e) => {
+                error!("API request failed: {:#}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        let message = match &self {
+            ApiError::Internal(_) => "internal error".to_string(),
+            other => other.to_string(),
+        };
+
+        let mut response = (status, Json(serde_json::json!({ "error": message }))).into_response();
+        if let ApiError::TooManyRequests(wait) = &self {
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(wait.as_secs().max(1)));
+        }
+        response
+    }
+}
+
+/// Where list endpoints read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Live in-memory state
+    #[default]
+    Memory,
+    /// Persisted inventory
+    Db,
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "memory" => Ok(Source::Memory),
+            "db" => Ok(Source::Db),
+            other => anyhow::bail!("Unknown source '{}' (memory or db)", other),
+        }
+    }
+}
+
+/// Pagination query parameters shared by the list endpoints
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pagination {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    /// `next_cursor` of the previous page, instead of `offset`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub source: Source,
+}
+
+impl Pagination {
+    /// Requested page size, clamped to `1..=MAX_LIMIT`
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// Where the page of a database list sorted by time starts
+    pub fn cursor(&self) -> Result<Cursor, ApiError> {
+        match self.start() {
+            Cursor::Offset(offset) if offset > MAX_OFFSET => Err(ApiError::BadRequest(format!(
+                "Offsets past {} are refused, page with `cursor`",
+                MAX_OFFSET
+            ))),
+            cursor => Ok(cursor),
+        }
+    }
+
+    /// Where the page of any other database list starts
+    pub fn offset(&self) -> Result<usize, ApiError> {
+        match self.cursor()? {
+            Cursor::Offset(offset) => Ok(offset),
+            Cursor::After(..) => Err(invalid_cursor()),
+        }
+    }
+
+    /// Cut the requested page out of an already filtered and sorted list
+    pub fn page<T>(&self, items: Vec<T>) -> Result<Page<T>, ApiError> {
+        let Cursor::Offset(offset) = self.start() else {
+            return Err(invalid_cursor());
+        };
+        let total = items.len() as u64;
+        let items = items.into_iter().skip(offset).take(self.limit()).collect();
+        Ok(self.wrap(items, total))
+    }
+
+    /// Sort a filtered list most recently seen first and cut the requested
+    /// page out of it
+    pub fn page_keyed<T: Keyed>(&self, items: Vec<T>) -> Page<T> {
+        let items = self.after_cursor(items);
+        let total = items.len() as u64;
+        self.cut_keyed(items, total)
+    }
+
+    /// Items past the cursor when it starts after an item
+    pub fn after_cursor<T: Keyed>(&self, mut items: Vec<T>) -> Vec<T> {
+        if let Cursor::After(last_seen, id) = self.start() {
+            items.retain(|item| item.key() < (last_seen, id));
+        }
+        items
+    }
+
+    /// Sort the first items past the cursor, out of `total`, most recently
+    /// seen first and cut the requested page out of them
+    pub fn cut_keyed<T: Keyed>(&self, mut items: Vec<T>, total: u64) -> Page<T> {
+        items.sort_by_key(|item| Reverse(item.key()));
+        let items = match self.start() {
+            Cursor::Offset(offset) => items.into_iter().skip(offset).take(self.limit()).collect(),
+            Cursor::After(..) => items.into_iter().take(self.limit()).collect(),
+        };
+        self.wrap_keyed(items, total)
+    }
+
+    /// Wrap an already paginated result
+    pub fn wrap<T>(&self, items: Vec<T>, total: u64) -> Page<T> {
+        let offset = match self.start() {
+            Cursor::Offset(offset) => offset,
+            Cursor::After(..) => 0,
+        };
+        let next_cursor = ((offset + items.len()) as u64) < total;
+        Page {
+            next_cursor: next_cursor.then_some(Cursor::Offset(offset + items.len())),
+            items,
+            total,
+            limit: self.limit(),
+            offset,
+        }
+    }
+
+    /// Wrap an already paginated result sorted most recently seen first,
+    /// whose next page starts after its last item
+    pub fn wrap_keyed<T: Keyed>(&self, items: Vec<T>, total: u64) -> Page<T> {
+        let mut page = self.wrap(items, total);
+        if page.next_cursor.is_some() {
+            page.next_cursor = page.items.last().map(|item| {
+                let (last_seen, id) = item.key();
+                Cursor::After(last_seen, id)
+            });
+        }
+        page
+    }
+
+    fn start(&self) -> Cursor {
+        self.cursor.unwrap_or(Cursor::Offset(self.offset))
+    }
+}
+
+fn invalid_cursor() -> ApiError {
+    ApiError::BadRequest("Cursor of another list".to_string())
+}
+
+/// One page of a list endpoint
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of matches across all pages, from the cursor on when the page
+    /// starts after an item
+    pub total: u64,
+    pub limit: usize,
+    pub offset: usize,
+    /// Where the next page starts, `None` on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Items of lists sorted most recently seen first, whose pages can start
+/// after an item
+pub trait Keyed {
+    /// Last seen time and ID
+    fn key(&self) -> (DateTime<Utc>, Uuid);
+}
+
+impl Keyed for DeviceSnapshot {
+    fn key(&self) -> (DateTime<Utc>, Uuid) {
+        (self.last_seen, self.id)
+    }
+}
+
+impl Keyed for FlowSnapshot {
+    fn key(&self) -> (DateTime<Utc>, Uuid) {
+        (self.last_seen, self.id)
+    }
+}
+
+impl Keyed for StoredAlert {
+    fn key(&self) -> (DateTime<Utc>, Uuid) {
+        (self.last_seen, self.id)
+    }
+}
+
+/// Build the API router
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/api/devices", get(devices::list))
+        .route("/api/devices/:mac", get(devices::get).delete(devices::purge))
+        .route("/api/devices/:mac/notes", put(devices::annotate))
+        .route("/api/devices/:mac/traffic", get(devices::traffic))
+        .route("/api/devices/:mac/qos", get(devices::qos))
+        .route("/api/devices/:mac/dns", get(devices::dns))
+        .route("/api/devices/:mac/anomalies", get(devices::anomalies))
+        .route("/api/flows", get(flows::list))
+        .route("/api/flows/history", get(flows::history))
+        .route("/api/dependencies", get(dependencies::list))
+        .route("/api/graph", get(graph::export))
+        .route("/api/topology/segments", get(topology::segments))
+        .route("/api/vlans", get(vlans::list))
+        .route("/api/vlans/subnets", get(vlans::subnets))
+        .route("/api/subnets/matrix", get(vlans::matrix))
+        .route("/api/multicast", get(multicast::list))
+        .route("/api/alerts", get(alerts::list))
+        .route("/api/alerts/:id", get(alerts::get))
+        .route("/api/alerts/:id/acknowledge", post(alerts::acknowledge))
+        .route("/api/alerts/:id/resolve", post(alerts::resolve))
+        .route("/api/maintenance", get(maintenance::list).post(maintenance::create))
+        .route("/api/maintenance/:id", delete(maintenance::end))
+        .route("/api/scanners", get(scanners::list))
+        .route("/api/dhcp/leases", get(dhcp::leases))
+        .route("/api/tls", get(tls::list))
+        .route("/api/tls/fingerprints", get(tls::fingerprints))
+        .route("/api/encryption", get(encryption::list))
+        .route("/api/ntp", get(ntp::list))
+        .route("/api/ot/devices", get(ot::devices))
+        .route("/api/ot/writes", get(ot::writes))
+        .route("/api/auth/servers", get(auth_servers::servers))
+        .route("/api/auth/clients", get(auth_servers::clients))
+        .route("/api/voip/calls", get(voip::calls))
+        .route("/api/search", get(search::devices))
+        .route("/api/top", get(top::list))
+        .route("/api/stream", get(stream::status))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::authenticate))
+        .with_state(state)
+}
+
+/// Serve the API until shutdown is signalled
+pub async fn serve(config: ApiConfig, state: ApiState, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to bind API listener on {}", config.bind))?;
+
+    info!("HTTP API listening on {}", config.bind);
+
+    axum::serve(listener, router(state).into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.recv().await;
+        })
+        .await
+        .with_context(|| "HTTP API server failed")?;
+
+    info!("HTTP API stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination_clamps_and_pages() {
+        let pagination = Pagination { limit: Some(5000), ..Default::default() };
+        assert_eq!(pagination.limit(), MAX_LIMIT);
+
+        let pagination = Pagination { limit: Some(2), offset: 3, ..Default::default() };
+        let page = pagination.page((0..10).collect::<Vec<_>>()).unwrap();
+        assert_eq!(page.items, vec![3, 4]);
+        assert_eq!(page.total, 10);
+        assert_eq!(page.next_cursor, Some(Cursor::Offset(5)));
+
+        let page = pagination.page(vec![1, 2]).unwrap();
+        assert!(page.items.is_empty());
+        assert_eq!(page.total, 2);
+        assert_eq!(page.next_cursor, None);
+
+        let deep = Pagination { offset: MAX_OFFSET + 1, ..Default::default() };
+        assert!(deep.offset().is_err());
+    }
+
+    #[test]
+    fn test_keyed_cursor() {
+        struct Item(i64, u128);
+        impl Keyed for Item {
+            fn key(&self) -> (DateTime<Utc>, Uuid) {
+                (DateTime::from_timestamp(self.0, 0).unwrap(), Uuid::from_u128(self.1))
+            }
+        }
+        // Two items seen at the same time are told apart by their ID
+        let items = || vec![Item(10, 1), Item(30, 3), Item(20, 2), Item(20, 4)];
+
+        let first = Pagination { limit: Some(2), ..Default::default() }.page_keyed(items());
+        assert_eq!(first.items.iter().map(|item| item.1).collect::<Vec<_>>(), [3, 4]);
+        let cursor = first.next_cursor.unwrap();
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+
+        let next = Pagination { limit: Some(2), cursor: Some(cursor), ..Default::default() };
+        let second = next.page_keyed(items());
+        assert_eq!(second.items.iter().map(|item| item.1).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!((second.total, second.next_cursor), (2, None));
+
+        // Cursors of time-sorted lists do not fit the others
+        assert!(next.page(vec![1, 2, 3]).is_err());
+        assert!("k12.not-an-id".parse::<Cursor>().is_err());
+    }
+}
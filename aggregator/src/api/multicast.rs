@@ -0,0 +1,38 @@
+//! Multicast group endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use chrono::Utc;
+
+use super::{ApiError, ApiState, Page, Pagination, Scope, Source};
+use crate::db::MulticastFilter;
+use crate::state::MulticastSnapshot;
+
+/// `GET /api/multicast`
+///
+/// Multicast groups with their members, senders and bandwidth, busiest
+/// first.
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<MulticastFilter>,
+) -> Result<Json<Page<MulticastSnapshot>>, ApiError> {
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let (groups, total) = api.db.list_multicast_groups(&filter, pagination.limit(), pagination.offset()?).await?;
+        return Ok(Json(pagination.wrap(groups, total)));
+    }
+
+    let mac = filter.mac.as_deref().map(str::to_ascii_lowercase);
+    let mut groups: Vec<MulticastSnapshot> = api.state.multicast.snapshots(Utc::now())
+        .into_iter()
+        .filter(|group| scope.sees(&api.state.tenants, Some(&group.site)))
+        .filter(|group| filter.site.as_ref().is_none_or(|site| *site == group.site))
+        .filter(|group| filter.group.is_none_or(|address| address == group.group))
+        .filter(|group| mac.as_ref().is_none_or(|mac| group.members.contains(mac) || group.senders.contains(mac)))
+        .collect();
+    groups.sort_by(|a, b| b.bits_per_sec.total_cmp(&a.bits_per_sec).then(b.bytes.cmp(&a.bytes)));
+
+    Ok(Json(pagination.page(groups)?))
+}
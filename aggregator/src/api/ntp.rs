@@ -0,0 +1,23 @@
+//! NTP server endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{NtpServerFilter, StoredNtpServer};
+
+/// `GET /api/ntp`
+///
+/// Time servers each device queries, most recently seen first.
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<NtpServerFilter>,
+) -> Result<Json<Page<StoredNtpServer>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (servers, total) = api.db
+        .list_ntp_servers(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(servers, total)))
+}
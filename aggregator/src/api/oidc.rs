@@ -0,0 +1,199 @@
+//! OpenID Connect access tokens
+//!
+//! Verifies the JWTs of an OIDC provider against its published signing keys
+//! (JWKS), fetched on first use and again when a token names a key not seen
+//! yet, at most once a minute. Symmetric (HMAC) signatures are refused: the
+//! keys are public. A verified token's role and tenant claims then decide
+//! its access.
+
+use anyhow::{Context, Result};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::auth::{Role, Scope};
+use super::ApiError;
+use crate::config::{OidcConfig, TenantConfig};
+use crate::state::TenantId;
+
+/// Shortest time between two fetches of the signing keys
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Signing keys last fetched
+#[derive(Default)]
+struct Keys {
+    keys: Vec<Jwk>,
+    fetched: Option<Instant>,
+}
+
+/// Verifier of a provider's access tokens
+pub struct Oidc {
+    config: OidcConfig,
+    client: reqwest::Client,
+    /// Configured tenants, by name
+    tenants: HashMap<String, TenantId>,
+    keys: Mutex<Keys>,
+}
+
+impl Oidc {
+    /// Verifier of the tokens of `config`, restricted to `tenants` by the
+    /// tenant claim
+    pub fn new(config: OidcConfig, tenants: &[TenantConfig]) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build OIDC HTTP client")?;
+        let tenants = tenants
+            .iter()
+            .filter_map(|tenant| Some((tenant.name.clone(), TenantId::new(&tenant.name)?)))
+            .collect();
+
+        Ok(Self { config, client, tenants, keys: Mutex::new(Keys::default()) })
+    }
+
+    /// Claims of `token`, once its signature, issuer, audience and expiry
+    /// are checked
+    pub async fn verify(&self, token: &str) -> Result<Value> {
+        let header = jsonwebtoken::decode_header(token).context("Malformed token")?;
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            anyhow::bail!("Symmetric signature {:?} refused", header.alg);
+        }
+        let key = self.key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        let data = jsonwebtoken::decode::<Value>(token, &key, &validation).context("Invalid token")?;
+        Ok(data.claims)
+    }
+
+    /// Access granted by verified `claims`
+    pub fn access(&self, claims: &Value) -> Result<(Scope, Role), ApiError> {
+        let roles = roles_of(claims, &self.config.roles_claim);
+        let role = if roles.contains(&self.config.admin_role.as_str()) {
+            Role::Admin
+        } else if roles.contains(&self.config.read_role.as_str()) {
+            Role::Read
+        } else {
+            return Err(ApiError::Forbidden("token has no NetSentinel role".to_string()));
+        };
+
+        let Some(claim) = &self.config.tenant_claim else {
+            return Ok((Scope::All, role));
+        };
+        let tenant = claim_at(claims, claim)
+            .and_then(Value::as_str)
+            .and_then(|name| self.tenants.get(name))
+            .ok_or_else(|| ApiError::Forbidden("token has no known tenant".to_string()))?;
+        Ok((Scope::Tenant(*tenant), role))
+    }
+
+    /// Signing key `kid` (the only one when unnamed), fetching the keys
+    /// again if it is unknown
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        let mut keys = self.keys.lock().await;
+        if let Some(jwk) = find(&keys.keys, kid) {
+            return DecodingKey::from_jwk(jwk).context("Unusable signing key");
+        }
+        if keys.fetched.is_some_and(|fetched| fetched.elapsed() < MIN_REFRESH) {
+            anyhow::bail!("Unknown signing key {:?}", kid);
+        }
+
+        keys.fetched = Some(Instant::now());
+        keys.keys = self.fetch_keys().await?.keys;
+        info!("Fetched {} OIDC signing keys of {}", keys.keys.len(), self.config.issuer);
+        let jwk = find(&keys.keys, kid).with_context(|| format!("Unknown signing key {:?}", kid))?;
+        DecodingKey::from_jwk(jwk).context("Unusable signing key")
+    }
+
+    async fn fetch_keys(&self) -> Result<JwkSet> {
+        let uri = match &self.config.jwks_uri {
+            Some(uri) => uri.clone(),
+            None => {
+                let discovery = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+                let document: Value = self.get(&discovery).await?;
+                document["jwks_uri"]
+                    .as_str()
+                    .with_context(|| format!("No jwks_uri in {}", discovery))?
+                    .to_string()
+            }
+        };
+        self.get(&uri).await
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch {}", url))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from {}", url))
+    }
+}
+
+/// Key `kid` of `keys`, or the only key when the token names none
+fn find<'a>(keys: &'a [Jwk], kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.iter().find(|jwk| jwk.common.key_id.as_deref() == Some(kid)),
+        None => match keys {
+            [jwk] => Some(jwk),
+            _ => None,
+        },
+    }
+}
+
+/// Claim at a dotted `path`
+fn claim_at<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(claims, |value, name| value.get(name))
+}
+
+/// Roles in the claim at `path`: a list, or a space-separated string as
+/// `scope` is
+fn roles_of<'a>(claims: &'a Value, path: &str) -> Vec<&'a str> {
+    match claim_at(claims, path) {
+        Some(Value::Array(roles)) => roles.iter().filter_map(Value::as_str).collect(),
+        Some(Value::String(roles)) => roles.split_whitespace().collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_access_of_claims() {
+        let config: OidcConfig = toml::from_str(
+            r#"
+issuer = "https://sso.example.com/realms/net"
+audience = "netsentinel"
+roles_claim = "realm_access.roles"
+tenant_claim = "tenant"
+"#,
+        )
+        .unwrap();
+        let tenants: Vec<TenantConfig> = vec![toml::from_str("name = \"acme\"\nsites = [\"paris\"]").unwrap()];
+        let oidc = Oidc::new(config, &tenants).unwrap();
+        let acme = Scope::Tenant(TenantId::new("acme").unwrap());
+
+        let claims = json!({"realm_access": {"roles": ["netsentinel:read"]}, "tenant": "acme"});
+        assert_eq!(oidc.access(&claims).unwrap(), (acme, Role::Read));
+        let claims = json!({"realm_access": {"roles": ["netsentinel:read", "netsentinel:admin"]}, "tenant": "acme"});
+        assert_eq!(oidc.access(&claims).unwrap(), (acme, Role::Admin));
+
+        // No role, or a tenant not configured
+        assert!(oidc.access(&json!({"realm_access": {"roles": ["other"]}, "tenant": "acme"})).is_err());
+        assert!(oidc.access(&json!({"realm_access": {"roles": ["netsentinel:read"]}, "tenant": "globex"})).is_err());
+        assert!(oidc.access(&json!({"realm_access": {"roles": ["netsentinel:read"]}})).is_err());
+
+        assert_eq!(roles_of(&json!({"scope": "openid netsentinel:read"}), "scope"), ["openid", "netsentinel:read"]);
+    }
+}
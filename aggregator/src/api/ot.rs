@@ -0,0 +1,39 @@
+//! Industrial device endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{OtDeviceFilter, OtWriteFilter, StoredOtDevice, StoredOtWrite};
+
+/// `GET /api/ot/devices`
+///
+/// Modbus, DNP3 and BACnet devices, most recently seen first.
+pub async fn devices(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<OtDeviceFilter>,
+) -> Result<Json<Page<StoredOtDevice>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (devices, total) = api.db
+        .list_ot_devices(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(devices, total)))
+}
+
+/// `GET /api/ot/writes`
+///
+/// Sources writing to industrial devices, most recent write first.
+pub async fn writes(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<OtWriteFilter>,
+) -> Result<Json<Page<StoredOtWrite>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (writes, total) = api.db
+        .list_ot_writes(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(writes, total)))
+}
@@ -0,0 +1,23 @@
+//! External scanner endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{ScannerFilter, StoredScanner};
+
+/// `GET /api/scanners`
+///
+/// External scanners are aggregator-wide: tenant tokens are refused.
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<ScannerFilter>,
+) -> Result<Json<Page<StoredScanner>>, ApiError> {
+    scope.require_all()?;
+    let (scanners, total) = api.db
+        .list_scanners(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(scanners, total)))
+}
@@ -0,0 +1,217 @@
+//! Device search endpoint
+//!
+//! Searches devices by any mix of address, vendor, hostname, type, tag and
+//! last-seen window. Devices in memory are matched live and the database
+//! adds those evicted since, so a search covers the whole inventory
+//! history. Memory has the last word on the devices it holds: they are
+//! never returned from the database.
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+use super::{devices, ApiError, ApiState, Keyed, Page, Pagination, Scope, Source};
+use crate::db::{Cursor, SearchFilter};
+use crate::state::{DeviceKey, DeviceSnapshot, MacAddr, SiteId};
+
+/// Device found by a search
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceMatch {
+    #[serde(flatten)]
+    pub device: DeviceSnapshot,
+    /// Hostname last announced over DHCP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Vendor of the OUI, from the `[vendors]` table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    pub device_type: String,
+    /// Whether the device is in memory or only in the database
+    pub source: Source,
+}
+
+impl Keyed for DeviceMatch {
+    fn key(&self) -> (DateTime<Utc>, Uuid) {
+        self.device.key()
+    }
+}
+
+/// `GET /api/search`
+///
+/// Devices matching every filter given, most recently seen first, from
+/// memory and the database together.
+pub async fn devices(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<SearchFilter>,
+) -> Result<Json<Page<DeviceMatch>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let cursor = pagination.cursor()?;
+    let ouis = filter.vendor.as_ref().map(|vendor| api.state.vendors.ouis_matching(vendor));
+
+    // Memory is authoritative for the devices it holds, matching or not
+    let visible = devices::visible(&api, scope, None);
+    let exclude: Vec<(String, String)> = visible
+        .iter()
+        .map(|device| {
+            let tenant = api.state.tenants.tenant_of_name(&device.site);
+            (tenant.to_string(), device.mac_address.clone())
+        })
+        .collect();
+    let live: Vec<DeviceMatch> = visible
+        .into_iter()
+        .map(|device| describe(&api, device, Source::Memory))
+        .filter(|found| matches(found, &filter, ouis.as_deref()))
+        .collect();
+    let live = pagination.after_cursor(live);
+
+    // Offsets count over both sources: the database fills the pages from
+    // its first match
+    let (db_cursor, db_limit) = match cursor {
+        Cursor::Offset(offset) => (Cursor::Offset(0), offset + pagination.limit()),
+        after => (after, pagination.limit()),
+    };
+    let (stored, stored_total) =
+        api.db.search_devices(&filter, ouis.as_deref(), &exclude, db_limit, db_cursor).await?;
+    let ids: Vec<Uuid> = stored.iter().map(|device| device.id).collect();
+    let hostnames = api.db.device_hostnames(&ids).await?;
+
+    let total = live.len() as u64 + stored_total;
+    let mut found = live;
+    found.extend(stored.into_iter().map(|device| {
+        let mut found = describe(&api, device, Source::Db);
+        found.hostname = hostnames.get(&found.device.id).cloned();
+        found
+    }));
+
+    Ok(Json(pagination.cut_keyed(found, total)))
+}
+
+/// Type of a device: its `type` tag, set by an enricher or a capture
+/// script, otherwise `gateway` or `unknown`
+pub fn device_type(device: &DeviceSnapshot) -> String {
+    match device.tags.get("type") {
+        Some(device_type) => device_type.clone(),
+        None if device.is_gateway => "gateway".to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Search result of `device`, with the hostname tracked in memory
+fn describe(api: &ApiState, device: DeviceSnapshot, source: Source) -> DeviceMatch {
+    let mac = MacAddr::from_string(&device.mac_address);
+    let hostname = mac.and_then(|mac| {
+        // The site last seen on first
+        std::iter::once(device.site.as_str())
+            .chain(device.sites.iter().map(|presence| presence.site.as_str()))
+            .filter_map(SiteId::new)
+            .find_map(|site| api.state.dhcp.hostname(&DeviceKey::new(site, mac)))
+    });
+
+    DeviceMatch {
+        hostname,
+        vendor: mac.and_then(|mac| api.state.vendors.name(&mac)).map(str::to_string),
+        device_type: device_type(&device),
+        source,
+        device,
+    }
+}
+
+/// Whether a device in memory matches `filter`, of the vendors' `ouis`
+/// when set
+fn matches(found: &DeviceMatch, filter: &SearchFilter, ouis: Option<&[String]>) -> bool {
+    let device = &found.device;
+    if filter.site.as_ref().is_some_and(|site| !device.sites.iter().any(|s| &s.site == site)) {
+        return false;
+    }
+    if filter.mac_part().is_some_and(|mac| !device.mac_address.contains(&mac)) {
+        return false;
+    }
+    let oui = device.mac_address.get(..8).unwrap_or_default().to_uppercase();
+    if filter.oui.as_ref().is_some_and(|o| o.to_uppercase().replace('-', ":") != oui) {
+        return false;
+    }
+    if ouis.is_some_and(|ouis| !ouis.contains(&oui)) {
+        return false;
+    }
+    if filter.ip.is_some_and(|ip| !device.ip_addresses.iter().any(|i| i.ip_address == ip)) {
+        return false;
+    }
+    if filter.cidr.is_some_and(|cidr| !device.ip_addresses.iter().any(|i| cidr.contains(i.ip_address))) {
+        return false;
+    }
+    if filter.vlan.is_some_and(|vlan| !device.vlans.contains(&vlan)) {
+        return false;
+    }
+    if let Some(hostname) = &filter.hostname {
+        let hostname = hostname.to_lowercase();
+        if !found.hostname.as_ref().is_some_and(|h| h.to_lowercase().contains(&hostname)) {
+            return false;
+        }
+    }
+    if filter.device_type.as_ref().is_some_and(|device_type| *device_type != found.device_type) {
+        return false;
+    }
+    match filter.tag() {
+        Some((name, None)) if !device.tags.contains_key(name) => return false,
+        Some((name, Some(value))) if device.tags.get(name).map(String::as_str) != Some(value) => return false,
+        _ => {}
+    }
+    if filter.since.is_some_and(|since| device.last_seen < since) {
+        return false;
+    }
+    if filter.until.is_some_and(|until| device.last_seen >= until) {
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DeviceState;
+
+    #[test]
+    fn test_search_matches() {
+        let mac = MacAddr::new([0x00, 0x1b, 0x63, 0x33, 0x44, 0x55]);
+        let device = DeviceState::new(DeviceKey::new(SiteId::default(), mac), Utc::now());
+        device.update(Some("10.0.20.5".parse().unwrap()), Some(20), 64, true, Utc::now().timestamp() as u64);
+        let mut device = device.snapshot();
+        device.tags.insert("type".to_string(), "printer".to_string());
+        let found = DeviceMatch {
+            hostname: Some("HP-LaserJet".to_string()),
+            vendor: None,
+            device_type: device_type(&device),
+            source: Source::Memory,
+            device,
+        };
+        let apple = ["00:1B:63".to_string()];
+
+        let filter = SearchFilter {
+            mac: Some("33-44".to_string()),
+            oui: Some("00:1b:63".to_string()),
+            cidr: Some("10.0.20.0/24".parse().unwrap()),
+            hostname: Some("laserjet".to_string()),
+            device_type: Some("printer".to_string()),
+            tag: Some("type:printer".to_string()),
+            since: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert!(matches(&found, &filter, Some(&apple)));
+        assert!(!matches(&found, &filter, Some(&[])));
+
+        for filter in [
+            SearchFilter { cidr: Some("10.0.30.0/24".parse().unwrap()), ..Default::default() },
+            SearchFilter { hostname: Some("nas".to_string()), ..Default::default() },
+            SearchFilter { device_type: Some("gateway".to_string()), ..Default::default() },
+            SearchFilter { tag: Some("owner".to_string()), ..Default::default() },
+            SearchFilter { until: Some(Utc::now() - chrono::Duration::hours(1)), ..Default::default() },
+        ] {
+            assert!(!matches(&found, &filter, None));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Stream position endpoint
+
+use axum::extract::State;
+use axum::{Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::warn;
+
+use super::{ApiError, ApiState, Scope};
+use crate::pipeline::{InstanceRegistry, StreamHead, StreamId};
+
+/// Saved position of one consumer
+#[derive(Debug, Serialize)]
+pub struct ConsumerCheckpoint {
+    pub consumer: String,
+    /// Last entry applied and persisted
+    pub last_id: String,
+    /// When the sensor added that entry to the stream
+    pub last_entry_at: Option<DateTime<Utc>>,
+    /// When the checkpoint was saved
+    pub updated_at: DateTime<Utc>,
+    /// Milliseconds between the entry and the head of the stream
+    pub behind_ms: Option<u64>,
+}
+
+/// Aggregation progress on the frames stream
+#[derive(Debug, Serialize)]
+pub struct StreamStatus {
+    pub stream: String,
+    pub consumer_group: String,
+    /// Newest entry, `None` when the stream is empty or Redis unreachable
+    pub head: Option<StreamHead>,
+    pub checkpoints: Vec<ConsumerCheckpoint>,
+}
+
+/// `GET /api/stream`
+///
+/// The head of the frames stream and the checkpoint of every consumer of
+/// the group, with how far behind the head each one is. Tenant tokens
+/// are refused.
+pub async fn status(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+) -> Result<Json<StreamStatus>, ApiError> {
+    scope.require_all()?;
+    let redis = api
+        .redis
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("frames are not read from a Redis stream".to_string()))?;
+
+    let head = match InstanceRegistry::connect(redis).await {
+        Ok(mut conn) => StreamHead::read(&mut conn, &redis.stream_name).await,
+        Err(e) => Err(e),
+    };
+    let head = head.unwrap_or_else(|e| {
+        warn!("Failed to read the stream head: {:#}", e);
+        None
+    });
+
+    let checkpoints = api
+        .db
+        .stream_checkpoints(&redis.stream_name, &redis.consumer_group)
+        .await?
+        .into_iter()
+        .map(|checkpoint| {
+            let id = StreamId::parse(&checkpoint.last_id);
+            ConsumerCheckpoint {
+                last_entry_at: id.and_then(|id| id.timestamp()),
+                behind_ms: id.zip(head).map(|(id, head)| head.behind_ms(id)),
+                consumer: checkpoint.consumer,
+                last_id: checkpoint.last_id,
+                updated_at: checkpoint.updated_at,
+            }
+        })
+        .collect();
+
+    Ok(Json(StreamStatus {
+        stream: redis.stream_name.clone(),
+        consumer_group: redis.consumer_group.clone(),
+        head,
+        checkpoints,
+    }))
+}
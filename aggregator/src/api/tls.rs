@@ -0,0 +1,35 @@
+//! TLS inventory endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{StoredTlsFingerprint, StoredTlsObservation, TlsFilter, TlsFingerprintFilter};
+
+/// `GET /api/tls`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<TlsFilter>,
+) -> Result<Json<Page<StoredTlsObservation>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (observations, total) = api.db
+        .list_tls_observations(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(observations, total)))
+}
+
+/// `GET /api/tls/fingerprints`
+pub async fn fingerprints(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<TlsFingerprintFilter>,
+) -> Result<Json<Page<StoredTlsFingerprint>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (fingerprints, total) = api.db
+        .list_tls_fingerprints(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(fingerprints, total)))
+}
@@ -0,0 +1,44 @@
+//! Top talker endpoint
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use serde::Deserialize;
+
+use super::{devices, ApiError, ApiState, Pagination, Scope, Source};
+use crate::db::{DeviceFilter, TopBy};
+use crate::state::DeviceSnapshot;
+
+/// Query parameters of the top talker endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct TopQuery {
+    #[serde(default)]
+    pub by: TopBy,
+}
+
+/// `GET /api/top`
+///
+/// The `limit` devices matching the device filters that sent and received
+/// the most bytes (or packets with `by=packets`).
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<DeviceFilter>,
+    Query(query): Query<TopQuery>,
+) -> Result<Json<Vec<DeviceSnapshot>>, ApiError> {
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let devices = api.db.top_devices(&filter, api.inactivity_timeout, query.by, pagination.limit()).await?;
+        return Ok(Json(devices));
+    }
+
+    let oui = filter.oui.as_ref().map(|o| o.to_uppercase());
+    let mut devices: Vec<DeviceSnapshot> = devices::visible(&api, scope, None)
+        .into_iter()
+        .filter(|device| devices::matches(device, &filter, oui.as_deref(), api.inactivity_timeout))
+        .collect();
+    devices.sort_by_key(|d| (std::cmp::Reverse(query.by.value(d)), std::cmp::Reverse(d.last_seen)));
+    devices.truncate(pagination.limit());
+
+    Ok(Json(devices))
+}
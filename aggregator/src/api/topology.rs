@@ -0,0 +1,36 @@
+//! L2 topology endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope, Source};
+use crate::db::SegmentFilter;
+use crate::state::L2Segment;
+
+/// `GET /api/topology/segments`
+///
+/// Devices grouped by the capture interfaces they are seen on, largest
+/// segment first.
+pub async fn segments(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<SegmentFilter>,
+) -> Result<Json<Page<L2Segment>>, ApiError> {
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let (segments, total) = api.db.list_l2_segments(&filter, pagination.limit(), pagination.offset()?).await?;
+        return Ok(Json(pagination.wrap(segments, total)));
+    }
+
+    let mac = filter.mac.as_deref().map(str::to_ascii_lowercase);
+    let segments: Vec<L2Segment> = api.state.topology.segments()
+        .into_iter()
+        .filter(|segment| scope.sees(&api.state.tenants, Some(&segment.site)))
+        .filter(|segment| filter.site.as_ref().is_none_or(|site| *site == segment.site))
+        .filter(|segment| mac.as_ref().is_none_or(|mac| segment.devices.contains(mac)))
+        .filter(|segment| filter.capture_point.as_ref().is_none_or(|point| segment.capture_points.contains(point)))
+        .collect();
+
+    Ok(Json(pagination.page(segments)?))
+}
@@ -0,0 +1,102 @@
+//! VLAN and subnet endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use chrono::{Duration, Utc};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope, Source};
+use crate::db::{SubnetMatrixFilter, VlanSubnetFilter};
+use crate::state::{composition, SubnetTraffic, VlanSnapshot, VlanSubnet};
+
+/// Hours of inter-subnet traffic summed by default and at most
+const DEFAULT_HOURS: i64 = 24;
+const MAX_HOURS: i64 = 24 * 90;
+
+/// `GET /api/vlans`
+pub async fn list(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Page<VlanSnapshot>>, ApiError> {
+    if pagination.source == Source::Db {
+        let (vlans, total) = api.db.list_vlans(scope.tenant().as_deref(), pagination.limit(), pagination.offset()?).await?;
+        return Ok(Json(pagination.wrap(vlans, total)));
+    }
+
+    let mut vlans: Vec<VlanSnapshot> = api.state.vlans.iter()
+        .filter(|entry| scope.includes(api.state.tenants.tenant_of(entry.key().0)))
+        .map(|entry| entry.value().snapshot())
+        .collect();
+    vlans.sort_by(|a, b| (&a.site, a.vlan_id, a.outer_vlan_id).cmp(&(&b.site, b.vlan_id, b.outer_vlan_id)));
+
+    Ok(Json(pagination.page(vlans)?))
+}
+
+/// `GET /api/vlans/subnets`
+///
+/// The subnets inferred on each VLAN, with their confidence and conflicts.
+pub async fn subnets(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<VlanSubnetFilter>,
+) -> Result<Json<Page<VlanSubnet>>, ApiError> {
+    filter.tenant = scope.tenant();
+    if pagination.source == Source::Db {
+        let (subnets, total) = api.db.list_vlan_subnets(&filter, pagination.limit(), pagination.offset()?).await?;
+        return Ok(Json(pagination.wrap(subnets, total)));
+    }
+
+    let mut subnets: Vec<VlanSubnet> = api.state.vlan_subnets.infer(api.vlan_subnet_prefix)
+        .into_iter()
+        .filter(|subnet| scope.sees(&api.state.tenants, Some(&subnet.site)) && matches(subnet, &filter))
+        .collect();
+    subnets.sort_by(|a, b| (&a.site, a.vlan_id, b.hosts).cmp(&(&b.site, b.vlan_id, a.hosts)));
+
+    Ok(Json(pagination.page(subnets)?))
+}
+
+/// `GET /api/subnets/matrix`
+///
+/// Traffic routed between subnets by the detected gateways over the last
+/// `hours` hours, per subnet pair, the heaviest first.
+pub async fn matrix(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<SubnetMatrixFilter>,
+) -> Result<Json<Page<SubnetTraffic>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let hours = filter.hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let since = Utc::now() - Duration::hours(hours);
+    if pagination.source == Source::Db {
+        let (pairs, total) = api.db.list_subnet_traffic(&filter, since, pagination.limit(), pagination.offset()?).await?;
+        return Ok(Json(pagination.wrap(pairs, total)));
+    }
+
+    let pairs: Vec<SubnetTraffic> = api.state.subnet_matrix.totals(composition::hour_of(since))
+        .into_iter()
+        .filter(|pair| scope.sees(&api.state.tenants, Some(&pair.site)))
+        .filter(|pair| filter.site.as_ref().is_none_or(|site| *site == pair.site))
+        .filter(|pair| filter.subnet.as_ref().is_none_or(|subnet| *subnet == pair.src_subnet || *subnet == pair.dst_subnet))
+        .collect();
+
+    Ok(Json(pagination.page(pairs)?))
+}
+
+/// Whether an inferred subnet passes `filter`
+fn matches(subnet: &VlanSubnet, filter: &VlanSubnetFilter) -> bool {
+    if filter.site.as_ref().is_some_and(|site| *site != subnet.site) {
+        return false;
+    }
+    if filter.vlan.is_some_and(|vlan| vlan != subnet.vlan_id) {
+        return false;
+    }
+    if filter.conflicts.is_some_and(|conflicts| conflicts == subnet.conflicts.is_empty()) {
+        return false;
+    }
+    if filter.ip.is_some_and(|ip| !subnet.contains(ip)) {
+        return false;
+    }
+    true
+}
@@ -0,0 +1,24 @@
+//! VoIP call endpoints
+
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+
+use super::{ApiError, ApiState, Page, Pagination, Scope};
+use crate::db::{SipCallFilter, StoredSipCall};
+
+/// `GET /api/voip/calls`
+///
+/// Calls followed from their SIP signaling, with the bandwidth of their
+/// media, most recently started first.
+pub async fn calls(
+    State(api): State<ApiState>,
+    Extension(scope): Extension<Scope>,
+    Query(pagination): Query<Pagination>,
+    Query(mut filter): Query<SipCallFilter>,
+) -> Result<Json<Page<StoredSipCall>>, ApiError> {
+    filter.tenant = scope.tenant();
+    let (calls, total) = api.db
+        .list_sip_calls(&filter, pagination.limit(), pagination.offset()?)
+        .await?;
+    Ok(Json(pagination.wrap(calls, total)))
+}
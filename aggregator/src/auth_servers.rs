@@ -0,0 +1,241 @@
+//! Authentication server mapping
+//!
+//! Windows infrastructure leans on a handful of servers: domain controllers
+//! answering Kerberos and LDAP, standalone directories, and SMB file
+//! servers. Every `interval_secs`, the service dependencies of the last
+//! `window_days` (see [`crate::state::dependency`]) are summed per server
+//! and authentication service; a server answering at least `min_clients`
+//! distinct clients over `min_flows` flows on a service provides it, and
+//! gets a role from the services it provides:
+//!
+//! - `domain_controller`: Kerberos and LDAP (or the global catalog)
+//! - `kerberos` or `ldap`: only one of them
+//! - `smb`: SMB shares, domain controllers included (SYSVOL, NETLOGON)
+//!
+//! Roles are stored in `auth_servers`, and a server no longer meeting the
+//! thresholds loses its role. Which clients depend on which servers is read
+//! from the dependencies on the services of their roles.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::types::Uuid;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::bandwidth::format_window;
+use crate::config::AuthServersConfig;
+use crate::db::Database;
+
+/// Authentication services, as named in the service dependencies
+pub const KERBEROS_SERVICES: &[&str] = &["tcp/88", "udp/88", "tcp/464", "udp/464"];
+pub const LDAP_SERVICES: &[&str] = &["tcp/389", "udp/389", "tcp/636", "tcp/3268", "tcp/3269"];
+pub const SMB_SERVICES: &[&str] = &["tcp/445", "tcp/139"];
+
+/// Role of an authentication server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthRole {
+    DomainController,
+    Kerberos,
+    Ldap,
+    Smb,
+}
+
+impl AuthRole {
+    pub const ALL: [AuthRole; 4] = [Self::DomainController, Self::Kerberos, Self::Ldap, Self::Smb];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DomainController => "domain_controller",
+            Self::Kerberos => "kerberos",
+            Self::Ldap => "ldap",
+            Self::Smb => "smb",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.as_str() == name)
+    }
+}
+
+/// All the authentication services
+pub fn auth_services() -> Vec<&'static str> {
+    [KERBEROS_SERVICES, LDAP_SERVICES, SMB_SERVICES].concat()
+}
+
+/// A server's activity on one authentication service
+#[derive(Debug, Clone)]
+pub struct ServiceActivity {
+    pub site: String,
+    pub server_mac: String,
+    pub device_id: Option<Uuid>,
+    pub service: String,
+    /// Distinct clients
+    pub clients: u64,
+    pub flows: u64,
+    pub bytes: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A device acting as an authentication server
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthServer {
+    pub site: String,
+    pub mac_address: String,
+    pub role: AuthRole,
+    /// Services of the role the server provides
+    pub services: Vec<String>,
+    /// Most distinct clients of one of the services
+    pub clients: u64,
+    pub flows: u64,
+    pub bytes: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    #[serde(skip)]
+    pub device_id: Option<Uuid>,
+}
+
+/// Roles of the servers in `activity`, counting the services answering at
+/// least `min_clients` clients over `min_flows` flows
+pub fn classify(activity: &[ServiceActivity], min_clients: u64, min_flows: u64) -> Vec<AuthServer> {
+    let mut servers: BTreeMap<(&str, &str), Vec<&ServiceActivity>> = BTreeMap::new();
+    for service in activity.iter().filter(|s| s.clients >= min_clients && s.flows >= min_flows) {
+        servers.entry((service.site.as_str(), service.server_mac.as_str())).or_default().push(service);
+    }
+
+    let mut classified = Vec::new();
+    for ((site, mac), services) in servers {
+        let of = |names: &[&str]| -> Vec<&ServiceActivity> {
+            services.iter().copied().filter(|s| names.contains(&s.service.as_str())).collect()
+        };
+        let (kerberos, ldap, smb) = (of(KERBEROS_SERVICES), of(LDAP_SERVICES), of(SMB_SERVICES));
+
+        let mut roles = Vec::new();
+        match (kerberos.is_empty(), ldap.is_empty()) {
+            (false, false) => roles.push((AuthRole::DomainController, [kerberos, ldap].concat())),
+            (false, true) => roles.push((AuthRole::Kerberos, kerberos)),
+            (true, false) => roles.push((AuthRole::Ldap, ldap)),
+            (true, true) => {}
+        }
+        if !smb.is_empty() {
+            roles.push((AuthRole::Smb, smb));
+        }
+
+        for (role, services) in roles {
+            let names: BTreeSet<&str> = services.iter().map(|s| s.service.as_str()).collect();
+            classified.push(AuthServer {
+                site: site.to_string(),
+                mac_address: mac.to_string(),
+                role,
+                services: names.into_iter().map(str::to_string).collect(),
+                clients: services.iter().map(|s| s.clients).max().unwrap_or(0),
+                flows: services.iter().map(|s| s.flows).sum(),
+                bytes: services.iter().map(|s| s.bytes).sum(),
+                first_seen: services.iter().map(|s| s.first_seen).min().unwrap_or_default(),
+                last_seen: services.iter().map(|s| s.last_seen).max().unwrap_or_default(),
+                device_id: services.iter().find_map(|s| s.device_id),
+            });
+        }
+    }
+
+    classified
+}
+
+/// Classifies authentication servers from the stored service dependencies
+pub struct AuthServerClassifier {
+    config: AuthServersConfig,
+    db: Arc<Database>,
+}
+
+impl AuthServerClassifier {
+    pub fn new(config: AuthServersConfig, db: Arc<Database>) -> Self {
+        Self { config, db }
+    }
+
+    /// Classify the servers from the dependencies active within the window
+    /// before `now`, returning the servers stored and the roles removed
+    pub async fn classify(&self, now: DateTime<Utc>) -> Result<(usize, u64)> {
+        let since = now - chrono::Duration::days(self.config.window_days as i64);
+        let activity = self.db.auth_service_activity(since, &auth_services()).await?;
+        let servers = classify(&activity, self.config.min_clients, self.config.min_flows);
+        let removed = self.db.replace_auth_servers(&servers, now).await?;
+        Ok((servers.len(), removed))
+    }
+
+    /// Classify until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Authentication server mapping enabled (every {}, over {} days)",
+            format_window(self.config.interval_secs),
+            self.config.window_days
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    match self.classify(Utc::now()).await {
+                        Ok((servers, removed)) => debug!("Classified {} authentication servers, {} roles removed", servers, removed),
+                        Err(e) => warn!("Authentication server mapping failed: {:#}", e),
+                    }
+                }
+            }
+        }
+
+        debug!("Authentication server mapping stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(mac: &str, service: &str, clients: u64, flows: u64) -> ServiceActivity {
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+        ServiceActivity {
+            site: "paris".to_string(),
+            server_mac: mac.to_string(),
+            device_id: None,
+            service: service.to_string(),
+            clients,
+            flows,
+            bytes: flows * 1000,
+            first_seen: at,
+            last_seen: at,
+        }
+    }
+
+    #[test]
+    fn test_classify_auth_servers() {
+        let dc = "00:11:22:33:44:01";
+        let files = "00:11:22:33:44:02";
+        let printer = "00:11:22:33:44:03";
+        let servers = classify(
+            &[
+                activity(dc, "tcp/88", 40, 900),
+                activity(dc, "udp/389", 45, 500),
+                activity(dc, "tcp/445", 30, 200),
+                activity(files, "tcp/445", 25, 400),
+                // Too few clients, then too few flows
+                activity(files, "tcp/389", 1, 50),
+                activity(printer, "tcp/445", 5, 3),
+            ],
+            3,
+            10,
+        );
+
+        let roles: Vec<(&str, AuthRole)> = servers.iter().map(|s| (s.mac_address.as_str(), s.role)).collect();
+        assert_eq!(roles, vec![(dc, AuthRole::DomainController), (dc, AuthRole::Smb), (files, AuthRole::Smb)]);
+        assert_eq!(servers[0].services, vec!["tcp/88", "udp/389"]);
+        assert_eq!((servers[0].clients, servers[0].flows), (45, 1400));
+        assert_eq!(AuthRole::from_name("domain_controller"), Some(AuthRole::DomainController));
+    }
+}
@@ -0,0 +1,429 @@
+//! Per-device bandwidth thresholds
+//!
+//! The flow byte counters are sampled every `sample_interval_secs` and the
+//! increments attributed to both endpoints' devices (sent by the source,
+//! received by the destination). Each threshold keeps a rolling window per
+//! device, split into buckets of a thirtieth of the window (at least one
+//! sample interval):
+//!
+//! - `max_mbps` is exceeded when every complete bucket of the window is
+//!   above the rate, i.e. the rate was sustained for the whole window;
+//! - `max_bytes` is exceeded when the window's total is above the volume.
+//!
+//! An alert, with the flows that contributed most in the window, is raised
+//! when a device crosses a threshold, and again only once it has dropped
+//! back below it.
+
+use chrono::Utc;
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::config::{BandwidthConfig, BandwidthDirection, BandwidthThreshold};
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, DeviceKey};
+
+/// Buckets per window
+const WINDOW_BUCKETS: u64 = 30;
+
+/// Traffic of one device during one bucket
+struct Bucket {
+    index: u64,
+    bytes: u64,
+    flows: HashMap<Uuid, u64>,
+}
+
+/// Buckets of a device's rolling window, oldest first
+#[derive(Default)]
+struct Window {
+    buckets: VecDeque<Bucket>,
+}
+
+impl Window {
+    fn add(&mut self, index: u64, bytes: u64, flow: Uuid) {
+        if self.buckets.back().is_none_or(|b| b.index != index) {
+            self.buckets.push_back(Bucket { index, bytes: 0, flows: HashMap::new() });
+        }
+        let bucket = self.buckets.back_mut().expect("bucket just pushed");
+        bucket.bytes += bytes;
+        *bucket.flows.entry(flow).or_default() += bytes;
+    }
+
+    fn in_range(&self, first: u64, last: u64) -> impl Iterator<Item = &Bucket> {
+        self.buckets.iter().filter(move |b| (first..=last).contains(&b.index))
+    }
+
+    /// Bytes per flow over the buckets `first..=last`, largest first
+    fn top_flows(&self, first: u64, last: u64, limit: usize) -> Vec<(Uuid, u64)> {
+        let mut flows: HashMap<Uuid, u64> = HashMap::new();
+        for bucket in self.in_range(first, last) {
+            for (id, bytes) in &bucket.flows {
+                *flows.entry(*id).or_default() += bytes;
+            }
+        }
+        let mut flows: Vec<_> = flows.into_iter().collect();
+        flows.sort_by_key(|f| std::cmp::Reverse(f.1));
+        flows.truncate(limit);
+        flows
+    }
+}
+
+/// A device over a threshold, pending its alert
+struct Breach {
+    threshold: usize,
+    device: DeviceKey,
+    /// Average Mbps (rate thresholds) or bytes (volume thresholds)
+    observed: f64,
+    flows: Vec<(Uuid, u64)>,
+}
+
+/// Whether `ip` is outside the private, loopback, link-local and multicast ranges
+pub(crate) fn is_external(ip: Ipv4Addr) -> bool {
+    !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_multicast()
+        || ip.is_broadcast() || ip.is_unspecified())
+}
+
+fn direction_name(direction: BandwidthDirection) -> &'static str {
+    match direction {
+        BandwidthDirection::Out => "out",
+        BandwidthDirection::In => "in",
+        BandwidthDirection::Both => "both",
+    }
+}
+
+/// `90s`, `5m`, `24h` style duration
+pub(crate) fn format_window(secs: u64) -> String {
+    match secs {
+        s if s.is_multiple_of(3600) => format!("{}h", s / 3600),
+        s if s.is_multiple_of(60) => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Decimal (SI) byte count
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Samples flow counters and checks the device thresholds
+pub struct BandwidthMonitor {
+    config: BandwidthConfig,
+    state: Arc<AggregatorState>,
+    /// Flow byte totals at the previous sample (`None` before the first)
+    flow_bytes: Option<HashMap<Uuid, u64>>,
+    windows: HashMap<(usize, DeviceKey), Window>,
+    /// Devices over a threshold at the previous sample
+    exceeded: HashSet<(usize, DeviceKey)>,
+}
+
+impl BandwidthMonitor {
+    pub fn new(config: BandwidthConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            flow_bytes: None,
+            windows: HashMap::new(),
+            exceeded: HashSet::new(),
+        }
+    }
+
+    /// Bucket length of `threshold` (seconds)
+    fn bucket_secs(&self, threshold: &BandwidthThreshold) -> u64 {
+        (threshold.window_secs / WINDOW_BUCKETS).max(self.config.sample_interval_secs)
+    }
+
+    /// Sample the flow counters at unix time `now` and return the alerts of
+    /// the devices that crossed a threshold
+    pub fn sample(&mut self, now: u64) -> Vec<Event> {
+        let thresholds: Vec<(&BandwidthThreshold, u64)> = self.config.thresholds.iter()
+            .map(|t| (t, self.bucket_secs(t)))
+            .collect();
+
+        // The first sample only sets the baseline: earlier traffic has no time reference
+        let previous = self.flow_bytes.take();
+        let mut totals = HashMap::with_capacity(previous.as_ref().map_or(0, |p| p.len()));
+
+        for flow in self.state.flows.iter() {
+            let total = flow.byte_count.load(Ordering::Relaxed);
+            totals.insert(flow.id, total);
+            let Some(previous) = &previous else { continue };
+            let delta = total.saturating_sub(previous.get(&flow.id).copied().unwrap_or(0));
+            if delta == 0 {
+                continue;
+            }
+
+            let key = &flow.key;
+            for (index, (threshold, bucket_secs)) in thresholds.iter().enumerate() {
+                if !threshold.vlan.is_empty() && !key.vlan_id.is_some_and(|v| threshold.vlan.contains(&v)) {
+                    continue;
+                }
+                let sides = [
+                    (key.src_device(), BandwidthDirection::Out, key.dst_ip),
+                    (key.dst_device(), BandwidthDirection::In, key.src_ip),
+                ];
+                for (device, direction, peer) in sides {
+                    if threshold.direction != BandwidthDirection::Both && threshold.direction != direction {
+                        continue;
+                    }
+                    if threshold.external_only && !peer.is_some_and(is_external) {
+                        continue;
+                    }
+                    if !threshold.mac.is_empty() {
+                        let mac = device.mac.to_string();
+                        if !threshold.mac.iter().any(|p| mac.starts_with(&p.to_ascii_lowercase())) {
+                            continue;
+                        }
+                    }
+                    self.windows.entry((index, device)).or_default().add(now / bucket_secs, delta, flow.id);
+                }
+            }
+        }
+        self.flow_bytes = Some(totals);
+
+        let mut breaches = Vec::new();
+        let mut exceeded = HashSet::new();
+        for (&(index, device), window) in self.windows.iter_mut() {
+            let (threshold, bucket_secs) = thresholds[index];
+            let buckets = (threshold.window_secs / bucket_secs).max(1);
+            let current = now / bucket_secs;
+            while window.buckets.front().is_some_and(|b| b.index + buckets < current) {
+                window.buckets.pop_front();
+            }
+
+            // Rates use complete buckets only; volumes include the current one
+            let (first, last, observed) = if let Some(max_mbps) = threshold.max_mbps {
+                let (first, last) = (current.saturating_sub(buckets), current.saturating_sub(1));
+                let mbps: Vec<f64> = window.in_range(first, last)
+                    .map(|b| b.bytes as f64 * 8.0 / bucket_secs as f64 / 1_000_000.0)
+                    .collect();
+                if current < buckets || mbps.len() as u64 != buckets || mbps.iter().any(|r| *r <= max_mbps) {
+                    continue;
+                }
+                (first, last, mbps.iter().sum::<f64>() / mbps.len() as f64)
+            } else {
+                let first = (current + 1).saturating_sub(buckets);
+                let bytes: u64 = window.in_range(first, current).map(|b| b.bytes).sum();
+                if threshold.max_bytes.is_none_or(|max| bytes <= max) {
+                    continue;
+                }
+                (first, current, bytes as f64)
+            };
+
+            exceeded.insert((index, device));
+            if !self.exceeded.contains(&(index, device)) {
+                let flows = window.top_flows(first, last, self.config.breakdown_flows);
+                breaches.push(Breach { threshold: index, device, observed, flows });
+            }
+        }
+        self.windows.retain(|_, window| !window.buckets.is_empty());
+        self.exceeded = exceeded;
+
+        breaches.into_iter().map(|breach| self.alert(breach)).collect()
+    }
+
+    /// Alert for a device that crossed a threshold
+    fn alert(&self, breach: Breach) -> Event {
+        let threshold = &self.config.thresholds[breach.threshold];
+        let mac = breach.device.mac.to_string();
+        let window = format_window(threshold.window_secs);
+        let peers = if threshold.external_only { " with external hosts" } else { "" };
+
+        let message = match threshold.max_mbps {
+            Some(max_mbps) => format!(
+                "Device {} sustained {:.1} Mbps{} for {} (threshold {} Mbps)",
+                mac, breach.observed, peers, window, max_mbps
+            ),
+            None => format!(
+                "Device {} exchanged {}{} in {} (threshold {})",
+                mac,
+                format_bytes(breach.observed),
+                peers,
+                window,
+                format_bytes(threshold.max_bytes.unwrap_or_default() as f64)
+            ),
+        };
+
+        let ids: HashMap<Uuid, u64> = breach.flows.iter().copied().collect();
+        let mut flows: Vec<_> = self.state.flows.iter()
+            .filter_map(|flow| {
+                let bytes = *ids.get(&flow.id)?;
+                Some(json!({
+                    "flow": flow.key.to_display_string(),
+                    "src_mac": flow.key.src_mac.to_string(),
+                    "dst_mac": flow.key.dst_mac.to_string(),
+                    "vlan_id": flow.key.vlan_id,
+                    "bytes": bytes,
+                }))
+            })
+            .collect();
+        flows.sort_by_key(|f| std::cmp::Reverse(f["bytes"].as_u64()));
+
+        let details = json!({
+            "threshold": threshold.name,
+            "window_secs": threshold.window_secs,
+            "direction": direction_name(threshold.direction),
+            "external_only": threshold.external_only,
+            "max_mbps": threshold.max_mbps,
+            "max_bytes": threshold.max_bytes,
+            "observed": breach.observed,
+            "flows": flows,
+        });
+
+        let ip = self.state.devices.get(&breach.device).and_then(|d| d.ips.iter().next().map(|ip| *ip.key()));
+
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: threshold.severity,
+            name: threshold.name.clone(),
+            message,
+            site: Some(breach.device.site.to_string()),
+            sensor: None,
+            mac: Some(mac),
+            ip,
+            channels: threshold.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Sample until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Checking {} bandwidth thresholds every {}s",
+            self.config.thresholds.len(),
+            self.config.sample_interval_secs
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.sample_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.sample(Utc::now().timestamp() as u64) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Bandwidth monitor stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use crate::state::{FlowKey, FlowState, MacAddr, SensorId, SiteId};
+
+
+    fn threshold(max_mbps: Option<f64>, max_bytes: Option<u64>) -> BandwidthThreshold {
+        BandwidthThreshold {
+            name: "bandwidth".to_string(),
+            severity: Severity::High,
+            max_mbps,
+            max_bytes,
+            window_secs: 60,
+            direction: BandwidthDirection::Out,
+            external_only: true,
+            mac: Vec::new(),
+            vlan: Vec::new(),
+            notify: Vec::new(),
+        }
+    }
+
+    fn monitor(threshold: BandwidthThreshold) -> (BandwidthMonitor, Arc<AggregatorState>) {
+        let state = Arc::new(AggregatorState::new());
+        let config = BandwidthConfig {
+            enabled: true,
+            sample_interval_secs: 10,
+            breakdown_flows: 10,
+            thresholds: vec![threshold],
+        };
+        (BandwidthMonitor::new(config, Arc::clone(&state)), state)
+    }
+
+    fn add_flow(state: &AggregatorState, dst_ip: Ipv4Addr) -> FlowKey {
+        let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            dst_mac: MacAddr::new([0x00, 0x66, 0x77, 0x88, 0x99, 0xaa]),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+            dst_ip: Some(dst_ip),
+            src_port: Some(50000),
+            dst_port: Some(443),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        };
+        state.flows.insert(key.clone(), FlowState::new(key.clone(), Utc::now()));
+        key
+    }
+
+    #[test]
+    fn test_volume_threshold() {
+        let (mut monitor, state) = monitor(threshold(None, Some(5000)));
+        let external = add_flow(&state, Ipv4Addr::new(8, 8, 8, 8));
+        let internal = add_flow(&state, Ipv4Addr::new(10, 0, 0, 1));
+        state.flows.get(&external).unwrap().update(100_000, None, 0);
+        assert!(monitor.sample(1000).is_empty());
+
+        for (now, bytes) in [(1010, 3000), (1020, 3000)] {
+            state.flows.get(&external).unwrap().update(bytes, None, 0);
+            state.flows.get(&internal).unwrap().update(50_000, None, 0);
+            let alerts = monitor.sample(now);
+            if now == 1010 {
+                assert!(alerts.is_empty());
+                continue;
+            }
+
+            assert_eq!(alerts.len(), 1);
+            let Event::Alert { mac, message, details, .. } = &alerts[0] else { panic!() };
+            assert_eq!(mac.as_deref(), Some("00:11:22:33:44:55"));
+            assert_eq!(message, "Device 00:11:22:33:44:55 exchanged 6.0 KB with external hosts in 1m (threshold 5.0 KB)");
+            let flows = details.as_ref().unwrap()["flows"].as_array().unwrap();
+            assert_eq!(flows.len(), 1);
+            assert_eq!(flows[0]["bytes"], 6000);
+        }
+
+        // Still over the threshold: no new alert
+        state.flows.get(&external).unwrap().update(10, None, 0);
+        assert!(monitor.sample(1030).is_empty());
+    }
+
+    #[test]
+    fn test_sustained_rate() {
+        // 0.0008 Mbps over 10s buckets = 1000 bytes per bucket
+        let (mut monitor, state) = monitor(threshold(Some(0.0008), None));
+        let key = add_flow(&state, Ipv4Addr::new(1, 1, 1, 1));
+        monitor.sample(0);
+
+        for now in (10..=60).step_by(10) {
+            state.flows.get(&key).unwrap().update(1500, None, 0);
+            assert!(monitor.sample(now).is_empty(), "alert at {}", now);
+        }
+
+        state.flows.get(&key).unwrap().update(1500, None, 0);
+        let alerts = monitor.sample(70);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Device 00:11:22:33:44:55 sustained 0.0 Mbps with external hosts for 1m (threshold 0.0008 Mbps)");
+    }
+}
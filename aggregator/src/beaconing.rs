@@ -0,0 +1,322 @@
+//! Beaconing detection
+//!
+//! Malware checking in with its command-and-control server tends to open
+//! small connections to the same destination at a fixed period, with a
+//! little jitter. Every new flow from an internal device to an external
+//! destination (IP and port) is recorded as a connection; once enough have
+//! been seen, the intervals between them are scored for regularity:
+//!
+//! - the period is the median interval;
+//! - the jitter is the median absolute deviation from it;
+//! - the score is `1 - jitter / period`, so 1.0 is perfectly periodic.
+//!
+//! Medians keep a missed or an extra check-in from hiding an otherwise
+//! regular beacon. Destinations that move more than `max_bytes_per_connection`
+//! on average are skipped as bulk traffic. Alerts carry the destination as
+//! their IP, so each beaconing destination is its own alert.
+//!
+//! Beacons that reuse one long-lived connection, or the same source port
+//! within the flow timeout, appear as a single flow and are not seen here.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::bandwidth::is_external;
+use crate::config::BeaconingConfig;
+use crate::events::{self, Event, EventSender};
+use crate::state::{AggregatorState, FlowKey, MacAddr, SensorId, SiteId};
+
+/// Connections kept per device and destination
+const MAX_CONNECTIONS: usize = 64;
+
+/// How often idle destinations are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Device and external destination
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Pair {
+    site: String,
+    mac: String,
+    dst_ip: Ipv4Addr,
+    dst_port: Option<u16>,
+}
+
+/// Connections seen for one pair, oldest first
+#[derive(Default)]
+struct History {
+    connections: VecDeque<(DateTime<Utc>, FlowKey)>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+/// Regularity of a series of connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Periodicity {
+    pub period_secs: f64,
+    pub jitter_secs: f64,
+    pub score: f64,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Score the intervals between `times` (in order); needs at least three
+pub fn periodicity(times: &[DateTime<Utc>]) -> Option<Periodicity> {
+    if times.len() < 3 {
+        return None;
+    }
+
+    let mut intervals: Vec<f64> = times.windows(2)
+        .map(|w| (w[1] - w[0]).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    let period_secs = median(&mut intervals);
+    if period_secs <= 0.0 {
+        return None;
+    }
+    let mut deviations: Vec<f64> = intervals.iter().map(|i| (i - period_secs).abs()).collect();
+    let jitter_secs = median(&mut deviations);
+
+    Some(Periodicity {
+        period_secs,
+        jitter_secs,
+        score: (1.0 - jitter_secs / period_secs).max(0.0),
+    })
+}
+
+/// Scores device-to-external-destination connections for beaconing
+pub struct BeaconDetector {
+    config: BeaconingConfig,
+    state: Arc<AggregatorState>,
+    history: HashMap<Pair, History>,
+}
+
+impl BeaconDetector {
+    pub fn new(config: BeaconingConfig, state: Arc<AggregatorState>) -> Self {
+        Self { config, state, history: HashMap::new() }
+    }
+
+    /// Flow key and pair of an outbound new-flow event
+    fn outbound(&self, event: &Event) -> Option<(Pair, FlowKey)> {
+        let Event::NewFlow {
+            site, sensor, src_mac, dst_mac, src_ip, dst_ip, src_port, dst_port, vlan_id, protocol,
+            icmp_type, icmp_code, icmp_id, ..
+        } = event else {
+            return None;
+        };
+        let (src, dst) = (src_ip.as_ref()?, dst_ip.as_ref()?);
+        if is_external(*src) || !is_external(*dst) {
+            return None;
+        }
+        if self.config.ignore_destinations.iter().any(|c| c.contains(*dst)) {
+            return None;
+        }
+
+        let key = FlowKey {
+            site: SiteId::new(site)?,
+            sensor: SensorId::new(sensor)?,
+            src_mac: MacAddr::from_string(src_mac)?,
+            dst_mac: MacAddr::from_string(dst_mac)?,
+            src_ip: Some(*src),
+            dst_ip: Some(*dst),
+            src_port: *src_port,
+            dst_port: *dst_port,
+            vlan_id: *vlan_id,
+            protocol: *protocol,
+            icmp_type: *icmp_type,
+            icmp_code: *icmp_code,
+            icmp_id: *icmp_id,
+        };
+        Some((Pair { site: site.clone(), mac: src_mac.clone(), dst_ip: *dst, dst_port: *dst_port }, key))
+    }
+
+    /// Average bytes of the connections still in the flow state
+    fn average_bytes(&self, history: &History) -> Option<u64> {
+        let bytes: Vec<u64> = history.connections.iter()
+            .filter_map(|(_, key)| self.state.flows.get(key).map(|f| f.byte_count.load(Ordering::Relaxed)))
+            .collect();
+        (!bytes.is_empty()).then(|| bytes.iter().sum::<u64>() / bytes.len() as u64)
+    }
+
+    /// Record a new flow, returning an alert if its destination is beaconing
+    pub fn observe(&mut self, event: &Event) -> Option<Event> {
+        let (pair, key) = self.outbound(event)?;
+        let now = event.timestamp();
+        let oldest = now - chrono::Duration::seconds(self.config.history_secs as i64);
+
+        let history = self.history.entry(pair.clone()).or_default();
+        history.connections.retain(|(t, _)| *t >= oldest);
+        history.connections.push_back((now, key));
+        if history.connections.len() > MAX_CONNECTIONS {
+            history.connections.pop_front();
+        }
+        if history.connections.len() < self.config.min_connections {
+            return None;
+        }
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        if history.last_alert.is_some_and(|at| now - at < cooldown) {
+            return None;
+        }
+
+        let times: Vec<DateTime<Utc>> = history.connections.iter().map(|(t, _)| *t).collect();
+        let found = periodicity(&times)?;
+        let period_range = self.config.min_period_secs as f64..=self.config.max_period_secs as f64;
+        if found.score < self.config.min_score || !period_range.contains(&found.period_secs) {
+            return None;
+        }
+
+        let history = &self.history[&pair];
+        let average_bytes = self.average_bytes(history);
+        if self.config.max_bytes_per_connection > 0
+            && average_bytes.is_some_and(|b| b > self.config.max_bytes_per_connection)
+        {
+            debug!("{} -> {} is periodic but too large for a beacon", pair.mac, pair.dst_ip);
+            return None;
+        }
+        let connections = history.connections.len();
+        let src_ip = history.connections.back().and_then(|(_, key)| key.src_ip);
+        self.history.get_mut(&pair).expect("pair just recorded").last_alert = Some(now);
+
+        let destination = match pair.dst_port {
+            Some(port) => format!("{}:{}", pair.dst_ip, port),
+            None => pair.dst_ip.to_string(),
+        };
+        Some(Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message: format!(
+                "{} beaconing to {} every {:.0}s (jitter {:.1}s, score {:.2}, {} connections)",
+                pair.mac, destination, found.period_secs, found.jitter_secs, found.score, connections
+            ),
+            site: Some(pair.site.clone()),
+            sensor: None,
+            mac: Some(pair.mac.clone()),
+            ip: Some(pair.dst_ip),
+            channels: self.config.notify.clone(),
+            details: Some(json!({
+                "src_ip": src_ip,
+                "dst_ip": pair.dst_ip,
+                "dst_port": pair.dst_port,
+                "period_secs": found.period_secs,
+                "jitter_secs": found.jitter_secs,
+                "score": found.score,
+                "connections": connections,
+                "average_bytes": average_bytes,
+            })),
+        })
+    }
+
+    /// Forget destinations without a connection in the history window
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let oldest = now - chrono::Duration::seconds(self.config.history_secs as i64);
+        self.history.retain(|_, h| h.connections.back().is_some_and(|(t, _)| *t >= oldest));
+    }
+
+    /// Score new flows until shutdown, sending alerts on `output`
+    pub async fn run(mut self, mut events: broadcast::Receiver<Event>, output: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Beaconing detection enabled (period {}-{}s, min score {})",
+            self.config.min_period_secs, self.config.max_period_secs, self.config.min_score
+        );
+
+        let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => self.prune(Utc::now()),
+                event = events::recv(&mut events, "Beaconing detection") => {
+                    let Some(event) = event else { break };
+                    if let Some(alert) = self.observe(&event) {
+                        let _ = output.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Beaconing detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> BeaconDetector {
+        let config: BeaconingConfig = toml::from_str(r#"
+min_connections = 6
+ignore_destinations = ["203.0.113.0/24"]
+"#).unwrap();
+        BeaconDetector::new(config, Arc::new(AggregatorState::new()))
+    }
+
+    fn connection(at: DateTime<Utc>, src_port: u16, dst: [u8; 4]) -> Event {
+        let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            dst_mac: MacAddr::new([0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0x01]),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+            dst_ip: Some(Ipv4Addr::from(dst)),
+            src_port: Some(src_port),
+            dst_port: Some(443),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        };
+        Event::new_flow(&key, at)
+    }
+
+    #[test]
+    fn test_periodicity() {
+        let start = Utc::now();
+        let at = |secs: f64| start + chrono::Duration::milliseconds((secs * 1000.0) as i64);
+
+        // 60s beacon with +-2s jitter and one missed check-in
+        let times: Vec<_> = [0.0, 61.0, 119.0, 180.0, 242.0, 360.0, 419.0, 481.0].into_iter().map(at).collect();
+        let found = periodicity(&times).unwrap();
+        assert_eq!(found.period_secs, 61.0);
+        assert!(found.score > 0.95, "{:?}", found);
+
+        // Irregular browsing
+        let times: Vec<_> = [0.0, 5.0, 200.0, 230.0, 900.0, 910.0].into_iter().map(at).collect();
+        assert!(periodicity(&times).unwrap().score < 0.5);
+    }
+
+    #[test]
+    fn test_beacon_alert() {
+        let mut detector = detector();
+        let start = Utc::now();
+
+        let mut alerts = Vec::new();
+        for i in 0..10u16 {
+            let at = start + chrono::Duration::seconds(300 * i as i64 + (i % 3) as i64);
+            alerts.extend(detector.observe(&connection(at, 40000 + i, [198, 51, 100, 7])));
+            // Ignored destination with the same pattern
+            assert!(detector.observe(&connection(at, 50000 + i, [203, 0, 113, 9])).is_none());
+        }
+
+        // Alerts once enough connections are seen, then waits for the cooldown
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { ip, message, details, .. } = &alerts[0] else { panic!() };
+        assert_eq!(*ip, Some(Ipv4Addr::new(198, 51, 100, 7)));
+        assert!(message.starts_with("00:11:22:33:44:55 beaconing to 198.51.100.7:443 every 301s"), "{}", message);
+        assert_eq!(details.as_ref().unwrap()["connections"], 6);
+    }
+}
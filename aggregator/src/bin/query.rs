@@ -0,0 +1,476 @@
+//! NetSentinel Query - Inventory queries from the command line
+//!
+//! Answers the common questions about the inventory (devices on a VLAN,
+//! flows of a device, top talkers) through the aggregator HTTP API, or
+//! straight from PostgreSQL with `--db`, as a table, JSON or CSV.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+use std::fmt::Write;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use netsentinel_aggregator::api::{Pagination, Source};
+use netsentinel_aggregator::bandwidth::format_bytes;
+use netsentinel_aggregator::config::Config;
+use netsentinel_aggregator::db::{Cursor, Database, DeviceFilter, FlowFilter, TopBy};
+
+/// Largest page the API returns
+const API_PAGE_SIZE: usize = 1000;
+
+/// NetSentinel inventory queries
+#[derive(Parser, Debug)]
+#[command(name = "netsentinel-query")]
+#[command(author = "SecuAAS")]
+#[command(version)]
+#[command(about = "Queries the NetSentinel inventory", long_about = None)]
+struct Args {
+    /// Base URL of the aggregator HTTP API
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8081")]
+    api: String,
+
+    /// Query PostgreSQL directly, with the database of the aggregator configuration
+    #[arg(long, global = true)]
+    db: bool,
+
+    /// Path to the aggregator configuration file (with --db)
+    #[arg(short, long, global = true, default_value = "/opt/netsentinel/config/aggregator.toml")]
+    config: PathBuf,
+
+    /// Where the API reads from: memory (live state) or db
+    #[arg(long, global = true, default_value = "memory", conflicts_with = "db")]
+    source: Source,
+
+    /// Output format: table, json or csv
+    #[arg(short, long, global = true, default_value = "table")]
+    format: OutputFormat,
+
+    /// Maximum number of results
+    #[arg(short = 'n', long, global = true, default_value_t = 100)]
+    limit: usize,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List devices, most recently seen first
+    Devices {
+        #[arg(long)]
+        site: Option<String>,
+
+        /// Seen on this VLAN
+        #[arg(long)]
+        vlan: Option<u16>,
+
+        /// Holding this IP address
+        #[arg(long)]
+        ip: Option<Ipv4Addr>,
+
+        /// With this OUI prefix, e.g. 00:11:22
+        #[arg(long)]
+        oui: Option<String>,
+
+        /// Only devices seen within the inactivity timeout
+        #[arg(long)]
+        active: bool,
+
+        /// Only gateways
+        #[arg(long)]
+        gateway: bool,
+    },
+
+    /// List flows, most recently seen first
+    Flows {
+        #[arg(long)]
+        site: Option<String>,
+
+        /// Flows from or to this MAC address
+        #[arg(long)]
+        device: Option<String>,
+
+        /// Flows from or to this IP address
+        #[arg(long)]
+        ip: Option<Ipv4Addr>,
+
+        /// Flows from or to this port
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// IP protocol number
+        #[arg(long)]
+        protocol: Option<u8>,
+
+        #[arg(long)]
+        vlan: Option<u16>,
+
+        /// Only flows seen in this window, e.g. 30m, 1h, 7d
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+    },
+
+    /// Devices that sent and received the most traffic
+    Top {
+        /// Counter to rank by: bytes or packets
+        #[arg(long, default_value = "bytes")]
+        by: TopBy,
+
+        #[arg(long)]
+        site: Option<String>,
+
+        #[arg(long)]
+        vlan: Option<u16>,
+    },
+}
+
+/// Output format of the results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            other => bail!("Unknown output format '{}' (table, json or csv)", other),
+        }
+    }
+}
+
+/// Where the results come from
+enum Backend {
+    Api { client: reqwest::Client, url: String, source: Source },
+    Db { db: Database, inactivity_timeout: u64 },
+}
+
+impl Backend {
+    async fn devices(&self, filter: &DeviceFilter, limit: usize) -> Result<Vec<Value>> {
+        match self {
+            Backend::Api { .. } => self.pages("/api/devices", filter, limit).await,
+            Backend::Db { db, inactivity_timeout } => {
+                let (devices, _) = db.list_devices(filter, *inactivity_timeout, limit, Cursor::default()).await?;
+                to_values(devices)
+            }
+        }
+    }
+
+    async fn flows(&self, filter: &FlowFilter, limit: usize) -> Result<Vec<Value>> {
+        match self {
+            Backend::Api { .. } => self.pages("/api/flows", filter, limit).await,
+            Backend::Db { db, .. } => {
+                let (flows, _) = db.list_flows(filter, limit, Cursor::default()).await?;
+                to_values(flows)
+            }
+        }
+    }
+
+    async fn top(&self, filter: &DeviceFilter, by: TopBy, limit: usize) -> Result<Vec<Value>> {
+        match self {
+            Backend::Api { client, url, source } => {
+                let pagination = Pagination { limit: Some(limit), source: *source, ..Default::default() };
+                let body = get(client.get(format!("{}/api/top", url)).query(&pagination).query(filter).query(&[("by", by)])).await?;
+                match body {
+                    Value::Array(devices) => Ok(devices),
+                    _ => bail!("Unexpected response from {}/api/top", url),
+                }
+            }
+            Backend::Db { db, inactivity_timeout } => {
+                to_values(db.top_devices(filter, *inactivity_timeout, by, limit).await?)
+            }
+        }
+    }
+
+    /// Up to `limit` items of a paginated API endpoint
+    async fn pages<F: serde::Serialize>(&self, path: &str, filter: &F, limit: usize) -> Result<Vec<Value>> {
+        let Backend::Api { client, url, source } = self else {
+            unreachable!("pages() is only used with the API");
+        };
+
+        let mut items = Vec::new();
+        let mut cursor = None;
+        while items.len() < limit {
+            let pagination = Pagination {
+                limit: Some((limit - items.len()).min(API_PAGE_SIZE)),
+                cursor,
+                source: *source,
+                ..Default::default()
+            };
+            let page = get(client.get(format!("{}{}", url, path)).query(&pagination).query(filter)).await?;
+            cursor = page["next_cursor"].as_str().map(Cursor::from_str).transpose()?;
+            let Some(Value::Array(page)) = page.get("items").cloned() else {
+                bail!("Unexpected response from {}{}", url, path);
+            };
+
+            items.extend(page);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Send an API request and decode its JSON body
+async fn get(request: reqwest::RequestBuilder) -> Result<Value> {
+    let response = request.send().await.with_context(|| "Failed to reach the aggregator API")?;
+    let status = response.status();
+    let body: Value = response.json().await.with_context(|| "Invalid response from the aggregator API")?;
+    if !status.is_success() {
+        let message = body["error"].as_str().unwrap_or("unknown error");
+        bail!("Aggregator API returned {}: {}", status, message);
+    }
+    Ok(body)
+}
+
+fn to_values<T: serde::Serialize>(items: Vec<T>) -> Result<Vec<Value>> {
+    items.into_iter().map(|item| serde_json::to_value(item).map_err(Into::into)).collect()
+}
+
+/// Start of a `30s`, `15m`, `1h`, `7d` window ending now
+fn parse_since(s: &str) -> Result<DateTime<Utc>> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| anyhow!("Invalid window '{}' (e.g. 30m, 1h, 7d)", s))?;
+    let window = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" | "" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        _ => None,
+    };
+    let window = window.ok_or_else(|| anyhow!("Invalid window '{}' (e.g. 30m, 1h, 7d)", s))?;
+    Ok(Utc::now() - window)
+}
+
+/// A column of the table and CSV outputs
+struct Column {
+    header: &'static str,
+    value: fn(&Value) -> String,
+    /// Byte count, shown in readable units in tables
+    bytes: bool,
+}
+
+impl Column {
+    const fn new(header: &'static str, value: fn(&Value) -> String) -> Self {
+        Self { header, value, bytes: false }
+    }
+
+    const fn bytes(header: &'static str, value: fn(&Value) -> String) -> Self {
+        Self { header, value, bytes: true }
+    }
+
+    fn display(&self, item: &Value) -> String {
+        let value = (self.value)(item);
+        match value.parse::<f64>() {
+            Ok(bytes) if self.bytes => format_bytes(bytes),
+            _ => value,
+        }
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn join(values: &Value, field: Option<&str>) -> String {
+    values.as_array().into_iter().flatten()
+        .map(|value| text(field.map_or(value, |field| &value[field])))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn sum(item: &Value, a: &str, b: &str) -> String {
+    (item[a].as_u64().unwrap_or(0) + item[b].as_u64().unwrap_or(0)).to_string()
+}
+
+const DEVICE_COLUMNS: &[Column] = &[
+    Column::new("mac", |d| text(&d["mac_address"])),
+    Column::new("site", |d| text(&d["site"])),
+    Column::new("ips", |d| join(&d["ip_addresses"], Some("ip_address"))),
+    Column::new("vlans", |d| join(&d["vlans"], None)),
+    Column::new("gateway", |d| text(&d["is_gateway"])),
+    Column::new("confidence", |d| text(&d["confidence"])),
+    Column::bytes("bytes_sent", |d| text(&d["bytes_sent"])),
+    Column::bytes("bytes_received", |d| text(&d["bytes_received"])),
+    Column::new("last_seen", |d| text(&d["last_seen"])),
+];
+
+const FLOW_COLUMNS: &[Column] = &[
+    Column::new("site", |f| text(&f["site"])),
+    Column::new("src_mac", |f| text(&f["src_mac"])),
+    Column::new("src_ip", |f| text(&f["src_ip"])),
+    Column::new("src_port", |f| text(&f["src_port"])),
+    Column::new("dst_mac", |f| text(&f["dst_mac"])),
+    Column::new("dst_ip", |f| text(&f["dst_ip"])),
+    Column::new("dst_port", |f| text(&f["dst_port"])),
+    Column::new("protocol", |f| text(&f["ip_protocol"])),
+    Column::new("vlan", |f| text(&f["vlan_id"])),
+    Column::new("packets", |f| text(&f["packet_count"])),
+    Column::bytes("bytes", |f| text(&f["byte_count"])),
+    Column::new("last_seen", |f| text(&f["last_seen"])),
+];
+
+const TOP_BYTES_COLUMNS: &[Column] = &[
+    Column::new("mac", |d| text(&d["mac_address"])),
+    Column::new("site", |d| text(&d["site"])),
+    Column::new("ips", |d| join(&d["ip_addresses"], Some("ip_address"))),
+    Column::bytes("bytes", |d| sum(d, "bytes_sent", "bytes_received")),
+    Column::bytes("bytes_sent", |d| text(&d["bytes_sent"])),
+    Column::bytes("bytes_received", |d| text(&d["bytes_received"])),
+];
+
+const TOP_PACKETS_COLUMNS: &[Column] = &[
+    Column::new("mac", |d| text(&d["mac_address"])),
+    Column::new("site", |d| text(&d["site"])),
+    Column::new("ips", |d| join(&d["ip_addresses"], Some("ip_address"))),
+    Column::new("packets", |d| sum(d, "packets_sent", "packets_received")),
+    Column::new("packets_sent", |d| text(&d["packets_sent"])),
+    Column::new("packets_received", |d| text(&d["packets_received"])),
+];
+
+/// Aligned columns, with byte counts in readable units
+fn render_table(columns: &[Column], items: &[Value]) -> String {
+    let rows: Vec<Vec<String>> = items.iter()
+        .map(|item| columns.iter().map(|column| column.display(item)).collect())
+        .collect();
+    let widths: Vec<usize> = columns.iter().enumerate()
+        .map(|(i, column)| rows.iter().map(|row| row[i].len()).chain([column.header.len()]).max().unwrap_or(0))
+        .collect();
+
+    let mut out = String::new();
+    let headers = columns.iter().map(|column| column.header.to_uppercase());
+    for row in std::iter::once(headers.collect()).chain(rows) {
+        let line: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        let _ = writeln!(out, "{}", line.join("  ").trim_end());
+    }
+    out
+}
+
+/// RFC 4180 CSV, with raw values
+fn render_csv(columns: &[Column], items: &[Value]) -> String {
+    let mut out = String::new();
+    let headers: Vec<&str> = columns.iter().map(|column| column.header).collect();
+    let _ = writeln!(out, "{}", headers.join(","));
+    for item in items {
+        let row: Vec<String> = columns.iter().map(|column| csv_field(&(column.value)(item))).collect();
+        let _ = writeln!(out, "{}", row.join(","));
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render(format: OutputFormat, columns: &[Column], items: Vec<Value>) -> Result<String> {
+    Ok(match format {
+        OutputFormat::Table => render_table(columns, &items),
+        OutputFormat::Csv => render_csv(columns, &items),
+        OutputFormat::Json => serde_json::to_string_pretty(&items)? + "\n",
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let backend = if args.db {
+        let config = Config::from_file(&args.config)?;
+        Backend::Db {
+            db: Database::connect(&config.database).await?,
+            inactivity_timeout: config.aggregation.inactivity_timeout,
+        }
+    } else {
+        Backend::Api {
+            client: reqwest::Client::new(),
+            url: args.api.trim_end_matches('/').to_string(),
+            source: args.source,
+        }
+    };
+
+    let output = match args.command {
+        Command::Devices { site, vlan, ip, oui, active, gateway } => {
+            let filter = DeviceFilter {
+                site,
+                vlan,
+                ip,
+                oui,
+                active: active.then_some(true),
+                gateway: gateway.then_some(true),
+                ..Default::default()
+            };
+            render(args.format, DEVICE_COLUMNS, backend.devices(&filter, args.limit).await?)?
+        }
+        Command::Flows { site, device, ip, port, protocol, vlan, since } => {
+            let filter = FlowFilter { site, mac: device, ip, port, protocol, vlan, since, ..Default::default() };
+            render(args.format, FLOW_COLUMNS, backend.flows(&filter, args.limit).await?)?
+        }
+        Command::Top { by, site, vlan } => {
+            let filter = DeviceFilter { site, vlan, ..Default::default() };
+            let columns = match by {
+                TopBy::Bytes => TOP_BYTES_COLUMNS,
+                TopBy::Packets => TOP_PACKETS_COLUMNS,
+            };
+            render(args.format, columns, backend.top(&filter, by, args.limit).await?)?
+        }
+    };
+
+    print!("{}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_since() {
+        let since = parse_since("90m").unwrap();
+        let expected = Utc::now() - Duration::minutes(90);
+        assert!((since - expected).num_seconds().abs() <= 1);
+
+        assert!(parse_since("2d").is_ok());
+        assert!(parse_since("h").is_err());
+        assert!(parse_since("1w").is_err());
+    }
+
+    #[test]
+    fn test_render_outputs() {
+        let devices = vec![json!({
+            "mac_address": "00:11:22:33:44:55",
+            "site": "paris, hq",
+            "ip_addresses": [{ "ip_address": "10.0.0.5" }, { "ip_address": "10.0.1.5" }],
+            "bytes_sent": 1500,
+            "bytes_received": 500,
+        })];
+
+        let table = render_table(TOP_BYTES_COLUMNS, &devices);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("MAC                SITE       IPS"));
+        assert!(lines[1].contains("10.0.0.5 10.0.1.5  2.0 KB"));
+
+        let csv = render_csv(TOP_BYTES_COLUMNS, &devices);
+        assert_eq!(csv, "mac,site,ips,bytes,bytes_sent,bytes_received\n\
+                         00:11:22:33:44:55,\"paris, hq\",10.0.0.5 10.0.1.5,2000,1500,500\n");
+    }
+}
@@ -0,0 +1,201 @@
+//! Daily asset change report
+//!
+//! Once a day, diffs the persisted inventory over the period since the
+//! previous report: devices that appeared or went quiet, services known
+//! devices started using and IP addresses they moved to. Changes of a device
+//! while it, or one of its VLANs, was under maintenance are left out. Each
+//! report is stored in the `change_reports` table and, optionally, sent to
+//! the notification sinks.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::ChangeReportConfig;
+use crate::db::{Database, DeviceChange, IpChange, ServiceChange};
+use crate::events::{Event, EventSender};
+
+/// Name of the event a report is notified as
+pub const REPORT_NAME: &str = "change-report";
+
+/// Changes to the inventory over one period
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeReport {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub new_devices: Vec<DeviceChange>,
+    /// Devices that went past the inactivity timeout during the period
+    pub disappeared_devices: Vec<DeviceChange>,
+    pub new_services: Vec<ServiceChange>,
+    pub ip_changes: Vec<IpChange>,
+}
+
+impl ChangeReport {
+    /// Whether nothing changed during the period
+    pub fn is_empty(&self) -> bool {
+        self.new_devices.is_empty()
+            && self.disappeared_devices.is_empty()
+            && self.new_services.is_empty()
+            && self.ip_changes.is_empty()
+    }
+
+    /// `2 new devices, 1 disappeared device, ...` summary
+    pub fn summary(&self) -> String {
+        [
+            count(self.new_devices.len(), "new device"),
+            count(self.disappeared_devices.len(), "disappeared device"),
+            count(self.new_services.len(), "new service"),
+            count(self.ip_changes.len(), "IP change"),
+        ].join(", ")
+    }
+}
+
+fn count(n: usize, what: &str) -> String {
+    format!("{} {}{}", n, what, if n == 1 { "" } else { "s" })
+}
+
+/// Generates the daily change report
+pub struct ChangeReporter {
+    config: ChangeReportConfig,
+    db: Arc<Database>,
+    inactivity_timeout: u64,
+    events: Option<EventSender>,
+}
+
+impl ChangeReporter {
+    /// Create a reporter reading the inventory from `db`
+    pub fn new(config: ChangeReportConfig, db: Arc<Database>, inactivity_timeout: u64) -> Self {
+        Self { config, db, inactivity_timeout, events: None }
+    }
+
+    /// Send reports to the notification sinks through `events` (when
+    /// `notify` is set)
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Diff the inventory between `start` and `end`
+    pub async fn generate(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<ChangeReport> {
+        // A device disappears once its inactivity timeout runs out
+        let timeout = Duration::seconds(self.inactivity_timeout as i64);
+
+        Ok(ChangeReport {
+            period_start: start,
+            period_end: end,
+            new_devices: self.db.devices_first_seen(start, end).await?,
+            disappeared_devices: self.db.devices_last_seen(start - timeout, end - timeout).await?,
+            new_services: self.db.new_services(start, end).await?,
+            ip_changes: self.db.ip_changes(start, end).await?,
+        })
+    }
+
+    /// Report the changes since the previous report (or over the last day)
+    /// up to `now`, store the report and notify it
+    pub async fn report(&self, now: DateTime<Utc>) -> Result<ChangeReport> {
+        let start = self.db.last_change_report_end().await?.unwrap_or(now - Duration::days(1));
+        let report = self.generate(start, now).await?;
+        self.db.insert_change_report(&report).await?;
+        info!("Change report: {}", report.summary());
+
+        if let Some(events) = self.events.as_ref().filter(|_| self.config.notify && !report.is_empty()) {
+            let _ = events.send(self.event(&report)?);
+        }
+
+        Ok(report)
+    }
+
+    /// The report as an alert event, sent straight to the sinks: reports are
+    /// not deduplicated or tracked like alerts
+    fn event(&self, report: &ChangeReport) -> Result<Event> {
+        Ok(Event::Alert {
+            timestamp: report.period_end,
+            severity: self.config.severity,
+            name: REPORT_NAME.to_string(),
+            message: format!(
+                "Asset changes since {}: {}",
+                report.period_start.format("%Y-%m-%d %H:%M UTC"),
+                report.summary()
+            ),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: None,
+            channels: self.config.channels.clone(),
+            details: Some(serde_json::to_value(report)?),
+        })
+    }
+
+    /// Report every day at `at` until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) {
+        info!("Generating asset change reports daily at {} UTC", self.config.at);
+
+        loop {
+            let now = Utc::now();
+            let wait = (next_run(now, self.config.at) - now).to_std().unwrap_or_default();
+
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = tokio::time::sleep(wait) => {
+                    if let Err(e) = self.report(Utc::now()).await {
+                        warn!("Change report failed: {:#}", e);
+                    }
+                }
+            }
+        }
+
+        debug!("Change reporter stopped");
+    }
+}
+
+/// Next time of day `at` strictly after `now`
+fn next_run(now: DateTime<Utc>, at: NaiveTime) -> DateTime<Utc> {
+    let today = now.date_naive().and_time(at).and_utc();
+    if today > now {
+        today
+    } else {
+        today + Duration::days(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_run() {
+        let at = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 5, 30, 0).unwrap();
+        assert_eq!(next_run(now, at), Utc.with_ymd_and_hms(2024, 3, 1, 6, 0, 0).unwrap());
+
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 6, 0, 0).unwrap();
+        assert_eq!(next_run(now, at), Utc.with_ymd_and_hms(2024, 3, 2, 6, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_summary() {
+        let now = Utc::now();
+        let device = DeviceChange {
+            site: "default".to_string(),
+            mac_address: "00:11:22:33:44:55".to_string(),
+            ip_address: Some("10.0.0.5".to_string()),
+            first_seen: now,
+            last_seen: now,
+        };
+        let report = ChangeReport {
+            period_start: now - Duration::days(1),
+            period_end: now,
+            new_devices: vec![device.clone(), device.clone()],
+            disappeared_devices: vec![device],
+            new_services: Vec::new(),
+            ip_changes: Vec::new(),
+        };
+
+        assert!(!report.is_empty());
+        assert_eq!(report.summary(), "2 new devices, 1 disappeared device, 0 new services, 0 IP changes");
+    }
+}
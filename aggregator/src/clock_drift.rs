@@ -0,0 +1,173 @@
+//! Sensor clock drift
+//!
+//! Every `sample_interval_secs`, the skew of each sensor's clock is
+//! estimated from the frames it sent since the previous sample (see
+//! [`crate::state::clock`]) and exported as a metric. A skew beyond
+//! `max_skew_ms`, ahead or behind, raises an alert: flows merged from
+//! sensors disagreeing on the time get their first and last seen times out
+//! of order.
+//!
+//! A drift alerts once, until the sensor's clock is back within the
+//! threshold. Samples with fewer than `min_frames` frames are left out, the
+//! fastest of a handful of frames saying little about the clock.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::bandwidth::format_window;
+use crate::config::ClockDriftConfig;
+use crate::events::{Event, EventSender};
+use crate::metrics::metrics;
+use crate::state::{AggregatorState, ClockWindow, SensorId};
+
+/// Detects sensors whose clock is off the aggregator's
+pub struct ClockDriftMonitor {
+    config: ClockDriftConfig,
+    state: Arc<AggregatorState>,
+    /// Sensors alerted on and not back within the threshold
+    drifting: HashSet<SensorId>,
+}
+
+impl ClockDriftMonitor {
+    pub fn new(config: ClockDriftConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            drifting: HashSet::new(),
+        }
+    }
+
+    /// Estimate the skew of each sensor at `now` and return the alerts of
+    /// the sensors starting to drift
+    pub fn sample(&mut self, now: DateTime<Utc>) -> Vec<Event> {
+        let mut alerts = Vec::new();
+
+        for (sensor, window) in self.state.clocks.take() {
+            let name = sensor.to_string();
+            if window.frames < self.config.min_frames || self.config.ignore_sensors.contains(&name) {
+                continue;
+            }
+
+            metrics().sensor_clock_offset.with_label_values(&[&name]).set(window.min_offset_ms as f64 / 1000.0);
+
+            if window.min_offset_ms.unsigned_abs() <= self.config.max_skew_ms {
+                self.drifting.remove(&sensor);
+            } else if self.drifting.insert(sensor) {
+                alerts.push(self.alert(&name, &window, now));
+            }
+        }
+
+        alerts
+    }
+
+    /// Alert for a sensor starting to drift
+    fn alert(&self, sensor: &str, window: &ClockWindow, now: DateTime<Utc>) -> Event {
+        let direction = if window.min_offset_ms > 0 { "behind" } else { "ahead of" };
+        let message = format!(
+            "Clock of sensor {} is {:.1}s {} the aggregator (threshold {:.1}s)",
+            sensor,
+            window.min_offset_ms.unsigned_abs() as f64 / 1000.0,
+            direction,
+            self.config.max_skew_ms as f64 / 1000.0
+        );
+
+        let details = json!({
+            "sensor": sensor,
+            "offset_ms": window.min_offset_ms,
+            "max_offset_ms": window.max_offset_ms,
+            "frames": window.frames,
+            "max_skew_ms": self.config.max_skew_ms,
+        });
+
+        Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(window.site.to_string()),
+            sensor: Some(sensor.to_string()),
+            mac: None,
+            ip: None,
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Sample until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Sensor clock drift detection enabled (samples every {}, threshold {}ms)",
+            format_window(self.config.sample_interval_secs),
+            self.config.max_skew_ms
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.sample_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.sample(Utc::now()) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Sensor clock drift detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CapturedFrame, MacAddr, SensorFrame, SiteId};
+
+    /// Record `frames` frames of `sensor` captured `offset_ms` before they are received
+    fn frames(state: &AggregatorState, sensor: &str, offset_ms: i64, frames: usize, at: DateTime<Utc>) {
+        let mut frame = SensorFrame {
+            site: SiteId::new("lyon").unwrap(),
+            sensor: SensorId::new(sensor).unwrap(),
+            frame: CapturedFrame::new("eth0", MacAddr::new([0, 1, 2, 3, 4, 5]), MacAddr::new([0, 1, 2, 3, 4, 6]), 0x0800, 60),
+        };
+        frame.frame.timestamp = at - chrono::Duration::milliseconds(offset_ms);
+        for _ in 0..frames {
+            state.clocks.record(&frame, at);
+        }
+    }
+
+    #[test]
+    fn test_clock_drift() {
+        let config: ClockDriftConfig = toml::from_str("min_frames = 5").unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut monitor = ClockDriftMonitor::new(config, Arc::clone(&state));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+
+        // In sync, too few frames, and a replay keeping its capture times
+        frames(&state, "edge-1", 40, 10, at);
+        frames(&state, "edge-2", -9000, 3, at);
+        frames(&state, "replay", 86_400_000, 10, at);
+        assert!(monitor.sample(at).is_empty());
+
+        frames(&state, "edge-2", -9000, 10, at);
+        let alerts = monitor.sample(at);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, sensor, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Clock of sensor edge-2 is 9.0s ahead of the aggregator (threshold 2.0s)");
+        assert_eq!(sensor.as_deref(), Some("edge-2"));
+
+        // Once per drift
+        frames(&state, "edge-2", -9000, 10, at);
+        assert!(monitor.sample(at).is_empty());
+        frames(&state, "edge-2", 100, 10, at);
+        assert!(monitor.sample(at).is_empty());
+        frames(&state, "edge-2", 5000, 10, at);
+        assert_eq!(monitor.sample(at).len(), 1);
+    }
+}
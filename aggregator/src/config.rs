@@ -1,12 +1,22 @@
 //! Configuration module for NetSentinel Aggregator
 
-use serde::Deserialize;
-use std::path::Path;
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 
+use netsentinel_types::{template, ServiceNames};
+
+use crate::events::{EventKind, Severity};
+use crate::rules::Cidr;
+use crate::state::{Tenants, Vendors};
+
 /// Main configuration structure
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct Config {
+    #[serde(default)]
     pub redis: RedisConfig,
     pub database: DatabaseConfig,
     pub aggregation: AggregationConfig,
@@ -15,10 +25,70 @@ pub struct Config {
     pub logging: LoggingConfig,
     #[serde(default)]
     pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub forwarder: Option<ForwarderConfig>,
+    #[serde(default)]
+    pub ipfix: Option<IpfixConfig>,
+    #[serde(default)]
+    pub rules: Option<RulesConfig>,
+    #[serde(default)]
+    pub plugins: Option<PluginsConfig>,
+    #[serde(default)]
+    pub bandwidth: Option<BandwidthConfig>,
+    #[serde(default)]
+    pub new_device_alerts: Option<NewDeviceAlertsConfig>,
+    #[serde(default)]
+    pub beaconing: Option<BeaconingConfig>,
+    #[serde(default)]
+    pub exfiltration: Option<ExfiltrationConfig>,
+    #[serde(default)]
+    pub connections: Option<ConnectionsConfig>,
+    #[serde(default)]
+    pub scanners: Option<ScannersConfig>,
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    #[serde(default)]
+    pub clock_drift: Option<ClockDriftConfig>,
+    #[serde(default)]
+    pub ntp: Option<NtpConfig>,
+    #[serde(default)]
+    pub auth_servers: Option<AuthServersConfig>,
+    #[serde(default)]
+    pub ot: Option<OtConfig>,
+    #[serde(default)]
+    pub legacy_protocols: Option<LegacyProtocolsConfig>,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub netbox: Option<NetBoxConfig>,
+    #[serde(default)]
+    pub servicenow: Option<ServiceNowConfig>,
+    #[serde(default)]
+    pub change_report: Option<ChangeReportConfig>,
+    #[serde(default)]
+    pub stream_retention: Option<StreamRetentionConfig>,
+    /// Customers served, each owning sites (`[[tenants]]`)
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    /// Service names of the deployment's own ports (port -> name), over the
+    /// well-known ones
+    #[serde(default)]
+    pub services: ServiceNames,
+    /// Vendor names of OUIs (`"00:1B:63" = "Apple"`), searched by the API
+    #[serde(default)]
+    pub vendors: Vendors,
 }
 
 /// Redis configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RedisConfig {
     /// Redis connection URL
     #[serde(default = "default_redis_url")]
@@ -32,8 +102,9 @@ pub struct RedisConfig {
     #[serde(default = "default_consumer_group")]
     pub consumer_group: String,
 
-    /// Consumer name
+    /// Consumer name (defaults to `<hostname>-<pid>`, unique per instance)
     #[serde(default = "default_consumer_name")]
+    #[schemars(skip_serializing)]
     pub consumer_name: String,
 
     /// Batch size for reading from stream
@@ -43,10 +114,87 @@ pub struct RedisConfig {
     /// Block timeout when reading (milliseconds)
     #[serde(default = "default_block_timeout", alias = "block_timeout_ms")]
     pub block_ms: u64,
+
+    /// When stream entries are acknowledged
+    #[serde(default)]
+    pub ack_mode: AckMode,
+
+    /// With `after_persist`, failed persist cycles after which entries are
+    /// acknowledged anyway, so rows that never persist cannot hold them
+    /// (and their memory) forever
+    #[serde(default = "default_ack_max_failed_cycles")]
+    pub ack_max_failed_cycles: u32,
+
+    /// Instance registry heartbeat interval (seconds)
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+
+    /// Instances without a heartbeat for this long are considered dead (seconds)
+    #[serde(default = "default_instance_ttl")]
+    pub instance_ttl_secs: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: default_redis_url(),
+            stream_name: default_stream_name(),
+            consumer_group: default_consumer_group(),
+            consumer_name: default_consumer_name(),
+            batch_size: default_batch_size(),
+            block_ms: default_block_timeout(),
+            ack_mode: AckMode::default(),
+            ack_max_failed_cycles: default_ack_max_failed_cycles(),
+            heartbeat_interval_secs: default_heartbeat_interval(),
+            instance_ttl_secs: default_instance_ttl(),
+        }
+    }
+}
+
+/// Trimming of the frames stream (`[stream_retention]`)
+///
+/// Entries every consumer group has acknowledged are trimmed; `max_len`
+/// and `max_age_secs` cap the stream even when entries are left
+/// unprocessed, so it cannot outgrow Redis memory while aggregators are down.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct StreamRetentionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds between trims
+    #[serde(default = "default_retention_interval")]
+    pub interval_secs: u64,
+
+    /// Trim entries acknowledged by every consumer group (MINID)
+    #[serde(default = "default_true")]
+    pub trim_acknowledged: bool,
+
+    /// Keep at most this many entries, processed or not (MAXLEN)
+    #[serde(default)]
+    pub max_len: Option<u64>,
+
+    /// Drop entries older than this, processed or not (MINID from the entry time)
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+
+    /// Let Redis trim whole radix tree nodes only (`~`), much cheaper
+    #[serde(default = "default_true")]
+    pub approximate: bool,
+}
+
+/// Stream acknowledgement mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AckMode {
+    /// XACK each entry as soon as it has been applied to the in-memory state
+    #[default]
+    Immediate,
+    /// XACK entries only once the state they affected has been persisted
+    AfterPersist,
 }
 
 /// Database configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct DatabaseConfig {
     /// PostgreSQL connection URL
     pub url: String,
@@ -61,7 +209,7 @@ pub struct DatabaseConfig {
 }
 
 /// Aggregation configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct AggregationConfig {
     /// Persist interval for devices/flows (seconds)
     #[serde(default = "default_persist_interval", alias = "persist_interval")]
@@ -78,10 +226,33 @@ pub struct AggregationConfig {
     /// Flow timeout (seconds)
     #[serde(default = "default_flow_timeout")]
     pub flow_timeout: u64,
+
+    /// Maximum time to drain in-flight work and persist on shutdown (seconds)
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+
+    /// Services kept per device and hour in the traffic composition, the
+    /// rest being summed as "other"
+    #[serde(default = "default_composition_top_services")]
+    pub composition_top_services: usize,
+
+    /// Names kept per device and hour in the DNS analytics
+    #[serde(default = "default_dns_top_domains")]
+    pub dns_top_domains: usize,
+
+    /// Prefix length of the subnets inferred on each VLAN, and of those of
+    /// the inter-subnet traffic matrix
+    #[serde(default = "default_vlan_subnet_prefix")]
+    pub vlan_subnet_prefix: u8,
+
+    /// Device IDs kept in memory by the persister; misses are resolved in
+    /// the database
+    #[serde(default = "default_device_id_cache_size")]
+    pub device_id_cache_size: usize,
 }
 
 /// Events configuration
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default, JsonSchema)]
 pub struct EventsConfig {
     /// Redis channel for real-time events
     #[serde(default = "default_events_channel")]
@@ -98,10 +269,14 @@ pub struct EventsConfig {
     /// Publish threshold alerts
     #[serde(default)]
     pub publish_alerts: bool,
+
+    /// Publish aggregator start and stop events
+    #[serde(default = "default_true")]
+    pub publish_lifecycle: bool,
 }
 
 /// Logging configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct LoggingConfig {
     #[serde(default = "default_log_level")]
     pub level: String,
@@ -117,7 +292,7 @@ pub struct LoggingConfig {
 }
 
 /// Metrics configuration
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct MetricsConfig {
     #[serde(default)]
     pub enabled: bool,
@@ -129,46 +304,2192 @@ pub struct MetricsConfig {
     pub path: String,
 }
 
-// Default value functions
-fn default_redis_url() -> String { "redis://127.0.0.1:6379".to_string() }
-fn default_stream_name() -> String { "netsentinel:frames".to_string() }
-fn default_consumer_group() -> String { "aggregator".to_string() }
-fn default_consumer_name() -> String { "aggregator-1".to_string() }
-fn default_batch_size() -> usize { 100 }
-fn default_block_timeout() -> u64 { 1000 }
-fn default_pool_size() -> u32 { 10 }
-fn default_connect_timeout() -> u64 { 30 }
-fn default_persist_interval() -> u64 { 60 }
-fn default_metrics_bucket() -> String { "1 minute".to_string() }
-fn default_inactivity_timeout() -> u64 { 300 }
-fn default_flow_timeout() -> u64 { 120 }
-fn default_events_channel() -> String { "netsentinel:events".to_string() }
-fn default_log_level() -> String { "info".to_string() }
-fn default_log_format() -> String { "pretty".to_string() }
-fn default_true() -> bool { true }
-fn default_metrics_port() -> u16 { 9101 }
-fn default_metrics_path() -> String { "/metrics".to_string() }
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: default_metrics_port(),
+            path: default_metrics_path(),
+        }
+    }
+}
 
-impl Config {
-    /// Load configuration from a TOML file
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = std::fs::read_to_string(path.as_ref())
-            .with_context(|| format!("Failed to read config file: {:?}", path.as_ref()))?;
+/// HTTP API configuration
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ApiConfig {
+    #[serde(default)]
+    pub enabled: bool,
 
-        toml::from_str(&content)
-            .with_context(|| "Failed to parse configuration")
+    /// Listen address of the HTTP API
+    #[serde(default = "default_api_bind")]
+    pub bind: String,
+
+    /// Bearer tokens with access to every tenant. Once any token is set,
+    /// here or on a tenant, or OIDC is, requests without a valid token are
+    /// refused.
+    #[serde(default)]
+    pub admin_tokens: Vec<String>,
+
+    /// Read-only bearer tokens of every tenant: inventory reads, but no
+    /// alert changes, annotations or purges
+    #[serde(default)]
+    pub read_tokens: Vec<String>,
+
+    /// Accept the access tokens of an OpenID Connect provider
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+
+    /// Requests per minute of each client (bearer token, or address when
+    /// the API is open), 0 for no limit; refused tokens count against their
+    /// address
+    #[serde(default = "default_api_rate_limit")]
+    pub requests_per_minute: u32,
+
+    /// Requests a client may make at once above its rate
+    #[serde(default = "default_api_burst")]
+    pub burst: u32,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_api_bind(),
+            admin_tokens: Vec::new(),
+            read_tokens: Vec::new(),
+            oidc: None,
+            requests_per_minute: default_api_rate_limit(),
+            burst: default_api_burst(),
+        }
     }
+}
 
-    /// Validate the configuration
-    pub fn validate(&self) -> Result<()> {
-        if self.database.max_connections < 1 {
-            anyhow::bail!("Database max_connections must be at least 1");
+/// OpenID Connect bearer tokens (`[api.oidc]`)
+///
+/// Tokens are JWTs signed with one of the provider's keys (RSA, ECDSA or
+/// EdDSA), issued by `issuer` for `audience` and not expired. Their role
+/// claim decides what they may do; tokens with neither role are refused.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct OidcConfig {
+    /// Issuer URL, matched against the tokens' `iss`
+    pub issuer: String,
+
+    /// Expected `aud` of the tokens
+    pub audience: String,
+
+    /// Signing keys (JWKS) URL, discovered from the issuer when unset
+    #[serde(default)]
+    pub jwks_uri: Option<String>,
+
+    /// Claim holding the roles, a list or a space-separated string; dotted
+    /// paths reach nested claims (`realm_access.roles`)
+    #[serde(default = "default_oidc_roles_claim")]
+    pub roles_claim: String,
+
+    /// Role granting read-only access
+    #[serde(default = "default_oidc_read_role")]
+    pub read_role: String,
+
+    /// Role granting full access
+    #[serde(default = "default_oidc_admin_role")]
+    pub admin_role: String,
+
+    /// Claim naming the tenant a token is restricted to. When set, tokens
+    /// without it are refused; unset, tokens see every tenant.
+    #[serde(default)]
+    pub tenant_claim: Option<String>,
+
+    /// Seconds of HTTP timeout fetching the signing keys
+    #[serde(default = "default_oidc_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Customer served by the aggregator (`[[tenants]]`)
+///
+/// A tenant owns sites: their devices, flows and alerts are the tenant's,
+/// stored with its name and only served to its API tokens. Sites no tenant
+/// owns belong to the `default` tenant.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct TenantConfig {
+    /// Tenant name, stored in every row of its sites
+    pub name: String,
+
+    /// Sites the tenant owns
+    pub sites: Vec<String>,
+
+    /// Bearer tokens of the tenant's API clients
+    #[serde(default)]
+    pub api_tokens: Vec<String>,
+
+    /// Read-only bearer tokens of the tenant
+    #[serde(default)]
+    pub read_tokens: Vec<String>,
+}
+
+/// Local admin socket (`[admin]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Unix socket the admin subcommands talk to (owner-only permissions)
+    #[serde(default = "default_admin_socket")]
+    pub socket: PathBuf,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket: default_admin_socket(),
         }
+    }
+}
 
-        if self.aggregation.persist_interval_secs < 1 {
-            anyhow::bail!("Persist interval must be at least 1 second");
+/// OpenTelemetry export (`[telemetry]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct TelemetryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// OTLP/HTTP collector base URL (`/v1/traces` and `/v1/metrics` are appended)
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+
+    /// Extra headers sent with every export (e.g. an API key)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// `service.name` resource attribute
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Export spans
+    #[serde(default = "default_true")]
+    pub traces: bool,
+
+    /// Export metrics
+    #[serde(default = "default_true")]
+    pub metrics: bool,
+
+    /// Fraction of traces sampled (0.0 - 1.0)
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+
+    /// Metrics export interval (seconds)
+    #[serde(default = "default_telemetry_interval")]
+    pub export_interval_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_otlp_endpoint(),
+            headers: HashMap::new(),
+            service_name: default_service_name(),
+            traces: true,
+            metrics: true,
+            sample_ratio: default_sample_ratio(),
+            export_interval_secs: default_telemetry_interval(),
         }
+    }
+}
 
-        Ok(())
+/// Outbound notification sinks
+#[derive(Debug, Clone, Deserialize, Default, JsonSchema)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+
+    #[serde(default)]
+    pub email: Option<EmailConfig>,
+
+    #[serde(default)]
+    pub slack: Option<ChatConfig>,
+
+    #[serde(default)]
+    pub teams: Option<ChatConfig>,
+
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+
+    #[serde(default)]
+    pub snmp: Option<SnmpConfig>,
+}
+
+/// Webhook notifications (`[notifications.webhook]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Endpoint events are POSTed to
+    pub url: String,
+
+    /// Extra request headers (e.g. `Authorization`)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Body template (minijinja); the event's fields are the template
+    /// context. Without a template the event is sent as JSON.
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// Event kinds to forward
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<EventKind>,
+
+    /// Request timeout in seconds
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Syslog/CEF forwarding (`[notifications.syslog]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SyslogConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Collector host name or address
+    pub host: String,
+
+    #[serde(default = "default_syslog_port")]
+    pub port: u16,
+
+    #[serde(default)]
+    pub protocol: SyslogProtocol,
+
+    #[serde(default)]
+    pub format: SyslogFormat,
+
+    /// Message framing on TCP/TLS (ignored for UDP)
+    #[serde(default)]
+    pub framing: SyslogFraming,
+
+    /// Syslog facility name (`local0` .. `local7`, `daemon`, ...)
+    #[serde(default = "default_syslog_facility")]
+    pub facility: String,
+
+    #[serde(default = "default_syslog_app_name")]
+    pub app_name: String,
+
+    /// PEM bundle of CAs trusted for TLS, in addition to the web PKI roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+
+    /// Event kinds to forward
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<EventKind>,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Syslog transport
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogProtocol {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+}
+
+/// Syslog message format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFormat {
+    /// RFC 5424 with event fields as structured data
+    #[default]
+    Rfc5424,
+    /// ArcSight Common Event Format inside an RFC 5424 envelope
+    Cef,
+    /// Event record JSON inside an RFC 5424 envelope
+    Json,
+}
+
+/// Stream framing (RFC 6587)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFraming {
+    /// `<length> <message>`, required by RFC 5425 for TLS
+    #[default]
+    OctetCounting,
+    /// Messages terminated by a newline
+    Newline,
+}
+
+/// SMTP email alerts (`[notifications.email]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct EmailConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    pub smtp_host: String,
+
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+
+    #[serde(default)]
+    pub security: SmtpSecurity,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Sender address, e.g. `NetSentinel <netsentinel@example.com>`
+    pub from: String,
+
+    /// Recipient addresses
+    pub to: Vec<String>,
+
+    /// Subject template (minijinja, same context as `body_template`)
+    #[serde(default = "default_email_subject")]
+    pub subject_template: String,
+
+    /// Body template; the event's fields plus `summary`, `severity`,
+    /// `hostname` and `suppressed` (events dropped by the rate limit since
+    /// the previous email) are available
+    #[serde(default = "default_email_body")]
+    pub body_template: String,
+
+    /// Event kinds to send
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<EventKind>,
+
+    /// Lowest severity that is emailed
+    #[serde(default = "default_email_min_severity")]
+    pub min_severity: Severity,
+
+    /// Only email new devices seen on these VLANs (empty: any VLAN)
+    #[serde(default)]
+    pub vlans: Vec<u16>,
+
+    /// Maximum emails per hour; further events are dropped and counted
+    #[serde(default = "default_email_max_per_hour")]
+    pub max_per_hour: u32,
+
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// SMTP connection security
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS (port 587)
+    #[default]
+    Starttls,
+    /// Implicit TLS (port 465)
+    Tls,
+    /// No encryption (local relays only)
+    None,
+}
+
+/// Slack or Microsoft Teams incoming webhook (`[notifications.slack]`,
+/// `[notifications.teams]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ChatConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Incoming webhook URL used by routes without their own
+    pub url: String,
+
+    /// Message template (minijinja); the event's fields plus `summary`,
+    /// `severity` and `hostname` are available
+    #[serde(default = "default_chat_template")]
+    pub template: String,
+
+    /// Routing rules, checked in order; an event goes to the first route
+    /// that matches. Without routes, events of the default kinds are sent
+    /// to `url`.
+    #[serde(default)]
+    pub routes: Vec<ChatRoute>,
+
+    /// Maximum messages per hour across all routes (0: unlimited)
+    #[serde(default = "default_chat_max_per_hour")]
+    pub max_per_hour: u32,
+
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Chat routing rule (`[[notifications.slack.routes]]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ChatRoute {
+    /// Event kinds matched by the route
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<EventKind>,
+
+    /// Lowest severity matched by the route
+    #[serde(default = "default_chat_min_severity")]
+    pub min_severity: Severity,
+
+    /// VLANs matched by the route (empty: any VLAN)
+    #[serde(default)]
+    pub vlans: Vec<u16>,
+
+    /// Slack channel override (`#netops`); Teams routes by webhook URL
+    #[serde(default)]
+    pub channel: Option<String>,
+
+    /// Webhook URL override
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Template override
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl Default for ChatRoute {
+    fn default() -> Self {
+        Self {
+            events: default_notify_events(),
+            min_severity: default_chat_min_severity(),
+            vlans: Vec::new(),
+            channel: None,
+            url: None,
+            template: None,
+        }
+    }
+}
+
+/// MQTT publishing (`[notifications.mqtt]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct MqttConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Broker host name or address
+    pub host: String,
+
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+
+    /// Client identifier (defaults to `netsentinel-<hostname>`)
+    #[serde(default)]
+    pub client_id: Option<String>,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Connect over TLS (usually port 8883)
+    #[serde(default)]
+    pub tls: bool,
+
+    /// PEM bundle of CAs trusted for TLS, in addition to the web PKI roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+
+    /// Topic template per event kind (minijinja, with the same context as
+    /// chat templates); kinds without an entry use `default_topic`
+    #[serde(default)]
+    pub topics: HashMap<EventKind, String>,
+
+    #[serde(default = "default_mqtt_topic")]
+    pub default_topic: String,
+
+    /// Quality of service (0, 1 or 2)
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+
+    /// Publish with the retain flag
+    #[serde(default)]
+    pub retain: bool,
+
+    #[serde(default = "default_mqtt_keep_alive")]
+    pub keep_alive_secs: u64,
+
+    /// Event kinds to publish
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<EventKind>,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// SNMP traps (`[notifications.snmp]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SnmpConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Trap receiver host name or address
+    pub host: String,
+
+    #[serde(default = "default_snmp_port")]
+    pub port: u16,
+
+    #[serde(default)]
+    pub version: SnmpVersion,
+
+    /// Community string (v2c)
+    #[serde(default = "default_snmp_community")]
+    pub community: String,
+
+    /// USM user name (v3)
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Authentication protocol (v3)
+    #[serde(default)]
+    pub auth_protocol: SnmpAuthProtocol,
+
+    /// Authentication passphrase (v3); enables authNoPriv
+    #[serde(default)]
+    pub auth_password: Option<String>,
+
+    /// AES-128 privacy passphrase (v3); with `auth_password` enables authPriv
+    #[serde(default)]
+    pub priv_password: Option<String>,
+
+    /// Authoritative engine ID as hex (v3); derived from the hostname by
+    /// default
+    #[serde(default)]
+    pub engine_id: Option<String>,
+
+    /// Root of the NetSentinel MIB (see `mibs/NETSENTINEL-MIB.txt`)
+    #[serde(default = "default_snmp_enterprise_oid")]
+    pub enterprise_oid: String,
+
+    /// Event kinds to send as traps
+    #[serde(default = "default_snmp_events")]
+    pub events: Vec<EventKind>,
+
+    /// Business hours outside which low-severity events are held back
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// SNMP protocol version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SnmpVersion {
+    #[default]
+    V2c,
+    V3,
+}
+
+/// SNMPv3 authentication protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SnmpAuthProtocol {
+    /// HMAC-SHA-96 (RFC 3414)
+    #[default]
+    Sha,
+    /// HMAC-SHA-256-192 (RFC 7860)
+    Sha256,
+}
+
+/// Bulk forwarding of events and flows to a log platform (`[forwarder]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ForwarderConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    pub backend: ForwarderBackend,
+
+    /// Elasticsearch base URL, or the full Splunk HEC event endpoint
+    /// (`https://splunk:8088/services/collector/event`)
+    pub url: String,
+
+    /// Target index; strftime patterns (`netsentinel-%Y.%m.%d`) are expanded
+    /// from the document timestamp. Optional for Splunk.
+    #[serde(default)]
+    pub index: Option<String>,
+
+    /// Index for flow records (defaults to `index`)
+    #[serde(default)]
+    pub flow_index: Option<String>,
+
+    /// Splunk sourcetype
+    #[serde(default = "default_forwarder_sourcetype")]
+    pub sourcetype: String,
+
+    /// Splunk HEC token
+    #[serde(default)]
+    pub token: Option<String>,
+
+    /// Elasticsearch API key (`Authorization: ApiKey ...`)
+    #[serde(default)]
+    pub api_key: Option<String>,
+
+    /// Elasticsearch basic auth
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Event kinds to forward
+    #[serde(default = "default_forwarder_events")]
+    pub events: Vec<EventKind>,
+
+    /// Also forward flow records updated since the previous flush
+    #[serde(default)]
+    pub flows: bool,
+
+    /// Documents per request
+    #[serde(default = "default_forwarder_batch_size")]
+    pub batch_size: usize,
+
+    /// Maximum time a document waits before being sent
+    #[serde(default = "default_forwarder_flush_interval")]
+    pub flush_interval_secs: u64,
+
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Forwarder destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwarderBackend {
+    Elasticsearch,
+    SplunkHec,
+}
+
+/// IPFIX export of aggregated flows (`[ipfix]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct IpfixConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Collector host
+    pub host: String,
+
+    /// Collector UDP port
+    #[serde(default = "default_ipfix_port")]
+    pub port: u16,
+
+    /// Seconds between exports
+    #[serde(default = "default_ipfix_interval")]
+    pub interval_secs: u64,
+
+    /// Observation domain ID sent in every message
+    #[serde(default)]
+    pub observation_domain_id: u32,
+
+    /// Maximum datagram size (bytes)
+    #[serde(default = "default_ipfix_message_size")]
+    pub max_message_size: usize,
+}
+
+/// Rule-based alerting (`[rules]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RulesConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Rule files (TOML or YAML, by extension) or directories holding them
+    pub files: Vec<PathBuf>,
+
+    /// Seconds between scans of the device and flow state
+    #[serde(default = "default_rules_scan_interval")]
+    pub scan_interval_secs: u64,
+}
+
+/// WASM detection plugins (`[plugins]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct PluginsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// WASM modules, or directories holding them (`*.wasm`)
+    pub modules: Vec<PathBuf>,
+
+    /// Seconds between the flow snapshots handed to the plugins
+    #[serde(default = "default_plugins_scan_interval")]
+    pub scan_interval_secs: u64,
+
+    /// Seconds between checks for added, changed or removed modules (0: never)
+    #[serde(default = "default_plugins_reload_interval")]
+    pub reload_interval_secs: u64,
+
+    /// Instructions a plugin may run per call
+    #[serde(default = "default_plugins_fuel")]
+    pub fuel: u64,
+
+    /// Linear memory limit of each plugin (MiB)
+    #[serde(default = "default_plugins_max_memory")]
+    pub max_memory_mb: usize,
+}
+
+/// Per-device bandwidth thresholds (`[bandwidth]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct BandwidthConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Seconds between samples of the flow counters
+    #[serde(default = "default_bandwidth_sample_interval")]
+    pub sample_interval_secs: u64,
+
+    /// Largest flows listed in an alert's breakdown
+    #[serde(default = "default_bandwidth_breakdown")]
+    pub breakdown_flows: usize,
+
+    pub thresholds: Vec<BandwidthThreshold>,
+}
+
+/// A static per-device threshold (`[[bandwidth.thresholds]]`); exactly one
+/// of `max_mbps` and `max_bytes` is set
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct BandwidthThreshold {
+    /// Alert name
+    pub name: String,
+
+    #[serde(default = "default_bandwidth_severity")]
+    pub severity: Severity,
+
+    /// Rate that must be exceeded throughout the window (megabits/s)
+    #[serde(default)]
+    pub max_mbps: Option<f64>,
+
+    /// Volume that must not be exceeded over the window (bytes)
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+
+    /// Rolling window (seconds)
+    #[serde(default = "default_bandwidth_window")]
+    pub window_secs: u64,
+
+    /// Traffic counted against the threshold
+    #[serde(default)]
+    pub direction: BandwidthDirection,
+
+    /// Only count traffic with public IPv4 peers
+    #[serde(default)]
+    pub external_only: bool,
+
+    /// MAC addresses or prefixes of the devices covered (default: all)
+    #[serde(default)]
+    pub mac: Vec<String>,
+
+    /// VLANs whose traffic is counted (default: all)
+    #[serde(default)]
+    pub vlan: Vec<u16>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Traffic direction, from the device's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BandwidthDirection {
+    Out,
+    In,
+    #[default]
+    Both,
+}
+
+/// Alerts on devices missing from an allowlist (`[new_device_alerts]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NewDeviceAlertsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// MAC addresses or prefixes (OUIs) allowed everywhere
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+
+    /// File of further allowed MACs or prefixes, one per line (`#` comments)
+    #[serde(default)]
+    pub allowlist_file: Option<PathBuf>,
+
+    /// Policies, the first one matching a device applies; devices matching
+    /// none are not alerted on
+    pub policies: Vec<NewDevicePolicy>,
+}
+
+/// Where devices are watched and how (`[[new_device_alerts.policies]]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NewDevicePolicy {
+    /// Alert name
+    pub name: String,
+
+    /// VLANs covered (a device matches on either its VLAN or its subnet;
+    /// a policy without both covers every device)
+    #[serde(default)]
+    pub vlan: Vec<u16>,
+
+    /// Subnets covered (CIDR notation)
+    #[serde(default)]
+    pub subnet: Vec<Cidr>,
+
+    #[serde(default = "default_new_device_severity")]
+    pub severity: Severity,
+
+    /// MAC addresses or prefixes allowed under this policy only
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Beaconing detection (`[beaconing]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct BeaconingConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_beaconing_name")]
+    pub name: String,
+
+    #[serde(default = "default_beaconing_severity")]
+    pub severity: Severity,
+
+    /// Connections needed before a destination is scored
+    #[serde(default = "default_beaconing_min_connections")]
+    pub min_connections: usize,
+
+    /// Shortest period considered (shorter ones are usually polling or retries)
+    #[serde(default = "default_beaconing_min_period")]
+    pub min_period_secs: u64,
+
+    /// Longest period considered
+    #[serde(default = "default_beaconing_max_period")]
+    pub max_period_secs: u64,
+
+    /// Connections older than this are forgotten
+    #[serde(default = "default_beaconing_history")]
+    pub history_secs: u64,
+
+    /// Regularity score (0.0 - 1.0) from which an alert is raised
+    #[serde(default = "default_beaconing_min_score")]
+    pub min_score: f64,
+
+    /// Average bytes per connection above which traffic is not a beacon
+    /// (0: no limit)
+    #[serde(default = "default_beaconing_max_bytes")]
+    pub max_bytes_per_connection: u64,
+
+    /// Seconds before the same device and destination alert again
+    #[serde(default = "default_beaconing_cooldown")]
+    pub cooldown_secs: u64,
+
+    /// Destinations never scored, e.g. NTP or update servers (CIDR notation)
+    #[serde(default)]
+    pub ignore_destinations: Vec<Cidr>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Outbound volume anomalies (`[exfiltration]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ExfiltrationConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_exfiltration_name")]
+    pub name: String,
+
+    #[serde(default = "default_exfiltration_severity")]
+    pub severity: Severity,
+
+    /// Seconds between samples of the flow counters
+    #[serde(default = "default_exfiltration_sample_interval")]
+    pub sample_interval_secs: u64,
+
+    /// Length of the periods compared against the baseline
+    #[serde(default = "default_exfiltration_bucket")]
+    pub bucket_secs: u64,
+
+    /// Periods the baseline averages over (exponentially weighted)
+    #[serde(default = "default_exfiltration_baseline")]
+    pub baseline_buckets: u32,
+
+    /// Periods learned before a device can alert
+    #[serde(default = "default_exfiltration_learning")]
+    pub learning_buckets: u32,
+
+    /// Standard deviations above the baseline that make a period a burst
+    #[serde(default = "default_exfiltration_burst_sigmas")]
+    pub burst_sigmas: f64,
+
+    /// Smallest burst alerted on (bytes)
+    #[serde(default = "default_exfiltration_min_burst")]
+    pub min_burst_bytes: u64,
+
+    /// Consecutive periods above the baseline that make a leak
+    #[serde(default = "default_exfiltration_leak_buckets")]
+    pub leak_buckets: usize,
+
+    /// Multiple of the baseline each leak period must exceed
+    #[serde(default = "default_exfiltration_leak_ratio")]
+    pub leak_ratio: f64,
+
+    /// Smallest leak alerted on, over all its periods (bytes)
+    #[serde(default = "default_exfiltration_min_leak")]
+    pub min_leak_bytes: u64,
+
+    /// Largest destinations listed in an alert's breakdown
+    #[serde(default = "default_exfiltration_breakdown")]
+    pub breakdown_destinations: usize,
+
+    /// Destinations not counted, e.g. backup or cloud storage (CIDR notation)
+    #[serde(default)]
+    pub ignore_destinations: Vec<Cidr>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Long-lived and half-open connections (`[connections]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ConnectionsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Seconds between scans of the flow state
+    #[serde(default = "default_connections_scan_interval")]
+    pub scan_interval_secs: u64,
+
+    /// Alert name for long-lived flows
+    #[serde(default = "default_long_lived_name")]
+    pub long_lived_name: String,
+
+    #[serde(default = "default_long_lived_severity")]
+    pub long_lived_severity: Severity,
+
+    /// Flows alive this long are always flagged (0: never)
+    #[serde(default = "default_long_lived_max_duration")]
+    pub max_duration_secs: u64,
+
+    /// Flows alive this long are flagged when far beyond their service's
+    /// typical duration
+    #[serde(default = "default_long_lived_min_duration")]
+    pub min_duration_secs: u64,
+
+    /// Multiple of the service's median duration that counts as far beyond
+    #[serde(default = "default_long_lived_factor")]
+    pub duration_factor: f64,
+
+    /// Flows of a service (protocol and destination port) needed to know
+    /// its typical duration
+    #[serde(default = "default_long_lived_min_samples")]
+    pub min_samples: usize,
+
+    /// Destination ports expected to stay open (e.g. VPN or SSH tunnels)
+    #[serde(default)]
+    pub ignore_ports: Vec<u16>,
+
+    /// Alert name for half-open connections
+    #[serde(default = "default_half_open_name")]
+    pub half_open_name: String,
+
+    #[serde(default = "default_half_open_severity")]
+    pub half_open_severity: Severity,
+
+    /// SYN-only flows from one device that raise an alert (0: disabled)
+    #[serde(default = "default_half_open_min")]
+    pub min_half_open: usize,
+
+    /// Seconds a SYN may wait for its handshake before counting as half-open
+    #[serde(default = "default_half_open_grace")]
+    pub half_open_grace_secs: u64,
+
+    /// Largest destinations listed in a half-open alert's breakdown
+    #[serde(default = "default_half_open_breakdown")]
+    pub breakdown_destinations: usize,
+
+    /// Notification sinks the alerts go to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// External scanner identification (`[scanners]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ScannersConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_scanners_name")]
+    pub name: String,
+
+    #[serde(default = "default_scanners_severity")]
+    pub severity: Severity,
+
+    /// Sliding window the probes of a source are counted over
+    #[serde(default = "default_scanners_window")]
+    pub window_secs: u64,
+
+    /// Distinct internal hosts probed within the window that make a scanner
+    #[serde(default = "default_scanners_min_hosts")]
+    pub min_hosts: usize,
+
+    /// Distinct services (protocol and port) probed within the window that
+    /// make a scanner
+    #[serde(default = "default_scanners_min_services")]
+    pub min_services: usize,
+
+    /// Seconds before the same scanner alerts again
+    #[serde(default = "default_scanners_cooldown")]
+    pub cooldown_secs: u64,
+
+    /// Seconds between writes of the `scanners` table
+    #[serde(default = "default_scanners_flush_interval")]
+    pub flush_interval_secs: u64,
+
+    /// Sources never flagged, e.g. an authorized vulnerability scanner (CIDR notation)
+    #[serde(default)]
+    pub ignore_sources: Vec<Cidr>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// NXDOMAIN spikes in the DNS analytics (`[dns]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct DnsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_dns_name")]
+    pub name: String,
+
+    #[serde(default = "default_dns_severity")]
+    pub severity: Severity,
+
+    /// Seconds between samples of the NXDOMAIN counters
+    #[serde(default = "default_dns_sample_interval")]
+    pub sample_interval_secs: u64,
+
+    /// Samples the baseline averages over (exponentially weighted)
+    #[serde(default = "default_dns_baseline")]
+    pub baseline_samples: u32,
+
+    /// Samples learned before a device can alert
+    #[serde(default = "default_dns_learning")]
+    pub learning_samples: u32,
+
+    /// Standard deviations above the baseline that make a spike
+    #[serde(default = "default_dns_spike_sigmas")]
+    pub spike_sigmas: f64,
+
+    /// Fewest NXDOMAIN responses within a sample that make a spike
+    #[serde(default = "default_dns_min_nxdomain")]
+    pub min_nxdomain: u64,
+
+    /// Smallest share of the sample's responses that must be NXDOMAIN
+    #[serde(default = "default_dns_min_ratio")]
+    pub min_ratio: f64,
+
+    /// Names with the most NXDOMAIN responses listed in an alert
+    #[serde(default = "default_dns_breakdown")]
+    pub breakdown_domains: usize,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Clock skew between the sensors and the aggregator (`[clock_drift]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ClockDriftConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_clock_drift_name")]
+    pub name: String,
+
+    #[serde(default = "default_clock_drift_severity")]
+    pub severity: Severity,
+
+    /// Seconds between estimates of each sensor's skew
+    #[serde(default = "default_clock_drift_sample_interval")]
+    pub sample_interval_secs: u64,
+
+    /// Skew, ahead or behind, that raises an alert (milliseconds)
+    #[serde(default = "default_clock_drift_max_skew")]
+    pub max_skew_ms: u64,
+
+    /// Fewest frames within a sample to estimate a sensor's skew
+    #[serde(default = "default_clock_drift_min_frames")]
+    pub min_frames: u64,
+
+    /// Sensors left out, such as pcap replays keeping their capture times
+    #[serde(default = "default_clock_drift_ignore")]
+    pub ignore_sensors: Vec<String>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Devices querying unauthorized time servers (`[ntp]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NtpConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_ntp_name")]
+    pub name: String,
+
+    #[serde(default = "default_ntp_severity")]
+    pub severity: Severity,
+
+    /// Seconds between scans of the time servers queried
+    #[serde(default = "default_ntp_scan_interval")]
+    pub scan_interval_secs: u64,
+
+    /// Time servers devices may query (CIDR notation)
+    #[serde(default)]
+    pub authorized_servers: Vec<Cidr>,
+
+    /// Only flag servers outside the private, loopback and link-local
+    /// ranges: internal servers count as authorized
+    #[serde(default = "default_true")]
+    pub external_only: bool,
+
+    /// Seconds before the same device and server alert again
+    #[serde(default = "default_ntp_cooldown")]
+    pub cooldown_secs: u64,
+
+    /// Devices never flagged, e.g. the internal time servers themselves
+    /// (CIDR notation)
+    #[serde(default)]
+    pub ignore_clients: Vec<Cidr>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Authentication server mapping (`[auth_servers]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AuthServersConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Seconds between classifications
+    #[serde(default = "default_auth_servers_interval")]
+    pub interval_secs: u64,
+
+    /// Only service dependencies active within this many days count
+    #[serde(default = "default_auth_servers_window")]
+    pub window_days: u32,
+
+    /// Distinct clients a server must answer on a service to provide it
+    #[serde(default = "default_auth_servers_min_clients")]
+    pub min_clients: u64,
+
+    /// Flows a server must answer on a service to provide it
+    #[serde(default = "default_auth_servers_min_flows")]
+    pub min_flows: u64,
+}
+
+/// Writes to industrial devices from unauthorized sources (`[ot]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct OtConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_ot_name")]
+    pub name: String,
+
+    #[serde(default = "default_ot_severity")]
+    pub severity: Severity,
+
+    /// Seconds between scans of the writes
+    #[serde(default = "default_ot_scan_interval")]
+    pub scan_interval_secs: u64,
+
+    /// Sources allowed to write to industrial devices, e.g. the SCADA
+    /// masters and engineering workstations (CIDR notation)
+    #[serde(default)]
+    pub authorized_writers: Vec<Cidr>,
+
+    /// Seconds before the same source and device alert again
+    #[serde(default = "default_ot_cooldown")]
+    pub cooldown_secs: u64,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Devices using legacy, insecure protocols (`[legacy_protocols]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct LegacyProtocolsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Alert name
+    #[serde(default = "default_legacy_name")]
+    pub name: String,
+
+    #[serde(default = "default_legacy_severity")]
+    pub severity: Severity,
+
+    /// Seconds between scans of the flows
+    #[serde(default = "default_legacy_scan_interval")]
+    pub scan_interval_secs: u64,
+
+    /// Protocols flagged (default: all of them)
+    #[serde(default = "default_legacy_protocols")]
+    pub protocols: Vec<LegacyProtocol>,
+
+    /// Addresses allowed to use each protocol, as client or server, e.g.
+    /// the switches only managed over telnet (CIDR notation)
+    #[serde(default)]
+    pub allow: HashMap<LegacyProtocol, Vec<Cidr>>,
+
+    /// Seconds before the same client, server and protocol alert again
+    #[serde(default = "default_legacy_cooldown")]
+    pub cooldown_secs: u64,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Legacy protocol, recognized by its ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LegacyProtocol {
+    /// Telnet (TCP 23)
+    Telnet,
+    /// FTP control connections (TCP 21)
+    Ftp,
+    /// SMB over the NetBIOS session service (TCP 139), the transport of
+    /// SMBv1; later versions run directly over TCP 445
+    Smb1,
+    /// SNMPv1 and v2c (UDP 161 and 162), authenticated by community string
+    Snmp,
+    /// NetBIOS name and datagram services (UDP 137 and 138)
+    Netbios,
+}
+
+impl LegacyProtocol {
+    pub const ALL: [LegacyProtocol; 5] = [Self::Telnet, Self::Ftp, Self::Smb1, Self::Snmp, Self::Netbios];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Telnet => "telnet",
+            Self::Ftp => "ftp",
+            Self::Smb1 => "smb1",
+            Self::Snmp => "snmp",
+            Self::Netbios => "netbios",
+        }
+    }
+}
+
+/// Alert lifecycle (`[alerts]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AlertsConfig {
+    /// Seconds before a still-open alert that keeps firing is notified
+    /// again (0: notify only once)
+    #[serde(default = "default_alert_renotify")]
+    pub renotify_interval_secs: u64,
+
+    /// Resolve alerts that have not fired for this many seconds (0: never)
+    #[serde(default)]
+    pub auto_resolve_secs: u64,
+
+    /// Windows during which matching alerts are recorded but not notified
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceWindow>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            renotify_interval_secs: default_alert_renotify(),
+            auto_resolve_secs: 0,
+            maintenance: Vec::new(),
+        }
+    }
+}
+
+/// Maintenance window (`[[alerts.maintenance]]`)
+///
+/// Either a one-off window (`start` and `end`) or a recurring daily one
+/// (`from` and `to`, UTC, optionally restricted to `days`). A daily window
+/// whose `to` is before its `from` runs past midnight.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct MaintenanceWindow {
+    pub name: String,
+
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+
+    /// Days the daily window starts on (default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: Option<NaiveTime>,
+    pub to: Option<NaiveTime>,
+
+    /// Alert names covered (default: all)
+    #[serde(default)]
+    pub alerts: Vec<String>,
+
+    /// MAC addresses or prefixes covered (default: all)
+    #[serde(default)]
+    pub mac: Vec<String>,
+}
+
+/// NetBox synchronization (`[netbox]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NetBoxConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// NetBox base URL (`https://netbox.example.com`)
+    pub url: String,
+
+    /// API token
+    pub token: String,
+
+    /// Seconds between synchronizations
+    #[serde(default = "default_netbox_interval")]
+    pub interval_secs: u64,
+
+    /// Log the changes that would be made without writing to NetBox
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Also export devices past the inactivity timeout
+    #[serde(default)]
+    pub include_inactive: bool,
+
+    /// Only export devices with at least this confidence score (0-100)
+    #[serde(default)]
+    pub min_confidence: u8,
+
+    /// Tag (which must exist in NetBox) applied to created objects
+    #[serde(default)]
+    pub tag: Option<String>,
+
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// ServiceNow CMDB export (`[servicenow]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ServiceNowConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub output: ServiceNowOutput,
+
+    /// Instance URL (`https://example.service-now.com`), for the Table API
+    #[serde(default)]
+    pub instance_url: Option<String>,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Directory import-set files are written to
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Seconds between exports
+    #[serde(default = "default_servicenow_interval")]
+    pub interval_secs: u64,
+
+    /// CI class of devices no mapping rule matches
+    #[serde(default = "default_servicenow_class")]
+    pub default_class: String,
+
+    /// Class mapping rules, checked in order
+    #[serde(default)]
+    pub classes: Vec<CiClassRule>,
+
+    /// Also export devices past the inactivity timeout
+    #[serde(default)]
+    pub include_inactive: bool,
+
+    /// Only export devices with at least this confidence score (0-100)
+    #[serde(default)]
+    pub min_confidence: u8,
+
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Where ServiceNow CIs are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceNowOutput {
+    /// Create or update CIs through the Table API
+    #[default]
+    TableApi,
+    /// Write JSON import-set files to `output_dir`
+    ImportSet,
+}
+
+/// Maps matching devices to a CI class (`[[servicenow.classes]]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct CiClassRule {
+    /// OUI prefix (`00:1B:63`)
+    #[serde(default)]
+    pub oui: Option<String>,
+
+    /// Match gateways (true) or non-gateways (false)
+    #[serde(default)]
+    pub gateway: Option<bool>,
+
+    /// Match devices seen on this VLAN
+    #[serde(default)]
+    pub vlan: Option<u16>,
+
+    /// CI class (table) name, e.g. `cmdb_ci_ip_router`
+    pub class: String,
+}
+
+/// Daily asset change report (`[change_report]`)
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ChangeReportConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Time of day (UTC) the report is generated
+    #[serde(default = "default_change_report_at")]
+    pub at: NaiveTime,
+
+    /// Also send reports with changes to the notification sinks
+    #[serde(default)]
+    pub notify: bool,
+
+    /// Notification sinks the report goes to (default: all of them)
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    #[serde(default = "default_change_report_severity")]
+    pub severity: Severity,
+}
+
+/// Delivery schedule of a notification sink (`[notifications.<sink>.schedule]`)
+///
+/// Outside the daily `from`..`to` business hours (UTC, starting on `days`),
+/// only events at or above `off_hours_min_severity` are delivered; the
+/// others are dropped, or held until the business hours start again with
+/// `off_hours = "defer"`. Without `from` and `to`, events are delivered at
+/// any time.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ScheduleConfig {
+    /// Days the business hours start on (default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: Option<NaiveTime>,
+    pub to: Option<NaiveTime>,
+
+    /// Lowest severity delivered outside business hours
+    #[serde(default = "default_off_hours_min_severity")]
+    pub off_hours_min_severity: Severity,
+
+    /// What happens to the other events outside business hours
+    #[serde(default)]
+    pub off_hours: OffHours,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            days: Vec::new(),
+            from: None,
+            to: None,
+            off_hours_min_severity: default_off_hours_min_severity(),
+            off_hours: OffHours::default(),
+        }
+    }
+}
+
+/// Handling of events held back outside business hours
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OffHours {
+    #[default]
+    Drop,
+    /// Deliver them once business hours start again
+    Defer,
+}
+
+/// Delivery retry policy with exponential backoff
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RetryConfig {
+    /// Attempts per event, including the first one
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, doubled on every further retry
+    #[serde(default = "default_retry_backoff")]
+    pub initial_backoff_ms: u64,
+
+    /// Upper bound of the retry delay
+    #[serde(default = "default_retry_max_backoff")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_attempts(),
+            initial_backoff_ms: default_retry_backoff(),
+            max_backoff_ms: default_retry_max_backoff(),
+        }
+    }
+}
+
+// Default value functions
+fn default_redis_url() -> String { "redis://127.0.0.1:6379".to_string() }
+fn default_stream_name() -> String { "netsentinel:frames".to_string() }
+fn default_consumer_group() -> String { "aggregator".to_string() }
+fn default_consumer_name() -> String { format!("{}-{}", local_hostname(), std::process::id()) }
+fn default_batch_size() -> usize { 100 }
+fn default_block_timeout() -> u64 { 1000 }
+fn default_heartbeat_interval() -> u64 { 10 }
+fn default_ack_max_failed_cycles() -> u32 { 10 }
+fn default_instance_ttl() -> u64 { 60 }
+fn default_pool_size() -> u32 { 10 }
+fn default_connect_timeout() -> u64 { 30 }
+fn default_persist_interval() -> u64 { 60 }
+fn default_metrics_bucket() -> String { "1 minute".to_string() }
+fn default_inactivity_timeout() -> u64 { 300 }
+fn default_flow_timeout() -> u64 { 120 }
+fn default_drain_timeout() -> u64 { 30 }
+fn default_composition_top_services() -> usize { 10 }
+fn default_dns_top_domains() -> usize { 10 }
+fn default_vlan_subnet_prefix() -> u8 { 24 }
+fn default_device_id_cache_size() -> usize { 100_000 }
+fn default_events_channel() -> String { "netsentinel:events".to_string() }
+fn default_log_level() -> String { "info".to_string() }
+fn default_log_format() -> String { "pretty".to_string() }
+fn default_true() -> bool { true }
+fn default_metrics_port() -> u16 { 9101 }
+fn default_metrics_path() -> String { "/metrics".to_string() }
+fn default_api_bind() -> String { "127.0.0.1:8081".to_string() }
+fn default_api_rate_limit() -> u32 { 600 }
+fn default_api_burst() -> u32 { 60 }
+fn default_oidc_roles_claim() -> String { "roles".to_string() }
+fn default_oidc_read_role() -> String { "netsentinel:read".to_string() }
+fn default_oidc_admin_role() -> String { "netsentinel:admin".to_string() }
+fn default_oidc_timeout() -> u64 { 10 }
+fn default_admin_socket() -> PathBuf { PathBuf::from("/run/netsentinel/aggregator.sock") }
+fn default_otlp_endpoint() -> String { "http://localhost:4318".to_string() }
+fn default_service_name() -> String { "netsentinel-aggregator".to_string() }
+fn default_sample_ratio() -> f64 { 1.0 }
+fn default_telemetry_interval() -> u64 { 60 }
+fn default_notify_events() -> Vec<EventKind> { vec![EventKind::NewDevice, EventKind::Alert] }
+fn default_notify_timeout() -> u64 { 10 }
+fn default_forwarder_sourcetype() -> String { "netsentinel".to_string() }
+fn default_forwarder_events() -> Vec<EventKind> { vec![EventKind::NewDevice, EventKind::NewFlow, EventKind::Alert] }
+fn default_forwarder_batch_size() -> usize { 500 }
+fn default_forwarder_flush_interval() -> u64 { 5 }
+fn default_smtp_port() -> u16 { 587 }
+fn default_email_subject() -> String { "[NetSentinel] {{ summary }}".to_string() }
+fn default_email_body() -> String {
+    "{{ summary }}\n\nSeverity: {{ severity }}\nTime: {{ timestamp }}\nSensor: {{ hostname }}\n\
+     {% if suppressed %}\n{{ suppressed }} earlier notifications were suppressed by the rate limit.\n{% endif %}"
+        .to_string()
+}
+fn default_email_min_severity() -> Severity { Severity::Low }
+fn default_email_max_per_hour() -> u32 { 20 }
+fn default_chat_template() -> String { "{{ summary }}\nSeverity: {{ severity }} - Sensor: {{ hostname }}".to_string() }
+fn default_chat_min_severity() -> Severity { Severity::Info }
+fn default_chat_max_per_hour() -> u32 { 60 }
+fn default_ipfix_port() -> u16 { 4739 }
+fn default_ipfix_interval() -> u64 { 60 }
+fn default_ipfix_message_size() -> usize { 1400 }
+fn default_rules_scan_interval() -> u64 { 60 }
+fn default_plugins_scan_interval() -> u64 { 60 }
+fn default_plugins_reload_interval() -> u64 { 10 }
+fn default_plugins_fuel() -> u64 { 10_000_000 }
+fn default_plugins_max_memory() -> usize { 16 }
+fn default_bandwidth_sample_interval() -> u64 { 10 }
+fn default_bandwidth_breakdown() -> usize { 10 }
+fn default_bandwidth_severity() -> Severity { Severity::High }
+fn default_bandwidth_window() -> u64 { 300 }
+fn default_new_device_severity() -> Severity { Severity::Medium }
+fn default_beaconing_name() -> String { "beaconing".to_string() }
+fn default_beaconing_severity() -> Severity { Severity::High }
+fn default_beaconing_min_connections() -> usize { 8 }
+fn default_beaconing_min_period() -> u64 { 10 }
+fn default_beaconing_max_period() -> u64 { 21_600 }
+fn default_beaconing_history() -> u64 { 86_400 }
+fn default_beaconing_min_score() -> f64 { 0.85 }
+fn default_beaconing_max_bytes() -> u64 { 65_536 }
+fn default_beaconing_cooldown() -> u64 { 3600 }
+fn default_exfiltration_name() -> String { "exfiltration".to_string() }
+fn default_exfiltration_severity() -> Severity { Severity::High }
+fn default_exfiltration_sample_interval() -> u64 { 60 }
+fn default_exfiltration_bucket() -> u64 { 3600 }
+fn default_exfiltration_baseline() -> u32 { 168 }
+fn default_exfiltration_learning() -> u32 { 24 }
+fn default_exfiltration_burst_sigmas() -> f64 { 4.0 }
+fn default_exfiltration_min_burst() -> u64 { 100_000_000 }
+fn default_exfiltration_leak_buckets() -> usize { 6 }
+fn default_exfiltration_leak_ratio() -> f64 { 2.0 }
+fn default_exfiltration_min_leak() -> u64 { 50_000_000 }
+fn default_exfiltration_breakdown() -> usize { 10 }
+fn default_connections_scan_interval() -> u64 { 60 }
+fn default_long_lived_name() -> String { "long-lived-connection".to_string() }
+fn default_long_lived_severity() -> Severity { Severity::Low }
+fn default_long_lived_max_duration() -> u64 { 86_400 }
+fn default_long_lived_min_duration() -> u64 { 3600 }
+fn default_long_lived_factor() -> f64 { 20.0 }
+fn default_long_lived_min_samples() -> usize { 20 }
+fn default_half_open_name() -> String { "half-open-connections".to_string() }
+fn default_half_open_severity() -> Severity { Severity::Medium }
+fn default_half_open_min() -> usize { 100 }
+fn default_half_open_grace() -> u64 { 10 }
+fn default_half_open_breakdown() -> usize { 10 }
+fn default_scanners_name() -> String { "external-scanner".to_string() }
+fn default_scanners_severity() -> Severity { Severity::Medium }
+fn default_scanners_window() -> u64 { 300 }
+fn default_scanners_min_hosts() -> usize { 20 }
+fn default_scanners_min_services() -> usize { 50 }
+fn default_scanners_cooldown() -> u64 { 3600 }
+fn default_scanners_flush_interval() -> u64 { 60 }
+fn default_dns_name() -> String { "nxdomain-spike".to_string() }
+fn default_dns_severity() -> Severity { Severity::Medium }
+fn default_dns_sample_interval() -> u64 { 60 }
+fn default_dns_baseline() -> u32 { 1440 }
+fn default_dns_learning() -> u32 { 60 }
+fn default_dns_spike_sigmas() -> f64 { 5.0 }
+fn default_dns_min_nxdomain() -> u64 { 50 }
+fn default_dns_min_ratio() -> f64 { 0.5 }
+fn default_dns_breakdown() -> usize { 10 }
+fn default_clock_drift_name() -> String { "sensor-clock-drift".to_string() }
+fn default_clock_drift_severity() -> Severity { Severity::Medium }
+fn default_clock_drift_sample_interval() -> u64 { 60 }
+fn default_clock_drift_max_skew() -> u64 { 2000 }
+fn default_clock_drift_min_frames() -> u64 { 10 }
+fn default_clock_drift_ignore() -> Vec<String> { vec!["replay".to_string()] }
+fn default_ntp_name() -> String { "unauthorized-ntp-server".to_string() }
+fn default_ntp_severity() -> Severity { Severity::Medium }
+fn default_ntp_scan_interval() -> u64 { 60 }
+fn default_ntp_cooldown() -> u64 { 86_400 }
+fn default_auth_servers_interval() -> u64 { 3600 }
+fn default_auth_servers_window() -> u32 { 7 }
+fn default_auth_servers_min_clients() -> u64 { 3 }
+fn default_auth_servers_min_flows() -> u64 { 10 }
+fn default_ot_name() -> String { "unauthorized-ot-write".to_string() }
+fn default_ot_severity() -> Severity { Severity::High }
+fn default_ot_scan_interval() -> u64 { 10 }
+fn default_ot_cooldown() -> u64 { 3600 }
+fn default_legacy_name() -> String { "legacy-protocol".to_string() }
+fn default_legacy_severity() -> Severity { Severity::Medium }
+fn default_legacy_scan_interval() -> u64 { 60 }
+fn default_legacy_protocols() -> Vec<LegacyProtocol> { LegacyProtocol::ALL.to_vec() }
+fn default_legacy_cooldown() -> u64 { 86_400 }
+fn default_alert_renotify() -> u64 { 3600 }
+fn default_retention_interval() -> u64 { 60 }
+fn default_netbox_interval() -> u64 { 3600 }
+fn default_servicenow_interval() -> u64 { 86_400 }
+fn default_servicenow_class() -> String { "cmdb_ci_ip_device".to_string() }
+fn default_change_report_at() -> NaiveTime { NaiveTime::from_hms_opt(6, 0, 0).unwrap_or_default() }
+fn default_change_report_severity() -> Severity { Severity::Low }
+fn default_mqtt_port() -> u16 { 1883 }
+fn default_mqtt_topic() -> String { "netsentinel/events/{{ type }}".to_string() }
+fn default_mqtt_qos() -> u8 { 1 }
+fn default_mqtt_keep_alive() -> u64 { 30 }
+fn default_snmp_port() -> u16 { 162 }
+fn default_snmp_community() -> String { "public".to_string() }
+fn default_snmp_enterprise_oid() -> String { "1.3.6.1.4.1.32473.1".to_string() }
+fn default_snmp_events() -> Vec<EventKind> { vec![EventKind::Alert] }
+fn default_syslog_port() -> u16 { 514 }
+fn default_syslog_facility() -> String { "local0".to_string() }
+fn default_syslog_app_name() -> String { "netsentinel".to_string() }
+fn default_off_hours_min_severity() -> Severity { Severity::Critical }
+fn default_retry_attempts() -> u32 { 3 }
+fn default_retry_backoff() -> u64 { 500 }
+fn default_retry_max_backoff() -> u64 { 30_000 }
+
+/// Hostname of the local machine (`unknown` if it cannot be determined)
+pub fn local_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl Config {
+    /// Load configuration from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read config file: {:?}", path.as_ref()))?;
+
+        toml::from_str(&content)
+            .with_context(|| "Failed to parse configuration")
+    }
+
+    /// Complete default configuration, commented from the field docs
+    pub fn template() -> String {
+        template::render(
+            &schemars::schema_for!(Config),
+            "NetSentinel Aggregator configuration, generated by `netsentinel-aggregator \
+             print-default-config`. Optional sections are commented out.",
+        )
+    }
+
+    /// Tenant of each site of `[[tenants]]`
+    pub fn site_tenants(&self) -> Tenants {
+        Tenants::new(self.tenants.iter().map(|tenant| (tenant.name.as_str(), &tenant.sites[..])))
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        if self.database.max_connections < 1 {
+            anyhow::bail!("Database max_connections must be at least 1");
+        }
+
+        if self.aggregation.persist_interval_secs < 1 {
+            anyhow::bail!("Persist interval must be at least 1 second");
+        }
+
+        if !(8..=30).contains(&self.aggregation.vlan_subnet_prefix) {
+            anyhow::bail!("aggregation.vlan_subnet_prefix must be between 8 and 30");
+        }
+
+        if self.aggregation.device_id_cache_size < 1 {
+            anyhow::bail!("aggregation.device_id_cache_size must be at least 1");
+        }
+
+        if self.redis.consumer_name.is_empty() {
+            anyhow::bail!("Redis consumer_name cannot be empty");
+        }
+
+        if self.redis.ack_max_failed_cycles < 1 {
+            anyhow::bail!("Redis ack_max_failed_cycles must be at least 1");
+        }
+
+        if self.telemetry.enabled {
+            if !(0.0..=1.0).contains(&self.telemetry.sample_ratio) {
+                anyhow::bail!("telemetry.sample_ratio must be between 0.0 and 1.0");
+            }
+            if self.telemetry.export_interval_secs < 1 {
+                anyhow::bail!("telemetry.export_interval_secs must be at least 1");
+            }
+        }
+
+        if self.redis.heartbeat_interval_secs < 1
+            || self.redis.instance_ttl_secs <= self.redis.heartbeat_interval_secs
+        {
+            anyhow::bail!("Redis instance_ttl_secs must exceed heartbeat_interval_secs (>= 1)");
+        }
+
+        if self.metrics.enabled && !self.metrics.path.starts_with('/') {
+            anyhow::bail!("Metrics path must start with '/'");
+        }
+
+        if let Some(webhook) = &self.notifications.webhook {
+            if webhook.enabled && !webhook.url.starts_with("http") {
+                anyhow::bail!("notifications.webhook.url must be an http(s) URL");
+            }
+            if webhook.retry.max_attempts < 1 {
+                anyhow::bail!("notifications.webhook.retry.max_attempts must be at least 1");
+            }
+        }
+
+        if let Some(email) = self.notifications.email.as_ref().filter(|e| e.enabled) {
+            if email.to.is_empty() {
+                anyhow::bail!("notifications.email.to needs at least one recipient");
+            }
+            if email.retry.max_attempts < 1 {
+                anyhow::bail!("notifications.email.retry.max_attempts must be at least 1");
+            }
+        }
+
+        for (name, chat) in [("slack", &self.notifications.slack), ("teams", &self.notifications.teams)] {
+            let Some(chat) = chat.as_ref().filter(|c| c.enabled) else { continue };
+            let urls = std::iter::once(&chat.url).chain(chat.routes.iter().filter_map(|r| r.url.as_ref()));
+            for url in urls {
+                if !url.starts_with("http") {
+                    anyhow::bail!("notifications.{} URL '{}' must be an http(s) URL", name, url);
+                }
+            }
+            if chat.retry.max_attempts < 1 {
+                anyhow::bail!("notifications.{}.retry.max_attempts must be at least 1", name);
+            }
+        }
+
+        if let Some(mqtt) = self.notifications.mqtt.as_ref().filter(|m| m.enabled) {
+            if mqtt.qos > 2 {
+                anyhow::bail!("notifications.mqtt.qos must be 0, 1 or 2");
+            }
+            if mqtt.keep_alive_secs < 1 {
+                anyhow::bail!("notifications.mqtt.keep_alive_secs must be at least 1");
+            }
+        }
+
+        if let Some(snmp) = self.notifications.snmp.as_ref().filter(|s| s.enabled) {
+            if snmp.version == SnmpVersion::V3 && (snmp.username.is_none() || snmp.auth_password.is_none()) {
+                anyhow::bail!("notifications.snmp v3 requires username and auth_password");
+            }
+        }
+
+        if let Some(forwarder) = self.forwarder.as_ref().filter(|f| f.enabled) {
+            if forwarder.batch_size < 1 || forwarder.flush_interval_secs < 1 {
+                anyhow::bail!("forwarder batch_size and flush_interval_secs must be at least 1");
+            }
+            match forwarder.backend {
+                ForwarderBackend::Elasticsearch if forwarder.index.is_none() => {
+                    anyhow::bail!("forwarder.index is required for Elasticsearch");
+                }
+                ForwarderBackend::SplunkHec if forwarder.token.is_none() => {
+                    anyhow::bail!("forwarder.token is required for Splunk HEC");
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(ipfix) = self.ipfix.as_ref().filter(|i| i.enabled) {
+            if ipfix.interval_secs < 1 {
+                anyhow::bail!("ipfix.interval_secs must be at least 1");
+            }
+            if !(512..=65_507).contains(&ipfix.max_message_size) {
+                anyhow::bail!("ipfix.max_message_size must be between 512 and 65507");
+            }
+        }
+
+        if let Some(rules) = self.rules.as_ref().filter(|r| r.enabled) {
+            if rules.files.is_empty() {
+                anyhow::bail!("rules.files must list at least one rule file or directory");
+            }
+            if rules.scan_interval_secs < 1 {
+                anyhow::bail!("rules.scan_interval_secs must be at least 1");
+            }
+        }
+
+        if let Some(plugins) = self.plugins.as_ref().filter(|p| p.enabled) {
+            if plugins.modules.is_empty() {
+                anyhow::bail!("plugins.modules must list at least one module or directory");
+            }
+            if plugins.scan_interval_secs < 1 {
+                anyhow::bail!("plugins.scan_interval_secs must be at least 1");
+            }
+            if plugins.fuel < 1 {
+                anyhow::bail!("plugins.fuel must be at least 1");
+            }
+            if !(1..=4096).contains(&plugins.max_memory_mb) {
+                anyhow::bail!("plugins.max_memory_mb must be between 1 and 4096");
+            }
+        }
+
+        if let Some(bandwidth) = self.bandwidth.as_ref().filter(|b| b.enabled) {
+            if bandwidth.sample_interval_secs < 1 {
+                anyhow::bail!("bandwidth.sample_interval_secs must be at least 1");
+            }
+            for threshold in &bandwidth.thresholds {
+                if threshold.max_mbps.is_some() == threshold.max_bytes.is_some() {
+                    anyhow::bail!("Bandwidth threshold '{}' needs exactly one of max_mbps and max_bytes", threshold.name);
+                }
+                if threshold.window_secs < bandwidth.sample_interval_secs {
+                    anyhow::bail!("Bandwidth threshold '{}': window_secs is shorter than the sample interval", threshold.name);
+                }
+            }
+        }
+
+        if let Some(alerts) = self.new_device_alerts.as_ref().filter(|a| a.enabled) {
+            if alerts.policies.is_empty() {
+                anyhow::bail!("new_device_alerts needs at least one policy");
+            }
+        }
+
+        if let Some(beaconing) = self.beaconing.as_ref().filter(|b| b.enabled) {
+            if beaconing.min_connections < 3 {
+                anyhow::bail!("beaconing.min_connections must be at least 3");
+            }
+            if beaconing.min_period_secs >= beaconing.max_period_secs {
+                anyhow::bail!("beaconing.min_period_secs must be below max_period_secs");
+            }
+            if !(0.0..=1.0).contains(&beaconing.min_score) {
+                anyhow::bail!("beaconing.min_score must be between 0.0 and 1.0");
+            }
+        }
+
+        if let Some(exfiltration) = self.exfiltration.as_ref().filter(|e| e.enabled) {
+            if exfiltration.sample_interval_secs < 1 {
+                anyhow::bail!("exfiltration.sample_interval_secs must be at least 1");
+            }
+            if exfiltration.bucket_secs < exfiltration.sample_interval_secs {
+                anyhow::bail!("exfiltration.bucket_secs is shorter than the sample interval");
+            }
+            if exfiltration.baseline_buckets < 2 || exfiltration.leak_buckets < 1 {
+                anyhow::bail!("exfiltration.baseline_buckets must be at least 2 and leak_buckets at least 1");
+            }
+        }
+
+        if let Some(connections) = self.connections.as_ref().filter(|c| c.enabled) {
+            if connections.scan_interval_secs < 1 {
+                anyhow::bail!("connections.scan_interval_secs must be at least 1");
+            }
+            if connections.duration_factor < 1.0 {
+                anyhow::bail!("connections.duration_factor must be at least 1.0");
+            }
+        }
+
+        if let Some(scanners) = self.scanners.as_ref().filter(|s| s.enabled) {
+            if scanners.window_secs < 1 || scanners.flush_interval_secs < 1 {
+                anyhow::bail!("scanners.window_secs and flush_interval_secs must be at least 1");
+            }
+            if scanners.min_hosts < 2 || scanners.min_services < 2 {
+                anyhow::bail!("scanners.min_hosts and min_services must be at least 2");
+            }
+        }
+
+        if let Some(dns) = self.dns.as_ref().filter(|d| d.enabled) {
+            if dns.sample_interval_secs < 1 {
+                anyhow::bail!("dns.sample_interval_secs must be at least 1");
+            }
+            if dns.baseline_samples < 2 {
+                anyhow::bail!("dns.baseline_samples must be at least 2");
+            }
+            if !(0.0..=1.0).contains(&dns.min_ratio) {
+                anyhow::bail!("dns.min_ratio must be between 0.0 and 1.0");
+            }
+        }
+
+        if let Some(clock_drift) = self.clock_drift.as_ref().filter(|c| c.enabled) {
+            if clock_drift.sample_interval_secs < 1 {
+                anyhow::bail!("clock_drift.sample_interval_secs must be at least 1");
+            }
+            if clock_drift.min_frames < 1 {
+                anyhow::bail!("clock_drift.min_frames must be at least 1");
+            }
+        }
+
+        if let Some(ntp) = self.ntp.as_ref().filter(|n| n.enabled) {
+            if ntp.scan_interval_secs < 1 {
+                anyhow::bail!("ntp.scan_interval_secs must be at least 1");
+            }
+        }
+
+        if let Some(auth_servers) = self.auth_servers.as_ref().filter(|a| a.enabled) {
+            if auth_servers.interval_secs < 1 {
+                anyhow::bail!("auth_servers.interval_secs must be at least 1");
+            }
+            if auth_servers.window_days < 1 {
+                anyhow::bail!("auth_servers.window_days must be at least 1");
+            }
+        }
+
+        if let Some(ot) = self.ot.as_ref().filter(|o| o.enabled) {
+            if ot.scan_interval_secs < 1 {
+                anyhow::bail!("ot.scan_interval_secs must be at least 1");
+            }
+        }
+
+        let notifications = &self.notifications;
+        let schedules = [
+            ("webhook", notifications.webhook.as_ref().map(|c| &c.schedule)),
+            ("syslog", notifications.syslog.as_ref().map(|c| &c.schedule)),
+            ("email", notifications.email.as_ref().map(|c| &c.schedule)),
+            ("slack", notifications.slack.as_ref().map(|c| &c.schedule)),
+            ("teams", notifications.teams.as_ref().map(|c| &c.schedule)),
+            ("mqtt", notifications.mqtt.as_ref().map(|c| &c.schedule)),
+            ("snmp", notifications.snmp.as_ref().map(|c| &c.schedule)),
+        ];
+        for (sink, schedule) in schedules {
+            if schedule.is_some_and(|s| s.from.is_some() != s.to.is_some()) {
+                anyhow::bail!("notifications.{}.schedule needs both from and to", sink);
+            }
+        }
+
+        for window in &self.alerts.maintenance {
+            match (window.start, window.end, window.from, window.to) {
+                (Some(start), Some(end), None, None) if start < end => {}
+                (Some(_), Some(_), None, None) => {
+                    anyhow::bail!("Maintenance window '{}' ends before it starts", window.name);
+                }
+                (None, None, Some(_), Some(_)) => {}
+                _ => anyhow::bail!(
+                    "Maintenance window '{}' needs either start and end, or from and to",
+                    window.name
+                ),
+            }
+        }
+
+        if let Some(retention) = self.stream_retention.as_ref().filter(|r| r.enabled) {
+            if retention.interval_secs < 1 {
+                anyhow::bail!("stream_retention.interval_secs must be at least 1");
+            }
+            if !retention.trim_acknowledged && retention.max_len.is_none() && retention.max_age_secs.is_none() {
+                anyhow::bail!("stream_retention needs trim_acknowledged, max_len or max_age_secs");
+            }
+        }
+
+        if let Some(netbox) = self.netbox.as_ref().filter(|n| n.enabled) {
+            if !netbox.url.starts_with("http") {
+                anyhow::bail!("netbox.url must be an http(s) URL");
+            }
+            if netbox.interval_secs < 1 {
+                anyhow::bail!("netbox.interval_secs must be at least 1");
+            }
+            if netbox.min_confidence > 100 {
+                anyhow::bail!("netbox.min_confidence must be at most 100");
+            }
+        }
+
+        if let Some(servicenow) = self.servicenow.as_ref().filter(|s| s.enabled) {
+            match servicenow.output {
+                ServiceNowOutput::TableApi if servicenow.instance_url.is_none() => {
+                    anyhow::bail!("servicenow.instance_url is required for the table_api output");
+                }
+                ServiceNowOutput::ImportSet if servicenow.output_dir.is_none() => {
+                    anyhow::bail!("servicenow.output_dir is required for the import_set output");
+                }
+                _ => {}
+            }
+            if servicenow.interval_secs < 1 {
+                anyhow::bail!("servicenow.interval_secs must be at least 1");
+            }
+            if servicenow.min_confidence > 100 {
+                anyhow::bail!("servicenow.min_confidence must be at most 100");
+            }
+        }
+
+        if self.api.enabled && self.api.bind.parse::<std::net::SocketAddr>().is_err() {
+            anyhow::bail!("Invalid api.bind address '{}'", self.api.bind);
+        }
+
+        let mut names = std::collections::HashSet::new();
+        let mut sites = std::collections::HashSet::new();
+        let mut tokens: std::collections::HashSet<&str> = std::collections::HashSet::new();
+        for token in self.api.admin_tokens.iter().chain(&self.api.read_tokens) {
+            if token.is_empty() || !tokens.insert(token) {
+                anyhow::bail!("api.admin_tokens and api.read_tokens must be non-empty and distinct");
+            }
+        }
+        if let Some(oidc) = &self.api.oidc {
+            if oidc.issuer.is_empty() || oidc.audience.is_empty() {
+                anyhow::bail!("api.oidc needs an issuer and an audience");
+            }
+            if oidc.read_role == oidc.admin_role {
+                anyhow::bail!("api.oidc.read_role and api.oidc.admin_role must differ");
+            }
+        }
+        for tenant in &self.tenants {
+            if tenant.name.is_empty() || tenant.name.len() > 64 || tenant.name == "default" {
+                anyhow::bail!("Tenant name '{}' must be 1 to 64 characters long and not 'default'", tenant.name);
+            }
+            if !names.insert(tenant.name.as_str()) {
+                anyhow::bail!("Duplicate tenant '{}'", tenant.name);
+            }
+            if tenant.sites.is_empty() {
+                anyhow::bail!("Tenant '{}' needs at least one site", tenant.name);
+            }
+            for site in &tenant.sites {
+                if site.is_empty() || site.len() > 64 {
+                    anyhow::bail!("Tenant '{}': site names must be 1 to 64 characters long", tenant.name);
+                }
+                if !sites.insert(site.as_str()) {
+                    anyhow::bail!("Site '{}' belongs to several tenants", site);
+                }
+            }
+            for token in tenant.api_tokens.iter().chain(&tenant.read_tokens) {
+                if token.is_empty() || !tokens.insert(token) {
+                    anyhow::bail!("Tenant '{}': API tokens must be non-empty and not shared", tenant.name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_loads() {
+        let template = Config::template();
+        let config: Config = toml::from_str(&template).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.redis.batch_size, default_batch_size());
+        assert!(config.forwarder.is_none());
+        assert!(template.contains("# [forwarder]\n"));
+    }
+
+    #[test]
+    fn test_tenants() {
+        let template = Config::template();
+        let tenants = r#"
+[[tenants]]
+name = "acme"
+sites = ["paris", "lyon"]
+api_tokens = ["acme-token"]
+
+[[tenants]]
+name = "globex"
+sites = ["nantes"]
+"#;
+        let config: Config = toml::from_str(&format!("{}{}", template, tenants)).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.site_tenants().tenant_of_name("lyon").as_str(), "acme");
+
+        // A site belongs to one tenant
+        let shared = tenants.replace(r#"["nantes"]"#, r#"["paris"]"#);
+        let config: Config = toml::from_str(&format!("{}{}", template, shared)).unwrap();
+        assert!(config.validate().is_err());
     }
 }
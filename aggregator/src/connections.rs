@@ -0,0 +1,318 @@
+//! Long-lived and half-open connections
+//!
+//! The flow state is scanned every `scan_interval_secs` for two kinds of
+//! suspicious connection:
+//!
+//! - long-lived flows, alive for `max_duration_secs`, or for
+//!   `min_duration_secs` and `duration_factor` times the median duration of
+//!   their service (protocol and destination port), e.g. a reverse shell
+//!   kept open for days on a port where connections last seconds;
+//! - half-open connections, TCP flows that never got past their SYN, when a
+//!   device has at least `min_half_open` of them, e.g. a client hammering a
+//!   dead service or a SYN scan.
+//!
+//! Each long-lived flow is alerted once; a device with half-open connections
+//! is alerted again only once it has dropped back below the threshold.
+//! Alerts for long-lived flows carry the destination as their IP, so each
+//! destination is its own alert.
+
+use chrono::Utc;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::config::ConnectionsConfig;
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, DeviceKey, FlowKey};
+
+/// `2d 3h`, `3h 12m`, `45m`, `30s` style duration
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 86_400 => format!("{}d {}h", s / 86_400, s % 86_400 / 3600),
+        s if s >= 3600 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Protocol and destination port of a flow
+type Service = (Option<u8>, Option<u16>);
+
+/// Destination IP and port of a flow
+type Destination = (Option<Ipv4Addr>, Option<u16>);
+
+/// A flow long enough to be checked against its service
+struct Candidate {
+    id: Uuid,
+    key: FlowKey,
+    duration: u64,
+    bytes: u64,
+}
+
+/// Flags long-lived flows and devices with many half-open connections
+pub struct ConnectionMonitor {
+    config: ConnectionsConfig,
+    state: Arc<AggregatorState>,
+    /// Long-lived flows already alerted
+    long_lived: HashSet<Uuid>,
+    /// Devices over the half-open threshold at the previous scan
+    half_open: HashSet<DeviceKey>,
+}
+
+impl ConnectionMonitor {
+    pub fn new(config: ConnectionsConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            long_lived: HashSet::new(),
+            half_open: HashSet::new(),
+        }
+    }
+
+    /// Scan the flow state at unix time `now`
+    pub fn scan(&mut self, now: u64) -> Vec<Event> {
+        let shortest = match self.config.max_duration_secs {
+            0 => self.config.min_duration_secs,
+            max => max.min(self.config.min_duration_secs),
+        };
+
+        let mut durations: HashMap<Service, Vec<u64>> = HashMap::new();
+        let mut candidates = Vec::new();
+        let mut syn_only: HashMap<DeviceKey, HashMap<Destination, u64>> = HashMap::new();
+
+        for flow in self.state.flows.iter() {
+            let key = &flow.key;
+            let duration = flow.duration_secs();
+            durations.entry((key.protocol, key.dst_port)).or_default().push(duration);
+
+            if duration >= shortest && !key.dst_port.is_some_and(|p| self.config.ignore_ports.contains(&p)) {
+                candidates.push(Candidate {
+                    id: flow.id,
+                    key: key.clone(),
+                    duration,
+                    bytes: flow.byte_count.load(Ordering::Relaxed),
+                });
+            }
+
+            let age = now.saturating_sub(flow.first_seen.timestamp() as u64);
+            if flow.is_tcp_syn_only() && age >= self.config.half_open_grace_secs {
+                *syn_only.entry(key.src_device()).or_default().entry((key.dst_ip, key.dst_port)).or_default() += 1;
+            }
+        }
+
+        let medians: HashMap<Service, u64> = durations.into_iter()
+            .filter(|(_, d)| d.len() >= self.config.min_samples)
+            .map(|(service, mut d)| {
+                d.sort_unstable();
+                (service, d[d.len() / 2])
+            })
+            .collect();
+
+        let mut alerts = Vec::new();
+        let mut long_lived = HashSet::new();
+        for candidate in candidates {
+            let median = medians.get(&(candidate.key.protocol, candidate.key.dst_port)).copied();
+            let absolute = self.config.max_duration_secs > 0 && candidate.duration >= self.config.max_duration_secs;
+            let relative = median.is_some_and(|m| {
+                candidate.duration >= self.config.min_duration_secs
+                    && candidate.duration as f64 >= self.config.duration_factor * m as f64
+            });
+            if !absolute && !relative {
+                continue;
+            }
+            long_lived.insert(candidate.id);
+            if !self.long_lived.contains(&candidate.id) {
+                alerts.push(self.long_lived_alert(&candidate, median));
+            }
+        }
+        self.long_lived = long_lived;
+
+        let mut half_open = HashSet::new();
+        if self.config.min_half_open > 0 {
+            for (device, destinations) in syn_only {
+                let count: u64 = destinations.values().sum();
+                if (count as usize) < self.config.min_half_open {
+                    continue;
+                }
+                half_open.insert(device);
+                if !self.half_open.contains(&device) {
+                    alerts.push(self.half_open_alert(device, count, destinations));
+                }
+            }
+        }
+        self.half_open = half_open;
+
+        alerts
+    }
+
+    fn long_lived_alert(&self, candidate: &Candidate, median: Option<u64>) -> Event {
+        let key = &candidate.key;
+        let typical = match (median, key.dst_port) {
+            (Some(m), Some(port)) => format!(" (typical for port {}: {})", port, format_duration(m)),
+            _ => String::new(),
+        };
+
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: self.config.long_lived_severity,
+            name: self.config.long_lived_name.clone(),
+            message: format!(
+                "Flow {} alive for {}{}",
+                key.to_display_string(), format_duration(candidate.duration), typical
+            ),
+            site: Some(key.site.to_string()),
+            sensor: None,
+            mac: Some(key.src_mac.to_string()),
+            ip: key.dst_ip,
+            channels: self.config.notify.clone(),
+            details: Some(json!({
+                "flow_id": candidate.id,
+                "flow": key.to_display_string(),
+                "src_ip": key.src_ip,
+                "dst_ip": key.dst_ip,
+                "dst_port": key.dst_port,
+                "protocol": key.protocol,
+                "duration_secs": candidate.duration,
+                "typical_duration_secs": median,
+                "bytes": candidate.bytes,
+            })),
+        }
+    }
+
+    fn half_open_alert(&self, device: DeviceKey, count: u64, destinations: HashMap<Destination, u64>) -> Event {
+        let distinct = destinations.len();
+        let mut destinations: Vec<_> = destinations.into_iter().collect();
+        destinations.sort_by_key(|d| std::cmp::Reverse(d.1));
+        destinations.truncate(self.config.breakdown_destinations);
+
+        let ip = self.state.devices.get(&device).and_then(|d| d.ips.iter().next().map(|ip| *ip.key()));
+
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: self.config.half_open_severity,
+            name: self.config.half_open_name.clone(),
+            message: format!(
+                "Device {} has {} half-open TCP connections to {} destinations",
+                device.mac, count, distinct
+            ),
+            site: Some(device.site.to_string()),
+            sensor: None,
+            mac: Some(device.mac.to_string()),
+            ip,
+            channels: self.config.notify.clone(),
+            details: Some(json!({
+                "half_open": count,
+                "destinations": destinations.iter().map(|((ip, port), count)| json!({
+                    "ip": ip,
+                    "port": port,
+                    "connections": count,
+                })).collect::<Vec<_>>(),
+            })),
+        }
+    }
+
+    /// Scan until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!("Checking for long-lived and half-open connections every {}s", self.config.scan_interval_secs);
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.scan(Utc::now().timestamp() as u64) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Connection monitor stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{FlowState, MacAddr, SensorId, SiteId};
+    use chrono::TimeZone;
+
+    fn monitor() -> (ConnectionMonitor, Arc<AggregatorState>) {
+        let config: ConnectionsConfig = toml::from_str(r#"
+min_duration_secs = 600
+min_samples = 5
+min_half_open = 3
+"#).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        (ConnectionMonitor::new(config, Arc::clone(&state)), state)
+    }
+
+    /// Add a flow seen from `first` to `last` (unix times) with `flags`
+    fn add_flow(state: &AggregatorState, src_port: u16, dst: [u8; 4], dst_port: u16, first: u64, last: u64, flags: u8) {
+        let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            dst_mac: MacAddr::new([0x00, 0x66, 0x77, 0x88, 0x99, 0xaa]),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+            dst_ip: Some(Ipv4Addr::from(dst)),
+            src_port: Some(src_port),
+            dst_port: Some(dst_port),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        };
+        let flow = FlowState::new(key.clone(), Utc.timestamp_opt(first as i64, 0).unwrap());
+        flow.update(100, Some(flags), last);
+        state.flows.insert(key, flow);
+    }
+
+    #[test]
+    fn test_long_lived() {
+        let (mut monitor, state) = monitor();
+        for port in 0..9 {
+            add_flow(&state, 40000 + port, [198, 51, 100, 7], 443, 1000, 1030, 0x18);
+        }
+        add_flow(&state, 41000, [198, 51, 100, 7], 443, 1000, 1000 + 7200, 0x18);
+        // Long, but no typical duration known for the port
+        add_flow(&state, 42000, [203, 0, 113, 9], 8443, 1000, 1000 + 7200, 0x18);
+
+        let alerts = monitor.scan(10_000);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, ip, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Flow 10.0.0.5:41000 -> 198.51.100.7:443 [TCP] alive for 2h 0m (typical for port 443: 30s)");
+        assert_eq!(*ip, Some(Ipv4Addr::new(198, 51, 100, 7)));
+
+        assert!(monitor.scan(10_060).is_empty());
+    }
+
+    #[test]
+    fn test_half_open() {
+        let (mut monitor, state) = monitor();
+        add_flow(&state, 40000, [10, 0, 0, 80], 80, 1000, 1000, 0x02);
+        add_flow(&state, 40001, [10, 0, 0, 80], 80, 1000, 1000, 0x02);
+        // Completed handshake
+        add_flow(&state, 40002, [10, 0, 0, 80], 80, 1000, 1001, 0x12);
+        // Still within the grace period
+        add_flow(&state, 40003, [10, 0, 0, 81], 80, 1995, 1995, 0x02);
+        assert!(monitor.scan(2000).is_empty());
+
+        let alerts = monitor.scan(2010);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, details, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Device 00:11:22:33:44:55 has 3 half-open TCP connections to 2 destinations");
+        assert_eq!(details.as_ref().unwrap()["destinations"][0]["connections"], 2);
+
+        assert!(monitor.scan(2020).is_empty());
+    }
+}
@@ -0,0 +1,3425 @@
+//! Read queries used by the HTTP API
+//!
+//! Rows are mapped into the same snapshot types the in-memory state produces
+//! so API consumers see one shape regardless of where the data came from.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use super::Database;
+use crate::alerts::AlertStatus;
+use crate::auth_servers::{AuthRole, AuthServer, ServiceActivity};
+use crate::events::Severity;
+use crate::rules::Cidr;
+use crate::state::MacAddr;
+use crate::state::{class_name, evidence_names, CertificateInfo, DeviceSnapshot, FlowSnapshot, IpSnapshot, MaintenanceEntry, MulticastSnapshot, SitePresence, SubnetTraffic, TcpOutcome, TcpHealthSnapshot, VlanSnapshot, VlanSubnet, L2Segment, ETHERTYPE_IPV4};
+
+/// Where a page of a list starts
+///
+/// Lists sorted most recently seen first can also start right after a row,
+/// which stays cheap however deep the page and steady while rows come and
+/// go. Written `o<offset>` or `k<microseconds>.<id>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cursor {
+    /// After this many rows
+    Offset(usize),
+    /// After the row last seen at this time with this ID
+    After(DateTime<Utc>, Uuid),
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor::Offset(0)
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cursor::Offset(offset) => write!(f, "o{}", offset),
+            Cursor::After(last_seen, id) => write!(f, "k{}.{}", last_seen.timestamp_micros(), id),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = match s.split_at_checked(1) {
+            Some(("o", offset)) => offset.parse().ok().map(Cursor::Offset),
+            Some(("k", key)) => key.split_once('.').and_then(|(micros, id)| {
+                let last_seen = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+                Some(Cursor::After(last_seen, id.parse().ok()?))
+            }),
+            _ => None,
+        };
+        parsed.with_context(|| format!("Invalid cursor '{}'", s))
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Start a list sorted most recently seen first at `cursor`, once its filters
+/// are pushed
+fn push_page(query: &mut QueryBuilder<Postgres>, cursor: Cursor, limit: usize) {
+    if let Cursor::After(last_seen, id) = cursor {
+        query.push(" AND (last_seen, id) < (").push_bind(last_seen).push(", ").push_bind(id).push(")");
+    }
+    query.push(" ORDER BY last_seen DESC, id DESC LIMIT ").push_bind(limit as i64);
+    if let Cursor::Offset(offset) = cursor {
+        query.push(" OFFSET ").push_bind(offset as i64);
+    }
+}
+
+/// Device list filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceFilter {
+    /// Seen on this site
+    pub site: Option<String>,
+    /// Seen on this VLAN
+    pub vlan: Option<u16>,
+    /// Holds this IP address
+    pub ip: Option<Ipv4Addr>,
+    /// OUI prefix, e.g. `00:11:22`
+    pub oui: Option<String>,
+    pub gateway: Option<bool>,
+    /// Seen within the inactivity timeout
+    pub active: Option<bool>,
+    /// Confidence score at least this high
+    pub min_confidence: Option<u8>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// Device search filter (`/api/search`); every filter given must hold
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFilter {
+    /// Seen on this site
+    pub site: Option<String>,
+    /// Part of the MAC address, e.g. `44:55`
+    pub mac: Option<String>,
+    /// OUI prefix, e.g. `00:11:22`
+    pub oui: Option<String>,
+    /// Part of a vendor name of the `[vendors]` table, ignoring case
+    pub vendor: Option<String>,
+    /// Holds this IP address
+    pub ip: Option<Ipv4Addr>,
+    /// Holds an IP address of this network, e.g. `10.0.20.0/24`
+    pub cidr: Option<Cidr>,
+    /// Seen on this VLAN
+    pub vlan: Option<u16>,
+    /// Part of the hostname announced over DHCP, ignoring case
+    pub hostname: Option<String>,
+    /// Device type: its `type` tag, otherwise `gateway` or `unknown`
+    #[serde(rename = "type")]
+    pub device_type: Option<String>,
+    /// Tag name, or `name:value`
+    pub tag: Option<String>,
+    /// Last seen at or after
+    pub since: Option<DateTime<Utc>>,
+    /// Last seen before
+    pub until: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+impl SearchFilter {
+    /// Name and value (when given) of the tag filter
+    pub fn tag(&self) -> Option<(&str, Option<&str>)> {
+        let tag = self.tag.as_deref()?;
+        Some(match tag.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (tag, None),
+        })
+    }
+
+    /// MAC address part, lowercase with `:` separators as addresses are
+    /// written
+    pub fn mac_part(&self) -> Option<String> {
+        self.mac.as_ref().map(|mac| mac.to_lowercase().replace('-', ":"))
+    }
+}
+
+/// Flow list filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlowFilter {
+    pub site: Option<String>,
+    /// Capture instance that saw the flow
+    pub sensor: Option<String>,
+    /// Source or destination MAC address
+    pub mac: Option<String>,
+    /// Source or destination IP address
+    pub ip: Option<Ipv4Addr>,
+    pub src_ip: Option<Ipv4Addr>,
+    pub dst_ip: Option<Ipv4Addr>,
+    /// Source or destination port
+    pub port: Option<u16>,
+    /// Service name, e.g. `https`
+    pub service: Option<String>,
+    /// IP protocol number
+    pub protocol: Option<u8>,
+    pub vlan: Option<u16>,
+    /// How the TCP handshake went (`established`, `rejected`, `unanswered`, `reset`)
+    pub tcp_outcome: Option<TcpOutcome>,
+    /// Seen at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// End of the flows of the flow history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEndpoint {
+    Mac(MacAddr),
+    /// IP address (a /32) or network
+    Network(Cidr),
+}
+
+impl FromStr for FlowEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(mac) = MacAddr::from_string(s) {
+            return Ok(FlowEndpoint::Mac(mac));
+        }
+        let network = s.parse().with_context(|| format!("Invalid endpoint '{}': not a MAC, IP or network", s))?;
+        Ok(FlowEndpoint::Network(network))
+    }
+}
+
+impl<'de> Deserialize<'de> for FlowEndpoint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(|e: anyhow::Error| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+/// Aggregation of the flow history, per flow when neither is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowGrouping {
+    pub hour: bool,
+    pub service: bool,
+}
+
+impl FromStr for FlowGrouping {
+    type Err = anyhow::Error;
+
+    /// `hour`, `service`, or both: `hour,service`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut grouping = FlowGrouping::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part {
+                "hour" => grouping.hour = true,
+                "service" => grouping.service = true,
+                other => bail!("Unknown aggregation '{}': expected hour or service", other),
+            }
+        }
+        Ok(grouping)
+    }
+}
+
+impl<'de> Deserialize<'de> for FlowGrouping {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Flow history filter (`/api/flows/history`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlowHistoryFilter {
+    /// Device the flows are from or to: MAC or IP address
+    pub device: FlowEndpoint,
+    /// Other end of the flows: MAC address, IP address or network
+    pub peer: Option<FlowEndpoint>,
+    pub site: Option<String>,
+    /// Window ending now, unless `since` is set (24 by default)
+    pub hours: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Aggregation: `hour`, `service` or `hour,service`
+    #[serde(default)]
+    pub by: FlowGrouping,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct FlowHistoryRow {
+    src_mac: Option<String>,
+    src_ip: Option<String>,
+    src_port: Option<i32>,
+    dst_mac: Option<String>,
+    dst_ip: Option<String>,
+    dst_port: Option<i32>,
+    ip_protocol: Option<i16>,
+    service: Option<String>,
+    hour: Option<DateTime<Utc>>,
+    first_hour: DateTime<Utc>,
+    last_hour: DateTime<Utc>,
+    flows: i64,
+    packets: i64,
+    bytes: i64,
+    total: i64,
+}
+
+/// Traffic of the flow history over a window, per flow or aggregated
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowHistory {
+    /// Start of the hour, when aggregated per hour
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hour: Option<DateTime<Utc>>,
+    /// Service of the flow, or of the aggregate (`other` when unnamed)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Endpoints of the flow, when not aggregated
+    #[serde(flatten)]
+    pub flow: Option<FlowEndpoints>,
+    /// First and last hours with traffic in the window
+    pub first_hour: DateTime<Utc>,
+    pub last_hour: DateTime<Utc>,
+    pub flows: u64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Endpoints of a flow of the flow history
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowEndpoints {
+    pub src_mac: String,
+    pub src_ip: Option<Ipv4Addr>,
+    pub src_port: Option<u16>,
+    pub dst_mac: String,
+    pub dst_ip: Option<Ipv4Addr>,
+    pub dst_port: Option<u16>,
+    pub protocol: Option<u8>,
+}
+
+/// Counter top devices are ranked by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopBy {
+    /// Bytes sent and received
+    #[default]
+    Bytes,
+    /// Packets sent and received
+    Packets,
+}
+
+impl TopBy {
+    /// Value of the counter for `device`
+    pub fn value(&self, device: &DeviceSnapshot) -> u64 {
+        match self {
+            TopBy::Bytes => device.bytes_sent + device.bytes_received,
+            TopBy::Packets => device.packets_sent + device.packets_received,
+        }
+    }
+
+    /// SQL expression of the counter over the `devices` table
+    fn column(&self) -> &'static str {
+        match self {
+            TopBy::Bytes => "COALESCE(total_bytes_sent, 0) + COALESCE(total_bytes_received, 0)",
+            TopBy::Packets => "COALESCE(total_packets_sent, 0) + COALESCE(total_packets_received, 0)",
+        }
+    }
+}
+
+impl FromStr for TopBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bytes" => Ok(TopBy::Bytes),
+            "packets" => Ok(TopBy::Packets),
+            other => bail!("Unknown counter '{}' (bytes or packets)", other),
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DeviceRow {
+    id: Uuid,
+    site: String,
+    mac_address: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total_packets_sent: Option<i64>,
+    total_packets_received: Option<i64>,
+    total_bytes_sent: Option<i64>,
+    total_bytes_received: Option<i64>,
+    is_gateway: Option<bool>,
+    is_flagged: Option<bool>,
+    evidence: i16,
+    confidence: i16,
+    tags: String,
+    tcp_data_segments: Option<i64>,
+    tcp_retransmits: Option<i64>,
+    tcp_resets: Option<i64>,
+    device_notes: Option<String>,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct DeviceIpRow {
+    device_id: Uuid,
+    ip_address: String,
+    vlan_id: Option<i16>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    packets_sent: Option<i64>,
+    packets_received: Option<i64>,
+    bytes_sent: Option<i64>,
+    bytes_received: Option<i64>,
+    arp_last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow)]
+struct DeviceSiteRow {
+    device_id: Uuid,
+    site: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total_packets_sent: Option<i64>,
+    total_packets_received: Option<i64>,
+    total_bytes_sent: Option<i64>,
+    total_bytes_received: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct FlowRow {
+    id: Uuid,
+    site: String,
+    sensor: String,
+    src_mac: String,
+    dst_mac: String,
+    src_ip: Option<String>,
+    dst_ip: Option<String>,
+    src_port: Option<i32>,
+    dst_port: Option<i32>,
+    vlan_id: Option<i16>,
+    ethertype: Option<i16>,
+    ip_protocol: Option<i16>,
+    icmp_type: Option<i16>,
+    icmp_code: Option<i16>,
+    icmp_id: Option<i32>,
+    service: Option<String>,
+    first_seen: DateTime<Utc>,
+    first_seen_ns: Option<i64>,
+    last_seen: DateTime<Utc>,
+    packet_count: i64,
+    byte_count: i64,
+    tcp_flags_seen: Option<i16>,
+    tcp_data_segments: Option<i64>,
+    tcp_retransmits: Option<i64>,
+    tcp_resets: Option<i64>,
+    tcp_outcome: Option<String>,
+    total: i64,
+}
+
+/// L2 segment list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SegmentFilter {
+    pub site: Option<String>,
+    /// Segment of this device
+    pub mac: Option<String>,
+    /// Seen on this `sensor/interface`
+    pub capture_point: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct SegmentRow {
+    site: String,
+    segment_id: String,
+    capture_points: Vec<String>,
+    devices: Vec<String>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// Multicast group list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MulticastFilter {
+    pub site: Option<String>,
+    pub group: Option<Ipv4Addr>,
+    /// Groups this device is a member or sender of
+    pub mac: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// Inter-subnet traffic matrix filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SubnetMatrixFilter {
+    pub site: Option<String>,
+    /// Pairs from or to this subnet (`10.0.20.0/24`, `0.0.0.0/0` outside)
+    pub subnet: Option<String>,
+    /// Hours of traffic summed (24 by default)
+    pub hours: Option<i64>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct SubnetTrafficRow {
+    site: String,
+    src_subnet: String,
+    dst_subnet: String,
+    packets: i64,
+    bytes: i64,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct MulticastRow {
+    site: String,
+    group_address: String,
+    members: Vec<String>,
+    senders: Vec<String>,
+    packets: i64,
+    bytes: i64,
+    bits_per_sec: f64,
+    peak_bits_per_sec: f64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// VLAN to subnet mapping filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VlanSubnetFilter {
+    pub site: Option<String>,
+    pub vlan: Option<u16>,
+    /// Subnet containing this address
+    pub ip: Option<Ipv4Addr>,
+    /// Only mappings with (or without) conflicts
+    pub conflicts: Option<bool>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct VlanSubnetRow {
+    site: String,
+    vlan_id: i16,
+    subnet: String,
+    hosts: i32,
+    confidence: f32,
+    is_primary: bool,
+    conflicts: Vec<String>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct VlanRow {
+    site: String,
+    vlan_id: i16,
+    outer_vlan_id: Option<i16>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total_packets: Option<i64>,
+    total_bytes: Option<i64>,
+    device_count: Option<i32>,
+    priority_packets: Vec<i64>,
+    priority_bytes: Vec<i64>,
+    total: i64,
+}
+
+/// Per-priority counters of a VLAN row, missing priorities counting zero
+fn priority_counts(counts: &[i64]) -> [u64; 8] {
+    let mut priorities = [0; 8];
+    for (priority, count) in priorities.iter_mut().zip(counts) {
+        *priority = *count as u64;
+    }
+    priorities
+}
+
+/// Alert list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AlertFilter {
+    pub status: Option<AlertStatus>,
+    /// Alert (rule or threshold) name
+    pub name: Option<String>,
+    /// Minimum severity
+    pub severity: Option<Severity>,
+    pub site: Option<String>,
+    /// Device MAC address
+    pub mac: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// Persisted alert, with its lifecycle state
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredAlert {
+    pub id: Uuid,
+    pub name: String,
+    pub severity: Severity,
+    pub message: String,
+    pub site: Option<String>,
+    pub mac_address: Option<String>,
+    pub ip_address: Option<Ipv4Addr>,
+    pub details: Option<serde_json::Value>,
+    pub status: AlertStatus,
+    /// Times the alert fired while unresolved
+    pub occurrences: u64,
+    pub raised_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub last_notified: Option<DateTime<Utc>>,
+    /// Maintenance window the alert last fired in
+    pub suppressed_by: Option<String>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<String>,
+    pub note: Option<String>,
+}
+
+/// A device's traffic during one hour, by service
+#[derive(Debug, Clone, Serialize)]
+pub struct HourlyComposition {
+    pub hour: DateTime<Utc>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub services: Vec<ServiceTraffic>,
+}
+
+/// Bytes of one service (or of the `other` bucket)
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceTraffic {
+    pub service: String,
+    /// Name of the service's port (`https`, or a name of `[services]`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// A device's DNS activity during one hour
+#[derive(Debug, Clone, Serialize)]
+pub struct HourlyDnsStats {
+    pub hour: DateTime<Utc>,
+    pub queries: u64,
+    pub responses: u64,
+    pub nxdomain: u64,
+    pub nxdomain_rate: f64,
+    pub distinct_domains: u32,
+    /// Mean Shannon entropy of the names queried (bits per character)
+    pub mean_entropy: f32,
+    pub dga_like_domains: u32,
+    pub top_domains: Vec<DomainQueries>,
+}
+
+/// Queries for one name
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainQueries {
+    pub domain: String,
+    pub queries: u64,
+}
+
+#[derive(FromRow)]
+struct HourlyDnsRow {
+    hour: DateTime<Utc>,
+    queries: i64,
+    responses: i64,
+    nxdomain: i64,
+    distinct_domains: i32,
+    mean_entropy: f32,
+    dga_like_domains: i32,
+    top_domains: Vec<String>,
+    top_domain_queries: Vec<i64>,
+}
+
+/// A device's scores by one baseline detector during one hour
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct HourlyAnomaly {
+    pub hour: DateTime<Utc>,
+    pub detector: String,
+    pub samples: i64,
+    /// Mean standard deviations from the baseline
+    pub mean_score: f64,
+    pub max_score: f64,
+    /// Mean value scored (bytes or NXDOMAIN responses per period)
+    pub mean_value: f64,
+    /// Baseline mean at the last sample
+    pub baseline: f64,
+}
+
+/// A device's traffic sent during one hour, by VLAN and QoS class
+#[derive(Debug, Clone, Serialize)]
+pub struct HourlyQosStats {
+    pub hour: DateTime<Utc>,
+    pub bytes: u64,
+    pub classes: Vec<QosClassTraffic>,
+}
+
+/// Bytes sent on one VLAN (0 when untagged) with one DSCP value
+#[derive(Debug, Clone, Serialize)]
+pub struct QosClassTraffic {
+    pub vlan_id: u16,
+    pub dscp: u8,
+    /// `ef`, `af41`, `cs1`, `be`...
+    pub class: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(FromRow)]
+struct HourlyQosRow {
+    hour: DateTime<Utc>,
+    vlan_id: i16,
+    dscp: i16,
+    packets: i64,
+    bytes: i64,
+}
+
+#[derive(FromRow)]
+struct HourlyTrafficRow {
+    hour: DateTime<Utc>,
+    service: String,
+    bytes_sent: i64,
+    bytes_received: i64,
+}
+
+/// Device that appeared or went quiet during a change report period
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DeviceChange {
+    pub site: String,
+    pub mac_address: String,
+    /// Most recently seen IP address
+    pub ip_address: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Service a known device started using during a change report period
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServiceChange {
+    pub site: String,
+    pub mac_address: String,
+    /// `tcp/443` style service name
+    pub service: String,
+    /// Hour the service was first seen in
+    pub first_seen: DateTime<Utc>,
+}
+
+/// IP address a known device started using during a change report period
+#[derive(Debug, Clone, Serialize)]
+pub struct IpChange {
+    pub site: String,
+    pub mac_address: String,
+    pub ip_address: String,
+    pub vlan_id: Option<u16>,
+    pub first_seen: DateTime<Utc>,
+    /// Addresses the device held before the period, most recent first
+    pub previous: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct IpChangeRow {
+    site: String,
+    mac_address: String,
+    ip_address: String,
+    vlan_id: Option<i16>,
+    first_seen: DateTime<Utc>,
+    previous: Vec<String>,
+}
+
+/// Traffic from one device to another under one service, summed over the
+/// flows active during a time window
+#[derive(Debug, Clone)]
+pub struct TrafficLink {
+    pub src_mac: String,
+    pub dst_mac: String,
+    pub ethertype: u16,
+    pub ip_protocol: Option<u8>,
+    /// Lower of the two ports
+    pub port: Option<u16>,
+    pub flows: u64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(FromRow)]
+struct TrafficLinkRow {
+    src_mac: String,
+    dst_mac: String,
+    ethertype: Option<i16>,
+    ip_protocol: Option<i16>,
+    port: Option<i32>,
+    flows: i64,
+    packets: i64,
+    bytes: i64,
+}
+
+/// Inventory details of a device drawn in the network graph
+#[derive(Debug, Clone, FromRow)]
+pub struct GraphDevice {
+    pub mac_address: String,
+    pub site: String,
+    /// Most recently seen IP address
+    pub ip_address: Option<String>,
+    pub is_gateway: Option<bool>,
+}
+
+/// Service dependency list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DependencyFilter {
+    pub site: Option<String>,
+    /// Client or server device MAC address
+    pub mac: Option<String>,
+    /// Client device MAC address
+    pub client: Option<String>,
+    /// Server device MAC address
+    pub server: Option<String>,
+    /// Service, e.g. `tcp/443` or `https`
+    pub service: Option<String>,
+    /// Only edges active in at least this many hours
+    pub min_active_hours: Option<u32>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A client device's dependency on a service of a server device
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredDependency {
+    pub site: String,
+    pub client_mac: String,
+    pub server_mac: String,
+    pub service: String,
+    /// Name of the service, e.g. `https`
+    pub service_name: Option<String>,
+    pub flows: u64,
+    pub packets_to_server: u64,
+    pub bytes_to_server: u64,
+    pub packets_to_client: u64,
+    pub bytes_to_client: u64,
+    pub active_hours: u32,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct DependencyRow {
+    site: String,
+    client_mac: String,
+    server_mac: String,
+    service: String,
+    service_name: Option<String>,
+    flows: i64,
+    packets_to_server: i64,
+    bytes_to_server: i64,
+    packets_to_client: i64,
+    bytes_to_client: i64,
+    active_hours: i32,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// Authentication server list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthServerFilter {
+    pub site: Option<String>,
+    /// Server MAC address
+    pub mac: Option<String>,
+    /// `domain_controller`, `kerberos`, `ldap` or `smb`
+    pub role: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct AuthServerRow {
+    site: String,
+    mac_address: String,
+    device_id: Option<Uuid>,
+    role: String,
+    services: Vec<String>,
+    clients: i32,
+    flows: i64,
+    bytes: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct ServiceActivityRow {
+    site: String,
+    server_mac: String,
+    device_id: Option<Uuid>,
+    service: String,
+    clients: i64,
+    flows: i64,
+    bytes: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+/// Authentication client filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthClientFilter {
+    pub site: Option<String>,
+    /// Client device MAC address
+    pub client: Option<String>,
+    /// Server MAC address
+    pub server: Option<String>,
+    /// Role of the server (default: all)
+    pub role: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A client's dependency on an authentication server
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuthClient {
+    pub site: String,
+    pub client_mac: String,
+    pub server_mac: String,
+    pub role: String,
+    /// Services of the role the client uses
+    pub services: Vec<String>,
+    pub flows: i64,
+    pub bytes: i64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    #[serde(skip)]
+    total: i64,
+}
+
+/// Scanner list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ScannerFilter {
+    /// Targeted service, e.g. `tcp/22`
+    pub service: Option<String>,
+    /// Only scanners seen within this many seconds
+    pub seen_within_secs: Option<u64>,
+}
+
+/// Maintenance list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MaintenanceFilter {
+    pub site: Option<String>,
+    pub mac: Option<String>,
+    pub vlan: Option<u16>,
+    /// In effect now (or over)
+    pub active: Option<bool>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(FromRow)]
+struct MaintenanceRow {
+    id: Uuid,
+    tenant: String,
+    site: String,
+    mac_address: Option<String>,
+    vlan_id: Option<i16>,
+    reason: Option<String>,
+    created_by: Option<String>,
+    starts_at: DateTime<Utc>,
+    until: DateTime<Utc>,
+    total: i64,
+}
+
+impl MaintenanceRow {
+    fn into_entry(self) -> MaintenanceEntry {
+        MaintenanceEntry {
+            id: self.id,
+            site: self.site,
+            mac_address: self.mac_address,
+            vlan_id: self.vlan_id.map(|v| v as u16),
+            reason: self.reason,
+            created_by: self.created_by,
+            starts_at: self.starts_at,
+            until: self.until,
+        }
+    }
+}
+
+const MAINTENANCE_COLUMNS: &str = "SELECT id, tenant, site, mac_address::text AS mac_address, vlan_id, reason, \
+    created_by, starts_at, until, COUNT(*) OVER () AS total FROM device_maintenance";
+
+/// Saved position of a frames stream consumer
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct StreamCheckpoint {
+    pub consumer: String,
+    /// Last entry applied and persisted
+    pub last_id: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// External source identified as a scanner
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredScanner {
+    pub ip_address: Ipv4Addr,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub probes: u64,
+    /// Most distinct internal hosts probed
+    pub hosts_targeted: u32,
+    pub services: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct ScannerRow {
+    ip_address: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    probes: i64,
+    hosts_targeted: i32,
+    services: Vec<String>,
+    total: i64,
+}
+
+/// TLS inventory filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsFilter {
+    pub site: Option<String>,
+    /// Client device MAC address
+    pub mac: Option<String>,
+    /// Server name containing this text, e.g. `dropbox`
+    pub sni: Option<String>,
+    /// Certificate already expired (or still valid)
+    pub expired: Option<bool>,
+    /// Certificate expiring within this many days
+    pub expires_within_days: Option<u32>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A client device's TLS destination and the certificate it presented
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredTlsObservation {
+    pub site: String,
+    pub client_mac: String,
+    pub server_ip: Ipv4Addr,
+    pub server_port: u16,
+    pub sni: Option<String>,
+    pub certificate: Option<CertificateInfo>,
+    pub handshakes: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// TLS fingerprint list filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsFingerprintFilter {
+    pub site: Option<String>,
+    /// Device MAC address
+    pub mac: Option<String>,
+    /// `ja3` or `ja3s`
+    pub kind: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A JA3 or JA3S fingerprint seen on a device
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredTlsFingerprint {
+    pub site: String,
+    pub mac_address: String,
+    pub kind: String,
+    pub fingerprint: String,
+    pub count: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Device NTP server filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NtpServerFilter {
+    pub site: Option<String>,
+    /// Client device MAC address
+    pub mac: Option<String>,
+    /// Time server address
+    pub server: Option<Ipv4Addr>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A time server a device queries
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredNtpServer {
+    pub site: String,
+    pub mac_address: String,
+    pub client_ip: Option<Ipv4Addr>,
+    pub server_ip: Ipv4Addr,
+    pub version: u8,
+    pub stratum: Option<u8>,
+    pub reference_id: Option<String>,
+    pub requests: u64,
+    pub responses: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct NtpServerRow {
+    site: String,
+    mac_address: String,
+    client_ip: Option<String>,
+    server_ip: String,
+    version: i16,
+    stratum: Option<i16>,
+    reference_id: Option<String>,
+    requests: i64,
+    responses: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// Industrial device filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OtDeviceFilter {
+    pub site: Option<String>,
+    pub mac: Option<String>,
+    /// `modbus`, `dnp3` or `bacnet`
+    pub protocol: Option<String>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// An industrial device, per protocol it speaks
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredOtDevice {
+    pub site: String,
+    pub mac_address: String,
+    pub protocol: String,
+    pub ip_address: Option<Ipv4Addr>,
+    /// Modbus units, DNP3 outstation addresses or BACnet device instances
+    pub units: Vec<u32>,
+    pub requests: u64,
+    pub responses: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct OtDeviceRow {
+    site: String,
+    mac_address: String,
+    protocol: String,
+    ip_address: Option<String>,
+    units: Vec<i32>,
+    requests: i64,
+    responses: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// Industrial write filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OtWriteFilter {
+    pub site: Option<String>,
+    /// MAC address of the source writing
+    pub source: Option<String>,
+    /// MAC address of the device written to
+    pub device: Option<String>,
+    /// `modbus`, `dnp3` or `bacnet`
+    pub protocol: Option<String>,
+    /// Only writes since this time
+    pub since: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// Writes of a source to an industrial device
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredOtWrite {
+    pub site: String,
+    pub protocol: String,
+    pub source_mac: String,
+    pub source_ip: Option<Ipv4Addr>,
+    pub device_mac: String,
+    pub device_ip: Option<Ipv4Addr>,
+    /// Modbus function codes, DNP3 function codes or BACnet services
+    pub functions: Vec<u8>,
+    pub writes: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct OtWriteRow {
+    site: String,
+    protocol: String,
+    source_mac: String,
+    source_ip: Option<String>,
+    device_mac: String,
+    device_ip: Option<String>,
+    functions: Vec<i16>,
+    writes: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// Encrypted traffic report filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EncryptionFilter {
+    pub site: Option<String>,
+    pub mac: Option<String>,
+    /// Only devices using this plaintext protocol (`telnet`, `http`)
+    pub protocol: Option<String>,
+    /// Only devices with plaintext traffic
+    pub plaintext: Option<bool>,
+    /// Start of the period, the last 24 hours when not given
+    pub since: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A device's encrypted and plaintext traffic over a period
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceEncryption {
+    pub site: String,
+    pub mac_address: String,
+    pub encrypted_bytes: u64,
+    pub plaintext_bytes: u64,
+    /// Bytes of services neither listed as encrypted nor as plaintext
+    pub other_bytes: u64,
+    /// Share of the classified bytes that were encrypted, from 0 to 1
+    pub encrypted_ratio: Option<f64>,
+    /// Plaintext protocols used, most bytes first
+    pub plaintext_protocols: Vec<ProtocolTraffic>,
+    /// Last hour with traffic
+    pub last_hour: DateTime<Utc>,
+}
+
+/// Bytes of one protocol
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolTraffic {
+    pub protocol: String,
+    pub bytes: u64,
+}
+
+#[derive(FromRow)]
+struct DeviceEncryptionRow {
+    site: String,
+    mac_address: String,
+    encrypted_bytes: i64,
+    plaintext_bytes: i64,
+    other_bytes: i64,
+    plaintext_protocols: Vec<String>,
+    plaintext_protocol_bytes: Vec<i64>,
+    last_hour: DateTime<Utc>,
+    total: i64,
+}
+
+/// VoIP call filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SipCallFilter {
+    pub site: Option<String>,
+    /// MAC address of the caller or the callee
+    pub mac: Option<String>,
+    /// `ringing`, `answered`, `ended` or `failed`
+    pub state: Option<String>,
+    /// Only calls active since this time
+    pub since: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// A VoIP call and the bandwidth of its media
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredSipCall {
+    pub site: String,
+    pub call_id: String,
+    pub caller_mac: String,
+    pub caller_ip: Option<Ipv4Addr>,
+    pub callee_mac: String,
+    pub callee_ip: Option<Ipv4Addr>,
+    pub from: String,
+    pub to: String,
+    pub state: String,
+    pub started_at: DateTime<Utc>,
+    pub answered_at: Option<DateTime<Utc>>,
+    pub ended_at: Option<DateTime<Utc>>,
+    /// From the answer to the hang-up, or to the last datagram of a call
+    /// still up
+    pub duration_secs: Option<i64>,
+    pub rtp_packets: u64,
+    pub rtp_bytes: u64,
+    /// Average RTP bandwidth over the call, both directions
+    pub kbps: Option<f64>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct SipCallRow {
+    site: String,
+    call_id: String,
+    caller_mac: String,
+    caller_ip: Option<String>,
+    callee_mac: String,
+    callee_ip: Option<String>,
+    from_uri: String,
+    to_uri: String,
+    state: String,
+    started_at: DateTime<Utc>,
+    answered_at: Option<DateTime<Utc>>,
+    ended_at: Option<DateTime<Utc>>,
+    rtp_packets: i64,
+    rtp_bytes: i64,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+impl From<SipCallRow> for StoredSipCall {
+    fn from(row: SipCallRow) -> Self {
+        let duration_secs = row.answered_at
+            .map(|answered| (row.ended_at.unwrap_or(row.last_seen) - answered).num_seconds().max(0));
+        let kbps = duration_secs
+            .filter(|&secs| secs > 0)
+            .map(|secs| (row.rtp_bytes as f64 * 8.0 / 1000.0 / secs as f64 * 10.0).round() / 10.0);
+        StoredSipCall {
+            site: row.site,
+            call_id: row.call_id,
+            caller_mac: row.caller_mac,
+            caller_ip: row.caller_ip.and_then(|ip| ip.parse().ok()),
+            callee_mac: row.callee_mac,
+            callee_ip: row.callee_ip.and_then(|ip| ip.parse().ok()),
+            from: row.from_uri,
+            to: row.to_uri,
+            state: row.state,
+            started_at: row.started_at,
+            answered_at: row.answered_at,
+            ended_at: row.ended_at,
+            duration_secs,
+            rtp_packets: row.rtp_packets as u64,
+            rtp_bytes: row.rtp_bytes as u64,
+            kbps,
+            last_seen: row.last_seen,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct TlsFingerprintRow {
+    site: String,
+    mac_address: String,
+    kind: String,
+    fingerprint: String,
+    count: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+/// DHCP lease history filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LeaseFilter {
+    pub site: Option<String>,
+    /// Client MAC address
+    pub mac: Option<String>,
+    /// Address leased, released or declined
+    pub ip: Option<Ipv4Addr>,
+    /// Only ACKs whose lease covered this time
+    pub at: Option<DateTime<Utc>>,
+    /// Only events since this time
+    pub since: Option<DateTime<Utc>>,
+    /// Tenant the caller is restricted to, set from its API token
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+/// One DHCP lease event
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredLease {
+    pub time: DateTime<Utc>,
+    pub event: String,
+    pub site: String,
+    pub mac_address: String,
+    pub ip_address: Option<Ipv4Addr>,
+    pub hostname: Option<String>,
+    pub lease_secs: Option<u64>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub server_ip: Option<Ipv4Addr>,
+}
+
+#[derive(FromRow)]
+struct LeaseRow {
+    time: DateTime<Utc>,
+    time_ns: Option<i64>,
+    event: String,
+    site: String,
+    mac_address: String,
+    ip_address: Option<String>,
+    hostname: Option<String>,
+    lease_secs: Option<i64>,
+    expires_at: Option<DateTime<Utc>>,
+    server_ip: Option<String>,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct TlsObservationRow {
+    site: String,
+    client_mac: String,
+    server_ip: String,
+    server_port: i32,
+    sni: Option<String>,
+    cert_sha256: Option<String>,
+    cert_subject: Option<String>,
+    cert_issuer: Option<String>,
+    cert_not_before: Option<DateTime<Utc>>,
+    cert_not_after: Option<DateTime<Utc>>,
+    handshakes: i64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    total: i64,
+}
+
+#[derive(FromRow)]
+struct AlertRow {
+    id: Uuid,
+    name: String,
+    severity: String,
+    message: String,
+    site: Option<String>,
+    mac_address: Option<String>,
+    ip_address: Option<String>,
+    details: Option<String>,
+    status: String,
+    occurrences: i32,
+    raised_at: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    last_notified: Option<DateTime<Utc>>,
+    suppressed_by: Option<String>,
+    acknowledged_at: Option<DateTime<Utc>>,
+    acknowledged_by: Option<String>,
+    resolved_at: Option<DateTime<Utc>>,
+    resolved_by: Option<String>,
+    note: Option<String>,
+    total: i64,
+}
+
+impl AlertRow {
+    fn into_alert(self) -> Result<StoredAlert> {
+        Ok(StoredAlert {
+            id: self.id,
+            name: self.name,
+            severity: serde_json::from_value(serde_json::Value::String(self.severity))?,
+            message: self.message,
+            site: self.site,
+            mac_address: self.mac_address,
+            ip_address: self.ip_address.and_then(|ip| ip.parse().ok()),
+            details: self.details.and_then(|d| serde_json::from_str(&d).ok()),
+            status: self.status.parse()?,
+            occurrences: self.occurrences as u64,
+            raised_at: self.raised_at,
+            last_seen: self.last_seen,
+            last_notified: self.last_notified,
+            suppressed_by: self.suppressed_by,
+            acknowledged_at: self.acknowledged_at,
+            acknowledged_by: self.acknowledged_by,
+            resolved_at: self.resolved_at,
+            resolved_by: self.resolved_by,
+            note: self.note,
+        })
+    }
+}
+
+/// Rows fetched per query by the `all_*` helpers
+const PAGE_SIZE: usize = 500;
+
+const DEVICE_COLUMNS: &str = "SELECT id, site, mac_address::text AS mac_address, first_seen, last_seen, \
+    total_packets_sent, total_packets_received, total_bytes_sent, total_bytes_received, \
+    is_gateway, is_flagged, evidence, confidence, tags::text AS tags, tcp_data_segments, tcp_retransmits, tcp_resets, \
+    device_notes, COUNT(*) OVER () AS total FROM devices";
+
+const ALERT_COLUMNS: &str = "SELECT id, name, severity, message, site, mac_address::text AS mac_address, \
+    host(ip_address) AS ip_address, details::text AS details, status, occurrences, raised_at, last_seen, \
+    last_notified, suppressed_by, acknowledged_at, acknowledged_by, resolved_at, resolved_by, note, \
+    COUNT(*) OVER () AS total FROM alerts";
+
+/// Severities at or above `min`, as stored in the `alerts` table
+fn severities_from(min: Severity) -> Vec<String> {
+    [Severity::Info, Severity::Low, Severity::Medium, Severity::High, Severity::Critical]
+        .into_iter()
+        .filter(|s| *s >= min)
+        .filter_map(|s| serde_json::to_value(s).ok()?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// A stored timestamp with the nanoseconds of its `_ns` column, when set
+///
+/// TIMESTAMPTZ columns keep microseconds; rows written before the
+/// nanosecond columns existed only have those.
+fn with_nanos(time: DateTime<Utc>, nanos: Option<i64>) -> DateTime<Utc> {
+    nanos.map_or(time, DateTime::from_timestamp_nanos)
+}
+
+/// Append the `WHERE` clause of `filter` to a query over the `devices` table
+fn push_device_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &DeviceFilter, inactivity_timeout: u64) {
+    query.push(" WHERE TRUE");
+
+    if let Some(site) = &filter.site {
+        query.push(" AND EXISTS (SELECT 1 FROM device_sites s WHERE s.device_id = devices.id AND s.site = ");
+        query.push_bind(site.clone()).push(")");
+    }
+    if let Some(tenant) = &filter.tenant {
+        query.push(" AND tenant = ").push_bind(tenant.clone());
+    }
+    if let Some(vlan) = filter.vlan {
+        query.push(" AND EXISTS (SELECT 1 FROM device_ips i WHERE i.device_id = devices.id AND i.vlan_id = ");
+        query.push_bind(vlan as i16).push(")");
+    }
+    if let Some(ip) = filter.ip {
+        query.push(" AND EXISTS (SELECT 1 FROM device_ips i WHERE i.device_id = devices.id AND i.ip_address = ");
+        query.push_bind(ip.to_string()).push("::inet)");
+    }
+    if let Some(oui) = &filter.oui {
+        query.push(" AND oui_prefix = ").push_bind(oui.to_uppercase());
+    }
+    if let Some(gateway) = filter.gateway {
+        query.push(" AND is_gateway = ").push_bind(gateway);
+    }
+    if let Some(active) = filter.active {
+        query.push(if active { " AND last_seen >= " } else { " AND last_seen < " });
+        query.push("NOW() - make_interval(secs => ").push_bind(inactivity_timeout as f64).push(")");
+    }
+    if let Some(min_confidence) = filter.min_confidence {
+        query.push(" AND confidence >= ").push_bind(min_confidence as i16);
+    }
+}
+
+/// Condition on the `side` (`src` or `dst`) end of a flow history row
+fn push_flow_endpoint(query: &mut QueryBuilder<'_, Postgres>, side: &str, endpoint: FlowEndpoint) {
+    match endpoint {
+        FlowEndpoint::Mac(mac) => query.push(format!("{}_mac = ", side)).push_bind(mac.to_string()).push("::macaddr"),
+        FlowEndpoint::Network(network) => {
+            query.push(format!("{}_ip <<= ", side)).push_bind(network.to_string()).push("::inet")
+        }
+    };
+}
+
+/// SQL type of a device, as `api::search::device_type` tells it
+const DEVICE_TYPE: &str = "COALESCE(tags->>'type', CASE WHEN is_gateway THEN 'gateway' ELSE 'unknown' END)";
+
+fn push_search_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &SearchFilter, ouis: Option<&[String]>) {
+    query.push(" WHERE TRUE");
+
+    if let Some(site) = &filter.site {
+        query.push(" AND EXISTS (SELECT 1 FROM device_sites s WHERE s.device_id = devices.id AND s.site = ");
+        query.push_bind(site.clone()).push(")");
+    }
+    if let Some(tenant) = &filter.tenant {
+        query.push(" AND tenant = ").push_bind(tenant.clone());
+    }
+    if let Some(mac) = filter.mac_part() {
+        query.push(" AND strpos(mac_address::text, ").push_bind(mac).push(") > 0");
+    }
+    if let Some(oui) = &filter.oui {
+        query.push(" AND oui_prefix = ").push_bind(oui.to_uppercase().replace('-', ":"));
+    }
+    if let Some(ouis) = ouis {
+        query.push(" AND oui_prefix = ANY(").push_bind(ouis.to_vec()).push(")");
+    }
+    if let Some(ip) = filter.ip {
+        query.push(" AND EXISTS (SELECT 1 FROM device_ips i WHERE i.device_id = devices.id AND i.ip_address = ");
+        query.push_bind(ip.to_string()).push("::inet)");
+    }
+    if let Some(cidr) = filter.cidr {
+        query.push(" AND EXISTS (SELECT 1 FROM device_ips i WHERE i.device_id = devices.id AND i.ip_address <<= ");
+        query.push_bind(cidr.to_string()).push("::inet)");
+    }
+    if let Some(vlan) = filter.vlan {
+        query.push(" AND EXISTS (SELECT 1 FROM device_ips i WHERE i.device_id = devices.id AND i.vlan_id = ");
+        query.push_bind(vlan as i16).push(")");
+    }
+    if let Some(hostname) = &filter.hostname {
+        query.push(
+            " AND EXISTS (SELECT 1 FROM dhcp_leases l WHERE l.mac_address = devices.mac_address \
+             AND l.tenant = devices.tenant AND strpos(lower(l.hostname), ",
+        );
+        query.push_bind(hostname.to_lowercase()).push(") > 0)");
+    }
+    if let Some(device_type) = &filter.device_type {
+        query.push(format!(" AND {} = ", DEVICE_TYPE)).push_bind(device_type.clone());
+    }
+    match filter.tag() {
+        Some((name, None)) => {
+            query.push(" AND tags ? ").push_bind(name.to_string());
+        }
+        Some((name, Some(value))) => {
+            query.push(" AND tags->>").push_bind(name.to_string()).push(" = ").push_bind(value.to_string());
+        }
+        None => {}
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND last_seen >= ").push_bind(since);
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND last_seen < ").push_bind(until);
+    }
+}
+
+impl Database {
+    /// Search devices matching `filter`, of the vendors' `ouis` when set,
+    /// leaving out the (tenant, MAC address) pairs of `exclude`; most
+    /// recently seen first
+    ///
+    /// Returns the requested page and the number of matches, from the
+    /// cursor on when it starts after a row.
+    pub async fn search_devices(
+        &self,
+        filter: &SearchFilter,
+        ouis: Option<&[String]>,
+        exclude: &[(String, String)],
+        limit: usize,
+        cursor: Cursor,
+    ) -> Result<(Vec<DeviceSnapshot>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(DEVICE_COLUMNS);
+        push_search_filter(&mut query, filter, ouis);
+        if !exclude.is_empty() {
+            let (tenants, macs): (Vec<String>, Vec<String>) = exclude.iter().cloned().unzip();
+            query.push(" AND NOT EXISTS (SELECT 1 FROM UNNEST(").push_bind(tenants);
+            query.push("::text[], ").push_bind(macs);
+            query.push("::text[]) AS x(tenant, mac) WHERE x.tenant = devices.tenant AND x.mac::macaddr = devices.mac_address)");
+        }
+        push_page(&mut query, cursor, limit);
+
+        let rows: Vec<DeviceRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to search devices")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let devices = self.devices_with_ips(rows).await?;
+        Ok((devices, total))
+    }
+
+    /// Hostname last announced over DHCP by each of the devices `ids`
+    pub async fn device_hostnames(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, String>> {
+        let rows: Vec<(Uuid, String)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (d.id) d.id, l.hostname
+            FROM devices d
+            JOIN dhcp_leases l ON l.mac_address = d.mac_address AND l.tenant = d.tenant
+            WHERE d.id = ANY($1) AND l.hostname IS NOT NULL AND l.hostname <> ''
+            ORDER BY d.id, l.time DESC
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| "Failed to get device hostnames")?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// List devices matching `filter`, most recently seen first
+    ///
+    /// Returns the requested page and the number of matches, from the
+    /// cursor on when it starts after a row.
+    pub async fn list_devices(
+        &self,
+        filter: &DeviceFilter,
+        inactivity_timeout: u64,
+        limit: usize,
+        cursor: Cursor,
+    ) -> Result<(Vec<DeviceSnapshot>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(DEVICE_COLUMNS);
+        push_device_filter(&mut query, filter, inactivity_timeout);
+        push_page(&mut query, cursor, limit);
+
+        let rows: Vec<DeviceRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list devices")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let devices = self.devices_with_ips(rows).await?;
+        Ok((devices, total))
+    }
+
+    /// The `limit` devices matching `filter` with the highest `by` counter
+    pub async fn top_devices(
+        &self,
+        filter: &DeviceFilter,
+        inactivity_timeout: u64,
+        by: TopBy,
+        limit: usize,
+    ) -> Result<Vec<DeviceSnapshot>> {
+        let mut query = QueryBuilder::<Postgres>::new(DEVICE_COLUMNS);
+        push_device_filter(&mut query, filter, inactivity_timeout);
+        query.push(format!(" ORDER BY {} DESC, last_seen DESC LIMIT ", by.column())).push_bind(limit as i64);
+
+        let rows: Vec<DeviceRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list top devices")?;
+
+        self.devices_with_ips(rows).await
+    }
+
+    /// All devices matching `filter`, fetched page by page
+    pub async fn all_devices(&self, filter: &DeviceFilter, inactivity_timeout: u64) -> Result<Vec<DeviceSnapshot>> {
+        let mut devices: Vec<DeviceSnapshot> = Vec::new();
+        loop {
+            let cursor = devices.last().map_or_else(Cursor::default, |last| Cursor::After(last.last_seen, last.id));
+            let (page, _) = self.list_devices(filter, inactivity_timeout, PAGE_SIZE, cursor).await?;
+            let done = page.len() < PAGE_SIZE;
+            devices.extend(page);
+            if done {
+                return Ok(devices);
+            }
+        }
+    }
+
+    /// Get a device by MAC address, of `tenant` when set, otherwise the last
+    /// seen of the tenants' devices with this address
+    pub async fn get_device(&self, mac: &str, tenant: Option<&str>) -> Result<Option<DeviceSnapshot>> {
+        let row: Option<DeviceRow> = sqlx::query_as(&format!(
+            "{} WHERE mac_address = $1::macaddr AND ($2::text IS NULL OR tenant = $2) ORDER BY last_seen DESC LIMIT 1",
+            DEVICE_COLUMNS
+        ))
+            .bind(mac)
+            .bind(tenant)
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("Failed to get device {}", mac))?;
+
+        Ok(self.devices_with_ips(row.into_iter().collect()).await?.pop())
+    }
+
+    /// Annotation of `mac`, of the tenant's device when given
+    pub async fn device_notes(&self, mac: &str, tenant: Option<&str>) -> Result<Option<String>> {
+        let notes: Option<Option<String>> = sqlx::query_scalar(
+            "SELECT device_notes FROM devices WHERE mac_address = $1::macaddr AND ($2::text IS NULL OR tenant = $2) \
+             ORDER BY last_seen DESC LIMIT 1",
+        )
+            .bind(mac)
+            .bind(tenant)
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("Failed to get the notes of device {}", mac))?;
+
+        Ok(notes.flatten())
+    }
+
+    /// Attach IP addresses, VLANs and site presence to device rows
+    async fn devices_with_ips(&self, rows: Vec<DeviceRow>) -> Result<Vec<DeviceSnapshot>> {
+        let ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
+
+        let ip_rows: Vec<DeviceIpRow> = sqlx::query_as(r#"
+            SELECT device_id, host(ip_address) AS ip_address, vlan_id, first_seen, last_seen,
+                   packets_sent, packets_received, bytes_sent, bytes_received, arp_last_seen
+            FROM device_ips
+            WHERE device_id = ANY($1) AND family(ip_address) = 4
+        "#)
+            .bind(&ids)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load device IPs")?;
+
+        let site_rows: Vec<DeviceSiteRow> = sqlx::query_as(r#"
+            SELECT device_id, site, first_seen, last_seen,
+                   total_packets_sent, total_packets_received, total_bytes_sent, total_bytes_received
+            FROM device_sites
+            WHERE device_id = ANY($1)
+            ORDER BY site
+        "#)
+            .bind(&ids)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load device sites")?;
+
+        let mut sites: HashMap<Uuid, Vec<SitePresence>> = HashMap::new();
+        for row in site_rows {
+            sites.entry(row.device_id).or_default().push(SitePresence {
+                site: row.site,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                packets_sent: row.total_packets_sent.unwrap_or(0) as u64,
+                packets_received: row.total_packets_received.unwrap_or(0) as u64,
+                bytes_sent: row.total_bytes_sent.unwrap_or(0) as u64,
+                bytes_received: row.total_bytes_received.unwrap_or(0) as u64,
+            });
+        }
+
+        let mut ips: HashMap<Uuid, Vec<IpSnapshot>> = HashMap::new();
+        for row in ip_rows {
+            let Ok(ip_address) = row.ip_address.parse() else { continue };
+            ips.entry(row.device_id).or_default().push(IpSnapshot {
+                ip_address,
+                vlan_id: row.vlan_id.map(|v| v as u16),
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                packets_sent: row.packets_sent.unwrap_or(0) as u64,
+                packets_received: row.packets_received.unwrap_or(0) as u64,
+                bytes_sent: row.bytes_sent.unwrap_or(0) as u64,
+                bytes_received: row.bytes_received.unwrap_or(0) as u64,
+                arp_last_seen: row.arp_last_seen,
+            });
+        }
+
+        Ok(rows.into_iter().map(|row| {
+            let ip_addresses = ips.remove(&row.id).unwrap_or_default();
+            let mut vlans: Vec<u16> = ip_addresses.iter().filter_map(|ip| ip.vlan_id).collect();
+            vlans.sort_unstable();
+            vlans.dedup();
+
+            DeviceSnapshot {
+                id: row.id,
+                site: row.site,
+                mac_address: row.mac_address,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                packets_sent: row.total_packets_sent.unwrap_or(0) as u64,
+                packets_received: row.total_packets_received.unwrap_or(0) as u64,
+                bytes_sent: row.total_bytes_sent.unwrap_or(0) as u64,
+                bytes_received: row.total_bytes_received.unwrap_or(0) as u64,
+                is_gateway: row.is_gateway.unwrap_or(false),
+                is_flagged: row.is_flagged.unwrap_or(false),
+                confidence: row.confidence as u8,
+                evidence: evidence_names(row.evidence as u8),
+                tags: serde_json::from_str(&row.tags).unwrap_or_default(),
+                ip_addresses,
+                vlans,
+                tcp: TcpHealthSnapshot {
+                    data_segments: row.tcp_data_segments.unwrap_or(0) as u64,
+                    retransmits: row.tcp_retransmits.unwrap_or(0) as u64,
+                    resets: row.tcp_resets.unwrap_or(0) as u64,
+                },
+                sites: sites.remove(&row.id).unwrap_or_default(),
+                notes: row.device_notes,
+            }
+        }).collect())
+    }
+
+    /// List flows matching `filter`, most recently seen first
+    pub async fn list_flows(
+        &self,
+        filter: &FlowFilter,
+        limit: usize,
+        cursor: Cursor,
+    ) -> Result<(Vec<FlowSnapshot>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT id, site, sensor, src_mac::text AS src_mac, dst_mac::text AS dst_mac,
+                   host(src_ip) AS src_ip, host(dst_ip) AS dst_ip, src_port, dst_port,
+                   vlan_id, ethertype, ip_protocol, icmp_type, icmp_code, icmp_id, service, first_seen, first_seen_ns, last_seen,
+                   packet_count, byte_count, tcp_flags_seen, tcp_data_segments, tcp_retransmits, tcp_resets,
+                   tcp_outcome, COUNT(*) OVER () AS total
+            FROM traffic_flows WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(sensor) = &filter.sensor {
+            query.push(" AND sensor = ").push_bind(sensor.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND (src_mac = ").push_bind(mac.clone()).push("::macaddr");
+            query.push(" OR dst_mac = ").push_bind(mac.clone()).push("::macaddr)");
+        }
+        if let Some(ip) = filter.ip {
+            query.push(" AND (src_ip = ").push_bind(ip.to_string()).push("::inet");
+            query.push(" OR dst_ip = ").push_bind(ip.to_string()).push("::inet)");
+        }
+        if let Some(ip) = filter.src_ip {
+            query.push(" AND src_ip = ").push_bind(ip.to_string()).push("::inet");
+        }
+        if let Some(ip) = filter.dst_ip {
+            query.push(" AND dst_ip = ").push_bind(ip.to_string()).push("::inet");
+        }
+        if let Some(port) = filter.port {
+            query.push(" AND (src_port = ").push_bind(port as i32);
+            query.push(" OR dst_port = ").push_bind(port as i32).push(")");
+        }
+        if let Some(service) = &filter.service {
+            query.push(" AND service = ").push_bind(service.to_lowercase());
+        }
+        if let Some(protocol) = filter.protocol {
+            query.push(" AND ip_protocol = ").push_bind(protocol as i16);
+        }
+        if let Some(vlan) = filter.vlan {
+            query.push(" AND vlan_id = ").push_bind(vlan as i16);
+        }
+        if let Some(outcome) = filter.tcp_outcome {
+            query.push(" AND tcp_outcome = ").push_bind(outcome.as_str());
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND last_seen >= ").push_bind(since);
+        }
+        push_page(&mut query, cursor, limit);
+
+        let rows: Vec<FlowRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list flows")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let flows = rows.into_iter().map(|row| FlowSnapshot {
+            id: row.id,
+            site: row.site,
+            sensor: row.sensor,
+            src_mac: row.src_mac,
+            dst_mac: row.dst_mac,
+            src_ip: row.src_ip.and_then(|ip| ip.parse().ok()),
+            dst_ip: row.dst_ip.and_then(|ip| ip.parse().ok()),
+            src_port: row.src_port.map(|p| p as u16),
+            dst_port: row.dst_port.map(|p| p as u16),
+            vlan_id: row.vlan_id.map(|v| v as u16),
+            ethertype: row.ethertype.map_or(ETHERTYPE_IPV4, |e| e as u16),
+            ip_protocol: row.ip_protocol.map(|p| p as u8),
+            icmp_type: row.icmp_type.map(|t| t as u8),
+            icmp_code: row.icmp_code.map(|c| c as u8),
+            icmp_id: row.icmp_id.map(|i| i as u16),
+            service: row.service,
+            first_seen: with_nanos(row.first_seen, row.first_seen_ns),
+            last_seen: row.last_seen,
+            packet_count: row.packet_count as u64,
+            byte_count: row.byte_count as u64,
+            tcp_flags_seen: row.tcp_flags_seen.unwrap_or(0) as u8,
+            tcp_outcome: row.tcp_outcome.as_deref().and_then(TcpOutcome::parse),
+            tcp: TcpHealthSnapshot {
+                data_segments: row.tcp_data_segments.unwrap_or(0) as u64,
+                retransmits: row.tcp_retransmits.unwrap_or(0) as u64,
+                resets: row.tcp_resets.unwrap_or(0) as u64,
+            },
+        }).collect();
+
+        Ok((flows, total))
+    }
+
+    /// All VLANs, fetched page by page
+    pub async fn all_vlans(&self) -> Result<Vec<VlanSnapshot>> {
+        let mut vlans = Vec::new();
+        loop {
+            let (page, total) = self.list_vlans(None, PAGE_SIZE, vlans.len()).await?;
+            let done = page.is_empty() || vlans.len() + page.len() >= total as usize;
+            vlans.extend(page);
+            if done {
+                return Ok(vlans);
+            }
+        }
+    }
+
+    /// List the VLANs of `tenant` (of every tenant when `None`) ordered by
+    /// VLAN ID
+    pub async fn list_vlans(&self, tenant: Option<&str>, limit: usize, offset: usize) -> Result<(Vec<VlanSnapshot>, u64)> {
+        let rows: Vec<VlanRow> = sqlx::query_as(r#"
+            SELECT site, vlan_id, outer_vlan_id, first_seen, last_seen, total_packets, total_bytes,
+                   device_count, priority_packets, priority_bytes, COUNT(*) OVER () AS total
+            FROM vlans
+            WHERE $3::text IS NULL OR tenant = $3
+            ORDER BY site, vlan_id, outer_vlan_id NULLS FIRST
+            LIMIT $1 OFFSET $2
+        "#)
+            .bind(limit as i64)
+            .bind(offset as i64)
+            .bind(tenant)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list VLANs")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let vlans = rows.into_iter().map(|row| VlanSnapshot {
+            site: row.site,
+            vlan_id: row.vlan_id as u16,
+            outer_vlan_id: row.outer_vlan_id.map(|v| v as u16),
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+            packet_count: row.total_packets.unwrap_or(0) as u64,
+            byte_count: row.total_bytes.unwrap_or(0) as u64,
+            device_count: row.device_count.unwrap_or(0) as u64,
+            priority_packets: priority_counts(&row.priority_packets),
+            priority_bytes: priority_counts(&row.priority_bytes),
+        }).collect();
+
+        Ok((vlans, total))
+    }
+
+    /// List the L2 segments, largest first
+    pub async fn list_l2_segments(
+        &self,
+        filter: &SegmentFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<L2Segment>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT s.site, s.segment_id, s.capture_points,
+                   ARRAY(SELECT d.mac_address::text FROM device_segments d
+                         WHERE d.site = s.site AND d.segment_id = s.segment_id
+                         ORDER BY d.mac_address) AS devices,
+                   s.first_seen, s.last_seen, COUNT(*) OVER () AS total
+            FROM l2_segments s WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND s.site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND s.tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND EXISTS (SELECT 1 FROM device_segments d WHERE d.site = s.site AND d.segment_id = s.segment_id");
+            query.push(" AND d.mac_address = ").push_bind(mac.clone()).push("::macaddr)");
+        }
+        if let Some(point) = &filter.capture_point {
+            query.push(" AND ").push_bind(point.clone()).push(" = ANY(s.capture_points)");
+        }
+
+        query.push(" ORDER BY s.device_count DESC, s.segment_id LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<SegmentRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list L2 segments")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let segments = rows.into_iter().map(|row| L2Segment {
+            site: row.site,
+            id: row.segment_id,
+            capture_points: row.capture_points,
+            devices: row.devices,
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+        }).collect();
+
+        Ok((segments, total))
+    }
+
+    /// List the multicast groups, busiest first
+    pub async fn list_multicast_groups(
+        &self,
+        filter: &MulticastFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<MulticastSnapshot>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, host(group_address) AS group_address, members::text[] AS members,
+                   senders::text[] AS senders, packets, bytes, bits_per_sec, peak_bits_per_sec,
+                   first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM multicast_groups WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(group) = filter.group {
+            query.push(" AND group_address = ").push_bind(group.to_string()).push("::inet");
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND (").push_bind(mac.clone()).push("::macaddr = ANY(members)");
+            query.push(" OR ").push_bind(mac.clone()).push("::macaddr = ANY(senders))");
+        }
+
+        query.push(" ORDER BY bits_per_sec DESC, bytes DESC, site, group_address LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<MulticastRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list multicast groups")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let groups = rows.into_iter()
+            .filter_map(|row| Some(MulticastSnapshot {
+                site: row.site,
+                group: row.group_address.parse().ok()?,
+                members: row.members,
+                senders: row.senders,
+                packets: row.packets as u64,
+                bytes: row.bytes as u64,
+                bits_per_sec: row.bits_per_sec,
+                peak_bits_per_sec: row.peak_bits_per_sec,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            }))
+            .collect();
+
+        Ok((groups, total))
+    }
+
+    /// Traffic between `filter.device` and its peers from `start` to `end`,
+    /// per flow (the heaviest first), hour or service
+    pub async fn flow_history(
+        &self,
+        filter: &FlowHistoryFilter,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<FlowHistory>, u64)> {
+        let per_flow = !filter.by.hour && !filter.by.service;
+        let endpoints = if per_flow {
+            "src_mac::text AS src_mac, host(src_ip) AS src_ip, src_port, \
+             dst_mac::text AS dst_mac, host(dst_ip) AS dst_ip, dst_port, ip_protocol"
+        } else {
+            "NULL::text AS src_mac, NULL::text AS src_ip, NULL::integer AS src_port, \
+             NULL::text AS dst_mac, NULL::text AS dst_ip, NULL::integer AS dst_port, NULL::smallint AS ip_protocol"
+        };
+        // The service a flow was last given
+        let service = match (per_flow, filter.by.service) {
+            (true, _) => "(array_agg(service ORDER BY hour DESC))[1]",
+            (false, true) => "COALESCE(service, 'other')",
+            (false, false) => "NULL::text",
+        };
+        let hour = if filter.by.hour { "hour" } else { "NULL::timestamptz" };
+        let (group_by, order_by) = match (filter.by.hour, filter.by.service) {
+            (false, false) => (
+                "flow_id, src_mac, src_ip, src_port, dst_mac, dst_ip, dst_port, ip_protocol",
+                "bytes DESC, src_mac, dst_mac",
+            ),
+            (true, false) => ("hour", "hour"),
+            (false, true) => ("COALESCE(service, 'other')", "bytes DESC, service"),
+            (true, true) => ("hour, COALESCE(service, 'other')", "hour, bytes DESC, service"),
+        };
+
+        let mut query = QueryBuilder::<Postgres>::new("SELECT ");
+        query.push(format!("{}, {} AS service, {} AS hour", endpoints, service, hour));
+        query.push(
+            ", MIN(hour) AS first_hour, MAX(hour) AS last_hour, COUNT(DISTINCT flow_id) AS flows, \
+             SUM(packets)::bigint AS packets, SUM(bytes)::bigint AS bytes, COUNT(*) OVER () AS total \
+             FROM flow_history WHERE hour >= date_trunc('hour', ",
+        );
+        query.push_bind(start).push("::timestamptz) AND hour < ").push_bind(end);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        query.push(" AND ((");
+        push_flow_endpoint(&mut query, "src", filter.device);
+        if let Some(peer) = filter.peer {
+            query.push(" AND ");
+            push_flow_endpoint(&mut query, "dst", peer);
+        }
+        query.push(") OR (");
+        push_flow_endpoint(&mut query, "dst", filter.device);
+        if let Some(peer) = filter.peer {
+            query.push(" AND ");
+            push_flow_endpoint(&mut query, "src", peer);
+        }
+        query.push("))");
+
+        query.push(" GROUP BY ").push(group_by);
+        query.push(" ORDER BY ").push(order_by);
+        query.push(" LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<FlowHistoryRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to query the flow history")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let history = rows.into_iter().map(|row| FlowHistory {
+            flow: match (row.src_mac, row.dst_mac) {
+                (Some(src_mac), Some(dst_mac)) => Some(FlowEndpoints {
+                    src_mac,
+                    src_ip: row.src_ip.and_then(|ip| ip.parse().ok()),
+                    src_port: row.src_port.map(|port| port as u16),
+                    dst_mac,
+                    dst_ip: row.dst_ip.and_then(|ip| ip.parse().ok()),
+                    dst_port: row.dst_port.map(|port| port as u16),
+                    protocol: row.ip_protocol.map(|protocol| protocol as u8),
+                }),
+                _ => None,
+            },
+            hour: row.hour,
+            service: row.service,
+            first_hour: row.first_hour,
+            last_hour: row.last_hour,
+            flows: row.flows as u64,
+            packets: row.packets as u64,
+            bytes: row.bytes as u64,
+        }).collect();
+
+        Ok((history, total))
+    }
+
+    /// Traffic routed between subnets since `since`, summed per subnet pair,
+    /// the heaviest first
+    pub async fn list_subnet_traffic(
+        &self,
+        filter: &SubnetMatrixFilter,
+        since: DateTime<Utc>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<SubnetTraffic>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, src_subnet::text AS src_subnet, dst_subnet::text AS dst_subnet,
+                   SUM(packets)::bigint AS packets, SUM(bytes)::bigint AS bytes, COUNT(*) OVER () AS total
+            FROM subnet_traffic_hourly WHERE hour >= "#);
+        query.push_bind(since);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(subnet) = &filter.subnet {
+            query.push(" AND (src_subnet = ").push_bind(subnet.clone()).push("::cidr");
+            query.push(" OR dst_subnet = ").push_bind(subnet.clone()).push("::cidr)");
+        }
+
+        query.push(" GROUP BY site, src_subnet, dst_subnet");
+        query.push(" ORDER BY bytes DESC, site, src_subnet, dst_subnet LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<SubnetTrafficRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list inter-subnet traffic")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let pairs = rows.into_iter().map(|row| SubnetTraffic {
+            site: row.site,
+            src_subnet: row.src_subnet,
+            dst_subnet: row.dst_subnet,
+            packets: row.packets as u64,
+            bytes: row.bytes as u64,
+        }).collect();
+
+        Ok((pairs, total))
+    }
+
+    /// List the subnets inferred on each VLAN
+    pub async fn list_vlan_subnets(
+        &self,
+        filter: &VlanSubnetFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<VlanSubnet>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, vlan_id, subnet::text AS subnet, hosts, confidence, is_primary, conflicts,
+                   first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM vlan_subnets WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(vlan) = filter.vlan {
+            query.push(" AND vlan_id = ").push_bind(vlan as i16);
+        }
+        if let Some(ip) = filter.ip {
+            query.push(" AND subnet >>= ").push_bind(ip.to_string()).push("::inet");
+        }
+        match filter.conflicts {
+            Some(true) => { query.push(" AND cardinality(conflicts) > 0"); }
+            Some(false) => { query.push(" AND cardinality(conflicts) = 0"); }
+            None => {}
+        }
+
+        query.push(" ORDER BY site, vlan_id, hosts DESC, subnet LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<VlanSubnetRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list VLAN subnets")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let subnets = rows.into_iter().map(|row| VlanSubnet {
+            site: row.site,
+            vlan_id: row.vlan_id as u16,
+            subnet: row.subnet,
+            hosts: row.hosts as u32,
+            confidence: row.confidence as f64,
+            primary: row.is_primary,
+            conflicts: row.conflicts,
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+        }).collect();
+
+        Ok((subnets, total))
+    }
+
+    /// List alerts matching `filter`, most recently seen first
+    pub async fn list_alerts(
+        &self,
+        filter: &AlertFilter,
+        limit: usize,
+        cursor: Cursor,
+    ) -> Result<(Vec<StoredAlert>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(ALERT_COLUMNS);
+        query.push(" WHERE TRUE");
+
+        if let Some(status) = filter.status {
+            query.push(" AND status = ").push_bind(status.as_str());
+        }
+        if let Some(name) = &filter.name {
+            query.push(" AND name = ").push_bind(name.clone());
+        }
+        if let Some(severity) = filter.severity {
+            query.push(" AND severity = ANY(").push_bind(severities_from(severity)).push(")");
+        }
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        push_page(&mut query, cursor, limit);
+
+        let rows: Vec<AlertRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list alerts")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let alerts = rows.into_iter().map(AlertRow::into_alert).collect::<Result<_>>()?;
+        Ok((alerts, total))
+    }
+
+    /// Get a single alert by ID
+    pub async fn get_alert(&self, id: Uuid) -> Result<Option<StoredAlert>> {
+        let row: Option<AlertRow> = sqlx::query_as(&format!("{} WHERE id = $1", ALERT_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("Failed to get alert {}", id))?;
+
+        row.map(AlertRow::into_alert).transpose()
+    }
+
+    /// Checkpoints of the consumers of `consumer_group` on `stream`
+    pub async fn stream_checkpoints(&self, stream: &str, consumer_group: &str) -> Result<Vec<StreamCheckpoint>> {
+        sqlx::query_as(r#"
+            SELECT consumer, last_id, updated_at FROM stream_checkpoints
+            WHERE stream = $1 AND consumer_group = $2
+            ORDER BY consumer
+        "#)
+            .bind(stream)
+            .bind(consumer_group)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list stream checkpoints")
+    }
+
+    /// List maintenance entries matching `filter`, latest ending first
+    pub async fn list_maintenance(
+        &self,
+        filter: &MaintenanceFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<MaintenanceEntry>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(MAINTENANCE_COLUMNS);
+        query.push(" WHERE TRUE");
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(vlan) = filter.vlan {
+            query.push(" AND vlan_id = ").push_bind(vlan as i16);
+        }
+        match filter.active {
+            Some(true) => query.push(" AND starts_at <= NOW() AND until > NOW()"),
+            Some(false) => query.push(" AND (starts_at > NOW() OR until <= NOW())"),
+            None => &mut query,
+        };
+
+        query.push(" ORDER BY until DESC, id LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<MaintenanceRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list maintenance")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        Ok((rows.into_iter().map(MaintenanceRow::into_entry).collect(), total))
+    }
+
+    /// Maintenance entry `id` and its tenant
+    pub async fn get_maintenance(&self, id: Uuid) -> Result<Option<(MaintenanceEntry, String)>> {
+        let row: Option<MaintenanceRow> = sqlx::query_as(&format!("{} WHERE id = $1", MAINTENANCE_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .with_context(|| format!("Failed to get maintenance {}", id))?;
+
+        Ok(row.map(|row| {
+            let tenant = row.tenant.clone();
+            (row.into_entry(), tenant)
+        }))
+    }
+
+    /// Maintenance entries not over at `at`, to load on startup
+    pub async fn pending_maintenance(&self, at: DateTime<Utc>) -> Result<Vec<MaintenanceEntry>> {
+        let rows: Vec<MaintenanceRow> = sqlx::query_as(&format!("{} WHERE until > $1", MAINTENANCE_COLUMNS))
+            .bind(at)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load maintenance")?;
+
+        Ok(rows.into_iter().map(MaintenanceRow::into_entry).collect())
+    }
+
+    /// List external scanners matching `filter`, most recently seen first
+    pub async fn list_scanners(
+        &self,
+        filter: &ScannerFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredScanner>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT host(ip_address) AS ip_address, first_seen, last_seen, probes, hosts_targeted, services,
+                   COUNT(*) OVER () AS total
+            FROM scanners WHERE TRUE"#);
+
+        if let Some(service) = &filter.service {
+            query.push(" AND ").push_bind(service.to_lowercase()).push(" = ANY(services)");
+        }
+        if let Some(secs) = filter.seen_within_secs {
+            query.push(" AND last_seen >= NOW() - make_interval(secs => ").push_bind(secs as f64).push(")");
+        }
+
+        query.push(" ORDER BY last_seen DESC LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<ScannerRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list scanners")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let scanners = rows.into_iter()
+            .filter_map(|row| Some(StoredScanner {
+                ip_address: row.ip_address.parse().ok()?,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                probes: row.probes as u64,
+                hosts_targeted: row.hosts_targeted as u32,
+                services: row.services,
+            }))
+            .collect();
+
+        Ok((scanners, total))
+    }
+
+    /// List client TLS destinations, most recently seen first
+    pub async fn list_tls_observations(
+        &self,
+        filter: &TlsFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredTlsObservation>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, client_mac::text AS client_mac, host(server_ip) AS server_ip, server_port, sni,
+                   cert_sha256, cert_subject, cert_issuer, cert_not_before, cert_not_after,
+                   handshakes, first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM tls_observations WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND client_mac = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(sni) = &filter.sni {
+            query.push(" AND sni ILIKE ").push_bind(format!("%{}%", sni));
+        }
+        match filter.expired {
+            Some(true) => { query.push(" AND cert_not_after < NOW()"); }
+            Some(false) => { query.push(" AND cert_not_after >= NOW()"); }
+            None => {}
+        }
+        if let Some(days) = filter.expires_within_days {
+            query.push(" AND cert_not_after < NOW() + make_interval(days => ").push_bind(days as i32).push(")");
+        }
+
+        query.push(" ORDER BY last_seen DESC LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<TlsObservationRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list TLS observations")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let observations = rows.into_iter()
+            .filter_map(|row| Some(StoredTlsObservation {
+                site: row.site,
+                client_mac: row.client_mac,
+                server_ip: row.server_ip.parse().ok()?,
+                server_port: row.server_port as u16,
+                sni: row.sni,
+                certificate: match (row.cert_sha256, row.cert_not_before, row.cert_not_after) {
+                    (Some(sha256), Some(not_before), Some(not_after)) => Some(CertificateInfo {
+                        sha256,
+                        subject: row.cert_subject,
+                        issuer: row.cert_issuer,
+                        not_before,
+                        not_after,
+                    }),
+                    _ => None,
+                },
+                handshakes: row.handshakes as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            }))
+            .collect();
+
+        Ok((observations, total))
+    }
+
+    /// List device TLS fingerprints, most recently seen first
+    pub async fn list_tls_fingerprints(
+        &self,
+        filter: &TlsFingerprintFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredTlsFingerprint>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, mac_address::text AS mac_address, kind, fingerprint, count, first_seen, last_seen,
+                   COUNT(*) OVER () AS total
+            FROM tls_fingerprints WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(kind) = &filter.kind {
+            query.push(" AND kind = ").push_bind(kind.to_lowercase());
+        }
+        if let Some(fingerprint) = &filter.fingerprint {
+            query.push(" AND fingerprint = ").push_bind(fingerprint.to_lowercase());
+        }
+
+        query.push(" ORDER BY last_seen DESC LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<TlsFingerprintRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list TLS fingerprints")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let fingerprints = rows.into_iter()
+            .map(|row| StoredTlsFingerprint {
+                site: row.site,
+                mac_address: row.mac_address,
+                kind: row.kind,
+                fingerprint: row.fingerprint,
+                count: row.count as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect();
+
+        Ok((fingerprints, total))
+    }
+
+    /// List the time servers devices query, most recently seen first
+    pub async fn list_ntp_servers(
+        &self,
+        filter: &NtpServerFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredNtpServer>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, mac_address::text AS mac_address, host(client_ip) AS client_ip, host(server_ip) AS server_ip,
+                   version, stratum, reference_id, requests, responses, first_seen, last_seen,
+                   COUNT(*) OVER () AS total
+            FROM device_ntp_servers WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(server) = filter.server {
+            query.push(" AND server_ip = ").push_bind(server.to_string()).push("::inet");
+        }
+
+        query.push(" ORDER BY last_seen DESC, site, mac_address, server_ip LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<NtpServerRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list NTP servers")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let servers = rows.into_iter()
+            .filter_map(|row| Some(StoredNtpServer {
+                site: row.site,
+                mac_address: row.mac_address,
+                client_ip: row.client_ip.and_then(|ip| ip.parse().ok()),
+                server_ip: row.server_ip.parse().ok()?,
+                version: row.version as u8,
+                stratum: row.stratum.map(|stratum| stratum as u8),
+                reference_id: row.reference_id,
+                requests: row.requests as u64,
+                responses: row.responses as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            }))
+            .collect();
+
+        Ok((servers, total))
+    }
+
+    /// List the industrial devices, most recently seen first
+    pub async fn list_ot_devices(
+        &self,
+        filter: &OtDeviceFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredOtDevice>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, mac_address::text AS mac_address, protocol, host(ip_address) AS ip_address, units,
+                   requests, responses, first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM ot_devices WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(protocol) = &filter.protocol {
+            query.push(" AND protocol = ").push_bind(protocol.to_lowercase());
+        }
+
+        query.push(" ORDER BY last_seen DESC, site, mac_address, protocol LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<OtDeviceRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list industrial devices")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let devices = rows.into_iter()
+            .map(|row| StoredOtDevice {
+                site: row.site,
+                mac_address: row.mac_address,
+                protocol: row.protocol,
+                ip_address: row.ip_address.and_then(|ip| ip.parse().ok()),
+                units: row.units.into_iter().map(|unit| unit as u32).collect(),
+                requests: row.requests as u64,
+                responses: row.responses as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect();
+
+        Ok((devices, total))
+    }
+
+    /// List the sources writing to industrial devices, most recent write
+    /// first
+    pub async fn list_ot_writes(
+        &self,
+        filter: &OtWriteFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredOtWrite>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, protocol, source_mac::text AS source_mac, host(source_ip) AS source_ip,
+                   device_mac::text AS device_mac, host(device_ip) AS device_ip, functions, writes,
+                   first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM ot_writes WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(source) = &filter.source {
+            query.push(" AND source_mac = ").push_bind(source.clone()).push("::macaddr");
+        }
+        if let Some(device) = &filter.device {
+            query.push(" AND device_mac = ").push_bind(device.clone()).push("::macaddr");
+        }
+        if let Some(protocol) = &filter.protocol {
+            query.push(" AND protocol = ").push_bind(protocol.to_lowercase());
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND last_seen >= ").push_bind(since);
+        }
+
+        query.push(" ORDER BY last_seen DESC, site, protocol, source_mac, device_mac LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<OtWriteRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list industrial writes")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let writes = rows.into_iter()
+            .map(|row| StoredOtWrite {
+                site: row.site,
+                protocol: row.protocol,
+                source_mac: row.source_mac,
+                source_ip: row.source_ip.and_then(|ip| ip.parse().ok()),
+                device_mac: row.device_mac,
+                device_ip: row.device_ip.and_then(|ip| ip.parse().ok()),
+                functions: row.functions.into_iter().map(|function| function as u8).collect(),
+                writes: row.writes as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect();
+
+        Ok((writes, total))
+    }
+
+    /// Encrypted and plaintext traffic of devices since `since`, most
+    /// plaintext bytes first
+    pub async fn list_device_encryption(
+        &self,
+        filter: &EncryptionFilter,
+        since: DateTime<Utc>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<DeviceEncryption>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            WITH usage AS (
+                SELECT site, mac_address, protocol, encrypted, SUM(bytes)::bigint AS bytes, MAX(hour) AS last_hour
+                FROM device_encryption_hourly WHERE hour >= "#);
+        query.push_bind(since);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+
+        query.push(r#"
+                GROUP BY site, mac_address, protocol, encrypted
+            )
+            SELECT site, mac_address::text AS mac_address,
+                   COALESCE(SUM(bytes) FILTER (WHERE encrypted), 0)::bigint AS encrypted_bytes,
+                   COALESCE(SUM(bytes) FILTER (WHERE NOT encrypted), 0)::bigint AS plaintext_bytes,
+                   COALESCE(SUM(bytes) FILTER (WHERE encrypted IS NULL), 0)::bigint AS other_bytes,
+                   COALESCE(ARRAY_AGG(protocol::text ORDER BY bytes DESC) FILTER (WHERE NOT encrypted), '{}') AS plaintext_protocols,
+                   COALESCE(ARRAY_AGG(bytes ORDER BY bytes DESC) FILTER (WHERE NOT encrypted), '{}') AS plaintext_protocol_bytes,
+                   MAX(last_hour) AS last_hour, COUNT(*) OVER () AS total
+            FROM usage GROUP BY site, mac_address HAVING TRUE"#);
+
+        if filter.plaintext == Some(true) {
+            query.push(" AND BOOL_OR(NOT encrypted)");
+        }
+        if let Some(protocol) = &filter.protocol {
+            query.push(" AND BOOL_OR(NOT encrypted AND protocol = ").push_bind(protocol.to_lowercase()).push(")");
+        }
+
+        query.push(" ORDER BY plaintext_bytes DESC, site, mac_address LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<DeviceEncryptionRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list encrypted traffic")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let devices = rows.into_iter()
+            .map(|row| {
+                let classified = row.encrypted_bytes + row.plaintext_bytes;
+                DeviceEncryption {
+                    site: row.site,
+                    mac_address: row.mac_address,
+                    encrypted_bytes: row.encrypted_bytes as u64,
+                    plaintext_bytes: row.plaintext_bytes as u64,
+                    other_bytes: row.other_bytes as u64,
+                    encrypted_ratio: (classified > 0).then(|| {
+                        (row.encrypted_bytes as f64 / classified as f64 * 1000.0).round() / 1000.0
+                    }),
+                    plaintext_protocols: row.plaintext_protocols.into_iter()
+                        .zip(row.plaintext_protocol_bytes)
+                        .map(|(protocol, bytes)| ProtocolTraffic { protocol, bytes: bytes as u64 })
+                        .collect(),
+                    last_hour: row.last_hour,
+                }
+            })
+            .collect();
+
+        Ok((devices, total))
+    }
+
+    /// List VoIP calls, most recently started first
+    pub async fn list_sip_calls(
+        &self,
+        filter: &SipCallFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredSipCall>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, call_id, caller_mac::text AS caller_mac, host(caller_ip) AS caller_ip,
+                   callee_mac::text AS callee_mac, host(callee_ip) AS callee_ip, from_uri, to_uri, state,
+                   started_at, answered_at, ended_at, rtp_packets, rtp_bytes, last_seen, COUNT(*) OVER () AS total
+            FROM sip_calls WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND (caller_mac = ").push_bind(mac.clone()).push("::macaddr");
+            query.push(" OR callee_mac = ").push_bind(mac.clone()).push("::macaddr)");
+        }
+        if let Some(state) = &filter.state {
+            query.push(" AND state = ").push_bind(state.to_lowercase());
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND last_seen >= ").push_bind(since);
+        }
+
+        query.push(" ORDER BY started_at DESC, site, call_id LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<SipCallRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list VoIP calls")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        Ok((rows.into_iter().map(StoredSipCall::from).collect(), total))
+    }
+
+    /// List DHCP lease events, most recent first
+    pub async fn list_leases(
+        &self,
+        filter: &LeaseFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredLease>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT time, time_ns, event, site, mac_address::text AS mac_address, host(ip_address) AS ip_address, hostname,
+                   lease_secs, expires_at, host(server_ip) AS server_ip, COUNT(*) OVER () AS total
+            FROM dhcp_leases WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(ip) = filter.ip {
+            query.push(" AND ip_address = ").push_bind(ip.to_string()).push("::inet");
+        }
+        if let Some(at) = filter.at {
+            query.push(" AND event = 'ack' AND time <= ").push_bind(at);
+            query.push(" AND (expires_at IS NULL OR expires_at >= ").push_bind(at).push(")");
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND time >= ").push_bind(since);
+        }
+
+        query.push(" ORDER BY time DESC, time_ns DESC NULLS LAST LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<LeaseRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list DHCP leases")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let leases = rows.into_iter()
+            .map(|row| StoredLease {
+                time: with_nanos(row.time, row.time_ns),
+                event: row.event,
+                site: row.site,
+                mac_address: row.mac_address,
+                ip_address: row.ip_address.and_then(|ip| ip.parse().ok()),
+                hostname: row.hostname,
+                lease_secs: row.lease_secs.map(|s| s as u64),
+                expires_at: row.expires_at,
+                server_ip: row.server_ip.and_then(|ip| ip.parse().ok()),
+            })
+            .collect();
+
+        Ok((leases, total))
+    }
+
+    /// Hourly traffic composition of a device since `since`, oldest first
+    pub async fn device_hourly_traffic(&self, site: &str, mac: &str, since: DateTime<Utc>) -> Result<Vec<HourlyComposition>> {
+        let rows: Vec<HourlyTrafficRow> = sqlx::query_as(r#"
+            SELECT hour, service, bytes_sent, bytes_received
+            FROM device_hourly_traffic
+            WHERE site = $1 AND mac_address = $2::macaddr AND hour >= $3
+            ORDER BY hour, bytes_sent + bytes_received DESC
+        "#)
+            .bind(site)
+            .bind(mac)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Failed to load hourly traffic of {}", mac))?;
+
+        let mut hours: Vec<HourlyComposition> = Vec::new();
+        for row in rows {
+            if hours.last().is_none_or(|h| h.hour != row.hour) {
+                hours.push(HourlyComposition { hour: row.hour, bytes_sent: 0, bytes_received: 0, services: Vec::new() });
+            }
+            let hour = hours.last_mut().expect("hour just pushed");
+            hour.bytes_sent += row.bytes_sent as u64;
+            hour.bytes_received += row.bytes_received as u64;
+            hour.services.push(ServiceTraffic {
+                service: row.service,
+                name: None,
+                bytes_sent: row.bytes_sent as u64,
+                bytes_received: row.bytes_received as u64,
+            });
+        }
+
+        Ok(hours)
+    }
+
+    /// Hourly traffic by QoS class a device sent since `since`, oldest first
+    pub async fn device_hourly_qos(&self, site: &str, mac: &str, since: DateTime<Utc>) -> Result<Vec<HourlyQosStats>> {
+        let rows: Vec<HourlyQosRow> = sqlx::query_as(r#"
+            SELECT hour, vlan_id, dscp, packets, bytes
+            FROM device_qos_hourly
+            WHERE site = $1 AND mac_address = $2::macaddr AND hour >= $3
+            ORDER BY hour, bytes DESC, vlan_id, dscp
+        "#)
+            .bind(site)
+            .bind(mac)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Failed to load hourly QoS classes of {}", mac))?;
+
+        let mut hours: Vec<HourlyQosStats> = Vec::new();
+        for row in rows {
+            if hours.last().is_none_or(|h| h.hour != row.hour) {
+                hours.push(HourlyQosStats { hour: row.hour, bytes: 0, classes: Vec::new() });
+            }
+            let hour = hours.last_mut().expect("hour just pushed");
+            hour.bytes += row.bytes as u64;
+            hour.classes.push(QosClassTraffic {
+                vlan_id: row.vlan_id as u16,
+                dscp: row.dscp as u8,
+                class: class_name(row.dscp as u8),
+                packets: row.packets as u64,
+                bytes: row.bytes as u64,
+            });
+        }
+
+        Ok(hours)
+    }
+
+    /// Hourly DNS activity of a device since `since`, oldest first
+    pub async fn device_hourly_dns(&self, site: &str, mac: &str, since: DateTime<Utc>) -> Result<Vec<HourlyDnsStats>> {
+        let rows: Vec<HourlyDnsRow> = sqlx::query_as(r#"
+            SELECT hour, queries, responses, nxdomain, distinct_domains, mean_entropy,
+                   dga_like_domains, top_domains, top_domain_queries
+            FROM device_dns_hourly
+            WHERE site = $1 AND mac_address = $2::macaddr AND hour >= $3
+            ORDER BY hour
+        "#)
+            .bind(site)
+            .bind(mac)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Failed to load hourly DNS activity of {}", mac))?;
+
+        Ok(rows.into_iter().map(|row| HourlyDnsStats {
+            hour: row.hour,
+            queries: row.queries as u64,
+            responses: row.responses as u64,
+            nxdomain: row.nxdomain as u64,
+            nxdomain_rate: if row.responses > 0 { row.nxdomain as f64 / row.responses as f64 } else { 0.0 },
+            distinct_domains: row.distinct_domains as u32,
+            mean_entropy: row.mean_entropy,
+            dga_like_domains: row.dga_like_domains as u32,
+            top_domains: row.top_domains.into_iter()
+                .zip(row.top_domain_queries)
+                .map(|(domain, queries)| DomainQueries { domain, queries: queries as u64 })
+                .collect(),
+        }).collect())
+    }
+
+    /// Hourly anomaly scores of a device since `since`, by detector, oldest first
+    pub async fn device_hourly_anomalies(&self, site: &str, mac: &str, since: DateTime<Utc>) -> Result<Vec<HourlyAnomaly>> {
+        sqlx::query_as(r#"
+            SELECT hour, detector, samples, mean_score, max_score, mean_value, baseline
+            FROM device_anomaly_hourly
+            WHERE site = $1 AND mac_address = $2::macaddr AND hour >= $3
+            ORDER BY detector, hour
+        "#)
+            .bind(site)
+            .bind(mac)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Failed to load hourly anomaly scores of {}", mac))
+    }
+
+    /// Devices first seen between `start` and `end`
+    pub async fn devices_first_seen(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<DeviceChange>> {
+        sqlx::query_as(r#"
+            SELECT site, mac_address::text AS mac_address,
+                   (SELECT host(ip_address) FROM device_ips i
+                    WHERE i.device_id = devices.id ORDER BY last_seen DESC LIMIT 1) AS ip_address,
+                   first_seen, last_seen
+            FROM devices
+            WHERE first_seen >= $1 AND first_seen < $2
+              AND NOT under_maintenance(site, mac_address, first_seen)
+            ORDER BY first_seen
+        "#)
+            .bind(start)
+            .bind(end)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load new devices")
+    }
+
+    /// Devices last seen between `start` and `end`
+    pub async fn devices_last_seen(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<DeviceChange>> {
+        sqlx::query_as(r#"
+            SELECT site, mac_address::text AS mac_address,
+                   (SELECT host(ip_address) FROM device_ips i
+                    WHERE i.device_id = devices.id ORDER BY last_seen DESC LIMIT 1) AS ip_address,
+                   first_seen, last_seen
+            FROM devices
+            WHERE last_seen >= $1 AND last_seen < $2
+              AND NOT under_maintenance(site, mac_address, last_seen)
+            ORDER BY last_seen
+        "#)
+            .bind(start)
+            .bind(end)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load quiet devices")
+    }
+
+    /// Services that devices known before `start` first used between `start`
+    /// and `end`
+    ///
+    /// Only services that made a device's hourly top list are known, and the
+    /// `other` bucket is left out.
+    pub async fn new_services(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<ServiceChange>> {
+        sqlx::query_as(r#"
+            SELECT DISTINCT ON (t.site, t.mac_address, t.service)
+                   t.site, t.mac_address::text AS mac_address, t.service, t.hour AS first_seen
+            FROM device_hourly_traffic t
+            JOIN devices d ON d.tenant = t.tenant AND d.mac_address = t.mac_address
+            WHERE t.hour >= $1 AND t.hour < $2 AND t.service <> 'other' AND d.first_seen < $1
+              AND NOT under_maintenance(t.site, t.mac_address, t.hour)
+              AND NOT EXISTS (
+                  SELECT 1 FROM device_hourly_traffic p
+                  WHERE p.site = t.site AND p.mac_address = t.mac_address
+                    AND p.service = t.service AND p.hour < $1
+              )
+            ORDER BY t.site, t.mac_address, t.service, t.hour
+        "#)
+            .bind(start)
+            .bind(end)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load new services")
+    }
+
+    /// IP addresses that devices known before `start` first held between
+    /// `start` and `end`
+    pub async fn ip_changes(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<IpChange>> {
+        let rows: Vec<IpChangeRow> = sqlx::query_as(r#"
+            SELECT d.site, d.mac_address::text AS mac_address, host(i.ip_address) AS ip_address,
+                   i.vlan_id, i.first_seen,
+                   ARRAY(SELECT host(p.ip_address) FROM device_ips p
+                         WHERE p.device_id = d.id AND p.first_seen < $1 AND family(p.ip_address) = 4
+                         ORDER BY p.last_seen DESC) AS previous
+            FROM device_ips i
+            JOIN devices d ON d.id = i.device_id
+            WHERE i.first_seen >= $1 AND i.first_seen < $2 AND d.first_seen < $1
+              AND family(i.ip_address) = 4
+              AND NOT under_maintenance(d.site, d.mac_address, i.first_seen)
+            ORDER BY i.first_seen
+        "#)
+            .bind(start)
+            .bind(end)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load IP changes")?;
+
+        Ok(rows.into_iter().map(|row| IpChange {
+            site: row.site,
+            mac_address: row.mac_address,
+            ip_address: row.ip_address,
+            vlan_id: row.vlan_id.map(|v| v as u16),
+            first_seen: row.first_seen,
+            previous: row.previous,
+        }).collect())
+    }
+
+    /// End of the period covered by the latest change report
+    pub async fn last_change_report_end(&self) -> Result<Option<DateTime<Utc>>> {
+        let row: (Option<DateTime<Utc>>,) = sqlx::query_as("SELECT MAX(period_end) FROM change_reports")
+            .fetch_one(&self.pool)
+            .await
+            .with_context(|| "Failed to load the latest change report")?;
+
+        Ok(row.0)
+    }
+
+    /// List service dependencies, heaviest first
+    pub async fn list_dependencies(
+        &self,
+        filter: &DependencyFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<StoredDependency>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, client_mac::text AS client_mac, server_mac::text AS server_mac, service, service_name,
+                   flows, packets_to_server, bytes_to_server, packets_to_client, bytes_to_client,
+                   active_hours, first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM service_dependencies WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND (client_mac = ").push_bind(mac.clone()).push("::macaddr");
+            query.push(" OR server_mac = ").push_bind(mac.clone()).push("::macaddr)");
+        }
+        if let Some(client) = &filter.client {
+            query.push(" AND client_mac = ").push_bind(client.clone()).push("::macaddr");
+        }
+        if let Some(server) = &filter.server {
+            query.push(" AND server_mac = ").push_bind(server.clone()).push("::macaddr");
+        }
+        if let Some(service) = &filter.service {
+            query.push(" AND (service = ").push_bind(service.to_lowercase());
+            query.push(" OR service_name = ").push_bind(service.to_lowercase()).push(")");
+        }
+        if let Some(hours) = filter.min_active_hours {
+            query.push(" AND active_hours >= ").push_bind(hours as i32);
+        }
+
+        query.push(" ORDER BY bytes_to_server + bytes_to_client DESC, last_seen DESC LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<DependencyRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list service dependencies")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let dependencies = rows.into_iter()
+            .map(|row| StoredDependency {
+                site: row.site,
+                client_mac: row.client_mac,
+                server_mac: row.server_mac,
+                service: row.service,
+                service_name: row.service_name,
+                flows: row.flows as u64,
+                packets_to_server: row.packets_to_server as u64,
+                bytes_to_server: row.bytes_to_server as u64,
+                packets_to_client: row.packets_to_client as u64,
+                bytes_to_client: row.bytes_to_client as u64,
+                active_hours: row.active_hours as u32,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect();
+
+        Ok((dependencies, total))
+    }
+
+    /// Activity of each server on `services` over the dependencies active
+    /// since `since`: distinct clients, flows and traffic
+    pub async fn auth_service_activity(&self, since: DateTime<Utc>, services: &[&str]) -> Result<Vec<ServiceActivity>> {
+        let rows: Vec<ServiceActivityRow> = sqlx::query_as(r#"
+            SELECT site, server_mac::text AS server_mac, (array_agg(server_device_id) FILTER (
+                       WHERE server_device_id IS NOT NULL))[1] AS device_id,
+                   service, COUNT(DISTINCT client_mac) AS clients, SUM(flows)::BIGINT AS flows,
+                   SUM(bytes_to_server + bytes_to_client)::BIGINT AS bytes,
+                   MIN(first_seen) AS first_seen, MAX(last_seen) AS last_seen
+            FROM service_dependencies
+            WHERE last_seen >= $1 AND service = ANY($2)
+            GROUP BY site, server_mac, service
+        "#)
+            .bind(since)
+            .bind(services)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load authentication service activity")?;
+
+        Ok(rows.into_iter()
+            .map(|row| ServiceActivity {
+                site: row.site,
+                server_mac: row.server_mac,
+                device_id: row.device_id,
+                service: row.service,
+                clients: row.clients as u64,
+                flows: row.flows as u64,
+                bytes: row.bytes as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect())
+    }
+
+    /// List the authentication servers, busiest first
+    pub async fn list_auth_servers(
+        &self,
+        filter: &AuthServerFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<AuthServer>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT site, mac_address::text AS mac_address, device_id, role, services::text[] AS services,
+                   clients, flows, bytes, first_seen, last_seen, COUNT(*) OVER () AS total
+            FROM auth_servers WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(mac) = &filter.mac {
+            query.push(" AND mac_address = ").push_bind(mac.clone()).push("::macaddr");
+        }
+        if let Some(role) = &filter.role {
+            query.push(" AND role = ").push_bind(role.to_lowercase());
+        }
+
+        query.push(" ORDER BY clients DESC, flows DESC, site, mac_address, role LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let rows: Vec<AuthServerRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list authentication servers")?;
+
+        let total = rows.first().map_or(0, |r| r.total as u64);
+        let servers = rows.into_iter()
+            .filter_map(|row| Some(AuthServer {
+                site: row.site,
+                mac_address: row.mac_address,
+                role: AuthRole::from_name(&row.role)?,
+                services: row.services,
+                clients: row.clients as u64,
+                flows: row.flows as u64,
+                bytes: row.bytes as u64,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                device_id: row.device_id,
+            }))
+            .collect();
+
+        Ok((servers, total))
+    }
+
+    /// List which clients depend on which authentication servers, busiest
+    /// first: their dependencies on the services of the servers' roles
+    pub async fn list_auth_clients(
+        &self,
+        filter: &AuthClientFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<AuthClient>, u64)> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT d.site, d.client_mac::text AS client_mac, d.server_mac::text AS server_mac, a.role,
+                   array_agg(DISTINCT d.service::text ORDER BY d.service::text) AS services,
+                   SUM(d.flows)::BIGINT AS flows, SUM(d.bytes_to_server + d.bytes_to_client)::BIGINT AS bytes,
+                   MIN(d.first_seen) AS first_seen, MAX(d.last_seen) AS last_seen, COUNT(*) OVER () AS total
+            FROM service_dependencies d
+            JOIN auth_servers a ON a.site = d.site AND a.mac_address = d.server_mac AND d.service = ANY(a.services)
+            WHERE TRUE"#);
+
+        if let Some(site) = &filter.site {
+            query.push(" AND d.site = ").push_bind(site.clone());
+        }
+        if let Some(tenant) = &filter.tenant {
+            query.push(" AND d.tenant = ").push_bind(tenant.clone());
+        }
+        if let Some(client) = &filter.client {
+            query.push(" AND d.client_mac = ").push_bind(client.clone()).push("::macaddr");
+        }
+        if let Some(server) = &filter.server {
+            query.push(" AND d.server_mac = ").push_bind(server.clone()).push("::macaddr");
+        }
+        if let Some(role) = &filter.role {
+            query.push(" AND a.role = ").push_bind(role.to_lowercase());
+        }
+
+        query.push(" GROUP BY d.site, d.client_mac, d.server_mac, a.role");
+        query.push(" ORDER BY flows DESC, d.site, d.client_mac, d.server_mac, a.role LIMIT ").push_bind(limit as i64);
+        query.push(" OFFSET ").push_bind(offset as i64);
+
+        let clients: Vec<AuthClient> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to list authentication clients")?;
+
+        let total = clients.first().map_or(0, |c| c.total as u64);
+        Ok((clients, total))
+    }
+
+    /// Traffic between devices over the flows active between `start` and
+    /// `end`, grouped by source, destination and service
+    pub async fn traffic_links(
+        &self,
+        site: Option<&str>,
+        tenant: Option<&str>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<TrafficLink>> {
+        let mut query = QueryBuilder::<Postgres>::new(r#"
+            SELECT src_mac::text AS src_mac, dst_mac::text AS dst_mac, ethertype, ip_protocol,
+                   LEAST(src_port, dst_port) AS port,
+                   COUNT(*) AS flows, SUM(packet_count)::BIGINT AS packets, SUM(byte_count)::BIGINT AS bytes
+            FROM traffic_flows WHERE last_seen >= "#);
+        query.push_bind(start).push(" AND first_seen <= ").push_bind(end);
+        if let Some(site) = site {
+            query.push(" AND site = ").push_bind(site.to_string());
+        }
+        if let Some(tenant) = tenant {
+            query.push(" AND tenant = ").push_bind(tenant.to_string());
+        }
+        query.push(" GROUP BY src_mac, dst_mac, ethertype, ip_protocol, LEAST(src_port, dst_port)");
+
+        let rows: Vec<TrafficLinkRow> = query
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load traffic links")?;
+
+        Ok(rows.into_iter().map(|row| TrafficLink {
+            src_mac: row.src_mac,
+            dst_mac: row.dst_mac,
+            ethertype: row.ethertype.map_or(ETHERTYPE_IPV4, |e| e as u16),
+            ip_protocol: row.ip_protocol.map(|p| p as u8),
+            port: row.port.map(|p| p as u16),
+            flows: row.flows as u64,
+            packets: row.packets as u64,
+            bytes: row.bytes as u64,
+        }).collect())
+    }
+
+    /// Inventory details of the devices with these MAC addresses, of
+    /// `tenant` when set
+    pub async fn graph_devices(&self, macs: &[String], tenant: Option<&str>) -> Result<Vec<GraphDevice>> {
+        sqlx::query_as(r#"
+            SELECT d.mac_address::text AS mac_address, d.site, d.is_gateway,
+                   (SELECT host(i.ip_address) FROM device_ips i
+                    WHERE i.device_id = d.id ORDER BY i.last_seen DESC LIMIT 1) AS ip_address
+            FROM devices d
+            WHERE d.mac_address = ANY($1::macaddr[]) AND ($2::text IS NULL OR d.tenant = $2)
+        "#)
+            .bind(macs)
+            .bind(tenant)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| "Failed to load graph devices")
+    }
+}
@@ -0,0 +1,229 @@
+//! NXDOMAIN spikes
+//!
+//! The per-device DNS response counters are sampled every
+//! `sample_interval_secs`. Each device learns a baseline of the NXDOMAIN
+//! responses it receives per sample, an exponentially weighted mean and
+//! standard deviation over about `baseline_samples` samples, quiet ones
+//! included. Once `learning_samples` samples have been learned, a sample
+//! `spike_sigmas` standard deviations above the mean raises an alert, provided
+//! it counts at least `min_nxdomain` NXDOMAINs making up `min_ratio` of the
+//! device's responses: malware cycling through generated domains until one
+//! resolves fails most of its lookups at once.
+//!
+//! A spike alerts once, however many samples it lasts, and its samples are
+//! left out of the baseline. Every sample of a learned baseline is scored
+//! (see `state::anomaly`). Alerts list the names of the current hour with
+//! the most NXDOMAIN responses.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::bandwidth::format_window;
+use crate::config::DnsConfig;
+use crate::events::{Event, EventSender};
+use crate::exfiltration::Baseline;
+use crate::state::composition::hour_of;
+use crate::state::{AggregatorState, DeviceKey, DnsTotals};
+
+/// NXDOMAIN history of one device
+#[derive(Default)]
+struct Profile {
+    baseline: Baseline,
+    /// Counters at the previous sample
+    previous: DnsTotals,
+    spiking: bool,
+}
+
+/// Responses and NXDOMAINs of a device within one sample
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    responses: u64,
+    nxdomain: u64,
+}
+
+/// Detects devices suddenly receiving many NXDOMAIN responses
+pub struct NxdomainDetector {
+    config: DnsConfig,
+    state: Arc<AggregatorState>,
+    /// Whether the counters have been sampled once
+    started: bool,
+    profiles: HashMap<DeviceKey, Profile>,
+}
+
+impl NxdomainDetector {
+    pub fn new(config: DnsConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            started: false,
+            profiles: HashMap::new(),
+        }
+    }
+
+    /// Weight of the newest sample in the baseline
+    fn alpha(&self) -> f64 {
+        2.0 / (self.config.baseline_samples as f64 + 1.0)
+    }
+
+    /// Sample the DNS counters at `now` and return the alerts of the devices
+    /// with a new spike
+    pub fn sample(&mut self, now: DateTime<Utc>) -> Vec<Event> {
+        let alpha = self.alpha();
+        // The first sample only sets the counters: earlier responses have no time reference
+        let started = std::mem::replace(&mut self.started, true);
+        let mut spikes = Vec::new();
+
+        for entry in self.state.dns.totals.iter() {
+            let totals = *entry.value();
+            let profile = self.profiles.entry(*entry.key()).or_default();
+            let previous = std::mem::replace(&mut profile.previous, totals);
+            if !started {
+                continue;
+            }
+
+            let sample = Sample {
+                responses: totals.responses.saturating_sub(previous.responses),
+                nxdomain: totals.nxdomain.saturating_sub(previous.nxdomain),
+            };
+            let learned = profile.baseline.buckets >= self.config.learning_samples;
+            let threshold = profile.baseline.mean + self.config.spike_sigmas * profile.baseline.stddev();
+            let spiking = learned
+                && sample.nxdomain as f64 > threshold
+                && sample.nxdomain >= self.config.min_nxdomain
+                && sample.nxdomain as f64 >= self.config.min_ratio * sample.responses as f64;
+
+            if learned {
+                let nxdomain = sample.nxdomain as f64;
+                let score = profile.baseline.score(nxdomain);
+                self.state.anomalies.record(*entry.key(), "nxdomain", now.timestamp(), nxdomain, profile.baseline.mean, score);
+            }
+            if spiking && !profile.spiking {
+                spikes.push((*entry.key(), sample));
+            }
+            profile.spiking = spiking;
+            if !spiking {
+                profile.baseline.update(sample.nxdomain as f64, alpha);
+            }
+        }
+
+        spikes.into_iter().map(|(device, spike)| self.alert(device, spike, now)).collect()
+    }
+
+    /// Alert for a device with a spike
+    fn alert(&self, device: DeviceKey, spike: Sample, now: DateTime<Utc>) -> Event {
+        let baseline = &self.profiles[&device].baseline;
+        let window = format_window(self.config.sample_interval_secs);
+        let domains = self.state.dns.hours.get(&(device, hour_of(now)))
+            .map(|hour| hour.top_nx_domains(self.config.breakdown_domains))
+            .unwrap_or_default();
+        let ratio = spike.nxdomain as f64 / spike.responses.max(1) as f64;
+
+        let message = format!(
+            "Device {} received {} NXDOMAIN responses within {} ({:.0}% of its responses, baseline {:.1})",
+            device.mac, spike.nxdomain, window, ratio * 100.0, baseline.mean
+        );
+
+        let details = json!({
+            "sample_secs": self.config.sample_interval_secs,
+            "responses": spike.responses,
+            "nxdomain": spike.nxdomain,
+            "nxdomain_ratio": ratio,
+            "baseline_nxdomain": baseline.mean,
+            "baseline_stddev": baseline.stddev(),
+            "domains": domains.iter().map(|(name, count)| json!({
+                "name": name,
+                "nxdomain": count,
+            })).collect::<Vec<_>>(),
+        });
+
+        let ip = self.state.devices.get(&device).and_then(|d| d.ips.iter().next().map(|ip| *ip.key()));
+
+        Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(device.site.to_string()),
+            sensor: None,
+            mac: Some(device.mac.to_string()),
+            ip,
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Sample until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "NXDOMAIN spike detection enabled (samples every {}, learning {})",
+            format_window(self.config.sample_interval_secs),
+            self.config.learning_samples
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.sample_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.sample(Utc::now()) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("NXDOMAIN spike detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DnsInfo, MacAddr, SiteId};
+
+    const HOST: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const RESOLVER: [u8; 6] = [0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+    /// Deliver `ok` resolved and `nx` NXDOMAIN responses to the host, then sample
+    fn responses(detector: &mut NxdomainDetector, state: &AggregatorState, ok: u64, nx: u64, at: DateTime<Utc>) -> Vec<Event> {
+        for i in 0..ok + nx {
+            let (rcode, qname) = if i < nx { (3, format!("k{}qz7vx3pl.com", i % 4)) } else { (0, "example.com".to_string()) };
+            let dns = DnsInfo { id: 1, response: true, rcode, qname: Some(qname), qtype: Some(1), answers: 0 };
+            let (resolver, host) = (MacAddr::new(RESOLVER), MacAddr::new(HOST));
+            state.dns.record(&dns, DeviceKey::new(SiteId::default(), resolver), DeviceKey::new(SiteId::default(), host), at);
+        }
+        detector.sample(at)
+    }
+
+    #[test]
+    fn test_nxdomain_spike() {
+        let config: DnsConfig = toml::from_str("learning_samples = 5\nmin_nxdomain = 20").unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut detector = NxdomainDetector::new(config, Arc::clone(&state));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+
+        assert!(responses(&mut detector, &state, 30, 1, at).is_empty());
+        for _ in 0..6 {
+            assert!(responses(&mut detector, &state, 30, 1, at).is_empty());
+        }
+
+        let alerts = responses(&mut detector, &state, 10, 40, at);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, details, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Device 00:11:22:33:44:55 received 40 NXDOMAIN responses within 1m (80% of its responses, baseline 1.0)");
+        let details = details.as_ref().unwrap();
+        assert_eq!(details["domains"].as_array().unwrap().len(), 4);
+
+        // Once per spike, and not learned as normal
+        assert!(responses(&mut detector, &state, 10, 40, at).is_empty());
+        assert!(responses(&mut detector, &state, 30, 1, at).is_empty());
+        assert_eq!(responses(&mut detector, &state, 10, 40, at).len(), 1);
+    }
+}
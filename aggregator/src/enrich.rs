@@ -0,0 +1,126 @@
+//! Frame enrichers
+//!
+//! Deployments add their own enrichment (lookups in an internal asset
+//! database, custom tagging) by implementing [`Enricher`] and registering
+//! it with [`Pipeline::with_enricher`](crate::Pipeline::with_enricher),
+//! without touching `state::process_frame`. Enrichers run in registration
+//! order after each frame has been applied to the state, and typically
+//! record their findings as device tags, which are persisted with the
+//! device. A failing enricher is logged and counted; it never stops the
+//! consumer.
+
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::metrics::metrics;
+use crate::state::{AggregatorState, SensorFrame};
+
+/// Future returned by [`Enricher::enrich`]
+pub type EnrichFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Custom enrichment applied to every consumed frame
+///
+/// `enrich` is awaited inline by the consumer, so slow lookups should be
+/// cached by the enricher rather than repeated for every frame.
+pub trait Enricher: Send + Sync + 'static {
+    /// Enricher name used in logs and metrics
+    fn name(&self) -> &'static str;
+
+    /// Enrich the state with `frame`, already applied to `state`
+    fn enrich<'a>(&'a self, frame: &'a SensorFrame, state: &'a AggregatorState) -> EnrichFuture<'a>;
+}
+
+/// Enrichers registered with the pipeline, in registration order
+#[derive(Clone, Default)]
+pub struct Enrichers {
+    enrichers: Vec<Arc<dyn Enricher>>,
+}
+
+impl Enrichers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `enricher` after those already registered
+    pub fn register(&mut self, enricher: impl Enricher) {
+        self.enrichers.push(Arc::new(enricher));
+    }
+
+    /// Whether no enricher is registered
+    pub fn is_empty(&self) -> bool {
+        self.enrichers.is_empty()
+    }
+
+    /// Run every enricher on `frame`
+    pub async fn enrich(&self, frame: &SensorFrame, state: &AggregatorState) {
+        for enricher in &self.enrichers {
+            if let Err(e) = enricher.enrich(frame, state).await {
+                debug!("Enricher {} failed: {:#}", enricher.name(), e);
+                metrics().enrich_errors.with_label_values(&[enricher.name()]).inc();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DeviceKey;
+
+    /// Tags devices whose OUI is in an (in-memory) asset list
+    struct AssetTagger;
+
+    impl Enricher for AssetTagger {
+        fn name(&self) -> &'static str {
+            "assets"
+        }
+
+        fn enrich<'a>(&'a self, frame: &'a SensorFrame, state: &'a AggregatorState) -> EnrichFuture<'a> {
+            Box::pin(async move {
+                if frame.src_mac.oui_bytes() != [0x00, 0x11, 0x22] {
+                    anyhow::bail!("unknown asset {}", frame.src_mac);
+                }
+                if let Some(device) = state.devices.get(&DeviceKey::new(frame.site, frame.src_mac)) {
+                    device.set_tag("owner", "it-ops");
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn frame(src_mac: &str) -> SensorFrame {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": src_mac,
+            "dst_mac": "66:77:88:99:aa:bb",
+            "ethertype": 0x0800,
+            "frame_size": 60,
+            "payload_size": 0,
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_enrichers_tag_devices() {
+        let state = AggregatorState::new();
+        let mut enrichers = Enrichers::new();
+        enrichers.register(AssetTagger);
+
+        let known = frame("00:11:22:33:44:55");
+        state.process_frame(&known);
+        enrichers.enrich(&known, &state).await;
+
+        let unknown = frame("aa:bb:cc:00:00:01");
+        state.process_frame(&unknown);
+        enrichers.enrich(&unknown, &state).await;
+
+        let device = state.devices.get(&DeviceKey::new(known.site, known.src_mac)).unwrap();
+        assert_eq!(device.snapshot().tags.get("owner").map(String::as_str), Some("it-ops"));
+        let device = state.devices.get(&DeviceKey::new(unknown.site, unknown.src_mac)).unwrap();
+        assert!(device.snapshot().tags.is_empty());
+        assert_eq!(metrics().enrich_errors.with_label_values(&["assets"]).get(), 1);
+    }
+}
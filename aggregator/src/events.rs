@@ -0,0 +1,579 @@
+//! Inventory, alert and lifecycle events
+//!
+//! The consumer emits an [`Event`] for every newly discovered device and
+//! flow on a broadcast channel, and the pipeline one when it starts and
+//! stops. The Redis publisher and the notification sinks each subscribe to
+//! that channel and forward the events they care about.
+//!
+//! Sinks sending events as JSON send an [`EventRecord`], whose format is
+//! versioned by [`EVENT_SCHEMA_VERSION`] and described by the JSON Schema
+//! `netsentinel-aggregator print-event-schema` prints.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use redis::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::EventsConfig;
+use crate::state::{DeviceKey, FlowKey, SensorId, DEFAULT_SITE};
+
+/// Version of [`EventRecord`], bumped on incompatible changes
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Events buffered per subscriber before slow subscribers start losing events
+const EVENT_BUFFER: usize = 4096;
+
+/// Sending half of the event channel
+pub type EventSender = broadcast::Sender<Event>;
+
+/// Create the event channel
+pub fn channel() -> EventSender {
+    broadcast::channel(EVENT_BUFFER).0
+}
+
+/// Event category, used by sinks to select what they forward
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewDevice,
+    NewFlow,
+    Alert,
+    Lifecycle,
+}
+
+/// Alert severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// Aggregator lifecycle transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecyclePhase {
+    /// Every task is running
+    Started,
+    /// Shutdown was requested, state is being persisted
+    Stopping,
+}
+
+/// Inventory, alert or lifecycle event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    NewDevice {
+        timestamp: DateTime<Utc>,
+        site: String,
+        sensor: String,
+        mac: String,
+        oui_prefix: String,
+        ip: Option<Ipv4Addr>,
+        vlan_id: Option<u16>,
+    },
+    NewFlow {
+        timestamp: DateTime<Utc>,
+        site: String,
+        sensor: String,
+        src_mac: String,
+        dst_mac: String,
+        src_ip: Option<Ipv4Addr>,
+        dst_ip: Option<Ipv4Addr>,
+        src_port: Option<u16>,
+        dst_port: Option<u16>,
+        vlan_id: Option<u16>,
+        protocol: Option<u8>,
+        /// ICMP type, code and echo identifier (ICMP flows)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_type: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_code: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_id: Option<u16>,
+    },
+    Alert {
+        timestamp: DateTime<Utc>,
+        severity: Severity,
+        /// Short machine-readable alert name
+        name: String,
+        message: String,
+        /// Site of the device or traffic alerted on
+        #[serde(skip_serializing_if = "Option::is_none")]
+        site: Option<String>,
+        /// Sensor alerted on, for alerts about a sensor rather than traffic
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sensor: Option<String>,
+        mac: Option<String>,
+        ip: Option<Ipv4Addr>,
+        /// Notification sinks the alert is restricted to (empty: all sinks)
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        channels: Vec<String>,
+        /// Structured context (e.g. the flows behind a threshold alert)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        details: Option<serde_json::Value>,
+    },
+    Lifecycle {
+        timestamp: DateTime<Utc>,
+        /// Consumer name of the aggregator instance
+        instance: String,
+        phase: LifecyclePhase,
+        version: String,
+    },
+}
+
+impl Event {
+    /// A device seen for the first time, by `sensor`
+    pub fn new_device(
+        device: DeviceKey,
+        sensor: SensorId,
+        ip: Option<Ipv4Addr>,
+        vlan_id: Option<u16>,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Event::NewDevice {
+            timestamp,
+            site: device.site.to_string(),
+            sensor: sensor.to_string(),
+            mac: device.mac.to_string(),
+            oui_prefix: device.mac.oui_prefix(),
+            ip,
+            vlan_id,
+        }
+    }
+
+    /// A flow seen for the first time
+    pub fn new_flow(key: &FlowKey, timestamp: DateTime<Utc>) -> Self {
+        Event::NewFlow {
+            timestamp,
+            site: key.site.to_string(),
+            sensor: key.sensor.to_string(),
+            src_mac: key.src_mac.to_string(),
+            dst_mac: key.dst_mac.to_string(),
+            src_ip: key.src_ip,
+            dst_ip: key.dst_ip,
+            src_port: key.src_port,
+            dst_port: key.dst_port,
+            vlan_id: key.vlan_id,
+            protocol: key.protocol,
+            icmp_type: key.icmp_type,
+            icmp_code: key.icmp_code,
+            icmp_id: key.icmp_id,
+        }
+    }
+
+    /// The aggregator instance `instance` went through `phase`
+    pub fn lifecycle(instance: &str, phase: LifecyclePhase) -> Self {
+        Event::Lifecycle {
+            timestamp: Utc::now(),
+            instance: instance.to_string(),
+            phase,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Event category
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::NewDevice { .. } => EventKind::NewDevice,
+            Event::NewFlow { .. } => EventKind::NewFlow,
+            Event::Alert { .. } => EventKind::Alert,
+            Event::Lifecycle { .. } => EventKind::Lifecycle,
+        }
+    }
+
+    /// Event severity (inventory and lifecycle events are informational or low)
+    pub fn severity(&self) -> Severity {
+        match self {
+            Event::NewDevice { .. } => Severity::Low,
+            Event::NewFlow { .. } | Event::Lifecycle { .. } => Severity::Info,
+            Event::Alert { severity, .. } => *severity,
+        }
+    }
+
+    /// Short machine-readable name: the alert name, the lifecycle phase or
+    /// the event type
+    pub fn name(&self) -> &str {
+        match self {
+            Event::NewDevice { .. } => "new_device",
+            Event::NewFlow { .. } => "new_flow",
+            Event::Alert { name, .. } => name,
+            Event::Lifecycle { phase: LifecyclePhase::Started, .. } => "started",
+            Event::Lifecycle { phase: LifecyclePhase::Stopping, .. } => "stopping",
+        }
+    }
+
+    /// VLAN the event was observed on, if any
+    pub fn vlan_id(&self) -> Option<u16> {
+        match self {
+            Event::NewDevice { vlan_id, .. } | Event::NewFlow { vlan_id, .. } => *vlan_id,
+            Event::Alert { .. } | Event::Lifecycle { .. } => None,
+        }
+    }
+
+    /// Whether the notification sink `name` should receive the event
+    pub fn routed_to(&self, name: &str) -> bool {
+        match self {
+            Event::Alert { channels, .. } => channels.is_empty() || channels.iter().any(|c| c == name),
+            _ => true,
+        }
+    }
+
+    /// When the event happened
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            Event::NewDevice { timestamp, .. }
+            | Event::NewFlow { timestamp, .. }
+            | Event::Alert { timestamp, .. }
+            | Event::Lifecycle { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// One-line human readable summary
+    pub fn summary(&self) -> String {
+        match self {
+            Event::NewDevice { site, mac, ip, vlan_id, .. } => {
+                let mut s = format!("New device {}", mac);
+                if let Some(ip) = ip {
+                    s.push_str(&format!(" ({})", ip));
+                }
+                if let Some(vlan) = vlan_id {
+                    s.push_str(&format!(" on VLAN {}", vlan));
+                }
+                if site != DEFAULT_SITE {
+                    s.push_str(&format!(" at site {}", site));
+                }
+                s
+            }
+            Event::NewFlow { src_mac, dst_mac, src_ip, dst_ip, dst_port, icmp_type, icmp_code, .. } => {
+                let src = src_ip.map_or(src_mac.clone(), |ip| ip.to_string());
+                let dst = dst_ip.map_or(dst_mac.clone(), |ip| ip.to_string());
+                match (dst_port, icmp_type.zip(*icmp_code)) {
+                    (Some(port), _) => format!("New flow {} -> {}:{}", src, dst, port),
+                    (None, Some((icmp_type, code))) => format!("New flow {} -> {} (ICMP {}/{})", src, dst, icmp_type, code),
+                    (None, None) => format!("New flow {} -> {}", src, dst),
+                }
+            }
+            Event::Alert { severity, message, .. } => format!("[{:?}] {}", severity, message),
+            Event::Lifecycle { instance, phase, version, .. } => match phase {
+                LifecyclePhase::Started => format!("Aggregator {} started (version {})", instance, version),
+                LifecyclePhase::Stopping => format!("Aggregator {} stopping", instance),
+            },
+        }
+    }
+
+    /// Published form of the event
+    pub fn record(&self) -> EventRecord {
+        let mut evidence = serde_json::Map::new();
+        let mut add = |key: &str, value: serde_json::Value| {
+            if !value.is_null() {
+                evidence.insert(key.to_string(), value);
+            }
+        };
+
+        let (site, sensor, summary, entities) = match self {
+            Event::NewDevice { site, sensor, mac, oui_prefix, ip, vlan_id, .. } => {
+                add("oui_prefix", oui_prefix.as_str().into());
+                add("vlan_id", (*vlan_id).into());
+                let device = EntityRef::Device { mac: mac.clone(), ip: *ip };
+                (Some(site.clone()), Some(sensor.clone()), self.summary(), vec![device])
+            }
+            Event::NewFlow {
+                site, sensor, src_mac, dst_mac, src_ip, dst_ip, src_port, dst_port, vlan_id, protocol,
+                icmp_type, icmp_code, icmp_id, ..
+            } => {
+                let entities = vec![
+                    EntityRef::Flow {
+                        src_mac: src_mac.clone(),
+                        dst_mac: dst_mac.clone(),
+                        src_ip: *src_ip,
+                        dst_ip: *dst_ip,
+                        src_port: *src_port,
+                        dst_port: *dst_port,
+                        protocol: *protocol,
+                        vlan_id: *vlan_id,
+                        icmp_type: *icmp_type,
+                        icmp_code: *icmp_code,
+                        icmp_id: *icmp_id,
+                    },
+                    EntityRef::Device { mac: src_mac.clone(), ip: *src_ip },
+                    EntityRef::Device { mac: dst_mac.clone(), ip: *dst_ip },
+                ];
+                (Some(site.clone()), Some(sensor.clone()), self.summary(), entities)
+            }
+            Event::Alert { message, site, sensor, mac, ip, details, .. } => {
+                // Object details become the evidence, other values are nested
+                match details {
+                    Some(serde_json::Value::Object(details)) => {
+                        for (key, value) in details {
+                            add(key, value.clone());
+                        }
+                    }
+                    Some(details) => add("details", details.clone()),
+                    None => {}
+                }
+                let entity = match (mac, ip) {
+                    (Some(mac), ip) => Some(EntityRef::Device { mac: mac.clone(), ip: *ip }),
+                    (None, Some(ip)) => Some(EntityRef::Host { ip: *ip }),
+                    (None, None) => None,
+                };
+                (site.clone(), sensor.clone(), message.clone(), entity.into_iter().collect())
+            }
+            Event::Lifecycle { instance, version, .. } => {
+                add("version", version.as_str().into());
+                (None, None, self.summary(), vec![EntityRef::Instance { name: instance.clone() }])
+            }
+        };
+
+        EventRecord {
+            schema_version: EVENT_SCHEMA_VERSION,
+            kind: self.kind(),
+            name: self.name().to_string(),
+            severity: self.severity(),
+            timestamp: self.timestamp(),
+            site,
+            sensor,
+            summary,
+            entities,
+            evidence,
+        }
+    }
+}
+
+/// Event as published by every sink sending JSON
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EventRecord {
+    /// Version of this format, bumped on incompatible changes
+    pub schema_version: u32,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// Alert name, lifecycle phase, or the event type for inventory events
+    pub name: String,
+    pub severity: Severity,
+    /// When the event happened, with nanosecond precision
+    #[serde(with = "netsentinel_types::timestamp")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub timestamp: DateTime<Utc>,
+    /// Site the event relates to, when it relates to one
+    pub site: Option<String>,
+    /// Sensor that observed the traffic, for inventory events
+    pub sensor: Option<String>,
+    /// One-line human readable description
+    pub summary: String,
+    /// Devices, hosts, flows or instances the event is about
+    pub entities: Vec<EntityRef>,
+    /// Observations behind the event (e.g. thresholds and the flows that
+    /// crossed them for an alert)
+    pub evidence: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Reference to what an event is about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EntityRef {
+    /// A device, by MAC address
+    Device {
+        mac: String,
+        ip: Option<Ipv4Addr>,
+    },
+    /// A host known by its IP address only
+    Host {
+        ip: Ipv4Addr,
+    },
+    /// A flow between two devices
+    Flow {
+        src_mac: String,
+        dst_mac: String,
+        src_ip: Option<Ipv4Addr>,
+        dst_ip: Option<Ipv4Addr>,
+        src_port: Option<u16>,
+        dst_port: Option<u16>,
+        protocol: Option<u8>,
+        vlan_id: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_type: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_code: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_id: Option<u16>,
+    },
+    /// An aggregator instance, by consumer name
+    Instance {
+        name: String,
+    },
+}
+
+/// JSON Schema of [`EventRecord`]
+pub fn record_schema() -> String {
+    let schema = schemars::schema_for!(EventRecord);
+    serde_json::to_string_pretty(&schema).expect("JSON Schema serializes")
+}
+
+/// Receive the next event, skipping over events lost to lag
+///
+/// Returns `None` once every sender is gone.
+pub async fn recv(rx: &mut broadcast::Receiver<Event>, subscriber: &str) -> Option<Event> {
+    loop {
+        match rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("{} fell behind, {} events dropped", subscriber, n);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Take the events still queued for a subscriber without waiting
+pub fn drain(rx: &mut broadcast::Receiver<Event>) -> Vec<Event> {
+    let mut events = Vec::new();
+    loop {
+        match rx.try_recv() {
+            Ok(event) => events.push(event),
+            Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+            Err(_) => break,
+        }
+    }
+    events
+}
+
+/// Publishes events to the configured Redis pub/sub channel
+pub struct EventPublisher {
+    config: EventsConfig,
+    client: Client,
+}
+
+impl EventPublisher {
+    /// Create a publisher on the Redis server at `redis_url`
+    pub fn new(config: EventsConfig, redis_url: &str) -> Result<Self> {
+        let client = Client::open(redis_url)
+            .with_context(|| format!("Failed to create Redis client: {}", redis_url))?;
+
+        Ok(Self { config, client })
+    }
+
+    fn wants(&self, kind: EventKind) -> bool {
+        match kind {
+            EventKind::NewDevice => self.config.publish_new_devices,
+            EventKind::NewFlow => self.config.publish_new_flows,
+            EventKind::Alert => self.config.publish_alerts,
+            EventKind::Lifecycle => self.config.publish_lifecycle,
+        }
+    }
+
+    async fn publish(&self, conn: &mut redis::aio::MultiplexedConnection, event: &Event) {
+        let payload = match serde_json::to_string(&event.record()) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize event: {}", e);
+                return;
+            }
+        };
+
+        let result: redis::RedisResult<i64> = redis::cmd("PUBLISH")
+            .arg(&self.config.channel)
+            .arg(payload)
+            .query_async(conn)
+            .await;
+        if let Err(e) = result {
+            warn!("Failed to publish event: {}", e);
+        }
+    }
+
+    /// Publish events until shutdown, then flush what is still queued
+    pub async fn run(self, mut events: broadcast::Receiver<Event>, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        let mut conn = self.client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| "Failed to connect to Redis")?;
+
+        info!("Publishing events to Redis channel '{}'", self.config.channel);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                event = recv(&mut events, "Event publisher") => {
+                    let Some(event) = event else { break };
+                    if self.wants(event.kind()) {
+                        self.publish(&mut conn, &event).await;
+                    }
+                }
+            }
+        }
+
+        for event in drain(&mut events) {
+            if self.wants(event.kind()) {
+                self.publish(&mut conn, &event).await;
+            }
+        }
+
+        debug!("Event publisher stopped");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+
+    #[test]
+    fn test_event_serialization() {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let device = DeviceKey::new(SiteId::default(), mac);
+        let event = Event::new_device(device, SensorId::default(), Some(Ipv4Addr::new(10, 0, 0, 1)), Some(20), Utc::now());
+
+        let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "new_device");
+        assert_eq!(json["site"], "default");
+        assert_eq!(json["mac"], "00:11:22:33:44:55");
+        assert_eq!(json["oui_prefix"], "00:11:22");
+        assert_eq!(event.kind(), EventKind::NewDevice);
+        assert_eq!(event.summary(), "New device 00:11:22:33:44:55 (10.0.0.1) on VLAN 20");
+
+        let device = DeviceKey::new(SiteId::new("lyon").unwrap(), mac);
+        let event = Event::new_device(device, SensorId::default(), None, None, Utc::now());
+        assert_eq!(event.summary(), "New device 00:11:22:33:44:55 at site lyon");
+    }
+
+    #[test]
+    fn test_event_record() {
+        let alert = Event::Alert {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            severity: Severity::High,
+            name: "beaconing".to_string(),
+            message: "Periodic connections to 203.0.113.7".to_string(),
+            site: Some("lyon".to_string()),
+            sensor: None,
+            mac: None,
+            ip: Some(Ipv4Addr::new(203, 0, 113, 7)),
+            channels: vec!["syslog".to_string()],
+            details: Some(serde_json::json!({ "interval_secs": 60 })),
+        };
+
+        let json = serde_json::to_value(alert.record()).unwrap();
+        assert_eq!(json["schema_version"], EVENT_SCHEMA_VERSION);
+        assert_eq!(json["type"], "alert");
+        assert_eq!(json["name"], "beaconing");
+        assert_eq!(json["severity"], "high");
+        assert_eq!(json["timestamp"], "2023-11-14T22:13:20.123456789Z");
+        assert_eq!(json["summary"], "Periodic connections to 203.0.113.7");
+        assert_eq!(json["entities"], serde_json::json!([{ "kind": "host", "ip": "203.0.113.7" }]));
+        assert_eq!(json["evidence"], serde_json::json!({ "interval_secs": 60 }));
+        assert!(json.get("channels").is_none());
+
+        let stopping = Event::lifecycle("aggregator-1", LifecyclePhase::Stopping).record();
+        assert_eq!(stopping.name, "stopping");
+        assert_eq!(stopping.entities, vec![EntityRef::Instance { name: "aggregator-1".to_string() }]);
+
+        let schema: serde_json::Value = serde_json::from_str(&record_schema()).unwrap();
+        assert!(schema["required"].as_array().unwrap().contains(&"schema_version".into()));
+    }
+}
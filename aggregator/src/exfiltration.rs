@@ -0,0 +1,424 @@
+//! Outbound volume anomalies
+//!
+//! The flow byte counters are sampled every `sample_interval_secs` and the
+//! increments of flows from an internal device to an external host are added
+//! to that device's outbound volume for the current period (`bucket_secs`).
+//! Each device learns a baseline of its volume per period, an exponentially
+//! weighted mean and standard deviation over about `baseline_buckets`
+//! periods, including the quiet ones. Once `learning_buckets` periods have
+//! been learned, two kinds of anomaly raise an alert:
+//!
+//! - a burst: the current period is `burst_sigmas` standard deviations above
+//!   the mean (and at least `min_burst_bytes`), e.g. an archive uploaded at
+//!   once;
+//! - a leak: each of the last `leak_buckets` periods is `leak_ratio` times the
+//!   mean (and they add up to `min_leak_bytes`), e.g. data trickled out to
+//!   stay under per-period thresholds.
+//!
+//! Periods with a burst or during a leak are left out of the baseline, so an
+//! ongoing transfer does not become normal. Every period of a learned
+//! baseline is scored (see `state::anomaly`). Alerts list the destinations
+//! that received the most. Baselines live in memory and are relearned after
+//! a restart.
+
+use chrono::Utc;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::bandwidth::{format_bytes, format_window, is_external};
+use crate::config::ExfiltrationConfig;
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, DeviceKey};
+
+/// Exponentially weighted mean and variance of a device's volume per period
+#[derive(Debug, Default)]
+pub(crate) struct Baseline {
+    pub(crate) mean: f64,
+    variance: f64,
+    pub(crate) buckets: u32,
+}
+
+impl Baseline {
+    pub(crate) fn update(&mut self, bytes: f64, alpha: f64) {
+        if self.buckets == 0 {
+            self.mean = bytes;
+        } else {
+            let diff = bytes - self.mean;
+            self.mean += alpha * diff;
+            self.variance = (1.0 - alpha) * (self.variance + alpha * diff * diff);
+        }
+        self.buckets += 1;
+    }
+
+    pub(crate) fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    /// Standard deviations `value` lies from the mean, the deviation being at
+    /// least 1 so that a flat baseline does not blow up any change
+    pub(crate) fn score(&self, value: f64) -> f64 {
+        (value - self.mean) / self.stddev().max(1.0)
+    }
+}
+
+/// Outbound traffic of a device during one period
+#[derive(Default)]
+struct Period {
+    index: u64,
+    bytes: u64,
+    destinations: HashMap<Ipv4Addr, u64>,
+    burst: bool,
+}
+
+impl Period {
+    fn new(index: u64) -> Self {
+        Self { index, ..Default::default() }
+    }
+}
+
+/// Volume history of one device
+struct Profile {
+    baseline: Baseline,
+    current: Period,
+    /// Completed periods, oldest first, at most `leak_buckets`
+    recent: VecDeque<Period>,
+    leaking: bool,
+    /// Last period with outbound traffic
+    last_active: u64,
+}
+
+/// Kind of anomaly found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anomaly {
+    Burst,
+    Leak,
+}
+
+/// Detects devices sending unusually much to external hosts
+pub struct ExfiltrationDetector {
+    config: ExfiltrationConfig,
+    state: Arc<AggregatorState>,
+    /// Flow byte totals at the previous sample (`None` before the first)
+    flow_bytes: Option<HashMap<Uuid, u64>>,
+    profiles: HashMap<DeviceKey, Profile>,
+}
+
+impl ExfiltrationDetector {
+    pub fn new(config: ExfiltrationConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            flow_bytes: None,
+            profiles: HashMap::new(),
+        }
+    }
+
+    /// Weight of the newest period in the baseline
+    fn alpha(&self) -> f64 {
+        2.0 / (self.config.baseline_buckets as f64 + 1.0)
+    }
+
+    /// Outbound bytes per device and destination since the previous sample
+    fn deltas(&mut self) -> HashMap<DeviceKey, HashMap<Ipv4Addr, u64>> {
+        // The first sample only sets the baseline: earlier traffic has no time reference
+        let previous = self.flow_bytes.take();
+        let mut totals = HashMap::with_capacity(previous.as_ref().map_or(0, |p| p.len()));
+        let mut deltas: HashMap<DeviceKey, HashMap<Ipv4Addr, u64>> = HashMap::new();
+
+        for flow in self.state.flows.iter() {
+            let total = flow.byte_count.load(Ordering::Relaxed);
+            totals.insert(flow.id, total);
+            let Some(previous) = &previous else { continue };
+            let (Some(src), Some(dst)) = (flow.key.src_ip, flow.key.dst_ip) else { continue };
+            if is_external(src) || !is_external(dst) {
+                continue;
+            }
+            if self.config.ignore_destinations.iter().any(|c| c.contains(dst)) {
+                continue;
+            }
+            let delta = total.saturating_sub(previous.get(&flow.id).copied().unwrap_or(0));
+            if delta > 0 {
+                *deltas.entry(flow.key.src_device()).or_default().entry(dst).or_default() += delta;
+            }
+        }
+        self.flow_bytes = Some(totals);
+        deltas
+    }
+
+    /// Sample the flow counters at unix time `now` and return the alerts of
+    /// the devices with an anomaly
+    pub fn sample(&mut self, now: u64) -> Vec<Event> {
+        let mut deltas = self.deltas();
+        let index = now / self.config.bucket_secs;
+        let alpha = self.alpha();
+
+        for device in deltas.keys() {
+            self.profiles.entry(*device).or_insert_with(|| Profile {
+                baseline: Baseline::default(),
+                current: Period::new(index),
+                recent: VecDeque::new(),
+                leaking: false,
+                last_active: index,
+            });
+        }
+
+        let mut anomalies = Vec::new();
+        for (device, profile) in self.profiles.iter_mut() {
+            // Complete the elapsed periods, quiet ones included (the
+            // baseline forgets anything older anyway)
+            let elapsed = index.saturating_sub(profile.current.index);
+            let skipped = elapsed.saturating_sub(1).min(self.config.baseline_buckets as u64);
+            let mut completed = Vec::new();
+            if elapsed > 0 {
+                let first_quiet = index - skipped;
+                let current = std::mem::replace(&mut profile.current, Period::new(index));
+                completed.push(current);
+                completed.extend((first_quiet..index).map(Period::new));
+            }
+            for period in completed {
+                let learned = profile.baseline.buckets >= self.config.learning_buckets;
+                profile.recent.push_back(period);
+                while profile.recent.len() > self.config.leak_buckets {
+                    profile.recent.pop_front();
+                }
+
+                let threshold = profile.baseline.mean * self.config.leak_ratio;
+                let leaking = learned
+                    && profile.recent.len() == self.config.leak_buckets
+                    && profile.recent.iter().all(|p| p.bytes as f64 > threshold)
+                    && profile.recent.iter().map(|p| p.bytes).sum::<u64>() >= self.config.min_leak_bytes;
+                if leaking && !profile.leaking {
+                    anomalies.push((*device, Anomaly::Leak));
+                }
+                profile.leaking = leaking;
+
+                let period = profile.recent.back().expect("period just pushed");
+                if learned {
+                    let start = (period.index * self.config.bucket_secs) as i64;
+                    let bytes = period.bytes as f64;
+                    let score = profile.baseline.score(bytes);
+                    self.state.anomalies.record(*device, "exfiltration", start, bytes, profile.baseline.mean, score);
+                }
+                if !period.burst && !profile.leaking {
+                    profile.baseline.update(period.bytes as f64, alpha);
+                }
+            }
+
+            if let Some(destinations) = deltas.remove(device) {
+                profile.last_active = index;
+                for (ip, bytes) in destinations {
+                    profile.current.bytes += bytes;
+                    *profile.current.destinations.entry(ip).or_default() += bytes;
+                }
+            }
+
+            let learned = profile.baseline.buckets >= self.config.learning_buckets;
+            let threshold = profile.baseline.mean + self.config.burst_sigmas * profile.baseline.stddev();
+            let current = &mut profile.current;
+            if learned && !current.burst
+                && current.bytes as f64 > threshold
+                && current.bytes >= self.config.min_burst_bytes
+            {
+                current.burst = true;
+                anomalies.push((*device, Anomaly::Burst));
+            }
+        }
+
+        // Forget devices quiet for a whole baseline
+        let horizon = self.config.baseline_buckets as u64;
+        self.profiles.retain(|_, p| index - p.last_active <= horizon);
+
+        anomalies.into_iter().map(|(device, anomaly)| self.alert(device, anomaly)).collect()
+    }
+
+    /// Alert for a device with an anomaly
+    fn alert(&self, device: DeviceKey, anomaly: Anomaly) -> Event {
+        let mac = device.mac;
+        let profile = &self.profiles[&device];
+        let baseline = &profile.baseline;
+        let period = format_window(self.config.bucket_secs);
+
+        let periods: Vec<&Period> = match anomaly {
+            Anomaly::Burst => vec![&profile.current],
+            Anomaly::Leak => profile.recent.iter().collect(),
+        };
+        let observed: u64 = periods.iter().map(|p| p.bytes).sum();
+        let mut destinations: HashMap<Ipv4Addr, u64> = HashMap::new();
+        for period in &periods {
+            for (ip, bytes) in &period.destinations {
+                *destinations.entry(*ip).or_default() += bytes;
+            }
+        }
+        let mut destinations: Vec<_> = destinations.into_iter().collect();
+        destinations.sort_by_key(|d| std::cmp::Reverse(d.1));
+        destinations.truncate(self.config.breakdown_destinations);
+
+        let message = match anomaly {
+            Anomaly::Burst => format!(
+                "Device {} sent {} to external hosts within {} (baseline {} per {})",
+                mac, format_bytes(observed as f64), period, format_bytes(baseline.mean), period
+            ),
+            Anomaly::Leak => format!(
+                "Device {} sent {} to external hosts over {}, above {}x its baseline of {} every {}",
+                mac,
+                format_bytes(observed as f64),
+                format_window(self.config.bucket_secs * periods.len() as u64),
+                self.config.leak_ratio,
+                format_bytes(baseline.mean),
+                period
+            ),
+        };
+
+        let details = json!({
+            "kind": match anomaly { Anomaly::Burst => "burst", Anomaly::Leak => "leak" },
+            "bucket_secs": self.config.bucket_secs,
+            "buckets": periods.len(),
+            "observed_bytes": observed,
+            "baseline_bytes": baseline.mean,
+            "baseline_stddev": baseline.stddev(),
+            "destinations": destinations.iter().map(|(ip, bytes)| json!({
+                "ip": ip,
+                "bytes": bytes,
+                "share": if observed > 0 { *bytes as f64 / observed as f64 } else { 0.0 },
+            })).collect::<Vec<_>>(),
+        });
+
+        let ip = self.state.devices.get(&device).and_then(|d| d.ips.iter().next().map(|ip| *ip.key()));
+
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(device.site.to_string()),
+            sensor: None,
+            mac: Some(mac.to_string()),
+            ip,
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Sample until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Exfiltration detection enabled ({} periods, learning {})",
+            format_window(self.config.bucket_secs),
+            self.config.learning_buckets
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.sample_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.sample(Utc::now().timestamp() as u64) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Exfiltration detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{FlowKey, FlowState, MacAddr, SensorId, SiteId};
+
+    fn detector(extra: &str) -> (ExfiltrationDetector, Arc<AggregatorState>) {
+        let config: ExfiltrationConfig = toml::from_str(&format!(r#"
+sample_interval_secs = 10
+bucket_secs = 60
+learning_buckets = 3
+leak_buckets = 3
+min_leak_bytes = 10000
+{}
+"#, extra)).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        (ExfiltrationDetector::new(config, Arc::clone(&state)), state)
+    }
+
+    fn add_flow(state: &AggregatorState, dst_ip: [u8; 4]) -> FlowKey {
+        let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            dst_mac: MacAddr::new([0x00, 0x66, 0x77, 0x88, 0x99, 0xaa]),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+            dst_ip: Some(Ipv4Addr::from(dst_ip)),
+            src_port: Some(50000),
+            dst_port: Some(443),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        };
+        state.flows.insert(key.clone(), FlowState::new(key.clone(), Utc::now()));
+        key
+    }
+
+    /// Send `bytes` to `key` during each of `periods`
+    fn traffic(detector: &mut ExfiltrationDetector, state: &AggregatorState, key: &FlowKey, periods: std::ops::Range<u64>, bytes: u64) -> Vec<Event> {
+        let mut alerts = Vec::new();
+        for period in periods {
+            state.flows.get(key).unwrap().update(bytes, None, 0);
+            alerts.extend(detector.sample(period * 60 + 30));
+        }
+        alerts
+    }
+
+    #[test]
+    fn test_burst() {
+        let (mut detector, state) = detector("min_burst_bytes = 100000");
+        let key = add_flow(&state, [198, 51, 100, 7]);
+        let other = add_flow(&state, [203, 0, 113, 9]);
+        detector.sample(0);
+
+        assert!(traffic(&mut detector, &state, &key, 0..5, 1000).is_empty());
+
+        state.flows.get(&other).unwrap().update(300_000, None, 0);
+        let alerts = traffic(&mut detector, &state, &key, 5..6, 900_000);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, details, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Device 00:11:22:33:44:55 sent 1.2 MB to external hosts within 1m (baseline 1.0 KB per 1m)");
+        let details = details.as_ref().unwrap();
+        assert_eq!(details["kind"], "burst");
+        assert_eq!(details["destinations"][0]["ip"], "198.51.100.7");
+        assert_eq!(details["destinations"][1]["bytes"], 300_000);
+
+        // Once per period
+        assert!(traffic(&mut detector, &state, &key, 5..6, 900_000).is_empty());
+    }
+
+    #[test]
+    fn test_leak() {
+        let (mut detector, state) = detector("min_burst_bytes = 1000000000");
+        let key = add_flow(&state, [198, 51, 100, 7]);
+        detector.sample(0);
+
+        assert!(traffic(&mut detector, &state, &key, 0..5, 1000).is_empty());
+
+        // Three periods at five times the baseline, alerted as the third completes
+        assert!(traffic(&mut detector, &state, &key, 5..8, 5000).is_empty());
+        let alerts = traffic(&mut detector, &state, &key, 8..9, 5000);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Device 00:11:22:33:44:55 sent 15.0 KB to external hosts over 3m, above 2x its baseline of 1.1 KB every 1m");
+
+        // Not again while it goes on
+        assert!(traffic(&mut detector, &state, &key, 9..12, 5000).is_empty());
+    }
+}
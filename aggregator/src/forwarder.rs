@@ -0,0 +1,324 @@
+//! Bulk forwarding to Elasticsearch or Splunk HEC
+//!
+//! Events (and optionally flow records) are buffered as JSON documents and
+//! sent in batches, either through the Elasticsearch `_bulk` API or to a
+//! Splunk HTTP Event Collector. A batch is sent when it reaches
+//! `batch_size` documents or when `flush_interval_secs` elapses.
+
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::config::{local_hostname, ForwarderBackend, ForwarderConfig};
+use crate::events::{self, Event};
+use crate::metrics::metrics;
+use crate::notifications::retry_delay;
+use crate::state::AggregatorState;
+
+/// A document waiting to be forwarded
+#[derive(Debug, Clone)]
+struct Document {
+    timestamp: DateTime<Utc>,
+    index: Option<String>,
+    body: Value,
+}
+
+/// Batches events and flow records to a log platform
+pub struct Forwarder {
+    config: ForwarderConfig,
+    client: reqwest::Client,
+    state: Arc<AggregatorState>,
+    hostname: String,
+    buffer: Vec<Document>,
+    /// Unix time of the previous flow scan
+    last_flow_scan: u64,
+}
+
+impl Forwarder {
+    /// Create a forwarder, validating index patterns and credentials
+    pub fn new(config: ForwarderConfig, state: Arc<AggregatorState>) -> Result<Self> {
+        for pattern in [&config.index, &config.flow_index].into_iter().flatten() {
+            if StrftimeItems::new(pattern).any(|item| matches!(item, Item::Error)) {
+                anyhow::bail!("Invalid forwarder index pattern '{}'", pattern);
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        let auth = match config.backend {
+            ForwarderBackend::Elasticsearch => {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+                config.api_key.as_ref().map(|key| format!("ApiKey {}", key))
+            }
+            ForwarderBackend::SplunkHec => {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                config.token.as_ref().map(|token| format!("Splunk {}", token))
+            }
+        };
+        if let Some(auth) = auth {
+            let mut value = HeaderValue::from_str(&auth).with_context(|| "Invalid forwarder credentials")?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build forwarder HTTP client")?;
+
+        Ok(Self {
+            config,
+            client,
+            state,
+            hostname: local_hostname(),
+            buffer: Vec::new(),
+            last_flow_scan: Utc::now().timestamp() as u64,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self.config.backend {
+            ForwarderBackend::Elasticsearch => "elasticsearch",
+            ForwarderBackend::SplunkHec => "splunk_hec",
+        }
+    }
+
+    /// Expand an index pattern for a document timestamp
+    fn index_for(pattern: Option<&String>, timestamp: DateTime<Utc>) -> Option<String> {
+        pattern.map(|p| timestamp.format(p).to_string())
+    }
+
+    /// Buffer an event if it is one of the forwarded kinds
+    fn push_event(&mut self, event: &Event) {
+        if !self.config.events.contains(&event.kind()) {
+            return;
+        }
+
+        match serde_json::to_value(event.record()) {
+            Ok(body) => self.buffer.push(Document {
+                timestamp: event.timestamp(),
+                index: Self::index_for(self.config.index.as_ref(), event.timestamp()),
+                body,
+            }),
+            Err(e) => warn!("Failed to serialize event: {}", e),
+        }
+    }
+
+    /// Buffer flow records updated since the previous scan
+    fn collect_flows(&mut self) {
+        let since = self.last_flow_scan;
+        self.last_flow_scan = Utc::now().timestamp() as u64;
+
+        let pattern = self.config.flow_index.as_ref().or(self.config.index.as_ref());
+        for entry in self.state.flows.iter() {
+            if entry.value().last_seen.load(Ordering::Relaxed) < since {
+                continue;
+            }
+
+            let snapshot = entry.value().snapshot(entry.key().ethertype(), &self.state.services);
+            let Ok(mut body) = serde_json::to_value(&snapshot) else { continue };
+            body["type"] = json!("flow");
+            self.buffer.push(Document {
+                timestamp: snapshot.last_seen,
+                index: Self::index_for(pattern, snapshot.last_seen),
+                body,
+            });
+        }
+    }
+
+    /// Encode a batch as a request body for the configured backend
+    fn encode(&self, documents: &[Document]) -> String {
+        let mut body = String::new();
+
+        for doc in documents {
+            match self.config.backend {
+                ForwarderBackend::Elasticsearch => {
+                    let mut source = doc.body.clone();
+                    source["@timestamp"] = json!(doc.timestamp);
+                    body.push_str(&json!({ "index": { "_index": doc.index } }).to_string());
+                    body.push('\n');
+                    body.push_str(&source.to_string());
+                    body.push('\n');
+                }
+                ForwarderBackend::SplunkHec => {
+                    let mut envelope = json!({
+                        "time": doc.timestamp.timestamp_millis() as f64 / 1000.0,
+                        "host": self.hostname,
+                        "source": "netsentinel-aggregator",
+                        "sourcetype": self.config.sourcetype,
+                        "event": doc.body,
+                    });
+                    if let Some(index) = &doc.index {
+                        envelope["index"] = json!(index);
+                    }
+                    body.push_str(&envelope.to_string());
+                }
+            }
+        }
+
+        body
+    }
+
+    /// Send one encoded batch
+    async fn send(&self, body: String) -> Result<()> {
+        let request = match self.config.backend {
+            ForwarderBackend::Elasticsearch => {
+                let url = format!("{}/_bulk", self.config.url.trim_end_matches('/'));
+                let request = self.client.post(url);
+                match (&self.config.username, &self.config.password) {
+                    (Some(user), password) => request.basic_auth(user, password.as_ref()),
+                    _ => request,
+                }
+            }
+            ForwarderBackend::SplunkHec => self.client.post(&self.config.url),
+        };
+
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("{}: request failed", self.name()))?
+            .error_for_status()
+            .with_context(|| format!("{}: request rejected", self.name()))?;
+
+        if self.config.backend == ForwarderBackend::Elasticsearch {
+            // Item-level failures (mapping errors, ...) would fail again on retry
+            let result: Value = response.json().await.unwrap_or_default();
+            if result["errors"].as_bool() == Some(true) {
+                let failed = result["items"].as_array().map_or(0, |items| {
+                    items.iter().filter(|item| item["index"]["error"].is_object()).count()
+                });
+                warn!("elasticsearch: {} documents rejected by bulk API", failed);
+                metrics().notifications_failed.with_label_values(&[self.name()]).inc_by(failed as u64);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send everything buffered, batch by batch
+    #[instrument(name = "forwarder_flush", skip_all, fields(backend = self.name(), documents = self.buffer.len()))]
+    async fn flush(&mut self) {
+        let documents = std::mem::take(&mut self.buffer);
+
+        for batch in documents.chunks(self.config.batch_size) {
+            let policy = &self.config.retry;
+            for attempt in 1..=policy.max_attempts {
+                match self.send(self.encode(batch)).await {
+                    Ok(()) => {
+                        debug!("{}: forwarded {} documents", self.name(), batch.len());
+                        break;
+                    }
+                    Err(e) if attempt < policy.max_attempts => {
+                        let delay = retry_delay(policy, attempt);
+                        warn!("{:#} (attempt {}), retrying in {:?}", e, attempt, delay);
+                        tokio::time::sleep(delay).await;
+                    }
+                    Err(e) => {
+                        error!("{:#}; dropping {} documents after {} attempts", e, batch.len(), attempt);
+                        metrics().notifications_failed.with_label_values(&[self.name()]).inc_by(batch.len() as u64);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forward until shutdown, then flush what is left
+    pub async fn run(mut self, mut events: broadcast::Receiver<Event>, mut shutdown: broadcast::Receiver<()>) {
+        info!("Forwarding to {} at {}", self.name(), self.config.url);
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.flush_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    if self.config.flows {
+                        self.collect_flows();
+                    }
+                    self.flush().await;
+                }
+                event = events::recv(&mut events, self.name()) => {
+                    let Some(event) = event else { break };
+                    self.push_event(&event);
+                    if self.buffer.len() >= self.config.batch_size {
+                        self.flush().await;
+                    }
+                }
+            }
+        }
+
+        for event in events::drain(&mut events) {
+            self.push_event(&event);
+        }
+        if self.config.flows {
+            self.collect_flows();
+        }
+        self.flush().await;
+
+        debug!("{} forwarder stopped", self.name());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::TimeZone;
+
+    fn forwarder(config: &str) -> Forwarder {
+        let config: ForwarderConfig = toml::from_str(config).unwrap();
+        Forwarder::new(config, Arc::new(AggregatorState::new())).unwrap()
+    }
+
+    fn event() -> Event {
+        let timestamp = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        Event::new_device(DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55])), SensorId::default(), None, None, timestamp)
+    }
+
+    #[test]
+    fn test_elasticsearch_bulk_body() {
+        let mut forwarder = forwarder(r#"
+            backend = "elasticsearch"
+            url = "http://localhost:9200/"
+            index = "netsentinel-%Y.%m.%d"
+        "#);
+        forwarder.push_event(&event());
+
+        let body = forwarder.encode(&forwarder.buffer);
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"index":{"_index":"netsentinel-2024.03.01"}}"#);
+        let doc: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(doc["type"], "new_device");
+        assert_eq!(doc["@timestamp"], "2024-03-01T12:00:00Z");
+    }
+
+    #[test]
+    fn test_splunk_hec_body() {
+        let mut forwarder = forwarder(r#"
+            backend = "splunk_hec"
+            url = "https://splunk:8088/services/collector/event"
+            token = "secret"
+            events = ["alert"]
+        "#);
+        forwarder.push_event(&event());
+        assert!(forwarder.buffer.is_empty());
+
+        forwarder.config.events.push(crate::events::EventKind::NewDevice);
+        forwarder.push_event(&event());
+        let envelope: Value = serde_json::from_str(&forwarder.encode(&forwarder.buffer)).unwrap();
+        assert_eq!(envelope["time"], 1709294400.0);
+        assert_eq!(envelope["sourcetype"], "netsentinel");
+        assert_eq!(envelope["event"]["entities"][0]["mac"], "00:11:22:33:44:55");
+        assert!(envelope.get("index").is_none());
+    }
+}
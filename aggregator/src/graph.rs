@@ -0,0 +1,313 @@
+//! Network graph export
+//!
+//! Draws the devices that exchanged traffic during a time window as nodes
+//! and their flows, summed per source and destination device, as directed
+//! edges. Flows are those active during the window, with their total
+//! counters. The graph renders as GraphML (yEd, Gephi), DOT (Graphviz) or
+//! the `{nodes, links}` JSON D3's force layouts take.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::db::{Database, GraphDevice, TrafficLink};
+use crate::state::{MacAddr, Service};
+
+/// Services listed at most per edge, the busiest first
+const MAX_EDGE_SERVICES: usize = 10;
+
+/// Output format of the graph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    Graphml,
+    Dot,
+}
+
+impl GraphFormat {
+    /// Media type of the rendered graph
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            GraphFormat::Json => "application/json",
+            GraphFormat::Graphml => "application/graphml+xml",
+            GraphFormat::Dot => "text/vnd.graphviz",
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(GraphFormat::Json),
+            "graphml" => Ok(GraphFormat::Graphml),
+            "dot" => Ok(GraphFormat::Dot),
+            other => bail!("Unknown graph format '{}' (json, graphml or dot)", other),
+        }
+    }
+}
+
+/// A device
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    /// MAC address
+    pub id: String,
+    pub site: Option<String>,
+    pub ip: Option<String>,
+    pub gateway: bool,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Traffic from one device to another
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub flows: u64,
+    pub packets: u64,
+    pub bytes: u64,
+    /// `tcp/443` style names, the busiest first
+    pub services: Vec<String>,
+}
+
+/// Devices and the traffic between them during a time window
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkGraph {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub nodes: Vec<GraphNode>,
+    pub links: Vec<GraphEdge>,
+}
+
+impl NetworkGraph {
+    /// Graph of the flows active between `start` and `end`, on `site` or all,
+    /// of `tenant` when set
+    pub async fn load(
+        db: &Database,
+        site: Option<&str>,
+        tenant: Option<&str>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Self> {
+        let links = db.traffic_links(site, tenant, start, end).await?;
+        let mut macs: Vec<String> = links.iter()
+            .flat_map(|link| [link.src_mac.clone(), link.dst_mac.clone()])
+            .collect();
+        macs.sort();
+        macs.dedup();
+        let devices = db.graph_devices(&macs, tenant).await?;
+
+        Ok(Self::build(start, end, links, devices))
+    }
+
+    /// Sum `links` per device pair; traffic to broadcast and multicast
+    /// addresses is left out
+    pub fn build(start: DateTime<Utc>, end: DateTime<Utc>, links: Vec<TrafficLink>, devices: Vec<GraphDevice>) -> Self {
+        let unicast = |mac: &str| MacAddr::from_string(mac).is_some_and(|mac| mac.as_bytes()[0] & 0x01 == 0);
+
+        let mut edges: BTreeMap<(String, String), (GraphEdge, HashMap<String, u64>)> = BTreeMap::new();
+        for link in links.into_iter().filter(|link| unicast(&link.dst_mac) && link.src_mac != link.dst_mac) {
+            let (edge, services) = edges.entry((link.src_mac.clone(), link.dst_mac.clone())).or_insert_with(|| {
+                let edge = GraphEdge {
+                    source: link.src_mac.clone(),
+                    target: link.dst_mac.clone(),
+                    flows: 0,
+                    packets: 0,
+                    bytes: 0,
+                    services: Vec::new(),
+                };
+                (edge, HashMap::new())
+            });
+            edge.flows += link.flows;
+            edge.packets += link.packets;
+            edge.bytes += link.bytes;
+
+            let service = Service { ethertype: link.ethertype, protocol: link.ip_protocol, port: link.port };
+            *services.entry(service.name()).or_default() += link.bytes;
+        }
+
+        let devices: HashMap<String, GraphDevice> = devices.into_iter().map(|d| (d.mac_address.clone(), d)).collect();
+        let mut nodes: BTreeMap<String, GraphNode> = BTreeMap::new();
+        let mut links = Vec::with_capacity(edges.len());
+        for (mut edge, services) in edges.into_values() {
+            node(&mut nodes, &devices, &edge.source).bytes_sent += edge.bytes;
+            node(&mut nodes, &devices, &edge.target).bytes_received += edge.bytes;
+
+            let mut services: Vec<(String, u64)> = services.into_iter().collect();
+            services.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            edge.services = services.into_iter().take(MAX_EDGE_SERVICES).map(|(name, _)| name).collect();
+            links.push(edge);
+        }
+
+        Self { start, end, nodes: nodes.into_values().collect(), links }
+    }
+
+    /// Render the graph as `format`
+    pub fn render(&self, format: GraphFormat) -> Result<String> {
+        match format {
+            GraphFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            GraphFormat::Graphml => Ok(self.to_graphml()),
+            GraphFormat::Dot => Ok(self.to_dot()),
+        }
+    }
+
+    fn to_graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"site\" for=\"node\" attr.name=\"site\" attr.type=\"string\"/>\n",
+            "  <key id=\"ip\" for=\"node\" attr.name=\"ip\" attr.type=\"string\"/>\n",
+            "  <key id=\"gateway\" for=\"node\" attr.name=\"gateway\" attr.type=\"boolean\"/>\n",
+            "  <key id=\"bytes_sent\" for=\"node\" attr.name=\"bytes_sent\" attr.type=\"long\"/>\n",
+            "  <key id=\"bytes_received\" for=\"node\" attr.name=\"bytes_received\" attr.type=\"long\"/>\n",
+            "  <key id=\"flows\" for=\"edge\" attr.name=\"flows\" attr.type=\"long\"/>\n",
+            "  <key id=\"packets\" for=\"edge\" attr.name=\"packets\" attr.type=\"long\"/>\n",
+            "  <key id=\"bytes\" for=\"edge\" attr.name=\"bytes\" attr.type=\"long\"/>\n",
+            "  <key id=\"services\" for=\"edge\" attr.name=\"services\" attr.type=\"string\"/>\n",
+            "  <graph id=\"netsentinel\" edgedefault=\"directed\">\n",
+        ));
+
+        for node in &self.nodes {
+            let _ = writeln!(out, "    <node id=\"{}\">", xml_escape(&node.id));
+            if let Some(site) = &node.site {
+                let _ = writeln!(out, "      <data key=\"site\">{}</data>", xml_escape(site));
+            }
+            if let Some(ip) = &node.ip {
+                let _ = writeln!(out, "      <data key=\"ip\">{}</data>", xml_escape(ip));
+            }
+            let _ = writeln!(out, "      <data key=\"gateway\">{}</data>", node.gateway);
+            let _ = writeln!(out, "      <data key=\"bytes_sent\">{}</data>", node.bytes_sent);
+            let _ = writeln!(out, "      <data key=\"bytes_received\">{}</data>", node.bytes_received);
+            out.push_str("    </node>\n");
+        }
+        for edge in &self.links {
+            let _ = writeln!(out, "    <edge source=\"{}\" target=\"{}\">", xml_escape(&edge.source), xml_escape(&edge.target));
+            let _ = writeln!(out, "      <data key=\"flows\">{}</data>", edge.flows);
+            let _ = writeln!(out, "      <data key=\"packets\">{}</data>", edge.packets);
+            let _ = writeln!(out, "      <data key=\"bytes\">{}</data>", edge.bytes);
+            let _ = writeln!(out, "      <data key=\"services\">{}</data>", xml_escape(&edge.services.join(" ")));
+            out.push_str("    </edge>\n");
+        }
+
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph netsentinel {\n");
+
+        for node in &self.nodes {
+            let label = match &node.ip {
+                Some(ip) => format!("{}\\n{}", ip, node.id),
+                None => node.id.clone(),
+            };
+            let shape = if node.gateway { "diamond" } else { "box" };
+            let _ = writeln!(out, "  \"{}\" [label=\"{}\", shape={}];", dot_escape(&node.id), dot_escape(&label), shape);
+        }
+        for edge in &self.links {
+            let _ = writeln!(
+                out,
+                "  \"{}\" -> \"{}\" [label=\"{}\", bytes={}, flows={}];",
+                dot_escape(&edge.source),
+                dot_escape(&edge.target),
+                dot_escape(&edge.services.join(" ")),
+                edge.bytes,
+                edge.flows,
+            );
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Node of `mac`, added with its inventory details on first use
+fn node<'a>(nodes: &'a mut BTreeMap<String, GraphNode>, devices: &HashMap<String, GraphDevice>, mac: &str) -> &'a mut GraphNode {
+    nodes.entry(mac.to_string()).or_insert_with(|| {
+        let device = devices.get(mac);
+        GraphNode {
+            id: mac.to_string(),
+            site: device.map(|d| d.site.clone()),
+            ip: device.and_then(|d| d.ip_address.clone()),
+            gateway: device.and_then(|d| d.is_gateway).unwrap_or(false),
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    })
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Escape quotes in a DOT string, keeping `\n` line breaks of labels
+fn dot_escape(s: &str) -> String {
+    s.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "00:11:22:33:44:55";
+    const B: &str = "00:66:77:88:99:aa";
+
+    fn link(src: &str, dst: &str, port: u16, bytes: u64) -> TrafficLink {
+        TrafficLink {
+            src_mac: src.to_string(),
+            dst_mac: dst.to_string(),
+            ethertype: 0x0800,
+            ip_protocol: Some(6),
+            port: Some(port),
+            flows: 1,
+            packets: 10,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_build_and_render() {
+        let device = GraphDevice {
+            mac_address: B.to_string(),
+            site: "default".to_string(),
+            ip_address: Some("10.0.0.1".to_string()),
+            is_gateway: Some(true),
+        };
+        let now = Utc::now();
+        let graph = NetworkGraph::build(now, now, vec![
+            link(A, B, 443, 1000),
+            link(A, B, 22, 5000),
+            link(B, A, 443, 300),
+            link(A, "ff:ff:ff:ff:ff:ff", 67, 100),
+        ], vec![device]);
+
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.links.len(), 2);
+        assert_eq!(graph.links[0].bytes, 6000);
+        assert_eq!(graph.links[0].services, vec!["tcp/22", "tcp/443"]);
+        assert_eq!(graph.nodes[0].bytes_sent, 6000);
+        assert!(graph.nodes[1].gateway);
+
+        let json: serde_json::Value = serde_json::from_str(&graph.render(GraphFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["links"][0]["source"], A);
+
+        let graphml = graph.render(GraphFormat::Graphml).unwrap();
+        assert!(graphml.contains(&format!("<edge source=\"{}\" target=\"{}\">", A, B)));
+        assert!(graphml.contains("<data key=\"ip\">10.0.0.1</data>"));
+
+        let dot = graph.render(GraphFormat::Dot).unwrap();
+        assert!(dot.contains(&format!("\"{}\" [label=\"10.0.0.1\\n{}\", shape=diamond];", B, B)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"tcp/22 tcp/443\", bytes=6000, flows=2];", A, B)));
+    }
+}
@@ -0,0 +1,402 @@
+//! IPFIX export of aggregated flows
+//!
+//! Periodically sends the flows updated since the previous export to an
+//! IPFIX (RFC 7011) collector over UDP, so NetSentinel can feed existing
+//! NetFlow tooling. Each record carries the packets and bytes seen since the
+//! flow was last exported (delta counters). IP flows use one template, flows
+//! between MAC addresses only another; both templates are re-sent at the
+//! start of every export, as collectors listening on UDP expect.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::IpfixConfig;
+use crate::state::{AggregatorState, FlowKey};
+
+/// IPFIX protocol version
+const VERSION: u16 = 10;
+
+/// Message header length
+const HEADER_LEN: usize = 16;
+
+/// Set ID of template sets
+const TEMPLATE_SET_ID: u16 = 2;
+
+/// Template of flows with IPv4 addresses
+const IPV4_TEMPLATE_ID: u16 = 256;
+
+/// Template of layer-2 only flows
+const L2_TEMPLATE_ID: u16 = 257;
+
+/// Information elements (IANA ID, length) of the IPv4 template
+const IPV4_FIELDS: &[(u16, u16)] = &[
+    (8, 4),   // sourceIPv4Address
+    (12, 4),  // destinationIPv4Address
+    (7, 2),   // sourceTransportPort
+    (11, 2),  // destinationTransportPort
+    (4, 1),   // protocolIdentifier
+    (6, 2),   // tcpControlBits
+    (32, 2),  // icmpTypeCodeIPv4
+    (58, 2),  // vlanId
+    (56, 6),  // sourceMacAddress
+    (80, 6),  // destinationMacAddress
+    (1, 8),   // octetDeltaCount
+    (2, 8),   // packetDeltaCount
+    (150, 4), // flowStartSeconds
+    (151, 4), // flowEndSeconds
+];
+
+/// Information elements (IANA ID, length) of the layer-2 template
+const L2_FIELDS: &[(u16, u16)] = &[
+    (56, 6),  // sourceMacAddress
+    (80, 6),  // destinationMacAddress
+    (58, 2),  // vlanId
+    (256, 2), // ethernetType
+    (1, 8),   // octetDeltaCount
+    (2, 8),   // packetDeltaCount
+    (150, 4), // flowStartSeconds
+    (151, 4), // flowEndSeconds
+];
+
+/// A flow record as exported
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub key: FlowKey,
+    pub tcp_flags: u8,
+    pub octets: u64,
+    pub packets: u64,
+    /// Unix time of the first packet
+    pub start: u32,
+    /// Unix time of the last packet
+    pub end: u32,
+}
+
+impl FlowRecord {
+    fn template_id(&self) -> u16 {
+        match (self.key.src_ip, self.key.dst_ip) {
+            (Some(_), Some(_)) => IPV4_TEMPLATE_ID,
+            _ => L2_TEMPLATE_ID,
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        let fields = match self.template_id() {
+            IPV4_TEMPLATE_ID => IPV4_FIELDS,
+            _ => L2_FIELDS,
+        };
+        fields.iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    /// Append the record in the field order of its template
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let key = &self.key;
+        if let (Some(src), Some(dst)) = (key.src_ip, key.dst_ip) {
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&dst.octets());
+            buf.extend_from_slice(&key.src_port.unwrap_or(0).to_be_bytes());
+            buf.extend_from_slice(&key.dst_port.unwrap_or(0).to_be_bytes());
+            buf.push(key.protocol.unwrap_or(0));
+            buf.extend_from_slice(&u16::from(self.tcp_flags).to_be_bytes());
+            buf.extend_from_slice(&[key.icmp_type.unwrap_or(0), key.icmp_code.unwrap_or(0)]);
+            buf.extend_from_slice(&key.vlan_id.unwrap_or(0).to_be_bytes());
+            buf.extend_from_slice(key.src_mac.as_bytes());
+            buf.extend_from_slice(key.dst_mac.as_bytes());
+        } else {
+            buf.extend_from_slice(key.src_mac.as_bytes());
+            buf.extend_from_slice(key.dst_mac.as_bytes());
+            buf.extend_from_slice(&key.vlan_id.unwrap_or(0).to_be_bytes());
+            buf.extend_from_slice(&key.ethertype().to_be_bytes());
+        }
+        buf.extend_from_slice(&self.octets.to_be_bytes());
+        buf.extend_from_slice(&self.packets.to_be_bytes());
+        buf.extend_from_slice(&self.start.to_be_bytes());
+        buf.extend_from_slice(&self.end.to_be_bytes());
+    }
+}
+
+/// Exports flow records to an IPFIX collector
+pub struct IpfixExporter {
+    config: IpfixConfig,
+    state: Arc<AggregatorState>,
+    socket: Option<UdpSocket>,
+    /// Data records sent so far (the message sequence number)
+    sequence: u32,
+    /// Packet and byte counts of each flow at its last export
+    exported: HashMap<Uuid, (u64, u64)>,
+}
+
+impl IpfixExporter {
+    /// Create an exporter; the collector is resolved on the first export
+    pub fn new(config: IpfixConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            socket: None,
+            sequence: 0,
+            exported: HashMap::new(),
+        }
+    }
+
+    /// Records for the traffic seen since the previous call
+    fn collect(&mut self) -> Vec<FlowRecord> {
+        let mut records = Vec::new();
+        let mut exported = HashMap::with_capacity(self.state.flows.len());
+
+        for entry in self.state.flows.iter() {
+            let flow = entry.value();
+            let packets = flow.packet_count.load(Ordering::Relaxed);
+            let octets = flow.byte_count.load(Ordering::Relaxed);
+            let (last_packets, last_octets) = self.exported.get(&flow.id).copied().unwrap_or_default();
+            exported.insert(flow.id, (packets, octets));
+
+            if packets <= last_packets {
+                continue;
+            }
+            records.push(FlowRecord {
+                key: entry.key().clone(),
+                tcp_flags: flow.tcp_flags_seen.load(Ordering::Relaxed),
+                octets: octets.saturating_sub(last_octets),
+                packets: packets - last_packets,
+                start: flow.first_seen.timestamp() as u32,
+                end: flow.last_seen.load(Ordering::Relaxed) as u32,
+            });
+        }
+
+        // Flows gone from the state are forgotten
+        self.exported = exported;
+        records.sort_by_key(FlowRecord::template_id);
+        records
+    }
+
+    /// Export the flows updated since the previous export, returning the
+    /// number of records sent
+    pub async fn export(&mut self) -> Result<usize> {
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect((self.config.host.as_str(), self.config.port)).await
+                .with_context(|| format!("Failed to resolve {}:{}", self.config.host, self.config.port))?;
+            self.socket = Some(socket);
+        }
+
+        let records = self.collect();
+        let messages = encode_messages(
+            &records,
+            chrono::Utc::now().timestamp() as u32,
+            self.config.observation_domain_id,
+            self.config.max_message_size,
+            &mut self.sequence,
+        );
+
+        let socket = self.socket.as_ref().expect("socket is connected above");
+        for message in &messages {
+            socket.send(message).await
+                .with_context(|| format!("Failed to send to {}:{}", self.config.host, self.config.port))?;
+        }
+
+        debug!("IPFIX: exported {} flow records in {} messages", records.len(), messages.len());
+        Ok(records.len())
+    }
+
+    /// Export every `interval_secs` until shutdown, then once more
+    pub async fn run(mut self, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Exporting flows over IPFIX to {}:{} every {}s",
+            self.config.host, self.config.port, self.config.interval_secs
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    if let Err(e) = self.export().await {
+                        warn!("IPFIX export failed: {:#}", e);
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self.export().await {
+            warn!("Final IPFIX export failed: {:#}", e);
+        }
+        debug!("IPFIX exporter stopped");
+    }
+}
+
+/// Encode `records` (sorted by template) into messages of at most
+/// `max_size` bytes, the first one starting with the template set
+fn encode_messages(
+    records: &[FlowRecord],
+    export_time: u32,
+    domain_id: u32,
+    max_size: usize,
+    sequence: &mut u32,
+) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut message = vec![0; HEADER_LEN];
+    template_set(&mut message);
+
+    // Data set being filled: (template ID, offset of its header)
+    let mut set: Option<(u16, usize)> = None;
+    let mut count: u32 = 0;
+
+    for record in records {
+        let template = record.template_id();
+        let needed = record.encoded_len() + if set.is_some_and(|(t, _)| t == template) { 0 } else { 4 };
+
+        if message.len() + needed > max_size && count > 0 {
+            close_set(&mut message, set.take());
+            finish(&mut message, export_time, *sequence, domain_id);
+            messages.push(std::mem::replace(&mut message, vec![0; HEADER_LEN]));
+            *sequence = sequence.wrapping_add(count);
+            count = 0;
+        }
+
+        if set.is_none_or(|(t, _)| t != template) {
+            close_set(&mut message, set.take());
+            set = Some((template, message.len()));
+            message.extend_from_slice(&template.to_be_bytes());
+            message.extend_from_slice(&[0, 0]);
+        }
+        record.encode(&mut message);
+        count += 1;
+    }
+
+    close_set(&mut message, set);
+    finish(&mut message, export_time, *sequence, domain_id);
+    messages.push(message);
+    *sequence = sequence.wrapping_add(count);
+
+    messages
+}
+
+/// Append the template set describing both record layouts
+fn template_set(buf: &mut Vec<u8>) {
+    let start = buf.len();
+    buf.extend_from_slice(&TEMPLATE_SET_ID.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+
+    for (id, fields) in [(IPV4_TEMPLATE_ID, IPV4_FIELDS), (L2_TEMPLATE_ID, L2_FIELDS)] {
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (element, length) in fields {
+            buf.extend_from_slice(&element.to_be_bytes());
+            buf.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+
+    close_set(buf, Some((TEMPLATE_SET_ID, start)));
+}
+
+/// Write the length of the set starting at `set`'s offset
+fn close_set(buf: &mut [u8], set: Option<(u16, usize)>) {
+    if let Some((_, start)) = set {
+        let len = (buf.len() - start) as u16;
+        buf[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Fill in the message header
+fn finish(buf: &mut [u8], export_time: u32, sequence: u32, domain_id: u32) {
+    let len = buf.len() as u16;
+    buf[0..2].copy_from_slice(&VERSION.to_be_bytes());
+    buf[2..4].copy_from_slice(&len.to_be_bytes());
+    buf[4..8].copy_from_slice(&export_time.to_be_bytes());
+    buf[8..12].copy_from_slice(&sequence.to_be_bytes());
+    buf[12..16].copy_from_slice(&domain_id.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SensorId, SiteId};
+    use std::net::Ipv4Addr;
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([buf[offset], buf[offset + 1]])
+    }
+
+    fn record(ip: bool) -> FlowRecord {
+        FlowRecord {
+            key: FlowKey {
+                site: SiteId::default(),
+                sensor: SensorId::default(),
+                src_mac: MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55]),
+                dst_mac: MacAddr::new([0, 0x66, 0x77, 0x88, 0x99, 0xaa]),
+                src_ip: ip.then_some(Ipv4Addr::new(10, 0, 0, 1)),
+                dst_ip: ip.then_some(Ipv4Addr::new(10, 0, 0, 2)),
+                src_port: ip.then_some(51000),
+                dst_port: ip.then_some(443),
+                vlan_id: Some(20),
+                protocol: ip.then_some(6),
+                icmp_type: None,
+                icmp_code: None,
+                icmp_id: None,
+            },
+            tcp_flags: 0x12,
+            octets: 1500,
+            packets: 3,
+            start: 1_700_000_000,
+            end: 1_700_000_060,
+        }
+    }
+
+    #[test]
+    fn test_message_layout() {
+        let mut sequence = 5;
+        let messages = encode_messages(&[record(true), record(false)], 1_700_000_100, 7, 1400, &mut sequence);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(sequence, 7);
+
+        let msg = &messages[0];
+        assert_eq!(u16_at(msg, 0), VERSION);
+        assert_eq!(u16_at(msg, 2) as usize, msg.len());
+        assert_eq!(&msg[8..12], &5u32.to_be_bytes());
+        assert_eq!(&msg[12..16], &7u32.to_be_bytes());
+
+        // Template set: header, then two templates of 4 + 4 per field bytes
+        let template_len = 4 + 4 + 4 * IPV4_FIELDS.len() + 4 + 4 * L2_FIELDS.len();
+        assert_eq!(u16_at(msg, 16), TEMPLATE_SET_ID);
+        assert_eq!(u16_at(msg, 18) as usize, template_len);
+        assert_eq!(u16_at(msg, 20), IPV4_TEMPLATE_ID);
+        assert_eq!(u16_at(msg, 22) as usize, IPV4_FIELDS.len());
+
+        // IPv4 data set, then the layer-2 one
+        let ipv4 = 16 + template_len;
+        assert_eq!(u16_at(msg, ipv4), IPV4_TEMPLATE_ID);
+        assert_eq!(u16_at(msg, ipv4 + 2) as usize, 4 + record(true).encoded_len());
+        assert_eq!(&msg[ipv4 + 4..ipv4 + 8], &[10, 0, 0, 1]);
+        assert_eq!(u16_at(msg, ipv4 + 14), 443);
+
+        let l2 = ipv4 + 4 + record(true).encoded_len();
+        assert_eq!(u16_at(msg, l2), L2_TEMPLATE_ID);
+        assert_eq!(msg.len(), l2 + 4 + record(false).encoded_len());
+    }
+
+    #[test]
+    fn test_split_messages() {
+        let records = vec![record(true); 40];
+        let mut sequence = u32::MAX;
+        let messages = encode_messages(&records, 0, 0, 600, &mut sequence);
+
+        assert!(messages.len() > 1);
+        assert!(messages.iter().all(|m| m.len() <= 600 && u16_at(m, 2) as usize == m.len()));
+        // Only the first message carries the templates
+        assert_eq!(u16_at(&messages[0], 16), TEMPLATE_SET_ID);
+        assert_eq!(u16_at(&messages[1], 16), IPV4_TEMPLATE_ID);
+        // Sequence numbers count the records of the previous messages (wrapping)
+        let data_set = 16 + u16_at(&messages[0], 18) as usize;
+        let first = (u16_at(&messages[0], data_set + 2) as usize - 4) / record(true).encoded_len();
+        assert_eq!(&messages[1][8..12], &(first as u32 - 1).to_be_bytes());
+        assert_eq!(sequence, 39);
+    }
+}
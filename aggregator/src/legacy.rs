@@ -0,0 +1,270 @@
+//! Legacy and insecure protocol usage
+//!
+//! Every `scan_interval_secs`, the flows active since the previous scan are
+//! checked for protocols sending credentials or files in the clear, or long
+//! deprecated: telnet, FTP, SMB over the NetBIOS session service (SMBv1),
+//! SNMPv1 and v2c community strings, and NetBIOS name and datagram
+//! services. Each client using one with a server raises an alert, unless
+//! either end is allowed that protocol in `allow`.
+//!
+//! Protocols are recognized by their ports, SNMP also by the version the
+//! capture decodes: SNMPv3 is not flagged. A client, server and protocol
+//! alert again only after `cooldown_secs`.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use netsentinel_types::service::ports;
+
+use crate::bandwidth::format_window;
+use crate::config::{LegacyProtocol, LegacyProtocolsConfig};
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, FlowState, MacAddr, SiteId};
+
+/// IP protocol numbers
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+/// A client using a legacy protocol with a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct UsageKey {
+    site: SiteId,
+    protocol: LegacyProtocol,
+    client: MacAddr,
+    server: MacAddr,
+}
+
+/// What a flow of the usage tells about it
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    client_ip: Option<Ipv4Addr>,
+    server_ip: Option<Ipv4Addr>,
+    port: u16,
+}
+
+/// Legacy protocols by IP protocol and server ports
+const SERVICES: &[(u8, LegacyProtocol, &[u16])] = &[
+    (TCP, LegacyProtocol::Telnet, &[ports::TELNET]),
+    (TCP, LegacyProtocol::Ftp, &[ports::FTP]),
+    (TCP, LegacyProtocol::Smb1, &[ports::NETBIOS_SSN]),
+    (UDP, LegacyProtocol::Snmp, &[ports::SNMP, ports::SNMP_TRAP]),
+    (UDP, LegacyProtocol::Netbios, &[ports::NETBIOS_NS, ports::NETBIOS_DGM]),
+];
+
+/// Legacy protocol of a flow, and whether its destination is the server
+fn classify(flow: &FlowState) -> Option<(LegacyProtocol, bool)> {
+    let key = &flow.key;
+    let (_, protocol, ports) = SERVICES.iter().find(|(ip_protocol, _, ports)| {
+        key.protocol == Some(*ip_protocol) && [key.dst_port, key.src_port].iter().flatten().any(|port| ports.contains(port))
+    })?;
+    // SNMPv3 authenticates without community string
+    if *protocol == LegacyProtocol::Snmp && !flow.is_snmp_community() {
+        return None;
+    }
+    Some((*protocol, key.dst_port.is_some_and(|port| ports.contains(&port))))
+}
+
+/// Detects devices using legacy, insecure protocols
+pub struct LegacyProtocolMonitor {
+    config: LegacyProtocolsConfig,
+    state: Arc<AggregatorState>,
+    /// Start of the previous scan
+    last_scan: Option<DateTime<Utc>>,
+    /// Last alert of each client, server and protocol
+    alerted: HashMap<UsageKey, DateTime<Utc>>,
+}
+
+impl LegacyProtocolMonitor {
+    pub fn new(config: LegacyProtocolsConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            last_scan: None,
+            alerted: HashMap::new(),
+        }
+    }
+
+    /// Whether either end of `usage` is allowed `protocol`
+    fn allowed(&self, protocol: LegacyProtocol, usage: &Usage) -> bool {
+        let Some(allow) = self.config.allow.get(&protocol) else {
+            return false;
+        };
+        [usage.client_ip, usage.server_ip].into_iter().flatten().any(|ip| allow.iter().any(|c| c.contains(ip)))
+    }
+
+    /// Check the flows active since the previous scan at `now` and return
+    /// the alerts of the legacy protocols used
+    pub fn scan(&mut self, now: DateTime<Utc>) -> Vec<Event> {
+        let since = self.last_scan.replace(now);
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        self.alerted.retain(|_, at| *at + cooldown > now);
+
+        // Both directions of a connection make one usage
+        let mut found: HashMap<UsageKey, Usage> = HashMap::new();
+        for flow in self.state.flows.iter() {
+            if since.is_some_and(|since| (flow.last_seen.load(Ordering::Relaxed) as i64) < since.timestamp()) {
+                continue;
+            }
+            let Some((protocol, to_server)) = classify(&flow) else { continue };
+            if !self.config.protocols.contains(&protocol) {
+                continue;
+            }
+            let key = &flow.key;
+            let (client, server, client_ip, server_ip, port) = if to_server {
+                (key.src_mac, key.dst_mac, key.src_ip, key.dst_ip, key.dst_port)
+            } else {
+                (key.dst_mac, key.src_mac, key.dst_ip, key.src_ip, key.src_port)
+            };
+            let usage = Usage { client_ip, server_ip, port: port.unwrap_or_default() };
+            let key = UsageKey { site: key.site, protocol, client, server };
+            if !self.alerted.contains_key(&key) && !self.allowed(protocol, &usage) {
+                found.entry(key).or_insert(usage);
+            }
+        }
+
+        let mut found: Vec<(UsageKey, Usage)> = found.into_iter().collect();
+        found.sort_by(|(a, _), (b, _)| {
+            (a.site.as_str(), a.protocol.as_str(), a.client.as_bytes(), a.server.as_bytes())
+                .cmp(&(b.site.as_str(), b.protocol.as_str(), b.client.as_bytes(), b.server.as_bytes()))
+        });
+
+        found.into_iter()
+            .map(|(key, usage)| {
+                self.alerted.insert(key, now);
+                self.alert(&key, &usage, now)
+            })
+            .collect()
+    }
+
+    /// Alert for a client using a legacy protocol with a server
+    fn alert(&self, key: &UsageKey, usage: &Usage, now: DateTime<Utc>) -> Event {
+        let server = match usage.server_ip {
+            Some(ip) => format!("{} ({})", key.server, ip),
+            None => key.server.to_string(),
+        };
+        let message = format!("{} uses legacy protocol {} with {}", key.client, key.protocol.as_str(), server);
+
+        let details = json!({
+            "protocol": key.protocol,
+            "port": usage.port,
+            "client_ip": usage.client_ip,
+            "server_mac": key.server.to_string(),
+            "server_ip": usage.server_ip,
+        });
+
+        Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(key.site.to_string()),
+            sensor: None,
+            mac: Some(key.client.to_string()),
+            ip: usage.client_ip,
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Scan until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        let protocols: Vec<&str> = self.config.protocols.iter().map(LegacyProtocol::as_str).collect();
+        info!(
+            "Legacy protocol detection enabled (scans every {}, {})",
+            format_window(self.config.scan_interval_secs),
+            protocols.join(", ")
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.scan(Utc::now()) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Legacy protocol detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CapturedFrame, SensorFrame};
+    use netsentinel_types::SnmpInfo;
+
+    /// A frame from `src` port `src_port` to `dst` port `dst_port` at `at`
+    #[allow(clippy::too_many_arguments)]
+    fn send(
+        state: &AggregatorState,
+        src: MacAddr,
+        src_ip: Ipv4Addr,
+        src_port: u16,
+        dst: MacAddr,
+        dst_ip: Ipv4Addr,
+        dst_port: u16,
+        protocol: u8,
+        snmp: Option<SnmpInfo>,
+        at: DateTime<Utc>,
+    ) {
+        let mut frame = CapturedFrame::new("eth0", src, dst, 0x0800, 80);
+        (frame.src_ip, frame.dst_ip) = (Some(src_ip), Some(dst_ip));
+        (frame.src_port, frame.dst_port) = (Some(src_port), Some(dst_port));
+        frame.ip_protocol = Some(protocol);
+        frame.snmp = snmp;
+        state.process_frame_at(&SensorFrame { site: SiteId::default(), sensor: Default::default(), frame }, at);
+    }
+
+    #[test]
+    fn test_legacy_protocols() {
+        let config: LegacyProtocolsConfig = toml::from_str(r#"
+            [allow]
+            telnet = ["10.0.9.0/24"]
+        "#).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut monitor = LegacyProtocolMonitor::new(config, Arc::clone(&state));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+        let admin = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let (admin_ip, printer_ip, switch_ip) = (Ipv4Addr::new(10, 0, 1, 5), Ipv4Addr::new(10, 0, 2, 7), Ipv4Addr::new(10, 0, 9, 2));
+        let printer = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x07]);
+        let switch = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x02]);
+
+        // Telnet to the printer and its answer, telnet to an allowed switch
+        send(&state, admin, admin_ip, 51000, printer, printer_ip, ports::TELNET, TCP, None, at);
+        send(&state, printer, printer_ip, ports::TELNET, admin, admin_ip, 51000, TCP, None, at);
+        send(&state, admin, admin_ip, 51001, switch, switch_ip, ports::TELNET, TCP, None, at);
+        // SNMPv3 polling, then v2c traps
+        let v3 = SnmpInfo { version: SnmpInfo::V3, pdu: None };
+        send(&state, admin, admin_ip, 51002, switch, switch_ip, ports::SNMP, UDP, Some(v3), at);
+        let trap = SnmpInfo { version: SnmpInfo::V2C, pdu: Some(7) };
+        send(&state, printer, printer_ip, 51003, admin, admin_ip, ports::SNMP_TRAP, UDP, Some(trap), at);
+
+        let alerts = monitor.scan(at);
+        let messages: Vec<&str> = alerts.iter().map(|alert| {
+            let Event::Alert { message, .. } = alert else { panic!() };
+            message.as_str()
+        }).collect();
+        assert_eq!(messages, vec![
+            "00:11:22:33:44:07 uses legacy protocol snmp with 00:11:22:33:44:55 (10.0.1.5)",
+            "00:11:22:33:44:55 uses legacy protocol telnet with 00:11:22:33:44:07 (10.0.2.7)",
+        ]);
+
+        // Once per cooldown
+        let later = at + chrono::Duration::minutes(5);
+        send(&state, admin, admin_ip, 51000, printer, printer_ip, ports::TELNET, TCP, None, later);
+        assert!(monitor.scan(later).is_empty());
+    }
+}
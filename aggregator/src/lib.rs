@@ -2,10 +2,39 @@
 //!
 //! Aggregates captured network data and persists to PostgreSQL/TimescaleDB.
 
+pub mod admin;
+pub mod alerts;
+pub mod api;
+pub mod auth_servers;
+pub mod bandwidth;
+pub mod beaconing;
+pub mod change_report;
+pub mod clock_drift;
 pub mod config;
+pub mod connections;
 pub mod db;
+pub mod enrich;
+pub mod dns;
+pub mod events;
+pub mod exfiltration;
+pub mod forwarder;
+pub mod graph;
+pub mod ipfix;
+pub mod legacy;
+pub mod logging;
+pub mod metrics;
+pub mod netbox;
+pub mod new_devices;
+pub mod notifications;
+pub mod ntp;
+pub mod ot;
+pub mod plugins;
 pub mod pipeline;
+pub mod rules;
+pub mod scanners;
+pub mod servicenow;
 pub mod state;
+pub mod telemetry;
 
 pub use config::Config;
 pub use db::Database;
@@ -0,0 +1,85 @@
+//! Runtime log filter
+//!
+//! The tracing filter sits behind a reload layer so that a running instance
+//! can turn on `debug` for one module (`netsentinel_aggregator::pipeline=debug`)
+//! through the admin socket, then go back to the configured filter, without
+//! a restart.
+
+use anyhow::{Context, Result};
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Handle replacing the log filter of the running process
+#[derive(Clone)]
+pub struct LogFilter {
+    /// Directives the process started with
+    base: String,
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// Wrap `filter` in a layer whose directives can be changed at runtime
+    pub fn new(filter: EnvFilter) -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let base = filter.to_string();
+        let (layer, handle) = reload::Layer::new(filter);
+        (layer, Self { base, handle })
+    }
+
+    /// Directives currently applied
+    pub fn current(&self) -> Result<String> {
+        self.handle.with_current(|filter| filter.to_string()).context("The log filter is gone")
+    }
+
+    /// Apply `directives` on top of the startup filter, or restore it on `reset`
+    ///
+    /// A directive replaces the startup one for the same target and adds to
+    /// the others. Returns the directives now applied.
+    pub fn set(&self, directives: &str) -> Result<String> {
+        let merged = match directives.trim() {
+            "" | "reset" => self.base.clone(),
+            directives => merge_directives(&self.base, directives),
+        };
+        let filter = EnvFilter::try_new(&merged).with_context(|| format!("Invalid log filter '{}'", directives))?;
+        self.handle.reload(filter).context("Failed to replace the log filter")?;
+
+        let current = self.current()?;
+        info!("Log filter set to {}", current);
+        Ok(current)
+    }
+}
+
+/// Target of a directive, empty for a bare level
+fn target(directive: &str) -> &str {
+    match directive.rsplit_once('=') {
+        Some((target, _)) => target,
+        None if directive.parse::<tracing::Level>().is_ok() || directive == "off" => "",
+        None => directive,
+    }
+}
+
+/// `base` with `overrides` replacing its directives for the same targets
+fn merge_directives(base: &str, overrides: &str) -> String {
+    let overrides: Vec<&str> = overrides.split(',').map(str::trim).filter(|d| !d.is_empty()).collect();
+    base.split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty() && !overrides.iter().any(|o| target(o) == target(d)))
+        .chain(overrides.iter().copied())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_directives() {
+        let base = "netsentinel_aggregator=info,sqlx=warn";
+        assert_eq!(
+            merge_directives(base, "netsentinel_aggregator::pipeline=debug"),
+            "netsentinel_aggregator=info,sqlx=warn,netsentinel_aggregator::pipeline=debug"
+        );
+        assert_eq!(merge_directives(base, "sqlx=debug, warn"), "netsentinel_aggregator=info,sqlx=debug,warn");
+        assert_eq!(merge_directives("info,redis=warn", "debug"), "redis=warn,debug");
+    }
+}
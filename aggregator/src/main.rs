@@ -3,14 +3,24 @@
 //! Consumes captured frames from Redis, aggregates them, and persists to PostgreSQL.
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tracing::{error, info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
+use netsentinel_aggregator::admin::{self, AdminRequest};
+use netsentinel_aggregator::change_report::ChangeReporter;
 use netsentinel_aggregator::config::Config;
-use netsentinel_aggregator::pipeline::Pipeline;
+use netsentinel_aggregator::db::Database;
+use netsentinel_aggregator::events;
+use netsentinel_aggregator::graph::{GraphFormat, NetworkGraph};
+use netsentinel_aggregator::logging::LogFilter;
+use netsentinel_aggregator::netbox::NetBoxExporter;
+use netsentinel_aggregator::servicenow::ServiceNowExporter;
+use netsentinel_aggregator::telemetry::Telemetry;
+use netsentinel_aggregator::pipeline::{InstanceRegistry, Pipeline, Replayer, ReplaySource, StreamId, PERSISTED_TABLES};
 
 /// NetSentinel Aggregator Service
 #[derive(Parser, Debug)]
@@ -20,26 +30,181 @@ use netsentinel_aggregator::pipeline::Pipeline;
 #[command(about = "Aggregates network data and persists to database", long_about = None)]
 struct Args {
     /// Path to configuration file
-    #[arg(short, long, default_value = "/opt/netsentinel/config/aggregator.toml")]
+    #[arg(short, long, global = true, default_value = "/opt/netsentinel/config/aggregator.toml")]
     config: PathBuf,
 
     /// Run in debug mode (verbose logging)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     debug: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-aggregate a range of the frame stream (or a dump) into a separate schema
+    Replay {
+        /// First stream entry ID to replay (inclusive, `-` for the oldest)
+        #[arg(long, default_value = "-")]
+        from: String,
+
+        /// Last stream entry ID to replay (inclusive, `+` for the newest)
+        #[arg(long, default_value = "+")]
+        to: String,
+
+        /// Start after the saved checkpoint of this consumer instead of --from
+        #[arg(long, value_name = "CONSUMER", conflicts_with = "from")]
+        from_checkpoint: Option<String>,
+
+        /// Replay a newline-delimited JSON frame dump instead of the stream
+        #[arg(long, conflicts_with_all = ["from", "to", "from_checkpoint"])]
+        dump: Option<PathBuf>,
+
+        /// Database schema receiving the rebuilt state
+        #[arg(long, default_value = "replay")]
+        schema: String,
+    },
+
+    /// List registered aggregator instances and stream consumers
+    Instances {
+        /// Remove dead instances (those without pending entries unless --force)
+        #[arg(long)]
+        prune: bool,
+
+        /// With --prune, also remove dead consumers that still have pending entries
+        #[arg(long, requires = "prune")]
+        force: bool,
+    },
+
+    /// Push devices, IPs and VLANs to NetBox once
+    NetboxSync {
+        /// Log the changes without writing to NetBox
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Export devices to the ServiceNow CMDB once
+    ServicenowExport,
+
+    /// Print the inventory changes over the last hours, without storing them
+    ChangeReport {
+        /// Length of the period reported on, up to now
+        #[arg(long, default_value_t = 24)]
+        hours: i64,
+    },
+
+    /// Print the device graph of the last hours as JSON, GraphML or DOT
+    Graph {
+        /// Output format: json (D3), graphml or dot
+        #[arg(long, default_value = "json")]
+        format: GraphFormat,
+
+        /// Length of the window drawn, up to now
+        #[arg(long, default_value_t = 24)]
+        hours: i64,
+
+        /// Only draw this site
+        #[arg(long)]
+        site: Option<String>,
+    },
+
+    /// Make the running instance persist its whole state now
+    ForcePersist,
+
+    /// Make the running instance persist, then evict its flows from memory
+    FlushFlows,
+
+    /// Print the running instance's state sizes and counters
+    ShowStats,
+
+    /// Forget a MAC address on every site, in the running instance and the database
+    PurgeDevice {
+        /// MAC address to purge
+        mac: String,
+    },
+
+    /// Print or change the running instance's log filter
+    LogLevel {
+        /// Directives added to the configured filter (`netsentinel_aggregator::pipeline=debug`),
+        /// or `reset` to restore it
+        filter: Option<String>,
+    },
+
+    /// Print a complete default configuration, with every field documented
+    PrintDefaultConfig,
+
+    /// Print the JSON Schema of published events
+    PrintEventSchema,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    // Print the configuration template and exit if requested (no config needed)
+    if let Some(Command::PrintDefaultConfig) = args.command {
+        print!("{}", Config::template());
+        return Ok(());
+    }
+    if let Some(Command::PrintEventSchema) = args.command {
+        println!("{}", events::record_schema());
+        return Ok(());
+    }
+
     // Load configuration
     let config = Config::from_file(&args.config)
         .with_context(|| format!("Failed to load config from {:?}", args.config))?;
 
     config.validate()?;
 
-    // Setup logging
-    setup_logging(&config, args.debug)?;
+    // Setup logging (kept alive so spans and metrics are flushed on exit)
+    let (_telemetry, log_filter) = setup_logging(&config, args.debug)?;
+
+    match args.command {
+        Some(Command::Replay { from, to, from_checkpoint, dump, schema }) => {
+            let source = match (dump, from_checkpoint) {
+                (Some(path), _) => ReplaySource::Dump(path),
+                (None, Some(consumer)) => {
+                    let from = checkpoint_start(&config, &consumer).await?;
+                    ReplaySource::Stream { from, to }
+                }
+                (None, None) => ReplaySource::Stream { from, to },
+            };
+            return run_replay(config, source, &schema).await;
+        }
+        Some(Command::Instances { prune, force }) => {
+            return run_instances(&config, prune, force).await;
+        }
+        Some(Command::NetboxSync { dry_run }) => {
+            return run_netbox_sync(config, dry_run).await;
+        }
+        Some(Command::ServicenowExport) => {
+            return run_servicenow_export(config).await;
+        }
+        Some(Command::ChangeReport { hours }) => {
+            return run_change_report(config, hours).await;
+        }
+        Some(Command::Graph { format, hours, site }) => {
+            return run_graph(config, format, hours, site).await;
+        }
+        Some(Command::ForcePersist) => {
+            return run_admin(&config, AdminRequest::ForcePersist).await;
+        }
+        Some(Command::FlushFlows) => {
+            return run_admin(&config, AdminRequest::FlushFlows).await;
+        }
+        Some(Command::ShowStats) => {
+            return run_admin(&config, AdminRequest::ShowStats).await;
+        }
+        Some(Command::PurgeDevice { mac }) => {
+            return run_admin(&config, AdminRequest::PurgeDevice { mac }).await;
+        }
+        Some(Command::LogLevel { filter }) => {
+            return run_admin(&config, AdminRequest::LogLevel { filter }).await;
+        }
+        Some(Command::PrintDefaultConfig) | Some(Command::PrintEventSchema) | None => {}
+    }
 
     info!("NetSentinel Aggregator starting...");
     info!("Redis: {}", config.redis.url);
@@ -50,12 +215,18 @@ async fn main() -> Result<()> {
         Pipeline::new(config)
             .await
             .with_context(|| "Failed to initialize pipeline")?
+            .with_log_filter(log_filter)
     );
 
-    // Setup signal handling
+    // Setup signal handling (a second signal aborts the drain)
     let pipeline_shutdown = Arc::clone(&pipeline);
+    let signalled = AtomicBool::new(false);
     ctrlc::set_handler(move || {
-        info!("Received shutdown signal");
+        if signalled.swap(true, Ordering::SeqCst) {
+            warn!("Received second shutdown signal, exiting immediately");
+            std::process::exit(130);
+        }
+        info!("Received shutdown signal, draining");
         pipeline_shutdown.shutdown();
     })
     .context("Failed to set Ctrl+C handler")?;
@@ -67,8 +238,139 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Setup logging based on configuration
-fn setup_logging(config: &Config, debug: bool) -> Result<()> {
+/// Rebuild state from historical frames into `schema`
+async fn run_replay(config: Config, source: ReplaySource, schema: &str) -> Result<()> {
+    info!("NetSentinel Aggregator replay into schema '{}'", schema);
+
+    Database::connect(&config.database)
+        .await?
+        .prepare_schema(schema, PERSISTED_TABLES)
+        .await?;
+
+    let db = Arc::new(Database::connect_with_schema(&config.database, schema).await?);
+    let replayer = Replayer::new(config.redis.clone(), config.aggregation.clone(), db)
+        .with_services(config.services.clone())
+        .with_tenants(config.site_tenants());
+    let stats = replayer.run(source).await?;
+
+    if let Some(last) = stats.last_entry_id {
+        info!("Last replayed entry: {}", last);
+    }
+    Ok(())
+}
+
+/// First stream entry after the saved checkpoint of `consumer`
+async fn checkpoint_start(config: &Config, consumer: &str) -> Result<String> {
+    let db = Database::connect(&config.database).await?;
+    let checkpoint = db
+        .stream_checkpoints(&config.redis.stream_name, &config.redis.consumer_group)
+        .await?
+        .into_iter()
+        .find(|c| c.consumer == consumer)
+        .with_context(|| format!("No checkpoint saved for consumer '{}'", consumer))?;
+
+    let id = StreamId::parse(&checkpoint.last_id)
+        .with_context(|| format!("Invalid checkpoint '{}' for consumer '{}'", checkpoint.last_id, consumer))?;
+    info!("Replaying after checkpoint {} of consumer '{}', saved at {}", id, consumer, checkpoint.updated_at);
+    Ok(id.next().to_string())
+}
+
+/// Synchronize the persisted inventory to NetBox once
+async fn run_netbox_sync(config: Config, dry_run: bool) -> Result<()> {
+    let netbox = config.netbox.clone().context("No [netbox] section in the configuration")?;
+    let db = Arc::new(Database::connect(&config.database).await?);
+
+    let report = NetBoxExporter::new(netbox, db, config.aggregation.inactivity_timeout)?
+        .with_dry_run(dry_run)
+        .sync()
+        .await?;
+
+    if report.failed > 0 {
+        anyhow::bail!("{} objects failed to synchronize", report.failed);
+    }
+    Ok(())
+}
+
+/// Export the persisted inventory to ServiceNow once
+async fn run_servicenow_export(config: Config) -> Result<()> {
+    let servicenow = config.servicenow.clone().context("No [servicenow] section in the configuration")?;
+    let db = Arc::new(Database::connect(&config.database).await?);
+
+    ServiceNowExporter::new(servicenow, db, config.aggregation.inactivity_timeout)?
+        .export()
+        .await?;
+    Ok(())
+}
+
+/// Print the changes over the last `hours` hours as JSON
+async fn run_change_report(config: Config, hours: i64) -> Result<()> {
+    let change_report = config.change_report.clone().context("No [change_report] section in the configuration")?;
+    let db = Arc::new(Database::connect(&config.database).await?);
+
+    let now = chrono::Utc::now();
+    let report = ChangeReporter::new(change_report, db, config.aggregation.inactivity_timeout)
+        .generate(now - chrono::Duration::hours(hours), now)
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// Print the device graph of the last `hours` hours
+async fn run_graph(config: Config, format: GraphFormat, hours: i64, site: Option<String>) -> Result<()> {
+    let db = Database::connect(&config.database).await?;
+
+    let now = chrono::Utc::now();
+    let graph = NetworkGraph::load(&db, site.as_deref(), None, now - chrono::Duration::hours(hours), now).await?;
+    print!("{}", graph.render(format)?);
+    Ok(())
+}
+
+/// Send `request` to the running instance's admin socket and print the outcome
+async fn run_admin(config: &Config, request: AdminRequest) -> Result<()> {
+    let outcome = admin::send(&config.admin.socket, &request).await?;
+    println!("{}", serde_json::to_string_pretty(&outcome)?);
+    Ok(())
+}
+
+/// Print the instance registry, optionally pruning dead consumers first
+async fn run_instances(config: &Config, prune: bool, force: bool) -> Result<()> {
+    let mut conn = InstanceRegistry::connect(&config.redis).await?;
+
+    if prune {
+        let report = InstanceRegistry::prune(&mut conn, &config.redis, None, force).await?;
+        for name in &report.pruned {
+            println!("pruned {}", name);
+        }
+    }
+
+    println!(
+        "{:<32} {:<6} {:<20} {:>8} {:>10} {:>12}",
+        "CONSUMER", "STATE", "HOST", "PID", "PENDING", "IDLE (s)"
+    );
+    for status in InstanceRegistry::list(&mut conn, &config.redis).await? {
+        let state = match (&status.info, status.alive) {
+            (None, _) => "unreg",
+            (Some(_), true) => "alive",
+            (Some(_), false) => "dead",
+        };
+        let host = status.info.as_ref().map(|i| i.hostname.as_str()).unwrap_or("-");
+        let pid = status.info.as_ref().map(|i| i.pid.to_string()).unwrap_or_else(|| "-".to_string());
+        let idle = status.idle_ms.map(|ms| (ms / 1000).to_string()).unwrap_or_else(|| "-".to_string());
+
+        println!(
+            "{:<32} {:<6} {:<20} {:>8} {:>10} {:>12}",
+            status.consumer_name, state, host, pid, status.pending, idle
+        );
+    }
+
+    Ok(())
+}
+
+/// Setup logging and the optional OpenTelemetry export
+///
+/// Returns the handle changing the log filter at runtime along with the
+/// telemetry providers.
+fn setup_logging(config: &Config, debug: bool) -> Result<(Telemetry, LogFilter)> {
     let level = if debug {
         Level::DEBUG
     } else {
@@ -87,7 +389,11 @@ fn setup_logging(config: &Config, debug: bool) -> Result<()> {
         .add_directive("sqlx=warn".parse().unwrap())
         .add_directive("redis=warn".parse().unwrap());
 
-    let subscriber = tracing_subscriber::registry().with(filter);
+    let (filter, log_filter) = LogFilter::new(filter);
+    let telemetry = Telemetry::new(&config.telemetry)?;
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(telemetry.tracer().map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
 
     if config.logging.format == "json" {
         subscriber.with(fmt::layer().json()).init();
@@ -95,5 +401,5 @@ fn setup_logging(config: &Config, debug: bool) -> Result<()> {
         subscriber.with(fmt::layer().with_target(true)).init();
     }
 
-    Ok(())
+    Ok((telemetry, log_filter))
 }
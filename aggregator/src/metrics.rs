@@ -0,0 +1,327 @@
+//! Prometheus metrics
+//!
+//! Counters are updated by the consumer and persister as they work; state
+//! sizes and the inventory (devices per site and VLAN, active flows, bytes
+//! per protocol) are sampled from memory when the endpoint is scraped.
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use chrono::Utc;
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LazyLock};
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::config::MetricsConfig;
+use crate::state::AggregatorState;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Get the process-wide metrics
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Aggregator metrics
+pub struct Metrics {
+    registry: Registry,
+    /// Stream entries applied to the state
+    pub frames_consumed: IntCounter,
+    /// Stream entries that could not be parsed as frames
+    pub frames_invalid: IntCounter,
+    /// Entries in the stream not yet delivered to the consumer group
+    pub stream_lag: IntGauge,
+    /// Entries delivered to the consumer group but not acknowledged
+    pub stream_pending: IntGauge,
+    /// Entries removed from the stream by the retention task
+    pub stream_trimmed: IntCounter,
+    /// Entries whose acknowledgement failed persist cycles held back
+    pub ack_deferred: IntGauge,
+    /// Entries acknowledged after failing `ack_max_failed_cycles` cycles
+    pub ack_abandoned: IntCounter,
+    /// In-memory state entries by kind
+    pub state_entries: IntGaugeVec,
+    /// Duration of persist cycles
+    pub persist_duration: Histogram,
+    /// Failed database writes (single rows or whole persist steps)
+    pub db_errors: IntCounter,
+    /// Device IDs the persister had to resolve in the database
+    pub device_id_misses: IntCounter,
+    /// Events dropped by a notification sink after exhausting retries
+    pub notifications_failed: IntCounterVec,
+    /// Frames an enricher failed on
+    pub enrich_errors: IntCounterVec,
+    /// Failed calls into detection plugins
+    pub plugin_errors: IntCounterVec,
+    /// Estimated clock skew of each sensor, positive when behind
+    pub sensor_clock_offset: GaugeVec,
+    /// Devices in memory per site
+    pub devices: IntGaugeVec,
+    /// Devices seen within the inactivity timeout per site
+    pub devices_active: IntGaugeVec,
+    /// Devices seen on each VLAN per site
+    pub vlan_devices: IntGaugeVec,
+    /// Flows seen within the inactivity timeout per site
+    pub flows_active: IntGaugeVec,
+    /// Bytes seen per protocol
+    pub protocol_bytes: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("netsentinel_aggregator".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let frames_consumed = IntCounter::new("frames_consumed_total", "Stream entries applied to the state")
+            .expect("valid metric");
+        let frames_invalid = IntCounter::new("frames_invalid_total", "Stream entries that failed to parse")
+            .expect("valid metric");
+        let stream_lag = IntGauge::new("stream_lag", "Stream entries not yet delivered to the consumer group")
+            .expect("valid metric");
+        let stream_pending = IntGauge::new("stream_pending", "Delivered but unacknowledged stream entries")
+            .expect("valid metric");
+        let stream_trimmed = IntCounter::new("stream_trimmed_total", "Stream entries removed by the retention task")
+            .expect("valid metric");
+        let ack_deferred = IntGauge::new("ack_deferred_entries", "Stream entries held back by failed persist cycles")
+            .expect("valid metric");
+        let ack_abandoned = IntCounter::new("ack_abandoned_total", "Stream entries acknowledged after exhausting persist retries")
+            .expect("valid metric");
+        let state_entries = IntGaugeVec::new(Opts::new("state_entries", "In-memory state entries"), &["kind"])
+            .expect("valid metric");
+        let persist_duration = Histogram::with_opts(
+            HistogramOpts::new("persist_duration_seconds", "Duration of persist cycles")
+                .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        )
+            .expect("valid metric");
+        let db_errors = IntCounter::new("db_errors_total", "Failed database writes")
+            .expect("valid metric");
+        let device_id_misses = IntCounter::new("device_id_cache_misses_total", "Device IDs resolved in the database")
+            .expect("valid metric");
+        let notifications_failed = IntCounterVec::new(
+            Opts::new("notifications_failed_total", "Events dropped by notification sinks"),
+            &["sink"],
+        )
+            .expect("valid metric");
+        let enrich_errors = IntCounterVec::new(
+            Opts::new("enrich_errors_total", "Frames an enricher failed on"),
+            &["enricher"],
+        )
+            .expect("valid metric");
+        let plugin_errors = IntCounterVec::new(
+            Opts::new("plugin_errors_total", "Failed calls into detection plugins"),
+            &["plugin"],
+        )
+            .expect("valid metric");
+
+        let sensor_clock_offset = GaugeVec::new(
+            Opts::new("sensor_clock_offset_seconds", "Estimated sensor clock skew, positive when behind"),
+            &["sensor"],
+        )
+            .expect("valid metric");
+
+        let devices = IntGaugeVec::new(Opts::new("devices", "Devices in memory"), &["site"])
+            .expect("valid metric");
+        let devices_active = IntGaugeVec::new(
+            Opts::new("devices_active", "Devices seen within the inactivity timeout"),
+            &["site"],
+        )
+            .expect("valid metric");
+        let vlan_devices = IntGaugeVec::new(Opts::new("vlan_devices", "Devices seen on each VLAN"), &["site", "vlan"])
+            .expect("valid metric");
+        let flows_active = IntGaugeVec::new(
+            Opts::new("flows_active", "Flows seen within the inactivity timeout"),
+            &["site"],
+        )
+            .expect("valid metric");
+        let protocol_bytes = IntCounterVec::new(Opts::new("protocol_bytes_total", "Bytes seen per protocol"), &["protocol"])
+            .expect("valid metric");
+
+        for collector in [
+            Box::new(frames_consumed.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(frames_invalid.clone()),
+            Box::new(stream_lag.clone()),
+            Box::new(stream_pending.clone()),
+            Box::new(stream_trimmed.clone()),
+            Box::new(ack_deferred.clone()),
+            Box::new(ack_abandoned.clone()),
+            Box::new(state_entries.clone()),
+            Box::new(persist_duration.clone()),
+            Box::new(db_errors.clone()),
+            Box::new(device_id_misses.clone()),
+            Box::new(notifications_failed.clone()),
+            Box::new(enrich_errors.clone()),
+            Box::new(plugin_errors.clone()),
+            Box::new(sensor_clock_offset.clone()),
+            Box::new(devices.clone()),
+            Box::new(devices_active.clone()),
+            Box::new(vlan_devices.clone()),
+            Box::new(flows_active.clone()),
+            Box::new(protocol_bytes.clone()),
+        ] {
+            registry.register(collector).expect("unique metric names");
+        }
+
+        Self {
+            registry,
+            frames_consumed,
+            frames_invalid,
+            stream_lag,
+            stream_pending,
+            stream_trimmed,
+            ack_deferred,
+            ack_abandoned,
+            state_entries,
+            persist_duration,
+            db_errors,
+            device_id_misses,
+            notifications_failed,
+            enrich_errors,
+            plugin_errors,
+            sensor_clock_offset,
+            devices,
+            devices_active,
+            vlan_devices,
+            flows_active,
+            protocol_bytes,
+        }
+    }
+
+    /// Sample state sizes and the inventory, devices and flows counting as
+    /// active when seen within `inactivity_timeout` seconds, and render all
+    /// metrics in the text format
+    pub fn render(&self, state: &AggregatorState, inactivity_timeout: u64) -> String {
+        let stats = state.stats_snapshot();
+        self.state_entries.with_label_values(&["devices"]).set(stats.total_devices as i64);
+        self.state_entries.with_label_values(&["flows"]).set(stats.total_flows as i64);
+        self.state_entries.with_label_values(&["protocols"]).set(stats.total_protocols as i64);
+        self.state_entries.with_label_values(&["vlans"]).set(stats.total_vlans as i64);
+        self.sample_inventory(state, inactivity_timeout);
+
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails on malformed metric families
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    fn sample_inventory(&self, state: &AggregatorState, inactivity_timeout: u64) {
+        // Sites and VLANs whose devices were all evicted disappear
+        self.devices.reset();
+        self.devices_active.reset();
+        self.vlan_devices.reset();
+        self.flows_active.reset();
+
+        for entry in state.devices.iter() {
+            let site = entry.key().site.as_str();
+            let device = entry.value();
+            self.devices.with_label_values(&[site]).inc();
+            if !device.is_inactive(inactivity_timeout) {
+                self.devices_active.with_label_values(&[site]).inc();
+            }
+            for vlan in device.vlans.iter() {
+                self.vlan_devices.with_label_values(&[site, &vlan.key().to_string()]).inc();
+            }
+        }
+
+        let now_ts = Utc::now().timestamp() as u64;
+        for entry in state.flows.iter() {
+            let last_seen = entry.value().last_seen.load(Ordering::Relaxed);
+            if now_ts.saturating_sub(last_seen) <= inactivity_timeout {
+                self.flows_active.with_label_values(&[entry.key().site.as_str()]).inc();
+            }
+        }
+
+        // Several EtherType and IP protocol pairs can share a name
+        let mut protocols: HashMap<&str, u64> = HashMap::new();
+        for entry in state.protocols.iter() {
+            *protocols.entry(entry.value().name()).or_default() += entry.value().byte_count.load(Ordering::Relaxed);
+        }
+        for (protocol, bytes) in protocols {
+            let counter = self.protocol_bytes.with_label_values(&[protocol]);
+            counter.inc_by(bytes.saturating_sub(counter.get()));
+        }
+    }
+}
+
+async fn handler(State((state, inactivity_timeout)): State<(Arc<AggregatorState>, u64)>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics().render(&state, inactivity_timeout),
+    )
+}
+
+/// Serve the metrics endpoint until shutdown is signalled
+pub async fn serve(
+    config: MetricsConfig,
+    state: Arc<AggregatorState>,
+    inactivity_timeout: u64,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Result<()> {
+    let addr = format!("0.0.0.0:{}", config.port);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+
+    info!("Metrics available on http://{}{}", addr, config.path);
+
+    let app = Router::new()
+        .route(&config.path, get(handler))
+        .with_state((state, inactivity_timeout));
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.recv().await;
+        })
+        .await
+        .with_context(|| "Metrics server failed")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, DeviceState, MacAddr, ProtocolStats, SiteId};
+
+    #[test]
+    fn test_render_includes_state_sizes() {
+        let state = AggregatorState::new();
+        metrics().frames_consumed.inc();
+
+        let text = metrics().render(&state, 300);
+        assert!(text.contains("netsentinel_aggregator_frames_consumed_total"));
+        assert!(text.contains("netsentinel_aggregator_state_entries{kind=\"devices\"} 0"));
+    }
+
+    #[test]
+    fn test_render_includes_inventory() {
+        // Not the process-wide metrics, which other tests render
+        let metrics = Metrics::new();
+        let state = AggregatorState::new();
+        let now = Utc::now();
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let device = DeviceState::new(DeviceKey::new(SiteId::new("paris").unwrap(), mac), now);
+        device.update(Some("10.0.20.5".parse().unwrap()), Some(20), 64, true, now.timestamp() as u64);
+        state.devices.insert(device.key(), device);
+        state.protocols.entry((0x0800, Some(6))).or_insert_with(|| ProtocolStats::new(0x0800, Some(6))).update(1500, 0);
+
+        let text = metrics.render(&state, 300);
+        assert!(text.contains("netsentinel_aggregator_devices{site=\"paris\"} 1"));
+        assert!(text.contains("netsentinel_aggregator_devices_active{site=\"paris\"} 1"));
+        assert!(text.contains("netsentinel_aggregator_vlan_devices{site=\"paris\",vlan=\"20\"} 1"));
+        assert!(text.contains("netsentinel_aggregator_protocol_bytes_total{protocol=\"TCP\"} 1500"));
+
+        // Counters never go back, and evicted sites disappear
+        state.devices.clear();
+        let text = metrics.render(&state, 300);
+        assert!(!text.contains("site=\"paris\""));
+        assert!(text.contains("netsentinel_aggregator_protocol_bytes_total{protocol=\"TCP\"} 1500"));
+    }
+}
@@ -0,0 +1,280 @@
+//! NetBox synchronization
+//!
+//! Periodically pushes the discovered inventory to NetBox through its REST
+//! API: VLANs as IPAM VLANs, IP addresses as IPAM addresses (described with
+//! the owning MAC) and devices as DCIM MAC addresses (NetBox 4.2+). Objects
+//! are looked up first and created when missing; existing objects are only
+//! patched when a field NetSentinel manages has changed. In dry-run mode the
+//! planned changes are logged instead of written.
+
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::NetBoxConfig;
+use crate::db::{Database, DeviceFilter};
+use crate::state::{DeviceSnapshot, MacAddr, VlanSnapshot};
+
+/// Outcome of one synchronization
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+/// A NetBox object NetSentinel keeps in sync
+struct Object {
+    /// API endpoint below `/api/`
+    endpoint: &'static str,
+    /// Query identifying the object
+    lookup: (&'static str, String),
+    /// Fields kept up to date on every sync
+    managed: Value,
+    /// Fields only set when the object is created
+    initial: Value,
+}
+
+/// Pushes devices, IPs and VLANs to NetBox
+pub struct NetBoxExporter {
+    config: NetBoxConfig,
+    client: reqwest::Client,
+    db: Arc<Database>,
+    inactivity_timeout: u64,
+}
+
+impl NetBoxExporter {
+    /// Create an exporter reading the inventory from `db`
+    pub fn new(config: NetBoxConfig, db: Arc<Database>, inactivity_timeout: u64) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let mut auth = HeaderValue::from_str(&format!("Token {}", config.token))
+            .with_context(|| "Invalid NetBox token")?;
+        auth.set_sensitive(true);
+        headers.insert(AUTHORIZATION, auth);
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build NetBox HTTP client")?;
+
+        Ok(Self { config, client, db, inactivity_timeout })
+    }
+
+    /// Log changes instead of writing them, regardless of the configuration
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run |= dry_run;
+        self
+    }
+
+    /// Synchronize the whole inventory once
+    pub async fn sync(&self) -> Result<SyncReport> {
+        let mut vlans = self.db.all_vlans().await?;
+        vlans.dedup_by_key(|v| v.vlan_id);
+        let filter = DeviceFilter {
+            active: (!self.config.include_inactive).then_some(true),
+            min_confidence: (self.config.min_confidence > 0).then_some(self.config.min_confidence),
+            ..Default::default()
+        };
+        let devices = self.db.all_devices(&filter, self.inactivity_timeout).await?;
+
+        let mut objects: Vec<Object> = vlans.iter().map(vlan_object).collect();
+        for device in &devices {
+            objects.push(mac_object(device));
+            objects.extend(device.cmdb_ips().map(|ip| ip_object(device, ip.ip_address)));
+        }
+        if let Some(tag) = &self.config.tag {
+            for object in &mut objects {
+                object.initial["tags"] = json!([{ "name": tag }]);
+            }
+        }
+
+        let mut report = SyncReport::default();
+        for object in &objects {
+            match self.apply(object).await {
+                Ok(Change::Created) => report.created += 1,
+                Ok(Change::Updated) => report.updated += 1,
+                Ok(Change::Unchanged) => report.unchanged += 1,
+                Err(e) => {
+                    warn!("NetBox: {} {}={}: {:#}", object.endpoint, object.lookup.0, object.lookup.1, e);
+                    report.failed += 1;
+                }
+            }
+        }
+
+        info!(
+            "NetBox sync{}: {} created, {} updated, {} unchanged, {} failed",
+            if self.config.dry_run { " (dry run)" } else { "" },
+            report.created, report.updated, report.unchanged, report.failed
+        );
+        Ok(report)
+    }
+
+    /// Synchronize every `interval_secs` until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) {
+        info!("Synchronizing inventory to NetBox at {}", self.config.url);
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    if let Err(e) = self.sync().await {
+                        warn!("NetBox sync failed: {:#}", e);
+                    }
+                }
+            }
+        }
+
+        debug!("NetBox exporter stopped");
+    }
+
+    /// Create or update one object
+    async fn apply(&self, object: &Object) -> Result<Change> {
+        let url = format!("{}/api/{}", self.config.url.trim_end_matches('/'), object.endpoint);
+
+        let found: Value = self.client
+            .get(&url)
+            .query(&[(object.lookup.0, object.lookup.1.as_str())])
+            .send()
+            .await
+            .with_context(|| "lookup failed")?
+            .error_for_status()
+            .with_context(|| "lookup rejected")?
+            .json()
+            .await
+            .with_context(|| "invalid lookup response")?;
+
+        let Some(current) = found["results"].as_array().and_then(|r| r.first()) else {
+            let mut body = object.initial.clone();
+            merge(&mut body, &object.managed);
+            if self.config.dry_run {
+                info!("NetBox (dry run): would create {} {}", object.endpoint, body);
+            } else {
+                self.client.post(&url).json(&body).send().await
+                    .with_context(|| "create failed")?
+                    .error_for_status()
+                    .with_context(|| "create rejected")?;
+            }
+            return Ok(Change::Created);
+        };
+
+        let changes = changes(current, &object.managed);
+        if changes.is_empty() {
+            return Ok(Change::Unchanged);
+        }
+
+        let id = current["id"].as_u64().context("object without id")?;
+        let body = Value::Object(changes);
+        if self.config.dry_run {
+            info!("NetBox (dry run): would update {}{}/ with {}", object.endpoint, id, body);
+        } else {
+            self.client.patch(format!("{}{}/", url, id)).json(&body).send().await
+                .with_context(|| "update failed")?
+                .error_for_status()
+                .with_context(|| "update rejected")?;
+        }
+        Ok(Change::Updated)
+    }
+}
+
+enum Change {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+fn vlan_object(vlan: &VlanSnapshot) -> Object {
+    Object {
+        endpoint: "ipam/vlans/",
+        lookup: ("vid", vlan.vlan_id.to_string()),
+        managed: json!({}),
+        initial: json!({
+            "vid": vlan.vlan_id,
+            "name": format!("VLAN {}", vlan.vlan_id),
+            "status": "active",
+        }),
+    }
+}
+
+fn mac_object(device: &DeviceSnapshot) -> Object {
+    let mut description = "Discovered by NetSentinel".to_string();
+    if let Some(mac) = MacAddr::from_string(&device.mac_address) {
+        description.push_str(&format!(", OUI {}", mac.oui_prefix()));
+    }
+    if !device.vlans.is_empty() {
+        let vlans: Vec<String> = device.vlans.iter().map(u16::to_string).collect();
+        description.push_str(&format!(", VLAN {}", vlans.join("/")));
+    }
+    if device.is_gateway {
+        description.push_str(", gateway");
+    }
+
+    Object {
+        endpoint: "dcim/mac-addresses/",
+        lookup: ("mac_address", device.mac_address.clone()),
+        managed: json!({ "description": description }),
+        initial: json!({ "mac_address": device.mac_address }),
+    }
+}
+
+fn ip_object(device: &DeviceSnapshot, ip: std::net::Ipv4Addr) -> Object {
+    Object {
+        endpoint: "ipam/ip-addresses/",
+        lookup: ("address", ip.to_string()),
+        managed: json!({ "description": format!("MAC {}", device.mac_address) }),
+        initial: json!({ "address": format!("{}/32", ip), "status": "active" }),
+    }
+}
+
+/// Managed fields whose value differs from the current object
+fn changes(current: &Value, managed: &Value) -> Map<String, Value> {
+    let mut changes = Map::new();
+
+    for (key, wanted) in managed.as_object().into_iter().flatten() {
+        // Choice fields come back as `{"value": ..., "label": ...}`
+        let actual = match &current[key] {
+            Value::Object(choice) if choice.contains_key("value") => &choice["value"],
+            value => value,
+        };
+        if actual != wanted {
+            changes.insert(key.clone(), wanted.clone());
+        }
+    }
+
+    changes
+}
+
+/// Copy the fields of `from` into `into`
+fn merge(into: &mut Value, from: &Value) {
+    if let (Some(into), Some(from)) = (into.as_object_mut(), from.as_object()) {
+        into.extend(from.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let current = json!({
+            "id": 7,
+            "description": "MAC 00:11:22:33:44:55",
+            "status": { "value": "active", "label": "Active" },
+        });
+
+        assert!(changes(&current, &json!({ "description": "MAC 00:11:22:33:44:55", "status": "active" })).is_empty());
+
+        let diff = changes(&current, &json!({ "description": "MAC 66:77:88:99:aa:bb", "status": "active" }));
+        assert_eq!(Value::Object(diff), json!({ "description": "MAC 66:77:88:99:aa:bb" }));
+    }
+}
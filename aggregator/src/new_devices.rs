@@ -0,0 +1,176 @@
+//! New-device alerts
+//!
+//! Watches new-device events and raises an alert for devices that show up
+//! where a policy applies (by VLAN or subnet) without being on the global or
+//! the policy's allowlist. Policies are checked in order, so a strict one
+//! (e.g. anything new on the OT VLAN is critical) goes before broader ones.
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::net::Ipv4Addr;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::config::{NewDeviceAlertsConfig, NewDevicePolicy};
+use crate::events::{self, Event, EventSender};
+
+/// Normalize a MAC address or prefix for prefix matching
+fn normalize(mac: &str) -> String {
+    mac.trim().to_ascii_lowercase().replace('-', ":")
+}
+
+fn listed(allowlist: &[String], mac: &str) -> bool {
+    allowlist.iter().any(|prefix| mac.starts_with(prefix.as_str()))
+}
+
+/// Raises alerts for devices missing from the allowlists
+pub struct NewDeviceAlerter {
+    config: NewDeviceAlertsConfig,
+    /// Global allowlist, including the allowlist file
+    allowlist: Vec<String>,
+}
+
+impl NewDeviceAlerter {
+    /// Create an alerter, reading the allowlist file if any
+    pub fn new(mut config: NewDeviceAlertsConfig) -> Result<Self> {
+        let mut allowlist: Vec<String> = config.allowlist.iter().map(|m| normalize(m)).collect();
+        if let Some(path) = &config.allowlist_file {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read allowlist file {:?}", path))?;
+            allowlist.extend(
+                content.lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().trim())
+                    .filter(|line| !line.is_empty())
+                    .map(normalize),
+            );
+        }
+        for policy in &mut config.policies {
+            policy.allowlist = policy.allowlist.iter().map(|m| normalize(m)).collect();
+        }
+
+        Ok(Self { config, allowlist })
+    }
+
+    /// Policy covering a device seen with `ip` on `vlan_id`
+    fn policy(&self, ip: Option<Ipv4Addr>, vlan_id: Option<u16>) -> Option<&NewDevicePolicy> {
+        self.config.policies.iter().find(|policy| {
+            (policy.vlan.is_empty() && policy.subnet.is_empty())
+                || vlan_id.is_some_and(|v| policy.vlan.contains(&v))
+                || ip.is_some_and(|ip| policy.subnet.iter().any(|s| s.contains(ip)))
+        })
+    }
+
+    /// Alert for `event`, if it is a new device that no allowlist covers
+    pub fn check(&self, event: &Event) -> Option<Event> {
+        let Event::NewDevice { timestamp, site, mac, oui_prefix, ip, vlan_id, .. } = event else {
+            return None;
+        };
+        let policy = self.policy(*ip, *vlan_id)?;
+        let normalized = normalize(mac);
+        if listed(&self.allowlist, &normalized) || listed(&policy.allowlist, &normalized) {
+            debug!("New device {} is allowlisted", mac);
+            return None;
+        }
+
+        Some(Event::Alert {
+            timestamp: *timestamp,
+            severity: policy.severity,
+            name: policy.name.clone(),
+            message: format!("{}, not on the allowlist", event.summary()),
+            site: Some(site.clone()),
+            sensor: None,
+            mac: Some(mac.clone()),
+            ip: *ip,
+            channels: policy.notify.clone(),
+            details: Some(json!({
+                "policy": policy.name,
+                "oui_prefix": oui_prefix,
+                "vlan_id": vlan_id,
+            })),
+        })
+    }
+
+    /// Check new devices until shutdown, sending alerts on `output`
+    pub async fn run(self, mut events: broadcast::Receiver<Event>, output: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "New-device alerts enabled ({} policies, {} allowlisted prefixes)",
+            self.config.policies.len(),
+            self.allowlist.len()
+        );
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                event = events::recv(&mut events, "New-device alerts") => {
+                    let Some(event) = event else { break };
+                    if let Some(alert) = self.check(&event) {
+                        let _ = output.send(alert);
+                    }
+                }
+            }
+        }
+
+        for event in events::drain(&mut events) {
+            if let Some(alert) = self.check(&event) {
+                let _ = output.send(alert);
+            }
+        }
+
+        debug!("New-device alerts stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::Utc;
+
+    fn alerter() -> NewDeviceAlerter {
+        let config: NewDeviceAlertsConfig = toml::from_str(r#"
+allowlist = ["00:1B:21"]
+
+[[policies]]
+name = "ot-device"
+vlan = [30]
+subnet = ["10.30.0.0/16"]
+severity = "critical"
+allowlist = ["00-80-f4"]
+
+[[policies]]
+name = "office-device"
+subnet = ["10.0.0.0/8"]
+severity = "low"
+"#).unwrap();
+        NewDeviceAlerter::new(config).unwrap()
+    }
+
+    fn new_device(mac: [u8; 6], ip: [u8; 4], vlan_id: Option<u16>) -> Event {
+        let device = DeviceKey::new(SiteId::default(), MacAddr::new(mac));
+        Event::new_device(device, SensorId::default(), Some(Ipv4Addr::from(ip)), vlan_id, Utc::now())
+    }
+
+    #[test]
+    fn test_policies() {
+        let alerter = alerter();
+
+        // OT VLAN, unknown vendor
+        let alert = alerter.check(&new_device([0x00, 0x11, 0x22, 0, 0, 1], [10, 30, 0, 5], Some(30))).unwrap();
+        let Event::Alert { name, severity, message, .. } = &alert else { panic!() };
+        assert_eq!(name, "ot-device");
+        assert_eq!(*severity, Severity::Critical);
+        assert_eq!(message, "New device 00:11:22:00:00:01 (10.30.0.5) on VLAN 30, not on the allowlist");
+
+        // OT subnet seen untagged, allowlisted for the OT policy
+        assert!(alerter.check(&new_device([0x00, 0x80, 0xf4, 0, 0, 1], [10, 30, 1, 1], None)).is_none());
+
+        // Office subnet, only the global allowlist applies
+        let alert = alerter.check(&new_device([0x00, 0x80, 0xf4, 0, 0, 1], [10, 1, 0, 1], None)).unwrap();
+        assert_eq!(alert.severity(), Severity::Low);
+        assert!(alerter.check(&new_device([0x00, 0x1b, 0x21, 0, 0, 1], [10, 1, 0, 1], None)).is_none());
+
+        // No policy for other networks
+        assert!(alerter.check(&new_device([0x00, 0x11, 0x22, 0, 0, 2], [192, 168, 1, 2], None)).is_none());
+    }
+}
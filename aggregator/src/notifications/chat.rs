@@ -0,0 +1,238 @@
+//! Slack and Microsoft Teams notifications
+//!
+//! Events are routed by rules (kind, minimum severity, VLAN) to a channel
+//! or webhook and rendered through a minijinja template. Slack receives the
+//! text as an incoming-webhook message, Teams as an Adaptive Card.
+
+use anyhow::{Context, Result};
+use minijinja::Environment;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::config::{local_hostname, ChatConfig, ChatRoute, RetryConfig, ScheduleConfig};
+use crate::events::{Event, Severity};
+
+use super::{template_context, Notifier, RateLimiter};
+
+/// Chat platform a notifier posts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatPlatform {
+    Slack,
+    Teams,
+}
+
+/// Posts events to Slack or Teams incoming webhooks
+pub struct ChatNotifier {
+    platform: ChatPlatform,
+    config: ChatConfig,
+    routes: Vec<ChatRoute>,
+    client: reqwest::Client,
+    templates: Environment<'static>,
+    limiter: RateLimiter,
+    hostname: String,
+}
+
+impl ChatNotifier {
+    /// Create a notifier, compiling the message templates
+    pub fn new(platform: ChatPlatform, config: ChatConfig) -> Result<Self> {
+        let routes = if config.routes.is_empty() {
+            vec![ChatRoute::default()]
+        } else {
+            config.routes.clone()
+        };
+
+        let mut templates = Environment::new();
+        templates.add_template_owned("default", config.template.clone())
+            .with_context(|| format!("Invalid {:?} template", platform))?;
+        for (i, route) in routes.iter().enumerate() {
+            if let Some(template) = &route.template {
+                templates.add_template_owned(format!("route{}", i), template.clone())
+                    .with_context(|| format!("Invalid {:?} template in route {}", platform, i + 1))?;
+            }
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build chat HTTP client")?;
+
+        Ok(Self {
+            platform,
+            routes,
+            client,
+            templates,
+            limiter: RateLimiter::per_hour(config.max_per_hour),
+            hostname: local_hostname(),
+            config,
+        })
+    }
+
+    /// Index of the first route matching `event`
+    fn route(&self, event: &Event) -> Option<usize> {
+        self.routes.iter().position(|route| {
+            route.events.contains(&event.kind())
+                && event.severity() >= route.min_severity
+                && (route.vlans.is_empty() || event.vlan_id().is_some_and(|v| route.vlans.contains(&v)))
+        })
+    }
+
+    /// Build the webhook URL and payload for `event`
+    pub fn message(&self, event: &Event) -> Result<(&str, Value)> {
+        let index = self.route(event).context("No route matches event")?;
+        let route = &self.routes[index];
+
+        let name = match route.template {
+            Some(_) => format!("route{}", index),
+            None => "default".to_string(),
+        };
+        let text = self.templates.get_template(&name)?
+            .render(template_context(event, &self.hostname))
+            .with_context(|| format!("Failed to render {:?} template", self.platform))?;
+
+        let payload = match self.platform {
+            ChatPlatform::Slack => {
+                let mut payload = json!({ "text": text });
+                if let Some(channel) = &route.channel {
+                    payload["channel"] = json!(channel);
+                }
+                payload
+            }
+            ChatPlatform::Teams => json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": {
+                        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                        "type": "AdaptiveCard",
+                        "version": "1.4",
+                        "body": [{
+                            "type": "TextBlock",
+                            "text": text,
+                            "wrap": true,
+                            "color": teams_color(event.severity()),
+                        }],
+                    },
+                }],
+            }),
+        };
+
+        Ok((route.url.as_deref().unwrap_or(&self.config.url), payload))
+    }
+}
+
+impl Notifier for ChatNotifier {
+    fn name(&self) -> &'static str {
+        match self.platform {
+            ChatPlatform::Slack => "slack",
+            ChatPlatform::Teams => "teams",
+        }
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.route(event).is_some() && self.limiter.try_acquire()
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let (url, payload) = self.message(event)?;
+
+        self.client
+            .post(url)
+            .json(&payload)
+            .send()
+            .await
+            .with_context(|| format!("{}: request failed", self.name()))?
+            .error_for_status()
+            .with_context(|| format!("{}: request rejected", self.name()))?;
+
+        Ok(())
+    }
+}
+
+/// Adaptive Card text color for a severity
+fn teams_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info | Severity::Low => "default",
+        Severity::Medium => "warning",
+        Severity::High | Severity::Critical => "attention",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::Utc;
+
+    fn notifier(platform: ChatPlatform, config: &str) -> ChatNotifier {
+        let config: ChatConfig = toml::from_str(config).unwrap();
+        let mut notifier = ChatNotifier::new(platform, config).unwrap();
+        notifier.hostname = "sensor".to_string();
+        notifier
+    }
+
+    fn new_device(vlan: u16) -> Event {
+        Event::new_device(DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55])), SensorId::default(), None, Some(vlan), Utc::now())
+    }
+
+    #[test]
+    fn test_slack_routes() {
+        let notifier = notifier(ChatPlatform::Slack, r##"
+            url = "https://hooks.slack.com/services/default"
+
+            [[routes]]
+            vlans = [99]
+            channel = "#restricted"
+            template = "Restricted: {{ mac }}"
+
+            [[routes]]
+            events = ["new_device"]
+        "##);
+
+        let (url, payload) = notifier.message(&new_device(99)).unwrap();
+        assert_eq!(url, "https://hooks.slack.com/services/default");
+        assert_eq!(payload, json!({ "text": "Restricted: 00:11:22:33:44:55", "channel": "#restricted" }));
+
+        let (_, payload) = notifier.message(&new_device(20)).unwrap();
+        assert_eq!(payload["text"], "New device 00:11:22:33:44:55 on VLAN 20\nSeverity: low - Sensor: sensor");
+        assert!(payload.get("channel").is_none());
+
+        let alert = Event::Alert {
+            timestamp: Utc::now(),
+            severity: Severity::Critical,
+            name: "test".to_string(),
+            message: "test".to_string(),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: None,
+            channels: Vec::new(),
+            details: None,
+        };
+        assert!(!notifier.wants(&alert));
+    }
+
+    #[test]
+    fn test_teams_card() {
+        let notifier = notifier(ChatPlatform::Teams, r#"
+            url = "https://example.webhook.office.com/default"
+
+            [[routes]]
+            events = ["new_device"]
+            url = "https://example.webhook.office.com/netops"
+        "#);
+
+        let (url, payload) = notifier.message(&new_device(20)).unwrap();
+        assert_eq!(url, "https://example.webhook.office.com/netops");
+        let card = &payload["attachments"][0]["content"];
+        assert_eq!(card["type"], "AdaptiveCard");
+        assert!(card["body"][0]["text"].as_str().unwrap().starts_with("New device 00:11:22:33:44:55"));
+    }
+}
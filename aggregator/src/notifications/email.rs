@@ -0,0 +1,198 @@
+//! Email alerts
+//!
+//! Sends high-priority events over SMTP. Events are filtered by kind,
+//! minimum severity and (for new devices) VLAN, then rate limited so that a
+//! burst of discoveries does not flood the recipients; the number of
+//! suppressed events is reported in the next email.
+
+use anyhow::{Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use minijinja::Environment;
+use std::time::Duration;
+
+use crate::config::{local_hostname, EmailConfig, RetryConfig, ScheduleConfig, SmtpSecurity};
+use crate::events::Event;
+
+use super::{template_context, Notifier, RateLimiter};
+
+/// Sends events as emails
+pub struct EmailNotifier {
+    config: EmailConfig,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    templates: Environment<'static>,
+    limiter: RateLimiter,
+    hostname: String,
+}
+
+impl EmailNotifier {
+    /// Create a notifier, validating addresses and compiling the templates
+    pub fn new(config: EmailConfig) -> Result<Self> {
+        let from: Mailbox = config.from.parse()
+            .with_context(|| format!("Invalid email sender '{}'", config.from))?;
+        let to = config.to.iter()
+            .map(|addr| addr.parse().with_context(|| format!("Invalid email recipient '{}'", addr)))
+            .collect::<Result<Vec<Mailbox>>>()?;
+        if to.is_empty() {
+            anyhow::bail!("Email notifications need at least one recipient");
+        }
+
+        let builder = match config.security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host),
+        };
+        let mut builder = builder
+            .port(config.smtp_port)
+            .timeout(Some(Duration::from_secs(config.timeout_secs)));
+        if let Some(username) = &config.username {
+            let password = config.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        let mut templates = Environment::new();
+        templates.add_template_owned("subject", config.subject_template.clone())
+            .with_context(|| "Invalid email subject template")?;
+        templates.add_template_owned("body", config.body_template.clone())
+            .with_context(|| "Invalid email body template")?;
+
+        Ok(Self {
+            from,
+            to,
+            transport: builder.build(),
+            templates,
+            limiter: RateLimiter::per_hour(config.max_per_hour),
+            hostname: local_hostname(),
+            config,
+        })
+    }
+
+    /// Whether `event` passes the kind, severity and VLAN filters
+    fn matches(&self, event: &Event) -> bool {
+        if !self.config.events.contains(&event.kind()) || event.severity() < self.config.min_severity {
+            return false;
+        }
+
+        match event {
+            Event::NewDevice { vlan_id, .. } if !self.config.vlans.is_empty() => {
+                vlan_id.is_some_and(|vlan| self.config.vlans.contains(&vlan))
+            }
+            _ => true,
+        }
+    }
+
+    /// Render the subject and body for `event`
+    pub fn render(&self, event: &Event, suppressed: u64) -> Result<(String, String)> {
+        let mut context = template_context(event, &self.hostname);
+        context["suppressed"] = suppressed.into();
+
+        let subject = self.templates.get_template("subject")?
+            .render(&context)
+            .with_context(|| "Failed to render email subject")?;
+        let body = self.templates.get_template("body")?
+            .render(&context)
+            .with_context(|| "Failed to render email body")?;
+
+        // Header values cannot span lines
+        Ok((subject.lines().next().unwrap_or_default().to_string(), body))
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.matches(event) && self.limiter.try_acquire()
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let suppressed = self.limiter.suppressed();
+        let (subject, body) = self.render(event, suppressed)?;
+
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .with_context(|| "Failed to build email")?;
+
+        self.transport.send(message).await
+            .with_context(|| format!("Failed to send email via {}", self.config.smtp_host))?;
+
+        self.limiter.clear_suppressed(suppressed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::{TimeZone, Utc};
+
+    fn notifier(extra: &str) -> EmailNotifier {
+        let config: EmailConfig = toml::from_str(&format!(
+            "smtp_host = \"localhost\"\nfrom = \"netsentinel@example.com\"\nto = [\"soc@example.com\"]\n{}",
+            extra
+        ))
+        .unwrap();
+        let mut notifier = EmailNotifier::new(config).unwrap();
+        notifier.hostname = "sensor".to_string();
+        notifier
+    }
+
+    fn new_device(vlan: u16) -> Event {
+        let timestamp = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        Event::new_device(DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55])), SensorId::default(), None, Some(vlan), timestamp)
+    }
+
+    #[test]
+    fn test_filters_and_rate_limit() {
+        let notifier = notifier("vlans = [99]\nmax_per_hour = 1");
+        let alert = Event::Alert {
+            timestamp: Utc::now(),
+            severity: Severity::Info,
+            name: "test".to_string(),
+            message: "test".to_string(),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: None,
+            channels: Vec::new(),
+            details: None,
+        };
+
+        assert!(!notifier.wants(&new_device(20)));
+        assert!(!notifier.wants(&alert));
+        assert!(notifier.wants(&new_device(99)));
+        assert!(!notifier.wants(&new_device(99)));
+        assert_eq!(notifier.limiter.suppressed(), 1);
+    }
+
+    #[test]
+    fn test_default_templates() {
+        let (subject, body) = notifier("").render(&new_device(99), 3).unwrap();
+
+        assert_eq!(subject, "[NetSentinel] New device 00:11:22:33:44:55 on VLAN 99");
+        assert!(body.starts_with("New device 00:11:22:33:44:55 on VLAN 99\n\nSeverity: low\n"));
+        assert!(body.contains("Sensor: sensor\n"));
+        assert!(body.contains("3 earlier notifications were suppressed"));
+    }
+}
@@ -0,0 +1,391 @@
+//! Outbound notifications
+//!
+//! Each sink implements [`Notifier`] and runs as its own task subscribed to
+//! the event channel, so a slow or unreachable endpoint only delays its own
+//! deliveries. Failed deliveries are retried per the sink's [`RetryConfig`]
+//! and then dropped. Outside the business hours of the sink's
+//! [`ScheduleConfig`], low-severity events are dropped or held back.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::{debug, error, info, warn};
+
+use crate::alerts::daily_window_active;
+use crate::config::{NotificationsConfig, OffHours, RetryConfig, ScheduleConfig};
+use crate::events::{self, Event, EventSender};
+use crate::metrics::metrics;
+
+pub mod chat;
+pub mod email;
+pub mod mqtt;
+pub mod snmp;
+pub mod syslog;
+pub mod webhook;
+
+pub use chat::{ChatNotifier, ChatPlatform};
+pub use email::EmailNotifier;
+pub use mqtt::MqttNotifier;
+pub use snmp::SnmpNotifier;
+pub use syslog::SyslogNotifier;
+pub use webhook::WebhookNotifier;
+
+/// An outbound notification sink
+pub trait Notifier: Send + Sync + 'static {
+    /// Sink name used in logs and metrics
+    fn name(&self) -> &'static str;
+
+    /// Whether the sink forwards `event`
+    fn wants(&self, event: &Event) -> bool;
+
+    /// Retry policy for failed deliveries
+    fn retry(&self) -> &RetryConfig;
+
+    /// Business hours of the sink
+    fn schedule(&self) -> &ScheduleConfig;
+
+    /// Deliver one event (a single attempt)
+    fn notify(&self, event: &Event) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Template context for an event: its fields plus `summary`, `severity`
+/// and the sensor `hostname`
+pub fn template_context(event: &Event, hostname: &str) -> serde_json::Value {
+    let mut context = serde_json::to_value(event).unwrap_or_default();
+    context["summary"] = event.summary().into();
+    context["severity"] = serde_json::to_value(event.severity()).unwrap_or_default();
+    context["hostname"] = hostname.into();
+    context
+}
+
+/// Sliding-window limit on notifications sent
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    sent: Mutex<VecDeque<Instant>>,
+    suppressed: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Allow at most `max` notifications per hour (0 disables the limit)
+    pub fn per_hour(max: u32) -> Self {
+        Self::new(max, Duration::from_secs(3600))
+    }
+
+    /// Allow at most `max` notifications per `window`
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            sent: Mutex::new(VecDeque::new()),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a slot, or count the notification as suppressed
+    pub fn try_acquire(&self) -> bool {
+        if self.max == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut sent = self.sent.lock();
+        while sent.front().is_some_and(|t| now.duration_since(*t) >= self.window) {
+            sent.pop_front();
+        }
+
+        if sent.len() < self.max as usize {
+            sent.push_back(now);
+            true
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Notifications suppressed since the count was last cleared
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+
+    /// Clear `n` suppressed notifications once they have been reported
+    pub fn clear_suppressed(&self, n: u64) {
+        self.suppressed.fetch_sub(n, Ordering::Relaxed);
+    }
+}
+
+/// TLS client configuration trusting the web PKI roots plus `ca_file`
+pub fn tls_config(ca_file: Option<&Path>) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    if let Some(path) = ca_file {
+        for cert in CertificateDer::pem_file_iter(path)
+            .with_context(|| format!("Failed to read CA file {:?}", path))?
+        {
+            let cert = cert.with_context(|| format!("Invalid certificate in {:?}", path))?;
+            roots.add(cert).with_context(|| format!("Unusable CA certificate in {:?}", path))?;
+        }
+    }
+
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+/// Delay before retry number `retry` (1-based)
+pub fn retry_delay(policy: &RetryConfig, retry: u32) -> Duration {
+    let factor = 1u64.checked_shl(retry.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_millis(policy.initial_backoff_ms.saturating_mul(factor).min(policy.max_backoff_ms))
+}
+
+/// Deliver `event`, retrying failed attempts with exponential backoff
+async fn deliver<N: Notifier>(notifier: &N, event: &Event) {
+    let policy = notifier.retry();
+
+    for attempt in 1..=policy.max_attempts {
+        match notifier.notify(event).await {
+            Ok(()) => {
+                debug!("{}: delivered {:?} event", notifier.name(), event.kind());
+                return;
+            }
+            Err(e) if attempt < policy.max_attempts => {
+                let delay = retry_delay(policy, attempt);
+                warn!("{}: delivery failed (attempt {}), retrying in {:?}: {:#}", notifier.name(), attempt, delay, e);
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                error!("{}: dropping {:?} event after {} attempts: {:#}", notifier.name(), event.kind(), attempt, e);
+                metrics().notifications_failed.with_label_values(&[notifier.name()]).inc();
+            }
+        }
+    }
+}
+
+/// What a sink's schedule does with an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gate {
+    Deliver,
+    /// Hold until business hours start again
+    Hold,
+    Drop,
+}
+
+/// Whether `schedule` is within business hours at `at` (always, without hours)
+pub fn business_hours(schedule: &ScheduleConfig, at: DateTime<Utc>) -> bool {
+    match (schedule.from, schedule.to) {
+        (Some(from), Some(to)) => daily_window_active(&schedule.days, from, to, at),
+        _ => true,
+    }
+}
+
+/// What `schedule` does with `event` at `at`
+pub fn gate(schedule: &ScheduleConfig, event: &Event, at: DateTime<Utc>) -> Gate {
+    if business_hours(schedule, at) || event.severity() >= schedule.off_hours_min_severity {
+        return Gate::Deliver;
+    }
+    match schedule.off_hours {
+        OffHours::Drop => Gate::Drop,
+        OffHours::Defer => Gate::Hold,
+    }
+}
+
+/// Events held back per sink outside business hours; the oldest are dropped
+/// beyond this
+const MAX_HELD: usize = 1000;
+
+/// How often held events are checked for business hours starting
+const HELD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Deliver `event` now, hold it back or drop it, per the sink's schedule
+async fn dispatch<N: Notifier>(notifier: &N, event: Event, held: &mut VecDeque<Event>) {
+    if !event.routed_to(notifier.name()) {
+        return;
+    }
+
+    match gate(notifier.schedule(), &event, Utc::now()) {
+        Gate::Deliver => {
+            if notifier.wants(&event) {
+                deliver(notifier, &event).await;
+            }
+        }
+        Gate::Hold => {
+            if held.len() >= MAX_HELD {
+                held.pop_front();
+                warn!("{}: too many events held outside business hours, dropping the oldest", notifier.name());
+            }
+            held.push_back(event);
+        }
+        Gate::Drop => debug!("{}: dropping {:?} event outside business hours", notifier.name(), event.kind()),
+    }
+}
+
+/// Forward events to `notifier` until shutdown, then flush what is queued
+pub async fn run<N: Notifier>(
+    notifier: N,
+    mut events: broadcast::Receiver<Event>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    info!("{} notifications enabled", notifier.name());
+
+    let mut held = VecDeque::new();
+    let mut ticker = tokio::time::interval(HELD_CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.recv() => break,
+            _ = ticker.tick(), if !held.is_empty() => {
+                if business_hours(notifier.schedule(), Utc::now()) {
+                    info!("{}: delivering {} events held outside business hours", notifier.name(), held.len());
+                    for event in held.drain(..) {
+                        if notifier.wants(&event) {
+                            deliver(&notifier, &event).await;
+                        }
+                    }
+                }
+            }
+            event = events::recv(&mut events, notifier.name()) => {
+                let Some(event) = event else { break };
+                dispatch(&notifier, event, &mut held).await;
+            }
+        }
+    }
+
+    for event in events::drain(&mut events) {
+        dispatch(&notifier, event, &mut held).await;
+    }
+    if !held.is_empty() {
+        warn!("{}: {} events held outside business hours were not delivered", notifier.name(), held.len());
+    }
+
+    debug!("{} notifications stopped", notifier.name());
+}
+
+/// Start a task for every enabled notification sink
+pub fn spawn(
+    config: &NotificationsConfig,
+    events: &EventSender,
+    shutdown: &broadcast::Sender<()>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let mut handles = Vec::new();
+
+    if let Some(webhook) = config.webhook.as_ref().filter(|c| c.enabled) {
+        let notifier = WebhookNotifier::new(webhook.clone())?;
+        handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+    }
+
+    if let Some(syslog) = config.syslog.as_ref().filter(|c| c.enabled) {
+        let notifier = SyslogNotifier::new(syslog.clone())?;
+        handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+    }
+
+    if let Some(email) = config.email.as_ref().filter(|c| c.enabled) {
+        let notifier = EmailNotifier::new(email.clone())?;
+        handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+    }
+
+    if let Some(mqtt) = config.mqtt.as_ref().filter(|c| c.enabled) {
+        let notifier = MqttNotifier::new(mqtt.clone())?;
+        handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+    }
+
+    if let Some(snmp) = config.snmp.as_ref().filter(|c| c.enabled) {
+        let notifier = SnmpNotifier::new(snmp.clone())?;
+        handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+    }
+
+    for (platform, chat) in [(ChatPlatform::Slack, &config.slack), (ChatPlatform::Teams, &config.teams)] {
+        if let Some(chat) = chat.as_ref().filter(|c| c.enabled) {
+            let notifier = ChatNotifier::new(platform, chat.clone())?;
+            handles.push(tokio::spawn(run(notifier, events.subscribe(), shutdown.subscribe())));
+        }
+    }
+
+    Ok(handles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        let policy = RetryConfig { max_attempts: 5, initial_backoff_ms: 500, max_backoff_ms: 3000 };
+
+        assert_eq!(retry_delay(&policy, 1), Duration::from_millis(500));
+        assert_eq!(retry_delay(&policy, 2), Duration::from_millis(1000));
+        assert_eq!(retry_delay(&policy, 3), Duration::from_millis(2000));
+        assert_eq!(retry_delay(&policy, 4), Duration::from_millis(3000));
+        assert_eq!(retry_delay(&policy, 80), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn test_schedule_gate() {
+        let schedule: ScheduleConfig = toml::from_str(r#"
+days = ["mon", "tue", "wed", "thu", "fri"]
+from = "08:00"
+to = "18:00"
+off_hours_min_severity = "high"
+off_hours = "defer"
+"#).unwrap();
+        let event = |severity| Event::Alert {
+            timestamp: Utc::now(),
+            severity,
+            name: "test".to_string(),
+            message: "test".to_string(),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: None,
+            channels: Vec::new(),
+            details: None,
+        };
+
+        // Friday 10:00, Friday 03:00 and Saturday 10:00
+        let open = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+        let night = Utc.with_ymd_and_hms(2024, 3, 1, 3, 0, 0).unwrap();
+        let weekend = Utc.with_ymd_and_hms(2024, 3, 2, 10, 0, 0).unwrap();
+
+        assert_eq!(gate(&schedule, &event(Severity::Low), open), Gate::Deliver);
+        assert_eq!(gate(&schedule, &event(Severity::Low), night), Gate::Hold);
+        assert_eq!(gate(&schedule, &event(Severity::Medium), weekend), Gate::Hold);
+        assert_eq!(gate(&schedule, &event(Severity::High), night), Gate::Deliver);
+
+        let schedule = ScheduleConfig { off_hours: OffHours::Drop, ..schedule };
+        assert_eq!(gate(&schedule, &event(Severity::Low), night), Gate::Drop);
+        assert_eq!(gate(&ScheduleConfig::default(), &event(Severity::Info), night), Gate::Deliver);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert_eq!(limiter.suppressed(), 2);
+
+        limiter.clear_suppressed(2);
+        assert_eq!(limiter.suppressed(), 0);
+        assert!(RateLimiter::per_hour(0).try_acquire());
+    }
+}
@@ -0,0 +1,183 @@
+//! MQTT publishing
+//!
+//! Publishes event records as JSON to an MQTT broker, on a topic rendered per
+//! event kind (e.g. `netsentinel/alerts/{{ severity }}`). The connection is
+//! driven by a background task that reconnects after broker failures;
+//! messages queued meanwhile are sent once the broker is back.
+
+use anyhow::{Context, Result};
+use minijinja::Environment;
+use rumqttc::{AsyncClient, ConnectionError, EventLoop, MqttOptions, QoS, TlsConfiguration, Transport};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::config::{local_hostname, MqttConfig, RetryConfig, ScheduleConfig};
+use crate::events::{Event, EventKind};
+
+use super::{template_context, tls_config, Notifier};
+
+/// Publish requests buffered while the broker is unreachable
+const REQUEST_CAPACITY: usize = 1024;
+
+/// Pause between reconnection attempts
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes events to an MQTT broker
+pub struct MqttNotifier {
+    config: MqttConfig,
+    client: AsyncClient,
+    qos: QoS,
+    topics: Environment<'static>,
+    hostname: String,
+}
+
+impl MqttNotifier {
+    /// Create a notifier and start its connection task
+    pub fn new(config: MqttConfig) -> Result<Self> {
+        let hostname = local_hostname();
+        let client_id = config.client_id.clone().unwrap_or_else(|| format!("netsentinel-{}", hostname));
+
+        let mut options = MqttOptions::new(client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(config.keep_alive_secs));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        if config.tls {
+            let tls = tls_config(config.ca_file.as_deref())?;
+            options.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(tls)));
+        }
+
+        let topics = topic_templates(&config)?;
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => anyhow::bail!("Invalid MQTT QoS {}", qos),
+        };
+
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        tokio::spawn(drive(eventloop, format!("{}:{}", config.host, config.port)));
+
+        Ok(Self { config, client, qos, topics, hostname })
+    }
+
+    /// Topic `event` is published on
+    pub fn topic(&self, event: &Event) -> Result<String> {
+        render_topic(&self.topics, event, &self.hostname)
+    }
+}
+
+impl Notifier for MqttNotifier {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.config.events.contains(&event.kind())
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let topic = self.topic(event)?;
+        let payload = serde_json::to_vec(&event.record()).with_context(|| "Failed to serialize event")?;
+
+        self.client
+            .publish(topic, self.qos, self.config.retain, payload)
+            .await
+            .with_context(|| "MQTT connection task stopped")
+    }
+}
+
+/// Compile the per-kind topic templates plus the default one
+fn topic_templates(config: &MqttConfig) -> Result<Environment<'static>> {
+    let mut env = Environment::new();
+    env.add_template_owned("default", config.default_topic.clone())
+        .with_context(|| "Invalid MQTT default_topic")?;
+    for (kind, topic) in &config.topics {
+        env.add_template_owned(kind_name(*kind), topic.clone())
+            .with_context(|| format!("Invalid MQTT topic for {:?}", kind))?;
+    }
+    Ok(env)
+}
+
+fn render_topic(topics: &Environment<'static>, event: &Event, hostname: &str) -> Result<String> {
+    let template = topics
+        .get_template(kind_name(event.kind()))
+        .or_else(|_| topics.get_template("default"))?;
+    let topic = template
+        .render(template_context(event, hostname))
+        .with_context(|| "Failed to render MQTT topic")?;
+
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        anyhow::bail!("Invalid MQTT topic '{}'", topic);
+    }
+    Ok(topic)
+}
+
+fn kind_name(kind: EventKind) -> &'static str {
+    match kind {
+        EventKind::NewDevice => "new_device",
+        EventKind::NewFlow => "new_flow",
+        EventKind::Alert => "alert",
+        EventKind::Lifecycle => "lifecycle",
+    }
+}
+
+/// Poll the connection until the client is dropped
+async fn drive(mut eventloop: EventLoop, broker: String) {
+    loop {
+        match eventloop.poll().await {
+            Ok(_) => {}
+            Err(ConnectionError::RequestsDone) => break,
+            Err(e) => {
+                warn!("MQTT connection to {} failed: {}; reconnecting in {:?}", broker, e, RECONNECT_DELAY);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+    debug!("MQTT connection to {} closed", broker);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::Utc;
+
+    #[test]
+    fn test_topics() {
+        let config: MqttConfig = toml::from_str(r#"
+            host = "localhost"
+
+            [topics]
+            alert = "netsentinel/alerts/{{ severity }}"
+        "#)
+        .unwrap();
+        let topics = topic_templates(&config).unwrap();
+
+        let device = Event::new_device(DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55])), SensorId::default(), None, None, Utc::now());
+        assert_eq!(render_topic(&topics, &device, "sensor").unwrap(), "netsentinel/events/new_device");
+
+        let alert = Event::Alert {
+            timestamp: Utc::now(),
+            severity: Severity::High,
+            name: "test".to_string(),
+            message: "test".to_string(),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: None,
+            channels: Vec::new(),
+            details: None,
+        };
+        assert_eq!(render_topic(&topics, &alert, "sensor").unwrap(), "netsentinel/alerts/high");
+    }
+}
@@ -0,0 +1,521 @@
+//! SNMP traps
+//!
+//! Sends events as SNMPv2c or SNMPv3 (USM, authNoPriv or authPriv) traps
+//! defined by the NetSentinel MIB (`mibs/NETSENTINEL-MIB.txt`). Messages are
+//! BER-encoded here; v3 authentication uses HMAC-SHA-96 or
+//! HMAC-SHA-256-192 and privacy AES-128-CFB, with this sensor acting as the
+//! authoritative engine.
+
+use aes::cipher::{AsyncStreamCipher, KeyIvInit};
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+use crate::config::{local_hostname, RetryConfig, ScheduleConfig, SnmpAuthProtocol, SnmpConfig, SnmpVersion};
+use crate::events::{Event, Severity};
+
+use super::Notifier;
+
+/// IANA enterprise number the default engine ID is derived from
+const ENTERPRISE_NUMBER: u32 = 32473;
+
+/// sysUpTime.0
+const SYS_UP_TIME: &[u32] = &[1, 3, 6, 1, 2, 1, 1, 3, 0];
+/// snmpTrapOID.0
+const SNMP_TRAP_OID: &[u32] = &[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+/// Largest message the receiver is told we accept
+const MAX_MESSAGE_SIZE: i64 = 65507;
+
+/// Varbind value
+#[derive(Debug, Clone)]
+enum Value {
+    Integer(i64),
+    String(String),
+    Oid(Vec<u32>),
+    IpAddress(Ipv4Addr),
+    TimeTicks(u32),
+}
+
+/// Localized USM keys
+struct Usm {
+    username: String,
+    engine_id: Vec<u8>,
+    /// Engine boots; the start time keeps it increasing across restarts
+    boots: i64,
+    protocol: SnmpAuthProtocol,
+    auth_key: Vec<u8>,
+    priv_key: Option<Vec<u8>>,
+    salt: AtomicU64,
+}
+
+/// Sends events as SNMP traps
+pub struct SnmpNotifier {
+    config: SnmpConfig,
+    enterprise: Vec<u32>,
+    hostname: String,
+    started: Instant,
+    usm: Option<Usm>,
+    request_id: AtomicI32,
+    socket: Mutex<Option<UdpSocket>>,
+}
+
+impl SnmpNotifier {
+    /// Create a notifier, localizing the v3 keys
+    pub fn new(config: SnmpConfig) -> Result<Self> {
+        let enterprise = parse_oid(&config.enterprise_oid)
+            .with_context(|| format!("Invalid enterprise OID '{}'", config.enterprise_oid))?;
+        let hostname = local_hostname();
+
+        let usm = match config.version {
+            SnmpVersion::V2c => None,
+            SnmpVersion::V3 => {
+                let engine_id = match &config.engine_id {
+                    Some(hex) => parse_hex(hex).with_context(|| format!("Invalid SNMP engine ID '{}'", hex))?,
+                    None => default_engine_id(&hostname),
+                };
+                if !(5..=32).contains(&engine_id.len()) {
+                    anyhow::bail!("SNMP engine ID must be 5 to 32 bytes");
+                }
+                let auth_password = config.auth_password.as_deref().context("SNMPv3 requires auth_password")?;
+                let protocol = config.auth_protocol;
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+
+                Some(Usm {
+                    username: config.username.clone().context("SNMPv3 requires username")?,
+                    auth_key: localize_key(protocol, auth_password, &engine_id),
+                    priv_key: config.priv_password.as_deref().map(|p| localize_key(protocol, p, &engine_id)),
+                    engine_id,
+                    boots: (now.as_secs() & 0x7fff_ffff) as i64,
+                    protocol,
+                    salt: AtomicU64::new(now.as_nanos() as u64),
+                })
+            }
+        };
+
+        Ok(Self {
+            config,
+            enterprise,
+            hostname,
+            started: Instant::now(),
+            usm,
+            request_id: AtomicI32::new(1),
+            socket: Mutex::new(None),
+        })
+    }
+
+    /// Varbinds of the trap for `event`
+    fn varbinds(&self, event: &Event) -> Vec<(Vec<u32>, Value)> {
+        let uptime = (self.started.elapsed().as_millis() / 10) as u32;
+        let notification = match event {
+            Event::NewDevice { .. } => 1,
+            Event::NewFlow { .. } => 2,
+            Event::Alert { .. } => 3,
+            Event::Lifecycle { .. } => 4,
+        };
+
+        let mut varbinds = vec![
+            (SYS_UP_TIME.to_vec(), Value::TimeTicks(uptime)),
+            (SNMP_TRAP_OID.to_vec(), Value::Oid(self.oid(&[0, notification]))),
+        ];
+        let mut object = |id: u32, value: Value| varbinds.push((self.oid(&[1, id, 0]), value));
+
+        object(1, Value::Integer(severity_code(event.severity())));
+        object(3, Value::String(event.summary()));
+        object(7, Value::String(self.hostname.clone()));
+        match event {
+            Event::NewDevice { mac, ip, vlan_id, .. } => {
+                object(4, Value::String(mac.clone()));
+                if let Some(ip) = ip {
+                    object(5, Value::IpAddress(*ip));
+                }
+                if let Some(vlan) = vlan_id {
+                    object(6, Value::Integer(*vlan as i64));
+                }
+            }
+            Event::NewFlow { src_mac, src_ip, vlan_id, .. } => {
+                object(4, Value::String(src_mac.clone()));
+                if let Some(ip) = src_ip {
+                    object(5, Value::IpAddress(*ip));
+                }
+                if let Some(vlan) = vlan_id {
+                    object(6, Value::Integer(*vlan as i64));
+                }
+            }
+            Event::Lifecycle { .. } => object(2, Value::String(event.name().to_string())),
+            Event::Alert { name, mac, ip, .. } => {
+                object(2, Value::String(name.clone()));
+                if let Some(mac) = mac {
+                    object(4, Value::String(mac.clone()));
+                }
+                if let Some(ip) = ip {
+                    object(5, Value::IpAddress(*ip));
+                }
+            }
+        }
+
+        varbinds
+    }
+
+    fn oid(&self, suffix: &[u32]) -> Vec<u32> {
+        self.enterprise.iter().chain(suffix).copied().collect()
+    }
+
+    /// Encode the complete trap message for `event`
+    pub fn encode(&self, event: &Event) -> Vec<u8> {
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let pdu = trap_pdu(request_id, &self.varbinds(event));
+
+        match &self.usm {
+            None => sequence(&[
+                integer(1),
+                octet_string(self.config.community.as_bytes()),
+                pdu,
+            ]),
+            Some(usm) => {
+                let engine_time = self.started.elapsed().as_secs() as i64;
+                usm.encode(request_id, engine_time, pdu)
+            }
+        }
+    }
+}
+
+impl Notifier for SnmpNotifier {
+    fn name(&self) -> &'static str {
+        "snmp"
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.config.events.contains(&event.kind())
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let message = self.encode(event);
+        let mut socket = self.socket.lock().await;
+
+        if socket.is_none() {
+            let udp = UdpSocket::bind("0.0.0.0:0").await?;
+            udp.connect((self.config.host.as_str(), self.config.port)).await
+                .with_context(|| format!("Failed to resolve {}:{}", self.config.host, self.config.port))?;
+            *socket = Some(udp);
+        }
+
+        let result = socket.as_ref().expect("bound above").send(&message).await;
+        if result.is_err() {
+            *socket = None;
+        }
+        result
+            .map(|_| ())
+            .with_context(|| format!("Failed to send trap to {}:{}", self.config.host, self.config.port))
+    }
+}
+
+impl Usm {
+    /// Length of the truncated HMAC carried in the message
+    fn auth_len(&self) -> usize {
+        match self.protocol {
+            SnmpAuthProtocol::Sha => 12,
+            SnmpAuthProtocol::Sha256 => 24,
+        }
+    }
+
+    /// Encode an authenticated (and possibly encrypted) v3 message
+    fn encode(&self, msg_id: i32, engine_time: i64, pdu: Vec<u8>) -> Vec<u8> {
+        let scoped_pdu = sequence(&[octet_string(&self.engine_id), octet_string(b""), pdu]);
+
+        let (flags, priv_params, data) = match &self.priv_key {
+            Some(key) => {
+                let salt = self.salt.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+                let mut iv = [0u8; 16];
+                iv[..4].copy_from_slice(&(self.boots as u32).to_be_bytes());
+                iv[4..8].copy_from_slice(&(engine_time as u32).to_be_bytes());
+                iv[8..].copy_from_slice(&salt);
+
+                let mut encrypted = scoped_pdu;
+                cfb_mode::Encryptor::<aes::Aes128>::new(key[..16].into(), &iv.into()).encrypt(&mut encrypted);
+                (0x03u8, salt.to_vec(), octet_string(&encrypted))
+            }
+            None => (0x01u8, Vec::new(), scoped_pdu),
+        };
+
+        let global = sequence(&[
+            integer(msg_id as i64),
+            integer(MAX_MESSAGE_SIZE),
+            octet_string(&[flags]),
+            integer(3),
+        ]);
+
+        // Authentication parameters are zeroed while the HMAC is computed
+        let usm_fields = [
+            octet_string(&self.engine_id),
+            integer(self.boots),
+            integer(engine_time),
+            octet_string(self.username.as_bytes()),
+        ];
+        let auth_offset_in_usm: usize = usm_fields.iter().map(Vec::len).sum::<usize>() + 2;
+        let mut usm_content = usm_fields.concat();
+        usm_content.extend(octet_string(&vec![0; self.auth_len()]));
+        usm_content.extend(octet_string(&priv_params));
+        let usm = tlv(0x30, &usm_content);
+        let usm_param = octet_string(&usm);
+
+        let version = integer(3);
+        let content = [version.clone(), global.clone(), usm_param.clone(), data].concat();
+        let mut message = tlv(0x30, &content);
+
+        let header_len = message.len() - content.len();
+        let usm_header = usm.len() - usm_content.len();
+        let param_header = usm_param.len() - usm.len();
+        let offset = header_len + version.len() + global.len() + param_header + usm_header + auth_offset_in_usm;
+
+        debug_assert_eq!(message[offset - 2..offset], [0x04, self.auth_len() as u8]);
+        let digest = hmac(self.protocol, &self.auth_key, &message);
+        message[offset..offset + self.auth_len()].copy_from_slice(&digest[..self.auth_len()]);
+        message
+    }
+}
+
+/// MIB severity value of an event
+fn severity_code(severity: Severity) -> i64 {
+    match severity {
+        Severity::Info => 1,
+        Severity::Low => 2,
+        Severity::Medium => 3,
+        Severity::High => 4,
+        Severity::Critical => 5,
+    }
+}
+
+/// RFC 3411 text-format engine ID for `hostname`
+fn default_engine_id(hostname: &str) -> Vec<u8> {
+    let mut id = (0x8000_0000 | ENTERPRISE_NUMBER).to_be_bytes().to_vec();
+    id.push(4);
+    id.extend(hostname.bytes().take(27));
+    if id.len() < 6 {
+        id.push(b'-');
+    }
+    id
+}
+
+/// Derive the localized key for `password` (RFC 3414 A.2)
+fn localize_key(protocol: SnmpAuthProtocol, password: &str, engine_id: &[u8]) -> Vec<u8> {
+    let expanded: Vec<u8> = password.bytes().cycle().take(1_048_576).collect();
+    let ku = digest(protocol, &[&expanded]);
+    digest(protocol, &[&ku, engine_id, &ku])
+}
+
+fn digest(protocol: SnmpAuthProtocol, parts: &[&[u8]]) -> Vec<u8> {
+    match protocol {
+        SnmpAuthProtocol::Sha => {
+            let mut hasher = Sha1::new();
+            parts.iter().for_each(|p| hasher.update(p));
+            hasher.finalize().to_vec()
+        }
+        SnmpAuthProtocol::Sha256 => {
+            let mut hasher = Sha256::new();
+            parts.iter().for_each(|p| hasher.update(p));
+            hasher.finalize().to_vec()
+        }
+    }
+}
+
+fn hmac(protocol: SnmpAuthProtocol, key: &[u8], data: &[u8]) -> Vec<u8> {
+    match protocol {
+        SnmpAuthProtocol::Sha => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        SnmpAuthProtocol::Sha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+fn parse_oid(s: &str) -> Option<Vec<u32>> {
+    let oid: Vec<u32> = s.trim_start_matches('.').split('.').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+    (oid.len() >= 2 && oid[0] <= 2).then_some(oid)
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_start_matches("0x");
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+// BER encoding
+
+/// SNMPv2-Trap-PDU
+fn trap_pdu(request_id: i32, varbinds: &[(Vec<u32>, Value)]) -> Vec<u8> {
+    let list: Vec<Vec<u8>> = varbinds
+        .iter()
+        .map(|(oid, value)| sequence(&[object_id(oid), encode_value(value)]))
+        .collect();
+
+    tlv(0xa7, &[integer(request_id as i64), integer(0), integer(0), sequence(&list)].concat())
+}
+
+fn encode_value(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Integer(v) => integer(*v),
+        Value::String(s) => octet_string(s.as_bytes()),
+        Value::Oid(oid) => object_id(oid),
+        Value::IpAddress(ip) => tlv(0x40, &ip.octets()),
+        Value::TimeTicks(t) => {
+            // Unsigned: encode as a non-negative integer with the TimeTicks tag
+            let mut encoded = integer(*t as i64);
+            encoded[0] = 0x43;
+            encoded
+        }
+    }
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Drop leading bytes that only repeat the sign
+    let mut start = 0;
+    while start < 7 {
+        let (b, next) = (bytes[start], bytes[start + 1]);
+        if (b == 0x00 && next & 0x80 == 0) || (b == 0xff && next & 0x80 != 0) {
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    tlv(0x02, &bytes[start..])
+}
+
+fn octet_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(0x04, bytes)
+}
+
+fn object_id(oid: &[u32]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let first = oid.first().copied().unwrap_or(0) * 40 + oid.get(1).copied().unwrap_or(0);
+
+    for &arc in std::iter::once(&first).chain(oid.iter().skip(2)) {
+        let mut chunk = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            chunk.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(chunk.into_iter().rev());
+    }
+
+    tlv(0x06, &content)
+}
+
+fn sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    tlv(0x30, &parts.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ber_primitives() {
+        assert_eq!(integer(0), [0x02, 0x01, 0x00]);
+        assert_eq!(integer(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(-1), [0x02, 0x01, 0xff]);
+        assert_eq!(object_id(SYS_UP_TIME), [0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00]);
+        assert_eq!(object_id(&[1, 3, 6, 1, 4, 1, 32473]), [0x06, 0x08, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x81, 0xfd, 0x59]);
+        assert_eq!(&tlv(0x04, &[0; 200])[..3], [0x04, 0x81, 200]);
+    }
+
+    #[test]
+    fn test_key_localization() {
+        // RFC 3414 appendix A.3.2
+        let engine_id = parse_hex("000000000000000000000002").unwrap();
+        let key = localize_key(SnmpAuthProtocol::Sha, "maplesyrup", &engine_id);
+        assert_eq!(key, parse_hex("6695febc9288e36282235fc7151f128497b38f3f").unwrap());
+    }
+
+    #[test]
+    fn test_v3_authentication() {
+        let config: SnmpConfig = toml::from_str(r#"
+            host = "127.0.0.1"
+            version = "v3"
+            username = "netsentinel"
+            auth_protocol = "sha256"
+            auth_password = "authpassphrase"
+            priv_password = "privpassphrase"
+            engine_id = "80007ed90473656e736f72"
+        "#)
+        .unwrap();
+        let notifier = SnmpNotifier::new(config).unwrap();
+        let usm = notifier.usm.as_ref().unwrap();
+
+        let message = usm.encode(7, 42, trap_pdu(7, &[]));
+        let marker = [&[0x04, 24][..], &[0; 24]].concat();
+        let offset = (0..message.len())
+            .find(|&i| message[i..i + 2] == [0x04, 24] && message[i + 26..].starts_with(&[0x04, 0x08]))
+            .unwrap()
+            + 2;
+
+        let mut zeroed = message.clone();
+        zeroed[offset..offset + 24].fill(0);
+        assert!(zeroed.windows(marker.len()).any(|w| w == marker.as_slice()));
+        assert_eq!(message[offset..offset + 24], hmac(SnmpAuthProtocol::Sha256, &usm.auth_key, &zeroed)[..24]);
+    }
+
+    #[test]
+    fn test_v2c_trap() {
+        let config: SnmpConfig = toml::from_str("host = \"127.0.0.1\"\ncommunity = \"noc\"").unwrap();
+        let notifier = SnmpNotifier::new(config).unwrap();
+        let event = Event::Alert {
+            timestamp: chrono::Utc::now(),
+            severity: Severity::High,
+            name: "gateway_mac_change".to_string(),
+            message: "Gateway MAC changed".to_string(),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            channels: Vec::new(),
+            details: None,
+        };
+
+        let message = notifier.encode(&event);
+        assert_eq!(message[0], 0x30);
+        assert!(message.windows(5).any(|w| w == [0x04, 0x03, b'n', b'o', b'c']));
+        assert!(message.windows(6).any(|w| w == [0x40, 0x04, 10, 0, 0, 1]));
+
+        // snmpTrapOID.0 = nsAlertNotification (enterprise.0.3)
+        let trap_oid = encode_value(&Value::Oid(notifier.oid(&[0, 3])));
+        assert!(message.windows(trap_oid.len()).any(|w| w == trap_oid.as_slice()));
+    }
+}
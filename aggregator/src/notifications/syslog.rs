@@ -0,0 +1,340 @@
+//! Syslog and CEF forwarding
+//!
+//! Events are formatted as RFC 5424 messages (event fields as structured
+//! data), or as CEF records or event record JSON inside an RFC 5424
+//! envelope, and sent to a collector over UDP, TCP or TLS. Stream connections are opened lazily and
+//! re-established on the next attempt after a write error.
+
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{local_hostname, RetryConfig, ScheduleConfig, SyslogConfig, SyslogFormat, SyslogFraming, SyslogProtocol};
+use crate::events::{Event, Severity};
+
+use super::{tls_config, Notifier};
+
+/// Structured data ID (32473 is the documentation enterprise number, RFC 5612)
+const SD_ID: &str = "netsentinel@32473";
+
+/// Open connection to the collector
+enum Connection {
+    Udp(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Send + Unpin>),
+}
+
+/// Forwards events to a syslog collector
+pub struct SyslogNotifier {
+    config: SyslogConfig,
+    facility: u8,
+    hostname: String,
+    tls: Option<TlsConnector>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SyslogNotifier {
+    /// Create a notifier; the collector is connected on the first event
+    pub fn new(config: SyslogConfig) -> Result<Self> {
+        let facility = facility_code(&config.facility)
+            .with_context(|| format!("Unknown syslog facility '{}'", config.facility))?;
+
+        let tls = match config.protocol {
+            SyslogProtocol::Tls => Some(TlsConnector::from(tls_config(config.ca_file.as_deref())?)),
+            _ => None,
+        };
+
+        Ok(Self {
+            config,
+            facility,
+            hostname: local_hostname(),
+            tls,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Format `event` as a complete syslog message (without framing)
+    pub fn format(&self, event: &Event) -> String {
+        let pri = self.facility as u16 * 8 + syslog_severity(event) as u16;
+        let header = format!(
+            "<{}>1 {} {} {} {} {}",
+            pri,
+            event.timestamp().to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.hostname,
+            self.config.app_name,
+            std::process::id(),
+            msg_id(event),
+        );
+
+        match self.config.format {
+            SyslogFormat::Rfc5424 => format!("{} {} {}", header, structured_data(event), event.summary()),
+            SyslogFormat::Cef => format!("{} - {}", header, cef(event)),
+            SyslogFormat::Json => {
+                let record = serde_json::to_string(&event.record()).unwrap_or_default();
+                format!("{} - {}", header, record)
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let addr = (self.config.host.as_str(), self.config.port);
+
+        match self.config.protocol {
+            SyslogProtocol::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(addr).await
+                    .with_context(|| format!("Failed to resolve {}:{}", self.config.host, self.config.port))?;
+                Ok(Connection::Udp(socket))
+            }
+            SyslogProtocol::Tcp => {
+                let stream = TcpStream::connect(addr).await
+                    .with_context(|| format!("Failed to connect to {}:{}", self.config.host, self.config.port))?;
+                Ok(Connection::Stream(Box::new(stream)))
+            }
+            SyslogProtocol::Tls => {
+                let connector = self.tls.as_ref().expect("TLS connector is built for the tls protocol");
+                let server_name = ServerName::try_from(self.config.host.clone())
+                    .with_context(|| format!("Invalid TLS server name '{}'", self.config.host))?;
+                let stream = TcpStream::connect(addr).await
+                    .with_context(|| format!("Failed to connect to {}:{}", self.config.host, self.config.port))?;
+                let stream = connector.connect(server_name, stream).await
+                    .with_context(|| format!("TLS handshake with {} failed", self.config.host))?;
+                Ok(Connection::Stream(Box::new(stream)))
+            }
+        }
+    }
+}
+
+impl Notifier for SyslogNotifier {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.config.events.contains(&event.kind())
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let message = self.format(event);
+        let mut connection = self.connection.lock().await;
+
+        if connection.is_none() {
+            *connection = Some(self.connect().await?);
+        }
+
+        let result = match connection.as_mut().expect("connected above") {
+            Connection::Udp(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+            Connection::Stream(stream) => {
+                let framed = match self.config.framing {
+                    SyslogFraming::OctetCounting => format!("{} {}", message.len(), message),
+                    SyslogFraming::Newline => format!("{}\n", message),
+                };
+                match stream.write_all(framed.as_bytes()).await {
+                    Ok(()) => stream.flush().await,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+
+        if result.is_err() {
+            // Reconnect on the next attempt
+            *connection = None;
+        }
+        result.with_context(|| format!("Failed to send to {}:{}", self.config.host, self.config.port))
+    }
+}
+
+/// Numeric code of a syslog facility name
+fn facility_code(name: &str) -> Option<u8> {
+    let code = match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "authpriv" => 10,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// RFC 5424 severity of an event
+fn syslog_severity(event: &Event) -> u8 {
+    match event.severity() {
+        Severity::Info => 6,
+        Severity::Low => 5,
+        Severity::Medium => 4,
+        Severity::High => 3,
+        Severity::Critical => 2,
+    }
+}
+
+/// CEF severity (0-10) of an event
+fn cef_severity(event: &Event) -> u8 {
+    match event.severity() {
+        Severity::Info => 1,
+        Severity::Low => 3,
+        Severity::Medium => 5,
+        Severity::High => 8,
+        Severity::Critical => 10,
+    }
+}
+
+fn msg_id(event: &Event) -> &str {
+    event.name()
+}
+
+/// Event fields as `(name, value)` pairs, in CEF extension naming
+fn fields(event: &Event) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("rt", event.timestamp().timestamp_millis().to_string())];
+    let mut push = |key, value: Option<String>| {
+        if let Some(value) = value {
+            fields.push((key, value));
+        }
+    };
+
+    match event {
+        Event::NewDevice { mac, oui_prefix, ip, vlan_id, .. } => {
+            push("smac", Some(mac.clone()));
+            push("src", ip.map(|ip| ip.to_string()));
+            push("cs1", Some(oui_prefix.clone()));
+            push("cs1Label", Some("oui".to_string()));
+            push("cn1", vlan_id.map(|v| v.to_string()));
+        }
+        Event::NewFlow { src_mac, dst_mac, src_ip, dst_ip, src_port, dst_port, vlan_id, protocol, .. } => {
+            push("smac", Some(src_mac.clone()));
+            push("dmac", Some(dst_mac.clone()));
+            push("src", src_ip.map(|ip| ip.to_string()));
+            push("dst", dst_ip.map(|ip| ip.to_string()));
+            push("spt", src_port.map(|p| p.to_string()));
+            push("dpt", dst_port.map(|p| p.to_string()));
+            push("proto", protocol.map(|p| p.to_string()));
+            push("cn1", vlan_id.map(|v| v.to_string()));
+        }
+        Event::Alert { message, mac, ip, .. } => {
+            push("msg", Some(message.clone()));
+            push("smac", mac.clone());
+            push("src", ip.map(|ip| ip.to_string()));
+        }
+        Event::Lifecycle { instance, version, .. } => {
+            push("dvchost", Some(instance.clone()));
+            push("cs2", Some(version.clone()));
+            push("cs2Label", Some("version".to_string()));
+        }
+    }
+
+    if fields.iter().any(|(key, _)| *key == "cn1") {
+        fields.push(("cn1Label", "vlan".to_string()));
+    }
+    fields
+}
+
+/// RFC 5424 structured data element holding the event fields
+fn structured_data(event: &Event) -> String {
+    let params: String = fields(event)
+        .into_iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]");
+            format!(" {}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("[{}{}]", SD_ID, params)
+}
+
+/// CEF record for an event
+fn cef(event: &Event) -> String {
+    let escape_header = |s: &str| s.replace('\\', "\\\\").replace('|', "\\|");
+    let escape_value = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('=', "\\=")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+
+    let extension: Vec<String> = fields(event)
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, escape_value(&value)))
+        .collect();
+
+    format!(
+        "CEF:0|SecuAAS|NetSentinel|{}|{}|{}|{}|{}",
+        env!("CARGO_PKG_VERSION"),
+        escape_header(msg_id(event)),
+        escape_header(&event.summary()),
+        cef_severity(event),
+        extension.join(" "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::{TimeZone, Utc};
+
+    fn notifier(format: &str) -> SyslogNotifier {
+        let config: SyslogConfig = toml::from_str(&format!("host = \"127.0.0.1\"\nformat = \"{}\"", format)).unwrap();
+        let mut notifier = SyslogNotifier::new(config).unwrap();
+        notifier.hostname = "sensor".to_string();
+        notifier
+    }
+
+    fn event() -> Event {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let timestamp = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        Event::new_device(DeviceKey::new(SiteId::default(), mac), SensorId::default(), Some("10.0.0.5".parse().unwrap()), Some(20), timestamp)
+    }
+
+    #[test]
+    fn test_rfc5424_format() {
+        let message = notifier("rfc5424").format(&event());
+        let expected_header = format!(
+            "<133>1 2024-03-01T12:00:00.000Z sensor netsentinel {} new_device [netsentinel@32473 rt=\"1709294400000\" smac=\"00:11:22:33:44:55\"",
+            std::process::id()
+        );
+
+        assert!(message.starts_with(&expected_header), "{}", message);
+        assert!(message.ends_with("] New device 00:11:22:33:44:55 (10.0.0.5) on VLAN 20"));
+    }
+
+    #[test]
+    fn test_cef_format() {
+        let message = notifier("cef").format(&event());
+
+        assert!(message.contains(" - CEF:0|SecuAAS|NetSentinel|"));
+        assert!(message.contains("|new_device|New device 00:11:22:33:44:55 (10.0.0.5) on VLAN 20|3|"));
+        assert!(message.contains("smac=00:11:22:33:44:55 src=10.0.0.5 cs1=00:11:22 cs1Label=oui cn1=20 cn1Label=vlan"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let message = notifier("json").format(&event());
+        let (header, record) = message.split_once(" - ").unwrap();
+
+        assert!(header.ends_with(" new_device"));
+        let record: serde_json::Value = serde_json::from_str(record).unwrap();
+        assert_eq!(record["type"], "new_device");
+        assert_eq!(record["sensor"], "unknown");
+        assert_eq!(record["entities"][0]["mac"], "00:11:22:33:44:55");
+    }
+}
@@ -0,0 +1,124 @@
+//! Webhook notifications
+//!
+//! POSTs events to an HTTP endpoint, either as the event record JSON or
+//! rendered through a user-supplied minijinja template.
+
+use anyhow::{Context, Result};
+use minijinja::Environment;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use std::time::Duration;
+
+use crate::config::{RetryConfig, ScheduleConfig, WebhookConfig};
+use crate::events::Event;
+
+use super::Notifier;
+
+const TEMPLATE_NAME: &str = "body";
+
+/// Delivers events to a webhook endpoint
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    templates: Option<Environment<'static>>,
+}
+
+impl WebhookNotifier {
+    /// Create a notifier, validating headers and compiling the template
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid webhook header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for webhook header '{}'", name))?;
+            headers.insert(name, value);
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build webhook HTTP client")?;
+
+        let templates = match &config.template {
+            Some(template) => {
+                let mut env = Environment::new();
+                env.add_template_owned(TEMPLATE_NAME, template.clone())
+                    .with_context(|| "Invalid webhook template")?;
+                Some(env)
+            }
+            None => None,
+        };
+
+        Ok(Self { config, client, templates })
+    }
+
+    /// Render the request body for `event`
+    pub fn render(&self, event: &Event) -> Result<String> {
+        match &self.templates {
+            Some(env) => env
+                .get_template(TEMPLATE_NAME)?
+                .render(event)
+                .with_context(|| "Failed to render webhook template"),
+            None => serde_json::to_string(&event.record()).with_context(|| "Failed to serialize event"),
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.config.events.contains(&event.kind())
+    }
+
+    fn retry(&self) -> &RetryConfig {
+        &self.config.retry
+    }
+
+    fn schedule(&self) -> &ScheduleConfig {
+        &self.config.schedule
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let body = self.render(event)?;
+
+        self.client
+            .post(&self.config.url)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("POST {} failed", self.config.url))?
+            .error_for_status()
+            .with_context(|| format!("POST {} rejected", self.config.url))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+    use chrono::Utc;
+
+    fn config(template: Option<&str>) -> WebhookConfig {
+        let mut config: WebhookConfig = toml::from_str(r#"url = "http://localhost:9/hook""#).unwrap();
+        config.template = template.map(str::to_string);
+        config
+    }
+
+    #[test]
+    fn test_render_template() {
+        let event = Event::new_device(DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55])), SensorId::default(), None, Some(7), Utc::now());
+
+        let notifier = WebhookNotifier::new(config(None)).unwrap();
+        assert!(notifier.render(&event).unwrap().contains(r#""type":"new_device""#));
+
+        let notifier = WebhookNotifier::new(config(Some(r#"{"text": {{ mac|tojson }}, "vlan": {{ vlan_id }}}"#))).unwrap();
+        assert_eq!(notifier.render(&event).unwrap(), r#"{"text": "00:11:22:33:44:55", "vlan": 7}"#);
+    }
+}
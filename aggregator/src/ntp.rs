@@ -0,0 +1,190 @@
+//! Unauthorized time servers
+//!
+//! Every `scan_interval_secs`, the time servers devices queried since the
+//! previous scan (see [`crate::state::ntp`]) are checked against
+//! `authorized_servers`. A device syncing its clock to another server, by
+//! default only an external one, raises an alert: compliance frameworks
+//! require devices to take their time from designated sources, and a clock
+//! set from anywhere scrambles the timestamps of its logs.
+//!
+//! A device and server pair alerts again only after `cooldown_secs`.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::bandwidth::{format_window, is_external};
+use crate::config::NtpConfig;
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, NtpAssociation, NtpKey};
+
+/// Detects devices querying unauthorized time servers
+pub struct NtpMonitor {
+    config: NtpConfig,
+    state: Arc<AggregatorState>,
+    /// Start of the previous scan
+    last_scan: Option<DateTime<Utc>>,
+    /// Last alert of each device and server
+    alerted: HashMap<NtpKey, DateTime<Utc>>,
+}
+
+impl NtpMonitor {
+    pub fn new(config: NtpConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            last_scan: None,
+            alerted: HashMap::new(),
+        }
+    }
+
+    /// Whether devices may query `server`
+    fn authorized(&self, server: Ipv4Addr) -> bool {
+        self.config.authorized_servers.iter().any(|c| c.contains(server))
+            || (self.config.external_only && !is_external(server))
+    }
+
+    /// Check the servers queried since the previous scan at `now` and
+    /// return the alerts of the unauthorized ones
+    pub fn scan(&mut self, now: DateTime<Utc>) -> Vec<Event> {
+        let since = self.last_scan.replace(now);
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        self.alerted.retain(|_, at| *at + cooldown > now);
+
+        let mut found: Vec<(NtpKey, NtpAssociation)> = self.state.ntp.associations.iter()
+            .filter(|entry| entry.last_request.is_some_and(|at| since.is_none_or(|since| at >= since)))
+            .filter(|entry| !self.authorized(entry.key().server_ip))
+            .filter(|entry| {
+                entry.client_ip.is_none_or(|ip| !self.config.ignore_clients.iter().any(|c| c.contains(ip)))
+            })
+            .filter(|entry| !self.alerted.contains_key(entry.key()))
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        found.sort_by(|(a, _), (b, _)| {
+            (a.site.as_str(), a.client_mac.as_bytes(), a.server_ip).cmp(&(b.site.as_str(), b.client_mac.as_bytes(), b.server_ip))
+        });
+
+        found.into_iter()
+            .map(|(key, association)| {
+                self.alerted.insert(key, now);
+                self.alert(&key, &association, now)
+            })
+            .collect()
+    }
+
+    /// Alert for a device querying an unauthorized server
+    fn alert(&self, key: &NtpKey, association: &NtpAssociation, now: DateTime<Utc>) -> Event {
+        let answered = match association.stratum {
+            Some(stratum) => format!("stratum {}", stratum),
+            None => "no response seen".to_string(),
+        };
+        let message = format!(
+            "{} queries unauthorized time server {} ({})",
+            key.client_mac, key.server_ip, answered
+        );
+
+        let details = json!({
+            "client_ip": association.client_ip,
+            "server_ip": key.server_ip,
+            "version": association.version,
+            "stratum": association.stratum,
+            "reference_id": association.reference_id,
+            "external": is_external(key.server_ip),
+            "first_seen": association.first_seen,
+        });
+
+        Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(key.site.to_string()),
+            sensor: None,
+            mac: Some(key.client_mac.to_string()),
+            ip: Some(key.server_ip),
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Scan until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Unauthorized NTP server detection enabled (scans every {}, {} authorized ranges{})",
+            format_window(self.config.scan_interval_secs),
+            self.config.authorized_servers.len(),
+            if self.config.external_only { ", external servers only" } else { "" }
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.scan(Utc::now()) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Unauthorized NTP server detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CapturedFrame, MacAddr, SensorFrame, SiteId};
+    use netsentinel_types::NtpInfo;
+
+    /// Record a request of `client` to `server` at `at`
+    fn request(state: &AggregatorState, client: MacAddr, client_ip: Ipv4Addr, server: Ipv4Addr, at: DateTime<Utc>) {
+        let mut frame = CapturedFrame::new("eth0", client, MacAddr::new([0, 1, 2, 3, 4, 1]), 0x0800, 90);
+        frame.src_ip = Some(client_ip);
+        frame.dst_ip = Some(server);
+        frame.ntp = Some(NtpInfo { version: 4, mode: 3, stratum: 0, reference_id: None });
+        state.ntp.observe(&SensorFrame { site: SiteId::new("lyon").unwrap(), sensor: Default::default(), frame }, at);
+    }
+
+    #[test]
+    fn test_unauthorized_ntp() {
+        let config: NtpConfig = toml::from_str(r#"
+            authorized_servers = ["198.51.100.0/24"]
+            ignore_clients = ["10.0.0.1"]
+        "#).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut monitor = NtpMonitor::new(config, Arc::clone(&state));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+        let camera = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let camera_ip = Ipv4Addr::new(10, 0, 0, 5);
+
+        // Authorized, internal, and queried by an ignored client
+        request(&state, camera, camera_ip, Ipv4Addr::new(198, 51, 100, 1), at);
+        request(&state, camera, camera_ip, Ipv4Addr::new(10, 0, 0, 1), at);
+        request(&state, MacAddr::new([0, 1, 2, 3, 4, 5]), Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(203, 0, 113, 9), at);
+        assert!(monitor.scan(at).is_empty());
+
+        request(&state, camera, camera_ip, Ipv4Addr::new(203, 0, 113, 123), at);
+        let alerts = monitor.scan(at);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, ip, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "00:11:22:33:44:55 queries unauthorized time server 203.0.113.123 (no response seen)");
+        assert_eq!(*ip, Some(Ipv4Addr::new(203, 0, 113, 123)));
+
+        // Once per cooldown
+        let later = at + chrono::Duration::minutes(1);
+        request(&state, camera, camera_ip, Ipv4Addr::new(203, 0, 113, 123), later);
+        assert!(monitor.scan(later).is_empty());
+        let next_day = at + chrono::Duration::days(1);
+        request(&state, camera, camera_ip, Ipv4Addr::new(203, 0, 113, 123), next_day);
+        assert_eq!(monitor.scan(next_day).len(), 1);
+    }
+}
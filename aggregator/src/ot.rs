@@ -0,0 +1,179 @@
+//! Unauthorized writes to industrial devices
+//!
+//! Every `scan_interval_secs`, the sources that wrote to an industrial
+//! device since the previous scan (see [`crate::state::ot`]) are checked
+//! against `authorized_writers`. A write from anywhere else, a Modbus
+//! register write, a DNP3 operate or a BACnet property write, raises an
+//! alert: on a control network only the SCADA masters and engineering
+//! workstations should change a PLC's setpoints or outputs.
+//!
+//! A source and device pair alerts again only after `cooldown_secs`.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::bandwidth::format_window;
+use crate::config::OtConfig;
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, OtWrite, OtWriteKey};
+
+/// Detects writes to industrial devices from unauthorized sources
+pub struct OtWriteMonitor {
+    config: OtConfig,
+    state: Arc<AggregatorState>,
+    /// Start of the previous scan
+    last_scan: Option<DateTime<Utc>>,
+    /// Last alert of each source and device
+    alerted: HashMap<OtWriteKey, DateTime<Utc>>,
+}
+
+impl OtWriteMonitor {
+    pub fn new(config: OtConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            last_scan: None,
+            alerted: HashMap::new(),
+        }
+    }
+
+    /// Whether the source of `write` may write to industrial devices
+    fn authorized(&self, write: &OtWrite) -> bool {
+        write.source_ip.is_some_and(|ip| self.config.authorized_writers.iter().any(|c| c.contains(ip)))
+    }
+
+    /// Check the writes since the previous scan at `now` and return the
+    /// alerts of the unauthorized ones
+    pub fn scan(&mut self, now: DateTime<Utc>) -> Vec<Event> {
+        let since = self.last_scan.replace(now);
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        self.alerted.retain(|_, at| *at + cooldown > now);
+
+        let mut found: Vec<(OtWriteKey, OtWrite)> = self.state.ot.writes.iter()
+            .filter(|entry| since.is_none_or(|since| entry.last_seen >= since))
+            .filter(|entry| !self.authorized(entry.value()))
+            .filter(|entry| !self.alerted.contains_key(entry.key()))
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        found.sort_by(|(a, _), (b, _)| {
+            (a.site.as_str(), a.protocol, a.source.as_bytes(), a.device.as_bytes())
+                .cmp(&(b.site.as_str(), b.protocol, b.source.as_bytes(), b.device.as_bytes()))
+        });
+
+        found.into_iter()
+            .map(|(key, write)| {
+                self.alerted.insert(key, now);
+                self.alert(&key, &write, now)
+            })
+            .collect()
+    }
+
+    /// Alert for a source writing to a device without authorization
+    fn alert(&self, key: &OtWriteKey, write: &OtWrite, now: DateTime<Utc>) -> Event {
+        let functions: Vec<String> = write.functions.iter().map(u8::to_string).collect();
+        let message = format!(
+            "{} writes to {} device {} without authorization (functions {})",
+            key.source, key.protocol.as_str(), key.device, functions.join(", ")
+        );
+
+        let details = json!({
+            "protocol": key.protocol,
+            "source_ip": write.source_ip,
+            "device_mac": key.device.to_string(),
+            "device_ip": write.device_ip,
+            "functions": write.functions,
+            "first_seen": write.first_seen,
+        });
+
+        Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message,
+            site: Some(key.site.to_string()),
+            sensor: None,
+            mac: Some(key.source.to_string()),
+            ip: write.source_ip,
+            channels: self.config.notify.clone(),
+            details: Some(details),
+        }
+    }
+
+    /// Scan until shutdown, sending alerts on `events`
+    pub async fn run(mut self, events: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "Unauthorized OT write detection enabled (scans every {}, {} authorized ranges)",
+            format_window(self.config.scan_interval_secs),
+            self.config.authorized_writers.len()
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    for alert in self.scan(Utc::now()) {
+                        let _ = events.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Unauthorized OT write detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CapturedFrame, MacAddr, SensorFrame, SiteId};
+    use netsentinel_types::{OtInfo, OtProtocol};
+    use std::net::Ipv4Addr;
+
+    /// Record a Modbus register write of `source` to the PLC at `at`
+    fn write(state: &AggregatorState, source: MacAddr, source_ip: Ipv4Addr, at: DateTime<Utc>) {
+        let plc = MacAddr::new([0x00, 0x0e, 0x8c, 0x00, 0x00, 0x10]);
+        let mut frame = CapturedFrame::new("eth0", source, plc, 0x0800, 80);
+        frame.src_ip = Some(source_ip);
+        frame.dst_ip = Some(Ipv4Addr::new(10, 20, 0, 10));
+        frame.ot = Some(OtInfo { protocol: OtProtocol::Modbus, function: 6, request: true, write: true, unit: Some(1) });
+        state.ot.observe(&SensorFrame { site: SiteId::new("plant").unwrap(), sensor: Default::default(), frame }, at);
+    }
+
+    #[test]
+    fn test_unauthorized_ot_write() {
+        let config: OtConfig = toml::from_str(r#"
+            authorized_writers = ["10.20.0.5"]
+        "#).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut monitor = OtWriteMonitor::new(config, Arc::clone(&state));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+        let laptop = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        // The engineering workstation
+        write(&state, MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x05]), Ipv4Addr::new(10, 20, 0, 5), at);
+        assert!(monitor.scan(at).is_empty());
+
+        write(&state, laptop, Ipv4Addr::new(10, 20, 0, 99), at);
+        let alerts = monitor.scan(at);
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, ip, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "00:11:22:33:44:55 writes to modbus device 00:0e:8c:00:00:10 without authorization (functions 6)");
+        assert_eq!(*ip, Some(Ipv4Addr::new(10, 20, 0, 99)));
+
+        // Once per cooldown
+        let later = at + chrono::Duration::minutes(1);
+        write(&state, laptop, Ipv4Addr::new(10, 20, 0, 99), later);
+        assert!(monitor.scan(later).is_empty());
+        let next_hour = at + chrono::Duration::hours(2);
+        write(&state, laptop, Ipv4Addr::new(10, 20, 0, 99), next_hour);
+        assert_eq!(monitor.scan(next_hour).len(), 1);
+    }
+}
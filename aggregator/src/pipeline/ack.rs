@@ -0,0 +1,210 @@
+//! Deferred stream acknowledgement for ack-after-persist delivery
+//!
+//! In `after_persist` mode the consumer does not XACK entries as it processes
+//! them. Instead their IDs are recorded here, and the persister acknowledges
+//! everything processed before a persist cycle once that cycle has been
+//! written to PostgreSQL. Entries of a crashed aggregator therefore stay in
+//! the consumer group's pending list and are re-read on restart.
+//!
+//! Entries of a failed cycle are retried with the next one, at most
+//! `ack_max_failed_cycles` times: a row that can never be written would
+//! otherwise hold back every entry from then on. Entries giving up are
+//! returned to the persister, which acknowledges and logs them.
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use redis::aio::MultiplexedConnection;
+use redis::Client;
+use tracing::debug;
+
+use crate::config::RedisConfig;
+use crate::metrics::metrics;
+
+/// Maximum number of IDs sent in a single XACK
+const ACK_CHUNK_SIZE: usize = 1000;
+
+/// Entries of a persist cycle, and the cycles that failed to persist them
+#[derive(Debug, Default)]
+pub struct PendingBatch {
+    entry_ids: Vec<String>,
+    failed_cycles: u32,
+}
+
+/// Tracks processed-but-unacknowledged stream entries
+pub struct AckTracker {
+    client: Client,
+    stream_name: String,
+    consumer_group: String,
+    max_failed_cycles: u32,
+    /// Retried batches, oldest first, then the entries tracked since
+    pending: Mutex<Vec<PendingBatch>>,
+}
+
+impl AckTracker {
+    /// Create a tracker for the configured stream and consumer group
+    pub fn new(config: &RedisConfig) -> Result<Self> {
+        let client = Client::open(config.url.as_str())
+            .with_context(|| format!("Failed to create Redis client: {}", config.url))?;
+
+        Ok(Self {
+            client,
+            stream_name: config.stream_name.clone(),
+            consumer_group: config.consumer_group.clone(),
+            max_failed_cycles: config.ack_max_failed_cycles,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Record an entry whose frame has been applied to the in-memory state
+    pub fn track(&self, entry_id: String) {
+        let mut pending = self.pending.lock();
+        match pending.last_mut() {
+            Some(batch) if batch.failed_cycles == 0 => batch.entry_ids.push(entry_id),
+            _ => pending.push(PendingBatch { entry_ids: vec![entry_id], failed_cycles: 0 }),
+        }
+    }
+
+    /// Number of entries waiting for a successful persist
+    pub fn pending_len(&self) -> usize {
+        self.pending.lock().iter().map(|batch| batch.entry_ids.len()).sum()
+    }
+
+    /// Take the entries covered by the persist cycle about to start
+    ///
+    /// Must be called *before* the state is written: anything processed
+    /// afterwards may not be included in the snapshot and stays pending.
+    pub fn begin_cycle(&self) -> Vec<PendingBatch> {
+        std::mem::take(&mut *self.pending.lock())
+    }
+
+    /// Return entries of a failed persist cycle so the next cycle retries
+    /// them, and take those that failed `ack_max_failed_cycles` times
+    pub fn abort_cycle(&self, batches: Vec<PendingBatch>) -> Vec<String> {
+        let mut exhausted = Vec::new();
+        let mut retried = Vec::with_capacity(batches.len());
+        for mut batch in batches {
+            batch.failed_cycles += 1;
+            if batch.failed_cycles >= self.max_failed_cycles {
+                exhausted.append(&mut batch.entry_ids);
+            } else {
+                retried.push(batch);
+            }
+        }
+        self.requeue(retried);
+        exhausted
+    }
+
+    /// Put batches back ahead of the entries tracked since they were taken
+    fn requeue(&self, mut batches: Vec<PendingBatch>) {
+        let mut pending = self.pending.lock();
+        batches.append(&mut pending);
+        *pending = batches;
+        record_deferred(&pending);
+    }
+
+    /// Acknowledge the entries of a successfully persisted cycle
+    pub async fn commit_cycle(&self, batches: Vec<PendingBatch>) -> Result<u64> {
+        let entry_ids: Vec<String> = batches.into_iter().flat_map(|batch| batch.entry_ids).collect();
+        record_deferred(&self.pending.lock());
+        self.acknowledge(entry_ids).await
+    }
+
+    /// XACK `entry_ids`, keeping those that could not be for the next cycle
+    pub async fn acknowledge(&self, entry_ids: Vec<String>) -> Result<u64> {
+        if entry_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = match self.connect().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                self.requeue(vec![PendingBatch { entry_ids, failed_cycles: 0 }]);
+                return Err(e);
+            }
+        };
+
+        let mut acked = 0;
+        for (i, chunk) in entry_ids.chunks(ACK_CHUNK_SIZE).enumerate() {
+            let result: redis::RedisResult<u64> = redis::cmd("XACK")
+                .arg(&self.stream_name)
+                .arg(&self.consumer_group)
+                .arg(chunk)
+                .query_async(&mut conn)
+                .await;
+
+            match result {
+                Ok(n) => acked += n,
+                Err(e) => {
+                    // Keep the unacknowledged remainder for the next cycle
+                    self.requeue(vec![PendingBatch { entry_ids: entry_ids[i * ACK_CHUNK_SIZE..].to_vec(), failed_cycles: 0 }]);
+                    return Err(e).with_context(|| "Failed to XACK persisted entries");
+                }
+            }
+        }
+
+        debug!("Acknowledged {} persisted stream entries", acked);
+        Ok(acked)
+    }
+
+    async fn connect(&self) -> Result<MultiplexedConnection> {
+        self.client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| "Failed to connect to Redis")
+    }
+}
+
+/// Export the entries held back by failed persist cycles
+fn record_deferred(pending: &[PendingBatch]) {
+    let deferred: usize = pending.iter().filter(|batch| batch.failed_cycles > 0).map(|batch| batch.entry_ids.len()).sum();
+    metrics().ack_deferred.set(deferred as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> AckTracker {
+        let config: RedisConfig = toml::from_str("ack_max_failed_cycles = 3").unwrap();
+        AckTracker::new(&config).unwrap()
+    }
+
+    fn ids(batches: &[PendingBatch]) -> Vec<&str> {
+        batches.iter().flat_map(|batch| batch.entry_ids.iter().map(String::as_str)).collect()
+    }
+
+    #[test]
+    fn test_cycle_take_and_abort() {
+        let tracker = tracker();
+        tracker.track("1-0".to_string());
+        tracker.track("2-0".to_string());
+
+        let cycle = tracker.begin_cycle();
+        assert_eq!(ids(&cycle), vec!["1-0", "2-0"]);
+        assert_eq!(tracker.pending_len(), 0);
+
+        // Entries processed during the failed cycle stay after the retried ones
+        tracker.track("3-0".to_string());
+        assert!(tracker.abort_cycle(cycle).is_empty());
+        assert_eq!(ids(&tracker.begin_cycle()), vec!["1-0", "2-0", "3-0"]);
+    }
+
+    #[test]
+    fn test_permanent_failure_gives_up() {
+        let tracker = tracker();
+        tracker.track("1-0".to_string());
+
+        // Every cycle fails, on a row that never persists
+        let mut exhausted = Vec::new();
+        for second in 2..=4 {
+            let cycle = tracker.begin_cycle();
+            tracker.track(format!("{}-0", second));
+            exhausted.push(tracker.abort_cycle(cycle));
+        }
+
+        // Each entry is given up after its third failed cycle
+        assert_eq!(exhausted, vec![Vec::<String>::new(), Vec::new(), vec!["1-0".to_string()]]);
+        assert_eq!(ids(&tracker.begin_cycle()), vec!["2-0", "3-0", "4-0"]);
+        assert_eq!(tracker.pending_len(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+//! In-memory frame consumer
+//!
+//! Takes frames from a channel instead of the Redis stream, for the
+//! all-in-one mode where capture and aggregation share one process. There
+//! is no consumer group: frames still queued at shutdown are processed, and
+//! frames lost in a crash are simply not seen, as with a capture restart.
+
+use anyhow::Result;
+use chrono::Utc;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tracing::info;
+
+use crate::enrich::Enrichers;
+use crate::events::EventSender;
+use crate::metrics::metrics;
+use crate::state::{AggregatorState, SensorFrame};
+
+use super::consumer::emit_events;
+
+/// Consumer of frames handed over in memory
+pub struct ChannelConsumer {
+    state: Arc<AggregatorState>,
+    frames: mpsc::Receiver<SensorFrame>,
+    events: Option<EventSender>,
+    enrichers: Enrichers,
+}
+
+impl ChannelConsumer {
+    /// Create a consumer of the frames sent on `frames`
+    pub fn new(state: Arc<AggregatorState>, frames: mpsc::Receiver<SensorFrame>) -> Self {
+        Self { state, frames, events: None, enrichers: Enrichers::new() }
+    }
+
+    /// Emit new-device and new-flow events on `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run `enrichers` on every frame once applied to the state
+    pub fn with_enrichers(mut self, enrichers: Enrichers) -> Self {
+        self.enrichers = enrichers;
+        self
+    }
+
+    /// Process frames until shutdown or until every sender is gone
+    pub async fn run(mut self, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        info!("Starting in-memory consumer");
+        let mut processed_count: u64 = 0;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    info!("Consumer received shutdown signal");
+                    // Frames already handed over are not dropped
+                    while let Ok(frame) = self.frames.try_recv() {
+                        self.process(&frame).await;
+                        processed_count += 1;
+                    }
+                    break;
+                }
+                frame = self.frames.recv() => {
+                    let Some(frame) = frame else { break };
+                    self.process(&frame).await;
+                    processed_count += 1;
+                }
+            }
+        }
+
+        info!("Consumer stopped. Total processed: {}", processed_count);
+        Ok(())
+    }
+
+    async fn process(&self, frame: &SensorFrame) {
+        let result = self.state.process_frame(frame);
+        self.state.clocks.record(frame, Utc::now());
+        self.enrichers.enrich(frame, &self.state).await;
+        if let Some(events) = &self.events {
+            emit_events(events, frame, &result);
+        }
+        metrics().frames_consumed.inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drains_on_shutdown() {
+        let state = Arc::new(AggregatorState::new());
+        let (frames_tx, frames) = mpsc::channel(16);
+        let (shutdown_tx, shutdown) = broadcast::channel(1);
+
+        let frame: SensorFrame = serde_json::from_value(serde_json::json!({
+            "site": "lab",
+            "sensor": "laptop",
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": "00:11:22:33:44:55",
+            "dst_mac": "66:77:88:99:aa:bb",
+            "ethertype": 0x0800,
+            "frame_size": 60,
+            "payload_size": 0,
+        })).unwrap();
+        for _ in 0..3 {
+            frames_tx.send(frame.clone()).await.unwrap();
+        }
+        shutdown_tx.send(()).unwrap();
+
+        ChannelConsumer::new(Arc::clone(&state), frames).run(shutdown).await.unwrap();
+        let stats = state.stats_snapshot();
+        assert_eq!(stats.total_packets, 3);
+        assert_eq!(stats.total_devices, 2);
+    }
+}
@@ -0,0 +1,118 @@
+//! Stream checkpoints
+//!
+//! The consumer records the last stream entry it applied to the state; once
+//! a persist cycle has written everything applied before it started, the
+//! persister saves that entry ID to the `stream_checkpoints` table. Each
+//! consumer reads its own share of the stream, so there is one checkpoint per
+//! consumer. A checkpoint tells how far behind the stream head aggregation
+//! is, and where to replay from when state applied after it was lost.
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use redis::aio::MultiplexedConnection;
+use serde::Serialize;
+
+use super::registry::parse_info_fields;
+use super::retention::StreamId;
+use crate::config::RedisConfig;
+
+/// Position of this instance's consumer in the frames stream
+pub struct StreamPosition {
+    pub stream: String,
+    pub consumer_group: String,
+    pub consumer: String,
+    processed: Mutex<Option<StreamId>>,
+}
+
+impl StreamPosition {
+    /// Position of the consumer of `config`, before anything was processed
+    pub fn new(config: &RedisConfig) -> Self {
+        Self {
+            stream: config.stream_name.clone(),
+            consumer_group: config.consumer_group.clone(),
+            consumer: config.consumer_name.clone(),
+            processed: Mutex::new(None),
+        }
+    }
+
+    /// Start from the checkpoint a previous run saved
+    pub fn with_checkpoint(self, checkpoint: Option<StreamId>) -> Self {
+        *self.processed.lock() = checkpoint;
+        self
+    }
+
+    /// Record an entry applied to the state
+    ///
+    /// Pending entries re-read on startup are older than the checkpoint and
+    /// leave it where it is.
+    pub fn advance(&self, id: StreamId) {
+        let mut processed = self.processed.lock();
+        if processed.is_none_or(|last| id > last) {
+            *processed = Some(id);
+        }
+    }
+
+    /// Last entry applied to the state
+    pub fn processed(&self) -> Option<StreamId> {
+        *self.processed.lock()
+    }
+}
+
+/// Newest entry of the stream
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StreamHead {
+    #[serde(serialize_with = "serialize_id")]
+    pub last_id: StreamId,
+    pub length: u64,
+}
+
+impl StreamHead {
+    /// Read the head of `stream`, `None` when the stream does not exist
+    pub async fn read(conn: &mut MultiplexedConnection, stream: &str) -> Result<Option<Self>> {
+        let info: redis::RedisResult<redis::Value> = redis::cmd("XINFO")
+            .arg("STREAM")
+            .arg(stream)
+            .query_async(conn)
+            .await;
+        let info = match info {
+            Ok(info) => info,
+            Err(e) if e.to_string().contains("no such key") => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read the head of stream '{}'", stream)),
+        };
+
+        let fields = parse_info_fields(&info);
+        let last_id = fields.get("last-generated-id").and_then(|id| StreamId::parse(id));
+        let length = fields.get("length").and_then(|l| l.parse().ok()).unwrap_or(0);
+        Ok(last_id.map(|last_id| Self { last_id, length }))
+    }
+
+    /// Milliseconds between `checkpoint` and the head
+    pub fn behind_ms(&self, checkpoint: StreamId) -> u64 {
+        self.last_id.ms.saturating_sub(checkpoint.ms)
+    }
+}
+
+fn serialize_id<S: serde::Serializer>(id: &StreamId, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_only_moves_forward() {
+        let config: RedisConfig = toml::from_str("").unwrap();
+        let position = StreamPosition::new(&config).with_checkpoint(StreamId::parse("100-0"));
+
+        // A pending entry re-read on startup
+        position.advance(StreamId::parse("90-3").unwrap());
+        assert_eq!(position.processed(), StreamId::parse("100-0"));
+
+        position.advance(StreamId::parse("100-1").unwrap());
+        assert_eq!(position.processed(), StreamId::parse("100-1"));
+
+        let head = StreamHead { last_id: StreamId::parse("1600-0").unwrap(), length: 10 };
+        assert_eq!(head.behind_ms(StreamId::parse("100-1").unwrap()), 1500);
+    }
+}
@@ -1,25 +1,86 @@
 //! Redis Stream consumer for captured frames
 
 use anyhow::{Context, Result};
+use chrono::Utc;
 use redis::aio::MultiplexedConnection;
 use redis::Client;
 use std::sync::Arc;
 use tokio::sync::broadcast;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::config::RedisConfig;
-use crate::state::{AggregatorState, CapturedFrame};
+use crate::enrich::Enrichers;
+use crate::metrics::metrics;
+use crate::events::{Event, EventSender};
+use crate::state::{AggregatorState, ProcessResult, SensorFrame};
+
+use super::ack::AckTracker;
+use super::checkpoint::{StreamHead, StreamPosition};
+use super::registry::{parse_info_fields, InstanceRegistry};
+use super::retention::StreamId;
 
 /// Redis stream consumer
 pub struct RedisConsumer {
     config: RedisConfig,
     state: Arc<AggregatorState>,
+    ack_tracker: Option<Arc<AckTracker>>,
+    position: Option<Arc<StreamPosition>>,
+    events: Option<EventSender>,
+    enrichers: Enrichers,
 }
 
 impl RedisConsumer {
     /// Create a new consumer
     pub fn new(config: RedisConfig, state: Arc<AggregatorState>) -> Self {
-        Self { config, state }
+        Self { config, state, ack_tracker: None, position: None, events: None, enrichers: Enrichers::new() }
+    }
+
+    /// Emit new-device and new-flow events on `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run `enrichers` on every frame once applied to the state
+    pub fn with_enrichers(mut self, enrichers: Enrichers) -> Self {
+        self.enrichers = enrichers;
+        self
+    }
+
+    /// Defer acknowledgements to `tracker` instead of XACKing after processing
+    pub fn with_ack_tracker(mut self, tracker: Arc<AckTracker>) -> Self {
+        self.ack_tracker = Some(tracker);
+        self
+    }
+
+    /// Record the entries applied to the state in `position`
+    pub fn with_position(mut self, position: Arc<StreamPosition>) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    /// Log where the consumer resumes, relative to the head of the stream
+    async fn report_resume(&self, conn: &mut MultiplexedConnection, position: &StreamPosition) {
+        let head = match StreamHead::read(conn, &self.config.stream_name).await {
+            Ok(head) => head,
+            Err(e) => {
+                warn!("Failed to read the stream head: {}", e);
+                return;
+            }
+        };
+
+        match (position.processed(), head) {
+            (Some(checkpoint), Some(head)) => info!(
+                "Resuming after checkpoint {}, {:.1}s behind the stream head {} ({} entries in the stream)",
+                checkpoint, head.behind_ms(checkpoint) as f64 / 1000.0, head.last_id, head.length
+            ),
+            (Some(checkpoint), None) => info!("Resuming after checkpoint {}, the stream is empty", checkpoint),
+            (None, Some(head)) => info!(
+                "No checkpoint for consumer {}, stream head is {} ({} entries in the stream)",
+                self.config.consumer_name, head.last_id, head.length
+            ),
+            (None, None) => info!("No checkpoint for consumer {}, the stream is empty", self.config.consumer_name),
+        }
     }
 
     /// Connect to Redis
@@ -62,25 +123,195 @@ impl RedisConsumer {
         Ok(())
     }
 
+    /// Read a batch for this consumer starting after `id`
+    ///
+    /// `>` reads new entries; any other ID reads this consumer's pending
+    /// (delivered but unacknowledged) entries after it.
+    async fn read_group(
+        &self,
+        conn: &mut MultiplexedConnection,
+        id: &str,
+    ) -> redis::RedisResult<redis::Value> {
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP")
+            .arg(&self.config.consumer_group)
+            .arg(&self.config.consumer_name)
+            .arg("COUNT")
+            .arg(self.config.batch_size);
+        if id == ">" {
+            cmd.arg("BLOCK").arg(self.config.block_ms);
+        }
+        cmd.arg("STREAMS")
+            .arg(&self.config.stream_name)
+            .arg(id)
+            .query_async(conn)
+            .await
+    }
+
+    /// Re-process entries pending for this consumer
+    ///
+    /// These were delivered but never acknowledged: entries claimed from dead
+    /// instances, or (with ack-after-persist) entries whose state was never
+    /// persisted because the aggregator crashed.
+    async fn recover_pending(&self, conn: &mut MultiplexedConnection) -> Result<u64> {
+        let mut cursor = "0".to_string();
+        let mut recovered = 0;
+
+        loop {
+            let value = self.read_group(conn, &cursor).await
+                .with_context(|| "Failed to read pending entries")?;
+
+            let entries = match parse_stream_response(&value) {
+                Some(entries) => entries,
+                None => break,
+            };
+
+            if let Some((last_id, _)) = entries.last() {
+                cursor = last_id.clone();
+            }
+            recovered += self.process_entries(conn, entries).await;
+        }
+
+        Ok(recovered)
+    }
+
+    /// Prune dead consumers and process the pending entries claimed from them
+    async fn take_over_dead(&self, conn: &mut MultiplexedConnection) -> u64 {
+        match InstanceRegistry::prune(conn, &self.config, Some(&self.config.consumer_name), false).await {
+            Ok(report) => {
+                if !report.pruned.is_empty() {
+                    info!("Pruned dead consumers: {:?}", report.pruned);
+                }
+                self.process_entries(conn, report.claimed).await
+            }
+            Err(e) => {
+                warn!("Failed to prune dead consumers: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Refresh the stream lag and pending gauges from XINFO GROUPS
+    ///
+    /// `lag` is only reported by Redis 7+; the gauge is left untouched otherwise.
+    async fn update_group_metrics(&self, conn: &mut MultiplexedConnection) {
+        let groups: redis::RedisResult<redis::Value> = redis::cmd("XINFO")
+            .arg("GROUPS")
+            .arg(&self.config.stream_name)
+            .query_async(conn)
+            .await;
+
+        let Ok(redis::Value::Bulk(groups)) = groups else { return };
+        for group in &groups {
+            let fields = parse_info_fields(group);
+            if fields.get("name") != Some(&self.config.consumer_group) {
+                continue;
+            }
+            if let Some(lag) = fields.get("lag").and_then(|v| v.parse().ok()) {
+                metrics().stream_lag.set(lag);
+            }
+            if let Some(pending) = fields.get("pending").and_then(|v| v.parse().ok()) {
+                metrics().stream_pending.set(pending);
+            }
+        }
+    }
+
+    /// Apply a batch of entries to the state and acknowledge (or track) them
+    #[instrument(name = "consume_batch", skip_all, fields(entries = entries.len()))]
+    async fn process_entries(
+        &self,
+        conn: &mut MultiplexedConnection,
+        entries: Vec<(String, String)>,
+    ) -> u64 {
+        let mut processed = 0;
+
+        for (entry_id, data) in entries {
+            if let Some(frame) = parse_frame_data(&data) {
+                // Process the frame
+                let result = self.state.process_frame(&frame);
+                // Received when added to the stream, however late it is consumed
+                let received = StreamId::parse(&entry_id).and_then(|id| id.timestamp()).unwrap_or_else(Utc::now);
+                self.state.clocks.record(&frame, received);
+                self.enrichers.enrich(&frame, &self.state).await;
+
+                // Log new devices/flows
+                for device in &result.new_devices {
+                    debug!("New device discovered: {}", device);
+                }
+                for flow in &result.new_flows {
+                    debug!("New flow: {}:{} -> {}:{}",
+                        flow.src_mac,
+                        flow.src_port.unwrap_or(0),
+                        flow.dst_mac,
+                        flow.dst_port.unwrap_or(0)
+                    );
+                }
+                if let Some(events) = &self.events {
+                    emit_events(events, &frame, &result);
+                }
+
+                processed += 1;
+                metrics().frames_consumed.inc();
+            } else {
+                metrics().frames_invalid.inc();
+            }
+
+            // Unparseable entries count as processed too
+            if let (Some(position), Some(id)) = (&self.position, StreamId::parse(&entry_id)) {
+                position.advance(id);
+            }
+
+            // Acknowledge the message (unparseable entries too, so they
+            // don't linger in the pending list forever)
+            match &self.ack_tracker {
+                Some(tracker) => tracker.track(entry_id),
+                None => {
+                    let _: redis::RedisResult<i64> = redis::cmd("XACK")
+                        .arg(&self.config.stream_name)
+                        .arg(&self.config.consumer_group)
+                        .arg(&entry_id)
+                        .query_async(conn)
+                        .await;
+                }
+            }
+        }
+
+        processed
+    }
+
     /// Run the consumer loop
     pub async fn run(&self, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
         let mut conn = self.connect().await?;
         self.ensure_consumer_group(&mut conn).await?;
 
-        let stream_name = &self.config.stream_name;
-        let group_name = &self.config.consumer_group;
-        let consumer_name = &self.config.consumer_name;
-        let batch_size = self.config.batch_size;
-        let block_ms = self.config.block_ms;
-
         info!(
-            "Starting consumer: stream={}, group={}, consumer={}, batch={}",
-            stream_name, group_name, consumer_name, batch_size
+            "Starting consumer: stream={}, group={}, consumer={}, batch={}, ack_mode={:?}",
+            self.config.stream_name, self.config.consumer_group,
+            self.config.consumer_name, self.config.batch_size, self.config.ack_mode
         );
 
+        if let Some(position) = &self.position {
+            self.report_resume(&mut conn, position).await;
+        }
+
         let mut processed_count: u64 = 0;
         let mut last_log = std::time::Instant::now();
 
+        // Before claiming anything: claimed entries join this consumer's
+        // pending list and must not be read a second time from there
+        let recovered = self.recover_pending(&mut conn).await?;
+        if recovered > 0 {
+            info!("Recovered {} unacknowledged entries from previous runs", recovered);
+        }
+        processed_count += recovered;
+
+        // Take over the pending entries of instances that died, now and on
+        // every heartbeat interval: a predecessor restarted under a new name
+        // only expires after `instance_ttl_secs`
+        let claim_interval = std::time::Duration::from_secs(self.config.heartbeat_interval_secs);
+        processed_count += self.take_over_dead(&mut conn).await;
+        let mut last_claim = std::time::Instant::now();
+
         loop {
             // Check for shutdown
             if shutdown.try_recv().is_ok() {
@@ -89,57 +320,15 @@ impl RedisConsumer {
             }
 
             // Read from stream using consumer group
-            let result: redis::RedisResult<redis::Value> = redis::cmd("XREADGROUP")
-                .arg("GROUP")
-                .arg(group_name)
-                .arg(consumer_name)
-                .arg("COUNT")
-                .arg(batch_size)
-                .arg("BLOCK")
-                .arg(block_ms)
-                .arg("STREAMS")
-                .arg(stream_name)
-                .arg(">")
-                .query_async(&mut conn)
-                .await;
-
-            match result {
+            match self.read_group(&mut conn, ">").await {
                 Ok(redis::Value::Nil) => {
                     // No messages available, continue
                     continue;
                 }
                 Ok(value) => {
                     // Parse and process messages
-                    if let Some(entries) = self.parse_stream_response(&value) {
-                        for (entry_id, data) in entries {
-                            if let Some(frame) = self.parse_frame_data(&data) {
-                                // Process the frame
-                                let result = self.state.process_frame(&frame);
-
-                                // Log new devices/flows
-                                for mac in &result.new_devices {
-                                    debug!("New device discovered: {}", mac.to_string());
-                                }
-                                for flow in &result.new_flows {
-                                    debug!("New flow: {}:{} -> {}:{}",
-                                        flow.src_mac.to_string(),
-                                        flow.src_port.unwrap_or(0),
-                                        flow.dst_mac.to_string(),
-                                        flow.dst_port.unwrap_or(0)
-                                    );
-                                }
-
-                                processed_count += 1;
-
-                                // Acknowledge the message
-                                let _: redis::RedisResult<i64> = redis::cmd("XACK")
-                                    .arg(stream_name)
-                                    .arg(group_name)
-                                    .arg(&entry_id)
-                                    .query_async(&mut conn)
-                                    .await;
-                            }
-                        }
+                    if let Some(entries) = parse_stream_response(&value) {
+                        processed_count += self.process_entries(&mut conn, entries).await;
                     }
                 }
                 Err(e) => {
@@ -148,6 +337,11 @@ impl RedisConsumer {
                 }
             }
 
+            if last_claim.elapsed() >= claim_interval {
+                processed_count += self.take_over_dead(&mut conn).await;
+                last_claim = std::time::Instant::now();
+            }
+
             // Periodic stats logging
             if last_log.elapsed().as_secs() >= 10 {
                 let stats = self.state.stats_snapshot();
@@ -156,6 +350,7 @@ impl RedisConsumer {
                     stats.total_packets, stats.total_bytes,
                     stats.total_devices, stats.total_flows
                 );
+                self.update_group_metrics(&mut conn).await;
                 last_log = std::time::Instant::now();
             }
         }
@@ -163,70 +358,149 @@ impl RedisConsumer {
         info!("Consumer stopped. Total processed: {}", processed_count);
         Ok(())
     }
+}
+
+/// Parse an XREADGROUP response into entry ID and data pairs
+///
+/// Response format: `[[stream_name, [[entry_id, [field, value, ...]], ...]]]`
+pub(crate) fn parse_stream_response(value: &redis::Value) -> Option<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+
+    if let redis::Value::Bulk(streams) = value {
+        for stream in streams {
+            if let redis::Value::Bulk(stream_data) = stream {
+                if stream_data.len() >= 2 {
+                    if let redis::Value::Bulk(messages) = &stream_data[1] {
+                        entries.extend(parse_entry_list(messages));
+                    }
+                }
+            }
+        }
+    }
 
-    /// Parse Redis stream response into entry ID and data pairs
-    fn parse_stream_response(&self, value: &redis::Value) -> Option<Vec<(String, String)>> {
-        // Response format: [[stream_name, [[entry_id, [field, value, ...]], ...]]]
-        let mut entries = Vec::new();
-
-        if let redis::Value::Bulk(streams) = value {
-            for stream in streams {
-                if let redis::Value::Bulk(stream_data) = stream {
-                    if stream_data.len() >= 2 {
-                        if let redis::Value::Bulk(messages) = &stream_data[1] {
-                            for message in messages {
-                                if let redis::Value::Bulk(msg_data) = message {
-                                    if msg_data.len() >= 2 {
-                                        let entry_id = self.value_to_string(&msg_data[0]);
-                                        if let redis::Value::Bulk(fields) = &msg_data[1] {
-                                            // Look for "data" field
-                                            let mut i = 0;
-                                            while i < fields.len() - 1 {
-                                                if let Some(key) = self.value_to_string(&fields[i]) {
-                                                    if key == "data" {
-                                                        if let Some(data) = self.value_to_string(&fields[i + 1]) {
-                                                            if let Some(id) = entry_id.clone() {
-                                                                entries.push((id, data));
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                                i += 2;
-                                            }
-                                        }
-                                    }
-                                }
+    if entries.is_empty() {
+        None
+    } else {
+        Some(entries)
+    }
+}
+
+/// Parse a list of stream entries (the body of XRANGE/XREADGROUP replies)
+///
+/// Entry format: `[entry_id, [field, value, ...]]`; only the `data` field is kept.
+pub(crate) fn parse_entry_list(messages: &[redis::Value]) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+
+    for message in messages {
+        if let redis::Value::Bulk(msg_data) = message {
+            if msg_data.len() >= 2 {
+                let entry_id = value_to_string(&msg_data[0]);
+                if let redis::Value::Bulk(fields) = &msg_data[1] {
+                    // Look for "data" field
+                    for pair in fields.chunks_exact(2) {
+                        if value_to_string(&pair[0]).as_deref() == Some("data") {
+                            if let (Some(id), Some(data)) = (entry_id.clone(), value_to_string(&pair[1])) {
+                                entries.push((id, data));
                             }
                         }
                     }
                 }
             }
         }
+    }
+
+    entries
+}
+
+/// Convert Redis Value to String
+fn value_to_string(value: &redis::Value) -> Option<String> {
+    match value {
+        redis::Value::Data(bytes) => String::from_utf8(bytes.clone()).ok(),
+        redis::Value::Status(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Publish events for the devices and flows `frame` introduced
+pub(crate) fn emit_events(events: &EventSender, frame: &SensorFrame, result: &ProcessResult) {
+    for device in &result.new_devices {
+        let ip = if device.mac == frame.src_mac { frame.src_ip } else { frame.dst_ip };
+        // Sending only fails when nobody subscribes, which is fine
+        let _ = events.send(Event::new_device(*device, frame.sensor, ip, frame.vlan_id(), frame.timestamp));
+    }
+    for flow in &result.new_flows {
+        let _ = events.send(Event::new_flow(flow, frame.timestamp));
+    }
+}
 
-        if entries.is_empty() {
+/// Parse frame data from JSON
+pub(crate) fn parse_frame_data(data: &str) -> Option<SensorFrame> {
+    match serde_json::from_str(data) {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            warn!("Failed to parse frame data: {}", e);
             None
-        } else {
-            Some(entries)
         }
     }
+}
 
-    /// Convert Redis Value to String
-    fn value_to_string(&self, value: &redis::Value) -> Option<String> {
-        match value {
-            redis::Value::Data(bytes) => String::from_utf8(bytes.clone()).ok(),
-            redis::Value::Status(s) => Some(s.clone()),
-            _ => None,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SiteId;
+
+    fn data(s: &str) -> redis::Value {
+        redis::Value::Data(s.as_bytes().to_vec())
     }
 
-    /// Parse frame data from JSON
-    fn parse_frame_data(&self, data: &str) -> Option<CapturedFrame> {
-        match serde_json::from_str(data) {
-            Ok(frame) => Some(frame),
-            Err(e) => {
-                warn!("Failed to parse frame data: {}", e);
-                None
-            }
-        }
+    #[test]
+    fn test_parse_entry_list() {
+        let messages = vec![
+            redis::Value::Bulk(vec![
+                data("1700000000000-0"),
+                redis::Value::Bulk(vec![data("data"), data("{\"a\":1}")]),
+            ]),
+            redis::Value::Bulk(vec![
+                data("1700000000000-1"),
+                redis::Value::Bulk(vec![data("other"), data("x")]),
+            ]),
+        ];
+
+        let entries = parse_entry_list(&messages);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, "1700000000000-0");
+        assert_eq!(entries[0].1, "{\"a\":1}");
+    }
+
+    #[test]
+    fn test_parse_stream_response() {
+        let response = redis::Value::Bulk(vec![redis::Value::Bulk(vec![
+            data("netsentinel:frames"),
+            redis::Value::Bulk(vec![redis::Value::Bulk(vec![
+                data("1-0"),
+                redis::Value::Bulk(vec![data("data"), data("{}")]),
+            ])]),
+        ])]);
+
+        let entries = parse_stream_response(&response).unwrap();
+        assert_eq!(entries, vec![("1-0".to_string(), "{}".to_string())]);
+        assert!(parse_stream_response(&redis::Value::Nil).is_none());
+    }
+
+    #[test]
+    fn test_parse_frame_data() {
+        let data = r#"{"sensor":"paris-core-1","site":"paris","timestamp":"2024-05-01T10:00:00Z",
+            "interface":"eth0","src_mac":"00:11:22:33:44:55","dst_mac":"66:77:88:99:aa:bb",
+            "ethertype":2048,"src_ip":"10.0.0.5","ttl":64,"frame_size":74,"payload_size":0}"#;
+        let frame = parse_frame_data(data).unwrap();
+        assert_eq!(frame.site.as_str(), "paris");
+        assert_eq!(frame.sensor.as_str(), "paris-core-1");
+        assert_eq!(frame.src_mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!(frame.ttl, Some(64));
+
+        // Older sensors stamp neither site nor sensor
+        let data = data.replace(r#""sensor":"paris-core-1","site":"paris","#, "");
+        assert_eq!(parse_frame_data(&data).unwrap().site, SiteId::default());
+        assert!(parse_frame_data(&data.replace("00:11:22:33:44:55", "not-a-mac")).is_none());
     }
 }
@@ -0,0 +1,153 @@
+//! Device ID resolution for the persister
+//!
+//! Rows referencing a device (flows, RTT, hourly traffic...) store its
+//! database ID. The mapping from tenant and MAC address to ID lives in the
+//! `devices` table, whose unique tenant and MAC address make it the same for
+//! every aggregator instance and across restarts: IDs missing from the local
+//! LRU cache are resolved with an upsert that creates the device row when no
+//! instance has persisted it yet.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+
+use crate::db::Database;
+use crate::metrics::metrics;
+use crate::state::{DeviceKey, MacAddr, TenantId, Tenants};
+
+/// Maximum number of devices resolved by a single query
+const RESOLVE_CHUNK_SIZE: usize = 1000;
+
+/// LRU cache of device IDs, backed by the `devices` table
+pub struct DeviceIds {
+    cache: LruCache<(TenantId, MacAddr), uuid::Uuid>,
+    tenants: Tenants,
+}
+
+impl DeviceIds {
+    /// Create a cache holding up to `capacity` IDs
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+            tenants: Tenants::default(),
+        }
+    }
+
+    /// Tell devices of different tenants apart with `tenants`
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Tenant and MAC address `key` has its row under
+    fn row(&self, key: &DeviceKey) -> (TenantId, MacAddr) {
+        (self.tenants.tenant_of(key.site), key.mac)
+    }
+
+    /// Record the ID a device upsert returned
+    pub fn insert(&mut self, key: &DeviceKey, id: uuid::Uuid) {
+        self.cache.put(self.row(key), id);
+    }
+
+    /// Forget the IDs of a purged MAC address, on every tenant
+    pub fn remove(&mut self, mac: &MacAddr) {
+        let rows: Vec<_> = self.cache.iter().map(|(row, _)| *row).filter(|(_, m)| m == mac).collect();
+        for row in rows {
+            self.cache.pop(&row);
+        }
+    }
+
+    /// IDs of `devices`, each with when it was seen
+    ///
+    /// Cache misses are resolved in the database, where a device no
+    /// instance has persisted yet is created with the given site and time;
+    /// its own upsert completes the row later. Devices of the sites of one
+    /// tenant share an ID. Multicast addresses are not devices and are left
+    /// out.
+    pub async fn resolve(
+        &mut self,
+        db: &Database,
+        devices: impl IntoIterator<Item = (DeviceKey, DateTime<Utc>)>,
+    ) -> Result<HashMap<DeviceKey, uuid::Uuid>> {
+        let mut ids = HashMap::new();
+        let mut missing = HashMap::new();
+        let mut unresolved = Vec::new();
+
+        for (key, seen) in devices {
+            if key.mac.is_multicast() || ids.contains_key(&key) {
+                continue;
+            }
+            let row = self.row(&key);
+            match self.cache.get(&row) {
+                Some(id) => {
+                    ids.insert(key, *id);
+                }
+                None => {
+                    missing.entry(row).or_insert((key, seen));
+                    unresolved.push((key, row));
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(ids);
+        }
+        metrics().device_id_misses.inc_by(missing.len() as u64);
+
+        let missing: Vec<(DeviceKey, DateTime<Utc>)> = missing.into_values().collect();
+        let mut resolved = HashMap::new();
+        for chunk in missing.chunks(RESOLVE_CHUNK_SIZE) {
+            for (tenant, mac, id) in db.resolve_device_ids(chunk).await? {
+                self.cache.put((tenant, mac), id);
+                resolved.insert((tenant, mac), id);
+            }
+        }
+        ids.extend(unresolved.into_iter().filter_map(|(key, row)| Some((key, *resolved.get(&row)?))));
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SiteId;
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut ids = DeviceIds::new(2);
+        let keys: Vec<DeviceKey> = (1..=3)
+            .map(|i| DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, i])))
+            .collect();
+        let rows: Vec<_> = keys.iter().map(|key| ids.row(key)).collect();
+
+        ids.insert(&keys[0], uuid::Uuid::new_v4());
+        ids.insert(&keys[1], uuid::Uuid::new_v4());
+        // Using the first ID makes the second the least recently used
+        assert!(ids.cache.get(&rows[0]).is_some());
+        ids.insert(&keys[2], uuid::Uuid::new_v4());
+
+        assert!(ids.cache.contains(&rows[0]));
+        assert!(!ids.cache.contains(&rows[1]));
+        assert!(ids.cache.contains(&rows[2]));
+
+        ids.remove(&keys[0].mac);
+        assert_eq!(ids.cache.len(), 1);
+    }
+
+    #[test]
+    fn test_rows_per_tenant() {
+        let sites = ["paris".to_string()];
+        let ids = DeviceIds::new(2).with_tenants(Tenants::new([("acme", &sites[..])]));
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        // The same MAC address is another device on another tenant's site
+        let paris = ids.row(&DeviceKey::new(SiteId::new("paris").unwrap(), mac));
+        let lyon = ids.row(&DeviceKey::new(SiteId::new("lyon").unwrap(), mac));
+        assert_eq!(paris, (TenantId::new("acme").unwrap(), mac));
+        assert_eq!(lyon, (TenantId::default(), mac));
+        assert_eq!(ids.row(&DeviceKey::new(SiteId::default(), mac)), lyon);
+    }
+}
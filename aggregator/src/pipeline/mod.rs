@@ -1,19 +1,109 @@
 //! Pipeline module for data processing
 
+pub mod ack;
+pub mod channel;
+pub mod checkpoint;
 pub mod consumer;
+pub mod device_ids;
 pub mod persister;
+pub mod registry;
+pub mod replay;
+pub mod retention;
 
+pub use ack::AckTracker;
+pub use channel::ChannelConsumer;
+pub use checkpoint::{StreamHead, StreamPosition};
 pub use consumer::RedisConsumer;
-pub use persister::Persister;
+pub use device_ids::DeviceIds;
+pub use persister::{PersistReport, PersistRequest, Persister, PurgeReport, PERSISTED_TABLES};
+pub use registry::{InstanceInfo, InstanceRegistry, InstanceStatus, PruneReport};
+pub use replay::{Replayer, ReplaySource, ReplayStats};
+pub use retention::{StreamId, StreamTrimmer};
 
+use chrono::Utc;
+use parking_lot::Mutex;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{info, error};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 use anyhow::Result;
 
-use crate::config::Config;
-use crate::state::AggregatorState;
+use crate::admin::AdminServer;
+use crate::alerts::AlertManager;
+use crate::api::{self, ApiState, ApiTokens, RateLimiter};
+use crate::auth_servers::AuthServerClassifier;
+use crate::bandwidth::BandwidthMonitor;
+use crate::beaconing::BeaconDetector;
+use crate::change_report::ChangeReporter;
+use crate::config::{AckMode, Config};
+use crate::clock_drift::ClockDriftMonitor;
+use crate::connections::ConnectionMonitor;
+use crate::state::{AggregatorState, SensorFrame};
 use crate::db::Database;
+use crate::enrich::{Enricher, Enrichers};
+use crate::dns::NxdomainDetector;
+use crate::events::{self, Event, EventPublisher, LifecyclePhase};
+use crate::exfiltration::ExfiltrationDetector;
+use crate::forwarder::Forwarder;
+use crate::ipfix::IpfixExporter;
+use crate::legacy::LegacyProtocolMonitor;
+use crate::logging::LogFilter;
+use crate::notifications;
+use crate::ntp::NtpMonitor;
+use crate::ot::OtWriteMonitor;
+use crate::plugins::PluginHost;
+use crate::metrics;
+use crate::netbox::NetBoxExporter;
+use crate::new_devices::NewDeviceAlerter;
+use crate::rules::RulesEngine;
+use crate::scanners::ScannerDetector;
+use crate::servicenow::ServiceNowExporter;
+use crate::telemetry;
+
+/// Pipeline tasks stopped in order once shutdown is requested
+///
+/// The consumer finishes its in-flight batch and stops reading, the rules
+/// engine forwards its last events, then `drain_tx` stops the persister
+/// (which runs the final persist and ack) and the other tasks.
+struct Drain {
+    /// Consumer, unless it already stopped
+    consumer: Option<JoinHandle<()>>,
+    rules: Option<JoinHandle<()>>,
+    drain_tx: broadcast::Sender<()>,
+    persister: JoinHandle<()>,
+    /// Registry, event sinks, exporters and servers, awaited last
+    others: Vec<JoinHandle<()>>,
+}
+
+impl Drain {
+    /// Wait for every task, giving up after `deadline`; whether all stopped
+    async fn run(self, deadline: tokio::time::Duration) -> bool {
+        info!("Draining pipeline (deadline {:?})", deadline);
+
+        let drain = async {
+            if let Some(h) = self.consumer {
+                let _ = h.await;
+            }
+            if let Some(h) = self.rules {
+                let _ = h.await;
+            }
+            let _ = self.drain_tx.send(());
+            let _ = self.persister.await;
+            for h in self.others {
+                let _ = h.await;
+            }
+        };
+
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            error!(
+                "Drain deadline of {:?} exceeded, exiting without a complete final persist",
+                deadline
+            );
+            return false;
+        }
+        true
+    }
+}
 
 /// Main pipeline orchestrator
 pub struct Pipeline {
@@ -21,13 +111,30 @@ pub struct Pipeline {
     state: Arc<AggregatorState>,
     db: Arc<Database>,
     shutdown_tx: broadcast::Sender<()>,
+    log_filter: Option<LogFilter>,
+    /// Frames handed over in memory, read instead of the Redis stream
+    frames: Mutex<Option<mpsc::Receiver<SensorFrame>>>,
+    enrichers: Enrichers,
 }
 
 impl Pipeline {
     /// Create a new pipeline
     pub async fn new(config: Config) -> Result<Self> {
-        let state = Arc::new(AggregatorState::new());
+        let state = Arc::new(
+            AggregatorState::new()
+                .with_services(config.services.clone())
+                .with_vendors(config.vendors.clone())
+                .with_subnet_prefix(config.aggregation.vlan_subnet_prefix)
+                .with_tenants(config.site_tenants()),
+        );
         let db = Arc::new(Database::connect(&config.database).await?);
+        db.replace_tenant_sites(&state.tenants).await?;
+        for entry in db.pending_maintenance(Utc::now()).await? {
+            state.maintenance.insert(entry);
+        }
+        if !config.tenants.is_empty() {
+            info!("Serving {} tenants", config.tenants.len());
+        }
         let (shutdown_tx, _) = broadcast::channel(1);
 
         Ok(Self {
@@ -35,9 +142,33 @@ impl Pipeline {
             state,
             db,
             shutdown_tx,
+            log_filter: None,
+            frames: Mutex::new(None),
+            enrichers: Enrichers::new(),
         })
     }
 
+    /// Let the admin socket change the log filter
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Read frames from `frames` instead of the Redis stream
+    ///
+    /// Nothing touches Redis then: there is no instance registry, stream
+    /// trimming, deferred acknowledgement or Redis event publishing.
+    pub fn with_frames(self, frames: mpsc::Receiver<SensorFrame>) -> Self {
+        *self.frames.lock() = Some(frames);
+        self
+    }
+
+    /// Run `enricher` on every consumed frame, after those already registered
+    pub fn with_enricher(mut self, enricher: impl Enricher) -> Self {
+        self.enrichers.register(enricher);
+        self
+    }
+
     /// Get the aggregator state
     pub fn state(&self) -> Arc<AggregatorState> {
         Arc::clone(&self.state)
@@ -52,71 +183,425 @@ impl Pipeline {
     pub async fn run(&self) -> Result<()> {
         info!("Starting aggregation pipeline");
 
-        // Create shutdown receivers for each component
+        // The consumer, API, admin socket and metrics endpoint follow the shutdown signal
+        // directly; the persister, registry and event sinks are stopped once
+        // the consumer has drained (see `drain`), so the final batch is still
+        // persisted and its events delivered.
+        let mut shutdown = self.shutdown_tx.subscribe();
         let consumer_shutdown = self.shutdown_tx.subscribe();
-        let persister_shutdown = self.shutdown_tx.subscribe();
-        let events_shutdown = self.shutdown_tx.subscribe();
+        let api_shutdown = self.shutdown_tx.subscribe();
+        let admin_shutdown = self.shutdown_tx.subscribe();
+        let metrics_shutdown = self.shutdown_tx.subscribe();
+        let (drain_tx, _) = broadcast::channel(1);
+        let persister_shutdown = drain_tx.subscribe();
+        let registry_shutdown = drain_tx.subscribe();
+        let events_shutdown = drain_tx.subscribe();
+        let events_tx = events::channel();
+        // Alerts go through the alert manager, which passes on to `events_tx`
+        // those due a notification
+        let alerts_tx = events::channel();
 
-        // Start Redis consumer
-        let consumer = RedisConsumer::new(
-            self.config.redis.clone(),
-            Arc::clone(&self.state),
-        );
-        let consumer_handle = tokio::spawn(async move {
-            if let Err(e) = consumer.run(consumer_shutdown).await {
-                error!("Consumer error: {}", e);
+        // Rules engine (optional): sits between the consumer and the event
+        // sinks, and stops once the consumer (its only sender) is gone
+        let (consumer_events, rules_handle) = match self.config.rules.as_ref().filter(|r| r.enabled) {
+            Some(config) => {
+                let engine = RulesEngine::new(config.clone(), Arc::clone(&self.state))?;
+                let raw_tx = events::channel();
+                let raw_rx = raw_tx.subscribe();
+                (raw_tx, Some(tokio::spawn(engine.run(raw_rx, events_tx.clone(), alerts_tx.clone()))))
             }
-        });
+            None => (events_tx.clone(), None),
+        };
+
+        let frames = self.frames.lock().take();
+        let in_memory = frames.is_some();
+
+        // Deferred acknowledgement (ack-after-persist mode)
+        let ack_tracker = match self.config.redis.ack_mode {
+            _ if in_memory => None,
+            AckMode::Immediate => None,
+            AckMode::AfterPersist => Some(Arc::new(AckTracker::new(&self.config.redis)?)),
+        };
+
+        // Position of the Redis consumer, resumed from its saved checkpoint
+        let position = if in_memory {
+            None
+        } else {
+            let checkpoint = self.load_checkpoint().await;
+            Some(Arc::new(StreamPosition::new(&self.config.redis).with_checkpoint(checkpoint)))
+        };
+
+        // Start the consumer: frames handed over in memory, or the Redis stream
+        let mut consumer_handle = match frames {
+            Some(frames) => {
+                let consumer = ChannelConsumer::new(Arc::clone(&self.state), frames)
+                    .with_events(consumer_events)
+                    .with_enrichers(self.enrichers.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = consumer.run(consumer_shutdown).await {
+                        error!("Consumer error: {}", e);
+                    }
+                })
+            }
+            None => {
+                let mut consumer = RedisConsumer::new(
+                    self.config.redis.clone(),
+                    Arc::clone(&self.state),
+                );
+                if let Some(tracker) = &ack_tracker {
+                    consumer = consumer.with_ack_tracker(Arc::clone(tracker));
+                }
+                if let Some(position) = &position {
+                    consumer = consumer.with_position(Arc::clone(position));
+                }
+                consumer = consumer.with_events(consumer_events).with_enrichers(self.enrichers.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = consumer.run(consumer_shutdown).await {
+                        error!("Consumer error: {}", e);
+                    }
+                })
+            }
+        };
 
         // Start persister
-        let persister = Persister::new(
+        let mut persister = Persister::new(
             self.config.aggregation.clone(),
             Arc::clone(&self.state),
             Arc::clone(&self.db),
         );
+        if let Some(tracker) = ack_tracker {
+            persister = persister.with_ack_tracker(tracker);
+        }
+        if let Some(position) = position {
+            persister = persister.with_stream_position(position);
+        }
+        let (persist_requests, requests_rx) = mpsc::channel(8);
+        if self.config.admin.enabled || self.config.api.enabled {
+            persister = persister.with_requests(requests_rx);
+        }
         let persister_handle = tokio::spawn(async move {
             if let Err(e) = persister.run(persister_shutdown).await {
                 error!("Persister error: {}", e);
             }
         });
 
+        // Register this instance and keep its heartbeat fresh
+        let registry_handle = (!in_memory).then(|| {
+            let registry = InstanceRegistry::new(self.config.redis.clone());
+            tokio::spawn(async move {
+                if let Err(e) = registry.run(registry_shutdown).await {
+                    error!("Instance registry error: {}", e);
+                }
+            })
+        });
+
+        // Trim the frames stream (optional)
+        let trimmer_handle = self.config.stream_retention.as_ref().filter(|r| r.enabled && !in_memory).map(|config| {
+            let trimmer = StreamTrimmer::new(self.config.redis.clone(), config.clone());
+            tokio::spawn(trimmer.run(self.shutdown_tx.subscribe()))
+        });
+
         // Start event publisher (optional)
-        let events_handle = if self.config.events.publish_new_devices ||
-                              self.config.events.publish_new_flows {
-            let config = self.config.clone();
+        let events_config = &self.config.events;
+        let events_handle = if !in_memory
+            && (events_config.publish_new_devices || events_config.publish_new_flows || events_config.publish_alerts)
+        {
+            let publisher = EventPublisher::new(events_config.clone(), &self.config.redis.url)?;
+            let events_rx = events_tx.subscribe();
+            Some(tokio::spawn(async move {
+                if let Err(e) = publisher.run(events_rx, events_shutdown).await {
+                    error!("Event publisher error: {}", e);
+                }
+            }))
+        } else {
+            None
+        };
+
+        // Start notification sinks
+        let mut notifier_handles = notifications::spawn(&self.config.notifications, &events_tx, &drain_tx)?;
+
+        // Start new-device alerts (optional)
+        if let Some(config) = self.config.new_device_alerts.as_ref().filter(|a| a.enabled) {
+            let alerter = NewDeviceAlerter::new(config.clone())?;
+            notifier_handles.push(tokio::spawn(alerter.run(events_tx.subscribe(), alerts_tx.clone(), drain_tx.subscribe())));
+        }
+
+        // Start beaconing detection (optional)
+        if let Some(config) = self.config.beaconing.as_ref().filter(|b| b.enabled) {
+            let detector = BeaconDetector::new(config.clone(), Arc::clone(&self.state));
+            notifier_handles.push(tokio::spawn(detector.run(events_tx.subscribe(), alerts_tx.clone(), drain_tx.subscribe())));
+        }
+
+        // Start WASM detection plugins (optional)
+        if let Some(config) = self.config.plugins.as_ref().filter(|p| p.enabled) {
+            let host = PluginHost::new(config.clone(), Arc::clone(&self.state))?;
+            notifier_handles.push(tokio::spawn(host.run(events_tx.subscribe(), alerts_tx.clone(), drain_tx.subscribe())));
+        }
+
+        // Start external scanner identification (optional)
+        if let Some(config) = self.config.scanners.as_ref().filter(|s| s.enabled) {
+            let detector = ScannerDetector::new(config.clone(), Arc::clone(&self.state))
+                .with_database(Arc::clone(&self.db));
+            notifier_handles.push(tokio::spawn(detector.run(events_tx.subscribe(), alerts_tx.clone(), drain_tx.subscribe())));
+        }
+
+        // Deduplicate and record alerts
+        let manager = AlertManager::new(self.config.alerts.clone(), Arc::clone(&self.db))
+            .with_state(Arc::clone(&self.state));
+        notifier_handles.push(tokio::spawn(manager.run(alerts_tx.subscribe(), events_tx.clone(), drain_tx.subscribe())));
+
+        // Start bulk forwarder (optional)
+        if let Some(config) = self.config.forwarder.as_ref().filter(|f| f.enabled) {
+            let forwarder = Forwarder::new(config.clone(), Arc::clone(&self.state))?;
+            notifier_handles.push(tokio::spawn(forwarder.run(events_tx.subscribe(), drain_tx.subscribe())));
+        }
+
+        // Start IPFIX flow export (optional)
+        if let Some(config) = self.config.ipfix.as_ref().filter(|i| i.enabled) {
+            let exporter = IpfixExporter::new(config.clone(), Arc::clone(&self.state));
+            notifier_handles.push(tokio::spawn(exporter.run(drain_tx.subscribe())));
+        }
+
+        // Start inventory exporters and the state-scanning detectors (optional)
+        let mut exporter_handles = Vec::new();
+        if let Some(config) = self.config.bandwidth.as_ref().filter(|b| b.enabled) {
+            let monitor = BandwidthMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.exfiltration.as_ref().filter(|e| e.enabled) {
+            let detector = ExfiltrationDetector::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(detector.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.connections.as_ref().filter(|c| c.enabled) {
+            let monitor = ConnectionMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.dns.as_ref().filter(|d| d.enabled) {
+            let detector = NxdomainDetector::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(detector.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.clock_drift.as_ref().filter(|c| c.enabled) {
+            let monitor = ClockDriftMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.ntp.as_ref().filter(|n| n.enabled) {
+            let monitor = NtpMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.ot.as_ref().filter(|o| o.enabled) {
+            let monitor = OtWriteMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.legacy_protocols.as_ref().filter(|l| l.enabled) {
+            let monitor = LegacyProtocolMonitor::new(config.clone(), Arc::clone(&self.state));
+            exporter_handles.push(tokio::spawn(monitor.run(alerts_tx.clone(), self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.auth_servers.as_ref().filter(|a| a.enabled) {
+            let classifier = AuthServerClassifier::new(config.clone(), Arc::clone(&self.db));
+            exporter_handles.push(tokio::spawn(classifier.run(self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.netbox.as_ref().filter(|n| n.enabled) {
+            let exporter = NetBoxExporter::new(
+                config.clone(),
+                Arc::clone(&self.db),
+                self.config.aggregation.inactivity_timeout,
+            )?;
+            exporter_handles.push(tokio::spawn(exporter.run(self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.servicenow.as_ref().filter(|s| s.enabled) {
+            let exporter = ServiceNowExporter::new(
+                config.clone(),
+                Arc::clone(&self.db),
+                self.config.aggregation.inactivity_timeout,
+            )?;
+            exporter_handles.push(tokio::spawn(exporter.run(self.shutdown_tx.subscribe())));
+        }
+        if let Some(config) = self.config.change_report.as_ref().filter(|c| c.enabled) {
+            let reporter = ChangeReporter::new(
+                config.clone(),
+                Arc::clone(&self.db),
+                self.config.aggregation.inactivity_timeout,
+            )
+            .with_events(events_tx.clone());
+            exporter_handles.push(tokio::spawn(reporter.run(self.shutdown_tx.subscribe())));
+        }
+
+        // Start HTTP API (optional)
+        let api_handle = if self.config.api.enabled {
+            let api_config = self.config.api.clone();
+            let api_state = ApiState {
+                state: Arc::clone(&self.state),
+                db: Arc::clone(&self.db),
+                inactivity_timeout: self.config.aggregation.inactivity_timeout,
+                vlan_subnet_prefix: self.config.aggregation.vlan_subnet_prefix,
+                redis: (!in_memory).then(|| self.config.redis.clone()),
+                tokens: ApiTokens::new(&self.config.api, &self.config.tenants)?,
+                persister: persist_requests.clone(),
+                limiter: Arc::new(RateLimiter::new(self.config.api.requests_per_minute, self.config.api.burst)),
+            };
+            Some(tokio::spawn(async move {
+                if let Err(e) = api::serve(api_config, api_state, api_shutdown).await {
+                    error!("HTTP API error: {:#}", e);
+                }
+            }))
+        } else {
+            None
+        };
+
+        // Start the local admin socket (optional)
+        let admin_handle = if self.config.admin.enabled {
+            let mut server = AdminServer::new(self.config.admin.clone(), Arc::clone(&self.state), persist_requests);
+            if let Some(log_filter) = &self.log_filter {
+                server = server.with_log_filter(log_filter.clone());
+            }
+            Some(tokio::spawn(async move {
+                if let Err(e) = server.run(admin_shutdown).await {
+                    error!("Admin socket error: {:#}", e);
+                }
+            }))
+        } else {
+            None
+        };
+
+        // Export metrics over OTLP (optional)
+        if self.config.telemetry.enabled && self.config.telemetry.metrics {
+            telemetry::observe(Arc::clone(&self.state));
+        }
+
+        // Start metrics endpoint (optional)
+        let metrics_handle = if self.config.metrics.enabled {
+            let metrics_config = self.config.metrics.clone();
             let state = Arc::clone(&self.state);
+            let inactivity_timeout = self.config.aggregation.inactivity_timeout;
             Some(tokio::spawn(async move {
-                // Event publishing logic would go here
-                let mut shutdown = events_shutdown;
-                loop {
-                    tokio::select! {
-                        _ = shutdown.recv() => {
-                            info!("Event publisher shutting down");
-                            break;
-                        }
-                        _ = tokio::time::sleep(tokio::time::Duration::from_secs(1)) => {
-                            // Check for new events to publish
-                        }
-                    }
+                if let Err(e) = metrics::serve(metrics_config, state, inactivity_timeout, metrics_shutdown).await {
+                    error!("Metrics endpoint error: {:#}", e);
                 }
             }))
         } else {
             None
         };
 
-        // Wait for all tasks
-        let _ = consumer_handle.await;
-        let _ = persister_handle.await;
-        if let Some(h) = events_handle {
-            let _ = h.await;
+        // Every task subscribed to events is running
+        let instance = &self.config.redis.consumer_name;
+        let _ = events_tx.send(Event::lifecycle(instance, LifecyclePhase::Started));
+
+        // Run until shutdown is requested (or the consumer stops on its own)
+        let mut consumer_done = false;
+        tokio::select! {
+            _ = shutdown.recv() => {}
+            _ = &mut consumer_handle => {
+                warn!("Consumer stopped unexpectedly, shutting down pipeline");
+                consumer_done = true;
+                // Stop the tasks that follow the shutdown signal directly
+                let _ = self.shutdown_tx.send(());
+            }
+        }
+
+        // Sinks drain their queue, this reaches them before they stop
+        let _ = events_tx.send(Event::lifecycle(instance, LifecyclePhase::Stopping));
+
+        // Drain in order, the final persist before deregistering (see `Drain`)
+        let deadline = tokio::time::Duration::from_secs(self.config.aggregation.drain_timeout_secs);
+        let mut others: Vec<JoinHandle<()>> = registry_handle.into_iter().chain(trimmer_handle).chain(events_handle).collect();
+        others.extend(notifier_handles);
+        others.extend(exporter_handles);
+        others.extend(api_handle.into_iter().chain(admin_handle).chain(metrics_handle));
+        let drain = Drain {
+            consumer: (!consumer_done).then_some(consumer_handle),
+            rules: rules_handle,
+            drain_tx,
+            persister: persister_handle,
+            others,
+        };
+        if !drain.run(deadline).await {
+            return Ok(());
         }
 
         info!("Pipeline stopped");
         Ok(())
     }
 
+    /// Checkpoint a previous run of this consumer saved
+    async fn load_checkpoint(&self) -> Option<StreamId> {
+        let redis = &self.config.redis;
+        match self.db.stream_checkpoints(&redis.stream_name, &redis.consumer_group).await {
+            Ok(checkpoints) => checkpoints
+                .into_iter()
+                .find(|c| c.consumer == redis.consumer_name)
+                .and_then(|c| StreamId::parse(&c.last_id)),
+            Err(e) => {
+                warn!("Failed to load the stream checkpoint: {}", e);
+                None
+            }
+        }
+    }
+
     /// Signal shutdown
     pub fn shutdown(&self) {
         let _ = self.shutdown_tx.send(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Persister recording the packets in the state when told to stop
+    fn persister(state: Arc<AggregatorState>, drain_tx: &broadcast::Sender<()>) -> (JoinHandle<()>, Arc<Mutex<Option<u64>>>) {
+        let persisted = Arc::new(Mutex::new(None));
+        let mut shutdown = drain_tx.subscribe();
+        let recorded = Arc::clone(&persisted);
+        let handle = tokio::spawn(async move {
+            let _ = shutdown.recv().await;
+            *recorded.lock() = Some(state.stats_snapshot().total_packets);
+        });
+        (handle, persisted)
+    }
+
+    fn frame() -> SensorFrame {
+        serde_json::from_value(serde_json::json!({
+            "site": "lab",
+            "sensor": "laptop",
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": "00:11:22:33:44:55",
+            "dst_mac": "66:77:88:99:aa:bb",
+            "ethertype": 0x0800,
+            "frame_size": 60,
+            "payload_size": 0,
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain_persists_last_batch() {
+        let state = Arc::new(AggregatorState::new());
+        let (frames_tx, frames) = mpsc::channel(16);
+        let (shutdown_tx, shutdown) = broadcast::channel(1);
+        let (drain_tx, _) = broadcast::channel(1);
+
+        for _ in 0..3 {
+            frames_tx.send(frame()).await.unwrap();
+        }
+        let (persister, persisted) = persister(Arc::clone(&state), &drain_tx);
+        let consumer = ChannelConsumer::new(Arc::clone(&state), frames);
+        shutdown_tx.send(()).unwrap();
+        let consumer = tokio::spawn(async move { consumer.run(shutdown).await.unwrap() });
+
+        let drain = Drain { consumer: Some(consumer), rules: None, drain_tx, persister, others: Vec::new() };
+        assert!(drain.run(Duration::from_secs(5)).await);
+        assert_eq!(*persisted.lock(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        let (drain_tx, _) = broadcast::channel(1);
+        // A final persist that never completes
+        let persister = tokio::spawn(std::future::pending::<()>());
+
+        let drain = Drain { consumer: None, rules: None, drain_tx, persister, others: Vec::new() };
+        let drained = tokio::time::timeout(Duration::from_secs(5), drain.run(Duration::from_millis(50))).await;
+        assert_eq!(drained.ok(), Some(false));
+    }
+}
@@ -1,22 +1,139 @@
 //! Periodic persistence of aggregated state to PostgreSQL
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{debug, error, info, warn};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, error, info, instrument, warn, Span};
 use uuid::Uuid;
 
 use crate::config::AggregationConfig;
 use crate::db::Database;
-use crate::state::{AggregatorState, MacAddr};
+use crate::metrics::metrics;
+use crate::state::{composition, AggregatorState, DeviceKey, HourlyEncryption, HourlyQos, MacAddr, SiteId};
+
+use super::ack::AckTracker;
+use super::checkpoint::StreamPosition;
+use super::device_ids::DeviceIds;
+use super::retention::StreamId;
+
+/// Flows updated per backfill query
+const BACKFILL_BATCH_SIZE: usize = 10_000;
+
+/// Tables a persist cycle writes, in dependency order
+///
+/// Replay clones each into its schema before persisting there: a table the
+/// persister writes that is missing here would get the replayed rows in
+/// `public`, next to the live ones.
+pub const PERSISTED_TABLES: &[&str] = &[
+    "devices",
+    "device_sites",
+    "device_ips",
+    "traffic_flows",
+    "protocol_stats",
+    "vlans",
+    "vlan_subnets",
+    "l2_segments",
+    "device_segments",
+    "device_rtt",
+    "device_hourly_traffic",
+    "device_qos_hourly",
+    "device_encryption_hourly",
+    "subnet_traffic_hourly",
+    "service_dependencies",
+    "tls_observations",
+    "tls_fingerprints",
+    "dhcp_leases",
+    "device_dns_hourly",
+    "device_anomaly_hourly",
+    "multicast_groups",
+    "device_ntp_servers",
+    "ot_devices",
+    "ot_writes",
+    "sip_calls",
+];
+
+/// Outcome of one persist cycle
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PersistReport {
+    pub devices: usize,
+    pub flows: usize,
+    pub protocols: usize,
+    pub vlans: usize,
+    /// Subnets inferred on VLANs
+    pub vlan_subnets: usize,
+    /// Inferred L2 segments
+    pub segments: usize,
+    /// Device pairs with new RTT samples
+    pub rtt: usize,
+    /// Device hours of traffic composition
+    pub composition: usize,
+    /// Device hours of traffic by QoS class
+    pub qos: usize,
+    /// Device hours of encrypted and plaintext traffic
+    pub encryption: usize,
+    /// Site hours of traffic between subnets
+    pub subnet_matrix: usize,
+    /// Service dependency edges with new traffic
+    pub dependencies: usize,
+    /// Client TLS destinations
+    pub tls: usize,
+    /// Device JA3 and JA3S fingerprints with new sightings
+    pub fingerprints: usize,
+    /// DHCP lease events
+    pub leases: usize,
+    /// Device hours of DNS activity
+    pub dns: usize,
+    /// Device hours of anomaly scores, by detector
+    pub anomalies: usize,
+    /// Multicast groups with new members, senders or traffic
+    pub multicast: usize,
+    /// Device time servers with new requests or responses
+    pub ntp: usize,
+    /// Industrial devices and writes with new messages
+    pub ot: usize,
+    /// VoIP calls with new signaling or media
+    pub calls: usize,
+    /// Rows that failed to persist
+    pub failures: usize,
+    #[serde(skip)]
+    pub elapsed: std::time::Duration,
+}
+
+/// Device and flow rows removed by a purge
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PurgeReport {
+    /// Per-site devices removed from memory
+    pub devices: usize,
+    pub flows: usize,
+    pub db_devices: u64,
+    pub db_flows: u64,
+}
+
+/// Work asked of a running persister outside its schedule (admin socket)
+pub enum PersistRequest {
+    /// Run a persist cycle now
+    Persist(oneshot::Sender<Result<PersistReport>>),
+    /// Run a persist cycle, then evict the flows it wrote from memory
+    FlushFlows(oneshot::Sender<Result<usize>>),
+    /// Forget a MAC address on every site, in memory and in the database
+    PurgeDevice(MacAddr, oneshot::Sender<Result<PurgeReport>>),
+}
 
 /// Persists aggregated state to the database periodically
 pub struct Persister {
     config: AggregationConfig,
     state: Arc<AggregatorState>,
     db: Arc<Database>,
-    device_ids: HashMap<MacAddr, Uuid>,
+    device_ids: DeviceIds,
+    ack_tracker: Option<Arc<AckTracker>>,
+    position: Option<Arc<StreamPosition>>,
+    /// Last checkpoint written to the database
+    saved_checkpoint: Option<StreamId>,
+    requests: Option<mpsc::Receiver<PersistRequest>>,
 }
 
 impl Persister {
@@ -27,13 +144,36 @@ impl Persister {
         db: Arc<Database>,
     ) -> Self {
         Self {
+            device_ids: DeviceIds::new(config.device_id_cache_size).with_tenants(state.tenants.clone()),
             config,
             state,
             db,
-            device_ids: HashMap::new(),
+            ack_tracker: None,
+            position: None,
+            saved_checkpoint: None,
+            requests: None,
         }
     }
 
+    /// Acknowledge tracked stream entries after each successful persist
+    pub fn with_ack_tracker(mut self, tracker: Arc<AckTracker>) -> Self {
+        self.ack_tracker = Some(tracker);
+        self
+    }
+
+    /// Save the consumer's checkpoint after each successful persist
+    pub fn with_stream_position(mut self, position: Arc<StreamPosition>) -> Self {
+        self.saved_checkpoint = position.processed();
+        self.position = Some(position);
+        self
+    }
+
+    /// Serve persist, flush and purge requests between scheduled cycles
+    pub fn with_requests(mut self, requests: mpsc::Receiver<PersistRequest>) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
     /// Run the persistence loop
     pub async fn run(mut self, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
         let interval = tokio::time::Duration::from_secs(self.config.persist_interval_secs);
@@ -42,22 +182,26 @@ impl Persister {
             "Starting persister with interval of {} seconds",
             self.config.persist_interval_secs
         );
+        self.backfill_flows().await;
 
         loop {
             tokio::select! {
                 _ = shutdown.recv() => {
                     info!("Persister received shutdown signal");
                     // Final persistence before shutdown
-                    if let Err(e) = self.persist_all().await {
+                    if let Err(e) = self.persist_cycle().await {
                         error!("Error in final persistence: {}", e);
                     }
                     break;
                 }
                 _ = tokio::time::sleep(interval) => {
-                    if let Err(e) = self.persist_all().await {
+                    if let Err(e) = self.persist_cycle().await {
                         error!("Error persisting state: {}", e);
                     }
                 }
+                Some(request) = next_request(&mut self.requests) => {
+                    self.serve(request).await;
+                }
             }
         }
 
@@ -65,60 +209,284 @@ impl Persister {
         Ok(())
     }
 
+    /// Answer a request from the admin socket
+    async fn serve(&mut self, request: PersistRequest) {
+        match request {
+            PersistRequest::Persist(reply) => {
+                info!("Persist requested");
+                let _ = reply.send(self.persist_cycle().await);
+            }
+            PersistRequest::FlushFlows(reply) => {
+                info!("Flow flush requested");
+                let _ = reply.send(self.flush_flows().await);
+            }
+            PersistRequest::PurgeDevice(mac, reply) => {
+                info!("Purge of device {} requested", mac);
+                let _ = reply.send(self.purge_device(mac).await);
+            }
+        }
+    }
+
+    /// Persist all state, then evict the flows written from memory
+    ///
+    /// Flows updated while the cycle ran are kept. An evicted flow seen
+    /// again starts over from zero, as after a restart.
+    async fn flush_flows(&mut self) -> Result<usize> {
+        let started = Utc::now().timestamp() as u64;
+        let report = self.persist_cycle().await?;
+        if report.failures > 0 {
+            anyhow::bail!("{} rows failed to persist, flows kept in memory", report.failures);
+        }
+
+        let mut evicted = 0;
+        self.state.flows.retain(|_, flow| {
+            let keep = flow.last_seen.load(Ordering::Relaxed) >= started;
+            evicted += usize::from(!keep);
+            keep
+        });
+        info!("Evicted {} flows after persisting them", evicted);
+        Ok(evicted)
+    }
+
+    /// Forget `mac` in memory, then delete it from the database
+    async fn purge_device(&mut self, mac: MacAddr) -> Result<PurgeReport> {
+        let (devices, flows) = self.state.purge_device(mac);
+        self.device_ids.remove(&mac);
+        let (db_devices, db_flows) = self.db.purge_device(&mac).await?;
+
+        Ok(PurgeReport { devices, flows, db_devices, db_flows })
+    }
+
+    /// Persist all state, then checkpoint and acknowledge the stream
+    /// entries it covers
+    ///
+    /// The checkpoint only moves when every row of the cycle was written.
+    pub async fn persist_cycle(&mut self) -> Result<PersistReport> {
+        // Entries applied so far are written by this cycle
+        let processed = self.position.as_ref().and_then(|position| position.processed());
+
+        let report = self.persist_and_ack().await?;
+        if report.failures == 0 {
+            if let Some(id) = processed {
+                self.save_checkpoint(id).await;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Record `id` as the last entry persisted, when it moved
+    async fn save_checkpoint(&mut self, id: StreamId) {
+        let Some(position) = &self.position else { return };
+        if self.saved_checkpoint == Some(id) {
+            return;
+        }
+
+        let last_id = id.to_string();
+        match self.db.save_stream_checkpoint(&position.stream, &position.consumer_group, &position.consumer, &last_id).await {
+            Ok(()) => self.saved_checkpoint = Some(id),
+            Err(e) => warn!("Failed to save stream checkpoint {}: {}", last_id, e),
+        }
+    }
+
+    /// Persist all state, then acknowledge the stream entries it covers
+    ///
+    /// With an ack tracker, entries are only XACKed when every row of the
+    /// cycle was written; otherwise they are retried with the next cycle,
+    /// until they failed `ack_max_failed_cycles` cycles.
+    async fn persist_and_ack(&mut self) -> Result<PersistReport> {
+        let tracker = match &self.ack_tracker {
+            Some(tracker) => Arc::clone(tracker),
+            None => return self.persist_all().await,
+        };
+
+        // Entries tracked so far have already been applied to the state
+        let entry_ids = tracker.begin_cycle();
+
+        match self.persist_all().await {
+            Ok(report) if report.failures == 0 => {
+                tracker.commit_cycle(entry_ids).await?;
+                Ok(report)
+            }
+            Ok(report) => {
+                warn!(
+                    "{} rows failed to persist, deferring ack of {} entries",
+                    report.failures, entry_ids.len()
+                );
+                let exhausted = tracker.abort_cycle(entry_ids);
+                give_up(&tracker, exhausted).await;
+                Ok(report)
+            }
+            Err(e) => {
+                let exhausted = tracker.abort_cycle(entry_ids);
+                give_up(&tracker, exhausted).await;
+                Err(e)
+            }
+        }
+    }
+
     /// Persist all state to the database
-    async fn persist_all(&mut self) -> Result<()> {
+    #[instrument(name = "persist_cycle", skip_all, fields(devices, flows, protocols, vlans, vlan_subnets, segments, rtt, composition, qos, encryption, subnet_matrix, dependencies, tls, fingerprints, leases, dns, anomalies, multicast, ntp, ot, calls, failures))]
+    pub async fn persist_all(&mut self) -> Result<PersistReport> {
         let start = std::time::Instant::now();
+        let mut report = PersistReport::default();
 
         // Persist devices
-        let device_count = self.persist_devices().await?;
+        report.devices = self.persist_devices(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
 
         // Persist flows
-        let flow_count = self.persist_flows().await?;
+        report.flows = self.persist_flows(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
 
         // Persist protocols
-        let protocol_count = self.persist_protocols().await?;
+        report.protocols = self.persist_protocols(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
 
         // Persist VLANs
-        let vlan_count = self.persist_vlans().await?;
+        report.vlans = self.persist_vlans(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist the VLAN to subnet mapping
+        report.vlan_subnets = self.persist_vlan_subnets(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist the inferred L2 segments
+        report.segments = self.persist_segments(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist handshake RTTs
+        report.rtt = self.persist_rtt(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist hourly traffic composition
+        report.composition = self.persist_composition(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist hourly traffic by QoS class
+        report.qos = self.persist_qos(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist the encrypted and plaintext traffic of devices
+        report.encryption = self.persist_encryption(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist hourly traffic between subnets
+        report.subnet_matrix = self.persist_subnet_matrix(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist service dependency edges
+        report.dependencies = self.persist_dependencies(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist TLS server names and certificates
+        report.tls = self.persist_tls(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist JA3/JA3S fingerprints
+        report.fingerprints = self.persist_fingerprints(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist DHCP lease events
+        report.leases = self.persist_leases(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist hourly DNS analytics
+        report.dns = self.persist_dns(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist hourly anomaly scores
+        report.anomalies = self.persist_anomalies(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist multicast group members, senders and bandwidth
+        report.multicast = self.persist_multicast(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist the time servers devices query
+        report.ntp = self.persist_ntp(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        // Persist the industrial devices and the writes to them
+        report.ot = self.persist_ot(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
 
-        let elapsed = start.elapsed();
+        // Persist the VoIP calls and the bandwidth of their media
+        report.calls = self.persist_calls(&mut report.failures).await
+            .inspect_err(|_| metrics().db_errors.inc())?;
+
+        report.elapsed = start.elapsed();
+        metrics().persist_duration.observe(report.elapsed.as_secs_f64());
+        metrics().db_errors.inc_by(report.failures as u64);
+        Span::current()
+            .record("devices", report.devices)
+            .record("flows", report.flows)
+            .record("protocols", report.protocols)
+            .record("vlans", report.vlans)
+            .record("vlan_subnets", report.vlan_subnets)
+            .record("segments", report.segments)
+            .record("rtt", report.rtt)
+            .record("composition", report.composition)
+            .record("qos", report.qos)
+            .record("encryption", report.encryption)
+            .record("subnet_matrix", report.subnet_matrix)
+            .record("dependencies", report.dependencies)
+            .record("tls", report.tls)
+            .record("fingerprints", report.fingerprints)
+            .record("leases", report.leases)
+            .record("dns", report.dns)
+            .record("anomalies", report.anomalies)
+            .record("multicast", report.multicast)
+            .record("ntp", report.ntp)
+            .record("ot", report.ot)
+            .record("calls", report.calls)
+            .record("failures", report.failures);
         info!(
-            "Persisted {} devices, {} flows, {} protocols, {} vlans in {:?}",
-            device_count, flow_count, protocol_count, vlan_count, elapsed
+            "Persisted {} devices, {} flows, {} protocols, {} vlans, {} vlan subnets, {} l2 segments, {} rtt pairs, \
+             {} device hours, {} qos hours, {} encryption hours, {} subnet matrix hours, {} dependencies, {} tls destinations, {} tls fingerprints, {} lease events, \
+             {} dns hours, {} anomaly score hours, {} multicast groups, {} ntp servers, {} ot devices and writes, {} calls in {:?}",
+            report.devices, report.flows, report.protocols, report.vlans, report.vlan_subnets, report.segments, report.rtt,
+            report.composition, report.qos, report.encryption, report.subnet_matrix, report.dependencies, report.tls, report.fingerprints, report.leases, report.dns,
+            report.anomalies, report.multicast, report.ntp, report.ot, report.calls, report.elapsed
         );
 
-        Ok(())
+        Ok(report)
     }
 
     /// Persist all devices
-    async fn persist_devices(&mut self) -> Result<usize> {
+    async fn persist_devices(&mut self, failures: &mut usize) -> Result<usize> {
         let mut count = 0;
 
         // Iterate over all devices in state
         for entry in self.state.devices.iter() {
-            let mac = entry.key().clone();
+            let key = *entry.key();
             let device = entry.value();
 
-            match self.db.upsert_device(&mac, device).await {
+            match self.db.upsert_device(&key, device).await {
                 Ok(device_id) => {
-                    // Cache the device ID for flow persistence
-                    self.device_ids.insert(mac.clone(), device_id);
+                    // Cache the device ID for the rows referencing it
+                    self.device_ids.insert(&key, device_id);
 
                     // Persist associated IPs
                     for ip_entry in device.ips.iter() {
                         let ip = *ip_entry.key();
                         let ip_state = ip_entry.value();
                         let vlan_id = ip_state.vlan_id;
+                        let arp_last_seen = match ip_state.arp_last_seen.load(Ordering::Relaxed) {
+                            0 => None,
+                            ts => DateTime::from_timestamp(ts as i64, 0),
+                        };
 
-                        if let Err(e) = self.db.upsert_device_ip(device_id, ip, vlan_id).await {
+                        if let Err(e) = self.db.upsert_device_ip(device_id, ip, vlan_id, arp_last_seen).await {
                             warn!("Failed to persist device IP {}: {}", ip, e);
+                            *failures += 1;
                         }
                     }
 
                     count += 1;
                 }
                 Err(e) => {
-                    warn!("Failed to persist device {}: {}", mac.to_string(), e);
+                    warn!("Failed to persist device {}: {}", key, e);
+                    *failures += 1;
                 }
             }
         }
@@ -127,23 +495,33 @@ impl Persister {
     }
 
     /// Persist all flows
-    async fn persist_flows(&self) -> Result<usize> {
+    async fn persist_flows(&mut self, failures: &mut usize) -> Result<usize> {
         let mut count = 0;
 
+        let endpoints: Vec<(DeviceKey, DateTime<Utc>)> = self.state.flows.iter()
+            .flat_map(|entry| {
+                let last_seen = entry.value().last_seen.load(Ordering::Relaxed) as i64;
+                let seen = DateTime::from_timestamp(last_seen, 0).unwrap_or_else(Utc::now);
+                [(entry.key().src_device(), seen), (entry.key().dst_device(), seen)]
+            })
+            .collect();
+        let device_ids = self.device_ids.resolve(&self.db, endpoints).await?;
+
         for entry in self.state.flows.iter() {
             let key = entry.key();
             let flow = entry.value();
 
-            // Look up device IDs
-            let src_device_id = self.device_ids.get(&key.src_mac).copied();
-            let dst_device_id = self.device_ids.get(&key.dst_mac).copied();
+            let src_device_id = device_ids.get(&key.src_device()).copied();
+            let dst_device_id = device_ids.get(&key.dst_device()).copied();
 
-            match self.db.upsert_flow(key, flow, src_device_id, dst_device_id).await {
+            let service = key.service(&self.state.services);
+            match self.db.upsert_flow(key, flow, service, src_device_id, dst_device_id).await {
                 Ok(_flow_id) => {
                     count += 1;
                 }
                 Err(e) => {
                     debug!("Failed to persist flow: {}", e);
+                    *failures += 1;
                 }
             }
         }
@@ -152,7 +530,7 @@ impl Persister {
     }
 
     /// Persist protocol statistics
-    async fn persist_protocols(&self) -> Result<usize> {
+    async fn persist_protocols(&self, failures: &mut usize) -> Result<usize> {
         let mut count = 0;
 
         for entry in self.state.protocols.iter() {
@@ -161,6 +539,7 @@ impl Persister {
 
             if let Err(e) = self.db.upsert_protocol(*ethertype, *ip_protocol, stats).await {
                 debug!("Failed to persist protocol stats: {}", e);
+                *failures += 1;
             } else {
                 count += 1;
             }
@@ -170,15 +549,314 @@ impl Persister {
     }
 
     /// Persist VLAN statistics
-    async fn persist_vlans(&self) -> Result<usize> {
+    async fn persist_vlans(&self, failures: &mut usize) -> Result<usize> {
         let mut count = 0;
 
         for entry in self.state.vlans.iter() {
-            let vlan_id = *entry.key();
+            let (site, vlan_id) = *entry.key();
             let stats = entry.value();
 
-            if let Err(e) = self.db.upsert_vlan(vlan_id, stats.outer_vlan_id, stats).await {
+            if let Err(e) = self.db.upsert_vlan(site, vlan_id, stats.outer_vlan_id, stats).await {
                 debug!("Failed to persist VLAN stats: {}", e);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Persist the subnets inferred on each VLAN, when hosts were seen
+    async fn persist_vlan_subnets(&self, failures: &mut usize) -> Result<usize> {
+        if !self.state.vlan_subnets.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(0);
+        }
+        let mut count = 0;
+
+        for subnet in self.state.vlan_subnets.infer(self.config.vlan_subnet_prefix) {
+            if let Err(e) = self.db.upsert_vlan_subnet(&subnet).await {
+                debug!("Failed to persist VLAN subnet: {}", e);
+                self.state.vlan_subnets.dirty.store(true, Ordering::Relaxed);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Persist the L2 segments, when a device was seen on a new capture interface
+    async fn persist_segments(&self, failures: &mut usize) -> Result<usize> {
+        if !self.state.topology.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(0);
+        }
+
+        let segments = self.state.topology.segments();
+        if let Err(e) = self.db.replace_l2_segments(&segments).await {
+            debug!("Failed to persist L2 segments: {}", e);
+            self.state.topology.dirty.store(true, Ordering::Relaxed);
+            *failures += 1;
+            return Ok(0);
+        }
+
+        Ok(segments.len())
+    }
+
+    /// Persist RTT percentiles of device pairs with new samples
+    async fn persist_rtt(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        let devices = self.state.rtt.pairs.iter()
+            .filter(|entry| entry.dirty.load(Ordering::Relaxed))
+            .flat_map(|entry| {
+                let (client, server) = *entry.key();
+                [client, server]
+            })
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for entry in self.state.rtt.pairs.iter() {
+            let pair = entry.value();
+            if !pair.dirty.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            let client_device_id = device_ids.get(&entry.key().0).copied();
+            let server_device_id = device_ids.get(&entry.key().1).copied();
+
+            if let Err(e) = self.db.upsert_rtt(&pair.snapshot(), client_device_id, server_device_id).await {
+                debug!("Failed to persist RTT: {}", e);
+                pair.dirty.store(true, Ordering::Relaxed);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Persist the traffic composition of device hours that changed
+    async fn persist_composition(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.composition.hours.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().0)
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.composition.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                changed.push((*entry.key(), entry.top(self.config.composition_top_services)));
+            }
+        }
+
+        for ((device, hour), services) in changed {
+            let device_id = device_ids.get(&device).copied();
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.replace_hourly_traffic(device_id, &device, start, &services).await {
+                debug!("Failed to persist hourly traffic: {}", e);
+                if let Some(mut entry) = self.state.composition.hours.get_mut(&(device, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        // Only the current and previous hour still change
+        let current = composition::hour_of(Utc::now());
+        self.state.composition.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Persist the traffic by QoS class of device hours that changed
+    async fn persist_qos(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.qos.hours.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().0)
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.qos.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                changed.push((*entry.key(), HourlyQos { classes: entry.classes.clone(), dirty: false }));
+            }
+        }
+
+        for ((device, hour), qos) in changed {
+            let device_id = device_ids.get(&device).copied();
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.replace_hourly_qos(device_id, &device, start, &qos).await {
+                debug!("Failed to persist hourly QoS classes: {}", e);
+                if let Some(mut entry) = self.state.qos.hours.get_mut(&(device, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let current = composition::hour_of(Utc::now());
+        self.state.qos.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Persist the encrypted and plaintext traffic of device hours that
+    /// changed
+    async fn persist_encryption(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.encryption.hours.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().0)
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.encryption.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                changed.push((*entry.key(), HourlyEncryption {
+                    protocols: entry.protocols.clone(),
+                    unclassified: entry.unclassified,
+                    dirty: false,
+                }));
+            }
+        }
+
+        for ((device, hour), encryption) in changed {
+            let device_id = device_ids.get(&device).copied();
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.replace_hourly_encryption(device_id, &device, start, &encryption).await {
+                debug!("Failed to persist hourly encrypted traffic: {}", e);
+                if let Some(mut entry) = self.state.encryption.hours.get_mut(&(device, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let current = composition::hour_of(Utc::now());
+        self.state.encryption.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Persist the traffic between subnets of site hours that changed
+    async fn persist_subnet_matrix(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        for mut entry in self.state.subnet_matrix.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                let (site, hour) = *entry.key();
+                changed.push((site, hour, self.state.subnet_matrix.pairs(site, &entry)));
+            }
+        }
+
+        for (site, hour, pairs) in changed {
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.replace_hourly_subnet_traffic(site, start, &pairs).await {
+                debug!("Failed to persist hourly subnet traffic: {}", e);
+                if let Some(mut entry) = self.state.subnet_matrix.hours.get_mut(&(site, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let current = composition::hour_of(Utc::now());
+        self.state.subnet_matrix.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Add the traffic of dependency edges since the last cycle
+    async fn persist_dependencies(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.dependencies.edges.iter()
+            .filter(|entry| entry.pending.since.is_some())
+            .flat_map(|entry| [entry.key().client, entry.key().server])
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.dependencies.edges.iter_mut() {
+            let first_seen = entry.first_seen;
+            if let Some(traffic) = entry.take() {
+                changed.push((*entry.key(), first_seen, traffic));
+            }
+        }
+
+        for (key, first_seen, traffic) in changed {
+            let client_device_id = device_ids.get(&key.client).copied();
+            let server_device_id = device_ids.get(&key.server).copied();
+
+            let service_name = key.service.port.and_then(|port| self.state.services.name(port));
+            if let Err(e) = self.db.add_dependency_traffic(&key, service_name, first_seen, &traffic, client_device_id, server_device_id).await {
+                debug!("Failed to persist dependency: {}", e);
+                if let Some(mut entry) = self.state.dependencies.edges.get_mut(&key) {
+                    entry.restore(traffic);
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let idle = Utc::now() - chrono::Duration::seconds(self.config.flow_timeout as i64);
+        self.state.dependencies.prune(idle);
+
+        Ok(count)
+    }
+
+    /// Persist TLS destinations with new handshakes or certificates
+    async fn persist_tls(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        let devices = self.state.tls.observations.iter()
+            .filter(|entry| entry.dirty.load(Ordering::Relaxed))
+            .map(|entry| DeviceKey::new(entry.key().site, entry.key().client_mac))
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for entry in self.state.tls.observations.iter() {
+            let observation = entry.value();
+            if !observation.dirty.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            let key = entry.key();
+            let device_id = device_ids.get(&DeviceKey::new(key.site, key.client_mac)).copied();
+
+            if let Err(e) = self.db.upsert_tls_observation(key, observation, device_id).await {
+                debug!("Failed to persist TLS observation: {}", e);
+                observation.dirty.store(true, Ordering::Relaxed);
+                *failures += 1;
             } else {
                 count += 1;
             }
@@ -186,4 +864,326 @@ impl Persister {
 
         Ok(count)
     }
+
+    /// Persist new sightings of device JA3 and JA3S fingerprints
+    async fn persist_fingerprints(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.tls.fingerprints.iter()
+            .filter(|entry| entry.values().any(|sightings| sightings.unpersisted > 0))
+            .map(|entry| *entry.key())
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.tls.fingerprints.iter_mut() {
+            let device = *entry.key();
+            for ((kind, fingerprint), sightings) in entry.value_mut().iter_mut() {
+                if sightings.unpersisted > 0 {
+                    changed.push((device, *kind, fingerprint.clone(), sightings.clone()));
+                    sightings.unpersisted = 0;
+                }
+            }
+        }
+
+        for (device, kind, fingerprint, sightings) in changed {
+            let device_id = device_ids.get(&device).copied();
+
+            if let Err(e) = self.db.upsert_tls_fingerprint(device_id, &device, kind, &fingerprint, &sightings).await {
+                debug!("Failed to persist TLS fingerprint: {}", e);
+                if let Some(mut fingerprints) = self.state.tls.fingerprints.get_mut(&device) {
+                    if let Some(entry) = fingerprints.get_mut(&(kind, fingerprint)) {
+                        entry.unpersisted += sightings.unpersisted;
+                    }
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Append queued DHCP lease events to the lease history
+    async fn persist_leases(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut failed = Vec::new();
+
+        let events = self.state.dhcp.drain();
+        let devices = events.iter().map(|event| DeviceKey::new(event.site, event.mac)).collect();
+        let device_ids = match self.resolve_device_ids(devices).await {
+            Ok(device_ids) => device_ids,
+            Err(e) => {
+                self.state.dhcp.requeue(events);
+                return Err(e);
+            }
+        };
+
+        for event in events {
+            let device_id = device_ids.get(&DeviceKey::new(event.site, event.mac)).copied();
+
+            if let Err(e) = self.db.insert_lease_event(&event, device_id).await {
+                debug!("Failed to persist lease event: {}", e);
+                failed.push(event);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+        self.state.dhcp.requeue(failed);
+
+        Ok(count)
+    }
+
+    /// Persist the DNS analytics of device hours that changed
+    async fn persist_dns(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.dns.hours.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().0)
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.dns.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                changed.push((*entry.key(), entry.summary(self.config.dns_top_domains)));
+            }
+        }
+
+        for ((device, hour), summary) in changed {
+            let device_id = device_ids.get(&device).copied();
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.upsert_hourly_dns(device_id, &device, start, &summary).await {
+                debug!("Failed to persist hourly DNS activity: {}", e);
+                if let Some(mut entry) = self.state.dns.hours.get_mut(&(device, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let current = composition::hour_of(Utc::now());
+        self.state.dns.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Persist the anomaly scores of device hours that changed
+    async fn persist_anomalies(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = Vec::new();
+
+        let devices = self.state.anomalies.hours.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().0)
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for mut entry in self.state.anomalies.hours.iter_mut() {
+            if entry.dirty {
+                entry.dirty = false;
+                changed.push((*entry.key(), entry.clone()));
+            }
+        }
+
+        for ((device, detector, hour), score) in changed {
+            let device_id = device_ids.get(&device).copied();
+            let start = DateTime::from_timestamp(hour, 0).unwrap_or_else(Utc::now);
+
+            if let Err(e) = self.db.upsert_hourly_anomaly(device_id, &device, detector, start, &score).await {
+                debug!("Failed to persist hourly anomaly scores: {}", e);
+                if let Some(mut entry) = self.state.anomalies.hours.get_mut(&(device, detector, hour)) {
+                    entry.dirty = true;
+                }
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let current = composition::hour_of(Utc::now());
+        self.state.anomalies.prune(current - composition::HOUR_SECS);
+
+        Ok(count)
+    }
+
+    /// Persist the multicast groups whose members, senders or traffic changed
+    async fn persist_multicast(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        for (group, traffic) in self.state.multicast.take(Utc::now()) {
+            if let Err(e) = self.db.upsert_multicast_group(&group, &traffic).await {
+                debug!("Failed to persist multicast group: {}", e);
+                let site = SiteId::new(&group.site).unwrap_or_default();
+                self.state.multicast.restore(site, group.group, traffic);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Persist the device time servers with new requests or responses
+    async fn persist_ntp(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        let devices = self.state.ntp.associations.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().client())
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for (key, association) in self.state.ntp.take() {
+            let device_id = device_ids.get(&key.client()).copied();
+            if let Err(e) = self.db.upsert_ntp_server(device_id, &key, &association).await {
+                debug!("Failed to persist NTP server: {}", e);
+                self.state.ntp.restore(key, &association);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let idle = Utc::now() - chrono::Duration::seconds(self.config.flow_timeout as i64);
+        self.state.ntp.prune(idle);
+
+        Ok(count)
+    }
+
+    /// Persist the industrial devices and the writes to them with new
+    /// messages
+    async fn persist_ot(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        let mut devices: Vec<DeviceKey> = self.state.ot.devices.iter()
+            .filter(|entry| entry.dirty)
+            .map(|entry| entry.key().device())
+            .collect();
+        devices.extend(self.state.ot.writes.iter()
+            .filter(|entry| entry.dirty)
+            .flat_map(|entry| [entry.key().source(), entry.key().device()]));
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for (key, device) in self.state.ot.take_devices() {
+            let device_id = device_ids.get(&key.device()).copied();
+            if let Err(e) = self.db.upsert_ot_device(device_id, &key, &device).await {
+                debug!("Failed to persist industrial device: {}", e);
+                self.state.ot.restore_device(key, &device);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        for (key, write) in self.state.ot.take_writes() {
+            let source_id = device_ids.get(&key.source()).copied();
+            let device_id = device_ids.get(&key.device()).copied();
+            if let Err(e) = self.db.upsert_ot_write(source_id, device_id, &key, &write).await {
+                debug!("Failed to persist industrial writes: {}", e);
+                self.state.ot.restore_write(key, &write);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let idle = Utc::now() - chrono::Duration::seconds(self.config.flow_timeout as i64);
+        self.state.ot.prune(idle);
+
+        Ok(count)
+    }
+
+    /// Persist the VoIP calls with new signaling or media
+    async fn persist_calls(&mut self, failures: &mut usize) -> Result<usize> {
+        let mut count = 0;
+
+        let devices: Vec<DeviceKey> = self.state.telephony.calls.iter()
+            .filter(|entry| entry.dirty)
+            .flat_map(|entry| [entry.caller(entry.key().site), entry.callee(entry.key().site)])
+            .collect();
+        let device_ids = self.resolve_device_ids(devices).await?;
+
+        for (key, call) in self.state.telephony.take_calls() {
+            let caller_id = device_ids.get(&call.caller(key.site)).copied();
+            let callee_id = device_ids.get(&call.callee(key.site)).copied();
+            if let Err(e) = self.db.upsert_sip_call(caller_id, callee_id, &key, &call).await {
+                debug!("Failed to persist call: {}", e);
+                self.state.telephony.restore(&key, &call);
+                *failures += 1;
+            } else {
+                count += 1;
+            }
+        }
+
+        let idle = Utc::now() - chrono::Duration::seconds(self.config.flow_timeout as i64);
+        self.state.telephony.prune(idle);
+
+        Ok(count)
+    }
+
+    /// IDs of `devices`, resolved in the database when not cached
+    ///
+    /// Devices missing from the database are created as seen now; the
+    /// records referencing them are all from the current cycle.
+    async fn resolve_device_ids(&mut self, devices: Vec<DeviceKey>) -> Result<HashMap<DeviceKey, Uuid>> {
+        let now = Utc::now();
+        self.device_ids.resolve(&self.db, devices.into_iter().map(|device| (device, now))).await
+    }
+
+    /// Fill in the device references of flows persisted without them, by
+    /// earlier versions or before their devices had a row
+    async fn backfill_flows(&self) {
+        let mut backfilled = 0;
+        loop {
+            match self.db.backfill_flow_device_ids(BACKFILL_BATCH_SIZE).await {
+                Ok(0) => break,
+                Ok(updated) => backfilled += updated,
+                Err(e) => {
+                    warn!("Failed to backfill flow device references: {:#}", e);
+                    break;
+                }
+            }
+        }
+        if backfilled > 0 {
+            info!("Backfilled the device references of {} flows", backfilled);
+        }
+    }
+}
+
+/// Next admin request, or never without a request channel
+async fn next_request(requests: &mut Option<mpsc::Receiver<PersistRequest>>) -> Option<PersistRequest> {
+    match requests {
+        Some(requests) => requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Acknowledge entries whose cycles kept failing, logging their range for
+/// a replay once the failing rows are fixed
+async fn give_up(tracker: &AckTracker, entry_ids: Vec<String>) {
+    if entry_ids.is_empty() {
+        return;
+    }
+    let ids: Vec<StreamId> = entry_ids.iter().filter_map(|id| StreamId::parse(id)).collect();
+    let (first, last) = (ids.iter().min(), ids.iter().max());
+    error!(
+        "Acknowledging {} stream entries ({} to {}) that failed every persist cycle; \
+         their state may be missing from the database",
+        entry_ids.len(),
+        first.map(ToString::to_string).unwrap_or_default(),
+        last.map(ToString::to_string).unwrap_or_default()
+    );
+    metrics().ack_abandoned.inc_by(entry_ids.len() as u64);
+    if let Err(e) = tracker.acknowledge(entry_ids).await {
+        warn!("Failed to acknowledge abandoned entries: {}", e);
+    }
 }
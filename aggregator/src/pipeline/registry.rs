@@ -0,0 +1,444 @@
+//! Aggregator instance registry
+//!
+//! Every running aggregator registers itself in a Redis hash
+//! (`<stream_name>:instances`) and refreshes a heartbeat there. Operators can
+//! list the registered instances next to the consumer group's own view of its
+//! consumers, and instances whose heartbeat expired can be pruned: their
+//! pending entries are handed to a live consumer before the dead consumer is
+//! removed from the group. Running consumers prune on every heartbeat
+//! interval, so an instance restarted under a new name (`<hostname>-<pid>`)
+//! before its predecessor's TTL expired still takes over its entries.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use redis::aio::MultiplexedConnection;
+use redis::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use super::consumer::parse_entry_list;
+use crate::config::{local_hostname, RedisConfig};
+
+/// Number of pending entries claimed per XPENDING/XCLAIM round
+const CLAIM_BATCH_SIZE: usize = 1000;
+
+/// Registry record of one aggregator instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub consumer_name: String,
+    pub hostname: String,
+    pub pid: u32,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat: DateTime<Utc>,
+}
+
+impl InstanceInfo {
+    /// Whether the instance sent a heartbeat within `ttl_secs` of `now`
+    pub fn is_alive(&self, ttl_secs: u64, now: DateTime<Utc>) -> bool {
+        (now - self.last_heartbeat).num_seconds() <= ttl_secs as i64
+    }
+}
+
+/// Combined registry and consumer-group view of a consumer
+#[derive(Debug, Clone)]
+pub struct InstanceStatus {
+    pub consumer_name: String,
+    /// Registry record, if the consumer registered itself
+    pub info: Option<InstanceInfo>,
+    /// Whether the heartbeat is fresh (always false when unregistered)
+    pub alive: bool,
+    /// Entries delivered to the consumer but not acknowledged
+    pub pending: u64,
+    /// Milliseconds since the consumer last interacted with the group
+    pub idle_ms: Option<u64>,
+}
+
+impl InstanceStatus {
+    /// Whether the consumer is dead: its registry heartbeat expired, or it
+    /// never registered and has been idle longer than `ttl_secs`
+    pub fn is_dead(&self, ttl_secs: u64) -> bool {
+        match &self.info {
+            Some(_) => !self.alive,
+            None => self.idle_ms.is_some_and(|idle| idle > ttl_secs * 1000),
+        }
+    }
+}
+
+/// Outcome of pruning dead consumers
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// Consumers removed from the group and the registry
+    pub pruned: Vec<String>,
+    /// Pending entries (ID and data) moved to the claiming consumer
+    pub claimed: Vec<(String, String)>,
+}
+
+/// Registers this instance and keeps its heartbeat fresh
+pub struct InstanceRegistry {
+    config: RedisConfig,
+    info: InstanceInfo,
+}
+
+impl InstanceRegistry {
+    /// Create a registry entry for the local instance
+    pub fn new(config: RedisConfig) -> Self {
+        let now = Utc::now();
+        let info = InstanceInfo {
+            consumer_name: config.consumer_name.clone(),
+            hostname: local_hostname(),
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: now,
+            last_heartbeat: now,
+        };
+
+        Self { config, info }
+    }
+
+    /// Redis hash holding the registry for `config`'s stream
+    pub fn registry_key(config: &RedisConfig) -> String {
+        format!("{}:instances", config.stream_name)
+    }
+
+    /// Connect to Redis
+    pub async fn connect(config: &RedisConfig) -> Result<MultiplexedConnection> {
+        let client = Client::open(config.url.as_str())
+            .with_context(|| format!("Failed to create Redis client: {}", config.url))?;
+
+        client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| "Failed to connect to Redis")
+    }
+
+    /// Write (or refresh) this instance's registry record
+    pub async fn heartbeat(&mut self, conn: &mut MultiplexedConnection) -> Result<()> {
+        self.info.last_heartbeat = Utc::now();
+        let json = serde_json::to_string(&self.info)?;
+
+        let _: i64 = redis::cmd("HSET")
+            .arg(Self::registry_key(&self.config))
+            .arg(&self.info.consumer_name)
+            .arg(json)
+            .query_async(conn)
+            .await
+            .with_context(|| "Failed to write instance heartbeat")?;
+
+        Ok(())
+    }
+
+    /// Register, heartbeat until shutdown, then deregister
+    pub async fn run(mut self, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        let mut conn = Self::connect(&self.config).await?;
+        let interval = tokio::time::Duration::from_secs(self.config.heartbeat_interval_secs);
+
+        self.heartbeat(&mut conn).await?;
+        info!(
+            "Registered instance '{}' (host={}, pid={})",
+            self.info.consumer_name, self.info.hostname, self.info.pid
+        );
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    break;
+                }
+                _ = tokio::time::sleep(interval) => {
+                    if let Err(e) = self.heartbeat(&mut conn).await {
+                        warn!("Instance heartbeat failed: {}", e);
+                    }
+                }
+            }
+        }
+
+        let _: redis::RedisResult<i64> = redis::cmd("HDEL")
+            .arg(Self::registry_key(&self.config))
+            .arg(&self.info.consumer_name)
+            .query_async(&mut conn)
+            .await;
+
+        info!("Deregistered instance '{}'", self.info.consumer_name);
+        Ok(())
+    }
+
+    /// List registered instances and consumer group members
+    pub async fn list(conn: &mut MultiplexedConnection, config: &RedisConfig) -> Result<Vec<InstanceStatus>> {
+        let now = Utc::now();
+
+        let registered: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(Self::registry_key(config))
+            .query_async(conn)
+            .await
+            .with_context(|| "Failed to read instance registry")?;
+
+        let consumers: redis::RedisResult<redis::Value> = redis::cmd("XINFO")
+            .arg("CONSUMERS")
+            .arg(&config.stream_name)
+            .arg(&config.consumer_group)
+            .query_async(conn)
+            .await;
+
+        // The group may not exist yet; the registry alone is still useful
+        let consumers = match consumers {
+            Ok(redis::Value::Bulk(consumers)) => consumers,
+            _ => Vec::new(),
+        };
+
+        Ok(merge_statuses(registered, &consumers, config.instance_ttl_secs, now))
+    }
+
+    /// Remove dead consumers, moving their pending entries to `claim_to`
+    ///
+    /// See [`InstanceStatus::is_dead`]. Consumers with pending entries are
+    /// only removed when `claim_to` is given (or `force` is set, which
+    /// discards their pending entries); the claimed entries are returned for
+    /// `claim_to` to process.
+    pub async fn prune(
+        conn: &mut MultiplexedConnection,
+        config: &RedisConfig,
+        claim_to: Option<&str>,
+        force: bool,
+    ) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+
+        for status in Self::list(conn, config).await? {
+            if !status.is_dead(config.instance_ttl_secs) || Some(status.consumer_name.as_str()) == claim_to {
+                continue;
+            }
+
+            if status.pending > 0 {
+                match claim_to {
+                    Some(target) => {
+                        let claimed = Self::claim_pending(conn, config, &status.consumer_name, target).await?;
+                        info!(
+                            "Claimed {} pending entries from dead consumer '{}'",
+                            claimed.len(), status.consumer_name
+                        );
+                        report.claimed.extend(claimed);
+                    }
+                    None if force => {
+                        warn!(
+                            "Dropping {} pending entries of dead consumer '{}'",
+                            status.pending, status.consumer_name
+                        );
+                    }
+                    None => {
+                        warn!(
+                            "Not pruning '{}': {} pending entries (use --force to discard)",
+                            status.consumer_name, status.pending
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            if status.idle_ms.is_some() {
+                let _: i64 = redis::cmd("XGROUP")
+                    .arg("DELCONSUMER")
+                    .arg(&config.stream_name)
+                    .arg(&config.consumer_group)
+                    .arg(&status.consumer_name)
+                    .query_async(conn)
+                    .await
+                    .with_context(|| format!("Failed to delete consumer {}", status.consumer_name))?;
+            }
+
+            let _: i64 = redis::cmd("HDEL")
+                .arg(Self::registry_key(config))
+                .arg(&status.consumer_name)
+                .query_async(conn)
+                .await?;
+
+            debug!("Pruned dead consumer '{}'", status.consumer_name);
+            report.pruned.push(status.consumer_name);
+        }
+
+        Ok(report)
+    }
+
+    /// Move every pending entry of `from` to consumer `to`, returning them
+    async fn claim_pending(
+        conn: &mut MultiplexedConnection,
+        config: &RedisConfig,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut claimed = Vec::new();
+
+        loop {
+            let pending: Vec<(String, String, u64, u64)> = redis::cmd("XPENDING")
+                .arg(&config.stream_name)
+                .arg(&config.consumer_group)
+                .arg("-")
+                .arg("+")
+                .arg(CLAIM_BATCH_SIZE)
+                .arg(from)
+                .query_async(conn)
+                .await
+                .with_context(|| format!("XPENDING for {} failed", from))?;
+
+            if pending.is_empty() {
+                break;
+            }
+
+            let ids: Vec<&str> = pending.iter().map(|(id, ..)| id.as_str()).collect();
+            let moved: Vec<redis::Value> = redis::cmd("XCLAIM")
+                .arg(&config.stream_name)
+                .arg(&config.consumer_group)
+                .arg(to)
+                .arg(0)
+                .arg(&ids)
+                .query_async(conn)
+                .await
+                .with_context(|| format!("XCLAIM from {} failed", from))?;
+
+            claimed.extend(parse_entry_list(&moved));
+            if moved.is_empty() {
+                break;
+            }
+        }
+
+        Ok(claimed)
+    }
+}
+
+/// Combine registry records and XINFO CONSUMERS replies into statuses
+fn merge_statuses(
+    registered: HashMap<String, String>,
+    consumers: &[redis::Value],
+    ttl_secs: u64,
+    now: DateTime<Utc>,
+) -> Vec<InstanceStatus> {
+    let mut statuses: HashMap<String, InstanceStatus> = HashMap::new();
+    for (name, json) in registered {
+        let info: Option<InstanceInfo> = match serde_json::from_str(&json) {
+            Ok(info) => Some(info),
+            Err(e) => {
+                warn!("Ignoring malformed registry entry '{}': {}", name, e);
+                None
+            }
+        };
+        let alive = info.as_ref().is_some_and(|i| i.is_alive(ttl_secs, now));
+        statuses.insert(name.clone(), InstanceStatus {
+            consumer_name: name,
+            info,
+            alive,
+            pending: 0,
+            idle_ms: None,
+        });
+    }
+
+    for consumer in consumers {
+        let fields = parse_info_fields(consumer);
+        let Some(name) = fields.get("name").cloned() else { continue };
+
+        let status = statuses.entry(name.clone()).or_insert_with(|| InstanceStatus {
+            consumer_name: name,
+            info: None,
+            alive: false,
+            pending: 0,
+            idle_ms: None,
+        });
+        status.pending = fields.get("pending").and_then(|v| v.parse().ok()).unwrap_or(0);
+        status.idle_ms = fields.get("idle").and_then(|v| v.parse().ok());
+    }
+
+    let mut statuses: Vec<InstanceStatus> = statuses.into_values().collect();
+    statuses.sort_by(|a, b| a.consumer_name.cmp(&b.consumer_name));
+    statuses
+}
+
+/// Parse a flat `[key, value, key, value, ...]` XINFO reply into a map
+pub(crate) fn parse_info_fields(value: &redis::Value) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+
+    if let redis::Value::Bulk(items) = value {
+        for pair in items.chunks_exact(2) {
+            let key = match &pair[0] {
+                redis::Value::Data(bytes) => String::from_utf8_lossy(bytes).to_string(),
+                redis::Value::Status(s) => s.clone(),
+                _ => continue,
+            };
+            let value = match &pair[1] {
+                redis::Value::Data(bytes) => String::from_utf8_lossy(bytes).to_string(),
+                redis::Value::Status(s) => s.clone(),
+                redis::Value::Int(i) => i.to_string(),
+                _ => continue,
+            };
+            fields.insert(key, value);
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_liveness() {
+        let now = Utc::now();
+        let info = InstanceInfo {
+            consumer_name: "host-1".to_string(),
+            hostname: "host".to_string(),
+            pid: 1,
+            version: "0.1.0".to_string(),
+            started_at: now,
+            last_heartbeat: now - chrono::Duration::seconds(30),
+        };
+
+        assert!(info.is_alive(60, now));
+        assert!(!info.is_alive(10, now));
+    }
+
+    #[test]
+    fn test_parse_info_fields() {
+        let value = redis::Value::Bulk(vec![
+            redis::Value::Data(b"name".to_vec()),
+            redis::Value::Data(b"host-42".to_vec()),
+            redis::Value::Data(b"pending".to_vec()),
+            redis::Value::Int(3),
+            redis::Value::Data(b"idle".to_vec()),
+            redis::Value::Int(1500),
+        ]);
+
+        let fields = parse_info_fields(&value);
+        assert_eq!(fields["name"], "host-42");
+        assert_eq!(fields["pending"], "3");
+        assert_eq!(fields["idle"], "1500");
+    }
+
+    #[test]
+    fn test_restarted_predecessor_claimed() {
+        // Restarted 5 s after its last heartbeat, under a new pid
+        let started = Utc::now();
+        let info = InstanceInfo {
+            consumer_name: "host-41".to_string(),
+            hostname: "host".to_string(),
+            pid: 41,
+            version: "0.1.0".to_string(),
+            started_at: started - chrono::Duration::hours(1),
+            last_heartbeat: started - chrono::Duration::seconds(5),
+        };
+        let registered = HashMap::from([("host-41".to_string(), serde_json::to_string(&info).unwrap())]);
+        let consumers = [redis::Value::Bulk(vec![
+            redis::Value::Data(b"name".to_vec()),
+            redis::Value::Data(b"host-41".to_vec()),
+            redis::Value::Data(b"pending".to_vec()),
+            redis::Value::Int(3),
+            redis::Value::Data(b"idle".to_vec()),
+            redis::Value::Int(5000),
+        ])];
+
+        // Still alive at startup, dead on a heartbeat tick once the TTL expired
+        let at_startup = merge_statuses(registered.clone(), &consumers, 60, started);
+        assert!(!at_startup[0].is_dead(60));
+        let later = merge_statuses(registered, &consumers, 60, started + chrono::Duration::seconds(60));
+        assert!(later[0].is_dead(60));
+        assert_eq!(later[0].pending, 3);
+    }
+}
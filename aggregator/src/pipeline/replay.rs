@@ -0,0 +1,200 @@
+//! Historical replay of captured frames
+//!
+//! Re-reads a range of the frame stream (or a dump file) through the normal
+//! aggregation logic into a fresh in-memory state, then persists the result
+//! into a separate database schema. Used to rebuild inventory after fixing an
+//! aggregation bug without touching the live tables.
+
+use anyhow::{Context, Result};
+use redis::Client;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::{AggregationConfig, RedisConfig};
+use crate::db::Database;
+use crate::state::{AggregatorState, SensorFrame, ServiceNames, Tenants};
+
+use super::consumer::{parse_entry_list, parse_frame_data};
+use super::persister::Persister;
+
+/// Where replayed frames come from
+#[derive(Debug, Clone)]
+pub enum ReplaySource {
+    /// Inclusive range of stream entry IDs (`-` and `+` for the stream ends)
+    Stream { from: String, to: String },
+    /// Newline-delimited JSON file, one captured frame per line
+    Dump(PathBuf),
+}
+
+/// Replay summary
+#[derive(Debug, Clone, Default)]
+pub struct ReplayStats {
+    /// Frames fed through the aggregation logic
+    pub frames_replayed: u64,
+    /// Entries that could not be parsed as frames
+    pub frames_skipped: u64,
+    /// Last stream entry ID read (stream sources only)
+    pub last_entry_id: Option<String>,
+}
+
+/// Replays historical frames into an isolated state
+pub struct Replayer {
+    redis: RedisConfig,
+    aggregation: AggregationConfig,
+    state: Arc<AggregatorState>,
+    db: Arc<Database>,
+}
+
+impl Replayer {
+    /// Create a new replayer persisting into `db`
+    ///
+    /// `db` should be connected with [`Database::connect_with_schema`] so the
+    /// rebuilt state does not overwrite the live inventory.
+    pub fn new(redis: RedisConfig, aggregation: AggregationConfig, db: Arc<Database>) -> Self {
+        Self {
+            redis,
+            state: Arc::new(AggregatorState::new().with_subnet_prefix(aggregation.vlan_subnet_prefix)),
+            aggregation,
+            db,
+        }
+    }
+
+    /// Name services with `services`, as the live pipeline does
+    pub fn with_services(mut self, services: ServiceNames) -> Self {
+        self.state = Arc::new(
+            AggregatorState::new()
+                .with_services(services)
+                .with_subnet_prefix(self.aggregation.vlan_subnet_prefix)
+                .with_tenants(self.state.tenants.clone()),
+        );
+        self
+    }
+
+    /// Assign sites to their tenants, as the live pipeline does
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.state = Arc::new(
+            AggregatorState::new()
+                .with_services(self.state.services.clone())
+                .with_subnet_prefix(self.aggregation.vlan_subnet_prefix)
+                .with_tenants(tenants),
+        );
+        self
+    }
+
+    /// Get the replay state
+    pub fn state(&self) -> Arc<AggregatorState> {
+        Arc::clone(&self.state)
+    }
+
+    /// Replay `source` and persist the resulting state
+    pub async fn run(&self, source: ReplaySource) -> Result<ReplayStats> {
+        info!("Starting replay from {:?}", source);
+
+        let stats = match source {
+            ReplaySource::Stream { from, to } => self.replay_stream(&from, &to).await?,
+            ReplaySource::Dump(path) => self.replay_dump(&path)?,
+        };
+
+        let mut persister = Persister::new(
+            self.aggregation.clone(),
+            Arc::clone(&self.state),
+            Arc::clone(&self.db),
+        );
+        persister.persist_all().await?;
+
+        info!(
+            "Replay finished: {} frames replayed, {} skipped",
+            stats.frames_replayed, stats.frames_skipped
+        );
+        Ok(stats)
+    }
+
+    /// Read `[from, to]` from the stream with paged XRANGE calls
+    async fn replay_stream(&self, from: &str, to: &str) -> Result<ReplayStats> {
+        let client = Client::open(self.redis.url.as_str())
+            .with_context(|| format!("Failed to create Redis client: {}", self.redis.url))?;
+        let mut conn = client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| "Failed to connect to Redis")?;
+
+        let mut stats = ReplayStats::default();
+        let mut start = from.to_string();
+        let mut pages: u64 = 0;
+
+        loop {
+            let value: redis::Value = redis::cmd("XRANGE")
+                .arg(&self.redis.stream_name)
+                .arg(&start)
+                .arg(to)
+                .arg("COUNT")
+                .arg(self.redis.batch_size)
+                .query_async(&mut conn)
+                .await
+                .with_context(|| format!("XRANGE {} {} failed", start, to))?;
+
+            let messages = match value {
+                redis::Value::Bulk(messages) if !messages.is_empty() => messages,
+                _ => break,
+            };
+            let page_len = messages.len();
+
+            for (entry_id, data) in parse_entry_list(&messages) {
+                self.replay_frame(parse_frame_data(&data), &mut stats);
+                stats.last_entry_id = Some(entry_id);
+            }
+
+            if page_len < self.redis.batch_size {
+                break;
+            }
+
+            // Continue after the last entry (exclusive range start)
+            match &stats.last_entry_id {
+                Some(id) => start = format!("({}", id),
+                None => break,
+            }
+
+            pages += 1;
+            if pages.is_multiple_of(100) {
+                info!("Replay progress: {} frames", stats.frames_replayed);
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Read frames from a newline-delimited JSON dump
+    fn replay_dump(&self, path: &Path) -> Result<ReplayStats> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open dump file: {:?}", path))?;
+
+        let mut stats = ReplayStats::default();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read {:?}", path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let frame = parse_frame_data(&line);
+            if frame.is_none() {
+                warn!("Skipping unparseable frame at {:?}:{}", path, line_no + 1);
+            }
+            self.replay_frame(frame, &mut stats);
+        }
+
+        Ok(stats)
+    }
+
+    /// Feed one frame through the aggregation logic at its capture time
+    fn replay_frame(&self, frame: Option<SensorFrame>, stats: &mut ReplayStats) {
+        match frame {
+            Some(frame) => {
+                self.state.process_frame_at(&frame, frame.timestamp);
+                stats.frames_replayed += 1;
+            }
+            None => stats.frames_skipped += 1,
+        }
+    }
+}
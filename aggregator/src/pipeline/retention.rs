@@ -0,0 +1,217 @@
+//! Frames stream retention
+//!
+//! Captures cap the stream with an approximate MAXLEN when they add entries,
+//! but that cap is sized for throughput, not for how long the aggregators
+//! may be away. The trimmer removes entries every consumer group has
+//! acknowledged (so only what was aggregated and persisted goes), and can
+//! also enforce a length or age limit that applies to unprocessed entries
+//! too, so the stream cannot outgrow Redis memory while aggregators are down.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use redis::aio::MultiplexedConnection;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use super::registry::parse_info_fields;
+use super::InstanceRegistry;
+use crate::config::{RedisConfig, StreamRetentionConfig};
+use crate::metrics::metrics;
+
+/// Stream entry ID (`<milliseconds>-<sequence>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// Parse `ms-seq`, or a bare `ms`
+    pub fn parse(id: &str) -> Option<Self> {
+        let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+        Some(Self { ms: ms.parse().ok()?, seq: seq.parse().ok()? })
+    }
+
+    /// When the entry was added to the stream
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_millis(self.ms as i64)
+    }
+
+    /// Smallest ID after this one
+    pub fn next(&self) -> Self {
+        match self.seq.checked_add(1) {
+            Some(seq) => Self { ms: self.ms, seq },
+            None => Self { ms: self.ms + 1, seq: 0 },
+        }
+    }
+}
+
+impl std::fmt::Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Periodically trims the frames stream
+pub struct StreamTrimmer {
+    redis: RedisConfig,
+    config: StreamRetentionConfig,
+}
+
+impl StreamTrimmer {
+    /// Create a trimmer for the stream of `redis`
+    pub fn new(redis: RedisConfig, config: StreamRetentionConfig) -> Self {
+        Self { redis, config }
+    }
+
+    /// Trim every `interval_secs` until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) {
+        let interval = tokio::time::Duration::from_secs(self.config.interval_secs);
+        let mut conn: Option<MultiplexedConnection> = None;
+        info!(
+            "Trimming stream '{}' every {}s (acknowledged={}, max_len={:?}, max_age_secs={:?})",
+            self.redis.stream_name, self.config.interval_secs, self.config.trim_acknowledged,
+            self.config.max_len, self.config.max_age_secs
+        );
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = tokio::time::sleep(interval) => {
+                    if conn.is_none() {
+                        match InstanceRegistry::connect(&self.redis).await {
+                            Ok(c) => conn = Some(c),
+                            Err(e) => {
+                                warn!("Stream trim skipped: {:#}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    let Some(c) = conn.as_mut() else { continue };
+                    match self.trim(c).await {
+                        Ok(0) => {}
+                        Ok(trimmed) => debug!("Trimmed {} entries from stream '{}'", trimmed, self.redis.stream_name),
+                        Err(e) => {
+                            warn!("Stream trim failed: {:#}", e);
+                            conn = None;
+                        }
+                    }
+                }
+            }
+        }
+
+        debug!("Stream trimmer stopped");
+    }
+
+    /// Apply the retention policy once, returning the number of entries removed
+    pub async fn trim(&self, conn: &mut MultiplexedConnection) -> Result<u64> {
+        let processed = match self.config.trim_acknowledged {
+            true => self.processed_bound(conn).await?,
+            false => None,
+        };
+        let expired = self.config.max_age_secs.map(|age| StreamId {
+            ms: (Utc::now().timestamp_millis().max(0) as u64).saturating_sub(age.saturating_mul(1000)),
+            seq: 0,
+        });
+
+        let mut trimmed = 0;
+        if let Some(min_id) = processed.max(expired) {
+            trimmed += self.xtrim(conn, "MINID", min_id.to_string()).await?;
+        }
+        if let Some(max_len) = self.config.max_len {
+            trimmed += self.xtrim(conn, "MAXLEN", max_len.to_string()).await?;
+        }
+
+        metrics().stream_trimmed.inc_by(trimmed);
+        Ok(trimmed)
+    }
+
+    /// Lowest entry ID some consumer group still needs, if there are groups
+    ///
+    /// Each group needs its oldest pending entry, or everything after its
+    /// last delivered one when nothing is pending.
+    async fn processed_bound(&self, conn: &mut MultiplexedConnection) -> Result<Option<StreamId>> {
+        let groups: redis::Value = redis::cmd("XINFO")
+            .arg("GROUPS")
+            .arg(&self.redis.stream_name)
+            .query_async(conn)
+            .await
+            .with_context(|| format!("Failed to read consumer groups of '{}'", self.redis.stream_name))?;
+        let redis::Value::Bulk(groups) = groups else { return Ok(None) };
+
+        let mut bound: Option<StreamId> = None;
+        for group in &groups {
+            let fields = parse_info_fields(group);
+            let Some(name) = fields.get("name") else { continue };
+            let pending: u64 = fields.get("pending").and_then(|v| v.parse().ok()).unwrap_or(0);
+
+            let needed = if pending > 0 {
+                let summary: redis::Value = redis::cmd("XPENDING")
+                    .arg(&self.redis.stream_name)
+                    .arg(name)
+                    .query_async(conn)
+                    .await
+                    .with_context(|| format!("Failed to read pending entries of group '{}'", name))?;
+                oldest_pending(&summary)
+            } else {
+                // Everything up to the last delivered entry is acknowledged
+                fields.get("last-delivered-id")
+                    .and_then(|id| StreamId::parse(id))
+                    .map(|id| StreamId { ms: id.ms, seq: id.seq.saturating_add(1) })
+            };
+            // A group whose position is unknown keeps everything
+            let Some(needed) = needed else { return Ok(None) };
+            bound = Some(bound.map_or(needed, |b| b.min(needed)));
+        }
+
+        Ok(bound)
+    }
+
+    /// `XTRIM <stream> <strategy> [~] <threshold>`
+    async fn xtrim(&self, conn: &mut MultiplexedConnection, strategy: &str, threshold: String) -> Result<u64> {
+        let mut cmd = redis::cmd("XTRIM");
+        cmd.arg(&self.redis.stream_name).arg(strategy);
+        if self.config.approximate {
+            cmd.arg("~");
+        }
+        cmd.arg(threshold)
+            .query_async(conn)
+            .await
+            .with_context(|| format!("Failed to trim stream '{}' by {}", self.redis.stream_name, strategy))
+    }
+}
+
+/// Smallest ID of an XPENDING summary reply
+fn oldest_pending(summary: &redis::Value) -> Option<StreamId> {
+    match summary {
+        redis::Value::Bulk(items) => match items.get(1) {
+            Some(redis::Value::Data(bytes)) => StreamId::parse(&String::from_utf8_lossy(bytes)),
+            Some(redis::Value::Status(s)) => StreamId::parse(s),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_ids() {
+        let a = StreamId::parse("1700000000000-5").unwrap();
+        let b = StreamId::parse("1700000000000-12").unwrap();
+        assert!(a < b);
+        assert_eq!(StreamId::parse("1700000000001"), Some(StreamId { ms: 1700000000001, seq: 0 }));
+        assert_eq!(StreamId::parse("not-an-id"), None);
+        assert_eq!(a.to_string(), "1700000000000-5");
+
+        let summary = redis::Value::Bulk(vec![
+            redis::Value::Int(2),
+            redis::Value::Data(b"1700000000000-5".to_vec()),
+            redis::Value::Data(b"1700000000000-12".to_vec()),
+            redis::Value::Nil,
+        ]);
+        assert_eq!(oldest_pending(&summary), Some(a));
+    }
+}
@@ -0,0 +1,420 @@
+//! WASM detection plugins
+//!
+//! Security teams ship custom detections as WebAssembly modules instead of
+//! patches to the aggregator. Each module is fed every new-device and
+//! new-flow event, and every `scan_interval_secs` the flows active since the
+//! previous scan; it answers with the alerts to raise. Modules are listed in
+//! `[plugins]` (files, or directories of `*.wasm`) and reloaded when they
+//! are added, changed or removed, without a restart.
+//!
+//! A module exports its `memory` and:
+//!
+//! - `alloc(len: i32) -> i32`, returning where the host may write `len`
+//!   bytes of input;
+//! - `on_event(ptr: i32, len: i32) -> i64` (optional), called with an event
+//!   as JSON, in the format of the published events;
+//! - `on_flows(ptr: i32, len: i32) -> i64` (optional), called with a JSON
+//!   array of flow snapshots.
+//!
+//! Both return 0 when there is nothing to report, or `ptr << 32 | len` of a
+//! JSON array of alerts (`name`, `message`, and optionally `severity`,
+//! `site`, `mac`, `ip`, `notify` and `details`).
+//!
+//! Modules are sandboxed: no host functions are provided, so a module
+//! importing anything is rejected, each call may run at most `fuel`
+//! instructions, and linear memory is capped at `max_memory_mb`. A failing
+//! call is logged and counted, and the module keeps being called.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use wasmi::{Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::config::PluginsConfig;
+use crate::events::{self, Event, EventSender, Severity};
+use crate::metrics::metrics;
+use crate::state::AggregatorState;
+
+/// Largest alert list a plugin may return (bytes)
+const MAX_OUTPUT: usize = 1 << 20;
+
+/// Alert returned by a plugin
+#[derive(Debug, Deserialize)]
+struct PluginAlert {
+    name: String,
+    message: String,
+    #[serde(default = "default_severity")]
+    severity: Severity,
+    site: Option<String>,
+    mac: Option<String>,
+    ip: Option<Ipv4Addr>,
+    #[serde(default)]
+    notify: Vec<String>,
+    details: Option<serde_json::Value>,
+}
+
+fn default_severity() -> Severity { Severity::Medium }
+
+impl PluginAlert {
+    fn into_event(self) -> Event {
+        Event::Alert {
+            timestamp: Utc::now(),
+            severity: self.severity,
+            name: self.name,
+            message: self.message,
+            site: self.site,
+            sensor: None,
+            mac: self.mac,
+            ip: self.ip,
+            channels: self.notify,
+            details: self.details,
+        }
+    }
+}
+
+/// A loaded module and its instance
+struct Plugin {
+    /// File stem, used in logs and metrics
+    name: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    on_event: Option<TypedFunc<(i32, i32), i64>>,
+    on_flows: Option<TypedFunc<(i32, i32), i64>>,
+}
+
+impl Plugin {
+    /// Compile and instantiate `wasm`, limiting its memory to `max_memory`
+    fn new(engine: &Engine, name: &str, wasm: &[u8], max_memory: usize) -> Result<Self> {
+        let module = Module::new(engine, wasm).context("Invalid WASM module")?;
+        let limits = StoreLimitsBuilder::new().memory_size(max_memory).instances(1).build();
+        let mut store = Store::new(engine, limits);
+        store.limiter(|limits| limits);
+
+        // No host functions: any import fails instantiation
+        let instance = Linker::new(engine)
+            .instantiate(&mut store, &module)
+            .context("Failed to instantiate module (plugins may not import anything)")?
+            .ensure_no_start(&mut store)
+            .context("Plugins may not have a start function")?;
+
+        let memory = instance.get_memory(&store, "memory").context("Module does not export its memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .context("Module does not export alloc(i32) -> i32")?;
+        let on_event = instance.get_typed_func::<(i32, i32), i64>(&store, "on_event").ok();
+        let on_flows = instance.get_typed_func::<(i32, i32), i64>(&store, "on_flows").ok();
+        if on_event.is_none() && on_flows.is_none() {
+            bail!("Module exports neither on_event nor on_flows");
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            path: PathBuf::new(),
+            modified: None,
+            store,
+            memory,
+            alloc,
+            on_event,
+            on_flows,
+        })
+    }
+
+    /// Load the module at `path`
+    fn load(engine: &Engine, path: &Path, max_memory: usize) -> Result<Self> {
+        let wasm = std::fs::read(path).with_context(|| format!("Failed to read plugin {:?}", path))?;
+        let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut plugin = Self::new(engine, &name, &wasm, max_memory)
+            .with_context(|| format!("Failed to load plugin {:?}", path))?;
+        plugin.path = path.to_path_buf();
+        plugin.modified = modified(path);
+        Ok(plugin)
+    }
+
+    /// Call `func` with `input`, with at most `fuel` instructions
+    fn call(&mut self, func: TypedFunc<(i32, i32), i64>, input: &[u8], fuel: u64) -> Result<Vec<Event>> {
+        self.store.set_fuel(fuel).map_err(|e| anyhow!("{}", e))?;
+        let len = i32::try_from(input.len()).context("Input too large")?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)
+            .map_err(|e| anyhow!("alloc returned memory out of bounds: {}", e))?;
+
+        let packed = func.call(&mut self.store, (ptr, len))?;
+        if packed == 0 {
+            return Ok(Vec::new());
+        }
+        let (ptr, len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+        if len > MAX_OUTPUT {
+            bail!("Output of {} bytes exceeds the {} bytes limit", len, MAX_OUTPUT);
+        }
+        let mut output = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut output)
+            .map_err(|e| anyhow!("Output out of bounds: {}", e))?;
+
+        let alerts: Vec<PluginAlert> = serde_json::from_slice(&output).context("Invalid alert list")?;
+        Ok(alerts.into_iter().map(PluginAlert::into_event).collect())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Module files named by `paths`, directories expanded to their `*.wasm`
+fn module_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+                .with_context(|| format!("Failed to read plugins directory {:?}", path))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|e| e == "wasm"))
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Runs the detection plugins
+pub struct PluginHost {
+    config: PluginsConfig,
+    state: Arc<AggregatorState>,
+    engine: Engine,
+    plugins: Vec<Plugin>,
+    /// Timestamp (seconds) of the previous flow scan
+    last_scan: u64,
+}
+
+impl PluginHost {
+    /// Load the configured modules
+    pub fn new(config: PluginsConfig, state: Arc<AggregatorState>) -> Result<Self> {
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+
+        let max_memory = config.max_memory_mb << 20;
+        let plugins = module_files(&config.modules)?
+            .iter()
+            .map(|path| Plugin::load(&engine, path, max_memory))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { config, state, engine, plugins, last_scan: Utc::now().timestamp() as u64 })
+    }
+
+    /// Number of loaded plugins
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    /// Whether no plugin is loaded
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Load added and changed modules and drop removed ones
+    ///
+    /// A module that fails to load keeps its previous version running.
+    pub fn reload(&mut self) {
+        let files = match module_files(&self.config.modules) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to list plugins: {:#}", e);
+                return;
+            }
+        };
+
+        let max_memory = self.config.max_memory_mb << 20;
+        let mut previous = std::mem::take(&mut self.plugins);
+        for path in files {
+            let current = previous.iter().position(|p| p.path == path).map(|i| previous.swap_remove(i));
+            match current {
+                Some(plugin) if plugin.modified == modified(&path) => self.plugins.push(plugin),
+                current => match Plugin::load(&self.engine, &path, max_memory) {
+                    Ok(plugin) => {
+                        info!("{} plugin {}", if current.is_some() { "Reloaded" } else { "Loaded" }, plugin.name);
+                        self.plugins.push(plugin);
+                    }
+                    Err(e) => {
+                        warn!("{:#}", e);
+                        self.plugins.extend(current);
+                    }
+                },
+            }
+        }
+        for plugin in previous {
+            info!("Unloaded plugin {}", plugin.name);
+        }
+    }
+
+    /// Feed `event` to the plugins, returning the alerts they raise
+    pub fn on_event(&mut self, event: &Event) -> Vec<Event> {
+        if matches!(event, Event::Alert { .. }) {
+            return Vec::new();
+        }
+        let input = match serde_json::to_vec(event) {
+            Ok(input) => input,
+            Err(_) => return Vec::new(),
+        };
+        self.call_all(|p| p.on_event, &input)
+    }
+
+    /// Feed the flows active since the previous scan to the plugins
+    pub fn scan(&mut self) -> Vec<Event> {
+        let since = self.last_scan;
+        self.last_scan = Utc::now().timestamp() as u64;
+        if self.plugins.iter().all(|p| p.on_flows.is_none()) {
+            return Vec::new();
+        }
+
+        let flows: Vec<_> = self.state.flows.iter()
+            .filter(|flow| flow.last_seen.load(Ordering::Relaxed) >= since)
+            .map(|flow| flow.snapshot(flow.key().ethertype(), &self.state.services))
+            .collect();
+        if flows.is_empty() {
+            return Vec::new();
+        }
+        let input = match serde_json::to_vec(&flows) {
+            Ok(input) => input,
+            Err(_) => return Vec::new(),
+        };
+        self.call_all(|p| p.on_flows, &input)
+    }
+
+    fn call_all(&mut self, func: impl Fn(&Plugin) -> Option<TypedFunc<(i32, i32), i64>>, input: &[u8]) -> Vec<Event> {
+        let mut alerts = Vec::new();
+        for plugin in &mut self.plugins {
+            let Some(func) = func(plugin) else { continue };
+            match plugin.call(func, input, self.config.fuel) {
+                Ok(raised) => alerts.extend(raised),
+                Err(e) => {
+                    debug!("Plugin {} failed: {:#}", plugin.name, e);
+                    metrics().plugin_errors.with_label_values(&[&plugin.name]).inc();
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Feed events from `events` to the plugins and send the alerts they
+    /// raise to `output`; scan flows and reload modules periodically
+    pub async fn run(mut self, mut events: broadcast::Receiver<Event>, output: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!("Running {} detection plugins", self.plugins.len());
+
+        let mut scan = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        scan.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let reloading = self.config.reload_interval_secs > 0;
+        let mut reload = tokio::time::interval(Duration::from_secs(self.config.reload_interval_secs.max(1)));
+        reload.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = scan.tick() => {
+                    for alert in self.scan() {
+                        let _ = output.send(alert);
+                    }
+                }
+                _ = reload.tick(), if reloading => self.reload(),
+                event = events::recv(&mut events, "Detection plugins") => {
+                    let Some(event) = event else { break };
+                    for alert in self.on_event(&event) {
+                        let _ = output.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Detection plugins stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceKey, MacAddr, SensorId, SiteId};
+
+    const ALERTS: &str = r#"[{"name":"wasm-test","severity":"high","message":"seen by plugin"}]"#;
+
+    /// Module answering every event with `ALERTS`, or looping forever
+    fn module(looping: bool) -> Vec<u8> {
+        let body = if looping {
+            "(loop $forever (br $forever)) i64.const 0".to_string()
+        } else {
+            format!("i64.const {}", ALERTS.len())
+        };
+        wat::parse_str(format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "on_event") (param i32 i32) (result i64) {}))"#,
+            ALERTS.replace('"', "\\\""),
+            body,
+        )).unwrap()
+    }
+
+    fn config() -> PluginsConfig {
+        PluginsConfig {
+            enabled: true,
+            modules: Vec::new(),
+            scan_interval_secs: 60,
+            reload_interval_secs: 0,
+            fuel: 100_000,
+            max_memory_mb: 1,
+        }
+    }
+
+    fn host(wasm: &[u8]) -> PluginHost {
+        let mut host = PluginHost::new(config(), Arc::new(AggregatorState::new())).unwrap();
+        let plugin = Plugin::new(&host.engine, "test", wasm, 1 << 20).unwrap();
+        host.plugins.push(plugin);
+        host
+    }
+
+    fn new_device() -> Event {
+        let key = DeviceKey::new(SiteId::default(), MacAddr::new([0, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        Event::new_device(key, SensorId::default(), None, None, Utc::now())
+    }
+
+    #[test]
+    fn test_plugin_alerts() {
+        let mut host = host(&module(false));
+        let alerts = host.on_event(&new_device());
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { name, severity, message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(name, "wasm-test");
+        assert_eq!(*severity, Severity::High);
+        assert_eq!(message, "seen by plugin");
+
+        // Alerts are not fed back to the plugins
+        assert!(host.on_event(&alerts[0]).is_empty());
+    }
+
+    #[test]
+    fn test_plugin_sandbox() {
+        // Out of fuel: the call is aborted, not the aggregator
+        let mut host = host(&module(true));
+        assert!(host.on_event(&new_device()).is_empty());
+        assert!(metrics().plugin_errors.with_label_values(&["test"]).get() >= 1);
+
+        // Host functions are not available
+        let engine = Engine::default();
+        let importing = wat::parse_str(r#"(module (import "env" "system" (func)) (memory (export "memory") 1))"#).unwrap();
+        assert!(Plugin::new(&engine, "importing", &importing, 1 << 20).is_err());
+    }
+}
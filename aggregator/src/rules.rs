@@ -0,0 +1,992 @@
+//! Rule-based alerting
+//!
+//! Rules are loaded from TOML or YAML files and evaluated in file order.
+//! Event rules see every new device and new flow from the consumer; the
+//! engine sits between the consumer and the event sinks, so a `suppress`
+//! rule keeps matching events from ever reaching them. Scan rules run over
+//! the device and flow state every `scan_interval_secs`, which also gives
+//! them byte and packet rates over the last interval. Matching `alert`
+//! rules raise an [`Event::Alert`], at most once per subject per cooldown.
+//! Device scans also see the JA3 and JA3S fingerprints each device was seen
+//! using, so known malware TLS clients can be listed in a rule. No alert is
+//! raised about a device under maintenance, or on a VLAN under maintenance.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "telnet"
+//! on = ["new_flow"]
+//! severity = "high"
+//! notify = ["slack"]
+//! match = { protocol = [6], dst_port = [23, 2323] }
+//! ```
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use minijinja::Environment;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::RulesConfig;
+use crate::events::{self, Event, EventSender, Severity};
+use crate::state::{
+    AggregatorState, DeviceKey, FingerprintKind, FlowKey, MacAddr, MaintenanceEntry, SensorId, ServiceNames, SiteId,
+    TcpOutcome,
+};
+
+/// Notification sinks an alert can be routed to
+const CHANNELS: &[&str] = &["webhook", "syslog", "email", "slack", "teams", "mqtt", "snmp"];
+
+/// What a rule is evaluated against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    NewDevice,
+    NewFlow,
+    /// Every device, at each state scan
+    DeviceScan,
+    /// Every flow, at each state scan
+    FlowScan,
+}
+
+impl Trigger {
+    fn is_scan(self) -> bool {
+        matches!(self, Trigger::DeviceScan | Trigger::FlowScan)
+    }
+}
+
+/// What a matching rule does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Raise an alert
+    #[default]
+    Alert,
+    /// Drop the event before it reaches the event sinks
+    Suppress,
+}
+
+/// IPv4 network in CIDR notation (a bare address is a /32)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: u32,
+    mask: u32,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.mask == self.network
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, "32"));
+        let addr: Ipv4Addr = addr.trim().parse().with_context(|| format!("Invalid address in '{}'", s))?;
+        let prefix: u32 = prefix.trim().parse().with_context(|| format!("Invalid prefix length in '{}'", s))?;
+        if prefix > 32 {
+            anyhow::bail!("Invalid prefix length in '{}'", s);
+        }
+        let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+        Ok(Self { network: u32::from(addr) & mask, mask })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.network), self.mask.count_ones())
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|e: anyhow::Error| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+impl JsonSchema for Cidr {
+    fn schema_name() -> String {
+        "Cidr".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Conditions of a rule; every condition given must hold, and a list
+/// matches if any of its entries does
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Match {
+    /// MAC address or prefix (e.g. an OUI `00:11:22`) of either endpoint
+    #[serde(default)]
+    pub mac: Vec<String>,
+    #[serde(default)]
+    pub src_mac: Vec<String>,
+    #[serde(default)]
+    pub dst_mac: Vec<String>,
+
+    /// Networks either endpoint (or any of a device's addresses) is in
+    #[serde(default)]
+    pub ip: Vec<Cidr>,
+    #[serde(default)]
+    pub src_ip: Vec<Cidr>,
+    #[serde(default)]
+    pub dst_ip: Vec<Cidr>,
+
+    /// Port of either endpoint
+    #[serde(default)]
+    pub port: Vec<u16>,
+    #[serde(default)]
+    pub src_port: Vec<u16>,
+    #[serde(default)]
+    pub dst_port: Vec<u16>,
+
+    /// IP protocol numbers
+    #[serde(default)]
+    pub protocol: Vec<u8>,
+
+    /// Service names of flows (`https`, or a name of `[services]`)
+    #[serde(default)]
+    pub service: Vec<String>,
+
+    /// VLAN IDs
+    #[serde(default)]
+    pub vlan: Vec<u16>,
+
+    /// How the TCP handshake of flows went (flow scans only)
+    #[serde(default)]
+    pub tcp_outcome: Vec<TcpOutcome>,
+
+    /// Whether the device is a gateway
+    #[serde(default)]
+    pub gateway: Option<bool>,
+
+    /// JA3 / JA3S fingerprints a device was seen using (device scans only)
+    #[serde(default)]
+    pub ja3: Vec<String>,
+    #[serde(default)]
+    pub ja3s: Vec<String>,
+
+    /// Minimum rate over the last scan interval (scan rules only)
+    #[serde(default)]
+    pub min_bytes_per_sec: Option<f64>,
+    #[serde(default)]
+    pub min_packets_per_sec: Option<f64>,
+}
+
+/// A single alerting rule
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Unique name, used as the alert name
+    pub name: String,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Events and scans the rule applies to
+    pub on: Vec<Trigger>,
+
+    #[serde(default, rename = "match")]
+    pub conditions: Match,
+
+    #[serde(default)]
+    pub action: Action,
+
+    /// Severity of the alert raised
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+
+    /// Alert message template (minijinja), rendered with the subject's
+    /// fields; defaults to the description and subject summary
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Notification sinks the alert goes to (default: all of them)
+    #[serde(default)]
+    pub notify: Vec<String>,
+
+    /// Minimum seconds between two alerts for the same subject
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+fn default_true() -> bool { true }
+fn default_severity() -> Severity { Severity::Medium }
+fn default_cooldown() -> u64 { 300 }
+
+/// Layout of a rule file
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+/// Parse the rules of a TOML or YAML document
+fn parse_rules(content: &str, yaml: bool) -> Result<Vec<Rule>> {
+    let file: RuleFile = if yaml {
+        config::Config::builder()
+            .add_source(config::File::from_str(content, config::FileFormat::Yaml))
+            .build()?
+            .try_deserialize()?
+    } else {
+        toml::from_str(content)?
+    };
+    Ok(file.rule)
+}
+
+/// Load the enabled rules from `paths` (files, or directories whose
+/// `.toml`, `.yaml` and `.yml` files are loaded in name order)
+pub fn load(paths: &[PathBuf]) -> Result<Vec<Rule>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+                .with_context(|| format!("Failed to read rules directory {:?}", path))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| rule_format(p).is_some())
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut rules = Vec::new();
+    for file in files {
+        let yaml = rule_format(&file).with_context(|| format!("Unknown rule file format: {:?}", file))?;
+        let content = std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read rule file {:?}", file))?;
+        let parsed = parse_rules(&content, yaml).with_context(|| format!("Failed to parse rule file {:?}", file))?;
+        rules.extend(parsed.into_iter().filter(|r| r.enabled));
+    }
+
+    validate(&rules)?;
+    Ok(rules)
+}
+
+/// Whether `path` is a YAML (`Some(true)`) or TOML (`Some(false)`) rule file
+fn rule_format(path: &Path) -> Option<bool> {
+    match path.extension()?.to_str()? {
+        "toml" => Some(false),
+        "yaml" | "yml" => Some(true),
+        _ => None,
+    }
+}
+
+fn validate(rules: &[Rule]) -> Result<()> {
+    let mut names = HashSet::new();
+    for rule in rules {
+        if !names.insert(rule.name.as_str()) {
+            anyhow::bail!("Duplicate rule name '{}'", rule.name);
+        }
+        if rule.on.is_empty() {
+            anyhow::bail!("Rule '{}' has no trigger", rule.name);
+        }
+        let has_rates = rule.conditions.min_bytes_per_sec.is_some() || rule.conditions.min_packets_per_sec.is_some();
+        if has_rates && !rule.on.iter().all(|t| t.is_scan()) {
+            anyhow::bail!("Rule '{}': rate conditions need device_scan or flow_scan triggers", rule.name);
+        }
+        let has_fingerprints = !rule.conditions.ja3.is_empty() || !rule.conditions.ja3s.is_empty();
+        if has_fingerprints && !rule.on.iter().all(|t| *t == Trigger::DeviceScan) {
+            anyhow::bail!("Rule '{}': ja3 and ja3s conditions need the device_scan trigger", rule.name);
+        }
+        if !rule.conditions.tcp_outcome.is_empty() && !rule.on.iter().all(|t| *t == Trigger::FlowScan) {
+            anyhow::bail!("Rule '{}': tcp_outcome conditions need the flow_scan trigger", rule.name);
+        }
+        if rule.action == Action::Suppress && rule.on.iter().any(|t| t.is_scan()) {
+            anyhow::bail!("Rule '{}': only events can be suppressed", rule.name);
+        }
+        if let Some(channel) = rule.notify.iter().find(|c| !CHANNELS.contains(&c.as_str())) {
+            anyhow::bail!("Rule '{}': unknown notification channel '{}'", rule.name, channel);
+        }
+    }
+    Ok(())
+}
+
+/// One side of a device or flow, as seen by the rules
+#[derive(Debug, Clone, Default, Serialize)]
+struct Endpoint {
+    mac: String,
+    ips: Vec<Ipv4Addr>,
+    port: Option<u16>,
+}
+
+/// What a rule is matched against: an event, a device or a flow
+#[derive(Debug, Clone, Default)]
+struct Subject {
+    /// Cooldown key
+    key: String,
+    summary: String,
+    site: String,
+    /// The device, or the flow's source
+    src: Endpoint,
+    /// The flow's destination
+    dst: Option<Endpoint>,
+    protocol: Option<u8>,
+    /// The flow's service name
+    service: Option<String>,
+    vlans: Vec<u16>,
+    /// How the flow's TCP handshake went, on flow scans
+    tcp_outcome: Option<TcpOutcome>,
+    gateway: Option<bool>,
+    /// JA3 and JA3S fingerprints the device was seen using
+    ja3: Vec<String>,
+    ja3s: Vec<String>,
+    /// Bytes and packets per second, on scans after the first
+    rates: Option<(f64, f64)>,
+}
+
+impl Subject {
+    fn device(site: &str, mac: &str, ips: Vec<Ipv4Addr>, vlans: Vec<u16>, gateway: Option<bool>, summary: String) -> Self {
+        Self {
+            key: format!("{}@{}", mac, site),
+            summary,
+            site: site.to_string(),
+            src: Endpoint { mac: mac.to_string(), ips, port: None },
+            vlans,
+            gateway,
+            ..Default::default()
+        }
+    }
+
+    fn flow(key: &FlowKey, summary: String, services: &ServiceNames) -> Self {
+        Self {
+            key: format!("{} vlan {:?} on {}", key.to_display_string(), key.vlan_id, key.sensor),
+            summary,
+            site: key.site.to_string(),
+            src: Endpoint { mac: key.src_mac.to_string(), ips: key.src_ip.into_iter().collect(), port: key.src_port },
+            dst: Some(Endpoint { mac: key.dst_mac.to_string(), ips: key.dst_ip.into_iter().collect(), port: key.dst_port }),
+            protocol: key.protocol,
+            service: key.service(services).map(str::to_string),
+            vlans: key.vlan_id.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        std::iter::once(&self.src).chain(self.dst.as_ref())
+    }
+
+    /// Template context of the alert message
+    fn context(&self, rule: &Rule) -> serde_json::Value {
+        serde_json::json!({
+            "rule": rule.name,
+            "description": rule.description,
+            "summary": self.summary,
+            "site": self.site,
+            "mac": self.src.mac,
+            "ip": self.src.ips.first(),
+            "ips": self.src.ips,
+            "src_port": self.src.port,
+            "dst_mac": self.dst.as_ref().map(|d| &d.mac),
+            "dst_ip": self.dst.as_ref().and_then(|d| d.ips.first()),
+            "dst_port": self.dst.as_ref().and_then(|d| d.port),
+            "protocol": self.protocol,
+            "service": self.service,
+            "vlan": self.vlans.first(),
+            "tcp_outcome": self.tcp_outcome.map(|outcome| outcome.as_str()),
+            "ja3": self.ja3,
+            "ja3s": self.ja3s,
+            "bytes_per_sec": self.rates.map(|r| r.0),
+            "packets_per_sec": self.rates.map(|r| r.1),
+        })
+    }
+}
+
+fn mac_matches(patterns: &[String], mac: &str) -> bool {
+    patterns.iter().any(|p| mac.starts_with(&p.to_ascii_lowercase()))
+}
+
+fn cidr_matches(networks: &[Cidr], ips: &[Ipv4Addr]) -> bool {
+    networks.iter().any(|n| ips.iter().any(|ip| n.contains(*ip)))
+}
+
+fn fingerprint_matches(fingerprints: &[String], seen: &[String]) -> bool {
+    fingerprints.iter().any(|f| seen.iter().any(|s| s.eq_ignore_ascii_case(f)))
+}
+
+fn port_matches(ports: &[u16], port: Option<u16>) -> bool {
+    port.is_some_and(|p| ports.contains(&p))
+}
+
+impl Match {
+    fn matches(&self, subject: &Subject) -> bool {
+        let dst = subject.dst.as_ref();
+
+        (self.mac.is_empty() || subject.endpoints().any(|e| mac_matches(&self.mac, &e.mac)))
+            && (self.src_mac.is_empty() || mac_matches(&self.src_mac, &subject.src.mac))
+            && (self.dst_mac.is_empty() || dst.is_some_and(|d| mac_matches(&self.dst_mac, &d.mac)))
+            && (self.ip.is_empty() || subject.endpoints().any(|e| cidr_matches(&self.ip, &e.ips)))
+            && (self.src_ip.is_empty() || cidr_matches(&self.src_ip, &subject.src.ips))
+            && (self.dst_ip.is_empty() || dst.is_some_and(|d| cidr_matches(&self.dst_ip, &d.ips)))
+            && (self.port.is_empty() || subject.endpoints().any(|e| port_matches(&self.port, e.port)))
+            && (self.src_port.is_empty() || port_matches(&self.src_port, subject.src.port))
+            && (self.dst_port.is_empty() || dst.is_some_and(|d| port_matches(&self.dst_port, d.port)))
+            && (self.protocol.is_empty() || subject.protocol.is_some_and(|p| self.protocol.contains(&p)))
+            && (self.service.is_empty() || subject.service.as_ref().is_some_and(|s| self.service.iter().any(|m| m.eq_ignore_ascii_case(s))))
+            && (self.vlan.is_empty() || subject.vlans.iter().any(|v| self.vlan.contains(v)))
+            && (self.tcp_outcome.is_empty() || subject.tcp_outcome.is_some_and(|o| self.tcp_outcome.contains(&o)))
+            && self.gateway.is_none_or(|g| subject.gateway == Some(g))
+            && (self.ja3.is_empty() || fingerprint_matches(&self.ja3, &subject.ja3))
+            && (self.ja3s.is_empty() || fingerprint_matches(&self.ja3s, &subject.ja3s))
+            && self.min_bytes_per_sec.is_none_or(|min| subject.rates.is_some_and(|r| r.0 >= min))
+            && self.min_packets_per_sec.is_none_or(|min| subject.rates.is_some_and(|r| r.1 >= min))
+    }
+}
+
+/// Outcome of evaluating an event
+#[derive(Debug, Default)]
+pub struct Verdict {
+    /// Alerts raised by the event
+    pub alerts: Vec<Event>,
+    /// Whether the event itself is dropped
+    pub suppressed: bool,
+}
+
+/// Evaluates rules against events and the aggregated state
+pub struct RulesEngine {
+    config: RulesConfig,
+    rules: Vec<Rule>,
+    state: Arc<AggregatorState>,
+    messages: Environment<'static>,
+    /// Last alert per (rule, subject)
+    fired: HashMap<(usize, String), Instant>,
+    /// Packet and byte totals at the previous scan, by device or flow ID
+    totals: HashMap<Uuid, (u64, u64)>,
+    last_scan: Option<Instant>,
+}
+
+impl RulesEngine {
+    /// Create an engine with the rules of the configured files
+    pub fn new(config: RulesConfig, state: Arc<AggregatorState>) -> Result<Self> {
+        let rules = load(&config.files)?;
+        Self::with_rules(config, rules, state)
+    }
+
+    /// Create an engine with the given rules
+    pub fn with_rules(config: RulesConfig, rules: Vec<Rule>, state: Arc<AggregatorState>) -> Result<Self> {
+        let mut messages = Environment::new();
+        for rule in &rules {
+            if let Some(message) = &rule.message {
+                messages
+                    .add_template_owned(rule.name.clone(), message.clone())
+                    .with_context(|| format!("Invalid message template in rule '{}'", rule.name))?;
+            }
+        }
+
+        Ok(Self {
+            config,
+            rules,
+            state,
+            messages,
+            fired: HashMap::new(),
+            totals: HashMap::new(),
+            last_scan: None,
+        })
+    }
+
+    /// Number of rules loaded
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether no rule is loaded
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluate the event rules against `event`
+    ///
+    /// Rules run in order; the first matching `suppress` rule drops the
+    /// event and ends the evaluation.
+    pub fn evaluate(&mut self, event: &Event) -> Verdict {
+        let (trigger, subject) = match event {
+            Event::NewDevice { site, mac, ip, vlan_id, .. } => {
+                let gateway = SiteId::new(site)
+                    .zip(MacAddr::from_string(mac))
+                    .and_then(|(s, m)| self.state.devices.get(&DeviceKey::new(s, m)).map(|d| d.is_gateway.load(Ordering::Relaxed)));
+                let subject = Subject::device(
+                    site, mac, ip.iter().copied().collect(), vlan_id.iter().copied().collect(), gateway, event.summary(),
+                );
+                (Trigger::NewDevice, subject)
+            }
+            Event::NewFlow {
+                site, sensor, src_mac, dst_mac, src_ip, dst_ip, src_port, dst_port, vlan_id, protocol,
+                icmp_type, icmp_code, icmp_id, ..
+            } => {
+                let key = FlowKey {
+                    site: SiteId::new(site).unwrap_or_default(),
+                    sensor: SensorId::new(sensor).unwrap_or_default(),
+                    src_mac: MacAddr::from_string(src_mac).unwrap_or(MacAddr::new([0; 6])),
+                    dst_mac: MacAddr::from_string(dst_mac).unwrap_or(MacAddr::new([0; 6])),
+                    src_ip: *src_ip,
+                    dst_ip: *dst_ip,
+                    src_port: *src_port,
+                    dst_port: *dst_port,
+                    vlan_id: *vlan_id,
+                    protocol: *protocol,
+                    icmp_type: *icmp_type,
+                    icmp_code: *icmp_code,
+                    icmp_id: *icmp_id,
+                };
+                (Trigger::NewFlow, Subject::flow(&key, event.summary(), &self.state.services))
+            }
+            Event::Alert { .. } | Event::Lifecycle { .. } => return Verdict::default(),
+        };
+
+        let mut verdict = Verdict::default();
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            if !rule.on.contains(&trigger) || !rule.conditions.matches(&subject) {
+                continue;
+            }
+            match rule.action {
+                Action::Suppress => {
+                    debug!("Rule '{}' suppressed: {}", rule.name, subject.summary);
+                    verdict.suppressed = true;
+                    break;
+                }
+                Action::Alert => verdict.alerts.extend(self.fire(index, &subject)),
+            }
+        }
+        verdict
+    }
+
+    /// Evaluate the scan rules against every device and flow
+    pub fn scan(&mut self) -> Vec<Event> {
+        let now = Instant::now();
+        let elapsed = self.last_scan.map(|t| now.duration_since(t).as_secs_f64()).filter(|s| *s > 0.0);
+        self.last_scan = Some(now);
+
+        let scans = |trigger| self.rules.iter().any(|r| r.on.contains(&trigger));
+        let (scan_devices, scan_flows) = (scans(Trigger::DeviceScan), scans(Trigger::FlowScan));
+
+        let mut totals = HashMap::new();
+        let mut rate = |id: Uuid, packets: u64, bytes: u64| {
+            let previous = self.totals.get(&id).copied();
+            totals.insert(id, (packets, bytes));
+            let (previous, elapsed) = (previous?, elapsed?);
+            Some((
+                bytes.saturating_sub(previous.1) as f64 / elapsed,
+                packets.saturating_sub(previous.0) as f64 / elapsed,
+            ))
+        };
+
+        let mut subjects = Vec::new();
+        if scan_devices {
+            for device in self.state.devices.iter() {
+                let mac = device.mac.to_string();
+                let mut subject = Subject::device(
+                    device.site.as_str(),
+                    &mac,
+                    device.ips.iter().map(|ip| *ip.key()).collect(),
+                    device.vlans.iter().map(|v| *v.key()).collect(),
+                    Some(device.is_gateway.load(Ordering::Relaxed)),
+                    format!("Device {}", mac),
+                );
+                let packets = device.packets_sent.load(Ordering::Relaxed) + device.packets_received.load(Ordering::Relaxed);
+                let bytes = device.bytes_sent.load(Ordering::Relaxed) + device.bytes_received.load(Ordering::Relaxed);
+                subject.rates = rate(device.id, packets, bytes);
+                subject.ja3 = self.state.tls.device_fingerprints(device.key(), FingerprintKind::Ja3);
+                subject.ja3s = self.state.tls.device_fingerprints(device.key(), FingerprintKind::Ja3s);
+                subjects.push((Trigger::DeviceScan, subject));
+            }
+        }
+        if scan_flows {
+            let now_ts = Utc::now().timestamp() as u64;
+            for flow in self.state.flows.iter() {
+                let mut subject = Subject::flow(&flow.key, format!("Flow {}", flow.key.to_display_string()), &self.state.services);
+                subject.tcp_outcome = flow.tcp_outcome(now_ts);
+                subject.rates = rate(
+                    flow.id,
+                    flow.packet_count.load(Ordering::Relaxed),
+                    flow.byte_count.load(Ordering::Relaxed),
+                );
+                subjects.push((Trigger::FlowScan, subject));
+            }
+        }
+        self.totals = totals;
+
+        let mut alerts = Vec::new();
+        for (trigger, subject) in &subjects {
+            for index in 0..self.rules.len() {
+                let rule = &self.rules[index];
+                if rule.on.contains(trigger) && rule.conditions.matches(subject) {
+                    alerts.extend(self.fire(index, subject));
+                }
+            }
+        }
+
+        // Forget cooldowns that have expired
+        let rules = &self.rules;
+        self.fired.retain(|(index, _), at| at.elapsed() < Duration::from_secs(rules[*index].cooldown_secs));
+
+        alerts
+    }
+
+    /// Maintenance covering the device or an endpoint of the flow of
+    /// `subject`, if any
+    fn maintenance(&self, subject: &Subject) -> Option<MaintenanceEntry> {
+        let site = SiteId::new(&subject.site)?;
+        let now = Utc::now();
+        subject.endpoints().find_map(|endpoint| {
+            let mac = MacAddr::from_string(&endpoint.mac)?;
+            self.state
+                .maintenance_of(DeviceKey::new(site, mac), now)
+                .or_else(|| self.state.maintenance.covering(site, mac, &subject.vlans, now))
+        })
+    }
+
+    /// Raise the alert of rule `index` for `subject`, unless still cooling
+    /// down or under maintenance
+    fn fire(&mut self, index: usize, subject: &Subject) -> Option<Event> {
+        let rule = &self.rules[index];
+        if let Some(maintenance) = self.maintenance(subject) {
+            debug!("Rule '{}' skipped under maintenance {}: {}", rule.name, maintenance.id, subject.summary);
+            return None;
+        }
+        let cooldown = Duration::from_secs(rule.cooldown_secs);
+        let key = (index, subject.key.clone());
+        if self.fired.get(&key).is_some_and(|at| at.elapsed() < cooldown) {
+            return None;
+        }
+        self.fired.insert(key, Instant::now());
+
+        let message = match self.messages.get_template(&rule.name) {
+            Ok(template) => template.render(subject.context(rule)).unwrap_or_else(|e| {
+                warn!("Failed to render message of rule '{}': {}", rule.name, e);
+                subject.summary.clone()
+            }),
+            Err(_) => match &rule.description {
+                Some(description) => format!("{}: {}", description, subject.summary),
+                None => format!("{}: {}", rule.name, subject.summary),
+            },
+        };
+
+        Some(Event::Alert {
+            timestamp: Utc::now(),
+            severity: rule.severity,
+            name: rule.name.clone(),
+            message,
+            site: Some(subject.site.clone()),
+            sensor: None,
+            mac: Some(subject.src.mac.clone()),
+            ip: subject.src.ips.first().copied(),
+            channels: rule.notify.clone(),
+            details: None,
+        })
+    }
+
+    /// Evaluate events from `input` and forward them to `output`, with the
+    /// alerts they raise going to `alerts`; scan the state periodically.
+    /// Stops once every sender of `input` is gone.
+    pub async fn run(mut self, mut input: broadcast::Receiver<Event>, output: EventSender, alerts: EventSender) {
+        info!(
+            "Evaluating {} alerting rules (state scan every {}s)",
+            self.rules.len(),
+            self.config.scan_interval_secs
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.scan_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    for alert in self.scan() {
+                        let _ = alerts.send(alert);
+                    }
+                }
+                event = events::recv(&mut input, "Rules engine") => {
+                    let Some(event) = event else { break };
+                    let verdict = self.evaluate(&event);
+                    if !verdict.suppressed {
+                        let _ = output.send(event);
+                    }
+                    for alert in verdict.alerts {
+                        let _ = alerts.send(alert);
+                    }
+                }
+            }
+        }
+
+        debug!("Rules engine stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceState, FlowState, SensorFrame, TcpFlags};
+
+    const RULES: &str = r#"
+[[rule]]
+name = "quiet-printers"
+on = ["new_device"]
+action = "suppress"
+match = { mac = ["00:11:22"] }
+
+[[rule]]
+name = "telnet"
+description = "Telnet in use"
+on = ["new_flow"]
+severity = "high"
+notify = ["slack"]
+match = { protocol = [6], dst_port = [23], src_ip = ["10.0.0.0/8"] }
+
+[[rule]]
+name = "hypervisor-access"
+on = ["new_flow"]
+message = "{{ service }} reached from {{ ip }}"
+match = { service = ["Proxmox"] }
+
+[[rule]]
+name = "heavy-flow"
+on = ["flow_scan"]
+message = "{{ summary }} at {{ bytes_per_sec | int }} B/s"
+match = { min_bytes_per_sec = 0.0 }
+"#;
+
+    fn config() -> RulesConfig {
+        RulesConfig { enabled: true, files: Vec::new(), scan_interval_secs: 60 }
+    }
+
+    fn flow_key(dst_port: u16) -> FlowKey {
+        FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0xaa, 0, 0, 0, 1]),
+            dst_mac: MacAddr::new([0x00, 0xaa, 0, 0, 0, 2]),
+            src_ip: Some(Ipv4Addr::new(10, 1, 2, 3)),
+            dst_ip: Some(Ipv4Addr::new(192, 168, 1, 1)),
+            src_port: Some(50000),
+            dst_port: Some(dst_port),
+            vlan_id: Some(10),
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        }
+    }
+
+    #[test]
+    fn test_event_rules() {
+        let rules = parse_rules(RULES, false).unwrap();
+        validate(&rules).unwrap();
+        let state = AggregatorState::new().with_services(ServiceNames::default().with_name(8006, "proxmox"));
+        let mut engine = RulesEngine::with_rules(config(), rules, Arc::new(state)).unwrap();
+
+        let printer = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0, 0, 1]));
+        let printer = Event::new_device(printer, SensorId::default(), None, None, Utc::now());
+        let verdict = engine.evaluate(&printer);
+        assert!(verdict.suppressed && verdict.alerts.is_empty());
+
+        let verdict = engine.evaluate(&Event::new_flow(&flow_key(23), Utc::now()));
+        assert!(!verdict.suppressed);
+        assert_eq!(verdict.alerts.len(), 1);
+        let Event::Alert { name, severity, message, .. } = &verdict.alerts[0] else { panic!() };
+        assert_eq!(name, "telnet");
+        assert_eq!(*severity, Severity::High);
+        assert_eq!(message, "Telnet in use: New flow 10.1.2.3 -> 192.168.1.1:23");
+        assert!(verdict.alerts[0].routed_to("slack") && !verdict.alerts[0].routed_to("email"));
+
+        // Same subject within the cooldown
+        assert!(engine.evaluate(&Event::new_flow(&flow_key(23), Utc::now())).alerts.is_empty());
+        assert!(engine.evaluate(&Event::new_flow(&flow_key(22), Utc::now())).alerts.is_empty());
+
+        // Services named by the configuration
+        let verdict = engine.evaluate(&Event::new_flow(&flow_key(8006), Utc::now()));
+        let Event::Alert { message, .. } = &verdict.alerts[0] else { panic!() };
+        assert_eq!(message, "proxmox reached from 10.1.2.3");
+    }
+
+    #[test]
+    fn test_scan_rates() {
+        let state = Arc::new(AggregatorState::new());
+        let flow = FlowState::new(flow_key(443), Utc::now());
+        flow.update(1000, None, 0);
+        state.flows.insert(flow_key(443), flow);
+
+        let rules = parse_rules(RULES, false).unwrap();
+        let mut engine = RulesEngine::with_rules(config(), rules, Arc::clone(&state)).unwrap();
+
+        // No rate before the second scan
+        assert!(engine.scan().is_empty());
+        std::thread::sleep(Duration::from_millis(10));
+        let alerts = engine.scan();
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "Flow 10.1.2.3:50000 -> 192.168.1.1:443 [TCP] at 0 B/s");
+    }
+
+    #[test]
+    fn test_maintenance_rules() {
+        let rules = parse_rules(RULES, false).unwrap();
+        let state = Arc::new(AggregatorState::new());
+        let mut engine = RulesEngine::with_rules(config(), rules, Arc::clone(&state)).unwrap();
+
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        state.maintenance.insert(MaintenanceEntry {
+            id,
+            site: SiteId::default().to_string(),
+            mac_address: None,
+            vlan_id: Some(10),
+            reason: Some("switch upgrade".to_string()),
+            created_by: None,
+            starts_at: now - chrono::Duration::minutes(1),
+            until: now + chrono::Duration::hours(1),
+        });
+        let verdict = engine.evaluate(&Event::new_flow(&flow_key(23), now));
+        assert!(!verdict.suppressed && verdict.alerts.is_empty());
+
+        state.maintenance.end(id, now);
+        assert_eq!(engine.evaluate(&Event::new_flow(&flow_key(23), now)).alerts.len(), 1);
+    }
+
+    #[test]
+    fn test_yaml_rules() {
+        let yaml = r#"
+rule:
+  - name: guest-vlan-gateway
+    on: [device_scan]
+    severity: critical
+    match:
+      vlan: [99]
+      gateway: true
+      ip: ["192.168.99.0/24"]
+"#;
+        let rules = parse_rules(yaml, true).unwrap();
+        assert_eq!(rules[0].on, vec![Trigger::DeviceScan]);
+        assert_eq!(rules[0].severity, Severity::Critical);
+        assert_eq!(rules[0].conditions.vlan, vec![99]);
+        assert!(rules[0].conditions.ip[0].contains(Ipv4Addr::new(192, 168, 99, 7)));
+
+        let mut invalid = rules.clone();
+        invalid[0].action = Action::Suppress;
+        assert!(validate(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_rules() {
+        let rules = parse_rules(r#"
+[[rule]]
+name = "malware-client"
+on = ["device_scan"]
+severity = "critical"
+message = "{{ mac }} uses a known malware TLS client"
+match = { ja3 = ["72A589DA586844D7F0818CE684948EEA", "e7d705a3286e19ea42f587b344ee6865"] }
+"#, false).unwrap();
+        validate(&rules).unwrap();
+
+        let state = Arc::new(AggregatorState::new());
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let device = DeviceKey::new(SiteId::default(), mac);
+        state.devices.insert(device, DeviceState::new(device, Utc::now()));
+        let mut engine = RulesEngine::with_rules(config(), rules.clone(), Arc::clone(&state)).unwrap();
+        assert!(engine.scan().is_empty());
+
+        let hello: SensorFrame = serde_json::from_value(serde_json::json!({
+            "timestamp": Utc::now(),
+            "interface": "eth0",
+            "src_mac": mac.to_string(),
+            "dst_mac": "00:66:77:88:99:aa",
+            "ethertype": 0x0800,
+            "src_ip": "10.0.0.5",
+            "dst_ip": "185.1.2.3",
+            "ip_protocol": 6,
+            "src_port": 50000,
+            "dst_port": 443,
+            "tls": { "ja3": "72a589da586844d7f0818ce684948eea" },
+            "frame_size": 517,
+            "payload_size": 451,
+        })).unwrap();
+        state.process_frame(&hello);
+
+        let alerts = engine.scan();
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "00:11:22:33:44:55 uses a known malware TLS client");
+
+        let mut invalid = rules;
+        invalid[0].on = vec![Trigger::NewFlow];
+        assert!(validate(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_tcp_outcome_rules() {
+        let rules = parse_rules(r#"
+[[rule]]
+name = "rejected-rdp"
+on = ["flow_scan"]
+message = "{{ ip }} was refused by {{ dst_ip }}:{{ dst_port }} ({{ tcp_outcome }})"
+match = { tcp_outcome = ["rejected"], dst_port = [3389] }
+"#, false).unwrap();
+        validate(&rules).unwrap();
+
+        let state = Arc::new(AggregatorState::new());
+        let mut engine = RulesEngine::with_rules(config(), rules.clone(), Arc::clone(&state)).unwrap();
+        let segment = |from_client: bool, flags: u8| -> SensorFrame {
+            let (client, server) = (("00:11:22:33:44:55", "10.0.0.5", 50000), ("00:66:77:88:99:aa", "10.0.0.9", 3389));
+            let (src, dst) = if from_client { (client, server) } else { (server, client) };
+            let mut frame: SensorFrame = serde_json::from_value(serde_json::json!({
+                "timestamp": Utc::now(),
+                "interface": "eth0",
+                "src_mac": src.0,
+                "dst_mac": dst.0,
+                "ethertype": 0x0800,
+                "src_ip": src.1,
+                "dst_ip": dst.1,
+                "ip_protocol": 6,
+                "src_port": src.2,
+                "dst_port": dst.2,
+                "frame_size": 60,
+                "payload_size": 0,
+            })).unwrap();
+            frame.tcp_flags = Some(TcpFlags::from_byte(flags));
+            frame
+        };
+
+        // SYN answered by RST-ACK
+        state.process_frame(&segment(true, 0x02));
+        assert!(engine.scan().is_empty());
+        state.process_frame(&segment(false, 0x14));
+
+        let alerts = engine.scan();
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "10.0.0.5 was refused by 10.0.0.9:3389 (rejected)");
+
+        let mut invalid = rules;
+        invalid[0].on = vec![Trigger::DeviceScan];
+        assert!(validate(&invalid).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! External scanner identification
+//!
+//! Every new flow from an external address to an internal one is a probe,
+//! unless it answers a flow the internal side opened (its reverse flow is
+//! already in the state). Probes are counted per source over a sliding
+//! window; a source that reaches `min_hosts` distinct internal hosts
+//! (horizontal scan) or `min_services` distinct services (vertical scan)
+//! is a scanner.
+//!
+//! Scanners are kept in the `scanners` table with their first and last
+//! probe, probe count and the services they targeted, and alert again only
+//! after `cooldown_secs`. Alerts carry the scanner as their IP.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::bandwidth::{format_window, is_external};
+use crate::config::ScannersConfig;
+use crate::db::Database;
+use crate::events::{self, Event, EventSender};
+use crate::state::{AggregatorState, FlowKey, MacAddr, SensorId, SiteId};
+
+/// Probes kept per source within the window
+const MAX_PROBES: usize = 4096;
+
+/// Distinct hosts and services recorded per scanner
+const MAX_HOSTS: usize = 65_536;
+pub const MAX_SERVICES: usize = 1024;
+
+/// Scanners without a probe for this long are dropped from memory once
+/// written (the table keeps them)
+const IDLE_SECS: i64 = 86_400;
+
+/// Services listed in an alert
+const ALERT_SERVICES: usize = 10;
+
+/// `tcp/22`, `udp/161`, `icmp` style service name
+fn service_name(protocol: Option<u8>, port: Option<u16>) -> String {
+    let protocol = match protocol {
+        Some(1) => return "icmp".to_string(),
+        Some(6) => "tcp".to_string(),
+        Some(17) => "udp".to_string(),
+        Some(p) => format!("ip{}", p),
+        None => "l2".to_string(),
+    };
+    match port {
+        Some(port) => format!("{}/{}", protocol, port),
+        None => protocol,
+    }
+}
+
+struct Probe {
+    at: DateTime<Utc>,
+    host: Ipv4Addr,
+    service: String,
+}
+
+/// Recent probes of an external source
+#[derive(Default)]
+struct Source {
+    probes: VecDeque<Probe>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+/// An external source identified as a scanner
+pub struct Scanner {
+    pub ip: Ipv4Addr,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Probes since the last write
+    pub new_probes: u64,
+    pub hosts: HashSet<Ipv4Addr>,
+    pub services: BTreeSet<String>,
+    dirty: bool,
+}
+
+impl Scanner {
+    fn record(&mut self, probe: &Probe) {
+        self.first_seen = self.first_seen.min(probe.at);
+        self.last_seen = self.last_seen.max(probe.at);
+        self.new_probes += 1;
+        if self.hosts.len() < MAX_HOSTS {
+            self.hosts.insert(probe.host);
+        }
+        if self.services.len() < MAX_SERVICES {
+            self.services.insert(probe.service.clone());
+        }
+        self.dirty = true;
+    }
+}
+
+/// Identifies external sources probing internal hosts and services
+pub struct ScannerDetector {
+    config: ScannersConfig,
+    state: Arc<AggregatorState>,
+    db: Option<Arc<Database>>,
+    sources: HashMap<Ipv4Addr, Source>,
+    scanners: HashMap<Ipv4Addr, Scanner>,
+}
+
+impl ScannerDetector {
+    pub fn new(config: ScannersConfig, state: Arc<AggregatorState>) -> Self {
+        Self {
+            config,
+            state,
+            db: None,
+            sources: HashMap::new(),
+            scanners: HashMap::new(),
+        }
+    }
+
+    /// Record scanners in the `scanners` table
+    pub fn with_database(mut self, db: Arc<Database>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Source and probe of an inbound new-flow event that is not a reply
+    fn inbound(&self, event: &Event) -> Option<(Ipv4Addr, Probe)> {
+        let Event::NewFlow {
+            timestamp, site, sensor, src_mac, dst_mac, src_ip, dst_ip, src_port, dst_port, vlan_id, protocol,
+            icmp_type, icmp_code, icmp_id,
+        } = event else {
+            return None;
+        };
+        let (src, dst) = (src_ip.as_ref()?, dst_ip.as_ref()?);
+        if !is_external(*src) || is_external(*dst) {
+            return None;
+        }
+        if self.config.ignore_sources.iter().any(|c| c.contains(*src)) {
+            return None;
+        }
+
+        let reverse = FlowKey {
+            site: SiteId::new(site)?,
+            sensor: SensorId::new(sensor)?,
+            src_mac: MacAddr::from_string(dst_mac)?,
+            dst_mac: MacAddr::from_string(src_mac)?,
+            src_ip: Some(*dst),
+            dst_ip: Some(*src),
+            src_port: *dst_port,
+            dst_port: *src_port,
+            vlan_id: *vlan_id,
+            protocol: *protocol,
+            // An echo reply answers the echo request of the same identifier
+            icmp_type: icmp_type.map(|t| match t {
+                0 => 8,
+                8 => 0,
+                t => t,
+            }),
+            icmp_code: *icmp_code,
+            icmp_id: *icmp_id,
+        };
+        if self.state.flows.contains_key(&reverse) {
+            return None;
+        }
+
+        Some((*src, Probe { at: *timestamp, host: *dst, service: service_name(*protocol, *dst_port) }))
+    }
+
+    /// Record a new flow, returning an alert if its source is scanning
+    pub fn observe(&mut self, event: &Event) -> Option<Event> {
+        let (ip, probe) = self.inbound(event)?;
+        let now = probe.at;
+        let oldest = now - chrono::Duration::seconds(self.config.window_secs as i64);
+
+        if let Some(scanner) = self.scanners.get_mut(&ip) {
+            scanner.record(&probe);
+        }
+        let source = self.sources.entry(ip).or_default();
+        source.probes.push_back(probe);
+        while source.probes.len() > MAX_PROBES || source.probes.front().is_some_and(|p| p.at < oldest) {
+            source.probes.pop_front();
+        }
+
+        let hosts: HashSet<Ipv4Addr> = source.probes.iter().map(|p| p.host).collect();
+        let mut services: HashMap<&str, u64> = HashMap::new();
+        for probe in &source.probes {
+            *services.entry(&probe.service).or_default() += 1;
+        }
+        if hosts.len() < self.config.min_hosts && services.len() < self.config.min_services {
+            return None;
+        }
+
+        if let Entry::Vacant(entry) = self.scanners.entry(ip) {
+            info!("Identified external scanner {}", ip);
+            let scanner = entry.insert(Scanner {
+                ip,
+                first_seen: now,
+                last_seen: now,
+                new_probes: 0,
+                hosts: HashSet::new(),
+                services: BTreeSet::new(),
+                dirty: true,
+            });
+            for probe in &source.probes {
+                scanner.record(probe);
+            }
+        }
+
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        if source.last_alert.is_some_and(|at| now - at < cooldown) {
+            return None;
+        }
+        source.last_alert = Some(now);
+
+        let probes = source.probes.len();
+        let mut services: Vec<(&str, u64)> = services.into_iter().collect();
+        services.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let distinct_services = services.len();
+        services.truncate(ALERT_SERVICES);
+
+        Some(Event::Alert {
+            timestamp: now,
+            severity: self.config.severity,
+            name: self.config.name.clone(),
+            message: format!(
+                "External scanner {} probed {} hosts on {} services in {}",
+                ip, hosts.len(), distinct_services, format_window(self.config.window_secs)
+            ),
+            site: None,
+            sensor: None,
+            mac: None,
+            ip: Some(ip),
+            channels: self.config.notify.clone(),
+            details: Some(json!({
+                "probes": probes,
+                "hosts": hosts.len(),
+                "services": distinct_services,
+                "window_secs": self.config.window_secs,
+                "top_services": services.iter().map(|(service, probes)| json!({
+                    "service": service,
+                    "probes": probes,
+                })).collect::<Vec<_>>(),
+            })),
+        })
+    }
+
+    /// Write scanners with new probes to the database
+    async fn flush(&mut self) {
+        let Some(db) = &self.db else { return };
+
+        for scanner in self.scanners.values_mut().filter(|s| s.dirty) {
+            match db.upsert_scanner(scanner).await {
+                Ok(()) => {
+                    scanner.new_probes = 0;
+                    scanner.dirty = false;
+                }
+                Err(e) => warn!("Failed to record scanner {}: {}", scanner.ip, e),
+            }
+        }
+    }
+
+    /// Forget sources without a probe in the window and idle scanners
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let oldest = now - chrono::Duration::seconds(self.config.window_secs as i64);
+        let cooldown = chrono::Duration::seconds(self.config.cooldown_secs as i64);
+        self.sources.retain(|_, s| {
+            s.probes.back().is_some_and(|p| p.at >= oldest) || s.last_alert.is_some_and(|at| now - at < cooldown)
+        });
+
+        let idle = now - chrono::Duration::seconds(IDLE_SECS);
+        self.scanners.retain(|_, s| s.dirty || s.last_seen >= idle);
+    }
+
+    /// Check new flows until shutdown, sending alerts on `output`
+    pub async fn run(mut self, mut events: broadcast::Receiver<Event>, output: EventSender, mut shutdown: broadcast::Receiver<()>) {
+        info!(
+            "External scanner detection enabled ({} hosts or {} services within {})",
+            self.config.min_hosts, self.config.min_services, format_window(self.config.window_secs)
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.flush_interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    self.flush().await;
+                    self.prune(Utc::now());
+                }
+                event = events::recv(&mut events, "Scanner detection") => {
+                    let Some(event) = event else { break };
+                    if let Some(alert) = self.observe(&event) {
+                        let _ = output.send(alert);
+                    }
+                }
+            }
+        }
+
+        self.flush().await;
+        debug!("Scanner detection stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{FlowState, SensorId, SiteId};
+
+    const GATEWAY: [u8; 6] = [0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0x01];
+
+    fn detector(state: Arc<AggregatorState>) -> ScannerDetector {
+        let config: ScannersConfig = toml::from_str(r#"
+min_hosts = 5
+min_services = 4
+ignore_sources = ["192.0.2.0/24"]
+"#).unwrap();
+        ScannerDetector::new(config, state)
+    }
+
+    fn key(src: [u8; 4], src_port: u16, dst: [u8; 4], dst_port: u16) -> FlowKey {
+        let host = if Ipv4Addr::from(src).is_private() { src[3] } else { dst[3] };
+        let host_mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, host]);
+        let outbound = Ipv4Addr::from(src).is_private();
+        FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: if outbound { host_mac } else { MacAddr::new(GATEWAY) },
+            dst_mac: if outbound { MacAddr::new(GATEWAY) } else { host_mac },
+            src_ip: Some(Ipv4Addr::from(src)),
+            dst_ip: Some(Ipv4Addr::from(dst)),
+            src_port: Some(src_port),
+            dst_port: Some(dst_port),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        }
+    }
+
+    #[test]
+    fn test_horizontal_scan() {
+        let state = Arc::new(AggregatorState::new());
+        let mut detector = detector(Arc::clone(&state));
+        let start = Utc::now();
+
+        // Replies to connections opened from inside are not probes
+        for host in 1..=8u8 {
+            let outbound = key([10, 0, 0, host], 40000, [198, 51, 100, 7], 443);
+            state.flows.insert(outbound.clone(), FlowState::new(outbound, start));
+            let reply = key([198, 51, 100, 7], 443, [10, 0, 0, host], 40000);
+            assert!(detector.observe(&Event::new_flow(&reply, start)).is_none());
+        }
+
+        let mut alerts = Vec::new();
+        for host in 1..=8u8 {
+            let at = start + chrono::Duration::seconds(host as i64);
+            let probe = key([203, 0, 113, 9], 61000, [10, 0, 0, host], 22);
+            alerts.extend(detector.observe(&Event::new_flow(&probe, at)));
+            // Authorized scanner
+            let probe = key([192, 0, 2, 10], 61000, [10, 0, 0, host], 22);
+            assert!(detector.observe(&Event::new_flow(&probe, at)).is_none());
+        }
+
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { message, ip, .. } = &alerts[0] else { panic!() };
+        assert_eq!(message, "External scanner 203.0.113.9 probed 5 hosts on 1 services in 5m");
+        assert_eq!(*ip, Some(Ipv4Addr::new(203, 0, 113, 9)));
+
+        let scanner = &detector.scanners[&Ipv4Addr::new(203, 0, 113, 9)];
+        assert_eq!(scanner.new_probes, 8);
+        assert_eq!(scanner.hosts.len(), 8);
+        assert_eq!(scanner.services.iter().collect::<Vec<_>>(), vec!["tcp/22"]);
+        assert!(!detector.scanners.contains_key(&Ipv4Addr::new(198, 51, 100, 7)));
+    }
+
+    #[test]
+    fn test_vertical_scan_window() {
+        let mut detector = detector(Arc::new(AggregatorState::new()));
+        let start = Utc::now();
+
+        // Probes spread wider than the window never add up
+        for (i, port) in [21, 22, 23, 25].into_iter().enumerate() {
+            let at = start + chrono::Duration::seconds(200 * i as i64);
+            let probe = key([203, 0, 113, 9], 61000, [10, 0, 0, 5], port);
+            assert!(detector.observe(&Event::new_flow(&probe, at)).is_none());
+        }
+
+        let at = start + chrono::Duration::seconds(700);
+        let mut alerts = Vec::new();
+        for port in [80, 443, 3389] {
+            let probe = key([203, 0, 113, 9], 61000, [10, 0, 0, 5], port);
+            alerts.extend(detector.observe(&Event::new_flow(&probe, at)));
+        }
+        assert_eq!(alerts.len(), 1);
+        let Event::Alert { details, .. } = &alerts[0] else { panic!() };
+        assert_eq!(details.as_ref().unwrap()["services"], 4);
+    }
+}
@@ -0,0 +1,269 @@
+//! ServiceNow CMDB export
+//!
+//! Turns the persisted device inventory into ServiceNow configuration items
+//! on a schedule. Each device becomes one CI whose class is picked by the
+//! configured mapping rules. CIs are either created or updated through the
+//! Table API (matched on MAC address) or written as a JSON import-set file
+//! for a ServiceNow data source to pick up.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::{CiClassRule, ServiceNowConfig, ServiceNowOutput};
+use crate::db::{Database, DeviceFilter};
+use crate::state::{DeviceSnapshot, MacAddr};
+
+/// ServiceNow date-time format (UTC)
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// A configuration item as sent to ServiceNow
+#[derive(Debug, Clone, Serialize)]
+pub struct CiRecord {
+    pub sys_class_name: String,
+    pub name: String,
+    pub mac_address: String,
+    pub ip_address: String,
+    pub short_description: String,
+    pub first_discovered: String,
+    pub last_discovered: String,
+}
+
+impl CiRecord {
+    /// Build the CI for a device, picking its class from `rules`
+    pub fn from_device(device: &DeviceSnapshot, rules: &[CiClassRule], default_class: &str) -> Self {
+        let latest_ip = device.cmdb_ips().max_by_key(|ip| ip.last_seen);
+
+        let mut description = "Discovered by NetSentinel".to_string();
+        if !device.vlans.is_empty() {
+            let vlans: Vec<String> = device.vlans.iter().map(u16::to_string).collect();
+            description.push_str(&format!(" on VLAN {}", vlans.join(", ")));
+        }
+
+        Self {
+            sys_class_name: class_for(device, rules).unwrap_or(default_class).to_string(),
+            name: device.mac_address.clone(),
+            mac_address: device.mac_address.clone(),
+            ip_address: latest_ip.map(|ip| ip.ip_address.to_string()).unwrap_or_default(),
+            short_description: description,
+            first_discovered: format_date(device.first_seen),
+            last_discovered: format_date(device.last_seen),
+        }
+    }
+}
+
+/// Exports the device inventory to the ServiceNow CMDB
+pub struct ServiceNowExporter {
+    config: ServiceNowConfig,
+    client: reqwest::Client,
+    db: Arc<Database>,
+    inactivity_timeout: u64,
+}
+
+impl ServiceNowExporter {
+    /// Create an exporter reading the inventory from `db`
+    pub fn new(config: ServiceNowConfig, db: Arc<Database>, inactivity_timeout: u64) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .with_context(|| "Failed to build ServiceNow HTTP client")?;
+
+        Ok(Self { config, client, db, inactivity_timeout })
+    }
+
+    /// Export the inventory once, returning the number of CIs exported
+    pub async fn export(&self) -> Result<usize> {
+        let filter = DeviceFilter {
+            active: (!self.config.include_inactive).then_some(true),
+            min_confidence: (self.config.min_confidence > 0).then_some(self.config.min_confidence),
+            ..Default::default()
+        };
+        let records: Vec<CiRecord> = self.db
+            .all_devices(&filter, self.inactivity_timeout)
+            .await?
+            .iter()
+            .map(|device| CiRecord::from_device(device, &self.config.classes, &self.config.default_class))
+            .collect();
+
+        match self.config.output {
+            ServiceNowOutput::TableApi => {
+                let mut failed = 0;
+                for record in &records {
+                    if let Err(e) = self.upsert(record).await {
+                        warn!("ServiceNow: CI {}: {:#}", record.mac_address, e);
+                        failed += 1;
+                    }
+                }
+                info!("ServiceNow export: {} CIs sent, {} failed", records.len() - failed, failed);
+                Ok(records.len() - failed)
+            }
+            ServiceNowOutput::ImportSet => {
+                let dir = self.config.output_dir.as_deref().context("servicenow.output_dir is not set")?;
+                let path = write_import_set(dir, &records)?;
+                info!("ServiceNow export: {} CIs written to {:?}", records.len(), path);
+                Ok(records.len())
+            }
+        }
+    }
+
+    /// Export every `interval_secs` until shutdown
+    pub async fn run(self, mut shutdown: broadcast::Receiver<()>) {
+        info!("Exporting CMDB items to ServiceNow every {}s", self.config.interval_secs);
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => break,
+                _ = ticker.tick() => {
+                    if let Err(e) = self.export().await {
+                        warn!("ServiceNow export failed: {:#}", e);
+                    }
+                }
+            }
+        }
+
+        debug!("ServiceNow exporter stopped");
+    }
+
+    /// Create or update a CI through the Table API, matching on MAC address
+    async fn upsert(&self, record: &CiRecord) -> Result<()> {
+        let base = self.config.instance_url.as_deref().context("servicenow.instance_url is not set")?;
+        let url = format!("{}/api/now/table/{}", base.trim_end_matches('/'), record.sys_class_name);
+
+        let found: Value = self
+            .request(self.client.get(&url))
+            .query(&[
+                ("sysparm_query", format!("mac_address={}", record.mac_address)),
+                ("sysparm_fields", "sys_id".to_string()),
+                ("sysparm_limit", "1".to_string()),
+            ])
+            .send()
+            .await
+            .with_context(|| "lookup failed")?
+            .error_for_status()
+            .with_context(|| "lookup rejected")?
+            .json()
+            .await
+            .with_context(|| "invalid lookup response")?;
+
+        let request = match found["result"][0]["sys_id"].as_str() {
+            Some(sys_id) => self.client.patch(format!("{}/{}", url, sys_id)),
+            None => self.client.post(&url),
+        };
+        self.request(request)
+            .json(record)
+            .send()
+            .await
+            .with_context(|| "write failed")?
+            .error_for_status()
+            .with_context(|| "write rejected")?;
+
+        Ok(())
+    }
+
+    fn request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = request.header(reqwest::header::ACCEPT, "application/json");
+        match &self.config.username {
+            Some(user) => request.basic_auth(user, self.config.password.as_ref()),
+            None => request,
+        }
+    }
+}
+
+/// CI class of the first rule matching `device`
+fn class_for<'a>(device: &DeviceSnapshot, rules: &'a [CiClassRule]) -> Option<&'a str> {
+    let oui = MacAddr::from_string(&device.mac_address).map(|mac| mac.oui_prefix());
+
+    rules.iter()
+        .find(|rule| {
+            rule.oui.as_ref().is_none_or(|o| oui.as_ref().is_some_and(|oui| oui.eq_ignore_ascii_case(o)))
+                && rule.gateway.is_none_or(|g| g == device.is_gateway)
+                && rule.vlan.is_none_or(|v| device.vlans.contains(&v))
+        })
+        .map(|rule| rule.class.as_str())
+}
+
+fn format_date(timestamp: DateTime<Utc>) -> String {
+    timestamp.format(DATE_FORMAT).to_string()
+}
+
+/// Write `records` as an import-set file, returning its path
+///
+/// The file is written under a temporary name and renamed so that a data
+/// source polling the directory never reads a partial file.
+fn write_import_set(dir: &Path, records: &[CiRecord]) -> Result<std::path::PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+    let name = format!("netsentinel_ci_{}.json", Utc::now().format("%Y%m%dT%H%M%S"));
+    let path = dir.join(&name);
+    let tmp = dir.join(format!(".{}.tmp", name));
+
+    let body = serde_json::to_vec_pretty(&serde_json::json!({ "records": records }))?;
+    std::fs::write(&tmp, body).with_context(|| format!("Failed to write {:?}", tmp))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("Failed to rename {:?}", tmp))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn device(mac: &str, is_gateway: bool, vlans: Vec<u16>) -> DeviceSnapshot {
+        let timestamp = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        DeviceSnapshot {
+            id: uuid::Uuid::nil(),
+            site: "default".to_string(),
+            mac_address: mac.to_string(),
+            first_seen: timestamp,
+            last_seen: timestamp,
+            packets_sent: 0,
+            packets_received: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            is_gateway,
+            is_flagged: false,
+            confidence: 0,
+            evidence: Vec::new(),
+            tags: Default::default(),
+            ip_addresses: Vec::new(),
+            vlans,
+            tcp: Default::default(),
+            sites: Vec::new(),
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn test_class_mapping() {
+        let rules = vec![
+            CiClassRule { oui: None, gateway: Some(true), vlan: None, class: "cmdb_ci_ip_router".to_string() },
+            CiClassRule {
+                oui: Some("00:1b:63".to_string()),
+                gateway: None,
+                vlan: Some(30),
+                class: "cmdb_ci_computer".to_string(),
+            },
+        ];
+
+        let router = CiRecord::from_device(&device("aa:bb:cc:00:00:01", true, vec![]), &rules, "cmdb_ci_ip_device");
+        assert_eq!(router.sys_class_name, "cmdb_ci_ip_router");
+
+        let mac = CiRecord::from_device(&device("00:1b:63:00:00:02", false, vec![30]), &rules, "cmdb_ci_ip_device");
+        assert_eq!(mac.sys_class_name, "cmdb_ci_computer");
+        assert_eq!(mac.short_description, "Discovered by NetSentinel on VLAN 30");
+        assert_eq!(mac.first_discovered, "2024-03-01 12:00:00");
+
+        let other = CiRecord::from_device(&device("00:1b:63:00:00:03", false, vec![20]), &rules, "cmdb_ci_ip_device");
+        assert_eq!(other.sys_class_name, "cmdb_ci_ip_device");
+    }
+}
@@ -0,0 +1,134 @@
+//! Per-device anomaly scores
+//!
+//! The baseline detectors (outbound volume, NXDOMAIN responses) score every
+//! period or sample of a device once its baseline is learned: how many
+//! standard deviations it lies above (or below) the mean. Scores are summed
+//! per device, detector and hour for the persister, so a device drifting
+//! away from its baseline shows before any period crosses an alert
+//! threshold.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+
+use super::composition::HOUR_SECS;
+use super::DeviceKey;
+
+/// Scores of a device by one detector during one hour
+#[derive(Debug, Clone, Default)]
+pub struct HourlyScore {
+    pub samples: u64,
+    pub score_sum: f64,
+    pub max_score: f64,
+    /// Sum of the values scored (bytes, NXDOMAIN responses)
+    pub value_sum: f64,
+    /// Baseline mean at the last sample
+    pub baseline: f64,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+impl HourlyScore {
+    pub fn mean_score(&self) -> f64 {
+        self.score_sum / self.samples.max(1) as f64
+    }
+
+    pub fn mean_value(&self) -> f64 {
+        self.value_sum / self.samples.max(1) as f64
+    }
+}
+
+/// Hourly anomaly scores by device and detector
+#[derive(Default)]
+pub struct AnomalyScores {
+    /// Keyed by device, detector and start of the hour (unix time)
+    pub hours: DashMap<(DeviceKey, &'static str, i64), HourlyScore>,
+}
+
+impl AnomalyScores {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the `score` of `value` of `device` by `detector`, measured
+    /// against a baseline of mean `baseline` at unix time `at`
+    pub fn record(&self, device: DeviceKey, detector: &'static str, at: i64, value: f64, baseline: f64, score: f64) {
+        let hour = at.div_euclid(HOUR_SECS) * HOUR_SECS;
+        let mut entry = self.hours.entry((device, detector, hour)).or_default();
+        entry.max_score = if entry.samples == 0 { score } else { entry.max_score.max(score) };
+        entry.samples += 1;
+        entry.score_sum += score;
+        entry.value_sum += value;
+        entry.baseline = baseline;
+        entry.dirty = true;
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, _, hour), score| *hour >= before || score.dirty);
+    }
+}
+
+/// Change of `scores` per day, the least squares slope over their hours:
+/// positive when a device drifts away above its baseline
+pub fn slope_per_day(scores: &[(DateTime<Utc>, f64)]) -> f64 {
+    if scores.len() < 2 {
+        return 0.0;
+    }
+    let n = scores.len() as f64;
+    let start = scores[0].0;
+    let days: Vec<f64> = scores.iter().map(|(at, _)| (*at - start).num_seconds() as f64 / 86_400.0).collect();
+    let mean_day = days.iter().sum::<f64>() / n;
+    let mean_score = scores.iter().map(|(_, score)| score).sum::<f64>() / n;
+
+    let (covariance, variance) = days.iter().zip(scores).fold((0.0, 0.0), |(cov, var), (day, (_, score))| {
+        (cov + (day - mean_day) * (score - mean_score), var + (day - mean_day).powi(2))
+    });
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+
+    #[test]
+    fn test_hourly_scores() {
+        let scores = AnomalyScores::new();
+        let device = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:42:00Z").unwrap().timestamp();
+
+        scores.record(device, "nxdomain", at, 2.0, 1.0, -0.5);
+        scores.record(device, "nxdomain", at + 60, 8.0, 1.0, 3.5);
+        scores.record(device, "nxdomain", at + 3600, 1.0, 1.5, 0.0);
+
+        let hour = at.div_euclid(HOUR_SECS) * HOUR_SECS;
+        let first = scores.hours.get(&(device, "nxdomain", hour)).unwrap().clone();
+        assert_eq!(first.samples, 2);
+        assert_eq!(first.mean_score(), 1.5);
+        assert_eq!(first.max_score, 3.5);
+        assert_eq!(first.mean_value(), 5.0);
+        assert_eq!(scores.hours.len(), 2);
+
+        scores.hours.alter_all(|_, mut score| {
+            score.dirty = false;
+            score
+        });
+        scores.prune(hour + HOUR_SECS);
+        assert_eq!(scores.hours.len(), 1);
+    }
+
+    #[test]
+    fn test_slope_per_day() {
+        let start = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z").unwrap().to_utc();
+        let drifting: Vec<_> = (0..48).map(|h| (start + chrono::Duration::hours(h), h as f64 / 24.0)).collect();
+        assert!((slope_per_day(&drifting) - 1.0).abs() < 1e-9);
+
+        let steady: Vec<_> = (0..48).map(|h| (start + chrono::Duration::hours(h), 0.3)).collect();
+        assert!(slope_per_day(&steady).abs() < 1e-9);
+        assert_eq!(slope_per_day(&drifting[..1]), 0.0);
+    }
+}
@@ -0,0 +1,91 @@
+//! Sensor clock offsets
+//!
+//! Each consumed frame gives one offset: the time it reached the aggregator
+//! minus its capture timestamp, that is its transit time plus the skew of
+//! the sensor's clock. Frames read from the stream are received at their
+//! entry time, so a consumer catching up on a backlog does not count as
+//! skew; frames handed over in memory are received at the wall clock.
+//!
+//! Transit is never negative and the fastest frames barely wait, so the
+//! smallest offset of a window estimates the skew: positive when the sensor
+//! clock is behind, negative when it is ahead.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+
+use super::{SensorFrame, SensorId, SiteId};
+
+/// Offsets of one sensor's frames since the window started
+#[derive(Debug, Clone, Copy)]
+pub struct ClockWindow {
+    /// Site of the sensor's last frame
+    pub site: SiteId,
+    pub frames: u64,
+    /// Smallest receive minus capture time (milliseconds)
+    pub min_offset_ms: i64,
+    /// Largest receive minus capture time (milliseconds)
+    pub max_offset_ms: i64,
+}
+
+/// Clock offset windows per sensor
+#[derive(Default)]
+pub struct SensorClocks {
+    windows: DashMap<SensorId, ClockWindow>,
+}
+
+impl SensorClocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the offset of a frame received at `received`
+    pub fn record(&self, frame: &SensorFrame, received: DateTime<Utc>) {
+        let offset_ms = (received - frame.timestamp).num_milliseconds();
+        self.windows
+            .entry(frame.sensor)
+            .and_modify(|window| {
+                window.site = frame.site;
+                window.frames += 1;
+                window.min_offset_ms = window.min_offset_ms.min(offset_ms);
+                window.max_offset_ms = window.max_offset_ms.max(offset_ms);
+            })
+            .or_insert(ClockWindow { site: frame.site, frames: 1, min_offset_ms: offset_ms, max_offset_ms: offset_ms });
+    }
+
+    /// Take the windows of every sensor, starting new ones
+    pub fn take(&self) -> Vec<(SensorId, ClockWindow)> {
+        let sensors: Vec<SensorId> = self.windows.iter().map(|entry| *entry.key()).collect();
+        sensors.into_iter().filter_map(|sensor| self.windows.remove(&sensor)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CapturedFrame, MacAddr};
+    use chrono::Duration;
+
+    #[test]
+    fn test_window_keeps_smallest_offset() {
+        let clocks = SensorClocks::new();
+        let received = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+        let mut frame = SensorFrame {
+            site: SiteId::new("lyon").unwrap(),
+            sensor: SensorId::new("edge-1").unwrap(),
+            frame: CapturedFrame::new("eth0", MacAddr::new([0, 1, 2, 3, 4, 5]), MacAddr::new([0, 1, 2, 3, 4, 6]), 0x0800, 60),
+        };
+
+        // Sensor 5s ahead, frames waiting up to 300ms in its batches
+        for wait_ms in [300, 20, 150] {
+            frame.frame.timestamp = received + Duration::seconds(5) - Duration::milliseconds(wait_ms);
+            clocks.record(&frame, received);
+        }
+
+        let windows = clocks.take();
+        assert_eq!(windows.len(), 1);
+        let (sensor, window) = windows[0];
+        assert_eq!(sensor.to_string(), "edge-1");
+        assert_eq!((window.frames, window.min_offset_ms, window.max_offset_ms), (3, -4980, -4700));
+        assert!(clocks.take().is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Per-device hourly traffic composition
+//!
+//! Bytes each device sends and receives are counted per hour and service,
+//! so its traffic mix can be shown without going through the flows. The
+//! service of a frame is its IP protocol and the lower of its two ports,
+//! which is the server side for most client traffic, or its ethertype for
+//! non-IP frames.
+//!
+//! Each hour is stored as its top services plus an `other` bucket, and
+//! rewritten whole as it fills up: an hour under way when the aggregator
+//! restarts only keeps what was counted after the restart.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::DeviceKey;
+
+/// Length of a composition period
+pub const HOUR_SECS: i64 = 3600;
+
+/// Name of the bucket the services beyond the top N are summed into
+pub const OTHER: &str = "other";
+
+/// Service a frame is counted under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Service {
+    pub ethertype: u16,
+    pub protocol: Option<u8>,
+    pub port: Option<u16>,
+}
+
+impl Service {
+    pub fn new(ethertype: u16, protocol: Option<u8>, src_port: Option<u16>, dst_port: Option<u16>) -> Self {
+        let port = match (src_port, dst_port) {
+            (Some(src), Some(dst)) => Some(src.min(dst)),
+            (port, None) | (None, port) => port,
+        };
+        Self { ethertype, protocol, port }
+    }
+
+    /// `tcp/443`, `udp/53`, `icmp`, `arp` style name
+    pub fn name(&self) -> String {
+        let protocol = match (self.ethertype, self.protocol) {
+            (0x0800, Some(1)) => return "icmp".to_string(),
+            (0x0800, Some(6)) => "tcp".to_string(),
+            (0x0800, Some(17)) => "udp".to_string(),
+            (0x0800, Some(p)) => format!("ip{}", p),
+            (0x0800, None) => return "ipv4".to_string(),
+            (0x0806, _) => return "arp".to_string(),
+            (0x86dd, _) => return "ipv6".to_string(),
+            (0x88cc, _) => return "lldp".to_string(),
+            (ethertype, _) => return format!("ethertype/0x{:04x}", ethertype),
+        };
+        match self.port {
+            Some(port) => format!("{}/{}", protocol, port),
+            None => protocol,
+        }
+    }
+}
+
+/// Port of a service name (`tcp/443`), none for portless services
+pub fn port_of(name: &str) -> Option<u16> {
+    match name.split_once('/')? {
+        ("tcp" | "udp", port) => port.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Bytes of one service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ServiceBytes {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl ServiceBytes {
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+/// Traffic of one device during one hour
+#[derive(Debug, Default)]
+pub struct HourlyTraffic {
+    pub services: HashMap<Service, ServiceBytes>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+impl HourlyTraffic {
+    /// The `n` services with the most bytes, and the rest summed as `other`
+    pub fn top(&self, n: usize) -> Vec<(String, ServiceBytes)> {
+        let mut services: Vec<(&Service, &ServiceBytes)> = self.services.iter().collect();
+        services.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then_with(|| a.0.name().cmp(&b.0.name())));
+
+        let mut top: Vec<(String, ServiceBytes)> = services.iter()
+            .take(n)
+            .map(|(service, bytes)| (service.name(), **bytes))
+            .collect();
+        if services.len() > n {
+            let other = services[n..].iter().fold(ServiceBytes::default(), |sum, (_, bytes)| ServiceBytes {
+                sent: sum.sent + bytes.sent,
+                received: sum.received + bytes.received,
+            });
+            top.push((OTHER.to_string(), other));
+        }
+        top
+    }
+}
+
+/// Start of the hour `at` falls in (unix time)
+pub fn hour_of(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(HOUR_SECS) * HOUR_SECS
+}
+
+/// Hourly traffic by device
+#[derive(Default)]
+pub struct TrafficComposition {
+    /// Keyed by device and start of the hour (unix time)
+    pub hours: DashMap<(DeviceKey, i64), HourlyTraffic>,
+}
+
+impl TrafficComposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `bytes` of `service` sent or received by `device` at `now`
+    pub fn record(&self, device: DeviceKey, service: Service, bytes: u64, sent: bool, now: DateTime<Utc>) {
+        let mut hour = self.hours.entry((device, hour_of(now))).or_default();
+        let counters = hour.services.entry(service).or_default();
+        if sent {
+            counters.sent += bytes;
+        } else {
+            counters.received += bytes;
+        }
+        hour.dirty = true;
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, hour), traffic| *hour >= before || traffic.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_hourly_top_services() {
+        let composition = TrafficComposition::new();
+        let mac = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 10, 42, 0).unwrap();
+
+        let https = Service::new(0x0800, Some(6), Some(51000), Some(443));
+        composition.record(mac, https, 1000, true, at);
+        composition.record(mac, Service::new(0x0800, Some(6), Some(443), Some(51000)), 9000, false, at);
+        composition.record(mac, Service::new(0x0800, Some(17), Some(53), Some(40000)), 300, false, at);
+        composition.record(mac, Service::new(0x0800, Some(1), None, None), 200, true, at);
+        composition.record(mac, Service::new(0x0806, None, None, None), 100, true, at);
+        // Next hour
+        composition.record(mac, https, 500, true, at + chrono::Duration::minutes(20));
+
+        let hour = composition.hours.get(&(mac, hour_of(at))).unwrap();
+        let top = hour.top(2);
+        assert_eq!(top, vec![
+            ("tcp/443".to_string(), ServiceBytes { sent: 1000, received: 9000 }),
+            ("udp/53".to_string(), ServiceBytes { sent: 0, received: 300 }),
+            (OTHER.to_string(), ServiceBytes { sent: 300, received: 0 }),
+        ]);
+        assert_eq!(hour_of(at), Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap().timestamp());
+        assert_eq!(port_of("udp/53"), Some(53));
+        assert_eq!(port_of("ethertype/0x88b5"), None);
+        assert_eq!(port_of(OTHER), None);
+        drop(hour);
+
+        for mut hour in composition.hours.iter_mut() {
+            hour.dirty = false;
+        }
+        composition.prune(hour_of(at) + HOUR_SECS);
+        assert_eq!(composition.hours.len(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+//! Service dependency map
+//!
+//! TCP and UDP traffic between two devices is rolled up into dependency
+//! edges: client device, server device and service (`tcp/443`). As in the
+//! traffic composition, the server is the side of the lower port, so every
+//! connection a client opens to the same service of a server lands on one
+//! edge whatever its ephemeral port. Edges count the flows opened, traffic
+//! each way and the hours they were active in, which tells a standing
+//! dependency from a one-off connection.
+//!
+//! Counters are kept as the traffic since the edge was last persisted and
+//! added to the stored totals, so they survive restarts.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+
+use super::composition::hour_of;
+use super::{DeviceKey, SensorFrame, Service, ETHERTYPE_IPV4};
+
+/// Client device, server device and service of an edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DependencyKey {
+    pub client: DeviceKey,
+    pub server: DeviceKey,
+    pub service: Service,
+}
+
+/// Traffic of an edge since it was last persisted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DependencyTraffic {
+    /// Flows the client opened
+    pub flows: u64,
+    pub packets_to_server: u64,
+    pub bytes_to_server: u64,
+    pub packets_to_client: u64,
+    pub bytes_to_client: u64,
+    /// Distinct hours with traffic
+    pub active_hours: u32,
+    /// First and last frame counted
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// A client's dependency on a service of a server
+#[derive(Debug)]
+pub struct Dependency {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub pending: DependencyTraffic,
+    /// Hour (unix time) last counted into `pending.active_hours`
+    hour: Option<i64>,
+}
+
+impl Dependency {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self { first_seen: now, last_seen: now, pending: DependencyTraffic::default(), hour: None }
+    }
+
+    fn record(&mut self, bytes: u64, to_server: bool, new_flow: bool, now: DateTime<Utc>) {
+        let pending = &mut self.pending;
+        if to_server {
+            pending.packets_to_server += 1;
+            pending.bytes_to_server += bytes;
+            if new_flow {
+                pending.flows += 1;
+            }
+        } else {
+            pending.packets_to_client += 1;
+            pending.bytes_to_client += bytes;
+        }
+
+        let hour = hour_of(now);
+        if self.hour != Some(hour) {
+            self.hour = Some(hour);
+            pending.active_hours += 1;
+        }
+        pending.since.get_or_insert(now);
+        pending.until = Some(now);
+        self.last_seen = self.last_seen.max(now);
+    }
+
+    /// Take the traffic counted since the last call, if any
+    ///
+    /// The hour under way is counted again by the next frame; the store
+    /// drops hours it already has.
+    pub fn take(&mut self) -> Option<DependencyTraffic> {
+        self.pending.since?;
+        self.hour = None;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// Put back traffic that failed to persist
+    pub fn restore(&mut self, traffic: DependencyTraffic) {
+        let pending = &mut self.pending;
+        pending.flows += traffic.flows;
+        pending.packets_to_server += traffic.packets_to_server;
+        pending.bytes_to_server += traffic.bytes_to_server;
+        pending.packets_to_client += traffic.packets_to_client;
+        pending.bytes_to_client += traffic.bytes_to_client;
+        pending.active_hours += traffic.active_hours;
+        pending.since = pending.since.min(traffic.since).or(traffic.since);
+        pending.until = pending.until.max(traffic.until);
+    }
+}
+
+/// Dependency edges between devices
+#[derive(Default)]
+pub struct DependencyMap {
+    pub edges: DashMap<DependencyKey, Dependency>,
+}
+
+impl DependencyMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `frame` against its edge, `new_flow` when it opened a flow
+    pub fn record(&self, frame: &SensorFrame, src: DeviceKey, dst: DeviceKey, new_flow: bool, now: DateTime<Utc>) {
+        let (Some(protocol @ (6 | 17)), Some(src_port), Some(dst_port)) = (frame.ip_protocol, frame.src_port, frame.dst_port) else {
+            return;
+        };
+        if frame.ethertype != ETHERTYPE_IPV4 || src == dst {
+            return;
+        }
+
+        // Between equal ports, the sender is taken as the client
+        let to_server = dst_port <= src_port;
+        let (client, server) = if to_server { (src, dst) } else { (dst, src) };
+        let key = DependencyKey {
+            client,
+            server,
+            service: Service::new(frame.ethertype, Some(protocol), Some(src_port), Some(dst_port)),
+        };
+
+        self.edges.entry(key)
+            .or_insert_with(|| Dependency::new(now))
+            .record(frame.frame_size as u64, to_server, new_flow, now);
+    }
+
+    /// Forget edges with nothing left to persist, idle since `before`
+    pub fn prune(&self, before: DateTime<Utc>) {
+        self.edges.retain(|_, edge| edge.last_seen >= before || edge.pending.since.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+    use chrono::TimeZone;
+
+    const CLIENT: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const SERVER: [u8; 6] = [0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+    fn frame(reply: bool, src_port: u16, dst_port: u16) -> SensorFrame {
+        let (src, dst) = if reply { (SERVER, CLIENT) } else { (CLIENT, SERVER) };
+        serde_json::from_value(serde_json::json!({
+            "timestamp": Utc::now(),
+            "interface": "eth0",
+            "src_mac": MacAddr::new(src).to_string(),
+            "dst_mac": MacAddr::new(dst).to_string(),
+            "ethertype": 0x0800,
+            "ip_protocol": 6,
+            "src_port": src_port,
+            "dst_port": dst_port,
+            "frame_size": 100,
+            "payload_size": 40,
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_edges() {
+        let map = DependencyMap::new();
+        let site = SiteId::default();
+        let client = DeviceKey::new(site, MacAddr::new(CLIENT));
+        let server = DeviceKey::new(site, MacAddr::new(SERVER));
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 10, 30, 0).unwrap();
+
+        // Two connections to the same service, and the server's replies
+        map.record(&frame(false, 50000, 443), client, server, true, at);
+        map.record(&frame(true, 443, 50000), server, client, true, at);
+        map.record(&frame(false, 50001, 443), client, server, true, at + chrono::Duration::hours(1));
+        map.record(&frame(false, 50001, 443), client, server, false, at + chrono::Duration::hours(1));
+
+        assert_eq!(map.edges.len(), 1);
+        let key = DependencyKey { client, server, service: Service::new(0x0800, Some(6), Some(443), None) };
+        let mut edge = map.edges.get_mut(&key).unwrap();
+        assert_eq!(edge.first_seen, at);
+
+        let traffic = edge.take().unwrap();
+        assert_eq!(traffic.flows, 2);
+        assert_eq!((traffic.packets_to_server, traffic.bytes_to_server), (3, 300));
+        assert_eq!((traffic.packets_to_client, traffic.bytes_to_client), (1, 100));
+        assert_eq!(traffic.active_hours, 2);
+        assert_eq!(traffic.since, Some(at));
+        assert!(edge.take().is_none());
+
+        edge.restore(traffic);
+        assert_eq!(edge.take(), Some(traffic));
+        drop(edge);
+
+        map.prune(at + chrono::Duration::hours(2));
+        assert!(map.edges.is_empty());
+    }
+}
@@ -1,18 +1,81 @@
 //! Device state management
+//!
+//! Besides its counters, each device records the kinds of evidence it was
+//! seen with. A device that answered ARP, took a DHCP lease and announced a
+//! hostname is almost certainly real; one seen once as the source of a
+//! multicast frame may be a spoofed or transient address. The confidence
+//! score weighs that evidence and the device's traffic volume from 0 to 100.
 
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, AtomicBool, Ordering};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicBool, Ordering};
 use std::net::Ipv4Addr;
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use uuid::Uuid;
 
-use super::MacAddr;
+use super::tcp::{TcpHealth, TcpHealthSnapshot};
+use super::{DeviceKey, MacAddr, SiteId};
+
+/// Sent an ARP request or reply
+pub const EVIDENCE_ARP: u8 = 0x01;
+/// Sent a DHCP message
+pub const EVIDENCE_DHCP: u8 = 0x02;
+/// Announced a hostname over DHCP
+pub const EVIDENCE_HOSTNAME: u8 = 0x04;
+/// Sent or answered a DNS query
+pub const EVIDENCE_DNS: u8 = 0x08;
+/// Sent a frame to a unicast address
+pub const EVIDENCE_UNICAST: u8 = 0x10;
+/// Received a unicast frame
+pub const EVIDENCE_RECEIVED: u8 = 0x20;
+
+/// Name and confidence weight of each kind of evidence
+const EVIDENCE: [(u8, &str, u8); 6] = [
+    (EVIDENCE_ARP, "arp", 20),
+    (EVIDENCE_DHCP, "dhcp", 20),
+    (EVIDENCE_HOSTNAME, "hostname", 15),
+    (EVIDENCE_DNS, "dns", 10),
+    (EVIDENCE_UNICAST, "unicast", 10),
+    (EVIDENCE_RECEIVED, "received", 5),
+];
+
+/// Names of the kinds of evidence in `evidence`
+pub fn evidence_names(evidence: u8) -> Vec<String> {
+    EVIDENCE.iter()
+        .filter(|(bit, _, _)| evidence & bit != 0)
+        .map(|(_, name, _)| name.to_string())
+        .collect()
+}
+
+/// Confidence score, 0 to 100, of a device seen with `evidence` and `packets`
+///
+/// Evidence accounts for up to 80 points and traffic volume for up to 20,
+/// 5 per order of magnitude from 10 packets.
+pub fn confidence(evidence: u8, packets: u64) -> u8 {
+    let weights: u8 = EVIDENCE.iter()
+        .filter(|(bit, _, _)| evidence & bit != 0)
+        .map(|(_, _, weight)| weight)
+        .sum();
+    let volume = match packets {
+        0..=9 => 0,
+        10..=99 => 5,
+        100..=999 => 10,
+        1000..=9999 => 15,
+        _ => 20,
+    };
+    weights + volume
+}
 
 /// Device state in memory
 pub struct DeviceState {
     /// Unique identifier
     pub id: Uuid,
 
+    /// Site the device was seen on
+    pub site: SiteId,
+
     /// MAC address
     pub mac: MacAddr,
 
@@ -40,12 +103,21 @@ pub struct DeviceState {
     /// VLANs this device has been seen on
     pub vlans: DashMap<u16, ()>,
 
+    /// TCP retransmission and reset counters of the segments sent
+    pub tcp: TcpHealth,
+
     /// Whether this device is a gateway
     pub is_gateway: AtomicBool,
 
     /// Whether this device is flagged for attention
     pub is_flagged: AtomicBool,
 
+    /// `EVIDENCE_*` bits the device was seen with
+    pub evidence: AtomicU8,
+
+    /// Tags set by enrichers and capture scripts (name -> value)
+    pub tags: DashMap<String, String>,
+
     /// Dirty flag (needs to be persisted)
     pub dirty: AtomicBool,
 }
@@ -75,14 +147,35 @@ pub struct IpState {
 
     /// Bytes received to this IP
     pub bytes_received: AtomicU64,
+
+    /// Last time the device announced this IP as the sender of an ARP
+    /// message (unix timestamp, 0 if never)
+    pub arp_last_seen: AtomicU64,
+}
+
+impl IpState {
+    fn new(ip: Ipv4Addr, vlan_id: Option<u16>, now_ts: u64) -> Self {
+        Self {
+            ip,
+            vlan_id,
+            first_seen: DateTime::from_timestamp(now_ts as i64, 0).unwrap_or_else(Utc::now),
+            last_seen: AtomicU64::new(now_ts),
+            packets_sent: AtomicU64::new(0),
+            packets_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            arp_last_seen: AtomicU64::new(0),
+        }
+    }
 }
 
 impl DeviceState {
     /// Create a new device state
-    pub fn new(mac: MacAddr, now: DateTime<Utc>) -> Self {
+    pub fn new(key: DeviceKey, now: DateTime<Utc>) -> Self {
         Self {
             id: Uuid::new_v4(),
-            mac,
+            site: key.site,
+            mac: key.mac,
             first_seen: now,
             last_seen: AtomicU64::new(now.timestamp() as u64),
             packets_sent: AtomicU64::new(0),
@@ -91,12 +184,34 @@ impl DeviceState {
             bytes_received: AtomicU64::new(0),
             ips: DashMap::new(),
             vlans: DashMap::new(),
+            tcp: TcpHealth::default(),
             is_gateway: AtomicBool::new(false),
             is_flagged: AtomicBool::new(false),
+            evidence: AtomicU8::new(0),
+            tags: DashMap::new(),
             dirty: AtomicBool::new(true),
         }
     }
 
+    /// Site and MAC address identifying the device
+    pub fn key(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.mac)
+    }
+
+    /// Set tag `name` to `value`, marking the device dirty if it changed
+    pub fn set_tag(&self, name: &str, value: &str) {
+        if self.tags.get(name).is_some_and(|v| v.as_str() == value) {
+            return;
+        }
+        self.tags.insert(name.to_string(), value.to_string());
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Tags by name
+    pub fn tag_map(&self) -> BTreeMap<String, String> {
+        self.tags.iter().map(|t| (t.key().clone(), t.value().clone())).collect()
+    }
+
     /// Update device state with new packet information
     pub fn update(
         &self,
@@ -134,16 +249,7 @@ impl DeviceState {
 
     /// Update IP address state
     fn update_ip(&self, ip: Ipv4Addr, vlan_id: Option<u16>, bytes: u64, is_source: bool, now_ts: u64) {
-        self.ips.entry(ip).or_insert_with(|| IpState {
-            ip,
-            vlan_id,
-            first_seen: Utc::now(),
-            last_seen: AtomicU64::new(now_ts),
-            packets_sent: AtomicU64::new(0),
-            packets_received: AtomicU64::new(0),
-            bytes_sent: AtomicU64::new(0),
-            bytes_received: AtomicU64::new(0),
-        });
+        self.ips.entry(ip).or_insert_with(|| IpState::new(ip, vlan_id, now_ts));
 
         if let Some(ip_state) = self.ips.get(&ip) {
             ip_state.last_seen.store(now_ts, Ordering::Relaxed);
@@ -157,6 +263,27 @@ impl DeviceState {
         }
     }
 
+    /// Bind `ip` to the device, announced as the sender of an ARP message
+    pub fn bind_arp(&self, ip: Ipv4Addr, vlan_id: Option<u16>, now_ts: u64) {
+        let ip_state = self.ips.entry(ip).or_insert_with(|| IpState::new(ip, vlan_id, now_ts));
+        ip_state.last_seen.store(now_ts, Ordering::Relaxed);
+        ip_state.arp_last_seen.store(now_ts, Ordering::Relaxed);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Record `evidence` bits, marking the device dirty when any is new
+    pub fn add_evidence(&self, evidence: u8) {
+        let previous = self.evidence.fetch_or(evidence, Ordering::Relaxed);
+        if previous | evidence != previous {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Confidence score from the evidence and traffic seen so far
+    pub fn confidence(&self) -> u8 {
+        confidence(self.evidence.load(Ordering::Relaxed), self.total_packets())
+    }
+
     /// Check if device is considered inactive
     pub fn is_inactive(&self, timeout_secs: u64) -> bool {
         let now_ts = Utc::now().timestamp() as u64;
@@ -198,9 +325,11 @@ impl DeviceState {
 }
 
 /// Device snapshot for persistence
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DeviceSnapshot {
     pub id: Uuid,
+    /// Site the device was last seen on
+    pub site: String,
     pub mac_address: String,
     pub first_seen: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
@@ -210,12 +339,36 @@ pub struct DeviceSnapshot {
     pub bytes_received: u64,
     pub is_gateway: bool,
     pub is_flagged: bool,
+    /// Confidence score, 0 to 100, that the device is real
+    pub confidence: u8,
+    /// Kinds of evidence the device was seen with
+    pub evidence: Vec<String>,
+    /// Tags set by enrichers and capture scripts
+    pub tags: BTreeMap<String, String>,
     pub ip_addresses: Vec<IpSnapshot>,
     pub vlans: Vec<u16>,
+    pub tcp: TcpHealthSnapshot,
+    /// Presence on each site the device was seen on
+    pub sites: Vec<SitePresence>,
+    /// Annotation set through the API, kept in the database only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Presence of a device on one site
+#[derive(Debug, Clone, Serialize)]
+pub struct SitePresence {
+    pub site: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
 }
 
 /// IP address snapshot
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct IpSnapshot {
     pub ip_address: Ipv4Addr,
     pub vlan_id: Option<u16>,
@@ -225,6 +378,9 @@ pub struct IpSnapshot {
     pub packets_received: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    /// Last time the device announced the address in ARP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arp_last_seen: Option<DateTime<Utc>>,
 }
 
 impl DeviceState {
@@ -242,12 +398,15 @@ impl DeviceState {
                 packets_received: ip_state.packets_received.load(Ordering::Relaxed),
                 bytes_sent: ip_state.bytes_sent.load(Ordering::Relaxed),
                 bytes_received: ip_state.bytes_received.load(Ordering::Relaxed),
+                arp_last_seen: match ip_state.arp_last_seen.load(Ordering::Relaxed) {
+                    0 => None,
+                    ts => DateTime::from_timestamp(ts as i64, 0),
+                },
             }
         }).collect();
 
-        DeviceSnapshot {
-            id: self.id,
-            mac_address: self.mac.to_string(),
+        let presence = SitePresence {
+            site: self.site.to_string(),
             first_seen: self.first_seen,
             last_seen: DateTime::from_timestamp(self.last_seen.load(Ordering::Relaxed) as i64, 0)
                 .unwrap_or(Utc::now()),
@@ -255,10 +414,178 @@ impl DeviceState {
             packets_received: self.packets_received.load(Ordering::Relaxed),
             bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
             bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        };
+
+        DeviceSnapshot {
+            id: self.id,
+            site: presence.site.clone(),
+            mac_address: self.mac.to_string(),
+            first_seen: presence.first_seen,
+            last_seen: presence.last_seen,
+            packets_sent: presence.packets_sent,
+            packets_received: presence.packets_received,
+            bytes_sent: presence.bytes_sent,
+            bytes_received: presence.bytes_received,
             is_gateway: self.is_gateway.load(Ordering::Relaxed),
             is_flagged: self.is_flagged.load(Ordering::Relaxed),
+            confidence: self.confidence(),
+            evidence: evidence_names(self.evidence.load(Ordering::Relaxed)),
+            tags: self.tag_map(),
             ip_addresses,
             vlans: self.vlan_list(),
+            tcp: self.tcp.snapshot(),
+            sites: vec![presence],
+            notes: None,
         }
     }
 }
+
+impl DeviceSnapshot {
+    /// Addresses to record in a CMDB: those the device announced in ARP,
+    /// all of them when it never did (e.g. seen past a router only)
+    pub fn cmdb_ips(&self) -> impl Iterator<Item = &IpSnapshot> {
+        let arp = self.ip_addresses.iter().any(|ip| ip.arp_last_seen.is_some());
+        self.ip_addresses.iter().filter(move |ip| !arp || ip.arp_last_seen.is_some())
+    }
+
+    /// Fold the snapshot of the same MAC address on another site into this one
+    fn absorb(&mut self, other: DeviceSnapshot) {
+        if other.first_seen < self.first_seen {
+            self.id = other.id;
+            self.first_seen = other.first_seen;
+        }
+        if other.last_seen > self.last_seen {
+            self.site = other.site;
+            self.last_seen = other.last_seen;
+        }
+        self.packets_sent += other.packets_sent;
+        self.packets_received += other.packets_received;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.is_gateway |= other.is_gateway;
+        self.is_flagged |= other.is_flagged;
+        self.confidence = self.confidence.max(other.confidence);
+        for evidence in other.evidence {
+            if !self.evidence.contains(&evidence) {
+                self.evidence.push(evidence);
+            }
+        }
+        for (name, value) in other.tags {
+            self.tags.entry(name).or_insert(value);
+        }
+        self.ip_addresses.extend(other.ip_addresses);
+        self.vlans.extend(other.vlans);
+        self.vlans.sort_unstable();
+        self.vlans.dedup();
+        self.tcp.data_segments += other.tcp.data_segments;
+        self.tcp.retransmits += other.tcp.retransmits;
+        self.tcp.resets += other.tcp.resets;
+        self.sites.extend(other.sites);
+    }
+}
+
+/// Merge per-site snapshots into one device per MAC address
+///
+/// A merged device keeps the ID of the site it was first seen on, reports
+/// the site it was last seen on, sums the counters of all its sites and
+/// keeps its highest confidence, the same way the `devices` table does.
+pub fn merge_sites(snapshots: impl IntoIterator<Item = DeviceSnapshot>) -> Vec<DeviceSnapshot> {
+    let mut devices: HashMap<String, DeviceSnapshot> = HashMap::new();
+    for snapshot in snapshots {
+        match devices.entry(snapshot.mac_address.clone()) {
+            Entry::Occupied(mut entry) => entry.get_mut().absorb(snapshot),
+            Entry::Vacant(entry) => {
+                entry.insert(snapshot);
+            }
+        }
+    }
+
+    devices.into_values()
+        .map(|mut device| {
+            device.sites.sort_by(|a, b| a.site.cmp(&b.site));
+            device
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_sites() {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let start = Utc::now() - chrono::Duration::hours(2);
+        let paris = DeviceState::new(DeviceKey::new(SiteId::new("paris").unwrap(), mac), start);
+        paris.update(Some("10.0.0.5".parse().unwrap()), Some(20), 100, true, start.timestamp() as u64);
+        let lyon = DeviceState::new(DeviceKey::new(SiteId::new("lyon").unwrap(), mac), start + chrono::Duration::hours(1));
+        lyon.update(Some("10.1.0.7".parse().unwrap()), Some(30), 50, true, Utc::now().timestamp() as u64);
+        let other = DeviceState::new(DeviceKey::new(SiteId::new("lyon").unwrap(), MacAddr::new([0x00; 6])), start);
+
+        let devices = merge_sites([lyon.snapshot(), paris.snapshot(), other.snapshot()]);
+        assert_eq!(devices.len(), 2);
+
+        let device = devices.iter().find(|d| d.mac_address == mac.to_string()).unwrap();
+        assert_eq!(device.id, paris.id);
+        assert_eq!(device.site, "lyon");
+        assert_eq!(device.first_seen, start);
+        assert_eq!(device.bytes_sent, 150);
+        assert_eq!(device.vlans, vec![20, 30]);
+        assert_eq!(device.ip_addresses.len(), 2);
+        let sites: Vec<&str> = device.sites.iter().map(|s| s.site.as_str()).collect();
+        assert_eq!(sites, vec!["lyon", "paris"]);
+    }
+
+    #[test]
+    fn test_confidence() {
+        // A single multicast frame is no evidence at all
+        assert_eq!(confidence(0, 1), 0);
+        assert_eq!(confidence(EVIDENCE_ARP | EVIDENCE_UNICAST, 50), 35);
+        let all = EVIDENCE.iter().fold(0, |bits, (bit, _, _)| bits | bit);
+        assert_eq!(confidence(all, u64::MAX), 100);
+
+        let device = DeviceState::new(DeviceKey::new(SiteId::default(), MacAddr::new([0x00; 6])), Utc::now());
+        device.clear_dirty();
+        device.add_evidence(EVIDENCE_DHCP | EVIDENCE_HOSTNAME);
+        assert!(device.is_dirty());
+        device.clear_dirty();
+        device.add_evidence(EVIDENCE_DHCP);
+        assert!(!device.is_dirty());
+        let snapshot = device.snapshot();
+        assert_eq!(snapshot.confidence, 35);
+        assert_eq!(snapshot.evidence, vec!["dhcp", "hostname"]);
+    }
+
+    #[test]
+    fn test_arp_binding() {
+        use crate::state::{AggregatorState, CapturedFrame, SensorFrame};
+        use netsentinel_types::ArpInfo;
+
+        let state = AggregatorState::new();
+        let router = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        let host = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x05]);
+        let sensor_frame = |frame| SensorFrame { site: SiteId::default(), sensor: Default::default(), frame };
+
+        // The router forwards a remote host's traffic, then answers ARP
+        let mut forwarded = CapturedFrame::new("eth0", router, host, 0x0800, 60);
+        (forwarded.src_ip, forwarded.dst_ip) = (Some("8.8.8.8".parse().unwrap()), Some("10.0.0.5".parse().unwrap()));
+        state.process_frame(&sensor_frame(forwarded));
+        let mut reply = CapturedFrame::new("eth0", router, host, 0x0806, 60);
+        reply.arp = Some(ArpInfo {
+            operation: ArpInfo::REPLY,
+            sender_mac: router,
+            sender_ip: "10.0.0.1".parse().unwrap(),
+            target_mac: host,
+            target_ip: "10.0.0.5".parse().unwrap(),
+        });
+        state.process_frame(&sensor_frame(reply));
+
+        let snapshot = |mac| state.devices.get(&DeviceKey::new(SiteId::default(), mac)).unwrap().snapshot();
+        let router = snapshot(router);
+        assert_eq!(router.ip_addresses.len(), 2);
+        let ips: Vec<Ipv4Addr> = router.cmdb_ips().map(|ip| ip.ip_address).collect();
+        assert_eq!(ips, vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        // Without ARP, the addresses of its traffic
+        assert_eq!(snapshot(host).cmdb_ips().count(), 1);
+    }
+}
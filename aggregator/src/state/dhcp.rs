@@ -0,0 +1,234 @@
+//! DHCP lease tracking
+//!
+//! Server acknowledgements, refusals and client releases and declines are
+//! turned into lease events, queued until the persister appends them to the
+//! lease history. Clients usually announce their hostname in DISCOVER and
+//! REQUEST rather than the server echoing it in its ACK, so the last one a
+//! client announced is remembered and attached to its leases.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+use super::{DeviceKey, MacAddr, SensorFrame, SiteId};
+
+/// Lease events kept at most while the database is unreachable
+const MAX_QUEUED: usize = 10_000;
+
+/// DHCP message types
+const DISCOVER: u8 = 1;
+const REQUEST: u8 = 3;
+const DECLINE: u8 = 4;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+const INFORM: u8 = 8;
+
+pub use netsentinel_types::DhcpInfo;
+
+/// What happened to a lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseEventKind {
+    /// Address assigned or renewed by the server
+    Ack,
+    /// Request refused by the server
+    Nak,
+    /// Address given back by the client
+    Release,
+    /// Address refused by the client (already in use)
+    Decline,
+}
+
+impl LeaseEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaseEventKind::Ack => "ack",
+            LeaseEventKind::Nak => "nak",
+            LeaseEventKind::Release => "release",
+            LeaseEventKind::Decline => "decline",
+        }
+    }
+}
+
+/// One lease event
+#[derive(Debug, Clone)]
+pub struct LeaseEvent {
+    pub at: DateTime<Utc>,
+    pub kind: LeaseEventKind,
+    pub site: SiteId,
+    pub mac: MacAddr,
+    pub ip: Option<Ipv4Addr>,
+    pub hostname: Option<String>,
+    pub lease_secs: Option<u32>,
+    pub server_ip: Option<Ipv4Addr>,
+    pub xid: u32,
+}
+
+impl LeaseEvent {
+    /// End of the lease granted by an ACK
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let secs = self.lease_secs.filter(|_| self.kind == LeaseEventKind::Ack)?;
+        // 0xffffffff is an infinite lease
+        (secs != u32::MAX).then(|| self.at + chrono::Duration::seconds(secs as i64))
+    }
+}
+
+/// What is remembered of a DHCP client
+#[derive(Default)]
+struct DhcpClient {
+    hostname: Option<String>,
+    /// Transaction and kind of the last event, to skip copies of a message
+    last: Option<(u32, LeaseEventKind)>,
+}
+
+/// Follows DHCP exchanges and queues lease events
+#[derive(Default)]
+pub struct DhcpTracker {
+    clients: DashMap<DeviceKey, DhcpClient>,
+    events: Mutex<VecDeque<LeaseEvent>>,
+}
+
+impl DhcpTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the DHCP message of `frame`, if any
+    pub fn observe(&self, frame: &SensorFrame, now: DateTime<Utc>) {
+        let Some(dhcp) = frame.dhcp.as_ref() else { return };
+        let client = DeviceKey::new(frame.site, dhcp.client_mac);
+
+        let (kind, ip) = match dhcp.message_type {
+            DISCOVER | REQUEST | INFORM => {
+                if let Some(hostname) = dhcp.hostname.as_ref().filter(|h| !h.is_empty()) {
+                    self.clients.entry(client).or_default().hostname = Some(hostname.clone());
+                }
+                return;
+            }
+            // Replies to INFORM carry no lease
+            ACK if dhcp.your_ip.is_none() => return,
+            ACK => (LeaseEventKind::Ack, dhcp.your_ip),
+            NAK => (LeaseEventKind::Nak, dhcp.requested_ip),
+            RELEASE => (LeaseEventKind::Release, dhcp.client_ip),
+            DECLINE => (LeaseEventKind::Decline, dhcp.requested_ip),
+            _ => return,
+        };
+
+        let hostname = {
+            let mut client = self.clients.entry(client).or_default();
+            if client.last == Some((dhcp.xid, kind)) {
+                return;
+            }
+            client.last = Some((dhcp.xid, kind));
+            dhcp.hostname.clone().or_else(|| client.hostname.clone())
+        };
+
+        // Server replies come from the server, client messages go to it
+        let server_ip = dhcp.server_id.or(match kind {
+            LeaseEventKind::Ack | LeaseEventKind::Nak => frame.src_ip,
+            LeaseEventKind::Release | LeaseEventKind::Decline => None,
+        });
+
+        self.push(LeaseEvent {
+            at: now,
+            kind,
+            site: frame.site,
+            mac: dhcp.client_mac,
+            ip,
+            hostname,
+            lease_secs: dhcp.lease_secs,
+            server_ip,
+            xid: dhcp.xid,
+        });
+    }
+
+    fn push(&self, event: LeaseEvent) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() >= MAX_QUEUED {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Hostname the client `key` last announced
+    pub fn hostname(&self, key: &DeviceKey) -> Option<String> {
+        self.clients.get(key).and_then(|client| client.hostname.clone())
+    }
+
+    /// Take the queued lease events, oldest first
+    pub fn drain(&self) -> Vec<LeaseEvent> {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        events.drain(..).collect()
+    }
+
+    /// Put back events that could not be persisted
+    pub fn requeue(&self, failed: Vec<LeaseEvent>) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        for event in failed.into_iter().rev() {
+            if events.len() >= MAX_QUEUED {
+                break;
+            }
+            events.push_front(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message_type: u8, src_ip: &str, dhcp: serde_json::Value) -> SensorFrame {
+        let mut dhcp = dhcp;
+        dhcp["message_type"] = message_type.into();
+        dhcp["xid"] = 0x3903f326u32.into();
+        dhcp["client_mac"] = "00:11:22:33:44:55".into();
+        serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": "00:11:22:33:44:55",
+            "dst_mac": "ff:ff:ff:ff:ff:ff",
+            "ethertype": 0x0800,
+            "src_ip": src_ip,
+            "dst_ip": "255.255.255.255",
+            "ip_protocol": 17,
+            "src_port": 68,
+            "dst_port": 67,
+            "dhcp": dhcp,
+            "frame_size": 342,
+            "payload_size": 300,
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_lease_events() {
+        let tracker = DhcpTracker::new();
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().to_utc();
+
+        tracker.observe(&frame(REQUEST, "0.0.0.0", serde_json::json!({ "hostname": "laptop" })), at);
+        let ack = frame(ACK, "192.168.1.1", serde_json::json!({ "your_ip": "192.168.1.100", "lease_secs": 3600 }));
+        tracker.observe(&ack, at);
+        // Same ACK seen twice
+        tracker.observe(&ack, at);
+        tracker.observe(&frame(RELEASE, "192.168.1.100", serde_json::json!({
+            "client_ip": "192.168.1.100",
+            "server_id": "192.168.1.1",
+        })), at);
+
+        let events = tracker.drain();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, LeaseEventKind::Ack);
+        assert_eq!(events[0].ip, Some(Ipv4Addr::new(192, 168, 1, 100)));
+        assert_eq!(events[0].hostname.as_deref(), Some("laptop"));
+        assert_eq!(events[0].server_ip, Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(events[0].expires_at(), Some(at + chrono::Duration::hours(1)));
+        assert_eq!(events[1].kind, LeaseEventKind::Release);
+        assert_eq!(events[1].expires_at(), None);
+
+        tracker.requeue(events);
+        assert_eq!(tracker.drain()[0].kind, LeaseEventKind::Ack);
+    }
+}
@@ -0,0 +1,257 @@
+//! Per-device DNS analytics
+//!
+//! Queries are counted against the device that sent them and responses
+//! against the device they were sent to, per hour: the names queried, and
+//! how many responses were NXDOMAIN. Domain generation algorithms (DGA)
+//! produce long random-looking labels, so each distinct name is also scored
+//! by the Shannon entropy of its longest label below the top-level domain.
+//!
+//! At most `MAX_DOMAINS` distinct names are kept per device hour; names
+//! beyond that are counted in the totals but not listed or scored.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::composition::hour_of;
+use super::DeviceKey;
+
+/// Distinct names kept per device hour
+pub const MAX_DOMAINS: usize = 4096;
+
+/// Labels at least this long and random-looking are DGA-like
+const DGA_MIN_LABEL_LEN: usize = 10;
+
+/// Entropy (bits per character) above which a long label is DGA-like
+const DGA_MIN_ENTROPY: f64 = 3.5;
+
+/// NXDOMAIN response code
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+pub use netsentinel_types::DnsInfo;
+
+/// Shannon entropy of `text`, in bits per character
+pub fn entropy(text: &str) -> f64 {
+    if text.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u32; 256];
+    for b in text.bytes() {
+        counts[b as usize] += 1;
+    }
+    let len = text.len() as f64;
+    counts.iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Longest label of `name` below its top-level domain
+fn scored_label(name: &str) -> &str {
+    let mut labels: Vec<&str> = name.split('.').collect();
+    if labels.len() > 1 {
+        labels.pop();
+    }
+    labels.into_iter().max_by_key(|l| l.len()).unwrap_or("")
+}
+
+/// DNS activity of one device during one hour
+#[derive(Debug, Default)]
+pub struct HourlyDns {
+    pub queries: u64,
+    pub responses: u64,
+    pub nxdomain: u64,
+    /// Queries per name
+    pub domains: HashMap<String, u64>,
+    /// NXDOMAIN responses per name
+    pub nx_domains: HashMap<String, u64>,
+    /// Sum of the entropy scores of the names in `domains`
+    entropy_sum: f64,
+    /// Names in `domains` that look generated
+    pub dga_like: u64,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+impl HourlyDns {
+    fn query(&mut self, name: Option<&str>) {
+        self.queries += 1;
+        let Some(name) = name else { return };
+        if let Some(count) = self.domains.get_mut(name) {
+            *count += 1;
+        } else if self.domains.len() < MAX_DOMAINS {
+            let label = scored_label(name);
+            let score = entropy(label);
+            self.entropy_sum += score;
+            if label.len() >= DGA_MIN_LABEL_LEN && score >= DGA_MIN_ENTROPY {
+                self.dga_like += 1;
+            }
+            self.domains.insert(name.to_string(), 1);
+        }
+    }
+
+    fn response(&mut self, name: Option<&str>, rcode: u8) {
+        self.responses += 1;
+        if rcode != RCODE_NXDOMAIN {
+            return;
+        }
+        self.nxdomain += 1;
+        if let Some(name) = name {
+            if let Some(count) = self.nx_domains.get_mut(name) {
+                *count += 1;
+            } else if self.nx_domains.len() < MAX_DOMAINS {
+                self.nx_domains.insert(name.to_string(), 1);
+            }
+        }
+    }
+
+    /// Share of responses that were NXDOMAIN
+    pub fn nxdomain_rate(&self) -> f64 {
+        if self.responses == 0 {
+            return 0.0;
+        }
+        self.nxdomain as f64 / self.responses as f64
+    }
+
+    /// Mean entropy score of the names queried
+    pub fn mean_entropy(&self) -> f64 {
+        if self.domains.is_empty() {
+            return 0.0;
+        }
+        self.entropy_sum / self.domains.len() as f64
+    }
+
+    /// The `n` names queried most
+    pub fn top_domains(&self, n: usize) -> Vec<(String, u64)> {
+        top(&self.domains, n)
+    }
+
+    /// The `n` names with the most NXDOMAIN responses
+    pub fn top_nx_domains(&self, n: usize) -> Vec<(String, u64)> {
+        top(&self.nx_domains, n)
+    }
+
+    /// What is persisted of the hour, with its `n` names queried most
+    pub fn summary(&self, n: usize) -> DnsSummary {
+        DnsSummary {
+            queries: self.queries,
+            responses: self.responses,
+            nxdomain: self.nxdomain,
+            distinct_domains: self.domains.len(),
+            mean_entropy: self.mean_entropy(),
+            dga_like: self.dga_like,
+            top_domains: self.top_domains(n),
+        }
+    }
+}
+
+/// Snapshot of a device's DNS hour, as persisted
+#[derive(Debug, Clone)]
+pub struct DnsSummary {
+    pub queries: u64,
+    pub responses: u64,
+    pub nxdomain: u64,
+    pub distinct_domains: usize,
+    pub mean_entropy: f64,
+    pub dga_like: u64,
+    pub top_domains: Vec<(String, u64)>,
+}
+
+fn top(counts: &HashMap<String, u64>, n: usize) -> Vec<(String, u64)> {
+    let mut names: Vec<(String, u64)> = counts.iter().map(|(name, count)| (name.clone(), *count)).collect();
+    names.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    names.truncate(n);
+    names
+}
+
+/// Responses and NXDOMAINs a device received since the aggregator started
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DnsTotals {
+    pub responses: u64,
+    pub nxdomain: u64,
+}
+
+/// Hourly DNS activity by device
+#[derive(Default)]
+pub struct DnsAnalytics {
+    /// Keyed by device and start of the hour (unix time)
+    pub hours: DashMap<(DeviceKey, i64), HourlyDns>,
+    pub totals: DashMap<DeviceKey, DnsTotals>,
+}
+
+impl DnsAnalytics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a DNS message from `src` to `dst`
+    pub fn record(&self, dns: &DnsInfo, src: DeviceKey, dst: DeviceKey, now: DateTime<Utc>) {
+        let name = dns.qname.as_deref().filter(|n| !n.is_empty());
+        let device = if dns.response { dst } else { src };
+
+        let mut hour = self.hours.entry((device, hour_of(now))).or_default();
+        if dns.response {
+            hour.response(name, dns.rcode);
+        } else {
+            hour.query(name);
+        }
+        hour.dirty = true;
+        drop(hour);
+
+        if dns.response {
+            let mut totals = self.totals.entry(device).or_default();
+            totals.responses += 1;
+            if dns.rcode == RCODE_NXDOMAIN {
+                totals.nxdomain += 1;
+            }
+        }
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, hour), dns| *hour >= before || dns.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+
+    fn dns(response: bool, rcode: u8, qname: &str) -> DnsInfo {
+        DnsInfo { id: 1, response, rcode, qname: Some(qname.to_string()), qtype: Some(1), answers: 0 }
+    }
+
+    #[test]
+    fn test_hourly_dns() {
+        let analytics = DnsAnalytics::new();
+        let host = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let resolver = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x66, 0x77, 0x88, 0x99, 0xaa]));
+        let at = DateTime::parse_from_rfc3339("2024-05-01T10:42:00Z").unwrap().to_utc();
+
+        for _ in 0..3 {
+            analytics.record(&dns(false, 0, "www.example.com"), host, resolver, at);
+            analytics.record(&dns(true, 0, "www.example.com"), resolver, host, at);
+        }
+        analytics.record(&dns(false, 0, "qx7vz3kp9wj2ml.net"), host, resolver, at);
+        analytics.record(&dns(true, RCODE_NXDOMAIN, "qx7vz3kp9wj2ml.net"), resolver, host, at);
+
+        let hour = analytics.hours.get(&(host, hour_of(at))).unwrap();
+        assert_eq!((hour.queries, hour.responses, hour.nxdomain), (4, 4, 1));
+        assert_eq!(hour.nxdomain_rate(), 0.25);
+        assert_eq!(hour.top_domains(1), vec![("www.example.com".to_string(), 3)]);
+        assert_eq!(hour.top_nx_domains(5), vec![("qx7vz3kp9wj2ml.net".to_string(), 1)]);
+        assert_eq!(hour.dga_like, 1);
+        assert!(hour.mean_entropy() > 2.5);
+        assert!(!analytics.hours.contains_key(&(resolver, hour_of(at))));
+        assert_eq!(analytics.totals.get(&host).unwrap().nxdomain, 1);
+
+        assert_eq!(entropy("aaaa"), 0.0);
+        assert_eq!(entropy("abcd"), 2.0);
+        assert_eq!(scored_label("mail.google.com"), "google");
+    }
+}
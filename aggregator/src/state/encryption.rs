@@ -0,0 +1,162 @@
+//! Per-device hourly encrypted and plaintext traffic
+//!
+//! Each IP frame is classified from the well-known port of its service:
+//! TLS, SSH, QUIC, IPsec and VPN protocols are encrypted, HTTP, telnet,
+//! FTP, SNMP and the other cleartext protocols are plaintext. A flow that
+//! carried a TLS handshake counts as encrypted `tls` whatever its port,
+//! which covers TLS on custom ports and STARTTLS upgrades of SMTP, IMAP or
+//! LDAP. Frames of unlisted services stay unclassified.
+//!
+//! Bytes each device sends and receives are counted per hour and protocol,
+//! giving the share of its traffic sent in the clear and which protocols
+//! carried it. Hours are persisted whole as they fill up, like the traffic
+//! composition.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::collections::HashMap;
+
+use super::composition::hour_of;
+use super::DeviceKey;
+
+/// IP protocol numbers
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ESP: u8 = 50;
+
+/// Protocol name of the flows that carried a TLS handshake on a port not
+/// listed as encrypted
+pub const TLS: &str = "tls";
+
+/// Services by IP protocol and server port: name, and whether encrypted
+const SERVICES: &[(u8, u16, &str, bool)] = &[
+    (TCP, 22, "ssh", true),
+    (TCP, 443, "https", true),
+    (UDP, 443, "quic", true),
+    (TCP, 465, "smtps", true),
+    (TCP, 636, "ldaps", true),
+    (TCP, 853, "dot", true),
+    (TCP, 989, "ftps", true),
+    (TCP, 990, "ftps", true),
+    (TCP, 993, "imaps", true),
+    (TCP, 995, "pop3s", true),
+    (TCP, 3269, "ldaps", true),
+    (TCP, 3389, "rdp", true),
+    (TCP, 5061, "sips", true),
+    (TCP, 5986, "winrm-https", true),
+    (TCP, 6514, "syslog-tls", true),
+    (TCP, 8443, "https", true),
+    (UDP, 500, "ike", true),
+    (UDP, 1194, "openvpn", true),
+    (UDP, 4500, "ipsec", true),
+    (UDP, 51820, "wireguard", true),
+    (TCP, 20, "ftp", false),
+    (TCP, 21, "ftp", false),
+    (TCP, 23, "telnet", false),
+    (TCP, 25, "smtp", false),
+    (UDP, 69, "tftp", false),
+    (TCP, 80, "http", false),
+    (TCP, 110, "pop3", false),
+    (TCP, 143, "imap", false),
+    (UDP, 161, "snmp", false),
+    (UDP, 162, "snmp", false),
+    (TCP, 389, "ldap", false),
+    (TCP, 512, "rexec", false),
+    (TCP, 513, "rlogin", false),
+    (TCP, 514, "rsh", false),
+    (UDP, 514, "syslog", false),
+    (TCP, 1883, "mqtt", false),
+    (UDP, 5060, "sip", false),
+    (TCP, 5060, "sip", false),
+    (TCP, 5985, "winrm", false),
+    (TCP, 8080, "http", false),
+];
+
+/// Protocol of a frame and whether it is encrypted, `tls` when its flow
+/// carried a TLS handshake; none for unlisted services
+pub fn classify(protocol: Option<u8>, src_port: Option<u16>, dst_port: Option<u16>, tls: bool) -> Option<(&'static str, bool)> {
+    if protocol == Some(ESP) {
+        return Some(("ipsec", true));
+    }
+    let protocol = protocol?;
+    // The server side first: a client's ephemeral port may be listed too
+    let service = [dst_port, src_port].into_iter().flatten().find_map(|port| {
+        SERVICES.iter().find(|(p, service_port, _, _)| *p == protocol && *service_port == port)
+    });
+    match service {
+        Some((_, _, name, encrypted)) if *encrypted || !tls => Some((name, *encrypted)),
+        _ if tls => Some((TLS, true)),
+        _ => None,
+    }
+}
+
+/// Traffic of one device during one hour
+#[derive(Debug, Default)]
+pub struct HourlyEncryption {
+    /// Classified bytes, by protocol and whether encrypted
+    pub protocols: HashMap<(&'static str, bool), u64>,
+    /// Bytes of unlisted services
+    pub unclassified: u64,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+/// Hourly encrypted and plaintext traffic by device
+#[derive(Default)]
+pub struct EncryptionRatios {
+    /// Keyed by device and start of the hour (unix time)
+    pub hours: DashMap<(DeviceKey, i64), HourlyEncryption>,
+}
+
+impl EncryptionRatios {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `bytes` sent or received by `device` at `now`, of `protocol`
+    /// as classified by [`classify`]
+    pub fn record(&self, device: DeviceKey, protocol: Option<(&'static str, bool)>, bytes: u64, now: DateTime<Utc>) {
+        let mut hour = self.hours.entry((device, hour_of(now))).or_default();
+        match protocol {
+            Some(protocol) => *hour.protocols.entry(protocol).or_default() += bytes,
+            None => hour.unclassified += bytes,
+        }
+        hour.dirty = true;
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, hour), encryption| *hour >= before || encryption.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_encryption_ratio() {
+        assert_eq!(classify(Some(TCP), Some(51000), Some(443), false), Some(("https", true)));
+        assert_eq!(classify(Some(TCP), Some(23), Some(51000), false), Some(("telnet", false)));
+        assert_eq!(classify(Some(UDP), Some(51000), Some(443), false), Some(("quic", true)));
+        // STARTTLS, TLS on a custom port, an unlisted service
+        assert_eq!(classify(Some(TCP), Some(51000), Some(25), true), Some((TLS, true)));
+        assert_eq!(classify(Some(TCP), Some(51000), Some(9443), true), Some((TLS, true)));
+        assert_eq!(classify(Some(TCP), Some(51000), Some(9443), false), None);
+        assert_eq!(classify(Some(ESP), None, None, false), Some(("ipsec", true)));
+
+        let ratios = EncryptionRatios::new();
+        let printer = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 10, 42, 0).unwrap();
+        ratios.record(printer, classify(Some(TCP), Some(51000), Some(443), false), 3000, at);
+        ratios.record(printer, classify(Some(TCP), Some(80), Some(51000), false), 1000, at);
+        ratios.record(printer, classify(Some(TCP), Some(51000), Some(9100), false), 500, at);
+
+        let hour = ratios.hours.get(&(printer, hour_of(at))).unwrap();
+        assert_eq!(hour.protocols[&("https", true)], 3000);
+        assert_eq!(hour.protocols[&("http", false)], 1000);
+        assert_eq!(hour.unclassified, 500);
+    }
+}
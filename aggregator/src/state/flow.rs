@@ -1,15 +1,72 @@
 //! Flow state management
 
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::net::Ipv4Addr;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::MacAddr;
+use super::tcp::{SequenceTracker, TcpHealth, TcpHealthSnapshot};
+use super::{DeviceKey, MacAddr, SensorId, ServiceNames, SiteId};
+
+/// IPv4 ethertype (flows are keyed on IPv4 addresses)
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+
+/// Seconds a SYN stays without answer before its flow is `unanswered`
+pub const UNANSWERED_SECS: u64 = 5;
+
+/// Handshake states of a TCP client flow
+const HANDSHAKE_NONE: u8 = 0;
+const HANDSHAKE_SYN: u8 = 1;
+const HANDSHAKE_ESTABLISHED: u8 = 2;
+const HANDSHAKE_REJECTED: u8 = 3;
+const HANDSHAKE_RESET: u8 = 4;
+
+/// How a TCP connection's handshake went, on the flow of its client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TcpOutcome {
+    /// The server answered the SYN
+    Established,
+    /// The server answered the SYN with a reset
+    Rejected,
+    /// The SYN got no answer within `UNANSWERED_SECS`
+    Unanswered,
+    /// Reset by either side once established
+    Reset,
+}
+
+impl TcpOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Established => "established",
+            Self::Rejected => "rejected",
+            Self::Unanswered => "unanswered",
+            Self::Reset => "reset",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "established" => Some(Self::Established),
+            "rejected" => Some(Self::Rejected),
+            "unanswered" => Some(Self::Unanswered),
+            "reset" => Some(Self::Reset),
+            _ => None,
+        }
+    }
+}
 
 /// Unique key for a flow
+///
+/// Flows are kept per sensor: two sensors of a site seeing the same
+/// conversation each report their own flow. ICMP has no ports: its type,
+/// code and echo identifier tell apart the ping sessions and the errors
+/// exchanged between two hosts.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FlowKey {
+    pub site: SiteId,
+    pub sensor: SensorId,
     pub src_mac: MacAddr,
     pub dst_mac: MacAddr,
     pub src_ip: Option<Ipv4Addr>,
@@ -18,9 +75,45 @@ pub struct FlowKey {
     pub dst_port: Option<u16>,
     pub vlan_id: Option<u16>,
     pub protocol: Option<u8>,
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
+    pub icmp_id: Option<u16>,
 }
 
 impl FlowKey {
+    /// Device that sent the flow
+    pub fn src_device(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.src_mac)
+    }
+
+    /// Device the flow was sent to
+    pub fn dst_device(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.dst_mac)
+    }
+
+    /// Name of the flow's service (`https`, `rdp`), from its ports
+    pub fn service<'a>(&self, services: &'a ServiceNames) -> Option<&'a str> {
+        services.of_ports(self.src_port, self.dst_port)
+    }
+
+    /// Key of the flow answering this one, endpoints swapped
+    pub fn reverse(&self) -> FlowKey {
+        FlowKey {
+            src_mac: self.dst_mac,
+            dst_mac: self.src_mac,
+            src_ip: self.dst_ip,
+            dst_ip: self.src_ip,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            ..self.clone()
+        }
+    }
+
+    /// Ethertype of the flow's frames (IPv4, or 0 for raw L2 flows)
+    pub fn ethertype(&self) -> u16 {
+        if self.src_ip.is_some() { ETHERTYPE_IPV4 } else { 0 }
+    }
+
     /// Create a string representation for logging
     pub fn to_display_string(&self) -> String {
         let src = if let Some(ip) = self.src_ip {
@@ -50,7 +143,13 @@ impl FlowKey {
             _ => "OTHER",
         }).unwrap_or("L2");
 
-        format!("{} -> {} [{}]", src, dst, proto)
+        let icmp = match (self.icmp_type, self.icmp_code, self.icmp_id) {
+            (Some(icmp_type), Some(code), Some(id)) => format!(" {}/{} id {}", icmp_type, code, id),
+            (Some(icmp_type), Some(code), None) => format!(" {}/{}", icmp_type, code),
+            _ => String::new(),
+        };
+
+        format!("{} -> {} [{}{}]", src, dst, proto, icmp)
     }
 }
 
@@ -77,6 +176,22 @@ pub struct FlowState {
     /// TCP flags seen (bitwise OR of all flags)
     pub tcp_flags_seen: AtomicU8,
 
+    /// TCP retransmission and reset counters
+    pub tcp: TcpHealth,
+
+    /// Highest TCP sequence number sent
+    pub tcp_seq: SequenceTracker,
+
+    /// `HANDSHAKE_*` state, on the flows opened by a SYN
+    handshake: AtomicU8,
+
+    /// Whether a TLS handshake message was seen on the flow
+    tls: AtomicBool,
+
+    /// Whether an SNMP message authenticated by community string (v1 or
+    /// v2c) was seen on the flow
+    snmp_community: AtomicBool,
+
     /// Dirty flag
     pub dirty: std::sync::atomic::AtomicBool,
 }
@@ -92,6 +207,11 @@ impl FlowState {
             packet_count: AtomicU64::new(0),
             byte_count: AtomicU64::new(0),
             tcp_flags_seen: AtomicU8::new(0),
+            tcp: TcpHealth::default(),
+            tcp_seq: SequenceTracker::default(),
+            handshake: AtomicU8::new(HANDSHAKE_NONE),
+            tls: AtomicBool::new(false),
+            snmp_community: AtomicBool::new(false),
             dirty: std::sync::atomic::AtomicBool::new(true),
         }
     }
@@ -110,6 +230,74 @@ impl FlowState {
         self.dirty.store(true, Ordering::Relaxed);
     }
 
+    /// A TLS handshake message was seen on the flow
+    pub fn mark_tls(&self) {
+        self.tls.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the flow carried a TLS handshake, its traffic encrypted
+    pub fn is_tls(&self) -> bool {
+        self.tls.load(Ordering::Relaxed)
+    }
+
+    /// An SNMPv1 or v2c message was seen on the flow
+    pub fn mark_snmp_community(&self) {
+        self.snmp_community.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the flow carried SNMP community strings in the clear
+    pub fn is_snmp_community(&self) -> bool {
+        self.snmp_community.load(Ordering::Relaxed)
+    }
+
+    /// Count a TCP segment, returning whether it is a retransmission
+    pub fn update_tcp(&self, seq: Option<u32>, payload_size: u32, rst: bool) -> bool {
+        let retransmit = seq.is_some_and(|seq| self.tcp_seq.observe(seq, payload_size));
+        self.tcp.record(payload_size > 0, retransmit, rst);
+        retransmit
+    }
+
+    /// Move the handshake from one of `from` to `to`, marking the flow dirty
+    fn advance_handshake(&self, from: &[u8], to: u8) {
+        let advanced = self.handshake.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+            from.contains(&state).then_some(to)
+        });
+        if advanced.is_ok() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The client sent its SYN
+    pub fn handshake_syn(&self) {
+        self.advance_handshake(&[HANDSHAKE_NONE], HANDSHAKE_SYN);
+    }
+
+    /// The server answered the SYN (SYN-ACK), or the client acknowledged it
+    pub fn handshake_answered(&self) {
+        self.advance_handshake(&[HANDSHAKE_SYN], HANDSHAKE_ESTABLISHED);
+    }
+
+    /// A reset was sent, by the server when `by_server`
+    pub fn handshake_reset(&self, by_server: bool) {
+        if by_server {
+            self.advance_handshake(&[HANDSHAKE_SYN], HANDSHAKE_REJECTED);
+        }
+        self.advance_handshake(&[HANDSHAKE_ESTABLISHED], HANDSHAKE_RESET);
+    }
+
+    /// Outcome of the handshake at `now_ts`, for flows opened by a SYN
+    pub fn tcp_outcome(&self, now_ts: u64) -> Option<TcpOutcome> {
+        match self.handshake.load(Ordering::Relaxed) {
+            HANDSHAKE_SYN if now_ts.saturating_sub(self.first_seen.timestamp() as u64) >= UNANSWERED_SECS => {
+                Some(TcpOutcome::Unanswered)
+            }
+            HANDSHAKE_ESTABLISHED => Some(TcpOutcome::Established),
+            HANDSHAKE_REJECTED => Some(TcpOutcome::Rejected),
+            HANDSHAKE_RESET => Some(TcpOutcome::Reset),
+            _ => None,
+        }
+    }
+
     /// Check if flow is timed out
     pub fn is_timed_out(&self, timeout_secs: u64) -> bool {
         let now_ts = Utc::now().timestamp() as u64;
@@ -124,6 +312,13 @@ impl FlowState {
         flags & 0x05 != 0
     }
 
+    /// Check if this is a TCP connection attempt that never got past its SYN
+    pub fn is_tcp_syn_only(&self) -> bool {
+        let flags = self.tcp_flags_seen.load(Ordering::Relaxed);
+        // Only SYN (0x02) among FIN/SYN/RST/PSH/ACK/URG
+        self.key.protocol == Some(6) && flags & 0x3f == 0x02
+    }
+
     /// Get duration of the flow in seconds
     pub fn duration_secs(&self) -> u64 {
         let last = self.last_seen.load(Ordering::Relaxed);
@@ -161,9 +356,11 @@ impl FlowState {
 }
 
 /// Flow snapshot for persistence
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FlowSnapshot {
     pub id: Uuid,
+    pub site: String,
+    pub sensor: String,
     pub src_mac: String,
     pub dst_mac: String,
     pub src_ip: Option<Ipv4Addr>,
@@ -173,18 +370,32 @@ pub struct FlowSnapshot {
     pub vlan_id: Option<u16>,
     pub ethertype: u16,
     pub ip_protocol: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_type: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_id: Option<u16>,
+    /// Service name of the flow's ports
+    pub service: Option<String>,
     pub first_seen: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
     pub packet_count: u64,
     pub byte_count: u64,
     pub tcp_flags_seen: u8,
+    /// How the handshake went, on the flows opened by a SYN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_outcome: Option<TcpOutcome>,
+    pub tcp: TcpHealthSnapshot,
 }
 
 impl FlowState {
-    /// Create a snapshot for persistence
-    pub fn snapshot(&self, ethertype: u16) -> FlowSnapshot {
+    /// Create a snapshot for persistence, naming its service with `services`
+    pub fn snapshot(&self, ethertype: u16, services: &ServiceNames) -> FlowSnapshot {
         FlowSnapshot {
             id: self.id,
+            site: self.key.site.to_string(),
+            sensor: self.key.sensor.to_string(),
             src_mac: self.key.src_mac.to_string(),
             dst_mac: self.key.dst_mac.to_string(),
             src_ip: self.key.src_ip,
@@ -194,12 +405,18 @@ impl FlowState {
             vlan_id: self.key.vlan_id,
             ethertype,
             ip_protocol: self.key.protocol,
+            icmp_type: self.key.icmp_type,
+            icmp_code: self.key.icmp_code,
+            icmp_id: self.key.icmp_id,
+            service: self.key.service(services).map(str::to_string),
             first_seen: self.first_seen,
             last_seen: DateTime::from_timestamp(self.last_seen.load(Ordering::Relaxed) as i64, 0)
                 .unwrap_or(Utc::now()),
             packet_count: self.packet_count.load(Ordering::Relaxed),
             byte_count: self.byte_count.load(Ordering::Relaxed),
             tcp_flags_seen: self.tcp_flags_seen.load(Ordering::Relaxed),
+            tcp_outcome: self.tcp_outcome(Utc::now().timestamp() as u64),
+            tcp: self.tcp.snapshot(),
         }
     }
 }
@@ -211,6 +428,8 @@ mod tests {
     #[test]
     fn test_flow_state() {
         let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
             src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
             dst_mac: MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
             src_ip: Some(Ipv4Addr::new(192, 168, 1, 1)),
@@ -219,6 +438,9 @@ mod tests {
             dst_port: Some(80),
             vlan_id: None,
             protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
         };
 
         let flow = FlowState::new(key.clone(), Utc::now());
@@ -237,9 +459,56 @@ mod tests {
         assert!(flags & 0x10 != 0); // ACK
     }
 
+    #[test]
+    fn test_tcp_outcome() {
+        let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
+            src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            dst_mac: MacAddr::new([0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb]),
+            src_ip: Some(Ipv4Addr::new(192, 168, 1, 1)),
+            dst_ip: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            src_port: Some(54321),
+            dst_port: Some(443),
+            vlan_id: None,
+            protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+        };
+        assert_eq!(key.reverse().reverse(), key);
+        let now = Utc::now();
+        let now_ts = now.timestamp() as u64;
+
+        // Not opened by a SYN seen here: no outcome
+        let flow = FlowState::new(key.clone(), now);
+        flow.handshake_answered();
+        assert_eq!(flow.tcp_outcome(now_ts), None);
+
+        // Unanswered once the grace period is over
+        flow.handshake_syn();
+        assert_eq!(flow.tcp_outcome(now_ts), None);
+        assert_eq!(flow.tcp_outcome(now_ts + UNANSWERED_SECS), Some(TcpOutcome::Unanswered));
+
+        // A reset by the server rejects the SYN, then later ones do not matter
+        flow.handshake_reset(true);
+        flow.handshake_answered();
+        assert_eq!(flow.tcp_outcome(now_ts), Some(TcpOutcome::Rejected));
+
+        // Established, then reset mid-stream by the client
+        let flow = FlowState::new(key, now);
+        flow.handshake_syn();
+        flow.handshake_answered();
+        assert_eq!(flow.tcp_outcome(now_ts), Some(TcpOutcome::Established));
+        flow.handshake_reset(false);
+        assert_eq!(flow.tcp_outcome(now_ts), Some(TcpOutcome::Reset));
+    }
+
     #[test]
     fn test_flow_key_display() {
         let key = FlowKey {
+            site: SiteId::default(),
+            sensor: SensorId::default(),
             src_mac: MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
             dst_mac: MacAddr::new([0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb]),
             src_ip: Some(Ipv4Addr::new(192, 168, 1, 1)),
@@ -248,11 +517,26 @@ mod tests {
             dst_port: Some(443),
             vlan_id: None,
             protocol: Some(6),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
         };
 
         let display = key.to_display_string();
         assert!(display.contains("192.168.1.1:54321"));
         assert!(display.contains("10.0.0.1:443"));
         assert!(display.contains("TCP"));
+
+        // Ping sessions are told apart by their echo identifier
+        let ping = FlowKey {
+            src_port: None,
+            dst_port: None,
+            protocol: Some(1),
+            icmp_type: Some(8),
+            icmp_code: Some(0),
+            icmp_id: Some(0x1234),
+            ..key
+        };
+        assert_eq!(ping.to_display_string(), "192.168.1.1 -> 10.0.0.1 [ICMP 8/0 id 4660]");
     }
 }
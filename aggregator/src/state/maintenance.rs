@@ -0,0 +1,146 @@
+//! Devices and VLANs under maintenance
+//!
+//! An operator puts a device, or a whole VLAN of a site, under maintenance
+//! until a set time through the API. Entries are stored in the database,
+//! which filters the change report with them, and kept here for the rules
+//! engine and the alert manager: rules raise no alert about a device under
+//! maintenance, or on one of its VLANs, and other alerts about it are
+//! recorded without being notified. Entries are loaded on startup and
+//! forgotten once over.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{MacAddr, SiteId};
+
+/// What is under maintenance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceTarget {
+    Device(MacAddr),
+    Vlan(u16),
+}
+
+/// A device or VLAN under maintenance
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceEntry {
+    pub id: Uuid,
+    pub site: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_id: Option<u16>,
+    pub reason: Option<String>,
+    /// Who set the maintenance
+    pub created_by: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+}
+
+impl MaintenanceEntry {
+    pub fn target(&self) -> Option<MaintenanceTarget> {
+        match (self.mac_address.as_deref().and_then(MacAddr::from_string), self.vlan_id) {
+            (Some(mac), None) => Some(MaintenanceTarget::Device(mac)),
+            (None, Some(vlan_id)) => Some(MaintenanceTarget::Vlan(vlan_id)),
+            _ => None,
+        }
+    }
+
+    /// Whether the maintenance is in effect at `at`
+    pub fn active(&self, at: DateTime<Utc>) -> bool {
+        self.starts_at <= at && at < self.until
+    }
+
+    /// Whether the maintenance covers device `mac` of `site`, which holds
+    /// addresses on `vlans`, at `at`
+    pub fn covers(&self, site: SiteId, mac: MacAddr, vlans: &[u16], at: DateTime<Utc>) -> bool {
+        if self.site != site.as_str() || !self.active(at) {
+            return false;
+        }
+        match self.target() {
+            Some(MaintenanceTarget::Device(device)) => device == mac,
+            Some(MaintenanceTarget::Vlan(vlan_id)) => vlans.contains(&vlan_id),
+            None => false,
+        }
+    }
+}
+
+/// Maintenance entries in effect or to come, by ID
+#[derive(Default)]
+pub struct Maintenance {
+    pub entries: DashMap<Uuid, MaintenanceEntry>,
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, entry: MaintenanceEntry) {
+        self.entries.insert(entry.id, entry);
+    }
+
+    /// End maintenance `id` at `at`
+    pub fn end(&self, id: Uuid, at: DateTime<Utc>) {
+        if let Some(mut entry) = self.entries.get_mut(&id) {
+            entry.until = entry.until.min(at).max(entry.starts_at);
+        }
+    }
+
+    /// Maintenance covering device `mac` of `site`, which holds addresses
+    /// on `vlans`, at `at`, if any
+    pub fn covering(&self, site: SiteId, mac: MacAddr, vlans: &[u16], at: DateTime<Utc>) -> Option<MaintenanceEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.covers(site, mac, vlans, at))
+            .map(|entry| entry.value().clone())
+    }
+
+    /// Forget the entries over at `at`
+    pub fn prune(&self, at: DateTime<Utc>) {
+        self.entries.retain(|_, entry| entry.until > at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_maintenance_coverage() {
+        let now = Utc::now();
+        let site = SiteId::new("paris").unwrap();
+        let printer = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let camera = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x66]);
+        let entry = |mac: Option<MacAddr>, vlan_id: Option<u16>| MaintenanceEntry {
+            id: Uuid::new_v4(),
+            site: "paris".to_string(),
+            mac_address: mac.map(|mac| mac.to_string()),
+            vlan_id,
+            reason: None,
+            created_by: None,
+            starts_at: now - Duration::minutes(5),
+            until: now + Duration::hours(1),
+        };
+
+        let maintenance = Maintenance::new();
+        let device = entry(Some(printer), None);
+        let cameras = entry(None, Some(30));
+        let device_id = device.id;
+        maintenance.insert(device);
+        maintenance.insert(cameras);
+
+        assert_eq!(maintenance.covering(site, printer, &[20], now).map(|e| e.id), Some(device_id));
+        assert!(maintenance.covering(site, camera, &[30], now).is_some());
+        assert!(maintenance.covering(site, camera, &[20], now).is_none());
+        assert!(maintenance.covering(SiteId::new("lyon").unwrap(), printer, &[20], now).is_none());
+        assert!(maintenance.covering(site, printer, &[20], now + Duration::hours(2)).is_none());
+
+        maintenance.end(device_id, now);
+        assert!(maintenance.covering(site, printer, &[20], now).is_none());
+        maintenance.prune(now);
+        assert_eq!(maintenance.entries.len(), 1);
+    }
+}
@@ -2,72 +2,143 @@
 //!
 //! Uses DashMap for lock-free concurrent access to device and flow state.
 
+pub mod anomaly;
+pub mod clock;
+pub mod composition;
+pub mod dependency;
 pub mod device;
+pub mod dhcp;
+pub mod dns;
+pub mod encryption;
 pub mod flow;
+pub mod maintenance;
+pub mod multicast;
+pub mod ntp;
+pub mod ot;
 pub mod protocol;
+pub mod qos;
+pub mod rtt;
+pub mod sip;
+pub mod site;
+pub mod subnet_matrix;
+pub mod tcp;
+pub mod tls;
+pub mod topology;
+pub mod vendor;
+pub mod vlan_subnet;
 
 use dashmap::DashMap;
+use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::net::Ipv4Addr;
 use chrono::{DateTime, Utc};
-use uuid::Uuid;
 
-pub use device::{DeviceState, IpState};
-pub use flow::{FlowKey, FlowState};
-pub use protocol::ProtocolStats;
+pub use netsentinel_types::{CapturedFrame, MacAddr, QinQInfo, ServiceNames, TcpFlags, VlanInfo};
+
+pub use anomaly::{AnomalyScores, HourlyScore};
+pub use clock::{ClockWindow, SensorClocks};
+pub use composition::{port_of, HourlyTraffic, Service, ServiceBytes, TrafficComposition};
+pub use dependency::{Dependency, DependencyKey, DependencyMap, DependencyTraffic};
+pub use device::{
+    confidence, evidence_names, merge_sites, DeviceSnapshot, DeviceState, IpSnapshot, IpState, SitePresence,
+    EVIDENCE_ARP, EVIDENCE_DHCP, EVIDENCE_DNS, EVIDENCE_HOSTNAME, EVIDENCE_RECEIVED, EVIDENCE_UNICAST,
+};
+pub use dhcp::{DhcpInfo, DhcpTracker, LeaseEvent, LeaseEventKind};
+pub use dns::{DnsAnalytics, DnsInfo, DnsSummary, DnsTotals, HourlyDns};
+pub use encryption::{EncryptionRatios, HourlyEncryption};
+pub use flow::{FlowKey, FlowSnapshot, FlowState, TcpOutcome, ETHERTYPE_IPV4};
+pub use maintenance::{Maintenance, MaintenanceEntry, MaintenanceTarget};
+pub use multicast::{GroupTraffic, MulticastGroup, MulticastGroups, MulticastSnapshot};
+pub use ntp::{NtpAssociation, NtpKey, NtpServers};
+pub use ot::{OtDevice, OtDeviceKey, OtDevices, OtWrite, OtWriteKey};
+pub use protocol::{ProtocolSnapshot, ProtocolStats};
+pub use qos::{class_name, ClassTraffic, HourlyQos, QosAccounting};
+pub use rtt::{PairRtt, RttSnapshot, RttTracker};
+pub use sip::{Call, CallKey, CallState, Telephony};
+pub use site::{DeviceKey, SensorId, SiteId, TenantId, Tenants, DEFAULT_SITE, DEFAULT_TENANT};
+pub use subnet_matrix::{HourlyMatrix, PairTraffic, SubnetMatrix, SubnetTraffic};
+pub use tcp::{TcpHealth, TcpHealthSnapshot};
+pub use tls::{CertificateInfo, Fingerprint, FingerprintKind, TlsInfo, TlsInventory, TlsKey, TlsObservation};
+pub use topology::{L2Segment, L2Topology, Sightings};
+pub use vendor::Vendors;
+pub use vlan_subnet::{VlanHost, VlanSubnet, VlanSubnets};
 
-/// MAC address wrapper for use as a key
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct MacAddr([u8; 6]);
+/// Global aggregator state
+pub struct AggregatorState {
+    /// Device states keyed by site and MAC address
+    pub devices: DashMap<DeviceKey, DeviceState>,
 
-impl MacAddr {
-    pub fn new(bytes: [u8; 6]) -> Self {
-        Self(bytes)
-    }
+    /// Flow states keyed by sensor and flow tuple
+    pub flows: DashMap<FlowKey, FlowState>,
 
-    pub fn from_string(s: &str) -> Option<Self> {
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() != 6 {
-            return None;
-        }
+    /// Protocol statistics
+    pub protocols: DashMap<(u16, Option<u8>), ProtocolStats>,
 
-        let mut bytes = [0u8; 6];
-        for (i, part) in parts.iter().enumerate() {
-            bytes[i] = u8::from_str_radix(part, 16).ok()?;
-        }
+    /// VLAN statistics per site
+    pub vlans: DashMap<(SiteId, u16), VlanStats>,
 
-        Some(Self(bytes))
-    }
+    /// Source addresses of devices per VLAN, for the VLAN to subnet mapping
+    pub vlan_subnets: VlanSubnets,
 
-    pub fn as_bytes(&self) -> &[u8; 6] {
-        &self.0
-    }
+    /// Gateways and hourly traffic between the subnets they route
+    pub subnet_matrix: SubnetMatrix,
 
-    pub fn to_string(&self) -> String {
-        format!(
-            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
-        )
-    }
+    /// TCP handshake round-trip times per device pair
+    pub rtt: RttTracker,
 
-    pub fn oui_prefix(&self) -> String {
-        format!("{:02X}:{:02X}:{:02X}", self.0[0], self.0[1], self.0[2])
-    }
-}
+    /// Hourly traffic per device and service
+    pub composition: TrafficComposition,
 
-/// Global aggregator state
-pub struct AggregatorState {
-    /// Device states keyed by MAC address
-    pub devices: DashMap<MacAddr, DeviceState>,
+    /// Hourly traffic per device, VLAN and DSCP value
+    pub qos: QosAccounting,
 
-    /// Flow states keyed by flow tuple
-    pub flows: DashMap<FlowKey, FlowState>,
+    /// Hourly encrypted and plaintext traffic per device
+    pub encryption: EncryptionRatios,
 
-    /// Protocol statistics
-    pub protocols: DashMap<(u16, Option<u8>), ProtocolStats>,
+    /// Client to server service dependencies
+    pub dependencies: DependencyMap,
 
-    /// VLAN statistics
-    pub vlans: DashMap<u16, VlanStats>,
+    /// TLS server names and certificates per client device
+    pub tls: TlsInventory,
+
+    /// DHCP lease events awaiting persistence
+    pub dhcp: DhcpTracker,
+
+    /// Hourly DNS activity per device
+    pub dns: DnsAnalytics,
+
+    /// Hourly anomaly scores of the baseline detectors per device
+    pub anomalies: AnomalyScores,
+
+    /// Members and senders of multicast groups
+    pub multicast: MulticastGroups,
+
+    /// Time servers queried by each device
+    pub ntp: NtpServers,
+
+    /// Industrial devices and the sources writing to them
+    pub ot: OtDevices,
+
+    /// VoIP calls and their media
+    pub telephony: Telephony,
+
+    /// Capture interfaces each device is seen on, for the L2 segments
+    pub topology: L2Topology,
+
+    /// Clock offsets of the sensors' frames
+    pub clocks: SensorClocks,
+
+    /// Service names of ports, for flows and dependencies
+    pub services: ServiceNames,
+
+    /// Vendor names of OUIs
+    pub vendors: Vendors,
+
+    /// Tenant of each site
+    pub tenants: Tenants,
+
+    /// Devices and VLANs under maintenance
+    pub maintenance: Maintenance,
 
     // Global counters
     pub total_packets: AtomicU64,
@@ -81,6 +152,7 @@ pub struct AggregatorState {
 
 /// VLAN statistics
 pub struct VlanStats {
+    pub site: SiteId,
     pub vlan_id: u16,
     pub outer_vlan_id: Option<u16>,
     pub first_seen: DateTime<Utc>,
@@ -88,6 +160,45 @@ pub struct VlanStats {
     pub packet_count: AtomicU64,
     pub byte_count: AtomicU64,
     pub device_count: AtomicU64,
+    /// Packets and bytes per 802.1p priority (PCP 0-7)
+    pub priority_packets: [AtomicU64; 8],
+    pub priority_bytes: [AtomicU64; 8],
+}
+
+/// VLAN statistics snapshot
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VlanSnapshot {
+    pub site: String,
+    pub vlan_id: u16,
+    pub outer_vlan_id: Option<u16>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub device_count: u64,
+    /// Packets and bytes per 802.1p priority, indexed by PCP (5 = voice,
+    /// 4 = video)
+    pub priority_packets: [u64; 8],
+    pub priority_bytes: [u64; 8],
+}
+
+impl VlanStats {
+    /// Create a snapshot for reporting
+    pub fn snapshot(&self) -> VlanSnapshot {
+        VlanSnapshot {
+            site: self.site.to_string(),
+            vlan_id: self.vlan_id,
+            outer_vlan_id: self.outer_vlan_id,
+            first_seen: self.first_seen,
+            last_seen: DateTime::from_timestamp(self.last_seen.load(Ordering::Relaxed) as i64, 0)
+                .unwrap_or(Utc::now()),
+            packet_count: self.packet_count.load(Ordering::Relaxed),
+            byte_count: self.byte_count.load(Ordering::Relaxed),
+            device_count: self.device_count.load(Ordering::Relaxed),
+            priority_packets: self.priority_packets.each_ref().map(|count| count.load(Ordering::Relaxed)),
+            priority_bytes: self.priority_bytes.each_ref().map(|count| count.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl AggregatorState {
@@ -98,6 +209,27 @@ impl AggregatorState {
             flows: DashMap::new(),
             protocols: DashMap::new(),
             vlans: DashMap::new(),
+            vlan_subnets: VlanSubnets::new(),
+            subnet_matrix: SubnetMatrix::default(),
+            rtt: RttTracker::new(),
+            composition: TrafficComposition::new(),
+            qos: QosAccounting::new(),
+            encryption: EncryptionRatios::new(),
+            dependencies: DependencyMap::new(),
+            tls: TlsInventory::new(),
+            dhcp: DhcpTracker::new(),
+            dns: DnsAnalytics::new(),
+            anomalies: AnomalyScores::new(),
+            multicast: MulticastGroups::new(),
+            ntp: NtpServers::new(),
+            ot: OtDevices::new(),
+            telephony: Telephony::new(),
+            topology: L2Topology::new(),
+            clocks: SensorClocks::new(),
+            services: ServiceNames::default(),
+            vendors: Vendors::default(),
+            tenants: Tenants::default(),
+            maintenance: Maintenance::new(),
             total_packets: AtomicU64::new(0),
             total_bytes: AtomicU64::new(0),
             total_devices: AtomicU64::new(0),
@@ -106,30 +238,68 @@ impl AggregatorState {
         }
     }
 
+    /// Name services with `services` rather than the well-known ports only
+    pub fn with_services(mut self, services: ServiceNames) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Name the vendors of devices with `vendors`
+    pub fn with_vendors(mut self, vendors: Vendors) -> Self {
+        self.vendors = vendors;
+        self
+    }
+
+    /// Assign sites to the tenants owning them in `tenants`
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Count the traffic between subnets `prefix` bits long (24 by default)
+    pub fn with_subnet_prefix(mut self, prefix: u8) -> Self {
+        self.subnet_matrix = SubnetMatrix::new(prefix);
+        self
+    }
+
+    /// Maintenance covering `device` at `at`, set on the device itself or
+    /// on one of its VLANs
+    pub fn maintenance_of(&self, device: DeviceKey, at: DateTime<Utc>) -> Option<MaintenanceEntry> {
+        if self.maintenance.entries.is_empty() {
+            return None;
+        }
+        let vlans: Vec<u16> = self.devices
+            .get(&device)
+            .map(|state| state.vlans.iter().map(|vlan| *vlan.key()).collect())
+            .unwrap_or_default();
+        self.maintenance.covering(device.site, device.mac, &vlans, at)
+    }
+
     /// Process a captured frame
-    pub fn process_frame(&self, frame: &CapturedFrame) -> ProcessResult {
+    pub fn process_frame(&self, frame: &SensorFrame) -> ProcessResult {
+        self.process_frame_at(frame, Utc::now())
+    }
+
+    /// Process a captured frame using `now` as the observation time
+    ///
+    /// Live consumption uses the wall clock; replay passes the frame's own
+    /// capture timestamp so rebuilt first/last-seen values match history.
+    pub fn process_frame_at(&self, frame: &SensorFrame, now: DateTime<Utc>) -> ProcessResult {
         let mut result = ProcessResult::default();
 
         // Update global counters
         self.total_packets.fetch_add(1, Ordering::Relaxed);
         self.total_bytes.fetch_add(frame.frame_size as u64, Ordering::Relaxed);
 
-        // Parse MAC addresses
-        let src_mac = match MacAddr::from_string(&frame.src_mac) {
-            Some(m) => m,
-            None => return result,
-        };
-        let dst_mac = match MacAddr::from_string(&frame.dst_mac) {
-            Some(m) => m,
-            None => return result,
-        };
-
-        let now = Utc::now();
+        let src_mac = frame.src_mac;
+        let dst_mac = frame.dst_mac;
         let now_ts = now.timestamp() as u64;
+        let src = DeviceKey::new(frame.site, src_mac);
+        let dst = DeviceKey::new(frame.site, dst_mac);
 
         // Update source device
         let src_is_new = self.update_device(
-            src_mac,
+            src,
             frame.src_ip,
             frame.vlan_id(),
             frame.frame_size as u64,
@@ -138,13 +308,31 @@ impl AggregatorState {
             now_ts,
         );
         if src_is_new {
-            result.new_devices.push(src_mac);
+            result.new_devices.push(src);
+        }
+        self.topology.record(src, frame.sensor, &frame.interface, now);
+        self.record_evidence(src, frame, dst_mac);
+        // The address a sender announces in ARP is bound with more
+        // confidence than those inferred from IP traffic, which a router
+        // sends for every host behind it
+        if let Some(arp) = frame.arp.as_ref().filter(|arp| arp.sender_mac == src_mac && !arp.is_probe()) {
+            if let Some(device) = self.devices.get(&src) {
+                device.bind_arp(arp.sender_ip, frame.vlan_id(), now_ts);
+            }
+        }
+        if !frame.tags.is_empty() {
+            // Tags set by the capture's scripting hooks
+            if let Some(device) = self.devices.get(&src) {
+                for (name, value) in &frame.tags {
+                    device.set_tag(name, value);
+                }
+            }
         }
 
         // Update destination device (if not broadcast/multicast)
-        if !dst_mac.0[0] & 0x01 == 0x01 {
+        if !dst_mac.is_multicast() {
             let dst_is_new = self.update_device(
-                dst_mac,
+                dst,
                 frame.dst_ip,
                 frame.vlan_id(),
                 frame.frame_size as u64,
@@ -153,12 +341,17 @@ impl AggregatorState {
                 now_ts,
             );
             if dst_is_new {
-                result.new_devices.push(dst_mac);
+                result.new_devices.push(dst);
+            }
+            if let Some(device) = self.devices.get(&dst) {
+                device.add_evidence(EVIDENCE_RECEIVED);
             }
         }
 
         // Update flow
         let flow_key = FlowKey {
+            site: frame.site,
+            sensor: frame.sensor,
             src_mac,
             dst_mac,
             src_ip: frame.src_ip,
@@ -167,9 +360,69 @@ impl AggregatorState {
             dst_port: frame.dst_port,
             vlan_id: frame.vlan_id(),
             protocol: frame.ip_protocol,
+            icmp_type: frame.icmp_type,
+            icmp_code: frame.icmp_code,
+            icmp_id: frame.icmp_id,
         };
 
-        let flow_is_new = self.update_flow(&flow_key, frame, now, now_ts);
+        let (flow_is_new, flow_tls) = self.update_flow(&flow_key, frame, now, now_ts);
+
+        // Count encrypted and plaintext traffic on both ends
+        if frame.src_ip.is_some() {
+            let protocol = encryption::classify(frame.ip_protocol, frame.src_port, frame.dst_port, flow_tls);
+            self.encryption.record(src, protocol, frame.frame_size as u64, now);
+            if !dst_mac.is_multicast() {
+                self.encryption.record(dst, protocol, frame.frame_size as u64, now);
+            }
+        }
+
+        // Time TCP handshakes and count retransmissions and resets
+        if let Some(flags) = &frame.tcp_flags {
+            self.rtt.observe(frame, src, dst);
+            self.update_tcp_health(&flow_key, frame, flags.rst);
+            self.update_handshake(&flow_key, flags);
+        }
+
+        // Record TLS server names and certificates
+        if frame.tls.is_some() {
+            self.tls.observe(frame, src, dst, now);
+        }
+
+        // Follow DHCP leases
+        if frame.dhcp.is_some() {
+            self.dhcp.observe(frame, now);
+        }
+
+        // Count DNS queries and response codes
+        if let Some(dns) = &frame.dns {
+            self.dns.record(dns, src, dst, now);
+        }
+
+        // Follow multicast group members and senders
+        if let Some(igmp) = &frame.igmp {
+            self.multicast.observe(frame.site, src_mac, igmp, now);
+        } else if let Some(group) = frame.dst_ip.filter(|ip| ip.is_multicast() && frame.ip_protocol != Some(multicast::IGMP)) {
+            self.multicast.record(frame.site, group, src_mac, frame.frame_size as u64, now);
+        }
+
+        // Record the time servers devices query
+        if frame.ntp.is_some() {
+            self.ntp.observe(frame, now);
+        }
+
+        // Inventory industrial devices and the writes to them
+        if frame.ot.is_some() {
+            self.ot.observe(frame, now);
+        }
+
+        // Follow calls, and count the RTP datagrams of their media
+        if let Some(sip) = &frame.sip {
+            self.telephony.observe(frame, now);
+            self.classify_voip(src, dst, sip);
+        } else if frame.ip_protocol == Some(sip::UDP) {
+            self.telephony.record_rtp(frame, now);
+        }
+
         if flow_is_new {
             result.new_flows.push(flow_key);
         }
@@ -177,18 +430,53 @@ impl AggregatorState {
         // Update protocol stats
         self.update_protocol(frame.ethertype, frame.ip_protocol, frame.frame_size as u64, now_ts);
 
+        // Update traffic composition of both devices
+        let service = Service::new(frame.ethertype, frame.ip_protocol, frame.src_port, frame.dst_port);
+        self.composition.record(src, service, frame.frame_size as u64, true, now);
+        if !dst_mac.is_multicast() {
+            self.composition.record(dst, service, frame.frame_size as u64, false, now);
+
+            // Roll the frame up into its client to server dependency
+            self.dependencies.record(frame, src, dst, flow_is_new, now);
+        }
+
+        // Detect gateways and count the traffic they route between subnets
+        if let Some(ip) = frame.src_ip {
+            if self.subnet_matrix.observe_source(src, ip) {
+                if let Some(device) = self.devices.get(&src) {
+                    device.is_gateway.store(true, Ordering::Relaxed);
+                    device.dirty.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        if let (Some(src_ip), Some(dst_ip)) = (frame.src_ip, frame.dst_ip) {
+            if !dst_mac.is_multicast() {
+                self.subnet_matrix.record(dst, src_ip, dst_ip, frame.frame_size as u64, now);
+            }
+        }
+
+        // Count the sender's QoS marking
+        if let Some(dscp) = frame.dscp {
+            self.qos.record(src, frame.vlan_id(), dscp, frame.frame_size as u64, now);
+        }
+
         // Update VLAN stats
         if let Some(vlan_id) = frame.vlan_id() {
-            self.update_vlan(vlan_id, frame.outer_vlan_id(), frame.frame_size as u64, now, now_ts);
+            let priority = frame.vlan_priority().unwrap_or(0);
+            self.update_vlan(frame.site, vlan_id, frame.outer_vlan_id(), priority, frame.frame_size as u64, now, now_ts);
+            if let Some(ip) = frame.src_ip {
+                self.vlan_subnets.record(frame.site, vlan_id, src_mac, ip, now);
+            }
         }
 
         result
     }
 
     /// Update or create a device entry
+    #[allow(clippy::too_many_arguments)]
     fn update_device(
         &self,
-        mac: MacAddr,
+        key: DeviceKey,
         ip: Option<Ipv4Addr>,
         vlan_id: Option<u16>,
         bytes: u64,
@@ -198,32 +486,125 @@ impl AggregatorState {
     ) -> bool {
         let mut is_new = false;
 
-        self.devices.entry(mac).or_insert_with(|| {
+        self.devices.entry(key).or_insert_with(|| {
             is_new = true;
             self.total_devices.fetch_add(1, Ordering::Relaxed);
-            DeviceState::new(mac, now)
+            DeviceState::new(key, now)
         }).update(ip, vlan_id, bytes, is_source, now_ts);
 
         is_new
     }
 
-    /// Update or create a flow entry
+    /// Type the devices of a SIP registration: the phone registering and
+    /// the PBX it registers with, unless an enricher or a script already
+    /// typed them
+    fn classify_voip(&self, src: DeviceKey, dst: DeviceKey, sip: &netsentinel_types::SipInfo) {
+        let register = sip.method == "REGISTER" && sip.status.is_none();
+        if let Some(device) = self.devices.get(&src) {
+            if let Some(user_agent) = &sip.user_agent {
+                device.set_tag(sip::USER_AGENT_TAG, user_agent);
+            }
+            if register && !device.tags.contains_key("type") {
+                device.set_tag("type", sip::PHONE_TYPE);
+            }
+        }
+        if let Some(device) = self.devices.get(&dst).filter(|_| register) {
+            if !device.tags.contains_key("type") {
+                device.set_tag("type", sip::PBX_TYPE);
+            }
+        }
+    }
+
+    /// Record what `frame` tells about its source device being real
+    fn record_evidence(&self, src: DeviceKey, frame: &SensorFrame, dst_mac: MacAddr) {
+        let mut evidence = 0;
+        if frame.ethertype == 0x0806 {
+            evidence |= EVIDENCE_ARP;
+        }
+        if let Some(dhcp) = &frame.dhcp {
+            evidence |= EVIDENCE_DHCP;
+            if dhcp.hostname.as_ref().is_some_and(|h| !h.is_empty()) {
+                evidence |= EVIDENCE_HOSTNAME;
+            }
+        }
+        if frame.dns.is_some() {
+            evidence |= EVIDENCE_DNS;
+        }
+        if !dst_mac.is_multicast() {
+            evidence |= EVIDENCE_UNICAST;
+        }
+
+        if let Some(device) = self.devices.get(&src) {
+            device.add_evidence(evidence);
+        }
+    }
+
+    /// Update or create a flow entry, returning whether it is new and
+    /// whether it carried a TLS handshake
     fn update_flow(
         &self,
         key: &FlowKey,
-        frame: &CapturedFrame,
+        frame: &SensorFrame,
         now: DateTime<Utc>,
         now_ts: u64,
-    ) -> bool {
+    ) -> (bool, bool) {
         let mut is_new = false;
 
-        self.flows.entry(key.clone()).or_insert_with(|| {
+        let flow = self.flows.entry(key.clone()).or_insert_with(|| {
             is_new = true;
             self.total_flows.fetch_add(1, Ordering::Relaxed);
             FlowState::new(key.clone(), now)
-        }).update(frame.frame_size as u64, frame.tcp_flags_byte(), now_ts);
+        });
+        flow.update(frame.frame_size as u64, frame.tcp_flags_byte(), now_ts);
+        if frame.tls.is_some() {
+            flow.mark_tls();
+        }
+        if frame.snmp.as_ref().is_some_and(|snmp| snmp.uses_community()) {
+            flow.mark_snmp_community();
+        }
 
-        is_new
+        (is_new, flow.is_tls())
+    }
+
+    /// Update TCP health counters of a flow and its source device
+    fn update_tcp_health(&self, key: &FlowKey, frame: &SensorFrame, rst: bool) {
+        let Some(flow) = self.flows.get(key) else { return };
+        let retransmit = flow.update_tcp(frame.tcp_seq, frame.payload_size, rst);
+        drop(flow);
+
+        if let Some(device) = self.devices.get(&key.src_device()) {
+            device.tcp.record(frame.payload_size > 0, retransmit, rst);
+        }
+    }
+
+    /// Follow the handshake of the connection a TCP segment belongs to, on
+    /// the flow of its client
+    fn update_handshake(&self, key: &FlowKey, flags: &TcpFlags) {
+        if flags.syn {
+            if !flags.ack {
+                if let Some(flow) = self.flows.get(key) {
+                    flow.handshake_syn();
+                }
+            } else if let Some(client) = self.flows.get(&key.reverse()) {
+                client.handshake_answered();
+            }
+            return;
+        }
+
+        if flags.rst {
+            if let Some(client) = self.flows.get(&key.reverse()) {
+                client.handshake_reset(true);
+            }
+            if let Some(flow) = self.flows.get(key) {
+                flow.handshake_reset(false);
+            }
+        } else if flags.ack {
+            // The client acknowledging the SYN-ACK, even when the server's
+            // side of the connection is not captured
+            if let Some(flow) = self.flows.get(key) {
+                flow.handshake_answered();
+            }
+        }
     }
 
     /// Update protocol statistics
@@ -235,15 +616,19 @@ impl AggregatorState {
     }
 
     /// Update VLAN statistics
+    #[allow(clippy::too_many_arguments)]
     fn update_vlan(
         &self,
+        site: SiteId,
         vlan_id: u16,
         outer_vlan_id: Option<u16>,
+        priority: u8,
         bytes: u64,
         now: DateTime<Utc>,
         now_ts: u64,
     ) {
-        self.vlans.entry(vlan_id).or_insert_with(|| VlanStats {
+        self.vlans.entry((site, vlan_id)).or_insert_with(|| VlanStats {
+            site,
             vlan_id,
             outer_vlan_id,
             first_seen: now,
@@ -251,12 +636,17 @@ impl AggregatorState {
             packet_count: AtomicU64::new(0),
             byte_count: AtomicU64::new(0),
             device_count: AtomicU64::new(0),
+            priority_packets: Default::default(),
+            priority_bytes: Default::default(),
         });
 
-        if let Some(vlan) = self.vlans.get(&vlan_id) {
+        if let Some(vlan) = self.vlans.get(&(site, vlan_id)) {
             vlan.packet_count.fetch_add(1, Ordering::Relaxed);
             vlan.byte_count.fetch_add(bytes, Ordering::Relaxed);
             vlan.last_seen.store(now_ts, Ordering::Relaxed);
+            let pcp = (priority & 0x07) as usize;
+            vlan.priority_packets[pcp].fetch_add(1, Ordering::Relaxed);
+            vlan.priority_bytes[pcp].fetch_add(bytes, Ordering::Relaxed);
         }
     }
 
@@ -272,6 +662,48 @@ impl AggregatorState {
             uptime_seconds: (Utc::now() - self.start_time).num_seconds() as u64,
         }
     }
+
+    /// Forget `mac` on every site, returning the devices and flows removed
+    ///
+    /// The trackers keyed by device drop it as well, so nothing about it is
+    /// persisted again unless it sends more frames.
+    pub fn purge_device(&self, mac: MacAddr) -> (usize, usize) {
+        let mut devices = 0;
+        self.devices.retain(|key, _| {
+            let keep = key.mac != mac;
+            devices += usize::from(!keep);
+            keep
+        });
+        let mut flows = 0;
+        self.flows.retain(|key, _| {
+            let keep = key.src_mac != mac && key.dst_mac != mac;
+            flows += usize::from(!keep);
+            keep
+        });
+
+        self.rtt.pairs.retain(|(client, server), _| client.mac != mac && server.mac != mac);
+        self.composition.hours.retain(|(device, _), _| device.mac != mac);
+        self.qos.hours.retain(|(device, _), _| device.mac != mac);
+        self.encryption.hours.retain(|(device, _), _| device.mac != mac);
+        self.dependencies.edges.retain(|key, _| key.client.mac != mac && key.server.mac != mac);
+        self.tls.observations.retain(|key, _| key.client_mac != mac);
+        self.tls.fingerprints.retain(|device, _| device.mac != mac);
+        self.dns.hours.retain(|(device, _), _| device.mac != mac);
+        self.dns.totals.retain(|device, _| device.mac != mac);
+        self.anomalies.hours.retain(|(device, _, _), _| device.mac != mac);
+        self.maintenance.entries.retain(|_, entry| entry.target() != Some(MaintenanceTarget::Device(mac)));
+        self.multicast.forget(mac);
+        self.ntp.associations.retain(|key, _| key.client_mac != mac);
+        self.ot.forget(mac);
+        self.telephony.forget(mac);
+        self.vlan_subnets.hosts.retain(|(_, _, host), _| *host != mac);
+        self.subnet_matrix.sources.retain(|device, _| device.mac != mac);
+        self.vlan_subnets.dirty.store(true, Ordering::Relaxed);
+        self.topology.devices.retain(|device, _| device.mac != mac);
+        self.topology.dirty.store(true, Ordering::Relaxed);
+
+        (devices, flows)
+    }
 }
 
 impl Default for AggregatorState {
@@ -283,12 +715,12 @@ impl Default for AggregatorState {
 /// Result of processing a frame
 #[derive(Debug, Default)]
 pub struct ProcessResult {
-    pub new_devices: Vec<MacAddr>,
+    pub new_devices: Vec<DeviceKey>,
     pub new_flows: Vec<FlowKey>,
 }
 
 /// State statistics snapshot
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct StateStats {
     pub total_packets: u64,
     pub total_bytes: u64,
@@ -299,72 +731,29 @@ pub struct StateStats {
     pub uptime_seconds: u64,
 }
 
-/// Captured frame structure (simplified for aggregator)
-#[derive(Debug, Clone, serde::Deserialize)]
-pub struct CapturedFrame {
-    pub timestamp: DateTime<Utc>,
-    pub interface: String,
-    pub src_mac: String,
-    pub dst_mac: String,
-    pub ethertype: u16,
-    pub vlan: Option<VlanInfo>,
-    pub qinq: Option<QinQInfo>,
-    pub src_ip: Option<Ipv4Addr>,
-    pub dst_ip: Option<Ipv4Addr>,
-    pub ip_protocol: Option<u8>,
-    pub src_port: Option<u16>,
-    pub dst_port: Option<u16>,
-    pub tcp_flags: Option<TcpFlags>,
-    pub frame_size: u32,
-    pub payload_size: u32,
-}
-
+/// Frame as read from the stream: a captured frame stamped with the site
+/// and sensor that published it
 #[derive(Debug, Clone, serde::Deserialize)]
-pub struct VlanInfo {
-    pub id: u16,
-    pub priority: u8,
-    pub dei: bool,
+pub struct SensorFrame {
+    /// Site and sensor that captured the frame (older sensors send neither)
+    #[serde(default)]
+    pub site: SiteId,
+    #[serde(default)]
+    pub sensor: SensorId,
+    #[serde(flatten)]
+    pub frame: CapturedFrame,
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
-pub struct QinQInfo {
-    pub outer_vlan: VlanInfo,
-    pub inner_vlan: VlanInfo,
-}
-
-#[derive(Debug, Clone, serde::Deserialize)]
-pub struct TcpFlags {
-    pub fin: bool,
-    pub syn: bool,
-    pub rst: bool,
-    pub psh: bool,
-    pub ack: bool,
-    pub urg: bool,
-}
+impl Deref for SensorFrame {
+    type Target = CapturedFrame;
 
-impl CapturedFrame {
-    pub fn vlan_id(&self) -> Option<u16> {
-        if let Some(ref qinq) = self.qinq {
-            Some(qinq.inner_vlan.id)
-        } else {
-            self.vlan.as_ref().map(|v| v.id)
-        }
-    }
-
-    pub fn outer_vlan_id(&self) -> Option<u16> {
-        self.qinq.as_ref().map(|q| q.outer_vlan.id)
+    fn deref(&self) -> &CapturedFrame {
+        &self.frame
     }
+}
 
-    pub fn tcp_flags_byte(&self) -> Option<u8> {
-        self.tcp_flags.as_ref().map(|f| {
-            let mut flags = 0u8;
-            if f.fin { flags |= 0x01; }
-            if f.syn { flags |= 0x02; }
-            if f.rst { flags |= 0x04; }
-            if f.psh { flags |= 0x08; }
-            if f.ack { flags |= 0x10; }
-            if f.urg { flags |= 0x20; }
-            flags
-        })
+impl DerefMut for SensorFrame {
+    fn deref_mut(&mut self) -> &mut CapturedFrame {
+        &mut self.frame
     }
 }
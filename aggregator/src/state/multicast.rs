@@ -0,0 +1,265 @@
+//! Multicast group inventory
+//!
+//! Which devices listen and send to which IPv4 multicast groups, for the
+//! capacity planning of AV streams and market data feeds. Members are the
+//! devices reporting a group with IGMP, until they leave it or stop
+//! reporting for longer than the membership interval; senders are the
+//! devices sending frames to the group address, until idle as long.
+//!
+//! Each group's traffic is counted between persist cycles, its bandwidth
+//! being the rate over the cycle.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use netsentinel_types::IgmpInfo;
+
+use super::{MacAddr, SiteId};
+
+/// IP protocol number of IGMP, whose messages are not group traffic
+pub const IGMP: u8 = 2;
+
+/// IGMPv2 group membership interval (robustness 2, query interval 125s,
+/// response 10s): a member not reporting for longer has left
+pub const MEMBERSHIP_INTERVAL_SECS: i64 = 260;
+
+/// Traffic to a group
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GroupTraffic {
+    pub packets: u64,
+    pub bytes: u64,
+    /// Start of the counting, for pending traffic when last persisted
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Members and senders of one group
+#[derive(Debug)]
+pub struct MulticastGroup {
+    /// Members, with the time of their last report
+    pub members: HashMap<MacAddr, DateTime<Utc>>,
+    /// Senders, with the time of their last frame
+    pub senders: HashMap<MacAddr, DateTime<Utc>>,
+    /// Traffic since the aggregator started
+    pub total: GroupTraffic,
+    /// Traffic since last persisted
+    pending: GroupTraffic,
+    /// Highest rate of a persist cycle
+    peak_bits_per_sec: f64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Members or senders changed since last persisted
+    dirty: bool,
+}
+
+impl MulticastGroup {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            members: HashMap::new(),
+            senders: HashMap::new(),
+            total: GroupTraffic { since: Some(now), ..Default::default() },
+            pending: GroupTraffic { since: Some(now), ..Default::default() },
+            peak_bits_per_sec: 0.0,
+            first_seen: now,
+            last_seen: now,
+            dirty: true,
+        }
+    }
+
+    /// Forget the members and senders idle since before `idle`
+    fn expire(&mut self, idle: DateTime<Utc>) {
+        let before = self.members.len() + self.senders.len();
+        self.members.retain(|_, seen| *seen >= idle);
+        self.senders.retain(|_, seen| *seen >= idle);
+        self.dirty |= self.members.len() + self.senders.len() != before;
+    }
+
+    /// Snapshot at `now`, leaving out the devices idle since before `idle`
+    fn snapshot(&self, site: SiteId, group: Ipv4Addr, now: DateTime<Utc>, idle: DateTime<Utc>) -> MulticastSnapshot {
+        let sorted = |devices: &HashMap<MacAddr, DateTime<Utc>>| {
+            let mut macs: Vec<String> = devices.iter()
+                .filter(|(_, seen)| **seen >= idle)
+                .map(|(mac, _)| mac.to_string())
+                .collect();
+            macs.sort();
+            macs
+        };
+        let since = self.pending.since.unwrap_or(now);
+        let elapsed = (now - since).num_milliseconds() as f64 / 1000.0;
+        let bits_per_sec = if elapsed > 0.0 { self.pending.bytes as f64 * 8.0 / elapsed } else { 0.0 };
+
+        MulticastSnapshot {
+            site: site.to_string(),
+            group,
+            members: sorted(&self.members),
+            senders: sorted(&self.senders),
+            packets: self.total.packets,
+            bytes: self.total.bytes,
+            bits_per_sec,
+            peak_bits_per_sec: self.peak_bits_per_sec.max(bits_per_sec),
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        }
+    }
+}
+
+/// A multicast group's members, senders and bandwidth
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MulticastSnapshot {
+    pub site: String,
+    pub group: Ipv4Addr,
+    pub members: Vec<String>,
+    pub senders: Vec<String>,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Rate since the group was last persisted
+    pub bits_per_sec: f64,
+    pub peak_bits_per_sec: f64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Multicast groups per site
+#[derive(Default)]
+pub struct MulticastGroups {
+    pub groups: DashMap<(SiteId, Ipv4Addr), MulticastGroup>,
+}
+
+impl MulticastGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the groups `mac` joined and left in an IGMP message
+    pub fn observe(&self, site: SiteId, mac: MacAddr, igmp: &IgmpInfo, now: DateTime<Utc>) {
+        for group in &igmp.joined {
+            let mut entry = self.groups.entry((site, *group)).or_insert_with(|| MulticastGroup::new(now));
+            entry.dirty |= entry.members.insert(mac, now).is_none();
+            entry.last_seen = entry.last_seen.max(now);
+        }
+        for group in &igmp.left {
+            if let Some(mut entry) = self.groups.get_mut(&(site, *group)) {
+                entry.dirty |= entry.members.remove(&mac).is_some();
+            }
+        }
+    }
+
+    /// Record `mac` sending `bytes` to `group`
+    pub fn record(&self, site: SiteId, group: Ipv4Addr, mac: MacAddr, bytes: u64, now: DateTime<Utc>) {
+        let mut entry = self.groups.entry((site, group)).or_insert_with(|| MulticastGroup::new(now));
+        let entry = entry.value_mut();
+        entry.dirty |= entry.senders.insert(mac, now).is_none();
+        entry.last_seen = entry.last_seen.max(now);
+        for traffic in [&mut entry.total, &mut entry.pending] {
+            traffic.packets += 1;
+            traffic.bytes += bytes;
+        }
+    }
+
+    /// Current groups, sorted by site and group
+    pub fn snapshots(&self, now: DateTime<Utc>) -> Vec<MulticastSnapshot> {
+        let idle = now - chrono::Duration::seconds(MEMBERSHIP_INTERVAL_SECS);
+        let mut groups: Vec<MulticastSnapshot> = self.groups.iter()
+            .map(|entry| {
+                let (site, group) = *entry.key();
+                entry.snapshot(site, group, now, idle)
+            })
+            .collect();
+        groups.sort_by(|a, b| (&a.site, a.group).cmp(&(&b.site, b.group)));
+        groups
+    }
+
+    /// Groups whose members, senders or traffic changed since the last
+    /// call, with their traffic since then
+    ///
+    /// Members and senders idle for the membership interval are forgotten,
+    /// and groups left without any afterwards.
+    pub fn take(&self, now: DateTime<Utc>) -> Vec<(MulticastSnapshot, GroupTraffic)> {
+        let idle = now - chrono::Duration::seconds(MEMBERSHIP_INTERVAL_SECS);
+        let mut changed = Vec::new();
+
+        for mut entry in self.groups.iter_mut() {
+            let (site, group) = *entry.key();
+            entry.expire(idle);
+            if !entry.dirty && entry.pending.packets == 0 {
+                continue;
+            }
+            let snapshot = entry.snapshot(site, group, now, idle);
+            entry.peak_bits_per_sec = snapshot.peak_bits_per_sec;
+            changed.push((snapshot, entry.pending));
+            entry.pending = GroupTraffic { since: Some(now), ..Default::default() };
+            entry.dirty = false;
+        }
+        self.groups.retain(|_, entry| !entry.members.is_empty() || !entry.senders.is_empty());
+
+        changed
+    }
+
+    /// Put back the traffic of a group that failed to persist
+    pub fn restore(&self, site: SiteId, group: Ipv4Addr, traffic: GroupTraffic) {
+        let now = Utc::now();
+        let mut entry = self.groups.entry((site, group)).or_insert_with(|| MulticastGroup::new(now));
+        let pending = &mut entry.pending;
+        pending.packets += traffic.packets;
+        pending.bytes += traffic.bytes;
+        pending.since = pending.since.min(traffic.since).or(traffic.since);
+        entry.dirty = true;
+    }
+
+    /// Forget `mac` as a member and sender of every group
+    pub fn forget(&self, mac: MacAddr) {
+        for mut entry in self.groups.iter_mut() {
+            let removed = entry.members.remove(&mac).is_some() | entry.senders.remove(&mac).is_some();
+            entry.dirty |= removed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_multicast_groups() {
+        let groups = MulticastGroups::new();
+        let (site, group) = (SiteId::default(), Ipv4Addr::new(239, 1, 1, 1));
+        let (encoder, display, panel) = (
+            MacAddr::new([0x00, 0x11, 0x22, 0, 0, 1]),
+            MacAddr::new([0x00, 0x11, 0x22, 0, 0, 2]),
+            MacAddr::new([0x00, 0x11, 0x22, 0, 0, 3]),
+        );
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap();
+
+        let join = IgmpInfo { message_type: 0x16, joined: vec![group], left: Vec::new() };
+        groups.observe(site, display, &join, at);
+        groups.observe(site, panel, &join, at);
+        for _ in 0..10 {
+            groups.record(site, group, encoder, 1250, at);
+        }
+
+        // 100 kbit over 10s
+        let changed = groups.take(at + chrono::Duration::seconds(10));
+        assert_eq!(changed.len(), 1);
+        let (snapshot, traffic) = &changed[0];
+        assert_eq!(snapshot.members, vec![display.to_string(), panel.to_string()]);
+        assert_eq!(snapshot.senders, vec![encoder.to_string()]);
+        assert_eq!(*traffic, GroupTraffic { packets: 10, bytes: 12_500, since: Some(at) });
+        assert_eq!(snapshot.bits_per_sec, 10_000.0);
+        assert_eq!(groups.snapshots(at + chrono::Duration::seconds(20))[0].peak_bits_per_sec, 10_000.0);
+        assert!(groups.take(at + chrono::Duration::seconds(20)).is_empty());
+
+        // A leave, then the rest going idle
+        let leave = IgmpInfo { message_type: 0x17, joined: Vec::new(), left: vec![group] };
+        groups.observe(site, panel, &leave, at + chrono::Duration::seconds(30));
+        let changed = groups.take(at + chrono::Duration::seconds(40));
+        assert_eq!(changed[0].0.members, vec![display.to_string()]);
+        assert_eq!(groups.snapshots(at + chrono::Duration::seconds(40)).len(), 1);
+
+        let changed = groups.take(at + chrono::Duration::seconds(MEMBERSHIP_INTERVAL_SECS + 1));
+        assert!(changed[0].0.members.is_empty() && changed[0].0.senders.is_empty());
+        assert!(groups.groups.is_empty());
+    }
+}
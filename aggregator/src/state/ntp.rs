@@ -0,0 +1,199 @@
+//! NTP servers per device
+//!
+//! Which time servers each device queries, from the NTP messages the
+//! capture decodes: client requests give the device and the server it asks,
+//! server responses the server's stratum and reference, i.e. where its time
+//! comes from. Symmetric and broadcast associations are left out.
+//!
+//! Requests and responses are counted between persist cycles; the NTP
+//! detector flags devices querying servers outside the authorized ones.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::net::Ipv4Addr;
+
+use netsentinel_types::NtpInfo;
+
+use super::{DeviceKey, MacAddr, SensorFrame, SiteId};
+
+/// Association modes followed
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+
+/// A device and a time server it queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NtpKey {
+    pub site: SiteId,
+    pub client_mac: MacAddr,
+    pub server_ip: Ipv4Addr,
+}
+
+impl NtpKey {
+    pub fn client(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.client_mac)
+    }
+}
+
+/// What is known of a device's time server
+#[derive(Debug, Clone)]
+pub struct NtpAssociation {
+    pub client_ip: Option<Ipv4Addr>,
+    /// Protocol version of the last request
+    pub version: u8,
+    /// Stratum and reference of the last response
+    pub stratum: Option<u8>,
+    pub reference_id: Option<String>,
+    /// Requests and responses since last persisted
+    pub requests: u64,
+    pub responses: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Last request of the device, the server answering or not
+    pub last_request: Option<DateTime<Utc>>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+impl NtpAssociation {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            client_ip: None,
+            version: 0,
+            stratum: None,
+            reference_id: None,
+            requests: 0,
+            responses: 0,
+            first_seen: now,
+            last_seen: now,
+            last_request: None,
+            dirty: true,
+        }
+    }
+
+    /// Record a response of the server
+    fn respond(&mut self, ntp: &NtpInfo, now: DateTime<Utc>) {
+        self.stratum = Some(ntp.stratum);
+        if ntp.reference_id.is_some() {
+            self.reference_id = ntp.reference_id.clone();
+        }
+        self.responses += 1;
+        self.last_seen = self.last_seen.max(now);
+        self.dirty = true;
+    }
+}
+
+/// Time servers queried by each device
+#[derive(Default)]
+pub struct NtpServers {
+    pub associations: DashMap<NtpKey, NtpAssociation>,
+}
+
+impl NtpServers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an NTP request or response
+    pub fn observe(&self, frame: &SensorFrame, now: DateTime<Utc>) {
+        let (Some(ntp), Some(src_ip), Some(dst_ip)) = (frame.ntp.as_ref(), frame.src_ip, frame.dst_ip) else {
+            return;
+        };
+
+        match ntp.mode {
+            MODE_CLIENT => {
+                let key = NtpKey { site: frame.site, client_mac: frame.src_mac, server_ip: dst_ip };
+                let mut entry = self.associations.entry(key).or_insert_with(|| NtpAssociation::new(now));
+                entry.client_ip = Some(src_ip);
+                entry.version = ntp.version;
+                entry.requests += 1;
+                entry.last_request = Some(now);
+                entry.last_seen = entry.last_seen.max(now);
+                entry.dirty = true;
+            }
+            MODE_SERVER => {
+                // Only responses to requests seen: a server answering a
+                // device behind another capture point says nothing of it
+                let key = NtpKey { site: frame.site, client_mac: frame.dst_mac, server_ip: src_ip };
+                if let Some(mut entry) = self.associations.get_mut(&key) {
+                    entry.respond(ntp, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Associations changed since the last call, with their requests and
+    /// responses since then
+    pub fn take(&self) -> Vec<(NtpKey, NtpAssociation)> {
+        let mut changed = Vec::new();
+        for mut entry in self.associations.iter_mut() {
+            if entry.dirty {
+                changed.push((*entry.key(), entry.clone()));
+                entry.requests = 0;
+                entry.responses = 0;
+                entry.dirty = false;
+            }
+        }
+        changed
+    }
+
+    /// Put back the counts of an association that failed to persist
+    pub fn restore(&self, key: NtpKey, association: &NtpAssociation) {
+        if let Some(mut entry) = self.associations.get_mut(&key) {
+            entry.requests += association.requests;
+            entry.responses += association.responses;
+            entry.dirty = true;
+        }
+    }
+
+    /// Forget associations with nothing left to persist, idle since `before`
+    pub fn prune(&self, before: DateTime<Utc>) {
+        self.associations.retain(|_, association| association.last_seen >= before || association.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::CapturedFrame;
+
+    fn frame(src: MacAddr, dst: MacAddr, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, mode: u8, stratum: u8) -> SensorFrame {
+        let mut frame = CapturedFrame::new("eth0", src, dst, 0x0800, 90);
+        frame.src_ip = Some(src_ip);
+        frame.dst_ip = Some(dst_ip);
+        frame.ntp = Some(NtpInfo { version: 4, mode, stratum, reference_id: (stratum == 1).then(|| "GPS".to_string()) });
+        SensorFrame { site: SiteId::default(), sensor: Default::default(), frame }
+    }
+
+    #[test]
+    fn test_ntp_servers() {
+        let servers = NtpServers::new();
+        let now = Utc::now();
+        let camera = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let router = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        let (camera_ip, pool) = (Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(203, 0, 113, 123));
+
+        // A response to a request never seen is left out
+        servers.observe(&frame(router, camera, pool, camera_ip, MODE_SERVER, 2), now);
+        assert!(servers.associations.is_empty());
+
+        servers.observe(&frame(camera, router, camera_ip, pool, MODE_CLIENT, 0), now);
+        servers.observe(&frame(router, camera, pool, camera_ip, MODE_SERVER, 1), now);
+        let key = NtpKey { site: SiteId::default(), client_mac: camera, server_ip: pool };
+
+        let changed = servers.take();
+        assert_eq!(changed.len(), 1);
+        let (taken, association) = &changed[0];
+        assert_eq!(*taken, key);
+        assert_eq!((association.requests, association.responses), (1, 1));
+        assert_eq!(association.stratum, Some(1));
+        assert_eq!(association.reference_id.as_deref(), Some("GPS"));
+        assert!(servers.take().is_empty());
+
+        servers.restore(key, association);
+        assert_eq!(servers.associations.get(&key).unwrap().requests, 1);
+        servers.take();
+        servers.prune(now + chrono::Duration::seconds(1));
+        assert!(servers.associations.is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Industrial devices
+//!
+//! PLCs, RTUs and building controllers, from the Modbus, DNP3 and BACnet
+//! messages the capture decodes: a device is the one requests go to, or the
+//! one answering or announcing itself; masters and workstations send the
+//! requests. Each device is kept with the units it serves (Modbus unit,
+//! DNP3 outstation address, BACnet device instance), and each source
+//! writing to a device (register and coil writes, DNP3 operates and
+//! restarts, BACnet property writes) with the functions it used.
+//!
+//! Messages and writes are counted between persist cycles; the OT detector
+//! flags writes from sources outside the authorized ones.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
+
+use netsentinel_types::OtProtocol;
+
+use super::{DeviceKey, MacAddr, SensorFrame, SiteId};
+
+/// An industrial device, per protocol it speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OtDeviceKey {
+    pub site: SiteId,
+    pub mac: MacAddr,
+    pub protocol: OtProtocol,
+}
+
+impl OtDeviceKey {
+    pub fn device(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.mac)
+    }
+}
+
+/// What is known of an industrial device
+#[derive(Debug, Clone)]
+pub struct OtDevice {
+    pub ip: Option<Ipv4Addr>,
+    /// Units, outstation addresses or device instances seen
+    pub units: BTreeSet<u32>,
+    /// Requests received and responses sent since last persisted
+    pub requests: u64,
+    pub responses: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+/// A source writing to an industrial device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OtWriteKey {
+    pub site: SiteId,
+    pub protocol: OtProtocol,
+    pub source: MacAddr,
+    pub device: MacAddr,
+}
+
+impl OtWriteKey {
+    pub fn source(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.source)
+    }
+
+    pub fn device(&self) -> DeviceKey {
+        DeviceKey::new(self.site, self.device)
+    }
+}
+
+/// Writes of a source to a device
+#[derive(Debug, Clone)]
+pub struct OtWrite {
+    pub source_ip: Option<Ipv4Addr>,
+    pub device_ip: Option<Ipv4Addr>,
+    /// Write functions used
+    pub functions: BTreeSet<u8>,
+    /// Writes since last persisted
+    pub writes: u64,
+    pub first_seen: DateTime<Utc>,
+    /// Last write
+    pub last_seen: DateTime<Utc>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+/// Industrial devices and the sources writing to them
+#[derive(Default)]
+pub struct OtDevices {
+    pub devices: DashMap<OtDeviceKey, OtDevice>,
+    pub writes: DashMap<OtWriteKey, OtWrite>,
+}
+
+impl OtDevices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a Modbus, DNP3 or BACnet message
+    pub fn observe(&self, frame: &SensorFrame, now: DateTime<Utc>) {
+        let Some(ot) = frame.ot.as_ref() else {
+            return;
+        };
+
+        // Broadcast requests (BACnet Who-Is) go to no device in particular
+        let (mac, ip) = if ot.request { (frame.dst_mac, frame.dst_ip) } else { (frame.src_mac, frame.src_ip) };
+        if mac.is_multicast() {
+            return;
+        }
+
+        let key = OtDeviceKey { site: frame.site, mac, protocol: ot.protocol };
+        let mut device = self.devices.entry(key).or_insert_with(|| OtDevice {
+            ip: None,
+            units: BTreeSet::new(),
+            requests: 0,
+            responses: 0,
+            first_seen: now,
+            last_seen: now,
+            dirty: true,
+        });
+        device.ip = ip.or(device.ip);
+        device.units.extend(ot.unit);
+        if ot.request {
+            device.requests += 1;
+        } else {
+            device.responses += 1;
+        }
+        device.last_seen = device.last_seen.max(now);
+        device.dirty = true;
+
+        if ot.write {
+            let key = OtWriteKey { site: frame.site, protocol: ot.protocol, source: frame.src_mac, device: mac };
+            let mut write = self.writes.entry(key).or_insert_with(|| OtWrite {
+                source_ip: None,
+                device_ip: None,
+                functions: BTreeSet::new(),
+                writes: 0,
+                first_seen: now,
+                last_seen: now,
+                dirty: true,
+            });
+            write.source_ip = frame.src_ip.or(write.source_ip);
+            write.device_ip = ip.or(write.device_ip);
+            write.functions.insert(ot.function);
+            write.writes += 1;
+            write.last_seen = write.last_seen.max(now);
+            write.dirty = true;
+        }
+    }
+
+    /// Devices changed since the last call, with their messages since then
+    pub fn take_devices(&self) -> Vec<(OtDeviceKey, OtDevice)> {
+        let mut changed = Vec::new();
+        for mut entry in self.devices.iter_mut() {
+            if entry.dirty {
+                changed.push((*entry.key(), entry.clone()));
+                entry.requests = 0;
+                entry.responses = 0;
+                entry.dirty = false;
+            }
+        }
+        changed
+    }
+
+    /// Writes changed since the last call, with their count since then
+    pub fn take_writes(&self) -> Vec<(OtWriteKey, OtWrite)> {
+        let mut changed = Vec::new();
+        for mut entry in self.writes.iter_mut() {
+            if entry.dirty {
+                changed.push((*entry.key(), entry.clone()));
+                entry.writes = 0;
+                entry.dirty = false;
+            }
+        }
+        changed
+    }
+
+    /// Put back the counts of a device that failed to persist
+    pub fn restore_device(&self, key: OtDeviceKey, device: &OtDevice) {
+        if let Some(mut entry) = self.devices.get_mut(&key) {
+            entry.requests += device.requests;
+            entry.responses += device.responses;
+            entry.dirty = true;
+        }
+    }
+
+    /// Put back the count of writes that failed to persist
+    pub fn restore_write(&self, key: OtWriteKey, write: &OtWrite) {
+        if let Some(mut entry) = self.writes.get_mut(&key) {
+            entry.writes += write.writes;
+            entry.dirty = true;
+        }
+    }
+
+    /// Forget devices and writes with nothing left to persist, idle since
+    /// `before`
+    pub fn prune(&self, before: DateTime<Utc>) {
+        self.devices.retain(|_, device| device.last_seen >= before || device.dirty);
+        self.writes.retain(|_, write| write.last_seen >= before || write.dirty);
+    }
+
+    /// Forget a device, as an industrial device and as a source of writes
+    pub fn forget(&self, mac: MacAddr) {
+        self.devices.retain(|key, _| key.mac != mac);
+        self.writes.retain(|key, _| key.source != mac && key.device != mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::CapturedFrame;
+    use netsentinel_types::OtInfo;
+
+    fn frame(src: MacAddr, dst: MacAddr, function: u8, request: bool, write: bool) -> SensorFrame {
+        let mut frame = CapturedFrame::new("eth0", src, dst, 0x0800, 80);
+        frame.src_ip = Some(Ipv4Addr::new(10, 20, 0, src.as_bytes()[5]));
+        frame.dst_ip = Some(Ipv4Addr::new(10, 20, 0, dst.as_bytes()[5]));
+        frame.ot = Some(OtInfo { protocol: OtProtocol::Modbus, function, request, write, unit: Some(1) });
+        SensorFrame { site: SiteId::default(), sensor: Default::default(), frame }
+    }
+
+    #[test]
+    fn test_ot_devices() {
+        let devices = OtDevices::new();
+        let now = Utc::now();
+        let hmi = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x05]);
+        let plc = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x10]);
+
+        devices.observe(&frame(hmi, plc, 3, true, false), now);
+        devices.observe(&frame(plc, hmi, 3, false, false), now);
+        devices.observe(&frame(hmi, plc, 6, true, true), now);
+        devices.observe(&frame(hmi, plc, 16, true, true), now);
+        // A broadcast request
+        devices.observe(&frame(hmi, MacAddr::new([0xff; 6]), 8, true, false), now);
+
+        let changed = devices.take_devices();
+        assert_eq!(changed.len(), 1);
+        let (key, device) = &changed[0];
+        assert_eq!((key.mac, key.protocol), (plc, OtProtocol::Modbus));
+        assert_eq!((device.requests, device.responses), (3, 1));
+        assert_eq!(device.units, BTreeSet::from([1]));
+
+        let writes = devices.take_writes();
+        assert_eq!(writes.len(), 1);
+        let (key, write) = &writes[0];
+        assert_eq!((key.source, key.device), (hmi, plc));
+        assert_eq!(write.functions, BTreeSet::from([6, 16]));
+        assert_eq!((write.writes, write.source_ip), (2, Some(Ipv4Addr::new(10, 20, 0, 5))));
+        assert!(devices.take_writes().is_empty());
+
+        devices.restore_write(*key, write);
+        assert_eq!(devices.writes.get(key).unwrap().writes, 2);
+        devices.forget(hmi);
+        assert!(devices.writes.is_empty());
+        devices.prune(now + chrono::Duration::seconds(1));
+        assert!(devices.devices.is_empty());
+    }
+}
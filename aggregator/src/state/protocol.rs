@@ -2,6 +2,7 @@
 
 use std::sync::atomic::{AtomicU64, Ordering};
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 
 /// Protocol statistics
 pub struct ProtocolStats {
@@ -74,7 +75,7 @@ impl ProtocolStats {
 }
 
 /// Protocol statistics snapshot
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ProtocolSnapshot {
     pub ethertype: u16,
     pub ip_protocol: Option<u8>,
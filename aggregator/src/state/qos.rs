@@ -0,0 +1,104 @@
+//! Per-device hourly traffic by QoS class
+//!
+//! Bytes each device sends are counted per hour, VLAN and DSCP value of
+//! their IP header, so the markings actually used on each VLAN can be
+//! checked against the QoS policy without touching the network: voice
+//! sent as best effort, bulk transfers marked EF, classes a VLAN should
+//! never carry. Markings are the sender's, so only sent traffic counts.
+//!
+//! Hours are persisted whole as they fill up, like the traffic composition.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::composition::hour_of;
+use super::DeviceKey;
+
+/// Name of a DSCP value: `ef`, `af41`, `cs1`, `be`, or `dscp13` for the
+/// values without a standard class
+pub fn class_name(dscp: u8) -> String {
+    match dscp {
+        0 => "be".to_string(),
+        44 => "voice-admit".to_string(),
+        46 => "ef".to_string(),
+        // Assured forwarding: class in bits 5-3, drop precedence in bits 2-1
+        d if (1..=4).contains(&(d >> 3)) && d & 0x06 != 0 && d & 0x01 == 0 => {
+            format!("af{}{}", d >> 3, (d & 0x06) >> 1)
+        }
+        d if d & 0x07 == 0 => format!("cs{}", d >> 3),
+        d => format!("dscp{}", d),
+    }
+}
+
+/// Traffic of one VLAN and DSCP value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ClassTraffic {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Traffic a device sent during one hour, by VLAN (0 when untagged) and DSCP
+#[derive(Debug, Default)]
+pub struct HourlyQos {
+    pub classes: HashMap<(u16, u8), ClassTraffic>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+/// Hourly traffic by device and QoS class
+#[derive(Default)]
+pub struct QosAccounting {
+    /// Keyed by device and start of the hour (unix time)
+    pub hours: DashMap<(DeviceKey, i64), HourlyQos>,
+}
+
+impl QosAccounting {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `bytes` sent by `device` on `vlan_id` marked `dscp` at `now`
+    pub fn record(&self, device: DeviceKey, vlan_id: Option<u16>, dscp: u8, bytes: u64, now: DateTime<Utc>) {
+        let mut hour = self.hours.entry((device, hour_of(now))).or_default();
+        let traffic = hour.classes.entry((vlan_id.unwrap_or(0), dscp)).or_default();
+        traffic.packets += 1;
+        traffic.bytes += bytes;
+        hour.dirty = true;
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, hour), qos| *hour >= before || qos.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MacAddr, SiteId};
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_qos_classes() {
+        assert_eq!(class_name(0), "be");
+        assert_eq!(class_name(46), "ef");
+        assert_eq!(class_name(34), "af41");
+        assert_eq!(class_name(14), "af13");
+        assert_eq!(class_name(8), "cs1");
+        assert_eq!(class_name(48), "cs6");
+        assert_eq!(class_name(13), "dscp13");
+
+        let qos = QosAccounting::new();
+        let phone = DeviceKey::new(SiteId::default(), MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 10, 42, 0).unwrap();
+        qos.record(phone, Some(20), 46, 200, at);
+        qos.record(phone, Some(20), 46, 200, at);
+        qos.record(phone, None, 0, 1500, at);
+
+        let hour = qos.hours.get(&(phone, hour_of(at))).unwrap();
+        assert_eq!(hour.classes[&(20, 46)], ClassTraffic { packets: 2, bytes: 400 });
+        assert_eq!(hour.classes[&(0, 0)], ClassTraffic { packets: 1, bytes: 1500 });
+    }
+}
@@ -0,0 +1,300 @@
+//! Passive round-trip time estimation
+//!
+//! TCP handshakes are timed from the capture point: the SYN to SYN-ACK gap
+//! is the round trip to the server, the SYN-ACK to ACK gap the round trip to
+//! the client, and their sum the handshake RTT between the two devices.
+//! Sequence numbers tie the three packets together, and handshakes with a
+//! retransmitted SYN or SYN-ACK are discarded as ambiguous. The last
+//! samples of each (client, server) device pair are kept for percentiles.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{DeviceKey, MacAddr, SensorFrame, SensorId, SiteId};
+
+/// Samples kept per device pair for the rolling percentiles
+const WINDOW_SAMPLES: usize = 256;
+
+/// Handshakes tracked at most before stale ones are dropped
+const MAX_PENDING: usize = 65_536;
+
+/// Handshakes not completed within this many seconds are stale
+const HANDSHAKE_TIMEOUT_SECS: i64 = 30;
+
+/// Sensor, and client and server address and port of a connection
+type Connection = (SensorId, Ipv4Addr, u16, Ipv4Addr, u16);
+
+/// A handshake seen up to its SYN or SYN-ACK
+struct Handshake {
+    client: DeviceKey,
+    server: DeviceKey,
+    syn_at: DateTime<Utc>,
+    client_isn: u32,
+    /// SYN-ACK time and server initial sequence number
+    syn_ack: Option<(DateTime<Utc>, u32)>,
+    retransmitted: bool,
+}
+
+/// One timed handshake (microseconds)
+#[derive(Debug, Clone, Copy)]
+pub struct RttSample {
+    /// Capture point to server and back
+    pub server_us: u64,
+    /// Capture point to client and back
+    pub client_us: u64,
+}
+
+impl RttSample {
+    pub fn total_us(&self) -> u64 {
+        self.server_us + self.client_us
+    }
+}
+
+/// Handshake RTTs between a client and a server device
+pub struct PairRtt {
+    pub site: SiteId,
+    pub client_mac: MacAddr,
+    pub server_mac: MacAddr,
+    samples: VecDeque<RttSample>,
+    /// Handshakes timed since the pair was first seen
+    pub total_samples: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub dirty: AtomicBool,
+}
+
+/// Rolling RTT percentiles of a device pair (milliseconds)
+#[derive(Debug, Clone, Serialize)]
+pub struct RttSnapshot {
+    pub site: String,
+    pub client_mac: String,
+    pub server_mac: String,
+    pub total_samples: u64,
+    /// Samples the percentiles are computed over
+    pub window_samples: usize,
+    pub min_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    /// Median server-side and client-side share of the RTT
+    pub server_p50_ms: f64,
+    pub client_p50_ms: f64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Nearest-rank percentile of sorted values, in milliseconds
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1] as f64 / 1000.0
+}
+
+impl PairRtt {
+    fn new(client: DeviceKey, server: DeviceKey, now: DateTime<Utc>) -> Self {
+        Self {
+            site: client.site,
+            client_mac: client.mac,
+            server_mac: server.mac,
+            samples: VecDeque::with_capacity(WINDOW_SAMPLES),
+            total_samples: 0,
+            first_seen: now,
+            last_seen: now,
+            dirty: AtomicBool::new(true),
+        }
+    }
+
+    fn record(&mut self, sample: RttSample, at: DateTime<Utc>) {
+        if self.samples.len() == WINDOW_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.total_samples += 1;
+        self.last_seen = at;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Percentiles over the current window
+    pub fn snapshot(&self) -> RttSnapshot {
+        let sorted = |f: fn(&RttSample) -> u64| {
+            let mut values: Vec<u64> = self.samples.iter().map(f).collect();
+            values.sort_unstable();
+            values
+        };
+        let total = sorted(RttSample::total_us);
+        let server = sorted(|s| s.server_us);
+        let client = sorted(|s| s.client_us);
+
+        RttSnapshot {
+            site: self.site.to_string(),
+            client_mac: self.client_mac.to_string(),
+            server_mac: self.server_mac.to_string(),
+            total_samples: self.total_samples,
+            window_samples: total.len(),
+            min_ms: percentile(&total, 0.0),
+            p50_ms: percentile(&total, 50.0),
+            p90_ms: percentile(&total, 90.0),
+            p99_ms: percentile(&total, 99.0),
+            max_ms: percentile(&total, 100.0),
+            server_p50_ms: percentile(&server, 50.0),
+            client_p50_ms: percentile(&client, 50.0),
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        }
+    }
+}
+
+/// Times TCP handshakes and keeps RTT samples per device pair
+#[derive(Default)]
+pub struct RttTracker {
+    pending: DashMap<Connection, Handshake>,
+    /// Keyed by (client, server)
+    pub pairs: DashMap<(DeviceKey, DeviceKey), PairRtt>,
+}
+
+impl RttTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow the handshake `frame` belongs to, if any
+    pub fn observe(&self, frame: &SensorFrame, src: DeviceKey, dst: DeviceKey) {
+        let (Some(flags), Some(src_ip), Some(dst_ip), Some(src_port), Some(dst_port), Some(seq), Some(ack)) = (
+            frame.tcp_flags.as_ref(),
+            frame.src_ip,
+            frame.dst_ip,
+            frame.src_port,
+            frame.dst_port,
+            frame.tcp_seq,
+            frame.tcp_ack,
+        ) else {
+            return;
+        };
+        let at = frame.timestamp;
+        let outbound = (frame.sensor, src_ip, src_port, dst_ip, dst_port);
+        let inbound = (frame.sensor, dst_ip, dst_port, src_ip, src_port);
+
+        if flags.rst {
+            self.pending.remove(&outbound);
+            self.pending.remove(&inbound);
+        } else if flags.syn && !flags.ack {
+            if self.pending.len() >= MAX_PENDING {
+                let stale = at - chrono::Duration::seconds(HANDSHAKE_TIMEOUT_SECS);
+                self.pending.retain(|_, h| h.syn_at >= stale);
+            }
+            self.pending.entry(outbound)
+                .and_modify(|h| h.retransmitted = true)
+                .or_insert(Handshake {
+                    client: src,
+                    server: dst,
+                    syn_at: at,
+                    client_isn: seq,
+                    syn_ack: None,
+                    retransmitted: false,
+                });
+        } else if flags.syn {
+            if let Some(mut handshake) = self.pending.get_mut(&inbound) {
+                if ack == handshake.client_isn.wrapping_add(1) {
+                    if handshake.syn_ack.is_some() {
+                        handshake.retransmitted = true;
+                    } else {
+                        handshake.syn_ack = Some((at, seq));
+                    }
+                }
+            }
+        } else if flags.ack {
+            let completes = self.pending.get(&outbound).is_some_and(|h| {
+                h.syn_ack.is_some_and(|(_, server_isn)| ack == server_isn.wrapping_add(1))
+            });
+            if !completes {
+                return;
+            }
+            let Some((_, handshake)) = self.pending.remove(&outbound) else { return };
+            let Some((syn_ack_at, _)) = handshake.syn_ack else { return };
+            if handshake.retransmitted {
+                return;
+            }
+
+            let micros = |d: chrono::Duration| d.num_microseconds().unwrap_or(0).max(0) as u64;
+            let sample = RttSample {
+                server_us: micros(syn_ack_at - handshake.syn_at),
+                client_us: micros(at - syn_ack_at),
+            };
+            self.pairs.entry((handshake.client, handshake.server))
+                .or_insert_with(|| PairRtt::new(handshake.client, handshake.server, at))
+                .record(sample, at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TcpFlags;
+
+    const CLIENT: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const SERVER: [u8; 6] = [0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+    /// Handshake packet at `ms` milliseconds, from the client unless `reply`
+    fn packet(ms: i64, reply: bool, syn: bool, ack: bool, seq: u32, ack_no: u32) -> SensorFrame {
+        let (src, dst) = if reply { (SERVER, CLIENT) } else { (CLIENT, SERVER) };
+        let (src_ip, dst_ip) = if reply { ([10, 0, 1, 80], [10, 0, 0, 5]) } else { ([10, 0, 0, 5], [10, 0, 1, 80]) };
+        let (src_port, dst_port) = if reply { (443, 50000) } else { (50000, 443) };
+        serde_json::from_value(serde_json::json!({
+            "timestamp": DateTime::from_timestamp_millis(1_700_000_000_000 + ms).unwrap(),
+            "interface": "eth0",
+            "src_mac": MacAddr::new(src).to_string(),
+            "dst_mac": MacAddr::new(dst).to_string(),
+            "ethertype": 0x0800,
+            "src_ip": Ipv4Addr::from(src_ip),
+            "dst_ip": Ipv4Addr::from(dst_ip),
+            "ip_protocol": 6,
+            "src_port": src_port,
+            "dst_port": dst_port,
+            "tcp_flags": TcpFlags { syn, ack, ..Default::default() },
+            "tcp_seq": seq,
+            "tcp_ack": ack_no,
+            "frame_size": 64,
+            "payload_size": 0,
+        })).unwrap()
+    }
+
+    fn observe(tracker: &RttTracker, frame: SensorFrame) {
+        tracker.observe(&frame, DeviceKey::new(frame.site, frame.src_mac), DeviceKey::new(frame.site, frame.dst_mac));
+    }
+
+    #[test]
+    fn test_handshake_rtt() {
+        let tracker = RttTracker::new();
+        for i in 0..10u32 {
+            let start = i as i64 * 1000;
+            let isn = 1000 * i;
+            observe(&tracker, packet(start, false, true, false, isn, 0));
+            observe(&tracker, packet(start + 20 + i as i64, true, true, true, 5000, isn + 1));
+            observe(&tracker, packet(start + 22 + i as i64, false, false, true, isn + 1, 5001));
+        }
+
+        // Retransmitted SYN: ambiguous, not sampled
+        observe(&tracker, packet(20_000, false, true, false, 7, 0));
+        observe(&tracker, packet(21_000, false, true, false, 7, 0));
+        observe(&tracker, packet(21_010, true, true, true, 9, 8));
+        observe(&tracker, packet(21_012, false, false, true, 8, 10));
+
+        let site = SiteId::default();
+        let pair = tracker.pairs.get(&(DeviceKey::new(site, MacAddr::new(CLIENT)), DeviceKey::new(site, MacAddr::new(SERVER)))).unwrap();
+        let snapshot = pair.snapshot();
+        assert_eq!(snapshot.total_samples, 10);
+        assert_eq!(snapshot.min_ms, 22.0);
+        assert_eq!(snapshot.p50_ms, 26.0);
+        assert_eq!(snapshot.max_ms, 31.0);
+        assert_eq!(snapshot.client_p50_ms, 2.0);
+        assert!(tracker.pending.is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+//! VoIP calls
+//!
+//! Calls are followed from their SIP signaling: an INVITE starts a call
+//! between its sender and receiver, a final response answers or rejects
+//! it, a BYE or CANCEL ends it. The SDP offer and answer announce where
+//! each side receives the call's RTP stream; datagrams sent to those
+//! endpoints are counted as the call's media, giving its bandwidth.
+//!
+//! Devices sending a REGISTER are phones, the ones they register with are
+//! PBXs (see `AggregatorState::process_frame`).
+//!
+//! RTP packets and bytes are counted between persist cycles.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::net::Ipv4Addr;
+
+use netsentinel_types::SipMedia;
+
+use super::{DeviceKey, MacAddr, SensorFrame, SiteId};
+
+/// IP protocol number of UDP, carrying RTP
+pub const UDP: u8 = 17;
+
+/// `type` tag of devices registering with a PBX, and of the PBX
+pub const PHONE_TYPE: &str = "voip-phone";
+pub const PBX_TYPE: &str = "voip-pbx";
+
+/// Tag of the SIP user agent a device announces
+pub const USER_AGENT_TAG: &str = "sip_user_agent";
+
+/// RTP endpoints kept per call: an offer and an answer, re-INVITEs moving
+/// the media
+const MAX_MEDIA: usize = 4;
+
+/// A call, by its SIP Call-ID
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallKey {
+    pub site: SiteId,
+    pub call_id: String,
+}
+
+/// Progress of a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallState {
+    /// Invited, not answered yet
+    Ringing,
+    Answered,
+    /// Hung up after being answered
+    Ended,
+    /// Rejected, cancelled or unanswered
+    Failed,
+}
+
+impl CallState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ringing => "ringing",
+            Self::Answered => "answered",
+            Self::Ended => "ended",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// What is known of a call
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// Sender of the INVITE, and where it went
+    pub caller: MacAddr,
+    pub caller_ip: Option<Ipv4Addr>,
+    pub callee: MacAddr,
+    pub callee_ip: Option<Ipv4Addr>,
+    /// From and To URIs of the INVITE
+    pub from: String,
+    pub to: String,
+    pub state: CallState,
+    pub started: DateTime<Utc>,
+    pub answered: Option<DateTime<Utc>>,
+    pub ended: Option<DateTime<Utc>>,
+    /// RTP endpoints announced in the SDP offer and answer
+    pub media: Vec<SipMedia>,
+    /// RTP datagrams and bytes since last persisted
+    pub rtp_packets: u64,
+    pub rtp_bytes: u64,
+    pub last_seen: DateTime<Utc>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+impl Call {
+    pub fn caller(&self, site: SiteId) -> DeviceKey {
+        DeviceKey::new(site, self.caller)
+    }
+
+    pub fn callee(&self, site: SiteId) -> DeviceKey {
+        DeviceKey::new(site, self.callee)
+    }
+}
+
+/// Calls and the RTP endpoints of their media
+#[derive(Default)]
+pub struct Telephony {
+    pub calls: DashMap<CallKey, Call>,
+    /// Call of each RTP endpoint announced
+    pub media: DashMap<(SiteId, SipMedia), CallKey>,
+}
+
+impl Telephony {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a SIP request or response
+    pub fn observe(&self, frame: &SensorFrame, now: DateTime<Utc>) {
+        let Some(sip) = frame.sip.as_ref() else {
+            return;
+        };
+        let key = CallKey { site: frame.site, call_id: sip.call_id.clone() };
+        let mut call = match (sip.method.as_str(), sip.status) {
+            // Calls are between two devices
+            ("INVITE", None) if frame.dst_mac.is_multicast() => return,
+            ("INVITE", None) => self.calls.entry(key.clone()).or_insert_with(|| Call {
+                caller: frame.src_mac,
+                caller_ip: frame.src_ip,
+                callee: frame.dst_mac,
+                callee_ip: frame.dst_ip,
+                from: sip.from.clone(),
+                to: sip.to.clone(),
+                state: CallState::Ringing,
+                started: now,
+                answered: None,
+                ended: None,
+                media: Vec::new(),
+                rtp_packets: 0,
+                rtp_bytes: 0,
+                last_seen: now,
+                dirty: true,
+            }),
+            ("INVITE" | "BYE" | "CANCEL", _) => match self.calls.get_mut(&key) {
+                Some(call) => call,
+                None => return,
+            },
+            // Registrations, presence and the like are no call
+            _ => return,
+        };
+
+        match (sip.method.as_str(), sip.status) {
+            ("INVITE", Some(200..=299)) if call.state == CallState::Ringing => {
+                call.state = CallState::Answered;
+                call.answered = Some(now);
+            }
+            ("INVITE", Some(300..)) if call.state == CallState::Ringing => {
+                call.state = CallState::Failed;
+                call.ended = Some(now);
+            }
+            ("BYE" | "CANCEL", None) if call.ended.is_none() => {
+                call.state = if call.state == CallState::Answered { CallState::Ended } else { CallState::Failed };
+                call.ended = Some(now);
+            }
+            _ => {}
+        }
+
+        if let Some(media) = sip.media {
+            if !call.media.contains(&media) && call.media.len() < MAX_MEDIA {
+                call.media.push(media);
+            }
+            self.media.insert((frame.site, media), key);
+        }
+        call.last_seen = call.last_seen.max(now);
+        call.dirty = true;
+    }
+
+    /// Count a UDP datagram sent to the RTP endpoint of a call
+    pub fn record_rtp(&self, frame: &SensorFrame, now: DateTime<Utc>) {
+        if self.media.is_empty() {
+            return;
+        }
+        let (Some(ip), Some(port)) = (frame.dst_ip, frame.dst_port) else {
+            return;
+        };
+        let Some(key) = self.media.get(&(frame.site, SipMedia { ip, port })).map(|key| key.clone()) else {
+            return;
+        };
+        if let Some(mut call) = self.calls.get_mut(&key) {
+            call.rtp_packets += 1;
+            call.rtp_bytes += frame.frame_size as u64;
+            call.last_seen = call.last_seen.max(now);
+            call.dirty = true;
+        }
+    }
+
+    /// Calls changed since the last call, with their media since then
+    pub fn take_calls(&self) -> Vec<(CallKey, Call)> {
+        let mut changed = Vec::new();
+        for mut entry in self.calls.iter_mut() {
+            if entry.dirty {
+                changed.push((entry.key().clone(), entry.clone()));
+                entry.rtp_packets = 0;
+                entry.rtp_bytes = 0;
+                entry.dirty = false;
+            }
+        }
+        changed
+    }
+
+    /// Put back the media counts of a call that failed to persist
+    pub fn restore(&self, key: &CallKey, call: &Call) {
+        if let Some(mut entry) = self.calls.get_mut(key) {
+            entry.rtp_packets += call.rtp_packets;
+            entry.rtp_bytes += call.rtp_bytes;
+            entry.dirty = true;
+        }
+    }
+
+    /// Forget calls with nothing left to persist, idle since `before`, and
+    /// their RTP endpoints
+    pub fn prune(&self, before: DateTime<Utc>) {
+        self.calls.retain(|_, call| call.last_seen >= before || call.dirty);
+        self.media.retain(|_, key| self.calls.contains_key(key));
+    }
+
+    /// Forget the calls of a device
+    pub fn forget(&self, mac: MacAddr) {
+        self.calls.retain(|_, call| call.caller != mac && call.callee != mac);
+        self.media.retain(|_, key| self.calls.contains_key(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::CapturedFrame;
+    use netsentinel_types::SipInfo;
+
+    fn frame(src: MacAddr, dst: MacAddr, sip: Option<SipInfo>) -> SensorFrame {
+        let mut frame = CapturedFrame::new("eth0", src, dst, 0x0800, 214);
+        frame.src_ip = Some(Ipv4Addr::new(10, 1, 0, src.as_bytes()[5]));
+        frame.dst_ip = Some(Ipv4Addr::new(10, 1, 0, dst.as_bytes()[5]));
+        frame.ip_protocol = Some(UDP);
+        frame.dst_port = Some(if sip.is_some() { 5060 } else { 49170 });
+        frame.sip = sip;
+        SensorFrame { site: SiteId::default(), sensor: Default::default(), frame }
+    }
+
+    fn sip(method: &str, status: Option<u16>, media: Option<SipMedia>) -> SipInfo {
+        SipInfo {
+            method: method.to_string(),
+            status,
+            call_id: "a84b4c76e66710".to_string(),
+            from: "sip:1001@pbx".to_string(),
+            to: "sip:1002@pbx".to_string(),
+            user_agent: None,
+            media,
+        }
+    }
+
+    #[test]
+    fn test_call_media() {
+        let telephony = Telephony::new();
+        let now = Utc::now();
+        let phone = MacAddr::new([0x80, 0x5e, 0xc0, 0x00, 0x00, 0x21]);
+        let pbx = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        let offer = SipMedia { ip: Ipv4Addr::new(10, 1, 0, 0x21), port: 49170 };
+        let answer = SipMedia { ip: Ipv4Addr::new(10, 1, 0, 0x01), port: 49170 };
+
+        telephony.observe(&frame(phone, pbx, Some(sip("INVITE", None, Some(offer)))), now);
+        telephony.observe(&frame(pbx, phone, Some(sip("INVITE", Some(180), None))), now);
+        telephony.observe(&frame(pbx, phone, Some(sip("INVITE", Some(200), Some(answer)))), now);
+        // RTP both ways, and a datagram to no call
+        for _ in 0..3 {
+            telephony.record_rtp(&frame(phone, pbx, None), now);
+            telephony.record_rtp(&frame(pbx, phone, None), now);
+        }
+        telephony.record_rtp(&frame(phone, MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x99]), None), now);
+        // Presence is no call
+        telephony.observe(&frame(phone, pbx, Some(SipInfo { call_id: "other".to_string(), ..sip("SUBSCRIBE", None, None) })), now);
+
+        let calls = telephony.take_calls();
+        assert_eq!(calls.len(), 1);
+        let (key, call) = &calls[0];
+        assert_eq!((call.caller, call.callee, call.state), (phone, pbx, CallState::Answered));
+        assert_eq!(call.media, vec![offer, answer]);
+        assert_eq!((call.rtp_packets, call.rtp_bytes), (6, 6 * 214));
+
+        telephony.observe(&frame(phone, pbx, Some(sip("BYE", None, None))), now);
+        let (_, call) = &telephony.take_calls()[0];
+        assert_eq!((call.state, call.rtp_packets), (CallState::Ended, 0));
+
+        telephony.restore(key, &calls[0].1);
+        assert_eq!(telephony.calls.get(key).unwrap().rtp_packets, 6);
+        telephony.forget(phone);
+        assert!(telephony.calls.is_empty() && telephony.media.is_empty());
+    }
+
+    #[test]
+    fn test_classify_voip() {
+        let state = crate::state::AggregatorState::new();
+        let phone = MacAddr::new([0x80, 0x5e, 0xc0, 0x00, 0x00, 0x21]);
+        let pbx = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x01]);
+        let register = SipInfo { user_agent: Some("Yealink SIP-T46U".to_string()), ..sip("REGISTER", None, None) };
+        state.process_frame_at(&frame(phone, pbx, Some(register)), Utc::now());
+        state.process_frame_at(&frame(pbx, phone, Some(sip("REGISTER", Some(200), None))), Utc::now());
+
+        let tag = |mac, name: &str| {
+            let device = state.devices.get(&DeviceKey::new(SiteId::default(), mac)).unwrap();
+            device.tags.get(name).map(|value| value.clone())
+        };
+        assert_eq!(tag(phone, "type").as_deref(), Some(PHONE_TYPE));
+        assert_eq!(tag(phone, USER_AGENT_TAG).as_deref(), Some("Yealink SIP-T46U"));
+        assert_eq!(tag(pbx, "type").as_deref(), Some(PBX_TYPE));
+        assert!(state.telephony.calls.is_empty());
+    }
+}
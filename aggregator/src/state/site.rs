@@ -0,0 +1,213 @@
+//! Sites and sensors
+//!
+//! Each capture instance (sensor) stamps its frames with its own ID and the
+//! site it watches. Devices are identified by site and MAC address, so one
+//! aggregator can serve several sites without merging devices that happen to
+//! share a MAC address (virtual router MACs, cloned VMs); flows are kept per
+//! sensor.
+//!
+//! Sites belong to tenants, the customers of an aggregator serving several:
+//! everything kept for a site is its tenant's. Sites no tenant owns belong
+//! to the `default` tenant.
+//!
+//! Names are interned: they are few, come from sensor configurations, and
+//! interning keeps the keys built for every frame `Copy`. At most `MAX_NAMES`
+//! distinct names are accepted; frames naming further sites or sensors are
+//! rejected.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+use super::MacAddr;
+
+/// Distinct site and sensor names accepted
+const MAX_NAMES: usize = 4096;
+
+/// Site of frames from sensors that do not name one
+pub const DEFAULT_SITE: &str = "default";
+
+/// Tenant of the sites no tenant owns
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Longest site or sensor name (stored as VARCHAR(64))
+const MAX_NAME_LEN: usize = 64;
+
+fn names() -> &'static RwLock<HashSet<&'static str>> {
+    static NAMES: OnceLock<RwLock<HashSet<&'static str>>> = OnceLock::new();
+    NAMES.get_or_init(Default::default)
+}
+
+/// The interned copy of `name`, `None` once `MAX_NAMES` names are interned
+fn intern(name: &str) -> Option<&'static str> {
+    if let Some(interned) = names().read().unwrap_or_else(|e| e.into_inner()).get(name) {
+        return Some(interned);
+    }
+    let mut names = names().write().unwrap_or_else(|e| e.into_inner());
+    if let Some(interned) = names.get(name) {
+        return Some(interned);
+    }
+    if names.len() >= MAX_NAMES {
+        return None;
+    }
+    let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+    names.insert(interned);
+    Some(interned)
+}
+
+macro_rules! interned_name {
+    ($(#[$meta:meta])* $name:ident, $default:expr, $what:literal) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(&'static str);
+
+        impl $name {
+            /// Intern `name`, `None` if it is empty, too long or one name too many
+            pub fn new(name: &str) -> Option<Self> {
+                if name.is_empty() || name.len() > MAX_NAME_LEN {
+                    return None;
+                }
+                intern(name).map(Self)
+            }
+
+            pub fn as_str(&self) -> &'static str {
+                self.0
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self($default)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let name = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+                Self::new(&name).ok_or_else(|| {
+                    serde::de::Error::custom(format!("Invalid or one too many {} name '{}'", $what, name))
+                })
+            }
+        }
+    };
+}
+
+interned_name!(
+    /// Site a sensor watches
+    SiteId, DEFAULT_SITE, "site"
+);
+
+interned_name!(
+    /// Capture instance that saw a frame
+    SensorId, "unknown", "sensor"
+);
+
+interned_name!(
+    /// Customer owning sites
+    TenantId, DEFAULT_TENANT, "tenant"
+);
+
+/// Tenant of each site
+#[derive(Debug, Clone, Default)]
+pub struct Tenants {
+    sites: HashMap<SiteId, TenantId>,
+}
+
+impl Tenants {
+    /// Tenants of `(tenant, sites)` pairs; names too long are left out
+    pub fn new<'a>(tenants: impl IntoIterator<Item = (&'a str, &'a [String])>) -> Self {
+        let mut sites = HashMap::new();
+        for (name, names) in tenants {
+            let Some(tenant) = TenantId::new(name) else { continue };
+            sites.extend(names.iter().filter_map(|site| SiteId::new(site)).map(|site| (site, tenant)));
+        }
+        Self { sites }
+    }
+
+    /// Whether any site belongs to a tenant other than the default
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// Tenant owning `site`
+    pub fn tenant_of(&self, site: SiteId) -> TenantId {
+        self.sites.get(&site).copied().unwrap_or_default()
+    }
+
+    /// Tenant owning the site named `site`
+    pub fn tenant_of_name(&self, site: &str) -> TenantId {
+        SiteId::new(site).map(|site| self.tenant_of(site)).unwrap_or_default()
+    }
+
+    /// Sites owned by a tenant other than the default, with their tenant
+    pub fn sites(&self) -> impl Iterator<Item = (SiteId, TenantId)> + '_ {
+        self.sites.iter().map(|(site, tenant)| (*site, *tenant))
+    }
+}
+
+/// A device: a MAC address on a site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceKey {
+    pub site: SiteId,
+    pub mac: MacAddr,
+}
+
+impl DeviceKey {
+    pub fn new(site: SiteId, mac: MacAddr) -> Self {
+        Self { site, mac }
+    }
+}
+
+impl fmt::Display for DeviceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.mac, self.site)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interned_names() {
+        let paris = SiteId::new("paris").unwrap();
+        assert_eq!(paris, SiteId::new(&String::from("paris")).unwrap());
+        assert!(std::ptr::eq(paris.as_str(), SiteId::new("paris").unwrap().as_str()));
+        assert_ne!(paris, SiteId::default());
+        assert!(SiteId::new("").is_none());
+        assert!(SensorId::new(&"x".repeat(65)).is_none());
+
+        let site: SiteId = serde_json::from_str("\"lyon\"").unwrap();
+        assert_eq!(serde_json::to_string(&site).unwrap(), "\"lyon\"");
+        assert!(serde_json::from_str::<SiteId>("\"\"").is_err());
+
+        let key = DeviceKey::new(paris, MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+        assert_eq!(key.to_string(), "00:11:22:33:44:55@paris");
+
+        let acme = ["paris".to_string(), "lyon".to_string()];
+        let tenants = Tenants::new([("acme", &acme[..])]);
+        assert_eq!(tenants.tenant_of(paris).as_str(), "acme");
+        assert_eq!(tenants.tenant_of_name("lyon"), TenantId::new("acme").unwrap());
+        assert_eq!(tenants.tenant_of(SiteId::new("nantes").unwrap()), TenantId::default());
+        assert_eq!(TenantId::default().as_str(), DEFAULT_TENANT);
+    }
+}
@@ -0,0 +1,211 @@
+//! Inter-subnet traffic matrix
+//!
+//! Routers forward the traffic of other subnets under their own MAC
+//! address, so a device sourcing addresses from several subnets is taken
+//! for a gateway (and flagged as one). Frames handed to a gateway are
+//! routed traffic: their bytes are counted per hour and (source subnet,
+//! destination subnet) pair, a passive view of the bandwidth between
+//! segments. Each routed packet counts once, on the frame bringing it to
+//! the gateway; the frame the gateway sends on carries the same packet.
+//!
+//! Subnets are `prefix` bits long, like the VLAN subnets. Addresses outside
+//! the private ranges all belong to `0.0.0.0/0`, the outside world.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+
+use super::composition::hour_of;
+use super::vlan_subnet::{is_host_address, network};
+use super::{DeviceKey, SiteId};
+
+/// Subnets a device sources addresses from to be taken for a gateway
+pub const GATEWAY_SUBNETS: usize = 2;
+
+/// Network of the addresses outside the private ranges
+pub const EXTERNAL: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
+
+/// Traffic from one subnet to another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PairTraffic {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Routed traffic of a site during one hour, by source and destination
+/// network
+#[derive(Debug, Default)]
+pub struct HourlyMatrix {
+    pub pairs: HashMap<(Ipv4Addr, Ipv4Addr), PairTraffic>,
+    /// Changed since last persisted
+    pub dirty: bool,
+}
+
+/// Traffic from one subnet to another through the site's gateways
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubnetTraffic {
+    pub site: String,
+    /// `10.0.20.0/24`, or `0.0.0.0/0` outside the private ranges
+    pub src_subnet: String,
+    pub dst_subnet: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Gateways and hourly inter-subnet traffic per site
+pub struct SubnetMatrix {
+    prefix: u8,
+    /// Networks each device sourced addresses from, up to `GATEWAY_SUBNETS`
+    pub sources: DashMap<DeviceKey, Vec<Ipv4Addr>>,
+    /// Keyed by site and start of the hour (unix time)
+    pub hours: DashMap<(SiteId, i64), HourlyMatrix>,
+}
+
+impl Default for SubnetMatrix {
+    fn default() -> Self {
+        Self::new(24)
+    }
+}
+
+impl SubnetMatrix {
+    /// Matrix of subnets `prefix` bits long
+    pub fn new(prefix: u8) -> Self {
+        Self {
+            prefix,
+            sources: DashMap::new(),
+            hours: DashMap::new(),
+        }
+    }
+
+    /// Network of `ip`
+    fn subnet(&self, ip: Ipv4Addr) -> Ipv4Addr {
+        if ip.is_private() { network(ip, self.prefix) } else { EXTERNAL }
+    }
+
+    /// `10.0.20.0/24` notation of a network
+    fn cidr(&self, net: Ipv4Addr) -> String {
+        if net == EXTERNAL { "0.0.0.0/0".to_string() } else { format!("{}/{}", net, self.prefix) }
+    }
+
+    /// Record `device` sourcing `ip`, returning whether this made it a gateway
+    pub fn observe_source(&self, device: DeviceKey, ip: Ipv4Addr) -> bool {
+        if !is_host_address(ip) {
+            return false;
+        }
+        let net = self.subnet(ip);
+        if self.sources.get(&device).is_some_and(|nets| nets.len() >= GATEWAY_SUBNETS || nets.contains(&net)) {
+            return false;
+        }
+        let mut nets = self.sources.entry(device).or_default();
+        if nets.len() >= GATEWAY_SUBNETS || nets.contains(&net) {
+            return false;
+        }
+        nets.push(net);
+        nets.len() == GATEWAY_SUBNETS
+    }
+
+    /// Whether `device` forwards the traffic of several subnets
+    pub fn is_gateway(&self, device: &DeviceKey) -> bool {
+        self.sources.get(device).is_some_and(|nets| nets.len() >= GATEWAY_SUBNETS)
+    }
+
+    /// Count a frame from `src_ip` to `dst_ip` handed to `gateway`
+    pub fn record(&self, gateway: DeviceKey, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, bytes: u64, now: DateTime<Utc>) {
+        if !is_host_address(src_ip) || !is_host_address(dst_ip) || !self.is_gateway(&gateway) {
+            return;
+        }
+        let (src, dst) = (self.subnet(src_ip), self.subnet(dst_ip));
+        if src == dst {
+            return;
+        }
+
+        let mut hour = self.hours.entry((gateway.site, hour_of(now))).or_default();
+        let traffic = hour.pairs.entry((src, dst)).or_default();
+        traffic.packets += 1;
+        traffic.bytes += bytes;
+        hour.dirty = true;
+    }
+
+    /// Subnet pairs of one hour of a site
+    pub fn pairs(&self, site: SiteId, matrix: &HourlyMatrix) -> Vec<SubnetTraffic> {
+        matrix.pairs.iter()
+            .map(|((src, dst), traffic)| SubnetTraffic {
+                site: site.to_string(),
+                src_subnet: self.cidr(*src),
+                dst_subnet: self.cidr(*dst),
+                packets: traffic.packets,
+                bytes: traffic.bytes,
+            })
+            .collect()
+    }
+
+    /// Traffic of each subnet pair in the hours starting from `since` (unix
+    /// time), the heaviest first
+    pub fn totals(&self, since: i64) -> Vec<SubnetTraffic> {
+        let mut totals: BTreeMap<(SiteId, Ipv4Addr, Ipv4Addr), PairTraffic> = BTreeMap::new();
+        for entry in self.hours.iter().filter(|entry| entry.key().1 >= since) {
+            let site = entry.key().0;
+            for (&(src, dst), traffic) in &entry.pairs {
+                let total = totals.entry((site, src, dst)).or_default();
+                total.packets += traffic.packets;
+                total.bytes += traffic.bytes;
+            }
+        }
+
+        let mut pairs: Vec<SubnetTraffic> = totals.into_iter()
+            .map(|((site, src, dst), traffic)| SubnetTraffic {
+                site: site.to_string(),
+                src_subnet: self.cidr(src),
+                dst_subnet: self.cidr(dst),
+                packets: traffic.packets,
+                bytes: traffic.bytes,
+            })
+            .collect();
+        pairs.sort_by_key(|pair| std::cmp::Reverse(pair.bytes));
+        pairs
+    }
+
+    /// Forget persisted hours that started before `before` (unix time)
+    pub fn prune(&self, before: i64) {
+        self.hours.retain(|(_, hour), matrix| *hour >= before || matrix.dirty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::MacAddr;
+
+    #[test]
+    fn test_subnet_matrix() {
+        let matrix = SubnetMatrix::new(24);
+        let site = SiteId::default();
+        let host = DeviceKey::new(site, MacAddr::new([0x00, 0x11, 0x22, 0, 0, 1]));
+        let router = DeviceKey::new(site, MacAddr::new([0x00, 0x11, 0x22, 0, 0, 0xfe]));
+        let now = Utc::now();
+        let (a, b) = (Ipv4Addr::new(10, 0, 10, 5), Ipv4Addr::new(10, 0, 20, 7));
+
+        // The router forwards 10.0.20.0/24 onto 10.0.10.0/24
+        assert!(!matrix.observe_source(router, Ipv4Addr::new(10, 0, 10, 1)));
+        assert!(!matrix.observe_source(host, a));
+        assert!(!matrix.observe_source(host, Ipv4Addr::UNSPECIFIED));
+        assert!(matrix.observe_source(router, b));
+        assert!(!matrix.observe_source(router, Ipv4Addr::new(10, 0, 30, 9)));
+        assert!(matrix.is_gateway(&router) && !matrix.is_gateway(&host));
+
+        matrix.record(router, a, b, 1000, now);
+        matrix.record(router, a, Ipv4Addr::new(10, 0, 20, 8), 500, now);
+        matrix.record(router, a, Ipv4Addr::new(8, 8, 8, 8), 100, now);
+        // Not routed: to a host, or within the subnet
+        matrix.record(host, b, a, 1000, now);
+        matrix.record(router, a, Ipv4Addr::new(10, 0, 10, 1), 1000, now);
+
+        let totals = matrix.totals(hour_of(now));
+        assert_eq!(totals.len(), 2);
+        assert_eq!((totals[0].src_subnet.as_str(), totals[0].dst_subnet.as_str()), ("10.0.10.0/24", "10.0.20.0/24"));
+        assert_eq!((totals[0].packets, totals[0].bytes), (2, 1500));
+        assert_eq!(totals[1].dst_subnet, "0.0.0.0/0");
+    }
+}
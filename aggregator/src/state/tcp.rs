@@ -0,0 +1,134 @@
+//! TCP health indicators
+//!
+//! Retransmissions are spotted from sequence numbers alone: a data segment
+//! that ends at or before the highest sequence number already seen in its
+//! direction covers a range that was sent before. Seen from a single capture
+//! point this also counts segments the capture missed the first copy of, so
+//! the rate is an indicator of a lossy path rather than an exact count.
+//! Resets are counted per flow and device to spot refused or aborted
+//! connections.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Set in `SequenceTracker::end` once a data segment has been seen
+const SEEN: u64 = 1 << 32;
+
+/// TCP health counters of a flow or of the segments a device sent
+#[derive(Debug, Default)]
+pub struct TcpHealth {
+    /// Segments carrying data
+    pub data_segments: AtomicU64,
+    /// Data segments repeating an already seen sequence range
+    pub retransmits: AtomicU64,
+    /// Segments with RST set
+    pub resets: AtomicU64,
+}
+
+/// TCP health counters at a point in time
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TcpHealthSnapshot {
+    pub data_segments: u64,
+    pub retransmits: u64,
+    pub resets: u64,
+}
+
+impl TcpHealth {
+    /// Count one segment
+    pub fn record(&self, data: bool, retransmit: bool, reset: bool) {
+        if data {
+            self.data_segments.fetch_add(1, Ordering::Relaxed);
+        }
+        if retransmit {
+            self.retransmits.fetch_add(1, Ordering::Relaxed);
+        }
+        if reset {
+            self.resets.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> TcpHealthSnapshot {
+        TcpHealthSnapshot {
+            data_segments: self.data_segments.load(Ordering::Relaxed),
+            retransmits: self.retransmits.load(Ordering::Relaxed),
+            resets: self.resets.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl TcpHealthSnapshot {
+    /// Share of data segments that were retransmitted
+    pub fn retransmit_rate(&self) -> f64 {
+        if self.data_segments == 0 {
+            return 0.0;
+        }
+        self.retransmits as f64 / self.data_segments as f64
+    }
+}
+
+/// Highest sequence number sent in one direction of a flow
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    /// End of the highest data segment, with `SEEN` set once there is one
+    end: AtomicU64,
+}
+
+impl SequenceTracker {
+    /// Record a data segment, returning whether it is a retransmission
+    ///
+    /// Keep-alives (one byte just below the highest end) are not counted.
+    pub fn observe(&self, seq: u32, len: u32) -> bool {
+        if len == 0 {
+            return false;
+        }
+        let end = seq.wrapping_add(len);
+        let mut retransmit = false;
+
+        let _ = self.end.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            retransmit = false;
+            if current & SEEN == 0 {
+                return Some(SEEN | end as u64);
+            }
+            let highest = current as u32;
+            // Sequence numbers wrap: compare the signed distance
+            if (end.wrapping_sub(highest) as i32) > 0 {
+                return Some(SEEN | end as u64);
+            }
+            retransmit = !(len == 1 && end == highest);
+            None
+        });
+
+        retransmit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retransmissions() {
+        let tracker = SequenceTracker::default();
+        assert!(!tracker.observe(1000, 100));
+        assert!(!tracker.observe(1100, 100));
+        // Same range again, then a partial overlap
+        assert!(tracker.observe(1100, 100));
+        assert!(tracker.observe(1000, 150));
+        // Pure ACKs and keep-alives
+        assert!(!tracker.observe(1200, 0));
+        assert!(!tracker.observe(1199, 1));
+        // Across the sequence number wrap
+        let tracker = SequenceTracker::default();
+        assert!(!tracker.observe(u32::MAX - 99, 100));
+        assert!(!tracker.observe(0, 100));
+        assert!(tracker.observe(u32::MAX - 99, 100));
+
+        let health = TcpHealth::default();
+        health.record(true, false, false);
+        health.record(true, true, false);
+        health.record(false, false, true);
+        let snapshot = health.snapshot();
+        assert_eq!(snapshot.resets, 1);
+        assert_eq!(snapshot.retransmit_rate(), 0.5);
+    }
+}
@@ -0,0 +1,278 @@
+//! TLS server inventory
+//!
+//! Capture reports the server name of each ClientHello and the leaf
+//! certificate of each Certificate message it sees whole. Both are kept per
+//! client device and destination, so the SaaS a device talks to and the
+//! certificates those servers present (and when they expire) can be listed.
+//! A certificate is tied to the server name of its connection by matching
+//! the ClientHello that opened it; TLS 1.3 servers encrypt their
+//! certificate, so their destinations only carry a server name.
+//!
+//! JA3 (ClientHello) and JA3S (ServerHello) fingerprints are counted per
+//! device. Both are kept against the client of the connection: its JA3s
+//! identify the TLS stacks it runs, its JA3Ss the servers answering them.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{DeviceKey, MacAddr, SensorFrame, SensorId, SiteId};
+
+/// ClientHellos awaiting a certificate at most before stale ones are dropped
+const MAX_PENDING: usize = 65_536;
+
+/// ClientHellos not answered within this many seconds are stale
+const HELLO_TIMEOUT_SECS: i64 = 30;
+
+/// Sensor, and client and server address and port of a connection
+type Connection = (SensorId, Ipv4Addr, u16, Ipv4Addr, u16);
+
+pub use netsentinel_types::{CertificateInfo, TlsInfo};
+
+/// Which hello a fingerprint was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FingerprintKind {
+    Ja3,
+    Ja3s,
+}
+
+impl FingerprintKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FingerprintKind::Ja3 => "ja3",
+            FingerprintKind::Ja3s => "ja3s",
+        }
+    }
+}
+
+/// Sightings of one fingerprint on a device
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    /// Handshakes since the fingerprint was first seen
+    pub count: u64,
+    /// Handshakes not written to the database yet
+    pub unpersisted: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A client device's TLS destination
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TlsKey {
+    pub site: SiteId,
+    pub client_mac: MacAddr,
+    pub server_ip: Ipv4Addr,
+    pub server_port: u16,
+    /// Server name, unknown when the ClientHello was not seen
+    pub sni: Option<String>,
+}
+
+/// Handshakes seen towards one destination
+pub struct TlsObservation {
+    /// Last certificate the server presented
+    pub certificate: Option<CertificateInfo>,
+    pub handshakes: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub dirty: AtomicBool,
+}
+
+impl TlsObservation {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            certificate: None,
+            handshakes: 0,
+            first_seen: now,
+            last_seen: now,
+            dirty: AtomicBool::new(true),
+        }
+    }
+}
+
+/// ClientHello of a connection, waiting for the server certificate
+struct PendingHello {
+    key: TlsKey,
+    at: DateTime<Utc>,
+}
+
+/// TLS destinations and server certificates per client device
+#[derive(Default)]
+pub struct TlsInventory {
+    pending: DashMap<Connection, PendingHello>,
+    pub observations: DashMap<TlsKey, TlsObservation>,
+    /// JA3 and JA3S fingerprints by client device
+    pub fingerprints: DashMap<DeviceKey, HashMap<(FingerprintKind, String), Fingerprint>>,
+}
+
+impl TlsInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the TLS metadata of `frame`, if any
+    pub fn observe(&self, frame: &SensorFrame, src: DeviceKey, dst: DeviceKey, now: DateTime<Utc>) {
+        let (Some(tls), Some(src_ip), Some(dst_ip), Some(src_port), Some(dst_port)) =
+            (frame.tls.as_ref(), frame.src_ip, frame.dst_ip, frame.src_port, frame.dst_port)
+        else {
+            return;
+        };
+
+        // The ClientHello comes from the client, the ServerHello goes to it
+        if let Some(ja3) = &tls.ja3 {
+            self.record_fingerprint(src, FingerprintKind::Ja3, ja3, now);
+        }
+        if let Some(ja3s) = &tls.ja3s {
+            self.record_fingerprint(dst, FingerprintKind::Ja3s, ja3s, now);
+        }
+
+        if let Some(sni) = &tls.sni {
+            // ClientHello: the sender is the client
+            let key = TlsKey {
+                site: src.site,
+                client_mac: src.mac,
+                server_ip: dst_ip,
+                server_port: dst_port,
+                sni: Some(sni.clone()),
+            };
+            self.record(key.clone(), None, now);
+
+            if self.pending.len() >= MAX_PENDING {
+                let stale = now - chrono::Duration::seconds(HELLO_TIMEOUT_SECS);
+                self.pending.retain(|_, hello| hello.at >= stale);
+            }
+            self.pending.insert((frame.sensor, src_ip, src_port, dst_ip, dst_port), PendingHello { key, at: now });
+        }
+
+        if let Some(certificate) = &tls.certificate {
+            // Certificate: the sender is the server
+            match self.pending.remove(&(frame.sensor, dst_ip, dst_port, src_ip, src_port)) {
+                Some((_, hello)) => {
+                    if let Some(mut observation) = self.observations.get_mut(&hello.key) {
+                        observation.certificate = Some(certificate.clone());
+                        observation.dirty.store(true, Ordering::Relaxed);
+                    }
+                }
+                None => {
+                    let key = TlsKey {
+                        site: dst.site,
+                        client_mac: dst.mac,
+                        server_ip: src_ip,
+                        server_port: src_port,
+                        sni: None,
+                    };
+                    self.record(key, Some(certificate.clone()), now);
+                }
+            }
+        }
+    }
+
+    fn record_fingerprint(&self, device: DeviceKey, kind: FingerprintKind, fingerprint: &str, now: DateTime<Utc>) {
+        let mut device = self.fingerprints.entry(device).or_default();
+        let sightings = device.entry((kind, fingerprint.to_string())).or_insert_with(|| Fingerprint {
+            count: 0,
+            unpersisted: 0,
+            first_seen: now,
+            last_seen: now,
+        });
+        sightings.count += 1;
+        sightings.unpersisted += 1;
+        sightings.last_seen = now;
+    }
+
+    /// Fingerprints of `kind` seen on a device
+    pub fn device_fingerprints(&self, device: &DeviceKey, kind: FingerprintKind) -> Vec<String> {
+        self.fingerprints.get(device).map_or_else(Vec::new, |device| {
+            device.keys().filter(|(k, _)| *k == kind).map(|(_, f)| f.clone()).collect()
+        })
+    }
+
+    fn record(&self, key: TlsKey, certificate: Option<CertificateInfo>, now: DateTime<Utc>) {
+        let mut observation = self.observations.entry(key).or_insert_with(|| TlsObservation::new(now));
+        observation.handshakes += 1;
+        observation.last_seen = now;
+        if certificate.is_some() {
+            observation.certificate = certificate;
+        }
+        observation.dirty.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const GATEWAY: [u8; 6] = [0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+    fn frame(reply: bool, tls: serde_json::Value) -> SensorFrame {
+        let (src, dst) = if reply { (GATEWAY, CLIENT) } else { (CLIENT, GATEWAY) };
+        let (src_ip, dst_ip) = if reply { ("52.1.2.3", "10.0.0.5") } else { ("10.0.0.5", "52.1.2.3") };
+        let (src_port, dst_port) = if reply { (443, 50000) } else { (50000, 443) };
+        serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": MacAddr::new(src).to_string(),
+            "dst_mac": MacAddr::new(dst).to_string(),
+            "ethertype": 0x0800,
+            "src_ip": src_ip,
+            "dst_ip": dst_ip,
+            "ip_protocol": 6,
+            "src_port": src_port,
+            "dst_port": dst_port,
+            "tls": tls,
+            "frame_size": 517,
+            "payload_size": 451,
+        })).unwrap()
+    }
+
+    fn observe(inventory: &TlsInventory, frame: SensorFrame) {
+        inventory.observe(&frame, DeviceKey::new(frame.site, frame.src_mac), DeviceKey::new(frame.site, frame.dst_mac), frame.timestamp);
+    }
+
+    #[test]
+    fn test_sni_and_certificate() {
+        let inventory = TlsInventory::new();
+        let certificate = serde_json::json!({
+            "sha256": "ab".repeat(32),
+            "subject": "*.dropbox.com",
+            "issuer": "DigiCert TLS RSA SHA256 2020 CA1",
+            "not_before": "2024-01-01T00:00:00Z",
+            "not_after": "2025-01-01T00:00:00Z",
+        });
+
+        let ja3 = "e7d705a3286e19ea42f587b344ee6865";
+        let ja3s = "ae4edc6faf64d08308082ad26be60767";
+        observe(&inventory, frame(false, serde_json::json!({ "sni": "www.dropbox.com", "ja3": ja3 })));
+        observe(&inventory, frame(true, serde_json::json!({ "certificate": certificate, "ja3s": ja3s })));
+        // A certificate without its ClientHello
+        let mut orphan = frame(true, serde_json::json!({ "certificate": certificate }));
+        orphan.src_port = Some(8443);
+        observe(&inventory, orphan);
+
+        assert_eq!(inventory.observations.len(), 2);
+        let key = TlsKey {
+            site: SiteId::default(),
+            client_mac: MacAddr::new(CLIENT),
+            server_ip: "52.1.2.3".parse().unwrap(),
+            server_port: 443,
+            sni: Some("www.dropbox.com".to_string()),
+        };
+        let observation = inventory.observations.get(&key).unwrap();
+        assert_eq!(observation.handshakes, 1);
+        assert_eq!(observation.certificate.as_ref().unwrap().subject.as_deref(), Some("*.dropbox.com"));
+        assert!(inventory.pending.is_empty());
+
+        let orphan = TlsKey { server_port: 8443, sni: None, ..key };
+        assert!(inventory.observations.get(&orphan).unwrap().certificate.is_some());
+
+        // Both fingerprints belong to the client
+        let client = DeviceKey::new(SiteId::default(), MacAddr::new(CLIENT));
+        assert_eq!(inventory.device_fingerprints(&client, FingerprintKind::Ja3), vec![ja3]);
+        assert_eq!(inventory.device_fingerprints(&client, FingerprintKind::Ja3s), vec![ja3s]);
+        assert!(!inventory.fingerprints.contains_key(&DeviceKey::new(SiteId::default(), MacAddr::new(GATEWAY))));
+    }
+}
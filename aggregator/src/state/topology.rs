@@ -0,0 +1,171 @@
+//! Layer 2 segment inference
+//!
+//! A capture interface sees the source MAC addresses of the devices behind
+//! the switch ports its SPAN or TAP mirrors. Devices seen as a source on
+//! exactly the same capture interfaces therefore sit in the same segment:
+//! behind the same port span. A router or uplink shows up on every span it
+//! forwards to, and so forms a segment of its own.
+//!
+//! Capture interfaces a device has not been seen on for `STALE_SECS` before
+//! its latest sighting are left out, so a device moved to another port
+//! joins its new segment.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{DeviceKey, SensorId, SiteId};
+
+/// Sightings older than this before a device's latest one are stale
+const STALE_SECS: i64 = 86_400;
+
+/// Capture interfaces a device was seen on as a source
+#[derive(Debug, Default)]
+pub struct Sightings {
+    /// Last sighting by sensor and interface
+    pub points: HashMap<SensorId, HashMap<String, DateTime<Utc>>>,
+    pub first_seen: Option<DateTime<Utc>>,
+}
+
+impl Sightings {
+    /// `sensor/interface` names of the capture points seen recently enough
+    fn signature(&self) -> Vec<String> {
+        let latest = self.points.values().flat_map(HashMap::values).max().copied();
+        let Some(latest) = latest else { return Vec::new() };
+        let fresh = latest - chrono::Duration::seconds(STALE_SECS);
+
+        let mut signature: Vec<String> = self.points.iter()
+            .flat_map(|(sensor, interfaces)| {
+                interfaces.iter()
+                    .filter(move |(_, at)| **at >= fresh)
+                    .map(move |(interface, _)| format!("{}/{}", sensor, interface))
+            })
+            .collect();
+        signature.sort();
+        signature
+    }
+
+    fn last_seen(&self) -> Option<DateTime<Utc>> {
+        self.points.values().flat_map(HashMap::values).max().copied()
+    }
+}
+
+/// Devices behind the same capture interfaces
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct L2Segment {
+    pub site: String,
+    /// Hash of the capture points, stable across restarts
+    pub id: String,
+    /// `sensor/interface` names
+    pub capture_points: Vec<String>,
+    /// MAC addresses
+    pub devices: Vec<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Segment ID of `capture_points` on `site`
+fn segment_id(site: SiteId, capture_points: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(site.as_str().as_bytes());
+    for point in capture_points {
+        hasher.update([0]);
+        hasher.update(point.as_bytes());
+    }
+    hasher.finalize().iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Capture interfaces each device was seen on
+#[derive(Default)]
+pub struct L2Topology {
+    pub devices: DashMap<DeviceKey, Sightings>,
+    /// A device was seen on a new capture interface since last persisted
+    pub dirty: AtomicBool,
+}
+
+impl L2Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `device` sending a frame captured by `sensor` on `interface`
+    pub fn record(&self, device: DeviceKey, sensor: SensorId, interface: &str, now: DateTime<Utc>) {
+        let mut sightings = self.devices.entry(device).or_default();
+        sightings.first_seen.get_or_insert(now);
+        let interfaces = sightings.points.entry(sensor).or_default();
+        match interfaces.get_mut(interface) {
+            Some(at) => *at = (*at).max(now),
+            None => {
+                interfaces.insert(interface.to_string(), now);
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Group devices into segments, largest first
+    pub fn segments(&self) -> Vec<L2Segment> {
+        let mut segments: BTreeMap<(SiteId, Vec<String>), L2Segment> = BTreeMap::new();
+        for entry in self.devices.iter() {
+            let device = *entry.key();
+            let sightings = entry.value();
+            let (Some(first_seen), Some(last_seen)) = (sightings.first_seen, sightings.last_seen()) else { continue };
+            let signature = sightings.signature();
+
+            let segment = segments.entry((device.site, signature.clone())).or_insert_with(|| L2Segment {
+                site: device.site.to_string(),
+                id: segment_id(device.site, &signature),
+                capture_points: signature,
+                devices: Vec::new(),
+                first_seen,
+                last_seen,
+            });
+            segment.devices.push(device.mac.to_string());
+            segment.first_seen = segment.first_seen.min(first_seen);
+            segment.last_seen = segment.last_seen.max(last_seen);
+        }
+
+        let mut segments: Vec<L2Segment> = segments.into_values().collect();
+        for segment in &mut segments {
+            segment.devices.sort();
+        }
+        segments.sort_by(|a, b| b.devices.len().cmp(&a.devices.len()).then_with(|| a.id.cmp(&b.id)));
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::MacAddr;
+
+    #[test]
+    fn test_segments() {
+        let topology = L2Topology::new();
+        let site = SiteId::default();
+        let sensor = SensorId::default();
+        let device = |n| DeviceKey::new(site, MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, n]));
+        let now = Utc::now();
+
+        // Two hosts behind eth1, one behind eth2, the router on both
+        topology.record(device(1), sensor, "eth1", now);
+        topology.record(device(2), sensor, "eth1", now);
+        topology.record(device(3), sensor, "eth2", now);
+        topology.record(device(9), sensor, "eth1", now);
+        topology.record(device(9), sensor, "eth2", now);
+        // Moved from eth2 to eth1 two days ago
+        topology.record(device(4), sensor, "eth2", now - chrono::Duration::days(2));
+        topology.record(device(4), sensor, "eth1", now);
+        assert!(topology.dirty.load(Ordering::Relaxed));
+
+        let segments = topology.segments();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].capture_points, vec![format!("{}/eth1", sensor)]);
+        assert_eq!(segments[0].devices.len(), 3);
+        assert_eq!(segments[0].first_seen, now - chrono::Duration::days(2));
+        assert!(segments.iter().any(|s| s.capture_points.len() == 2 && s.devices == vec![device(9).mac.to_string()]));
+        assert_eq!(segments[0].id, segment_id(site, &segments[0].capture_points));
+    }
+}
@@ -0,0 +1,78 @@
+//! Device vendors
+//!
+//! Vendors are named from the OUI, the first three bytes of the MAC address,
+//! through the deployment's `[vendors]` table (OUI -> name): no registry
+//! ships with the aggregator, and a site usually cares about a few dozen.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::MacAddr;
+
+/// Vendor names of OUIs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Vendors {
+    #[serde(deserialize_with = "oui_keys")]
+    names: HashMap<String, String>,
+}
+
+/// OUI -> name table, OUIs normalized to `00:1B:63`
+fn oui_keys<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, String>, D::Error> {
+    use serde::de::Error;
+
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(oui, name)| {
+            let mac = MacAddr::from_string(&format!("{}:00:00:00", oui.replace('-', ":")))
+                .ok_or_else(|| D::Error::custom(format!("invalid OUI '{}' in vendors", oui)))?;
+            if name.is_empty() {
+                return Err(D::Error::custom(format!("empty vendor name of OUI {}", oui)));
+            }
+            Ok((mac.oui_prefix(), name))
+        })
+        .collect()
+}
+
+impl Vendors {
+    /// Vendor of `mac`
+    pub fn name(&self, mac: &MacAddr) -> Option<&str> {
+        self.names.get(&mac.oui_prefix()).map(String::as_str)
+    }
+
+    /// OUIs of the vendors whose name contains `search`, ignoring case
+    pub fn ouis_matching(&self, search: &str) -> Vec<String> {
+        let search = search.to_lowercase();
+        let mut ouis: Vec<String> = self.names
+            .iter()
+            .filter(|(_, name)| name.to_lowercase().contains(&search))
+            .map(|(oui, _)| oui.clone())
+            .collect();
+        ouis.sort();
+        ouis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vendor_names() {
+        let vendors: Vendors = toml::from_str(
+            r#"
+"00:1b:63" = "Apple"
+"3C-22-FB" = "Apple"
+"00:50:56" = "VMware"
+"#,
+        )
+        .unwrap();
+        let mac = MacAddr::new([0x3c, 0x22, 0xfb, 0x01, 0x02, 0x03]);
+        assert_eq!(vendors.name(&mac), Some("Apple"));
+        assert_eq!(vendors.ouis_matching("apple"), ["00:1B:63", "3C:22:FB"]);
+        assert!(vendors.ouis_matching("cisco").is_empty());
+
+        assert!(toml::from_str::<Vendors>(r#""00:1b" = "Apple""#).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! VLAN to subnet mapping
+//!
+//! The source addresses each device uses on a tagged VLAN tell which IP
+//! subnets live on it. A subnet's weight on a VLAN is the number of hosts
+//! sourcing addresses from it there, and its confidence that weight's share
+//! of the VLAN's hosts. Routers forward traffic from many subnets under
+//! their own MAC address, so devices sourcing addresses from more than one
+//! subnet on a VLAN are left out.
+//!
+//! Two kinds of conflict are flagged: a VLAN carrying several subnets
+//! (secondary addressing or a misplaced host) and a subnet seen on several
+//! VLANs of the same site (a mis-tagged port or a stretched subnet).
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{MacAddr, SiteId};
+
+/// Addresses kept per device and VLAN; a device using more is a router
+const MAX_HOST_IPS: usize = 8;
+
+/// Conflict of a VLAN also carrying other subnets
+pub const SEVERAL_SUBNETS: &str = "several_subnets";
+
+/// Conflict of a subnet also seen on other VLANs
+pub const SEVERAL_VLANS: &str = "several_vlans";
+
+/// Source addresses of a device on one VLAN
+#[derive(Debug)]
+pub struct VlanHost {
+    pub ips: Vec<Ipv4Addr>,
+    /// Used more than `MAX_HOST_IPS` addresses
+    pub routed: bool,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A subnet inferred on a VLAN
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VlanSubnet {
+    pub site: String,
+    pub vlan_id: u16,
+    /// `10.0.20.0/24`
+    pub subnet: String,
+    /// Devices sourcing addresses from the subnet on the VLAN
+    pub hosts: u32,
+    /// Share of the VLAN's hosts in the subnet
+    pub confidence: f64,
+    /// The VLAN's subnet with the most hosts
+    pub primary: bool,
+    pub conflicts: Vec<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl VlanSubnet {
+    /// Whether the subnet contains `ip`
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let Some((net, prefix)) = self.subnet.split_once('/') else { return false };
+        match (net.parse::<Ipv4Addr>(), prefix.parse::<u8>()) {
+            (Ok(net), Ok(prefix)) if prefix <= 32 => network(ip, prefix) == net,
+            _ => false,
+        }
+    }
+}
+
+/// Whether `ip` identifies a host of the subnet it belongs to
+pub(super) fn is_host_address(ip: Ipv4Addr) -> bool {
+    !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || ip.is_link_local() || ip.is_loopback())
+}
+
+/// Network of `ip` with a `prefix` bits mask
+pub(super) fn network(ip: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    Ipv4Addr::from(u32::from(ip) & mask)
+}
+
+/// Hosts of a subnet on a VLAN
+struct Sighting {
+    hosts: u32,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+/// Source addresses of devices per tagged VLAN
+#[derive(Default)]
+pub struct VlanSubnets {
+    /// Keyed by site, VLAN and device
+    pub hosts: DashMap<(SiteId, u16, MacAddr), VlanHost>,
+    /// Changed since last persisted
+    pub dirty: AtomicBool,
+}
+
+impl VlanSubnets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `mac` sourcing `ip` on `vlan_id`
+    pub fn record(&self, site: SiteId, vlan_id: u16, mac: MacAddr, ip: Ipv4Addr, now: DateTime<Utc>) {
+        if !is_host_address(ip) {
+            return;
+        }
+
+        let mut host = self.hosts.entry((site, vlan_id, mac)).or_insert_with(|| VlanHost {
+            ips: Vec::new(),
+            routed: false,
+            first_seen: now,
+            last_seen: now,
+        });
+        host.last_seen = host.last_seen.max(now);
+        if !host.routed && !host.ips.contains(&ip) {
+            if host.ips.len() < MAX_HOST_IPS {
+                host.ips.push(ip);
+            } else {
+                host.routed = true;
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Subnets of each VLAN, `prefix` bits long
+    pub fn infer(&self, prefix: u8) -> Vec<VlanSubnet> {
+        let mut sightings: BTreeMap<(SiteId, u16, Ipv4Addr), Sighting> = BTreeMap::new();
+        for entry in self.hosts.iter() {
+            let (site, vlan_id, _) = *entry.key();
+            let host = entry.value();
+            let Some(first) = host.ips.first().map(|ip| network(*ip, prefix)) else { continue };
+            if host.routed || host.ips.iter().any(|ip| network(*ip, prefix) != first) {
+                continue;
+            }
+
+            let sighting = sightings.entry((site, vlan_id, first)).or_insert(Sighting {
+                hosts: 0,
+                first_seen: host.first_seen,
+                last_seen: host.last_seen,
+            });
+            sighting.hosts += 1;
+            sighting.first_seen = sighting.first_seen.min(host.first_seen);
+            sighting.last_seen = sighting.last_seen.max(host.last_seen);
+        }
+
+        let mut vlan_hosts: HashMap<(SiteId, u16), (u32, u32, Ipv4Addr)> = HashMap::new();
+        let mut subnet_vlans: HashMap<(SiteId, Ipv4Addr), u32> = HashMap::new();
+        for (&(site, vlan_id, net), &Sighting { hosts, .. }) in &sightings {
+            // Total hosts, and the most hosts of one subnet and that subnet
+            let vlan = vlan_hosts.entry((site, vlan_id)).or_insert((0, 0, net));
+            vlan.0 += hosts;
+            if hosts > vlan.1 {
+                vlan.1 = hosts;
+                vlan.2 = net;
+            }
+            *subnet_vlans.entry((site, net)).or_default() += 1;
+        }
+
+        sightings.into_iter().map(|((site, vlan_id, net), Sighting { hosts, first_seen, last_seen })| {
+            let (total, _, primary) = vlan_hosts[&(site, vlan_id)];
+            let mut conflicts = Vec::new();
+            if hosts < total {
+                conflicts.push(SEVERAL_SUBNETS.to_string());
+            }
+            if subnet_vlans[&(site, net)] > 1 {
+                conflicts.push(SEVERAL_VLANS.to_string());
+            }
+
+            VlanSubnet {
+                site: site.to_string(),
+                vlan_id,
+                subnet: format!("{}/{}", net, prefix),
+                hosts,
+                confidence: hosts as f64 / total as f64,
+                primary: net == primary,
+                conflicts,
+                first_seen,
+                last_seen,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(n: u8) -> MacAddr {
+        MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, n])
+    }
+
+    #[test]
+    fn test_infer() {
+        let subnets = VlanSubnets::new();
+        let site = SiteId::default();
+        let now = Utc::now();
+
+        // VLAN 10: three hosts in 10.0.10.0/24, one in 10.0.20.0/24
+        for n in 1..=3 {
+            subnets.record(site, 10, mac(n), Ipv4Addr::new(10, 0, 10, n), now);
+        }
+        subnets.record(site, 10, mac(4), Ipv4Addr::new(10, 0, 20, 4), now);
+        // The router forwards other subnets onto VLAN 10: left out
+        subnets.record(site, 10, mac(9), Ipv4Addr::new(10, 0, 10, 1), now);
+        subnets.record(site, 10, mac(9), Ipv4Addr::new(10, 0, 30, 7), now);
+        subnets.record(site, 10, mac(9), Ipv4Addr::new(8, 8, 8, 8), now);
+        // VLAN 20: two hosts in 10.0.20.0/24, and a DHCP client without an address yet
+        subnets.record(site, 20, mac(5), Ipv4Addr::new(10, 0, 20, 5), now);
+        subnets.record(site, 20, mac(6), Ipv4Addr::new(10, 0, 20, 6), now);
+        subnets.record(site, 20, mac(7), Ipv4Addr::UNSPECIFIED, now);
+
+        let inferred = subnets.infer(24);
+        let summary: Vec<(u16, &str, u32, bool, &[String])> = inferred.iter()
+            .map(|s| (s.vlan_id, s.subnet.as_str(), s.hosts, s.primary, s.conflicts.as_slice()))
+            .collect();
+        assert_eq!(summary, vec![
+            (10, "10.0.10.0/24", 3, true, &[SEVERAL_SUBNETS.to_string()][..]),
+            (10, "10.0.20.0/24", 1, false, &[SEVERAL_SUBNETS.to_string(), SEVERAL_VLANS.to_string()][..]),
+            (20, "10.0.20.0/24", 2, true, &[SEVERAL_VLANS.to_string()][..]),
+        ]);
+        assert_eq!(inferred[0].confidence, 0.75);
+        assert_eq!(inferred[2].confidence, 1.0);
+        assert!(inferred[0].contains(Ipv4Addr::new(10, 0, 10, 200)));
+        assert!(!inferred[0].contains(Ipv4Addr::new(10, 0, 11, 1)));
+
+        assert_eq!(network(Ipv4Addr::new(172, 16, 5, 9), 16), Ipv4Addr::new(172, 16, 0, 0));
+        assert_eq!(network(Ipv4Addr::new(172, 16, 5, 9), 0), Ipv4Addr::UNSPECIFIED);
+    }
+}
@@ -0,0 +1,160 @@
+//! OpenTelemetry export
+//!
+//! Sends spans (persist cycles, consumed batches, forwarder flushes) and
+//! metrics to an OTLP/HTTP collector. Spans come from the `tracing`
+//! instrumentation through a subscriber layer; metrics are the counters and
+//! gauges of [`crate::metrics`], observed at each export.
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::TelemetryConfig;
+use crate::metrics::metrics;
+use crate::state::AggregatorState;
+
+/// Instrumentation scope of the aggregator spans and metrics
+const SCOPE: &str = "netsentinel-aggregator";
+
+/// Timeout of a single export request
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OTLP providers, flushed and shut down when dropped
+#[derive(Default)]
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+impl Telemetry {
+    /// Build the exporters enabled in `config`
+    ///
+    /// The meter provider is installed globally so that [`observe`] can
+    /// register instruments once the state exists.
+    pub fn new(config: &TelemetryConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+
+        let resource = Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+            .build();
+        let endpoint = config.endpoint.trim_end_matches('/');
+
+        let tracer_provider = if config.traces {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint))
+                .with_headers(config.headers.clone())
+                .with_timeout(EXPORT_TIMEOUT)
+                .build()
+                .with_context(|| "Failed to build OTLP span exporter")?;
+
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+                    .with_resource(resource.clone())
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        let meter_provider = if config.metrics {
+            let exporter = MetricExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/metrics", endpoint))
+                .with_headers(config.headers.clone())
+                .with_timeout(EXPORT_TIMEOUT)
+                .build()
+                .with_context(|| "Failed to build OTLP metric exporter")?;
+            let reader = PeriodicReader::builder(exporter)
+                .with_interval(Duration::from_secs(config.export_interval_secs))
+                .build();
+
+            let provider = SdkMeterProvider::builder()
+                .with_reader(reader)
+                .with_resource(resource)
+                .build();
+            global::set_meter_provider(provider.clone());
+            Some(provider)
+        } else {
+            None
+        };
+
+        Ok(Self { tracer_provider, meter_provider })
+    }
+
+    /// Tracer for the `tracing` layer, when spans are exported
+    pub fn tracer(&self) -> Option<SdkTracer> {
+        self.tracer_provider.as_ref().map(|provider| provider.tracer(SCOPE))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush spans: {}", e);
+            }
+        }
+        if let Some(provider) = self.meter_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush metrics: {}", e);
+            }
+        }
+    }
+}
+
+/// Register the aggregator metrics with the global meter provider
+pub fn observe(state: Arc<AggregatorState>) {
+    let meter = global::meter(SCOPE);
+
+    meter.u64_observable_counter("netsentinel.frames.consumed")
+        .with_description("Stream entries applied to the state")
+        .with_callback(|observer| observer.observe(metrics().frames_consumed.get(), &[]))
+        .build();
+    meter.u64_observable_counter("netsentinel.frames.invalid")
+        .with_description("Stream entries that failed to parse")
+        .with_callback(|observer| observer.observe(metrics().frames_invalid.get(), &[]))
+        .build();
+    meter.u64_observable_counter("netsentinel.db.errors")
+        .with_description("Failed database writes")
+        .with_callback(|observer| observer.observe(metrics().db_errors.get(), &[]))
+        .build();
+    meter.i64_observable_gauge("netsentinel.stream.lag")
+        .with_description("Stream entries not yet delivered to the consumer group")
+        .with_callback(|observer| observer.observe(metrics().stream_lag.get(), &[]))
+        .build();
+    meter.i64_observable_gauge("netsentinel.stream.pending")
+        .with_description("Delivered but unacknowledged stream entries")
+        .with_callback(|observer| observer.observe(metrics().stream_pending.get(), &[]))
+        .build();
+    meter.u64_observable_counter("netsentinel.stream.trimmed")
+        .with_description("Stream entries removed by the retention task")
+        .with_callback(|observer| observer.observe(metrics().stream_trimmed.get(), &[]))
+        .build();
+    meter.u64_observable_gauge("netsentinel.state.entries")
+        .with_description("In-memory state entries")
+        .with_callback(move |observer| {
+            let stats = state.stats_snapshot();
+            for (kind, count) in [
+                ("devices", stats.total_devices),
+                ("flows", stats.total_flows),
+                ("protocols", stats.total_protocols),
+                ("vlans", stats.total_vlans),
+            ] {
+                observer.observe(count as u64, &[KeyValue::new("kind", kind)]);
+            }
+        })
+        .build();
+}
@@ -0,0 +1,198 @@
+//! NetSentinel All-in-One - Capture, aggregation and storage in one process
+//!
+//! For laptops, proofs of concept and very small sites where running the
+//! capture, Redis and the aggregator separately is overkill: frames go from
+//! the capture straight to the aggregator over an in-memory channel, and
+//! PostgreSQL still stores the inventory. One configuration file holds the
+//! sections of both modules (`[capture]`, `[sensor]` and `[flow_input]` for
+//! the capture, the rest for the aggregator); `[redis]` is not used.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tracing::{info, warn, Level};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use netsentinel_aggregator::logging::LogFilter;
+use netsentinel_aggregator::state::{SensorFrame, SensorId, SiteId};
+use netsentinel_aggregator::telemetry::Telemetry;
+use netsentinel_aggregator::Pipeline;
+use netsentinel_capture::autotune::{self, Tunables};
+use netsentinel_capture::capture::{print_interfaces, CapturedFrame};
+use netsentinel_capture::input::Input;
+
+/// NetSentinel capture, aggregation and storage in a single process
+#[derive(Parser, Debug)]
+#[command(name = "netsentinel-allinone")]
+#[command(author = "SecuAAS")]
+#[command(version)]
+#[command(about = "Passive network inventory in a single process, without Redis", long_about = None)]
+struct Args {
+    /// Path to configuration file
+    #[arg(short, long, default_value = "/opt/netsentinel/config/allinone.toml")]
+    config: PathBuf,
+
+    /// List available network interfaces
+    #[arg(long)]
+    list_interfaces: bool,
+
+    /// Run in debug mode (verbose logging)
+    #[arg(short, long)]
+    debug: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // List interfaces and exit if requested
+    if args.list_interfaces {
+        print_interfaces();
+        return Ok(());
+    }
+
+    // Load both halves of the configuration from the same file
+    let capture_config = netsentinel_capture::Config::from_file(&args.config)
+        .with_context(|| format!("Failed to load config from {:?}", args.config))?;
+    capture_config.validate()?;
+    let config = netsentinel_aggregator::Config::from_file(&args.config)
+        .with_context(|| format!("Failed to load config from {:?}", args.config))?;
+    config.validate()?;
+
+    // Setup logging (kept alive so spans and metrics are flushed on exit)
+    let (_telemetry, log_filter) = setup_logging(&config, args.debug)?;
+
+    let site = SiteId::new(&capture_config.sensor.site).context("Invalid sensor.site")?;
+    let sensor = SensorId::new(&capture_config.sensor.id).context("Invalid sensor.id")?;
+
+    info!("NetSentinel All-in-One starting...");
+    info!("Mode: {}", capture_config.capture.mode);
+    info!("Sensor: {} (site {})", sensor, site);
+    info!("Database: {}", config.database.url);
+
+    // Frames go from the capture to the aggregator, stamped with the sensor
+    let capacity = if capture_config.script.enabled {
+        capture_config.capture.ring_buffer_size
+    } else {
+        capture_config.capture.ring_buffer_capacity()
+    };
+    let (frame_tx, mut frame_rx) = mpsc::channel::<CapturedFrame>(capacity);
+    let (sensor_tx, sensor_rx) = mpsc::channel::<SensorFrame>(capture_config.capture.ring_buffer_size);
+    tokio::spawn(async move {
+        while let Some(frame) = frame_rx.recv().await {
+            // Frames of a mapped ERSPAN session belong to its site
+            let site = frame.erspan.as_ref().and_then(|e| e.site.as_deref()).and_then(SiteId::new).unwrap_or(site);
+            if sensor_tx.send(SensorFrame { site, sensor, frame }).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let pipeline = Arc::new(
+        Pipeline::new(config)
+            .await
+            .with_context(|| "Failed to initialize pipeline")?
+            .with_log_filter(log_filter)
+            .with_frames(sensor_rx),
+    );
+
+    // Start the input: flow export listeners, or capture on all interfaces,
+    // the ring buffer following the drops when auto-tuned (no Redis batches)
+    let tuning = capture_config.capture.autotune.enabled.then(|| Arc::new(Tunables::new(&capture_config)));
+    let input = Input::start(&capture_config, frame_tx, tuning.clone())?;
+    let autotune_handle = tuning.map(|tunables| {
+        tokio::spawn(autotune::run(capture_config.capture.autotune.clone(), tunables, None))
+    });
+
+    // Setup signal handling (a second signal aborts the drain)
+    let stop = Arc::new(Notify::new());
+    let signal = Arc::clone(&stop);
+    let signalled = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if signalled.swap(true, Ordering::SeqCst) {
+            warn!("Received second shutdown signal, exiting immediately");
+            std::process::exit(130);
+        }
+        info!("Received shutdown signal, draining");
+        signal.notify_one();
+    })
+    .context("Failed to set Ctrl+C handler")?;
+
+    // Run the pipeline until a signal, then stop capturing and let it drain
+    let mut run = tokio::spawn({
+        let pipeline = Arc::clone(&pipeline);
+        async move { pipeline.run().await }
+    });
+    let finished = tokio::select! {
+        _ = stop.notified() => {
+            pipeline.shutdown();
+            None
+        }
+        result = &mut run => Some(result),
+    };
+
+    if let Some(h) = autotune_handle {
+        h.abort();
+    }
+    let stats = input.stop();
+    info!(
+        "Final stats: packets={}, bytes={}, dropped={}, errors={}, shed={}, mode changes={}",
+        stats.packets_captured,
+        stats.bytes_captured,
+        stats.packets_dropped,
+        stats.parse_errors,
+        stats.frames_shed,
+        stats.mode_changes
+    );
+
+    let result = match finished {
+        Some(result) => result,
+        None => run.await,
+    };
+    result.context("Pipeline task failed")??;
+
+    info!("NetSentinel All-in-One stopped");
+    Ok(())
+}
+
+/// Setup logging and the optional OpenTelemetry export
+///
+/// Returns the handle changing the log filter at runtime along with the
+/// telemetry providers.
+fn setup_logging(config: &netsentinel_aggregator::Config, debug: bool) -> Result<(Telemetry, LogFilter)> {
+    let level = if debug {
+        Level::DEBUG
+    } else {
+        match config.logging.level.to_lowercase().as_str() {
+            "trace" => Level::TRACE,
+            "debug" => Level::DEBUG,
+            "info" => Level::INFO,
+            "warn" | "warning" => Level::WARN,
+            "error" => Level::ERROR,
+            _ => Level::INFO,
+        }
+    };
+
+    let filter = EnvFilter::from_default_env()
+        .add_directive(format!("netsentinel_allinone={}", level).parse().unwrap())
+        .add_directive(format!("netsentinel_capture={}", level).parse().unwrap())
+        .add_directive(format!("netsentinel_aggregator={}", level).parse().unwrap())
+        .add_directive("sqlx=warn".parse().unwrap());
+
+    let (filter, log_filter) = LogFilter::new(filter);
+    let telemetry = Telemetry::new(&config.telemetry)?;
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(telemetry.tracer().map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    if config.logging.format == "json" {
+        subscriber.with(fmt::layer().json()).init();
+    } else {
+        subscriber.with(fmt::layer().with_target(true)).init();
+    }
+
+    Ok((telemetry, log_filter))
+}
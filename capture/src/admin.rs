@@ -0,0 +1,153 @@
+//! Local admin socket
+//!
+//! A running capture listens on a Unix socket for changes of its log
+//! filter, so debugging a sensor in production doesn't need a restart. Each
+//! connection carries one JSON request line and gets one JSON response line
+//! back, the same protocol as the aggregator's admin socket; `--log-level`
+//! is the client side. Access is limited to the socket owner.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::config::AdminConfig;
+use crate::logging::LogFilter;
+
+/// Longest request line accepted
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+/// Operation asked of a running capture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// Print the log filter, or apply directives on top of the configured one
+    LogLevel {
+        #[serde(default)]
+        filter: Option<String>,
+    },
+}
+
+/// Outcome of a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminResponse {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+/// Serves admin requests on the local socket
+pub struct AdminServer {
+    config: AdminConfig,
+    log_filter: LogFilter,
+}
+
+impl AdminServer {
+    /// Create a server changing the log filter through `log_filter`
+    pub fn new(config: AdminConfig, log_filter: LogFilter) -> Self {
+        Self { config, log_filter }
+    }
+
+    /// Accept connections until `running` turns false
+    pub async fn run(self, mut running: watch::Receiver<bool>) -> Result<()> {
+        let path = &self.config.socket;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        // A socket left behind by an instance that did not stop cleanly
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                anyhow::bail!("Admin socket {:?} is in use by another instance", path);
+            }
+            std::fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {:?}", path))?;
+        }
+
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind admin socket {:?}", path))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict admin socket {:?}", path))?;
+        info!("Admin socket listening on {:?}", path);
+
+        loop {
+            tokio::select! {
+                _ = running.wait_for(|running| !running) => break,
+                accepted = listener.accept() => {
+                    let (stream, _) = match accepted {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            warn!("Admin socket accept failed: {}", e);
+                            continue;
+                        }
+                    };
+                    let log_filter = self.log_filter.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &log_filter).await {
+                            debug!("Admin connection failed: {:#}", e);
+                        }
+                    });
+                }
+            }
+        }
+
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+}
+
+/// Read one request from `stream` and write its response
+async fn handle(stream: UnixStream, log_filter: &LogFilter) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read.take(MAX_REQUEST_BYTES)).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<AdminRequest>(&line) {
+        Ok(request) => {
+            info!("Admin request: {:?}", request);
+            match answer(request, log_filter) {
+                Ok(value) => AdminResponse::Ok(value),
+                Err(e) => AdminResponse::Error(format!("{:#}", e)),
+            }
+        }
+        Err(e) => AdminResponse::Error(format!("Invalid request: {}", e)),
+    };
+
+    let mut body = serde_json::to_vec(&response)?;
+    body.push(b'\n');
+    write.write_all(&body).await?;
+    Ok(())
+}
+
+/// Carry out `request`
+fn answer(request: AdminRequest, log_filter: &LogFilter) -> Result<serde_json::Value> {
+    match request {
+        AdminRequest::LogLevel { filter } => {
+            let current = match filter {
+                Some(directives) => log_filter.set(&directives)?,
+                None => log_filter.current()?,
+            };
+            Ok(serde_json::json!({ "filter": current }))
+        }
+    }
+}
+
+/// Send `request` to the capture listening on `socket`
+pub async fn send(socket: &Path, request: &AdminRequest) -> Result<serde_json::Value> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("Failed to connect to {:?}; is the capture running with [admin] enabled?", socket))?;
+    let (read, mut write) = stream.into_split();
+
+    let mut body = serde_json::to_vec(request)?;
+    body.push(b'\n');
+    write.write_all(&body).await?;
+
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+    match serde_json::from_str(&line).with_context(|| "Invalid response from the admin socket")? {
+        AdminResponse::Ok(value) => Ok(value),
+        AdminResponse::Error(message) => anyhow::bail!(message),
+    }
+}
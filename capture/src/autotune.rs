@@ -0,0 +1,381 @@
+//! Runtime tuning of the ring buffer and Redis batch sizes
+//!
+//! With `[capture.autotune]` enabled, the channel the captures send to is
+//! created at its largest size and its current capacity is enforced by the
+//! senders, so it grows and shrinks without being replaced (the channel
+//! only allocates room for the frames it holds). Every `interval_secs` the
+//! tuner looks at what happened since its last check:
+//!
+//! - frames dropped on a full channel double the ring buffer;
+//! - a Redis output flushing full batches most of the time doubles the batch
+//!   size, fewer and larger pipelines writing more frames per round trip;
+//! - after several quiet intervals, a ring buffer never filled past a
+//!   quarter is halved, and so are batches filling up while the output
+//!   idles, which then wait less before being written.
+//!
+//! Sizes stay within the configured bounds and every change is logged.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use crate::capture::{CapturedFrame, FrameSink};
+use crate::config::{AutoTuneConfig, Config};
+use crate::metrics::{metrics, Channel};
+use crate::output::redis::OutputStats;
+
+/// Quiet intervals before sizes are shrunk
+pub const QUIET_INTERVALS: u32 = 6;
+
+/// Share of the time spent flushing above which the output is busy
+const BUSY: f64 = 0.5;
+
+/// Share of the time spent flushing below which the output is idle
+const IDLE: f64 = 0.1;
+
+/// How long a sender waits before checking again for room in the channel
+const DELIVER_RETRY: Duration = Duration::from_millis(1);
+
+/// Current sizes, and what the senders saw since the last check
+#[derive(Debug)]
+pub struct Tunables {
+    ring_buffer_size: AtomicUsize,
+    batch_size: AtomicUsize,
+    /// Channel the captures send to, `None` in flow mode
+    channel: Option<Channel<'static>>,
+    /// Frames dropped on a full channel
+    dropped: AtomicU64,
+    /// Most frames waiting in the channel
+    peak_depth: AtomicUsize,
+}
+
+impl Tunables {
+    /// Sizes of `config` to start from
+    pub fn new(config: &Config) -> Self {
+        let channel = match (config.capture.mode.as_str(), config.script.enabled) {
+            ("flow", _) => None,
+            (_, true) => Some(Channel::Script),
+            (_, false) => Some(Channel::Output),
+        };
+        Self {
+            ring_buffer_size: AtomicUsize::new(config.capture.ring_buffer_size),
+            batch_size: AtomicUsize::new(config.capture.batch_size),
+            channel,
+            dropped: AtomicU64::new(0),
+            peak_depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Current capacity of the channel the captures send to
+    pub fn ring_buffer_size(&self) -> usize {
+        self.ring_buffer_size.load(Ordering::Relaxed)
+    }
+
+    /// Current Redis batch size
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.load(Ordering::Relaxed)
+    }
+
+    /// Capacity of the Redis output channel, when it is the tuned one
+    pub fn output_capacity(&self) -> Option<usize> {
+        matches!(self.channel, Some(Channel::Output)).then(|| self.ring_buffer_size())
+    }
+
+    /// What the senders saw since the last call
+    fn take(&self) -> (u64, usize) {
+        (self.dropped.swap(0, Ordering::Relaxed), self.peak_depth.swap(0, Ordering::Relaxed))
+    }
+
+    /// Apply an adjustment of the tuner
+    fn apply(&self, adjustment: &Adjustment) {
+        match adjustment.setting {
+            Setting::RingBufferSize => {
+                self.ring_buffer_size.store(adjustment.to, Ordering::Relaxed);
+                if let Some(channel) = self.channel {
+                    metrics().resize_channel(channel, adjustment.to);
+                }
+            }
+            Setting::BatchSize => {
+                self.batch_size.store(adjustment.to, Ordering::Relaxed);
+                metrics().batch_size.set(adjustment.to as i64);
+            }
+        }
+    }
+}
+
+/// Sender to the tuned channel, holding it to its current capacity
+#[derive(Clone)]
+pub struct TunedSender {
+    tx: mpsc::Sender<CapturedFrame>,
+    tunables: Arc<Tunables>,
+}
+
+impl TunedSender {
+    pub fn new(tx: mpsc::Sender<CapturedFrame>, tunables: Arc<Tunables>) -> Self {
+        Self { tx, tunables }
+    }
+
+    /// Frames waiting in the channel, recorded for the tuner
+    fn depth(&self) -> usize {
+        let depth = self.tx.max_capacity() - self.tx.capacity();
+        self.tunables.peak_depth.fetch_max(depth, Ordering::Relaxed);
+        depth
+    }
+}
+
+impl FrameSink for TunedSender {
+    fn offer(&self, frame: CapturedFrame) -> bool {
+        if self.depth() >= self.tunables.ring_buffer_size() {
+            self.tunables.dropped.fetch_add(1, Ordering::Relaxed);
+            debug!("Channel full, dropping frame");
+            return !self.tx.is_closed();
+        }
+        match self.tx.try_send(frame) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.tunables.dropped.fetch_add(1, Ordering::Relaxed);
+                debug!("Channel full, dropping frame");
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn deliver(&self, frame: CapturedFrame) -> bool {
+        while self.depth() >= self.tunables.ring_buffer_size() {
+            if self.tx.is_closed() {
+                return false;
+            }
+            std::thread::sleep(DELIVER_RETRY);
+        }
+        self.tx.blocking_send(frame).is_ok()
+    }
+}
+
+/// Size the tuner adjusts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    RingBufferSize,
+    BatchSize,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RingBufferSize => write!(f, "ring_buffer_size"),
+            Self::BatchSize => write!(f, "batch_size"),
+        }
+    }
+}
+
+/// A size changed, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustment {
+    pub setting: Setting,
+    pub from: usize,
+    pub to: usize,
+    pub reason: String,
+}
+
+/// What happened during one interval
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    /// Frames dropped on a full channel
+    pub dropped: u64,
+    /// Most frames waiting in the channel
+    pub peak_depth: usize,
+    /// Redis batches flushed, and their frames
+    pub flushes: u64,
+    pub frames_flushed: u64,
+    /// Time spent flushing
+    pub flush_time: Duration,
+    pub elapsed: Duration,
+}
+
+/// Sizing decisions from one interval to the next
+#[derive(Debug)]
+pub struct Tuner {
+    config: AutoTuneConfig,
+    /// `None` when the ring buffer is not tuned
+    ring_buffer_size: Option<usize>,
+    batch_size: usize,
+    /// Intervals without drops or a busy output
+    quiet: u32,
+}
+
+impl Tuner {
+    pub fn new(config: &AutoTuneConfig, ring_buffer_size: Option<usize>, batch_size: usize) -> Self {
+        Self { config: config.clone(), ring_buffer_size, batch_size, quiet: 0 }
+    }
+
+    /// Sizes for the next interval, given what happened during the last one
+    pub fn adjust(&mut self, sample: &Sample) -> Vec<Adjustment> {
+        let busy = if sample.elapsed.is_zero() { 0.0 } else { sample.flush_time.as_secs_f64() / sample.elapsed.as_secs_f64() };
+        // Most batches were flushed full rather than on the flush interval
+        let full = sample.flushes > 0 && sample.frames_flushed * 2 >= sample.flushes * self.batch_size as u64;
+        let (min_ring, max_ring) = (self.config.min_ring_buffer_size, self.config.max_ring_buffer_size);
+        let (min_batch, max_batch) = (self.config.min_batch_size, self.config.max_batch_size);
+
+        let mut adjustments = Vec::new();
+        let mut resize = |setting, from: usize, to: usize, reason: String| {
+            if to != from {
+                adjustments.push(Adjustment { setting, from, to, reason });
+            }
+            to
+        };
+
+        if sample.dropped > 0 || (busy >= BUSY && full) {
+            self.quiet = 0;
+            if let Some(size) = self.ring_buffer_size.filter(|_| sample.dropped > 0) {
+                let reason = format!("{} frames dropped on a full channel", sample.dropped);
+                self.ring_buffer_size = Some(resize(Setting::RingBufferSize, size, (size * 2).min(max_ring), reason));
+            }
+            if busy >= BUSY && full {
+                let reason = format!("output flushing full batches {:.0}% of the time", busy * 100.0);
+                self.batch_size = resize(Setting::BatchSize, self.batch_size, (self.batch_size * 2).min(max_batch), reason);
+            }
+            return adjustments;
+        }
+
+        self.quiet += 1;
+        if self.quiet < QUIET_INTERVALS {
+            return adjustments;
+        }
+        self.quiet = 0;
+        if let Some(size) = self.ring_buffer_size.filter(|size| sample.peak_depth * 4 < *size) {
+            let reason = format!("at most {} frames waiting", sample.peak_depth);
+            self.ring_buffer_size = Some(resize(Setting::RingBufferSize, size, (size / 2).max(min_ring), reason));
+        }
+        if full && busy < IDLE {
+            let reason = format!("full batches while flushing {:.0}% of the time", busy * 100.0);
+            self.batch_size = resize(Setting::BatchSize, self.batch_size, (self.batch_size / 2).max(min_batch), reason);
+        }
+        adjustments
+    }
+}
+
+/// Adjust `tunables` every interval until aborted, from what the senders
+/// and the Redis `output` (if any) saw
+pub async fn run(config: AutoTuneConfig, tunables: Arc<Tunables>, output: Option<Arc<OutputStats>>) {
+    let ring_buffer_size = tunables.channel.map(|_| tunables.ring_buffer_size());
+    let mut tuner = Tuner::new(&config, ring_buffer_size, tunables.batch_size());
+    let mut tuned = Vec::new();
+    if let Some(size) = ring_buffer_size {
+        tuned.push(format!(
+            "ring_buffer_size {} ({}..={})", size, config.min_ring_buffer_size, config.max_ring_buffer_size
+        ));
+    }
+    if output.is_some() {
+        tuned.push(format!(
+            "batch_size {} ({}..={})", tunables.batch_size(), config.min_batch_size, config.max_batch_size
+        ));
+    }
+    if tuned.is_empty() {
+        info!("Nothing to auto-tune: no capture channel nor Redis output");
+        return;
+    }
+    info!("Auto-tuning every {}s: {}", config.interval_secs, tuned.join(", "));
+
+    let read_output = |output: &OutputStats| {
+        (
+            output.flushes.load(Ordering::Relaxed),
+            output.frames_sent.load(Ordering::Relaxed),
+            output.flush_time_us.load(Ordering::Relaxed),
+        )
+    };
+    let mut last_output = output.as_deref().map(read_output).unwrap_or_default();
+    let mut last = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        let (dropped, peak_depth) = tunables.take();
+        let current = output.as_deref().map(read_output).unwrap_or_default();
+        let sample = Sample {
+            dropped,
+            peak_depth,
+            flushes: current.0 - last_output.0,
+            frames_flushed: current.1 - last_output.1,
+            flush_time: Duration::from_micros(current.2 - last_output.2),
+            elapsed: now - last,
+        };
+        (last_output, last) = (current, now);
+
+        for adjustment in tuner.adjust(&sample) {
+            info!(
+                "Auto-tune: {} {} -> {} ({})",
+                adjustment.setting, adjustment.from, adjustment.to, adjustment.reason
+            );
+            tunables.apply(&adjustment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuner() -> Tuner {
+        let config = AutoTuneConfig {
+            enabled: true,
+            min_ring_buffer_size: 1024,
+            max_ring_buffer_size: 16384,
+            min_batch_size: 100,
+            max_batch_size: 4000,
+            ..Default::default()
+        };
+        Tuner::new(&config, Some(8192), 1000)
+    }
+
+    #[test]
+    fn test_grow_and_shrink() {
+        let mut tuner = tuner();
+        let interval = Duration::from_secs(10);
+
+        // Drops grow the ring buffer up to its bound
+        let dropped = Sample { dropped: 50, peak_depth: 8192, elapsed: interval, ..Default::default() };
+        let adjustments = tuner.adjust(&dropped);
+        assert_eq!(adjustments.len(), 1);
+        assert_eq!((adjustments[0].setting, adjustments[0].from, adjustments[0].to), (Setting::RingBufferSize, 8192, 16384));
+        assert!(tuner.adjust(&dropped).is_empty());
+
+        // Full batches flushed 60% of the time grow the batch size
+        let busy = Sample {
+            flushes: 100,
+            frames_flushed: 100_000,
+            flush_time: Duration::from_secs(6),
+            elapsed: interval,
+            ..Default::default()
+        };
+        let adjustments = tuner.adjust(&busy);
+        assert_eq!((adjustments[0].setting, adjustments[0].to), (Setting::BatchSize, 2000));
+
+        // Batches flushed on the interval, a busy output is not enough
+        let timed_out = Sample { flushes: 100, frames_flushed: 1000, ..busy.clone() };
+        assert!(tuner.adjust(&timed_out).is_empty());
+
+        // Quiet for long enough: an idle ring buffer shrinks, full batches
+        // flushed by an idle output too
+        let quiet = Sample {
+            peak_depth: 100,
+            flushes: 10,
+            frames_flushed: 20_000,
+            flush_time: Duration::from_millis(50),
+            elapsed: interval,
+            ..Default::default()
+        };
+        // (the previous interval already was)
+        for _ in 2..QUIET_INTERVALS {
+            assert!(tuner.adjust(&quiet).is_empty());
+        }
+        let adjustments = tuner.adjust(&quiet);
+        assert_eq!(adjustments.len(), 2);
+        assert_eq!((adjustments[0].setting, adjustments[0].to), (Setting::RingBufferSize, 8192));
+        assert_eq!((adjustments[1].setting, adjustments[1].to), (Setting::BatchSize, 1000));
+    }
+}
@@ -0,0 +1,194 @@
+//! NetSentinel Replay - Pcap to Redis stream replay
+//!
+//! Decodes the packets of pcap files and adds them to a frames stream, as the
+//! capture would have, for capacity testing the aggregator and reprocessing
+//! incident captures.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, Level};
+use tracing_subscriber::EnvFilter;
+
+use netsentinel_capture::capture::{CapturedFrame, PcapReader};
+use netsentinel_capture::config::{RedisConfig, SensorConfig};
+use netsentinel_capture::decode::parse_frame;
+use netsentinel_capture::output::RedisOutput;
+
+/// NetSentinel pcap replay
+#[derive(Parser, Debug)]
+#[command(name = "netsentinel-replay")]
+#[command(author = "SecuAAS")]
+#[command(version)]
+#[command(about = "Replays pcap files into the NetSentinel frames stream", long_about = None)]
+struct Args {
+    /// Pcap or pcapng files, replayed in order
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Redis connection URL
+    #[arg(long, default_value = "redis://127.0.0.1:6379")]
+    redis_url: String,
+
+    /// Stream the frames are added to
+    #[arg(long, default_value = "netsentinel:frames")]
+    stream: String,
+
+    /// Approximate maximum length of the stream
+    #[arg(long, default_value_t = 100_000)]
+    max_stream_length: usize,
+
+    /// Sensor identifier stamped on the frames
+    #[arg(long, default_value = "replay")]
+    sensor: String,
+
+    /// Site stamped on the frames
+    #[arg(long, default_value = "default")]
+    site: String,
+
+    /// Interface name stamped on the frames
+    #[arg(long, default_value = "replay")]
+    interface: String,
+
+    /// Replay speed, as a multiple of the captured timing (0: as fast as possible)
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Stamp frames with the time they are replayed instead of their capture time
+    #[arg(long)]
+    rewrite_timestamps: bool,
+
+    /// Number of times the files are replayed
+    #[arg(long, default_value_t = 1)]
+    loops: u32,
+
+    /// Frames per Redis pipeline
+    #[arg(long, default_value_t = 1000)]
+    batch_size: usize,
+
+    /// Run in debug mode (verbose logging)
+    #[arg(short, long)]
+    debug: bool,
+}
+
+/// Replay counters
+#[derive(Debug, Default)]
+struct ReplayStats {
+    frames: u64,
+    decode_errors: u64,
+    skipped: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let level = if args.debug { Level::DEBUG } else { Level::INFO };
+    let filter = EnvFilter::from_default_env()
+        .add_directive(format!("netsentinel_capture={}", level).parse()?)
+        .add_directive(format!("netsentinel_replay={}", level).parse()?)
+        .add_directive("redis=warn".parse()?);
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    if !(args.speed >= 0.0 && args.speed.is_finite()) {
+        bail!("--speed must be a positive number, or 0");
+    }
+    if args.batch_size == 0 {
+        bail!("--batch-size must be at least 1");
+    }
+
+    let redis = RedisConfig {
+        url: args.redis_url.clone(),
+        stream_name: args.stream.clone(),
+        max_stream_length: args.max_stream_length,
+        ..RedisConfig::default()
+    };
+    let sensor = SensorConfig { id: args.sensor.clone(), site: args.site.clone() };
+    let output = RedisOutput::new(redis).with_sensor(sensor);
+    let output_stats = output.stats();
+
+    let (frame_tx, frame_rx) = mpsc::channel(args.batch_size * 4);
+    let batch_size = args.batch_size;
+    let writer = tokio::spawn(async move { output.run(frame_rx, batch_size, 100).await });
+
+    info!(
+        "Replaying {} file(s) to stream {} (speed {}, {} timestamps)",
+        args.files.len(),
+        args.stream,
+        if args.speed > 0.0 { format!("x{}", args.speed) } else { "max".to_string() },
+        if args.rewrite_timestamps { "rewritten" } else { "original" },
+    );
+    let started = Instant::now();
+    let replayed = replay(&args, frame_tx).await;
+
+    // The writer flushes what is left once the channel is closed
+    writer.await??;
+    let stats = replayed?;
+
+    let elapsed = started.elapsed().as_secs_f64();
+    info!(
+        "Replayed {} frames in {:.1}s ({:.0} frames/s): sent={}, send_errors={}, decode_errors={}, skipped={}",
+        stats.frames,
+        elapsed,
+        stats.frames as f64 / elapsed.max(0.001),
+        output_stats.frames_sent.load(Ordering::Relaxed),
+        output_stats.send_errors.load(Ordering::Relaxed),
+        stats.decode_errors,
+        stats.skipped,
+    );
+    Ok(())
+}
+
+/// Decode the packets of every file and hand them to the Redis output
+///
+/// With a non-zero speed, each packet is sent at its offset from the first
+/// packet of the pass, divided by the speed; every loop starts a new pass.
+async fn replay(args: &Args, frame_tx: mpsc::Sender<CapturedFrame>) -> Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+
+    for pass in 1..=args.loops {
+        let pass_start = Instant::now();
+        let mut first = None;
+
+        for path in &args.files {
+            let mut reader = PcapReader::open(path)?;
+            debug!("Replaying {:?} (pass {}/{})", path, pass, args.loops);
+
+            while let Some(packet) = reader.next_packet().with_context(|| format!("Failed to read {:?}", path))? {
+                if args.speed > 0.0 {
+                    let first = *first.get_or_insert(packet.timestamp);
+                    let offset = (packet.timestamp - first).to_std().unwrap_or_default();
+                    let due = pass_start + Duration::from_secs_f64(offset.as_secs_f64() / args.speed);
+                    if due > Instant::now() {
+                        tokio::time::sleep_until(due).await;
+                    }
+                }
+
+                let mut frame = match parse_frame(&args.interface, &packet.data) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        debug!("Undecodable packet in {:?}: {}", path, e);
+                        stats.decode_errors += 1;
+                        continue;
+                    }
+                };
+                if !args.rewrite_timestamps {
+                    frame.timestamp = packet.timestamp;
+                }
+
+                frame_tx.send(frame).await.map_err(|_| anyhow!("Redis output stopped"))?;
+                stats.frames += 1;
+                if stats.frames.is_multiple_of(100_000) {
+                    info!("Replayed {} frames", stats.frames);
+                }
+            }
+            stats.skipped += reader.skipped();
+        }
+    }
+
+    Ok(stats)
+}
@@ -1,19 +1,88 @@
 //! AF_PACKET capture using pnet for cross-platform compatibility
 //!
 //! This module provides high-performance packet capture using pnet's
-//! datalink layer, which uses AF_PACKET on Linux.
+//! datalink layer, which uses AF_PACKET on Linux. Busy interfaces can
+//! instead be read from a TPACKET_V3 ring buffer (see [`super::ring`]).
 
 use anyhow::{Context, Result, bail};
 use crossbeam_channel::{Sender, bounded};
-use pnet::datalink::{self, Channel, Config};
+use pnet::datalink::{self, Channel, Config, DataLinkReceiver};
+use std::io;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
-use super::frame::CapturedFrame;
+use netsentinel_types::CapturedFrame;
 use super::interface::NetworkInterface;
+use super::netns;
+use super::policy::Policy;
+use super::shed::{Admission, CaptureMode, Shedder};
+use super::stream::FrameStream;
+use super::filter::BpfInstruction;
+use super::ring::PacketRing;
+use super::socket::{PacketSocket, TimestampedPacket};
+use super::timestamp::TimestampSource;
 use crate::decode;
+use crate::config::{ReassemblyConfig, RingConfig, ShedConfig};
+use crate::decode::erspan::ErspanSessions;
+use crate::decode::reassembly::Reassembler;
+use crate::metrics::{self, metrics};
+
+/// Default size of the socket read buffer of a capture
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 65536;
+
+/// How long a read waits before the capture loop checks it was stopped
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Frames read between two checks of the kernel's drop counter
+const DROPS_CHECK_FRAMES: u64 = 65536;
+
+/// Destination of the frames decoded by a capture thread
+///
+/// Sending never blocks the capture: a full channel drops the frame.
+pub trait FrameSink: Send + 'static {
+    /// Offer a frame, returning false once the receiving side is gone
+    fn offer(&self, frame: CapturedFrame) -> bool;
+
+    /// Send a frame, waiting for room, returning false once the receiving
+    /// side is gone
+    fn deliver(&self, frame: CapturedFrame) -> bool;
+}
+
+impl FrameSink for Sender<CapturedFrame> {
+    fn offer(&self, frame: CapturedFrame) -> bool {
+        match self.try_send(frame) {
+            Ok(()) => true,
+            Err(crossbeam_channel::TrySendError::Full(_)) => {
+                debug!("Channel full, dropping frame");
+                true
+            }
+            Err(crossbeam_channel::TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    fn deliver(&self, frame: CapturedFrame) -> bool {
+        self.send(frame).is_ok()
+    }
+}
+
+impl FrameSink for tokio::sync::mpsc::Sender<CapturedFrame> {
+    fn offer(&self, frame: CapturedFrame) -> bool {
+        match self.try_send(frame) {
+            Ok(()) => true,
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                debug!("Channel full, dropping frame");
+                true
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn deliver(&self, frame: CapturedFrame) -> bool {
+        self.blocking_send(frame).is_ok()
+    }
+}
 
 /// Capture statistics
 #[derive(Debug, Default)]
@@ -26,6 +95,16 @@ pub struct CaptureStats {
     pub packets_dropped: AtomicU64,
     /// Parse errors
     pub parse_errors: AtomicU64,
+    /// Frames dropped undecoded while degraded
+    pub frames_shed: AtomicU64,
+    /// Frames dropped by the protocol policy
+    pub frames_denied: AtomicU64,
+    /// Switches between full and degraded decoding
+    pub mode_changes: AtomicU64,
+    /// Whether the capture is degraded
+    pub degraded: AtomicBool,
+    /// Average decode and enqueue time of a frame, in nanoseconds
+    pub frame_latency_ns: AtomicU64,
 }
 
 impl CaptureStats {
@@ -39,24 +118,72 @@ impl CaptureStats {
             bytes_captured: self.bytes_captured.load(Ordering::Relaxed),
             packets_dropped: self.packets_dropped.load(Ordering::Relaxed),
             parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            frames_shed: self.frames_shed.load(Ordering::Relaxed),
+            frames_denied: self.frames_denied.load(Ordering::Relaxed),
+            mode_changes: self.mode_changes.load(Ordering::Relaxed),
+            degraded: self.degraded.load(Ordering::Relaxed),
+            frame_latency_ns: self.frame_latency_ns.load(Ordering::Relaxed),
         }
     }
 }
 
 /// Snapshot of capture statistics (non-atomic copy)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CaptureStatsSnapshot {
     pub packets_captured: u64,
     pub bytes_captured: u64,
     pub packets_dropped: u64,
     pub parse_errors: u64,
+    pub frames_shed: u64,
+    pub frames_denied: u64,
+    pub mode_changes: u64,
+    pub degraded: bool,
+    pub frame_latency_ns: u64,
+}
+
+impl CaptureStatsSnapshot {
+    /// Add the statistics of another capture
+    ///
+    /// The combination is degraded when any capture is, with the highest
+    /// frame latency.
+    pub fn add(&mut self, other: &CaptureStatsSnapshot) {
+        self.packets_captured += other.packets_captured;
+        self.bytes_captured += other.bytes_captured;
+        self.packets_dropped += other.packets_dropped;
+        self.parse_errors += other.parse_errors;
+        self.frames_shed += other.frames_shed;
+        self.frames_denied += other.frames_denied;
+        self.mode_changes += other.mode_changes;
+        self.degraded |= other.degraded;
+        self.frame_latency_ns = self.frame_latency_ns.max(other.frame_latency_ns);
+    }
 }
 
 /// AF_PACKET based capture
 pub struct AfPacketCapture {
     interface: NetworkInterface,
+    /// Named network namespace of the interface, if not the process's own
+    netns: Option<String>,
+    /// Interface name frames and statistics are reported under
+    name: String,
     promiscuous: bool,
     snap_length: usize,
+    read_buffer_size: usize,
+    /// Frames buffered for this interface alone before the shared channel
+    channel_capacity: Option<usize>,
+    timestamp_source: TimestampSource,
+    /// Sessions of the ERSPAN traffic to decapsulate, if any
+    erspan: Option<Arc<ErspanSessions>>,
+    /// Latency budget, if frames are shed when over it
+    shed: Option<ShedConfig>,
+    /// Protocol policy of the decoded frames, if any
+    policy: Option<Arc<Policy>>,
+    /// BPF program run by the kernel on each frame, if any
+    filter: Option<Vec<BpfInstruction>>,
+    /// TCP reassembly of the connections on selected ports, if enabled
+    reassembly: Option<ReassemblyConfig>,
+    /// Ring buffer frames are read from, if not a socket
+    ring: Option<RingConfig>,
     stats: Arc<CaptureStats>,
     running: Arc<AtomicBool>,
 }
@@ -64,21 +191,121 @@ pub struct AfPacketCapture {
 impl AfPacketCapture {
     /// Create a new AF_PACKET capture instance
     pub fn new(interface_name: &str, promiscuous: bool, snap_length: usize) -> Result<Self> {
-        let interface = NetworkInterface::by_name(interface_name)?;
-        interface.validate_for_capture()?;
+        Self::open(interface_name, None, promiscuous, snap_length)
+    }
+
+    /// Create a capture of an interface of the named network namespace
+    /// `netns`, reported as `netns/interface`
+    pub fn new_in_netns(interface_name: &str, netns: &str, promiscuous: bool, snap_length: usize) -> Result<Self> {
+        Self::open(interface_name, Some(netns), promiscuous, snap_length)
+    }
+
+    fn open(interface_name: &str, netns: Option<&str>, promiscuous: bool, snap_length: usize) -> Result<Self> {
+        let interface = {
+            let _netns = netns.map(netns::enter).transpose()?;
+            let interface = NetworkInterface::by_name(interface_name)?;
+            interface.validate_for_capture()?;
+            interface
+        };
 
         Ok(Self {
+            name: netns::qualified_name(netns, &interface.name),
+            netns: netns.map(str::to_string),
             interface,
             promiscuous,
             snap_length,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            channel_capacity: None,
+            timestamp_source: TimestampSource::default(),
+            erspan: None,
+            shed: None,
+            policy: None,
+            filter: None,
+            reassembly: None,
+            ring: None,
             stats: Arc::new(CaptureStats::new()),
             running: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    /// Get the interface name
+    /// Size of the socket read buffer
+    pub fn with_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Read buffer size, raised to hold the largest frame of the interface
+    ///
+    /// Frames larger than the read buffer are clipped by the socket.
+    fn read_buffer_size(&self) -> usize {
+        match self.interface.max_frame_size() {
+            Some(max_frame) if max_frame > self.read_buffer_size => {
+                warn!(
+                    "Read buffer of interface '{}' raised from {} to {} bytes to hold frames of its MTU ({})",
+                    self.name, self.read_buffer_size, max_frame, self.interface.mtu.unwrap_or_default()
+                );
+                max_frame
+            }
+            _ => self.read_buffer_size,
+        }
+    }
+
+    /// Buffer up to `capacity` frames for this interface alone
+    ///
+    /// Frames then go through a channel of their own before the shared one,
+    /// so a burst on this interface only drops frames of this interface.
+    pub fn with_channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = Some(capacity);
+        self
+    }
+
+    /// Clock the frames are stamped with
+    pub fn with_timestamp_source(mut self, timestamp_source: TimestampSource) -> Self {
+        self.timestamp_source = timestamp_source;
+        self
+    }
+
+    /// Decapsulate ERSPAN traffic, tagging frames with their session
+    pub fn with_erspan(mut self, sessions: Arc<ErspanSessions>) -> Self {
+        self.erspan = Some(sessions);
+        self
+    }
+
+    /// Degrade decoding when frames take longer than the budget of `config`
+    pub fn with_shed(mut self, config: ShedConfig) -> Self {
+        self.shed = Some(config);
+        self
+    }
+
+    /// Only send the decoded frames the protocol policy admits
+    pub fn with_policy(mut self, policy: Arc<Policy>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Only capture the frames accepted by a compiled capture filter
+    pub fn with_filter(mut self, program: Vec<BpfInstruction>) -> Self {
+        self.filter = Some(program);
+        self
+    }
+
+    /// Decode the connections on the ports of `config` from their
+    /// reassembled TCP streams
+    pub fn with_reassembly(mut self, config: ReassemblyConfig) -> Self {
+        self.reassembly = Some(config);
+        self
+    }
+
+    /// Read frames from a TPACKET_V3 ring buffer of `config` rather than
+    /// from a socket
+    pub fn with_ring(mut self, config: RingConfig) -> Self {
+        self.ring = Some(config);
+        self
+    }
+
+    /// Get the interface name, prefixed with its network namespace if any
     pub fn interface_name(&self) -> &str {
-        &self.interface.name
+        &self.name
     }
 
     /// Get capture statistics
@@ -97,11 +324,16 @@ impl AfPacketCapture {
     }
 
     /// Start capture loop, sending frames to the provided channel
-    pub fn start(&self, frame_sender: Sender<CapturedFrame>) -> Result<()> {
+    ///
+    /// Returns when the capture is stopped or the channel is closed.
+    pub fn start<S: FrameSink>(&self, frame_sender: S) -> Result<()> {
         if self.running.swap(true, Ordering::SeqCst) {
-            bail!("Capture already running on interface {}", self.interface.name);
+            bail!("Capture already running on interface {}", self.name);
         }
 
+        // Sockets and ioctls of the interface are those of its namespace
+        let _netns = self.netns.as_deref().map(netns::enter).transpose()?;
+
         // Set promiscuous mode if requested
         if self.promiscuous {
             if let Err(e) = self.interface.set_promiscuous(true) {
@@ -109,52 +341,101 @@ impl AfPacketCapture {
             }
         }
 
-        // Create datalink channel
-        let config = Config {
-            read_timeout: Some(Duration::from_millis(100)),
-            write_buffer_size: 0, // We don't write
-            read_buffer_size: 65536,
-            ..Default::default()
-        };
-
-        // Find the pnet interface
-        let interfaces = datalink::interfaces();
-        let pnet_interface = interfaces
-            .into_iter()
-            .find(|i| i.name == self.interface.name)
-            .with_context(|| format!("Interface '{}' not found", self.interface.name))?;
-
-        let (_, mut rx) = match datalink::channel(&pnet_interface, config) {
-            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => bail!("Unhandled channel type"),
-            Err(e) => bail!("Failed to create datalink channel: {}", e),
+        // Open the ring, or the socket with room for the largest frame
+        let (mut rx, buffer) = match &self.ring {
+            Some(ring) => {
+                let rx = self.open_ring(ring)?;
+                (rx, format!("ring: {} blocks of {} bytes", ring.block_count, ring.block_size))
+            }
+            None => {
+                let read_buffer_size = self.read_buffer_size();
+                (self.open_receiver(read_buffer_size)?, format!("read buffer: {}", read_buffer_size))
+            }
         };
 
         info!(
-            "Started capture on interface '{}' (promiscuous: {})",
-            self.interface.name, self.promiscuous
+            "Started capture on interface '{}' (promiscuous: {}, snap length: {}, {}, timestamps: {})",
+            self.name, self.promiscuous, self.snap_length, buffer, self.timestamp_source
         );
+        if let Some(max_frame) = self.interface.max_frame_size().filter(|max| *max > self.snap_length) {
+            warn!(
+                "Snap length {} of interface '{}' is below its largest frames ({} bytes with MTU {}): \
+                 their end is not decoded",
+                self.snap_length, self.name, max_frame, self.interface.mtu.unwrap_or_default()
+            );
+        }
 
-        let interface_name = self.interface.name.clone();
+        let interface_name = self.name.clone();
         let stats = Arc::clone(&self.stats);
         let running = Arc::clone(&self.running);
+        let mut truncation_reported = false;
+        let mut shedder = self.shed.as_ref().map(Shedder::new);
+        let mut streams = self.reassembly.as_ref().map(Reassembler::new);
+        let mut frames_read: u64 = 0;
 
         // Capture loop
         while running.load(Ordering::SeqCst) {
+            // Frames the kernel could not queue, when it reports them
+            if frames_read.is_multiple_of(DROPS_CHECK_FRAMES) {
+                if let Some(dropped) = rx.dropped() {
+                    stats.packets_dropped.fetch_add(dropped, Ordering::Relaxed);
+                }
+            }
+
             match rx.next() {
-                Ok(packet) => {
-                    let frame_size = packet.len() as u32;
+                Ok(TimestampedPacket { data: packet, length, timestamp }) => {
+                    let frame_size = length as u32;
+                    frames_read += 1;
 
                     // Update stats
                     stats.packets_captured.fetch_add(1, Ordering::Relaxed);
                     stats.bytes_captured.fetch_add(frame_size as u64, Ordering::Relaxed);
 
-                    // Decode the frame
-                    match decode::parse_frame(&interface_name, packet) {
-                        Ok(frame) => {
-                            // Send to channel (non-blocking)
-                            if let Err(e) = frame_sender.try_send(frame) {
-                                debug!("Channel full, dropping frame: {}", e);
+                    // Decode the frame, past the snap length only the size counts
+                    if length > self.snap_length && !truncation_reported {
+                        warn!(
+                            "Frame of {} bytes on interface '{}' truncated to the snap length of {}",
+                            length, interface_name, self.snap_length
+                        );
+                        truncation_reported = true;
+                    }
+                    let snapped = &packet[..packet.len().min(self.snap_length)];
+                    let clipped = (length - snapped.len()) as u32;
+
+                    // Over the latency budget, shed frames and their metadata
+                    let admission = shedder.as_mut().map_or(Admission::Full, Shedder::admit);
+                    if admission == Admission::Shed {
+                        stats.frames_shed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let started = Instant::now();
+
+                    // Remote mirrors: decode the frame inside the ERSPAN packet
+                    let erspan = self.erspan.as_ref().and_then(|_| decode::erspan::decapsulate(snapped));
+                    let data = erspan.as_ref().map_or(snapped, |erspan| erspan.frame);
+
+                    let decoded = match (admission, streams.as_mut()) {
+                        (Admission::Full, Some(streams)) => decode::parse_frame_reassembled(&interface_name, data, streams),
+                        (Admission::Full, None) => decode::parse_frame(&interface_name, data),
+                        _ => decode::parse_headers(&interface_name, data),
+                    };
+                    match decoded {
+                        Ok(mut frame) => {
+                            // Decoding counted the bytes it was given
+                            frame.frame_size += clipped;
+                            if let (Some(sessions), Some(erspan)) = (&self.erspan, &erspan) {
+                                sessions.tag(&mut frame, erspan);
+                            }
+                            // Decoding stamped the frame with the userspace clock
+                            if let Some(timestamp) = timestamp {
+                                frame.timestamp = timestamp;
+                            }
+                            // Send to channel (non-blocking), unless the policy drops it
+                            if self.policy.as_ref().is_some_and(|policy| !policy.admit(&frame)) {
+                                stats.frames_denied.fetch_add(1, Ordering::Relaxed);
+                            } else if !frame_sender.offer(frame) {
+                                warn!("Frame channel closed on interface '{}'", interface_name);
+                                break;
                             }
                         }
                         Err(e) => {
@@ -162,13 +443,38 @@ impl AfPacketCapture {
                             debug!("Failed to parse frame: {}", e);
                         }
                     }
+
+                    if let Some(shedder) = shedder.as_mut() {
+                        let now = Instant::now();
+                        let transition = shedder.record(now - started, now);
+                        stats.frame_latency_ns.store(shedder.average().as_nanos() as u64, Ordering::Relaxed);
+                        if let Some(mode) = transition {
+                            stats.mode_changes.fetch_add(1, Ordering::Relaxed);
+                            stats.degraded.store(mode == CaptureMode::Degraded, Ordering::Relaxed);
+                            match mode {
+                                CaptureMode::Degraded => warn!(
+                                    "Capture on '{}' over its latency budget ({:?} per frame): decoding headers only, sampling frames",
+                                    interface_name, shedder.average()
+                                ),
+                                CaptureMode::Full => info!(
+                                    "Capture on '{}' back within its latency budget ({:?} per frame): full decoding",
+                                    interface_name, shedder.average()
+                                ),
+                            }
+                        }
+                    }
                 }
                 Err(e) => {
                     // Timeout is expected, other errors should be logged
                     let err_str = e.to_string().to_lowercase();
-                    if !err_str.contains("timed out") && !err_str.contains("timeout") {
+                    let timed_out = matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted)
+                        || err_str.contains("timed out")
+                        || err_str.contains("timeout");
+                    if !timed_out {
                         error!("Error receiving packet: {}", e);
                     }
+                    // Check the drop counter on the next turn
+                    frames_read = 0;
                 }
             }
         }
@@ -180,10 +486,73 @@ impl AfPacketCapture {
             }
         }
 
-        info!("Capture stopped on interface '{}'", self.interface.name);
+        info!("Capture stopped on interface '{}'", self.name);
         Ok(())
     }
 
+    /// Open the socket frames are read from
+    ///
+    /// pnet's datalink channel exposes neither receive timestamps nor its
+    /// socket options: kernel timestamps and capture filters need a socket
+    /// of our own.
+    fn open_receiver(&self, read_buffer_size: usize) -> Result<Receiver> {
+        let kernel_timestamps = self.timestamp_source == TimestampSource::Kernel;
+        if kernel_timestamps || self.filter.is_some() {
+            let mut socket = PacketSocket::new(read_buffer_size, READ_TIMEOUT)?;
+            if kernel_timestamps {
+                socket = socket.with_kernel_timestamps()?;
+            }
+            if let Some(program) = &self.filter {
+                socket = socket.with_filter(program)?;
+                info!("Capture filter of {} instructions attached on '{}'", program.len(), self.name);
+            }
+            let socket = socket
+                .bind(self.interface.index)
+                .with_context(|| format!("Failed to open packet socket on '{}'", self.name))?;
+            return Ok(Receiver::Socket(socket));
+        }
+
+        let config = Config {
+            read_timeout: Some(READ_TIMEOUT),
+            write_buffer_size: 0, // We don't write
+            read_buffer_size,
+            ..Default::default()
+        };
+
+        // Find the pnet interface
+        let interfaces = datalink::interfaces();
+        let pnet_interface = interfaces
+            .into_iter()
+            .find(|i| i.name == self.interface.name)
+            .with_context(|| format!("Interface '{}' not found", self.interface.name))?;
+
+        match datalink::channel(&pnet_interface, config) {
+            Ok(Channel::Ethernet(_, rx)) => Ok(Receiver::Datalink(rx)),
+            Ok(_) => bail!("Unhandled channel type"),
+            Err(e) => bail!("Failed to create datalink channel: {}", e),
+        }
+    }
+
+    /// Open the ring buffer frames are read from
+    ///
+    /// The kernel stamps the frames of the ring whatever the timestamp
+    /// source, and the filter runs before frames are written to it.
+    fn open_ring(&self, config: &RingConfig) -> Result<Receiver> {
+        let mut ring = PacketRing::new(config, READ_TIMEOUT)
+            .with_context(|| format!("Failed to open packet ring on '{}'", self.name))?;
+        if self.timestamp_source == TimestampSource::Kernel {
+            ring = ring.with_kernel_timestamps();
+        }
+        if let Some(program) = &self.filter {
+            ring = ring.with_filter(program)?;
+            info!("Capture filter of {} instructions attached on '{}'", program.len(), self.name);
+        }
+        let ring = ring
+            .bind(self.interface.index)
+            .with_context(|| format!("Failed to open packet ring on '{}'", self.name))?;
+        Ok(Receiver::Ring(Box::new(ring)))
+    }
+
     /// Start capture in a new thread
     pub fn start_threaded(self: Arc<Self>, buffer_size: usize) -> Result<(std::thread::JoinHandle<()>, crossbeam_channel::Receiver<CapturedFrame>)> {
         let (tx, rx) = bounded(buffer_size);
@@ -197,6 +566,23 @@ impl AfPacketCapture {
 
         Ok((handle, rx))
     }
+
+    /// Start capture in a new thread, yielding the frames as an async stream
+    ///
+    /// The capture stops when the stream is dropped.
+    pub fn into_stream(self, buffer_size: usize) -> FrameStream {
+        let capture = Arc::new(self);
+        let (tx, rx) = tokio::sync::mpsc::channel(buffer_size);
+
+        let thread = Arc::clone(&capture);
+        std::thread::spawn(move || {
+            if let Err(e) = thread.start(tx) {
+                error!("Capture thread error: {}", e);
+            }
+        });
+
+        FrameStream::new(vec![capture], rx)
+    }
 }
 
 impl Drop for AfPacketCapture {
@@ -205,7 +591,37 @@ impl Drop for AfPacketCapture {
     }
 }
 
+/// Socket a capture reads its frames from
+enum Receiver {
+    /// pnet datalink channel, frames are stamped when decoded
+    Datalink(Box<dyn DataLinkReceiver>),
+    /// Packet socket of our own, filtered or returning kernel timestamps
+    /// (only when enabled)
+    Socket(PacketSocket),
+    /// TPACKET_V3 ring buffer, frames are read in place
+    Ring(Box<PacketRing>),
+}
+
+impl Receiver {
+    fn next(&mut self) -> io::Result<TimestampedPacket<'_>> {
+        match self {
+            Self::Datalink(rx) => rx.next().map(|data| TimestampedPacket { data, length: data.len(), timestamp: None }),
+            Self::Socket(socket) => socket.receive(),
+            Self::Ring(ring) => ring.receive(),
+        }
+    }
+
+    /// Frames dropped by the kernel since the previous call, if it tells
+    fn dropped(&self) -> Option<u64> {
+        match self {
+            Self::Ring(ring) => ring.dropped().ok(),
+            _ => None,
+        }
+    }
+}
+
 /// Multi-interface capture manager
+#[derive(Default)]
 pub struct MultiCapture {
     captures: Vec<Arc<AfPacketCapture>>,
     running: Arc<AtomicBool>,
@@ -222,36 +638,74 @@ impl MultiCapture {
     /// Add an interface to capture
     pub fn add_interface(&mut self, name: &str, promiscuous: bool, snap_length: usize) -> Result<()> {
         let capture = AfPacketCapture::new(name, promiscuous, snap_length)?;
-        self.captures.push(Arc::new(capture));
+        self.add_capture(capture);
         Ok(())
     }
 
+    /// Add an interface capture with its own settings
+    pub fn add_capture(&mut self, capture: AfPacketCapture) {
+        self.captures.push(Arc::new(capture));
+    }
+
     /// Start all captures
     pub fn start_all(&self, buffer_size: usize) -> Result<(Vec<std::thread::JoinHandle<()>>, crossbeam_channel::Receiver<CapturedFrame>)> {
+        // Create a single channel for all captures
+        let (tx, rx) = bounded(buffer_size);
+        let handles = self.start_with(tx)?;
+
+        Ok((handles, rx))
+    }
+
+    /// Start all captures, each thread sending its frames to a clone of `sink`
+    pub fn start_with<S: FrameSink + Clone>(&self, sink: S) -> Result<Vec<std::thread::JoinHandle<()>>> {
         if self.captures.is_empty() {
             bail!("No interfaces configured for capture");
         }
 
         self.running.store(true, Ordering::SeqCst);
 
-        // Create a single channel for all captures
-        let (tx, rx) = bounded(buffer_size);
         let mut handles = Vec::new();
 
         for capture in &self.captures {
             let cap = Arc::clone(capture);
-            let sender = tx.clone();
 
-            let handle = std::thread::spawn(move || {
-                if let Err(e) = cap.start(sender) {
-                    error!("Capture error on {}: {}", cap.interface_name(), e);
+            let handle = match capture.channel_capacity {
+                None => {
+                    let sender = sink.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = cap.start(sender) {
+                            error!("Capture error on {}: {}", cap.interface_name(), e);
+                        }
+                    })
                 }
-            });
+                Some(capacity) => {
+                    // The interface channel absorbs bursts, the forwarder
+                    // waits for room in the shared one
+                    let (tx, rx) = bounded(capacity);
+                    let sender = sink.clone();
+                    let interface = capture.interface_name().to_string();
+                    metrics().open_channel(metrics::Channel::Interface(&interface), capacity);
+                    let forwarder = std::thread::spawn(move || {
+                        for frame in rx.iter() {
+                            metrics().sample_channel(metrics::Channel::Interface(&interface), rx.len());
+                            if !sender.deliver(frame) {
+                                break;
+                            }
+                        }
+                    });
+                    handles.push(forwarder);
+                    std::thread::spawn(move || {
+                        if let Err(e) = cap.start(tx) {
+                            error!("Capture error on {}: {}", cap.interface_name(), e);
+                        }
+                    })
+                }
+            };
 
             handles.push(handle);
         }
 
-        Ok((handles, rx))
+        Ok(handles)
     }
 
     /// Stop all captures
@@ -262,23 +716,20 @@ impl MultiCapture {
         }
     }
 
+    /// Get the statistics of each capture, by interface name
+    pub fn interface_stats(&self) -> Vec<(String, Arc<CaptureStats>)> {
+        self.captures
+            .iter()
+            .map(|capture| (capture.interface_name().to_string(), capture.stats()))
+            .collect()
+    }
+
     /// Get combined statistics from all captures
     pub fn combined_stats(&self) -> CaptureStatsSnapshot {
-        let mut combined = CaptureStatsSnapshot {
-            packets_captured: 0,
-            bytes_captured: 0,
-            packets_dropped: 0,
-            parse_errors: 0,
-        };
-
+        let mut combined = CaptureStatsSnapshot::default();
         for capture in &self.captures {
-            let stats = capture.stats().snapshot();
-            combined.packets_captured += stats.packets_captured;
-            combined.bytes_captured += stats.bytes_captured;
-            combined.packets_dropped += stats.packets_dropped;
-            combined.parse_errors += stats.parse_errors;
+            combined.add(&capture.stats().snapshot());
         }
-
         combined
     }
 }
@@ -0,0 +1,204 @@
+//! Embedding API
+//!
+//! Capture and decoding for other Rust services, without the Redis output or
+//! the binary: the builder opens the interfaces and the returned handle
+//! yields decoded frames.
+//!
+//! ```no_run
+//! use netsentinel_capture::CaptureBuilder;
+//!
+//! let capture = CaptureBuilder::interface("eth0").promiscuous(true).build()?;
+//! for frame in capture {
+//!     println!("{} -> {} ({} bytes)", frame.src_mac, frame.dst_mac, frame.frame_size);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+use crossbeam_channel::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+
+use netsentinel_types::CapturedFrame;
+use super::filter;
+use super::af_packet::{AfPacketCapture, CaptureStats, CaptureStatsSnapshot, MultiCapture, DEFAULT_READ_BUFFER_SIZE};
+use super::timestamp::TimestampSource;
+use crate::config::CaptureFilter;
+
+/// Builder of an embedded capture
+#[derive(Debug, Clone)]
+pub struct CaptureBuilder {
+    interfaces: Vec<String>,
+    promiscuous: bool,
+    snap_length: usize,
+    read_buffer_size: usize,
+    timestamp_source: TimestampSource,
+    filter: Option<CaptureFilter>,
+    buffer_size: usize,
+}
+
+impl CaptureBuilder {
+    /// Capture on a single interface
+    pub fn interface(name: &str) -> Self {
+        Self::interfaces([name])
+    }
+
+    /// Capture on several interfaces, merged in one frame stream
+    pub fn interfaces<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            interfaces: names.into_iter().map(Into::into).collect(),
+            promiscuous: false,
+            snap_length: 1518,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            timestamp_source: TimestampSource::default(),
+            filter: None,
+            buffer_size: 8192,
+        }
+    }
+
+    /// Put the interfaces in promiscuous mode while capturing (off by default)
+    pub fn promiscuous(mut self, promiscuous: bool) -> Self {
+        self.promiscuous = promiscuous;
+        self
+    }
+
+    /// Maximum frame size to capture (1518 bytes by default)
+    pub fn snap_length(mut self, snap_length: usize) -> Self {
+        self.snap_length = snap_length;
+        self
+    }
+
+    /// Socket read buffer size of each interface (64 KiB by default)
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Clock frames are stamped with (decode time by default)
+    pub fn timestamp_source(mut self, timestamp_source: TimestampSource) -> Self {
+        self.timestamp_source = timestamp_source;
+        self
+    }
+
+    /// Only capture the frames matching `filter` (all frames by default)
+    pub fn filter(mut self, filter: CaptureFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Decoded frames buffered before new ones are dropped (8192 by default)
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Open the interfaces and start capturing
+    pub fn build(self) -> Result<CaptureHandle> {
+        let program = self.filter.as_ref().map(filter::compile).transpose()?;
+        let mut capture = MultiCapture::new();
+        for name in &self.interfaces {
+            let mut interface = AfPacketCapture::new(name, self.promiscuous, self.snap_length)?
+                .with_read_buffer_size(self.read_buffer_size)
+                .with_timestamp_source(self.timestamp_source);
+            if let Some(program) = &program {
+                interface = interface.with_filter(program.clone());
+            }
+            capture.add_capture(interface);
+        }
+        let (threads, frames) = capture.start_all(self.buffer_size)?;
+
+        Ok(CaptureHandle { capture, threads, frames })
+    }
+}
+
+/// Running embedded capture
+///
+/// Iterating blocks until the next decoded frame and ends once the capture
+/// is stopped and the buffered frames are consumed. Dropping the handle
+/// stops the capture.
+pub struct CaptureHandle {
+    capture: MultiCapture,
+    threads: Vec<std::thread::JoinHandle<()>>,
+    frames: Receiver<CapturedFrame>,
+}
+
+impl CaptureHandle {
+    /// Wait up to `timeout` for the next frame
+    ///
+    /// Returns `None` on timeout as well as once the capture has stopped.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<CapturedFrame> {
+        self.frames.recv_timeout(timeout).ok()
+    }
+
+    /// Next frame if one is already buffered
+    pub fn try_next(&self) -> Option<CapturedFrame> {
+        self.frames.try_recv().ok()
+    }
+
+    /// Channel of decoded frames, for use with `crossbeam_channel::select!`
+    pub fn frames(&self) -> &Receiver<CapturedFrame> {
+        &self.frames
+    }
+
+    /// Statistics of each interface, by name
+    pub fn interface_stats(&self) -> Vec<(String, Arc<CaptureStats>)> {
+        self.capture.interface_stats()
+    }
+
+    /// Combined statistics of all interfaces
+    pub fn stats(&self) -> CaptureStatsSnapshot {
+        self.capture.combined_stats()
+    }
+
+    /// Stop capturing and wait for the capture threads
+    ///
+    /// Frames already buffered can still be read afterwards.
+    pub fn stop(&mut self) {
+        self.capture.stop_all();
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Iterator for CaptureHandle {
+    type Item = CapturedFrame;
+
+    fn next(&mut self) -> Option<CapturedFrame> {
+        self.frames.recv().ok()
+    }
+}
+
+impl Drop for CaptureHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_options() {
+        let builder = CaptureBuilder::interface("eth0").promiscuous(true).snap_length(9000);
+        assert_eq!(builder.interfaces, vec!["eth0".to_string()]);
+        assert!(builder.promiscuous);
+        assert_eq!(builder.snap_length, 9000);
+        assert_eq!(builder.buffer_size, 8192);
+
+        let builder = CaptureBuilder::interfaces(["eth0", "eth1"]);
+        assert_eq!(builder.interfaces.len(), 2);
+        assert!(!builder.promiscuous);
+    }
+
+    #[test]
+    fn test_build_unknown_interface() {
+        assert!(CaptureBuilder::interface("nonexistent0").build().is_err());
+        assert!(CaptureBuilder::interfaces(Vec::<String>::new()).build().is_err());
+    }
+}
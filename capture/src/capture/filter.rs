@@ -0,0 +1,735 @@
+//! Capture filters
+//!
+//! The `filter` setting of the configuration is a small structured DSL
+//! (`{ vlan = [10], proto = ["tcp"], not_port = [6379] }`) compiled here
+//! into a classic BPF program, which the kernel runs on the interface
+//! socket: frames filtered out are never copied to the capture.
+//!
+//! The program first reads the fields the DSL can test into scratch memory
+//! (VLAN ID, EtherType, IP protocol, addresses and ports, or a marker when
+//! the frame has none), then checks each list in turn. VLAN tags are read
+//! from the packet metadata when the NIC strips them, or from the frame.
+//!
+//! Alongside the lists, `expr` takes a tcpdump-style expression over the
+//! same fields (`vlan 10 and (tcp port 443 or udp)`), parsed here into tests
+//! on the scratch memory. Filters neither can express take a program
+//! compiled by tcpdump instead (`bpf`, in the `tcpdump -ddd` format), checked
+//! here before the kernel verifies it.
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+
+use crate::config::CaptureFilter;
+
+/// Classic BPF instruction (`struct sock_filter`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// Protocols a filter can select
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterProtocol {
+    Arp,
+    Ipv4,
+    Ipv6,
+    Icmp,
+    Icmp6,
+    Tcp,
+    Udp,
+    Gre,
+    Sctp,
+}
+
+impl FilterProtocol {
+    /// Scratch slot and value identifying the protocol
+    fn test(self) -> (u32, u32) {
+        match self {
+            Self::Arp => (ETHERTYPE, 0x0806),
+            Self::Ipv4 => (ETHERTYPE, 0x0800),
+            Self::Ipv6 => (ETHERTYPE, 0x86DD),
+            Self::Icmp => (PROTO, 1),
+            Self::Icmp6 => (PROTO, 58),
+            Self::Tcp => (PROTO, 6),
+            Self::Udp => (PROTO, 17),
+            Self::Gre => (PROTO, 47),
+            Self::Sctp => (PROTO, 132),
+        }
+    }
+}
+
+// Instruction classes, sizes, modes and operations
+const LD: u16 = 0x00;
+const LDX: u16 = 0x01;
+const ST: u16 = 0x02;
+const ALU: u16 = 0x04;
+const JMP: u16 = 0x05;
+const RET: u16 = 0x06;
+const MISC: u16 = 0x07;
+const W: u16 = 0x00;
+const H: u16 = 0x08;
+const B: u16 = 0x10;
+const IMM: u16 = 0x00;
+const ABS: u16 = 0x20;
+const IND: u16 = 0x40;
+const MEM: u16 = 0x60;
+const ADD: u16 = 0x00;
+const AND: u16 = 0x50;
+const LSH: u16 = 0x60;
+const JA: u16 = 0x00;
+const JEQ: u16 = 0x10;
+const JSET: u16 = 0x40;
+const X: u16 = 0x08;
+const TAX: u16 = 0x00;
+const TXA: u16 = 0x80;
+
+// Packet metadata loads (SKF_AD_OFF + SKF_AD_VLAN_TAG / _PRESENT)
+const AD_VLAN_TAG: u32 = 0xFFFF_F000 + 44;
+const AD_VLAN_TAG_PRESENT: u32 = 0xFFFF_F000 + 48;
+
+// Scratch memory slots
+const VLAN: u32 = 0;
+const ETHERTYPE: u32 = 1;
+const PROTO: u32 = 2;
+const SRC_IP: u32 = 3;
+const DST_IP: u32 = 4;
+const SRC_PORT: u32 = 5;
+const DST_PORT: u32 = 6;
+
+/// Value of a scratch slot the frame has no field for
+const NONE: u32 = u32::MAX;
+
+/// Entries allowed in one list, keeping jumps within the 8-bit offsets of
+/// conditional instructions
+pub const MAX_FILTER_ENTRIES: usize = 64;
+
+/// Instructions the kernel accepts in a program (`BPF_MAXINSNS`)
+const MAX_INSTRUCTIONS: usize = 4096;
+
+/// Compile `filter` into a BPF program accepting the frames it selects
+pub fn compile(filter: &CaptureFilter) -> Result<Vec<BpfInstruction>> {
+    if let Some(program) = &filter.bpf {
+        return parse_program(program);
+    }
+
+    let mut asm = Assembler::default();
+    prologue(&mut asm);
+
+    let slots = |slots: &[u32], values: &[u32]| -> Vec<(u32, u32)> {
+        slots.iter().flat_map(|slot| values.iter().map(move |value| (*slot, *value))).collect()
+    };
+    let vlans: Vec<u32> = filter.vlan.iter().map(|v| *v as u32).collect();
+    let not_vlans: Vec<u32> = filter.not_vlan.iter().map(|v| *v as u32).collect();
+    let hosts: Vec<u32> = filter.host.iter().map(|ip| u32::from(*ip)).collect();
+    let not_hosts: Vec<u32> = filter.not_host.iter().map(|ip| u32::from(*ip)).collect();
+    let ports: Vec<u32> = filter.port.iter().map(|p| *p as u32).collect();
+    let not_ports: Vec<u32> = filter.not_port.iter().map(|p| *p as u32).collect();
+
+    let lists = [
+        (slots(&[VLAN], &vlans), false),
+        (slots(&[VLAN], &not_vlans), true),
+        (filter.proto.iter().map(|p| p.test()).collect(), false),
+        (filter.not_proto.iter().map(|p| p.test()).collect(), true),
+        (slots(&[SRC_IP, DST_IP], &hosts), false),
+        (slots(&[SRC_IP, DST_IP], &not_hosts), true),
+        (slots(&[SRC_PORT, DST_PORT], &ports), false),
+        (slots(&[SRC_PORT, DST_PORT], &not_ports), true),
+    ];
+    for (tests, negate) in lists {
+        if !tests.is_empty() {
+            any_of(&mut asm, &tests, negate);
+        }
+    }
+    if let Some(expr) = &filter.expr {
+        let expr = parse_expr(expr)?;
+        let (accepted, rejected) = (asm.label(), asm.label());
+        branch(&mut asm, &expr, accepted, rejected);
+        asm.place(rejected);
+        asm.stmt(RET, 0);
+        asm.place(accepted);
+    }
+
+    // Accept the whole frame
+    asm.stmt(RET, u32::MAX);
+    asm.assemble()
+}
+
+/// Parse a program in the `tcpdump -ddd` format: its instruction count,
+/// then `code jt jf k` in decimal for each instruction, one per line or
+/// separated by commas
+pub fn parse_program(text: &str) -> Result<Vec<BpfInstruction>> {
+    let mut lines = text.split(['\n', ',']).map(str::trim).filter(|line| !line.is_empty());
+    let count: usize = lines
+        .next()
+        .context("Capture filter program is empty")?
+        .parse()
+        .context("Capture filter program must start with its instruction count")?;
+
+    let program = lines
+        .enumerate()
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match fields[..] {
+                [code, jt, jf, k] => (|| Some(BpfInstruction {
+                    code: code.parse().ok()?,
+                    jt: jt.parse().ok()?,
+                    jf: jf.parse().ok()?,
+                    k: k.parse().ok()?,
+                }))(),
+                _ => None,
+            };
+            parsed.with_context(|| format!("Capture filter instruction {} is not 'code jt jf k': {}", i, line))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if program.len() != count {
+        bail!("Capture filter program declares {} instructions but has {}", count, program.len());
+    }
+    if program.is_empty() || program.len() > MAX_INSTRUCTIONS {
+        bail!("Capture filter program must have 1 to {} instructions", MAX_INSTRUCTIONS);
+    }
+    // Jumps stay within the program, which ends with a return
+    for (i, insn) in program.iter().enumerate() {
+        if insn.code & 0x07 != JMP {
+            continue;
+        }
+        let farthest = if insn.code & 0xF0 == JA { insn.k as usize } else { insn.jt.max(insn.jf) as usize };
+        if i + 1 + farthest >= program.len() {
+            bail!("Capture filter instruction {} jumps past the end of the program", i);
+        }
+    }
+    if program[program.len() - 1].code & 0x07 != RET {
+        bail!("Capture filter program must end with a return");
+    }
+    Ok(program)
+}
+
+/// Node of a filter expression
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    /// One of the `(slot, value)` tests holds
+    Any(Vec<(u32, u32)>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Parse a tcpdump-style expression: `vlan [ID]`, `[src|dst] host IPV4`,
+/// `[src|dst] port PORT` and protocol names (optionally followed by a port,
+/// as in `tcp port 443`), combined with `and`/`&&`, `or`/`||`, `not`/`!`
+/// and parentheses
+fn parse_expr(text: &str) -> Result<Expr> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let length = if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else if rest.starts_with(['(', ')', '!']) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || "()!&|".contains(c)).unwrap_or(rest.len()).max(1)
+        };
+        tokens.push(&rest[..length]);
+        rest = &rest[length..];
+    }
+
+    let mut parser = ExprParser { tokens, next: 0 };
+    let expr = parser.or()?;
+    if let Some(token) = parser.peek() {
+        bail!("Capture filter expression has an unexpected '{}'", token);
+    }
+    Ok(expr)
+}
+
+/// Recursive descent over the tokens of an expression, `not` binding
+/// tighter than `and`, and `and` than `or`
+struct ExprParser<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn take(&mut self) -> Result<&'a str> {
+        let token = self.peek().context("Capture filter expression ends early")?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.next += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.next += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        match self.take()? {
+            "not" | "!" => Ok(Expr::Not(Box::new(self.not()?))),
+            "(" => {
+                let expr = self.or()?;
+                if self.take()? != ")" {
+                    bail!("Capture filter expression misses a ')'");
+                }
+                Ok(expr)
+            }
+            token => self.primitive(token),
+        }
+    }
+
+    fn primitive(&mut self, token: &str) -> Result<Expr> {
+        let number = |token: Option<&str>| token.and_then(|t| t.parse::<u16>().ok());
+
+        match token {
+            "vlan" => match number(self.peek()) {
+                Some(vlan) if vlan > 4095 => bail!("Filter VLAN {} must be between 0 and 4095", vlan),
+                Some(vlan) => {
+                    self.next += 1;
+                    Ok(Expr::Any(vec![(VLAN, vlan as u32)]))
+                }
+                // Any tag
+                None => Ok(Expr::Not(Box::new(Expr::Any(vec![(VLAN, NONE)])))),
+            },
+            "src" | "dst" | "host" | "port" => {
+                let (slots, kind) = match token {
+                    "src" => (vec![SRC_IP, SRC_PORT], self.take()?),
+                    "dst" => (vec![DST_IP, DST_PORT], self.take()?),
+                    _ => (vec![SRC_IP, DST_IP, SRC_PORT, DST_PORT], token),
+                };
+                let value = self.take()?;
+                let (slots, value) = match kind {
+                    "host" => {
+                        let host: Ipv4Addr = value
+                            .parse()
+                            .with_context(|| format!("Capture filter host '{}' is not an IPv4 address", value))?;
+                        (&slots[..slots.len() / 2], u32::from(host))
+                    }
+                    "port" => {
+                        let port = number(Some(value))
+                            .with_context(|| format!("Capture filter port '{}' is not a port number", value))?;
+                        (&slots[slots.len() / 2..], port as u32)
+                    }
+                    _ => bail!("Capture filter expects 'host' or 'port' after '{}', not '{}'", token, kind),
+                };
+                Ok(Expr::Any(slots.iter().map(|slot| (*slot, value)).collect()))
+            }
+            _ => {
+                let protocol = match token {
+                    "ip" => FilterProtocol::Ipv4,
+                    "ip6" => FilterProtocol::Ipv6,
+                    _ => serde_json::from_value(serde_json::Value::from(token))
+                        .map_err(|_| anyhow::anyhow!("Capture filter expression has an unknown term '{}'", token))?,
+                };
+                let expr = Expr::Any(vec![protocol.test()]);
+                // `tcp port 443`, `udp src port 53`
+                match self.peek() {
+                    Some(next @ ("src" | "dst" | "port")) => {
+                        self.next += 1;
+                        let port = self.primitive(next)?;
+                        Ok(Expr::And(Box::new(expr), Box::new(port)))
+                    }
+                    _ => Ok(expr),
+                }
+            }
+        }
+    }
+}
+
+/// Jump to `on_true` when `expr` holds for the frame, to `on_false` otherwise
+///
+/// Conditional jumps only reach a label placed right after the tests, long
+/// jumps (`ja`) the targets, so expressions of any size fit the 8-bit
+/// offsets.
+fn branch(asm: &mut Assembler, expr: &Expr, on_true: usize, on_false: usize) {
+    match expr {
+        Expr::Any(tests) => {
+            let matched = asm.label();
+            let mut loaded = None;
+            for (slot, value) in tests {
+                if loaded != Some(*slot) {
+                    asm.stmt(LD | MEM, *slot);
+                    loaded = Some(*slot);
+                }
+                asm.jump(JEQ, *value, Some(matched), None);
+            }
+            asm.ja(on_false);
+            asm.place(matched);
+            asm.ja(on_true);
+        }
+        Expr::Not(expr) => branch(asm, expr, on_false, on_true),
+        Expr::And(left, right) => {
+            let next = asm.label();
+            branch(asm, left, next, on_false);
+            asm.place(next);
+            branch(asm, right, on_true, on_false);
+        }
+        Expr::Or(left, right) => {
+            let next = asm.label();
+            branch(asm, left, on_true, next);
+            asm.place(next);
+            branch(asm, right, on_true, on_false);
+        }
+    }
+}
+
+/// Read the fields of the frame into scratch memory
+fn prologue(asm: &mut Assembler) {
+    let (inline, untagged, ethertype, ipv4, ipv6, l4, ports, done) =
+        (asm.label(), asm.label(), asm.label(), asm.label(), asm.label(), asm.label(), asm.label(), asm.label());
+
+    // Fields the frame may not have
+    asm.stmt(LD | IMM, NONE);
+    for slot in [PROTO, SRC_IP, DST_IP, SRC_PORT, DST_PORT] {
+        asm.stmt(ST, slot);
+    }
+
+    // X: offset of the network header; A: EtherType
+    asm.stmt(LDX | W | IMM, 14);
+    asm.stmt(LD | W | ABS, AD_VLAN_TAG_PRESENT);
+    asm.jump(JEQ, 0, Some(inline), None);
+    // Tag stripped by the NIC, kept in the packet metadata
+    asm.stmt(LD | W | ABS, AD_VLAN_TAG);
+    asm.stmt(ALU | AND, 0x0FFF);
+    asm.stmt(ST, VLAN);
+    asm.stmt(LD | H | ABS, 12);
+    asm.ja(ethertype);
+
+    asm.place(inline);
+    asm.stmt(LD | H | ABS, 12);
+    asm.jump(JEQ, 0x8100, None, Some(untagged));
+    asm.stmt(LD | H | ABS, 14);
+    asm.stmt(ALU | AND, 0x0FFF);
+    asm.stmt(ST, VLAN);
+    asm.stmt(LDX | W | IMM, 18);
+    asm.stmt(LD | H | ABS, 16);
+    asm.ja(ethertype);
+
+    asm.place(untagged);
+    asm.stmt(ST, ETHERTYPE);
+    asm.stmt(LD | IMM, NONE);
+    asm.stmt(ST, VLAN);
+    asm.stmt(LD | MEM, ETHERTYPE);
+
+    asm.place(ethertype);
+    asm.stmt(ST, ETHERTYPE);
+    asm.jump(JEQ, 0x0800, Some(ipv4), None);
+    asm.jump(JEQ, 0x86DD, Some(ipv6), Some(done));
+
+    asm.place(ipv4);
+    asm.stmt(LD | B | IND, 9);
+    asm.stmt(ST, PROTO);
+    asm.stmt(LD | W | IND, 12);
+    asm.stmt(ST, SRC_IP);
+    asm.stmt(LD | W | IND, 16);
+    asm.stmt(ST, DST_IP);
+    // Only first fragments carry the ports
+    asm.stmt(LD | H | IND, 6);
+    asm.jump(JSET, 0x1FFF, Some(done), None);
+    asm.stmt(LD | B | IND, 0);
+    asm.stmt(ALU | AND, 0x0F);
+    asm.stmt(ALU | LSH, 2);
+    asm.stmt(ALU | ADD | X, 0);
+    asm.stmt(MISC | TAX, 0);
+    asm.ja(l4);
+
+    // Without extension headers
+    asm.place(ipv6);
+    asm.stmt(LD | B | IND, 6);
+    asm.stmt(ST, PROTO);
+    asm.stmt(MISC | TXA, 0);
+    asm.stmt(ALU | ADD, 40);
+    asm.stmt(MISC | TAX, 0);
+
+    // X: offset of the transport header
+    asm.place(l4);
+    asm.stmt(LD | MEM, PROTO);
+    asm.jump(JEQ, 6, Some(ports), None);
+    asm.jump(JEQ, 17, Some(ports), None);
+    asm.jump(JEQ, 132, Some(ports), Some(done));
+    asm.place(ports);
+    asm.stmt(LD | H | IND, 0);
+    asm.stmt(ST, SRC_PORT);
+    asm.stmt(LD | H | IND, 2);
+    asm.stmt(ST, DST_PORT);
+
+    asm.place(done);
+}
+
+/// Keep frames where one of the `(slot, value)` tests holds, or where none
+/// does when `negate` is set
+fn any_of(asm: &mut Assembler, tests: &[(u32, u32)], negate: bool) {
+    let (rejected, next) = (asm.label(), asm.label());
+    let on_match = if negate { rejected } else { next };
+
+    let mut loaded = None;
+    for (slot, value) in tests {
+        if loaded != Some(*slot) {
+            asm.stmt(LD | MEM, *slot);
+            loaded = Some(*slot);
+        }
+        asm.jump(JEQ, *value, Some(on_match), None);
+    }
+    if negate {
+        asm.ja(next);
+    }
+
+    asm.place(rejected);
+    asm.stmt(RET, 0);
+    asm.place(next);
+}
+
+/// Instruction whose jumps go to labels until assembled
+struct Pending {
+    code: u16,
+    k: u32,
+    jt: Option<usize>,
+    jf: Option<usize>,
+}
+
+/// Instructions with forward jumps to labels
+#[derive(Default)]
+struct Assembler {
+    code: Vec<Pending>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Assembler {
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    /// The label now points at the next instruction
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn stmt(&mut self, code: u16, k: u32) {
+        self.code.push(Pending { code, k, jt: None, jf: None });
+    }
+
+    /// Conditional jump, `None` going on with the next instruction
+    fn jump(&mut self, op: u16, k: u32, jt: Option<usize>, jf: Option<usize>) {
+        self.code.push(Pending { code: JMP | op, k, jt, jf });
+    }
+
+    fn ja(&mut self, label: usize) {
+        self.code.push(Pending { code: JMP | JA, k: 0, jt: Some(label), jf: None });
+    }
+
+    fn assemble(self) -> Result<Vec<BpfInstruction>> {
+        let labels = self.labels;
+        let offset = |from: usize, label: Option<usize>| -> Result<u32> {
+            let Some(label) = label else { return Ok(0) };
+            let Some(target) = labels[label] else { bail!("Capture filter jumps to an unplaced label") };
+            Ok((target - from - 1) as u32)
+        };
+
+        self.code
+            .iter()
+            .enumerate()
+            .map(|(i, insn)| {
+                let jt = offset(i, insn.jt)?;
+                let jf = offset(i, insn.jf)?;
+                if insn.code == JMP | JA {
+                    return Ok(BpfInstruction { code: insn.code, jt: 0, jf: 0, k: jt });
+                }
+                if jt > u8::MAX as u32 || jf > u8::MAX as u32 {
+                    bail!("Capture filter is too long");
+                }
+                Ok(BpfInstruction { code: insn.code, jt: jt as u8, jf: jf as u8, k: insn.k })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `program` on `packet` as the kernel would, without a stripped tag
+    fn run(program: &[BpfInstruction], packet: &[u8]) -> bool {
+        let (mut a, mut x, mut mem) = (0u32, 0u32, [0u32; 16]);
+        let load = |offset: u32, size: usize| -> Option<u32> {
+            if offset == AD_VLAN_TAG_PRESENT {
+                return Some(0);
+            }
+            let bytes = packet.get(offset as usize..offset as usize + size)?;
+            Some(bytes.iter().fold(0, |value, b| value << 8 | *b as u32))
+        };
+        let size = |code: u16| match code & 0x18 { W => 4, H => 2, _ => 1 };
+
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            pc += 1;
+            match insn.code & 0x07 {
+                LD => match insn.code & 0xE0 {
+                    IMM => a = insn.k,
+                    ABS => match load(insn.k, size(insn.code)) { Some(v) => a = v, None => return false },
+                    IND => match load(x + insn.k, size(insn.code)) { Some(v) => a = v, None => return false },
+                    MEM => a = mem[insn.k as usize],
+                    _ => unreachable!(),
+                },
+                LDX => x = if insn.code & 0xE0 == MEM { mem[insn.k as usize] } else { insn.k },
+                ST => mem[insn.k as usize] = a,
+                ALU => {
+                    let operand = if insn.code & X != 0 { x } else { insn.k };
+                    a = match insn.code & 0xF0 {
+                        ADD => a.wrapping_add(operand),
+                        AND => a & operand,
+                        LSH => a << operand,
+                        _ => unreachable!(),
+                    };
+                }
+                JMP => {
+                    let taken = match insn.code & 0xF0 {
+                        JA => {
+                            pc += insn.k as usize;
+                            continue;
+                        }
+                        JEQ => a == insn.k,
+                        JSET => a & insn.k != 0,
+                        _ => unreachable!(),
+                    };
+                    pc += (if taken { insn.jt } else { insn.jf }) as usize;
+                }
+                RET => return insn.k > 0,
+                MISC => if insn.code & TXA != 0 { a = x } else { x = a },
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// IPv4 frame, optionally 802.1Q tagged
+    fn ipv4_frame(vlan: Option<u16>, protocol: u8, src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        if let Some(vlan) = vlan {
+            frame.extend_from_slice(&[0x81, 0x00]);
+            frame.extend_from_slice(&vlan.to_be_bytes());
+        }
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0x00, 0x00, 40, 0x00, 0x00, 0x40, 0x00, 64, protocol, 0x00, 0x00]);
+        frame.extend_from_slice(&[192, 168, 1, 10, 10, 0, 0, 1]);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0; 16]);
+        frame
+    }
+
+    fn filter(toml: &str) -> Vec<BpfInstruction> {
+        compile(&toml::from_str(toml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_filter_lists() {
+        let program = filter(r#"vlan = [10]
+proto = ["tcp"]
+not_port = [6379]"#);
+        assert!(run(&program, &ipv4_frame(Some(10), 6, 51000, 443)));
+        assert!(!run(&program, &ipv4_frame(Some(10), 6, 51000, 6379)));
+        assert!(!run(&program, &ipv4_frame(Some(10), 6, 6379, 51000)));
+        assert!(!run(&program, &ipv4_frame(Some(20), 6, 51000, 443)));
+        assert!(!run(&program, &ipv4_frame(None, 6, 51000, 443)));
+        assert!(!run(&program, &ipv4_frame(Some(10), 17, 51000, 53)));
+
+        let program = filter(r#"host = ["10.0.0.1"]
+port = [53, 443]
+not_vlan = [99]"#);
+        assert!(run(&program, &ipv4_frame(None, 17, 51000, 53)));
+        assert!(run(&program, &ipv4_frame(Some(10), 6, 443, 51000)));
+        assert!(!run(&program, &ipv4_frame(Some(99), 6, 443, 51000)));
+        assert!(!run(&program, &ipv4_frame(None, 6, 51000, 80)));
+        // No port on ICMP
+        assert!(!run(&program, &ipv4_frame(None, 1, 0, 53)));
+    }
+
+    #[test]
+    fn test_filter_non_ip_frames() {
+        let mut arp = vec![0xff; 6];
+        arp.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08, 0x06]);
+        arp.extend_from_slice(&[0; 28]);
+
+        assert!(run(&filter(r#"proto = ["arp"]"#), &arp));
+        assert!(!run(&filter(r#"proto = ["arp"]"#), &ipv4_frame(None, 6, 1, 2)));
+        // Frames without ports are not excluded by a port
+        assert!(run(&filter("not_port = [22]"), &arp));
+        assert!(run(&filter(""), &arp));
+
+        // Lists up to the maximum still fit the jump offsets
+        let ports: Vec<String> = (1..=MAX_FILTER_ENTRIES).map(|p| p.to_string()).collect();
+        let program = filter(&format!("port = [{}]", ports.join(", ")));
+        assert!(run(&program, &ipv4_frame(None, 6, 51000, MAX_FILTER_ENTRIES as u16)));
+    }
+
+    #[test]
+    fn test_filter_expression() {
+        let program = filter(r#"expr = "(vlan 10 || vlan 20) and not (tcp port 6379 or src host 10.0.0.1)""#);
+        assert!(run(&program, &ipv4_frame(Some(20), 17, 51000, 6379)));
+        assert!(!run(&program, &ipv4_frame(Some(20), 6, 51000, 6379)));
+        assert!(!run(&program, &ipv4_frame(Some(30), 17, 51000, 53)));
+        assert!(!run(&program, &ipv4_frame(None, 17, 51000, 53)));
+        // Test frames go from 192.168.1.10 to 10.0.0.1
+        assert!(!run(&filter(r#"expr = "vlan and src host 192.168.1.10""#), &ipv4_frame(None, 6, 1, 2)));
+        assert!(run(&filter(r#"expr = "vlan and dst host 10.0.0.1""#), &ipv4_frame(Some(10), 6, 1, 2)));
+        assert!(!run(&filter(r#"expr = "src host 10.0.0.1""#), &ipv4_frame(Some(10), 6, 1, 2)));
+
+        // Combined with the lists
+        let program = filter(r#"proto = ["udp"]
+expr = "dst port 53 or udp src port 53""#);
+        assert!(run(&program, &ipv4_frame(None, 17, 51000, 53)));
+        assert!(run(&program, &ipv4_frame(None, 17, 53, 51000)));
+        assert!(!run(&program, &ipv4_frame(None, 6, 51000, 53)));
+
+        assert_eq!(parse_expr("not ip6").unwrap(), Expr::Not(Box::new(Expr::Any(vec![(ETHERTYPE, 0x86DD)]))));
+        for invalid in ["", "tcp and", "(udp", "udp)", "vlan 5000", "host fe80::1", "src tcp", "port http", "net 10.0.0.0/8"] {
+            assert!(parse_expr(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_filter_program() {
+        // tcpdump -ddd 'ip proto tcp'
+        let program = filter(r#"bpf = """
+6
+40 0 0 12
+21 0 3 2048
+48 0 0 23
+21 0 1 6
+6 0 0 262144
+6 0 0 0
+""""#);
+        assert!(run(&program, &ipv4_frame(None, 6, 51000, 443)));
+        assert!(!run(&program, &ipv4_frame(None, 17, 51000, 53)));
+        assert_eq!(parse_program("6,40 0 0 12,21 0 3 2048,48 0 0 23,21 0 1 6,6 0 0 262144,6 0 0 0").unwrap(), program);
+
+        // Count, jump and return checks
+        assert!(parse_program("2\n6 0 0 262144\n").is_err());
+        assert!(parse_program("2\n21 0 1 2048\n6 0 0 0").is_err());
+        assert!(parse_program("1\n40 0 0 12").is_err());
+        assert!(parse_program("1\nret #0").is_err());
+    }
+}
@@ -5,6 +5,9 @@ use pnet::datalink::{self, NetworkInterface as PnetInterface};
 use std::net::IpAddr;
 use tracing::{info, warn};
 
+/// Ethernet header plus two VLAN tags (QinQ), on top of the MTU
+const L2_OVERHEAD: u32 = 14 + 2 * 4;
+
 /// Represents a network interface
 #[derive(Debug, Clone)]
 pub struct NetworkInterface {
@@ -69,6 +72,8 @@ impl NetworkInterface {
         let is_loopback = iface.is_loopback();
         let index = iface.index;
 
+        let mtu = read_mtu(&iface.name);
+
         Ok(Self {
             name: iface.name,
             index,
@@ -76,10 +81,15 @@ impl NetworkInterface {
             ips,
             is_up,
             is_loopback,
-            mtu: None, // pnet doesn't expose MTU directly
+            mtu,
         })
     }
 
+    /// Largest frame the interface can carry, headers and VLAN tags included
+    pub fn max_frame_size(&self) -> Option<usize> {
+        self.mtu.map(|mtu| (mtu + L2_OVERHEAD) as usize)
+    }
+
     /// Check if the interface is valid for capture
     pub fn validate_for_capture(&self) -> Result<()> {
         if !self.is_up {
@@ -91,7 +101,7 @@ impl NetworkInterface {
         }
 
         info!(
-            "Interface '{}' validated: MAC={}, IPs={:?}",
+            "Interface '{}' validated: MAC={}, MTU={}, IPs={:?}",
             self.name,
             self.mac
                 .map(|m| format!(
@@ -99,6 +109,7 @@ impl NetworkInterface {
                     m[0], m[1], m[2], m[3], m[4], m[5]
                 ))
                 .unwrap_or_else(|| "unknown".to_string()),
+            self.mtu.map_or("unknown".to_string(), |mtu| mtu.to_string()),
             self.ips
         );
 
@@ -109,14 +120,15 @@ impl NetworkInterface {
     #[cfg(target_os = "linux")]
     pub fn set_promiscuous(&self, enable: bool) -> Result<()> {
         use std::ffi::CString;
-        use libc::{c_int, c_short, ioctl, socket, AF_INET, IFF_PROMISC, SIOCGIFFLAGS, SIOCSIFFLAGS, SOCK_DGRAM};
+        use libc::{c_short, ioctl, socket, AF_INET, IFF_PROMISC, SIOCGIFFLAGS, SIOCSIFFLAGS, SOCK_DGRAM};
         use std::mem::zeroed;
 
-        // ifreq structure
+        // ifreq structure, padded to the size the kernel copies back
         #[repr(C)]
         struct ifreq {
             ifr_name: [libc::c_char; 16],
             ifr_flags: c_short,
+            _pad: [u8; 22],
         }
 
         unsafe {
@@ -175,6 +187,45 @@ impl NetworkInterface {
     }
 }
 
+/// MTU of interface `name`, read with the SIOCGIFMTU ioctl
+#[cfg(target_os = "linux")]
+fn read_mtu(name: &str) -> Option<u32> {
+    use libc::{c_int, ioctl, socket, AF_INET, SIOCGIFMTU, SOCK_DGRAM};
+
+    // ifreq structure, padded to the size the kernel copies back
+    #[repr(C)]
+    struct ifreq {
+        ifr_name: [libc::c_char; 16],
+        ifr_mtu: c_int,
+        _pad: [u8; 20],
+    }
+
+    if name.len() > 15 || name.contains('\0') {
+        return None;
+    }
+
+    unsafe {
+        let sock = socket(AF_INET, SOCK_DGRAM, 0);
+        if sock < 0 {
+            return None;
+        }
+
+        let mut req: ifreq = std::mem::zeroed();
+        for (i, &b) in name.as_bytes().iter().enumerate() {
+            req.ifr_name[i] = b as libc::c_char;
+        }
+
+        let result = ioctl(sock, SIOCGIFMTU as libc::c_ulong, &mut req as *mut ifreq);
+        libc::close(sock);
+        (result >= 0 && req.ifr_mtu > 0).then_some(req.ifr_mtu as u32)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_mtu(_name: &str) -> Option<u32> {
+    None
+}
+
 /// Print information about all interfaces
 pub fn print_interfaces() {
     println!("Available network interfaces:");
@@ -193,10 +244,11 @@ pub fn print_interfaces() {
 
         let status = if iface.is_up { "UP" } else { "DOWN" };
         let loopback = if iface.is_loopback { " (loopback)" } else { "" };
+        let mtu = iface.mtu.map(|mtu| format!(" mtu {}", mtu)).unwrap_or_default();
 
         println!(
-            "{}: {} [{}{}]",
-            iface.name, mac_str, status, loopback
+            "{}: {} [{}{}]{}",
+            iface.name, mac_str, status, loopback, mtu
         );
 
         for ip in &iface.ips {
@@ -221,14 +273,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_max_frame_size() {
+        let mut iface = NetworkInterface {
+            name: "eth0".to_string(),
+            index: 1,
+            mac: None,
+            ips: Vec::new(),
+            is_up: true,
+            is_loopback: false,
+            mtu: None,
+        };
+        assert_eq!(iface.max_frame_size(), None);
+
+        iface.mtu = Some(9000);
+        assert_eq!(iface.max_frame_size(), Some(9022));
+    }
+
     #[test]
     fn test_interface_by_name() {
         // loopback should exist on all systems
-        let lo = NetworkInterface::by_name("lo");
-        if lo.is_ok() {
-            let lo = lo.unwrap();
+        if let Ok(lo) = NetworkInterface::by_name("lo") {
             assert!(lo.is_loopback);
             assert!(lo.is_up);
+            #[cfg(target_os = "linux")]
+            assert!(lo.mtu.is_some_and(|mtu| mtu >= 1500));
         }
     }
 }
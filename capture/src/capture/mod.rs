@@ -1,9 +1,23 @@
 //! Capture module - Network packet capture functionality
 
 pub mod af_packet;
+pub mod builder;
+pub mod filter;
 pub mod interface;
-pub mod frame;
+pub mod netns;
+pub mod pcap;
+pub mod policy;
+pub mod ring;
+pub mod shed;
+pub mod socket;
+pub mod stream;
+pub mod timestamp;
 
-pub use af_packet::{AfPacketCapture, MultiCapture, CaptureStats, CaptureStatsSnapshot};
+pub use af_packet::{AfPacketCapture, MultiCapture, CaptureStats, CaptureStatsSnapshot, FrameSink, DEFAULT_READ_BUFFER_SIZE};
+pub use builder::{CaptureBuilder, CaptureHandle};
 pub use interface::{NetworkInterface, print_interfaces};
-pub use frame::{CapturedFrame, MacAddr, VlanInfo, QinQInfo, TcpFlags};
+pub use pcap::{PcapPacket, PcapReader};
+pub use policy::Policy;
+pub use stream::FrameStream;
+pub use timestamp::TimestampSource;
+pub use netsentinel_types::{CapturedFrame, MacAddr, VlanInfo, QinQInfo, TcpFlags};
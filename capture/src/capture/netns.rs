@@ -0,0 +1,159 @@
+//! Network namespaces
+//!
+//! On container hosts each tenant's mirror can land in a network namespace
+//! of its own. Named namespaces (`ip netns add`) are bind-mounted under
+//! `/run/netns`; a thread entering one sees its interfaces, and the packet
+//! sockets and ioctls it opens there stay in it. Each capture thread enters
+//! the namespace of its interface for as long as it captures, so one process
+//! captures across several namespaces.
+//!
+//! Interface names and namespaces may be patterns (`*` for any characters,
+//! `?` for one), discovered once at startup.
+
+use anyhow::{Context, Result};
+
+use super::interface::NetworkInterface;
+
+/// Directory of the named network namespaces
+pub const NETNS_DIR: &str = "/run/netns";
+
+/// Whether `name` matches `pattern`, with `*` matching any characters and
+/// `?` exactly one
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Last `*` seen, and the name position it currently stands for
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` take one more character
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Whether `name` is a pattern rather than a name
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Named network namespaces matching `pattern`, sorted
+pub fn discover(pattern: &str) -> Result<Vec<String>> {
+    if !is_pattern(pattern) {
+        return Ok(vec![pattern.to_string()]);
+    }
+    let entries = match std::fs::read_dir(NETNS_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list network namespaces in {}", NETNS_DIR)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| matches(pattern, name))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Interfaces suitable for monitoring (up, not loopback) matching
+/// `pattern`, in network namespace `netns` or the current one, sorted
+pub fn discover_interfaces(netns: Option<&str>, pattern: &str) -> Result<Vec<String>> {
+    let _guard = netns.map(enter).transpose()?;
+    let mut names: Vec<String> = NetworkInterface::list_monitoring()
+        .into_iter()
+        .map(|iface| iface.name)
+        .filter(|name| matches(pattern, name))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Name interfaces of a namespace are reported under: `netns/interface`
+pub fn qualified_name(netns: Option<&str>, interface: &str) -> String {
+    match netns {
+        Some(netns) => format!("{}/{}", netns, interface),
+        None => interface.to_string(),
+    }
+}
+
+/// Current thread inside another network namespace, back in its own when
+/// dropped
+#[cfg(target_os = "linux")]
+pub struct NetnsGuard {
+    original: std::fs::File,
+    netns: String,
+}
+
+/// Move the current thread into the named network namespace `name`
+#[cfg(target_os = "linux")]
+pub fn enter(name: &str) -> Result<NetnsGuard> {
+    use std::os::fd::AsRawFd;
+
+    let original = std::fs::File::open("/proc/thread-self/ns/net")
+        .with_context(|| "Failed to open the current network namespace")?;
+    let target = std::fs::File::open(std::path::Path::new(NETNS_DIR).join(name))
+        .with_context(|| format!("Network namespace '{}' not found in {}", name, NETNS_DIR))?;
+    if unsafe { libc::setns(target.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to enter network namespace '{}'. Are you running as root?", name));
+    }
+    Ok(NetnsGuard { original, netns: name.to_string() })
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for NetnsGuard {
+    fn drop(&mut self) {
+        use std::os::fd::AsRawFd;
+
+        if unsafe { libc::setns(self.original.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+            tracing::error!(
+                "Failed to leave network namespace '{}': {}",
+                self.netns,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Network namespaces are only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub struct NetnsGuard;
+
+#[cfg(not(target_os = "linux"))]
+pub fn enter(name: &str) -> Result<NetnsGuard> {
+    anyhow::bail!("Network namespace '{}' requested, but namespaces are only supported on Linux", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("mirror*", "mirror0"));
+        assert!(matches("mirror*", "mirror"));
+        assert!(matches("tenant-?", "tenant-a"));
+        assert!(matches("*-mirror-*", "tenant-a-mirror-0"));
+        assert!(matches("eth0", "eth0"));
+        assert!(!matches("eth0", "eth01"));
+        assert!(!matches("tenant-?", "tenant-ab"));
+        assert!(!matches("*mirror", "mirror0"));
+        assert_eq!(qualified_name(Some("tenant-a"), "eth0"), "tenant-a/eth0");
+        assert_eq!(qualified_name(None, "eth0"), "eth0");
+    }
+}
@@ -0,0 +1,348 @@
+//! Pcap file reader
+//!
+//! Reads the packets of classic pcap files (microsecond or nanosecond
+//! timestamps, either byte order) and pcapng files, for replaying captures
+//! taken elsewhere. Only Ethernet packets are returned; packets of other
+//! link types are counted and skipped.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+/// LINKTYPE_ETHERNET
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Largest pcapng block or packet record accepted
+const MAX_RECORD: usize = 16 * 1024 * 1024;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+/// Packet read from a capture file
+#[derive(Debug, Clone)]
+pub struct PcapPacket {
+    /// Capture time
+    pub timestamp: DateTime<Utc>,
+    /// Frame bytes as captured (possibly truncated to the snap length)
+    pub data: Vec<u8>,
+}
+
+enum Format {
+    Classic { big_endian: bool, nanos: bool, linktype: u32 },
+    Ng { big_endian: bool, interfaces: Vec<Interface> },
+}
+
+/// pcapng interface description
+struct Interface {
+    linktype: u32,
+    /// Timestamp units per second
+    units: u64,
+}
+
+/// Reader of the packets of a pcap or pcapng file
+pub struct PcapReader<R> {
+    reader: R,
+    format: Format,
+    skipped: u64,
+}
+
+impl PcapReader<BufReader<File>> {
+    /// Open a capture file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref())
+            .with_context(|| format!("Failed to open {:?}", path.as_ref()))?;
+        Self::new(BufReader::new(file))
+            .with_context(|| format!("Failed to read {:?}", path.as_ref()))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the file header from `reader`
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).context("Missing file header")?;
+
+        let format = match magic {
+            [0xd4, 0xc3, 0xb2, 0xa1] => classic_header(&mut reader, false, false)?,
+            [0xa1, 0xb2, 0xc3, 0xd4] => classic_header(&mut reader, true, false)?,
+            [0x4d, 0x3c, 0xb2, 0xa1] => classic_header(&mut reader, false, true)?,
+            [0xa1, 0xb2, 0x3c, 0x4d] => classic_header(&mut reader, true, true)?,
+            [0x0a, 0x0d, 0x0d, 0x0a] => {
+                let big_endian = section_header(&mut reader)?;
+                Format::Ng { big_endian, interfaces: Vec::new() }
+            }
+            _ => bail!("Not a pcap or pcapng file"),
+        };
+
+        Ok(Self { reader, format, skipped: 0 })
+    }
+
+    /// Packets skipped so far because they are not Ethernet frames
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Next Ethernet packet, or `None` at the end of the file
+    pub fn next_packet(&mut self) -> Result<Option<PcapPacket>> {
+        loop {
+            let packet = match self.format {
+                Format::Classic { .. } => self.next_classic()?,
+                Format::Ng { .. } => self.next_ng()?,
+            };
+            match packet {
+                Some((LINKTYPE_ETHERNET, packet)) => return Ok(Some(packet)),
+                Some(_) => self.skipped += 1,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn next_classic(&mut self) -> Result<Option<(u32, PcapPacket)>> {
+        let Format::Classic { big_endian, nanos, linktype } = self.format else {
+            unreachable!()
+        };
+        let mut header = [0u8; 16];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = u32_at(&header, 0, big_endian) as i64;
+        let fraction = u32_at(&header, 4, big_endian) as u64;
+        let length = u32_at(&header, 8, big_endian) as usize;
+        if length > MAX_RECORD {
+            bail!("Packet record of {} bytes is too large", length);
+        }
+        let mut data = vec![0u8; length];
+        self.reader.read_exact(&mut data).context("Truncated packet record")?;
+
+        let units = if nanos { 1_000_000_000 } else { 1_000_000 };
+        let timestamp = timestamp(seconds as u64 * units + fraction, units);
+        Ok(Some((linktype, PcapPacket { timestamp, data })))
+    }
+
+    fn next_ng(&mut self) -> Result<Option<(u32, PcapPacket)>> {
+        loop {
+            let Format::Ng { big_endian, ref mut interfaces } = self.format else {
+                unreachable!()
+            };
+            let mut header = [0u8; 8];
+            if !read_or_eof(&mut self.reader, &mut header)? {
+                return Ok(None);
+            }
+
+            let block_type = u32_at(&header, 0, big_endian);
+            if block_type == PCAPNG_SECTION_HEADER {
+                // A new section may switch byte order and redefines the interfaces
+                let big_endian = section_header_after_type(&mut self.reader, header)?;
+                self.format = Format::Ng { big_endian, interfaces: Vec::new() };
+                continue;
+            }
+
+            let length = u32_at(&header, 4, big_endian) as usize;
+            if !(12..=MAX_RECORD).contains(&length) || !length.is_multiple_of(4) {
+                bail!("Invalid pcapng block length {}", length);
+            }
+            let mut body = vec![0u8; length - 8];
+            self.reader.read_exact(&mut body).context("Truncated pcapng block")?;
+            let body = &body[..length - 12];
+
+            match block_type {
+                PCAPNG_INTERFACE if body.len() >= 8 => {
+                    interfaces.push(Interface {
+                        linktype: u16_at(body, 0, big_endian) as u32,
+                        units: timestamp_units(&body[8..], big_endian),
+                    });
+                }
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = interfaces.get(u32_at(body, 0, big_endian) as usize)
+                        .context("Packet block for an undeclared interface")?;
+                    let ticks = (u32_at(body, 4, big_endian) as u64) << 32 | u32_at(body, 8, big_endian) as u64;
+                    let captured = u32_at(body, 12, big_endian) as usize;
+                    let data = body.get(20..20 + captured).context("Truncated packet block")?;
+                    let packet = PcapPacket { timestamp: timestamp(ticks, interface.units), data: data.to_vec() };
+                    return Ok(Some((interface.linktype, packet)));
+                }
+                // Statistics, name resolution, custom blocks...
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<PcapPacket>;
+
+    fn next(&mut self) -> Option<Result<PcapPacket>> {
+        self.next_packet().transpose()
+    }
+}
+
+fn classic_header<R: Read>(reader: &mut R, big_endian: bool, nanos: bool) -> Result<Format> {
+    let mut header = [0u8; 20];
+    reader.read_exact(&mut header).context("Truncated pcap header")?;
+    // The upper bits carry FCS information
+    let linktype = u32_at(&header, 16, big_endian) & 0xffff;
+    Ok(Format::Classic { big_endian, nanos, linktype })
+}
+
+/// Read the rest of a section header block, whose type was already read
+fn section_header<R: Read>(reader: &mut R) -> Result<bool> {
+    let mut header = [0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0];
+    reader.read_exact(&mut header[4..]).context("Truncated pcapng section header")?;
+    section_header_after_type(reader, header)
+}
+
+/// Read the rest of a section header block from its type and length, and
+/// return whether the section is big-endian
+fn section_header_after_type<R: Read>(reader: &mut R, header: [u8; 8]) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).context("Truncated pcapng section header")?;
+    let big_endian = match u32::from_le_bytes(magic) {
+        PCAPNG_BYTE_ORDER_MAGIC => false,
+        m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+        _ => bail!("Invalid pcapng byte-order magic"),
+    };
+
+    let length = u32_at(&header, 4, big_endian) as usize;
+    if !(28..=MAX_RECORD).contains(&length) {
+        bail!("Invalid pcapng section header length {}", length);
+    }
+    let mut rest = vec![0u8; length - 12];
+    reader.read_exact(&mut rest).context("Truncated pcapng section header")?;
+    Ok(big_endian)
+}
+
+/// Timestamp units per second from the `if_tsresol` option (microseconds by default)
+fn timestamp_units(mut options: &[u8], big_endian: bool) -> u64 {
+    while options.len() >= 4 {
+        let code = u16_at(options, 0, big_endian);
+        let length = u16_at(options, 2, big_endian) as usize;
+        let value = options.get(4..4 + length).unwrap_or_default();
+        match (code, value.first()) {
+            (0, _) => break,
+            (9, Some(&resolution)) => {
+                let exponent = (resolution & 0x7f) as u32;
+                let base: u64 = if resolution & 0x80 == 0 { 10 } else { 2 };
+                return base.checked_pow(exponent).filter(|u| *u > 0).unwrap_or(1_000_000);
+            }
+            _ => {}
+        }
+        let padded = 4 + length.div_ceil(4) * 4;
+        options = options.get(padded..).unwrap_or_default();
+    }
+    1_000_000
+}
+
+fn timestamp(ticks: u64, units: u64) -> DateTime<Utc> {
+    let seconds = (ticks / units) as i64;
+    let nanos = ((ticks % units) as u128 * 1_000_000_000 / units as u128) as u32;
+    DateTime::from_timestamp(seconds, nanos).unwrap_or_default()
+}
+
+/// Fill `buf`, returning false at a clean end of file
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => bail!("Truncated capture file"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn u16_at(bytes: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let raw = [bytes[offset], bytes[offset + 1]];
+    if big_endian { u16::from_be_bytes(raw) } else { u16::from_le_bytes(raw) }
+}
+
+fn u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let raw = [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]];
+    if big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: [u8; 14] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08, 0x06];
+
+    #[test]
+    fn test_classic_pcap() {
+        let mut file = Vec::new();
+        file.extend_from_slice(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0]);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (seconds, micros) in [(1_700_000_000u32, 250_000u32), (1_700_000_001, 0)] {
+            file.extend_from_slice(&seconds.to_le_bytes());
+            file.extend_from_slice(&micros.to_le_bytes());
+            file.extend_from_slice(&(FRAME.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(FRAME.len() as u32).to_le_bytes());
+            file.extend_from_slice(&FRAME);
+        }
+
+        let packets: Vec<PcapPacket> = PcapReader::new(file.as_slice()).unwrap().map(Result::unwrap).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].data, FRAME);
+        assert_eq!(packets[0].timestamp.timestamp_millis(), 1_700_000_000_250);
+        assert_eq!(packets[1].timestamp.timestamp(), 1_700_000_001);
+
+        // A truncated record is an error, not the end of the file
+        file.truncate(file.len() - 4);
+        let mut reader = PcapReader::new(file.as_slice()).unwrap();
+        assert!(reader.next_packet().unwrap().is_some());
+        assert!(reader.next_packet().is_err());
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    fn packet_block(interface: u32, ticks: u64, data: &[u8]) -> Vec<u8> {
+        let mut body = interface.to_le_bytes().to_vec();
+        body.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ticks as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        block(PCAPNG_ENHANCED_PACKET, &body)
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0]);
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        let mut file = block(PCAPNG_SECTION_HEADER, &section);
+
+        // Ethernet with nanosecond timestamps, then a raw IP interface
+        let mut ethernet = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        ethernet.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend(block(PCAPNG_INTERFACE, &ethernet));
+        file.extend(block(PCAPNG_INTERFACE, &[101, 0, 0, 0, 0, 0, 0, 0]));
+
+        file.extend(packet_block(1, 1_700_000_000_000_000, &[0x45; 20]));
+        file.extend(packet_block(0, 1_700_000_000_123_456_789, &FRAME));
+
+        let mut reader = PcapReader::new(file.as_slice()).unwrap();
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, FRAME);
+        assert_eq!(packet.timestamp.timestamp_nanos_opt(), Some(1_700_000_000_123_456_789));
+        assert!(reader.next_packet().unwrap().is_none());
+        assert_eq!(reader.skipped(), 1);
+    }
+}
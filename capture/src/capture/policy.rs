@@ -0,0 +1,129 @@
+//! Protocol policy
+//!
+//! The capture filter decides which frames the kernel hands over; the
+//! policy decides which of the decoded frames are sent. In noisy L2 domains
+//! it keeps the chatter no one looks at (IPv6 multicast, spanning tree,
+//! LLDP, a whole VLAN) out of the stream. Rules are tried in order and the
+//! first one matching a frame decides: `allow` sends it, `drop` discards
+//! it. Frames no rule matches are sent.
+//!
+//! The frames of each rule are counted in the `policy_frames_total` metric,
+//! labelled by rule and action.
+
+use prometheus::IntCounter;
+
+use netsentinel_types::CapturedFrame;
+use super::filter::FilterProtocol;
+use crate::config::{PolicyAction, PolicyRule};
+use crate::metrics::metrics;
+
+/// Whether `frame` is of `protocol`
+fn is_protocol(frame: &CapturedFrame, protocol: FilterProtocol) -> bool {
+    match protocol {
+        FilterProtocol::Arp => frame.is_arp(),
+        FilterProtocol::Ipv4 => frame.is_ipv4(),
+        FilterProtocol::Ipv6 => frame.is_ipv6(),
+        FilterProtocol::Icmp => frame.ip_protocol == Some(1),
+        FilterProtocol::Icmp6 => frame.ip_protocol == Some(58),
+        FilterProtocol::Tcp => frame.ip_protocol == Some(6),
+        FilterProtocol::Udp => frame.ip_protocol == Some(17),
+        FilterProtocol::Gre => frame.ip_protocol == Some(47),
+        FilterProtocol::Sctp => frame.ip_protocol == Some(132),
+    }
+}
+
+/// Whether `frame` matches every criterion of `rule`
+fn matches(rule: &PolicyRule, frame: &CapturedFrame) -> bool {
+    (rule.ethertype.is_empty() || rule.ethertype.contains(&frame.ethertype))
+        && (rule.proto.is_empty() || rule.proto.iter().any(|protocol| is_protocol(frame, *protocol)))
+        && (rule.vlan.is_empty() || frame.vlan_id().is_some_and(|vlan| rule.vlan.contains(&vlan)))
+        && rule.multicast.is_none_or(|multicast| frame.dst_mac.is_multicast() == multicast)
+}
+
+/// Protocol policy shared by the captures
+pub struct Policy {
+    /// Rules in order, with the counter of their frames
+    rules: Vec<(PolicyRule, IntCounter)>,
+}
+
+impl Policy {
+    pub fn new(rules: &[PolicyRule]) -> Self {
+        Self {
+            rules: rules
+                .iter()
+                .map(|rule| {
+                    let counter = metrics().policy_frames.with_label_values(&[&rule.name, rule.action.as_str()]);
+                    (rule.clone(), counter)
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether `frame` is sent, counting it under the rule deciding so
+    pub fn admit(&self, frame: &CapturedFrame) -> bool {
+        match self.rules.iter().find(|(rule, _)| matches(rule, frame)) {
+            Some((rule, counter)) => {
+                counter.inc();
+                rule.action == PolicyAction::Allow
+            }
+            None => true,
+        }
+    }
+
+    /// Frames matched so far by each rule, in order
+    pub fn counts(&self) -> Vec<(&str, PolicyAction, u64)> {
+        self.rules.iter().map(|(rule, counter)| (rule.name.as_str(), rule.action, counter.get())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netsentinel_types::{MacAddr, VlanInfo};
+
+    fn frame(dst: [u8; 6], ethertype: u16, ip_protocol: Option<u8>, vlan: Option<u16>) -> CapturedFrame {
+        let src = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let mut frame = CapturedFrame::new("eth0", src, MacAddr::new(dst), ethertype, 90);
+        frame.ip_protocol = ip_protocol;
+        frame.vlan = vlan.map(VlanInfo::from_tci);
+        frame
+    }
+
+    #[test]
+    fn test_policy() {
+        let config: crate::config::CaptureConfig = toml::from_str(r#"
+            [[policy]]
+            name = "test-mdns-allowed"
+            action = "allow"
+            proto = ["udp"]
+            vlan = [10]
+
+            [[policy]]
+            name = "test-ipv6-multicast"
+            ethertype = [0x86DD]
+            multicast = true
+
+            [[policy]]
+            name = "test-lab-vlan"
+            vlan = [99]
+        "#).unwrap();
+        let policy = Policy::new(&config.policy);
+        let ipv6_multicast = [0x33, 0x33, 0x00, 0x00, 0x00, 0xfb];
+        let unicast = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+
+        // IPv6 multicast dropped, except UDP on VLAN 10; IPv6 unicast sent
+        assert!(!policy.admit(&frame(ipv6_multicast, 0x86DD, Some(58), None)));
+        assert!(!policy.admit(&frame(ipv6_multicast, 0x86DD, Some(17), Some(20))));
+        assert!(policy.admit(&frame(ipv6_multicast, 0x86DD, Some(17), Some(10))));
+        assert!(policy.admit(&frame(unicast, 0x86DD, Some(6), None)));
+        // Whole VLAN dropped
+        assert!(!policy.admit(&frame(unicast, 0x0800, Some(6), Some(99))));
+        assert!(policy.admit(&frame(unicast, 0x0800, Some(6), Some(98))));
+
+        assert_eq!(policy.counts(), vec![
+            ("test-mdns-allowed", PolicyAction::Allow, 1),
+            ("test-ipv6-multicast", PolicyAction::Drop, 2),
+            ("test-lab-vlan", PolicyAction::Drop, 1),
+        ]);
+    }
+}
@@ -0,0 +1,362 @@
+//! TPACKET_V3 ring buffer
+//!
+//! A packet socket with a receive ring (`PACKET_RX_RING`) shares memory
+//! with the kernel instead of copying each frame out with a read: the
+//! kernel fills blocks of frames and hands each one over when it is full,
+//! or after `block_timeout_ms` on a quiet link. Frames are decoded in place
+//! and a block goes back to the kernel once its last frame is read, so a
+//! busy interface costs a system call per block rather than per frame.
+//!
+//! The kernel strips the VLAN tag of frames whose NIC offloads it and
+//! reports it beside the frame; such frames are copied with their tag put
+//! back, as the decoders expect it in the frame.
+
+use anyhow::Result;
+use std::io;
+use std::time::Duration;
+
+use super::filter::BpfInstruction;
+use super::socket::TimestampedPacket;
+use crate::config::RingConfig;
+
+/// Frame slots the kernel accounts blocks in; TPACKET_V3 packs frames of
+/// any size in a block, so this only has to divide the block size
+#[cfg(target_os = "linux")]
+const FRAME_SIZE: u32 = 2048;
+
+/// `linux/if_packet.h` definitions, not all exposed by every libc release
+#[cfg(target_os = "linux")]
+mod sys {
+    pub const PACKET_RX_RING: libc::c_int = 5;
+    pub const PACKET_STATISTICS: libc::c_int = 6;
+    pub const PACKET_VERSION: libc::c_int = 10;
+    pub const TPACKET_V3: libc::c_int = 2;
+
+    /// Block status: owned by the kernel, or handed over to the capture
+    pub const TP_STATUS_KERNEL: u32 = 0;
+    pub const TP_STATUS_USER: u32 = 1;
+
+    /// Packet status: VLAN tag (and its TPID) stripped from the frame
+    pub const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+    pub const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+
+    #[repr(C)]
+    pub struct TpacketReq3 {
+        pub tp_block_size: u32,
+        pub tp_block_nr: u32,
+        pub tp_frame_size: u32,
+        pub tp_frame_nr: u32,
+        pub tp_retire_blk_tov: u32,
+        pub tp_sizeof_priv: u32,
+        pub tp_feature_req_word: u32,
+    }
+
+    /// `tpacket_block_desc` with its version 1 header
+    #[repr(C)]
+    pub struct BlockDescriptor {
+        pub version: u32,
+        pub offset_to_priv: u32,
+        pub block_status: u32,
+        pub num_pkts: u32,
+        pub offset_to_first_pkt: u32,
+        pub blk_len: u32,
+        pub seq_num: u64,
+        pub ts_first_pkt: [u32; 2],
+        pub ts_last_pkt: [u32; 2],
+    }
+
+    #[repr(C)]
+    pub struct Tpacket3Header {
+        pub tp_next_offset: u32,
+        pub tp_sec: u32,
+        pub tp_nsec: u32,
+        pub tp_snaplen: u32,
+        pub tp_len: u32,
+        pub tp_status: u32,
+        pub tp_mac: u16,
+        pub tp_net: u16,
+        pub tp_rxhash: u32,
+        pub tp_vlan_tci: u32,
+        pub tp_vlan_tpid: u16,
+        pub tp_padding: u16,
+    }
+
+    #[repr(C)]
+    pub struct TpacketStatsV3 {
+        pub tp_packets: u32,
+        pub tp_drops: u32,
+        pub tp_freeze_q_cnt: u32,
+    }
+}
+
+/// Packet socket reading the frames of one interface from a ring buffer
+#[cfg(target_os = "linux")]
+pub struct PacketRing {
+    fd: std::os::fd::OwnedFd,
+    /// The mapped ring, `block_count` blocks of `block_size` bytes
+    map: *mut u8,
+    block_size: usize,
+    block_count: usize,
+    /// Milliseconds a read waits for a block
+    timeout_ms: libc::c_int,
+    kernel_timestamps: bool,
+    /// Block being read, handed over by the kernel when `held`
+    block: usize,
+    held: bool,
+    /// Frames left in the block, and the offset of the next one
+    remaining: u32,
+    offset: usize,
+    /// Frame with its VLAN tag put back
+    tagged: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl PacketRing {
+    /// Open a socket with a ring of `config`, receiving nothing until bound
+    ///
+    /// Reads give up after `read_timeout` with a `WouldBlock` error, so the
+    /// capture loop can check whether it was stopped.
+    pub fn new(config: &RingConfig, read_timeout: Duration) -> Result<Self> {
+        use anyhow::Context;
+        use super::socket::{open, set_option};
+
+        let fd = open()?;
+        set_option(&fd, libc::SOL_PACKET, sys::PACKET_VERSION, &sys::TPACKET_V3)
+            .context("Failed to select TPACKET_V3, it needs Linux 3.2 or later")?;
+
+        let block_size = config.block_size as u32;
+        let block_count = config.block_count as u32;
+        let request = sys::TpacketReq3 {
+            tp_block_size: block_size,
+            tp_block_nr: block_count,
+            tp_frame_size: FRAME_SIZE,
+            tp_frame_nr: block_size / FRAME_SIZE * block_count,
+            tp_retire_blk_tov: config.block_timeout_ms,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        set_option(&fd, libc::SOL_PACKET, sys::PACKET_RX_RING, &request).with_context(|| {
+            format!("Failed to allocate a ring of {} blocks of {} bytes", config.block_count, config.block_size)
+        })?;
+
+        let length = config.block_size * config.block_count;
+        let map = unsafe {
+            use std::os::fd::AsRawFd;
+            libc::mmap(
+                std::ptr::null_mut(),
+                length,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error()).context("Failed to map the packet ring");
+        }
+
+        Ok(Self {
+            fd,
+            map: map as *mut u8,
+            block_size: config.block_size,
+            block_count: config.block_count,
+            timeout_ms: read_timeout.as_millis() as libc::c_int,
+            kernel_timestamps: false,
+            block: 0,
+            held: false,
+            remaining: 0,
+            offset: 0,
+            tagged: Vec::new(),
+        })
+    }
+
+    /// Return each packet with its kernel receive timestamp
+    ///
+    /// The kernel stamps every frame of the ring, this only reports it.
+    pub fn with_kernel_timestamps(mut self) -> Self {
+        self.kernel_timestamps = true;
+        self
+    }
+
+    /// Only receive the packets accepted by a classic BPF `program`
+    pub fn with_filter(self, program: &[BpfInstruction]) -> Result<Self> {
+        use anyhow::Context;
+
+        super::socket::attach_filter(&self.fd, program).context("Failed to attach the capture filter")?;
+        Ok(self)
+    }
+
+    /// Start receiving every packet of interface `index`
+    pub fn bind(self, index: u32) -> Result<Self> {
+        use anyhow::Context;
+
+        super::socket::bind(&self.fd, index).context("Failed to bind packet socket")?;
+        Ok(self)
+    }
+
+    /// Frames the kernel dropped, the ring being full, since the previous
+    /// call
+    pub fn dropped(&self) -> io::Result<u64> {
+        use std::os::fd::AsRawFd;
+
+        let mut stats = sys::TpacketStatsV3 { tp_packets: 0, tp_drops: 0, tp_freeze_q_cnt: 0 };
+        let mut length = std::mem::size_of::<sys::TpacketStatsV3>() as libc::socklen_t;
+        // Reading the statistics resets them
+        let result = unsafe {
+            libc::getsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_PACKET,
+                sys::PACKET_STATISTICS,
+                &mut stats as *mut sys::TpacketStatsV3 as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result < 0 { Err(io::Error::last_os_error()) } else { Ok(stats.tp_drops as u64) }
+    }
+
+    fn descriptor(&self) -> *mut sys::BlockDescriptor {
+        unsafe { self.map.add(self.block * self.block_size) as *mut sys::BlockDescriptor }
+    }
+
+    /// Status of the current block, read after the kernel's writes to it
+    fn status(&self) -> &std::sync::atomic::AtomicU32 {
+        unsafe { &*(std::ptr::addr_of_mut!((*self.descriptor()).block_status) as *const std::sync::atomic::AtomicU32) }
+    }
+
+    /// Wait for the kernel to hand over the current block
+    fn wait(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut poll = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN | libc::POLLERR, revents: 0 };
+        match unsafe { libc::poll(&mut poll, 1, self.timeout_ms) } {
+            result if result < 0 => Err(io::Error::last_os_error()),
+            0 => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for the next packet
+    ///
+    /// The packet is read in place: the previous one is released, and its
+    /// block returned to the kernel once read, by the next call.
+    pub fn receive(&mut self) -> io::Result<TimestampedPacket<'_>> {
+        use std::sync::atomic::Ordering;
+
+        loop {
+            if self.held {
+                if self.remaining > 0 {
+                    break;
+                }
+                self.status().store(sys::TP_STATUS_KERNEL, Ordering::Release);
+                self.block = (self.block + 1) % self.block_count;
+                self.held = false;
+            }
+            if self.status().load(Ordering::Acquire) & sys::TP_STATUS_USER == 0 {
+                self.wait()?;
+                continue;
+            }
+            let descriptor = unsafe { &*self.descriptor() };
+            self.held = true;
+            self.remaining = descriptor.num_pkts;
+            self.offset = descriptor.offset_to_first_pkt as usize;
+        }
+
+        let block = unsafe { self.map.add(self.block * self.block_size) };
+        let header = unsafe { std::ptr::read(block.add(self.offset) as *const sys::Tpacket3Header) };
+        let start = self.offset + header.tp_mac as usize;
+        let snapped = (header.tp_snaplen as usize).min(self.block_size.saturating_sub(start));
+        let data = unsafe { std::slice::from_raw_parts(block.add(start), snapped) };
+        self.remaining -= 1;
+        self.offset += header.tp_next_offset as usize;
+
+        let timestamp = if self.kernel_timestamps {
+            chrono::DateTime::from_timestamp(header.tp_sec as i64, header.tp_nsec)
+        } else {
+            None
+        };
+
+        if header.tp_status & sys::TP_STATUS_VLAN_VALID != 0 && data.len() >= 12 {
+            let tpid = if header.tp_status & sys::TP_STATUS_VLAN_TPID_VALID != 0 {
+                header.tp_vlan_tpid
+            } else {
+                0x8100
+            };
+            self.tagged.clear();
+            self.tagged.extend_from_slice(&data[..12]);
+            self.tagged.extend_from_slice(&tpid.to_be_bytes());
+            self.tagged.extend_from_slice(&(header.tp_vlan_tci as u16).to_be_bytes());
+            self.tagged.extend_from_slice(&data[12..]);
+            return Ok(TimestampedPacket { data: &self.tagged, length: header.tp_len as usize + 4, timestamp });
+        }
+
+        Ok(TimestampedPacket { data, length: header.tp_len as usize, timestamp })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for PacketRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.map as *mut libc::c_void, self.block_size * self.block_count);
+        }
+    }
+}
+
+/// Packet rings are only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub struct PacketRing;
+
+#[cfg(not(target_os = "linux"))]
+impl PacketRing {
+    pub fn new(_config: &RingConfig, _read_timeout: Duration) -> Result<Self> {
+        anyhow::bail!("Ring buffer capture is only supported on Linux")
+    }
+
+    pub fn with_kernel_timestamps(self) -> Self {
+        self
+    }
+
+    pub fn with_filter(self, _program: &[BpfInstruction]) -> Result<Self> {
+        Ok(self)
+    }
+
+    pub fn bind(self, _index: u32) -> Result<Self> {
+        Ok(self)
+    }
+
+    pub fn dropped(&self) -> io::Result<u64> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn receive(&mut self) -> io::Result<TimestampedPacket<'_>> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::capture::interface::NetworkInterface;
+
+    #[test]
+    #[ignore = "needs CAP_NET_RAW"]
+    fn test_ring_on_loopback() {
+        let index = NetworkInterface::by_name("lo").unwrap().index;
+        let config = RingConfig { enabled: true, block_size: 65536, block_count: 4, block_timeout_ms: 1 };
+        let mut ring = PacketRing::new(&config, Duration::from_millis(100))
+            .map(PacketRing::with_kernel_timestamps)
+            .and_then(|ring| ring.bind(index))
+            .unwrap();
+
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let before = chrono::Utc::now();
+        sender.send_to(b"netsentinel", "127.0.0.1:9").unwrap();
+
+        let packet = ring.receive().unwrap();
+        assert_eq!(packet.length, packet.data.len());
+        assert!(packet.data.ends_with(b"netsentinel"));
+        let timestamp = packet.timestamp.expect("no kernel timestamp");
+        assert!(timestamp >= before && timestamp <= chrono::Utc::now());
+        assert_eq!(ring.dropped().unwrap(), 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Latency budget and load shedding
+//!
+//! Each capture thread measures how long it spends decoding and enqueueing
+//! a frame. When the running average goes over the configured budget the
+//! capture falls behind the wire, and rather than letting the socket and
+//! the channels overflow and drop frames at random, it degrades: frames are
+//! decoded down to their transport headers only (no TLS, DHCP or DNS
+//! metadata), and only one frame in `sample_rate` is kept. Full decoding
+//! resumes once the average is back under half the budget and the capture
+//! has been degraded for `recovery_secs`.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::ShedConfig;
+
+/// Weight of the latest frame in the average latency (1/64)
+const AVERAGE_WEIGHT: f64 = 1.0 / 64.0;
+
+/// How a capture decodes its frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Every frame, with its application metadata
+    Full,
+    /// Sampled frames, transport headers only
+    Degraded,
+}
+
+impl fmt::Display for CaptureMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "full"),
+            Self::Degraded => write!(f, "degraded"),
+        }
+    }
+}
+
+/// What to do with the next frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Decode it entirely
+    Full,
+    /// Decode its headers only
+    Headers,
+    /// Drop it undecoded
+    Shed,
+}
+
+/// Latency tracking and shedding state of one capture thread
+#[derive(Debug)]
+pub struct Shedder {
+    budget_ns: f64,
+    sample_rate: u64,
+    recovery: Duration,
+    average_ns: f64,
+    mode: CaptureMode,
+    since: Instant,
+    frames: u64,
+}
+
+impl Shedder {
+    pub fn new(config: &ShedConfig) -> Self {
+        Self {
+            budget_ns: Duration::from_micros(config.budget_us).as_nanos() as f64,
+            sample_rate: config.sample_rate.max(1) as u64,
+            recovery: Duration::from_secs(config.recovery_secs),
+            average_ns: 0.0,
+            mode: CaptureMode::Full,
+            since: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    /// Current mode
+    pub fn mode(&self) -> CaptureMode {
+        self.mode
+    }
+
+    /// Average decode and enqueue time of a frame
+    pub fn average(&self) -> Duration {
+        Duration::from_nanos(self.average_ns as u64)
+    }
+
+    /// Decide how to handle the next frame
+    pub fn admit(&mut self) -> Admission {
+        match self.mode {
+            CaptureMode::Full => Admission::Full,
+            CaptureMode::Degraded => {
+                self.frames += 1;
+                if self.frames.is_multiple_of(self.sample_rate) { Admission::Headers } else { Admission::Shed }
+            }
+        }
+    }
+
+    /// Record the time spent on an admitted frame
+    ///
+    /// Returns the new mode when this frame changed it.
+    pub fn record(&mut self, elapsed: Duration, now: Instant) -> Option<CaptureMode> {
+        let elapsed_ns = elapsed.as_nanos() as f64;
+        self.average_ns += (elapsed_ns - self.average_ns) * AVERAGE_WEIGHT;
+
+        let mode = match self.mode {
+            CaptureMode::Full if self.average_ns > self.budget_ns => CaptureMode::Degraded,
+            CaptureMode::Degraded
+                if self.average_ns < self.budget_ns / 2.0 && now.duration_since(self.since) >= self.recovery =>
+            {
+                CaptureMode::Full
+            }
+            _ => return None,
+        };
+        self.mode = mode;
+        self.since = now;
+        self.frames = 0;
+        Some(mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder() -> Shedder {
+        Shedder::new(&ShedConfig { budget_us: 10, sample_rate: 4, recovery_secs: 5 })
+    }
+
+    #[test]
+    fn test_degrade_and_recover() {
+        let mut shedder = shedder();
+        let start = Instant::now();
+
+        // Within budget: every frame is fully decoded
+        for _ in 0..1000 {
+            assert_eq!(shedder.admit(), Admission::Full);
+            assert_eq!(shedder.record(Duration::from_micros(5), start), None);
+        }
+
+        // Falling behind: degraded once the average crosses the budget
+        let mut transition = None;
+        for _ in 0..1000 {
+            transition = transition.or(shedder.record(Duration::from_micros(50), start));
+        }
+        assert_eq!(transition, Some(CaptureMode::Degraded));
+        let admissions: Vec<_> = (0..8).map(|_| shedder.admit()).collect();
+        assert_eq!(admissions.iter().filter(|a| **a == Admission::Headers).count(), 2);
+        assert_eq!(admissions.iter().filter(|a| **a == Admission::Shed).count(), 6);
+
+        // Back under half the budget, but not degraded for long enough
+        for _ in 0..1000 {
+            assert_eq!(shedder.record(Duration::from_micros(1), start + Duration::from_secs(1)), None);
+        }
+        assert_eq!(shedder.mode(), CaptureMode::Degraded);
+        let later = start + Duration::from_secs(6);
+        assert_eq!(shedder.record(Duration::from_micros(1), later), Some(CaptureMode::Full));
+        assert_eq!(shedder.admit(), Admission::Full);
+    }
+}
@@ -0,0 +1,233 @@
+//! Packet socket
+//!
+//! pnet's datalink channel hides its socket, so captures needing socket
+//! options read from an AF_PACKET socket of their own: one returning kernel
+//! receive timestamps (`SO_TIMESTAMPNS`), or one with a capture filter
+//! attached. The socket is only bound to the interface once configured, so
+//! no unfiltered packet is queued before the filter is in place.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::io;
+use std::time::Duration;
+
+use super::filter::BpfInstruction;
+
+/// Packet read from a [`PacketSocket`] or a [`super::ring::PacketRing`]
+pub struct TimestampedPacket<'a> {
+    /// Packet bytes, clipped to the read buffer or ring block
+    pub data: &'a [u8],
+    /// Length of the packet on the wire
+    pub length: usize,
+    /// Kernel receive time, when timestamps are enabled
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// AF_PACKET socket reading the packets of one interface
+#[cfg(target_os = "linux")]
+pub struct PacketSocket {
+    fd: std::os::fd::OwnedFd,
+    buffer: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl PacketSocket {
+    /// Open a socket receiving nothing until bound
+    ///
+    /// Reads give up after `read_timeout` with a `WouldBlock` error, so the
+    /// capture loop can check whether it was stopped.
+    pub fn new(read_buffer_size: usize, read_timeout: Duration) -> Result<Self> {
+        use anyhow::Context;
+
+        let socket = Self {
+            fd: open()?,
+            buffer: vec![0; read_buffer_size],
+        };
+
+        let timeout = libc::timeval {
+            tv_sec: read_timeout.as_secs() as libc::time_t,
+            tv_usec: read_timeout.subsec_micros() as libc::suseconds_t,
+        };
+        set_option(&socket.fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)
+            .context("Failed to set the packet socket read timeout")?;
+        Ok(socket)
+    }
+
+    /// Return each packet with its kernel receive timestamp
+    pub fn with_kernel_timestamps(self) -> Result<Self> {
+        use anyhow::Context;
+
+        let enable: libc::c_int = 1;
+        set_option(&self.fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, &enable)
+            .context("Failed to enable kernel timestamps")?;
+        Ok(self)
+    }
+
+    /// Only receive the packets accepted by a classic BPF `program`
+    pub fn with_filter(self, program: &[BpfInstruction]) -> Result<Self> {
+        use anyhow::Context;
+
+        attach_filter(&self.fd, program).context("Failed to attach the capture filter")?;
+        Ok(self)
+    }
+
+    /// Start receiving every packet of interface `index`
+    pub fn bind(self, index: u32) -> Result<Self> {
+        use anyhow::Context;
+
+        bind(&self.fd, index).context("Failed to bind packet socket")?;
+        Ok(self)
+    }
+
+    /// Wait for the next packet
+    pub fn receive(&mut self) -> io::Result<TimestampedPacket<'_>> {
+        use std::os::fd::AsRawFd;
+
+        let mut iov = libc::iovec {
+            iov_base: self.buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: self.buffer.len(),
+        };
+        // Room for the timestamp control message, aligned for cmsghdr
+        let mut control = [0u64; 8];
+
+        unsafe {
+            let mut message: libc::msghdr = std::mem::zeroed();
+            message.msg_iov = &mut iov;
+            message.msg_iovlen = 1;
+            message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            message.msg_controllen = std::mem::size_of_val(&control) as _;
+
+            // With MSG_TRUNC, packet sockets return the length on the wire
+            let length = libc::recvmsg(self.fd.as_raw_fd(), &mut message, libc::MSG_TRUNC);
+            if length < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let length = length as usize;
+
+            let mut timestamp = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&message);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS {
+                    let time = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                    timestamp = DateTime::from_timestamp(time.tv_sec as i64, time.tv_nsec as u32);
+                }
+                cmsg = libc::CMSG_NXTHDR(&message, cmsg);
+            }
+
+            Ok(TimestampedPacket {
+                data: &self.buffer[..length.min(self.buffer.len())],
+                length,
+                timestamp,
+            })
+        }
+    }
+}
+
+/// Open an AF_PACKET socket, receiving nothing until bound
+#[cfg(target_os = "linux")]
+pub(super) fn open() -> Result<std::os::fd::OwnedFd> {
+    use anyhow::Context;
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // Protocol 0 until bound: the socket does not receive yet
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to open packet socket. Are you running as root?");
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Set a socket option of `fd`
+#[cfg(target_os = "linux")]
+pub(super) fn set_option<T>(fd: &std::os::fd::OwnedFd, level: libc::c_int, option: libc::c_int, value: &T) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            option,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Attach a classic BPF `program` to `fd`
+#[cfg(target_os = "linux")]
+pub(super) fn attach_filter(fd: &std::os::fd::OwnedFd, program: &[BpfInstruction]) -> io::Result<()> {
+    let mut filter: Vec<libc::sock_filter> = program
+        .iter()
+        .map(|insn| libc::sock_filter { code: insn.code, jt: insn.jt, jf: insn.jf, k: insn.k })
+        .collect();
+    let fprog = libc::sock_fprog { len: filter.len() as libc::c_ushort, filter: filter.as_mut_ptr() };
+    set_option(fd, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &fprog)
+}
+
+/// Bind `fd` to every packet of interface `index`
+#[cfg(target_os = "linux")]
+pub(super) fn bind(fd: &std::os::fd::OwnedFd, index: u32) -> io::Result<()> {
+    use std::mem::size_of;
+    use std::os::fd::AsRawFd;
+
+    let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    address.sll_family = libc::AF_PACKET as libc::c_ushort;
+    address.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    address.sll_ifindex = index as libc::c_int;
+    let address_ptr = &address as *const libc::sockaddr_ll as *const libc::sockaddr;
+    let result = unsafe { libc::bind(fd.as_raw_fd(), address_ptr, size_of::<libc::sockaddr_ll>() as libc::socklen_t) };
+    if result < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Packet sockets are only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub struct PacketSocket;
+
+#[cfg(not(target_os = "linux"))]
+impl PacketSocket {
+    pub fn new(_read_buffer_size: usize, _read_timeout: Duration) -> Result<Self> {
+        anyhow::bail!("Kernel timestamps and capture filters are only supported on Linux")
+    }
+
+    pub fn with_kernel_timestamps(self) -> Result<Self> {
+        Ok(self)
+    }
+
+    pub fn with_filter(self, _program: &[BpfInstruction]) -> Result<Self> {
+        Ok(self)
+    }
+
+    pub fn bind(self, _index: u32) -> Result<Self> {
+        Ok(self)
+    }
+
+    pub fn receive(&mut self) -> io::Result<TimestampedPacket<'_>> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::capture::interface::NetworkInterface;
+
+    #[test]
+    #[ignore = "needs CAP_NET_RAW"]
+    fn test_kernel_timestamps_on_loopback() {
+        let index = NetworkInterface::by_name("lo").unwrap().index;
+        let mut socket = PacketSocket::new(65536, Duration::from_millis(100))
+            .and_then(PacketSocket::with_kernel_timestamps)
+            .and_then(|socket| socket.bind(index))
+            .unwrap();
+
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let before = Utc::now();
+        sender.send_to(b"netsentinel", "127.0.0.1:9").unwrap();
+
+        let packet = socket.receive().unwrap();
+        assert_eq!(packet.length, packet.data.len());
+        let timestamp = packet.timestamp.expect("no kernel timestamp");
+        assert!(timestamp >= before && timestamp <= Utc::now());
+    }
+}
@@ -0,0 +1,98 @@
+//! Async frame stream
+//!
+//! Frames of capture threads as a `Stream`, for tokio consumers that would
+//! otherwise bridge the crossbeam channel themselves.
+//!
+//! ```no_run
+//! use netsentinel_capture::capture::AfPacketCapture;
+//! use tokio_stream::StreamExt;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut frames = AfPacketCapture::new("eth0", true, 1518)?.into_stream(8192);
+//! while let Some(frame) = frames.next().await {
+//!     println!("{} -> {}", frame.src_mac, frame.dst_mac);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+
+use netsentinel_types::CapturedFrame;
+use super::af_packet::{AfPacketCapture, CaptureStatsSnapshot};
+
+/// Stream of the frames decoded by running captures
+///
+/// Ends once the captures are stopped and the buffered frames consumed.
+/// Dropping the stream stops the captures.
+pub struct FrameStream {
+    captures: Vec<Arc<AfPacketCapture>>,
+    frames: mpsc::Receiver<CapturedFrame>,
+}
+
+impl FrameStream {
+    pub(crate) fn new(captures: Vec<Arc<AfPacketCapture>>, frames: mpsc::Receiver<CapturedFrame>) -> Self {
+        Self { captures, frames }
+    }
+
+    /// Combined statistics of the captures
+    pub fn stats(&self) -> CaptureStatsSnapshot {
+        let mut combined = CaptureStatsSnapshot::default();
+        for capture in &self.captures {
+            combined.add(&capture.stats().snapshot());
+        }
+        combined
+    }
+
+    /// Stop the captures; frames already buffered are still yielded
+    pub fn stop(&self) {
+        for capture in &self.captures {
+            capture.stop();
+        }
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = CapturedFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<CapturedFrame>> {
+        self.frames.poll_recv(cx)
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_stream_ends_with_senders() {
+        let (tx, rx) = mpsc::channel(4);
+        let mut stream = FrameStream::new(Vec::new(), rx);
+
+        let frame: CapturedFrame = serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-05-01T10:00:00Z",
+            "interface": "eth0",
+            "src_mac": "00:11:22:33:44:55",
+            "dst_mac": "66:77:88:99:aa:bb",
+            "ethertype": 0x0800,
+            "frame_size": 60,
+            "payload_size": 0,
+        })).unwrap();
+        tx.send(frame).await.unwrap();
+        drop(tx);
+
+        assert_eq!(stream.next().await.map(|frame| frame.frame_size), Some(60));
+        assert!(stream.next().await.is_none());
+    }
+}
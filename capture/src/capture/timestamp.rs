@@ -0,0 +1,58 @@
+//! Frame timestamp sources
+//!
+//! Frames are stamped either with the time the kernel received them or with
+//! the wall clock when the capture thread decodes them. Kernel timestamps are
+//! read along with each packet from a [`PacketSocket`](super::socket::PacketSocket)
+//! (`SO_TIMESTAMPNS`), and are not delayed when the capture thread is
+//! descheduled or working through a burst; both have nanosecond resolution.
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Clock frames are stamped with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampSource {
+    /// Time the kernel received the frame
+    Kernel,
+    /// Wall clock when the capture thread decodes the frame
+    #[default]
+    Userspace,
+}
+
+impl fmt::Display for TimestampSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kernel => write!(f, "kernel"),
+            Self::Userspace => write!(f, "userspace"),
+        }
+    }
+}
+
+impl FromStr for TimestampSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "kernel" => Ok(Self::Kernel),
+            "userspace" => Ok(Self::Userspace),
+            _ => bail!("Invalid timestamp source: '{}' (expected kernel or userspace)", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_source() {
+        assert_eq!("Kernel".parse::<TimestampSource>().unwrap(), TimestampSource::Kernel);
+        assert_eq!("userspace".parse::<TimestampSource>().unwrap(), TimestampSource::Userspace);
+        assert!("hardware".parse::<TimestampSource>().is_err());
+        assert_eq!(TimestampSource::default().to_string(), "userspace");
+    }
+}
@@ -0,0 +1,178 @@
+//! Flow collector input
+//!
+//! Alternative to AF_PACKET capture for sites without a SPAN port: listens
+//! for sFlow v5, NetFlow v9 and IPFIX datagrams exported by switches and
+//! converts packet samples and flow records into `CapturedFrame`s, which
+//! then follow the normal path to the Redis stream.
+
+pub mod netflow;
+pub mod sflow;
+
+use anyhow::{Context, Result, bail};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+use crate::capture::{CaptureStats, CapturedFrame};
+use crate::config::FlowInputConfig;
+
+pub use netflow::NetflowDecoder;
+
+/// Largest datagram accepted
+const MAX_DATAGRAM: usize = 65535;
+
+/// Flow export protocols
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Sflow,
+    /// NetFlow v9 and IPFIX (told apart by the version field)
+    Netflow,
+}
+
+/// Listens for flow exports and turns them into frames
+pub struct FlowCollector {
+    config: FlowInputConfig,
+    sflow_stats: Arc<CaptureStats>,
+    netflow_stats: Arc<CaptureStats>,
+}
+
+impl FlowCollector {
+    /// Create a collector for the listeners enabled in `config`
+    pub fn new(config: FlowInputConfig) -> Self {
+        Self {
+            config,
+            sflow_stats: Arc::new(CaptureStats::new()),
+            netflow_stats: Arc::new(CaptureStats::new()),
+        }
+    }
+
+    /// Get the statistics of each listener, by protocol name
+    pub fn stats(&self) -> Vec<(String, Arc<CaptureStats>)> {
+        vec![
+            ("sflow".to_string(), Arc::clone(&self.sflow_stats)),
+            ("netflow".to_string(), Arc::clone(&self.netflow_stats)),
+        ]
+    }
+
+    /// Receive datagrams until the frame channel closes
+    pub async fn run(self, frame_tx: mpsc::Sender<CapturedFrame>) -> Result<()> {
+        let mut listeners = JoinSet::new();
+
+        for (protocol, port, stats) in [
+            (Protocol::Sflow, self.config.sflow_port, &self.sflow_stats),
+            (Protocol::Netflow, self.config.netflow_port, &self.netflow_stats),
+        ] {
+            if port == 0 {
+                continue;
+            }
+            let addr = SocketAddr::new(self.config.bind, port);
+            let socket = UdpSocket::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind {:?} listener on {}", protocol, addr))?;
+            info!("Listening for {:?} on {}", protocol, addr);
+
+            listeners.spawn(listen(socket, protocol, Arc::clone(stats), frame_tx.clone()));
+        }
+
+        if listeners.is_empty() {
+            bail!("No flow listener enabled (sflow_port and netflow_port are both 0)");
+        }
+        while let Some(result) = listeners.join_next().await {
+            result??;
+        }
+        Ok(())
+    }
+}
+
+/// Decode datagrams from one socket
+async fn listen(
+    socket: UdpSocket,
+    protocol: Protocol,
+    stats: Arc<CaptureStats>,
+    frame_tx: mpsc::Sender<CapturedFrame>,
+) -> Result<()> {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    let mut netflow = NetflowDecoder::new();
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let datagram = &buf[..len];
+
+        let frames = match protocol {
+            Protocol::Sflow => sflow::decode(datagram),
+            Protocol::Netflow => netflow.decode(peer.ip(), datagram),
+        };
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => {
+                stats.parse_errors.fetch_add(1, Ordering::Relaxed);
+                debug!("Invalid {:?} datagram from {}: {}", protocol, peer, e);
+                continue;
+            }
+        };
+
+        for frame in frames {
+            stats.packets_captured.fetch_add(1, Ordering::Relaxed);
+            stats.bytes_captured.fetch_add(frame.frame_size as u64, Ordering::Relaxed);
+
+            match frame_tx.try_send(frame) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    stats.packets_dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Big-endian reader over a datagram
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            bail!("Truncated datagram: needed {} bytes at offset {}", len, self.offset);
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn ipv4(&mut self) -> Result<Ipv4Addr> {
+        self.u32().map(Ipv4Addr::from)
+    }
+}
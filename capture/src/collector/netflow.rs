@@ -0,0 +1,409 @@
+//! NetFlow v9 and IPFIX decoding
+//!
+//! Templates are learned per exporter and observation domain (source ID in
+//! NetFlow v9); data records arriving before their template are skipped.
+//! Each record becomes one frame carrying the flow's addresses, ports and
+//! byte count. The aggregator tracks devices by MAC address, so records
+//! whose template has no source and destination MAC fields are ignored.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use tracing::{debug, warn};
+
+use netsentinel_types::{CapturedFrame, MacAddr, TcpFlags, VlanInfo};
+use crate::decode::ethernet::ETHERTYPE_IPV4;
+
+use super::Reader;
+
+const NETFLOW_V9: u16 = 9;
+const IPFIX: u16 = 10;
+
+/// Template set IDs (options templates are not needed)
+const V9_TEMPLATE_SET: u16 = 0;
+const IPFIX_TEMPLATE_SET: u16 = 2;
+
+/// First data set ID
+const MIN_DATA_SET: u16 = 256;
+
+/// IPFIX length announcing a variable-length field
+const VARIABLE_LENGTH: u16 = 65535;
+
+/// Information elements used to build frames (NetFlow v9 field types share
+/// these IDs)
+mod ie {
+    pub const OCTET_DELTA_COUNT: u16 = 1;
+    pub const PROTOCOL: u16 = 4;
+    pub const IP_CLASS_OF_SERVICE: u16 = 5;
+    pub const TCP_FLAGS: u16 = 6;
+    pub const SRC_PORT: u16 = 7;
+    pub const SRC_IPV4: u16 = 8;
+    pub const DST_PORT: u16 = 11;
+    pub const DST_IPV4: u16 = 12;
+    pub const ICMP_TYPE_CODE_IPV4: u16 = 32;
+    pub const LAST_SWITCHED: u16 = 21;
+    pub const OCTET_TOTAL_COUNT: u16 = 85;
+    pub const SRC_MAC: u16 = 56;
+    pub const POST_DST_MAC: u16 = 57;
+    pub const VLAN_ID: u16 = 58;
+    pub const DST_MAC: u16 = 80;
+    pub const POST_SRC_MAC: u16 = 81;
+    pub const FLOW_END_SECONDS: u16 = 151;
+    pub const FLOW_END_MILLISECONDS: u16 = 153;
+    pub const DOT1Q_VLAN_ID: u16 = 243;
+    pub const ETHERNET_TYPE: u16 = 256;
+}
+
+/// Field of a template
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    id: u16,
+    length: u16,
+    /// Enterprise-specific (IPFIX), never interpreted
+    enterprise: bool,
+}
+
+/// Template key: exporter, observation domain / source ID, template ID
+type TemplateKey = (IpAddr, u32, u16);
+
+/// Decodes NetFlow v9 and IPFIX datagrams, keeping the templates seen
+#[derive(Default)]
+pub struct NetflowDecoder {
+    templates: HashMap<TemplateKey, Vec<Field>>,
+}
+
+/// Header values needed to interpret records
+struct Context {
+    exporter: IpAddr,
+    domain: u32,
+    version: u16,
+    export_time: DateTime<Utc>,
+    /// NetFlow v9 boot time (ms since the epoch), for `LAST_SWITCHED`
+    boot_ms: i64,
+}
+
+impl NetflowDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a datagram from `exporter` into one frame per usable record
+    pub fn decode(&mut self, exporter: IpAddr, data: &[u8]) -> Result<Vec<CapturedFrame>> {
+        let mut reader = Reader::new(data);
+
+        let version = reader.u16()?;
+        let context = match version {
+            NETFLOW_V9 => {
+                let _count = reader.u16()?;
+                let uptime_ms = reader.u32()? as i64;
+                let unix_secs = reader.u32()? as i64;
+                let _sequence = reader.u32()?;
+                let domain = reader.u32()?;
+                Context {
+                    exporter,
+                    domain,
+                    version,
+                    export_time: timestamp(unix_secs * 1000),
+                    boot_ms: unix_secs * 1000 - uptime_ms,
+                }
+            }
+            IPFIX => {
+                let length = reader.u16()? as usize;
+                if length > data.len() {
+                    bail!("IPFIX message length {} exceeds datagram size {}", length, data.len());
+                }
+                reader = Reader { data: &data[..length], offset: 4 };
+                let export_secs = reader.u32()? as i64;
+                let _sequence = reader.u32()?;
+                let domain = reader.u32()?;
+                Context {
+                    exporter,
+                    domain,
+                    version,
+                    export_time: timestamp(export_secs * 1000),
+                    boot_ms: 0,
+                }
+            }
+            other => bail!("Unsupported NetFlow version {}", other),
+        };
+
+        let mut frames = Vec::new();
+        while reader.remaining() >= 4 {
+            let set_id = reader.u16()?;
+            let length = reader.u16()? as usize;
+            if length < 4 {
+                bail!("Invalid set length {}", length);
+            }
+            let mut set = Reader::new(reader.bytes(length - 4)?);
+
+            match set_id {
+                V9_TEMPLATE_SET if version == NETFLOW_V9 => self.templates_from(&context, &mut set)?,
+                IPFIX_TEMPLATE_SET if version == IPFIX => self.templates_from(&context, &mut set)?,
+                id if id >= MIN_DATA_SET => self.records_from(&context, id, &mut set, &mut frames)?,
+                _ => {}
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Learn (or withdraw) the templates of a template set
+    fn templates_from(&mut self, context: &Context, set: &mut Reader) -> Result<()> {
+        while set.remaining() >= 4 {
+            let template_id = set.u16()?;
+            let field_count = set.u16()?;
+            let key = (context.exporter, context.domain, template_id);
+
+            if field_count == 0 {
+                self.templates.remove(&key);
+                continue;
+            }
+
+            let mut fields = Vec::with_capacity(field_count as usize);
+            for _ in 0..field_count {
+                let id = set.u16()?;
+                let length = set.u16()?;
+                let enterprise = context.version == IPFIX && id & 0x8000 != 0;
+                if enterprise {
+                    set.skip(4)?;
+                }
+                fields.push(Field { id: id & 0x7fff, length, enterprise });
+            }
+
+            let has_macs = [ie::SRC_MAC, ie::POST_SRC_MAC].iter().any(|id| has_field(&fields, *id))
+                && [ie::DST_MAC, ie::POST_DST_MAC].iter().any(|id| has_field(&fields, *id));
+            if !has_macs && !self.templates.contains_key(&key) {
+                warn!(
+                    "Template {} from {} has no MAC address fields; its records are ignored",
+                    template_id, context.exporter
+                );
+            }
+            self.templates.insert(key, fields);
+        }
+        Ok(())
+    }
+
+    /// Convert the records of a data set into frames
+    fn records_from(
+        &self,
+        context: &Context,
+        template_id: u16,
+        set: &mut Reader,
+        frames: &mut Vec<CapturedFrame>,
+    ) -> Result<()> {
+        let Some(fields) = self.templates.get(&(context.exporter, context.domain, template_id)) else {
+            debug!("No template {} from {} yet, skipping data set", template_id, context.exporter);
+            return Ok(());
+        };
+
+        // Variable-length fields take at least one byte; anything shorter is padding
+        let min_length: usize = fields.iter()
+            .map(|f| if f.length == VARIABLE_LENGTH { 1 } else { f.length as usize })
+            .sum();
+        if min_length == 0 {
+            return Ok(());
+        }
+
+        let interface = format!("netflow:{}", context.exporter);
+        while set.remaining() >= min_length {
+            let mut record = Record::default();
+            for field in fields {
+                let length = match field.length {
+                    VARIABLE_LENGTH => match set.u8()? {
+                        255 => set.u16()? as usize,
+                        length => length as usize,
+                    },
+                    length => length as usize,
+                };
+                let value = set.bytes(length)?;
+                if !field.enterprise {
+                    record.set(field.id, value, context);
+                }
+            }
+            if let Some(frame) = record.into_frame(&interface, context.export_time) {
+                frames.push(frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields of one flow record
+#[derive(Default)]
+struct Record {
+    src_mac: Option<MacAddr>,
+    dst_mac: Option<MacAddr>,
+    src_ip: Option<Ipv4Addr>,
+    dst_ip: Option<Ipv4Addr>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+    protocol: Option<u8>,
+    /// Type of service byte, DSCP in its upper 6 bits
+    tos: Option<u8>,
+    tcp_flags: Option<u8>,
+    icmp_type_code: Option<u16>,
+    vlan_id: Option<u16>,
+    ethertype: Option<u16>,
+    octets: u64,
+    end: Option<DateTime<Utc>>,
+}
+
+impl Record {
+    fn set(&mut self, id: u16, value: &[u8], context: &Context) {
+        let number = value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+
+        match id {
+            ie::SRC_MAC => self.src_mac = self.src_mac.or(MacAddr::from_slice(value)),
+            ie::POST_SRC_MAC => self.src_mac = self.src_mac.or(MacAddr::from_slice(value)),
+            ie::DST_MAC => self.dst_mac = self.dst_mac.or(MacAddr::from_slice(value)),
+            ie::POST_DST_MAC => self.dst_mac = self.dst_mac.or(MacAddr::from_slice(value)),
+            ie::SRC_IPV4 if value.len() == 4 => self.src_ip = Some(Ipv4Addr::from(number as u32)),
+            ie::DST_IPV4 if value.len() == 4 => self.dst_ip = Some(Ipv4Addr::from(number as u32)),
+            ie::SRC_PORT => self.src_port = Some(number as u16),
+            ie::DST_PORT => self.dst_port = Some(number as u16),
+            ie::PROTOCOL => self.protocol = Some(number as u8),
+            ie::IP_CLASS_OF_SERVICE => self.tos = Some(number as u8),
+            ie::TCP_FLAGS => self.tcp_flags = Some(number as u8),
+            ie::ICMP_TYPE_CODE_IPV4 => self.icmp_type_code = Some(number as u16),
+            ie::VLAN_ID | ie::DOT1Q_VLAN_ID if number != 0 => self.vlan_id = Some(number as u16 & 0x0fff),
+            ie::ETHERNET_TYPE => self.ethertype = Some(number as u16),
+            ie::OCTET_DELTA_COUNT | ie::OCTET_TOTAL_COUNT => self.octets = self.octets.max(number),
+            ie::FLOW_END_SECONDS => self.end = Some(timestamp(number as i64 * 1000)),
+            ie::FLOW_END_MILLISECONDS => self.end = Some(timestamp(number as i64)),
+            ie::LAST_SWITCHED if context.version == NETFLOW_V9 => {
+                self.end = Some(timestamp(context.boot_ms + number as i64));
+            }
+            _ => {}
+        }
+    }
+
+    fn into_frame(self, interface: &str, export_time: DateTime<Utc>) -> Option<CapturedFrame> {
+        let (src_mac, dst_mac) = (self.src_mac?, self.dst_mac?);
+
+        let ethertype = self.ethertype
+            .unwrap_or(if self.src_ip.is_some() { ETHERTYPE_IPV4 } else { 0 });
+        let frame_size = self.octets.min(u32::MAX as u64) as u32;
+
+        let mut frame = CapturedFrame::new(interface, src_mac, dst_mac, ethertype, frame_size);
+        frame.timestamp = self.end.unwrap_or(export_time);
+        frame.vlan = self.vlan_id.map(|id| VlanInfo { id, priority: 0, dei: false });
+        frame.src_ip = self.src_ip;
+        frame.dst_ip = self.dst_ip;
+        frame.ip_protocol = self.protocol;
+        frame.dscp = self.tos.map(|tos| tos >> 2);
+        frame.src_port = self.src_port;
+        frame.dst_port = self.dst_port;
+        if self.protocol == Some(6) {
+            frame.tcp_flags = self.tcp_flags.map(TcpFlags::from_byte);
+        }
+        if self.protocol == Some(1) {
+            // ICMP has no ports: exporters without the type and code field
+            // put them in the destination port (type * 256 + code)
+            if let Some(type_code) = self.icmp_type_code.or(self.dst_port) {
+                frame.icmp_type = Some((type_code >> 8) as u8);
+                frame.icmp_code = Some(type_code as u8);
+            }
+            frame.src_port = None;
+            frame.dst_port = None;
+        }
+        Some(frame)
+    }
+}
+
+fn has_field(fields: &[Field], id: u16) -> bool {
+    fields.iter().any(|f| f.id == id && !f.enterprise)
+}
+
+fn timestamp(ms: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(ms).single().unwrap_or_else(Utc::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1));
+
+    /// Template fields: src/dst MAC, src/dst IPv4, ports, protocol, bytes
+    const FIELDS: &[(u16, u16)] = &[(56, 6), (80, 6), (8, 4), (12, 4), (7, 2), (11, 2), (4, 1), (1, 4)];
+
+    fn set(id: u16, body: &[u8]) -> Vec<u8> {
+        let mut set = id.to_be_bytes().to_vec();
+        set.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        set.extend_from_slice(body);
+        set
+    }
+
+    fn template(id: u16) -> Vec<u8> {
+        let mut body = id.to_be_bytes().to_vec();
+        body.extend_from_slice(&(FIELDS.len() as u16).to_be_bytes());
+        for (field, length) in FIELDS {
+            body.extend_from_slice(&field.to_be_bytes());
+            body.extend_from_slice(&length.to_be_bytes());
+        }
+        body
+    }
+
+    fn record() -> Vec<u8> {
+        let mut record = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+        record.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        record.extend_from_slice(&[0xc7, 0x38, 0x00, 0x35, 17]);
+        record.extend_from_slice(&4200u32.to_be_bytes());
+        record
+    }
+
+    #[test]
+    fn test_netflow_v9() {
+        let mut data_set = record();
+        data_set.extend_from_slice(&[0, 0, 0]); // padding
+
+        let mut datagram = vec![0, 9, 0, 2];
+        datagram.extend_from_slice(&60_000u32.to_be_bytes()); // uptime
+        datagram.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+        datagram.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]); // sequence, source ID
+        let mut data_before_template = datagram.clone();
+        datagram.extend(set(V9_TEMPLATE_SET, &template(300)));
+        datagram.extend(set(300, &data_set));
+        data_before_template.extend(set(300, &data_set));
+
+        let mut decoder = NetflowDecoder::new();
+        assert!(decoder.decode(EXPORTER, &data_before_template).unwrap().is_empty());
+
+        let frames = decoder.decode(EXPORTER, &datagram).unwrap();
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert_eq!(frame.interface, "netflow:192.168.0.1");
+        assert_eq!(frame.src_mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!(frame.dst_ip, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(frame.dst_port, Some(53));
+        assert_eq!(frame.ethertype, ETHERTYPE_IPV4);
+        assert_eq!(frame.frame_size, 4200);
+
+        // Another exporter's records don't use this template
+        let other = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+        assert!(decoder.decode(other, &data_before_template).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ipfix() {
+        // The second record is an ICMP port unreachable
+        let mut icmp = record();
+        icmp[20..25].copy_from_slice(&[0x00, 0x00, 0x03, 0x03, 1]);
+        let mut body = set(IPFIX_TEMPLATE_SET, &template(256));
+        body.extend(set(256, &[record(), icmp].concat()));
+
+        let mut message = vec![0, 10];
+        message.extend_from_slice(&(body.len() as u16 + 16).to_be_bytes());
+        message.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7]); // sequence, domain
+        message.extend(body);
+
+        let frames = NetflowDecoder::new().decode(EXPORTER, &message).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].src_port, Some(51000));
+        assert_eq!(frames[0].timestamp.timestamp(), 1_700_000_000);
+        assert_eq!((frames[1].icmp_type, frames[1].icmp_code), (Some(3), Some(3)));
+        assert!(frames[1].dst_port.is_none());
+    }
+}
@@ -1,23 +1,51 @@
 //! Configuration module for NetSentinel Capture
 
+use schemars::JsonSchema;
 use serde::Deserialize;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 
+use netsentinel_types::{template, ServiceNames};
+
+use crate::capture::filter::{FilterProtocol, MAX_FILTER_ENTRIES};
+use crate::capture::TimestampSource;
+use crate::output::projection::FrameField;
+
 /// Main configuration structure
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct Config {
     pub capture: CaptureConfig,
+    #[serde(default)]
     pub redis: RedisConfig,
     pub logging: LoggingConfig,
     #[serde(default)]
     pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub flow_input: FlowInputConfig,
+    #[serde(default)]
+    pub sensor: SensorConfig,
+    #[serde(default)]
+    pub script: ScriptConfig,
+    /// Service names of the deployment's own ports (port -> name), over the
+    /// well-known ones
+    #[serde(default)]
+    pub services: ServiceNames,
+    /// Tenants whose frames go to streams of their own
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
 }
 
 /// Capture settings
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct CaptureConfig {
-    /// Capture mode: "mirror" or "bypass"
+    /// Capture mode: "mirror", "bypass" or "flow" (sFlow/NetFlow/IPFIX
+    /// exports instead of packet capture, see `[flow_input]`)
     #[serde(default = "default_mode")]
     pub mode: String,
 
@@ -29,6 +57,20 @@ pub struct CaptureConfig {
     #[serde(default = "default_snap_length")]
     pub snap_length: usize,
 
+    /// Socket read buffer size of each interface, in bytes
+    #[serde(default = "default_read_buffer_size")]
+    pub read_buffer_size: usize,
+
+    /// Clock frames are stamped with: "kernel" (receive time) or
+    /// "userspace" (decode time)
+    #[serde(default)]
+    pub timestamp_source: TimestampSource,
+
+    /// Frames to capture, filtered in the kernel, e.g.
+    /// `{ vlan = [10], proto = ["tcp"], not_port = [6379] }` (default: all)
+    #[serde(default)]
+    pub filter: Option<CaptureFilter>,
+
     /// Flush interval in milliseconds
     #[serde(default = "default_flush_interval")]
     pub flush_interval_ms: u64,
@@ -37,22 +79,441 @@ pub struct CaptureConfig {
     #[serde(default = "default_batch_size")]
     pub batch_size: usize,
 
-    /// Network interfaces to monitor
+    /// Network interfaces to monitor (unused in flow mode)
+    #[serde(default)]
     pub interfaces: Vec<InterfaceConfig>,
+
+    /// Sites and segments of the ERSPAN sessions received on interfaces
+    /// with `erspan` enabled
+    #[serde(default)]
+    pub erspan_sessions: Vec<ErspanSessionConfig>,
+
+    /// Per-frame latency budget, and what to shed when over it
+    #[serde(default)]
+    pub shed: ShedConfig,
+
+    /// TCP stream reassembly of the connections on selected ports
+    #[serde(default)]
+    pub reassembly: ReassemblyConfig,
+
+    /// TPACKET_V3 ring buffer the interfaces are read from, instead of a
+    /// read per frame
+    #[serde(default)]
+    pub ring: RingConfig,
+
+    /// Runtime tuning of `ring_buffer_size` and `batch_size`
+    #[serde(default)]
+    pub autotune: AutoTuneConfig,
+
+    /// Frames dropped after decoding, by the first rule they match, e.g.
+    /// `{ name = "ipv6-multicast", ethertype = [0x86DD], multicast = true }`
+    /// (default: all frames are sent)
+    #[serde(default)]
+    pub policy: Vec<PolicyRule>,
+}
+
+impl CaptureConfig {
+    /// Frames the channel the captures send to is created for: its largest
+    /// size when auto-tuned, the tuner enforcing the current one
+    pub fn ring_buffer_capacity(&self) -> usize {
+        if self.autotune.enabled && self.mode != "flow" {
+            self.autotune.max_ring_buffer_size
+        } else {
+            self.ring_buffer_size
+        }
+    }
+}
+
+/// Per-frame latency budget (`[capture.shed]`)
+///
+/// Over budget, a capture decodes headers only and samples its frames
+/// until it catches up.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ShedConfig {
+    /// Average decode and enqueue time of a frame, in microseconds, above
+    /// which captures degrade (0: never)
+    #[serde(default)]
+    pub budget_us: u64,
+
+    /// Keep one frame in `sample_rate` while degraded (1: keep all)
+    #[serde(default = "default_shed_sample_rate")]
+    pub sample_rate: u32,
+
+    /// Minimum time degraded before trying full decoding again, in seconds
+    #[serde(default = "default_shed_recovery")]
+    pub recovery_secs: u64,
+}
+
+impl Default for ShedConfig {
+    fn default() -> Self {
+        Self {
+            budget_us: 0,
+            sample_rate: default_shed_sample_rate(),
+            recovery_secs: default_shed_recovery(),
+        }
+    }
+}
+
+/// Runtime tuning of the ring buffer and batch sizes (`[capture.autotune]`)
+///
+/// `ring_buffer_size` and `batch_size` are the sizes to start from; the
+/// tuner keeps them within these bounds. In flow mode only the batch size
+/// is tuned.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AutoTuneConfig {
+    /// Adjust the sizes to the frames dropped and the Redis flushes
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Time between two adjustments, in seconds
+    #[serde(default = "default_autotune_interval")]
+    pub interval_secs: u64,
+
+    /// Smallest ring buffer size (frames)
+    #[serde(default = "default_autotune_min_ring_buffer_size")]
+    pub min_ring_buffer_size: usize,
+
+    /// Largest ring buffer size (frames)
+    #[serde(default = "default_autotune_max_ring_buffer_size")]
+    pub max_ring_buffer_size: usize,
+
+    /// Smallest Redis batch size (frames)
+    #[serde(default = "default_autotune_min_batch_size")]
+    pub min_batch_size: usize,
+
+    /// Largest Redis batch size (frames)
+    #[serde(default = "default_autotune_max_batch_size")]
+    pub max_batch_size: usize,
+}
+
+impl Default for AutoTuneConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_autotune_interval(),
+            min_ring_buffer_size: default_autotune_min_ring_buffer_size(),
+            max_ring_buffer_size: default_autotune_max_ring_buffer_size(),
+            min_batch_size: default_autotune_min_batch_size(),
+            max_batch_size: default_autotune_max_batch_size(),
+        }
+    }
+}
+
+/// TCP stream reassembly (`[capture.reassembly]`)
+///
+/// Decoders of the connections on `ports` read ordered byte streams rather
+/// than single segments. Each interface buffers at most `max_streams` times
+/// `max_stream_bytes`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ReassemblyConfig {
+    /// Reassemble TCP streams
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Ports, client or server side, of the connections to reassemble
+    #[serde(default = "default_reassembly_ports")]
+    pub ports: Vec<u16>,
+
+    /// Connection directions followed per interface
+    #[serde(default = "default_reassembly_max_streams")]
+    pub max_streams: usize,
+
+    /// Bytes buffered per direction, in order or not; a direction over it
+    /// is no longer reassembled
+    #[serde(default = "default_reassembly_max_stream_bytes")]
+    pub max_stream_bytes: usize,
+
+    /// Seconds a direction stays followed without new segments
+    #[serde(default = "default_reassembly_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ports: default_reassembly_ports(),
+            max_streams: default_reassembly_max_streams(),
+            max_stream_bytes: default_reassembly_max_stream_bytes(),
+            timeout_secs: default_reassembly_timeout(),
+        }
+    }
+}
+
+/// TPACKET_V3 ring buffer (`[capture.ring]`)
+///
+/// The kernel writes frames to blocks of memory shared with the capture,
+/// which decodes them in place. Each interface maps `block_size` times
+/// `block_count` bytes; `read_buffer_size` does not apply.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RingConfig {
+    /// Read every interface from a ring buffer
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Size of a block, in bytes: a multiple of 4096 holding the largest
+    /// frame
+    #[serde(default = "default_ring_block_size")]
+    pub block_size: usize,
+
+    /// Blocks of the ring of each interface
+    #[serde(default = "default_ring_block_count")]
+    pub block_count: usize,
+
+    /// Milliseconds before the kernel hands over a block that is not full
+    #[serde(default = "default_ring_block_timeout")]
+    pub block_timeout_ms: u32,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            block_size: default_ring_block_size(),
+            block_count: default_ring_block_count(),
+            block_timeout_ms: default_ring_block_timeout(),
+        }
+    }
 }
 
 /// Interface configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct InterfaceConfig {
+    /// Interface name, or a pattern (`*`, `?`) capturing every matching
+    /// interface that is up, e.g. `mirror*`
     pub name: String,
+
+    /// Named network namespace of the interface (`/run/netns`), or a
+    /// pattern capturing in every matching namespace, e.g. `tenant-*`
+    /// (default: the capture's own)
+    #[serde(default)]
+    pub netns: Option<String>,
+
     #[serde(default = "default_true")]
     pub promiscuous: bool,
     #[serde(default)]
     pub description: Option<String>,
+
+    /// Maximum frame size to capture on this interface (default:
+    /// `capture.snap_length`)
+    #[serde(default)]
+    pub snap_length: Option<usize>,
+
+    /// Socket read buffer size of this interface, in bytes (default:
+    /// `capture.read_buffer_size`)
+    #[serde(default)]
+    pub read_buffer_size: Option<usize>,
+
+    /// Frames buffered for this interface alone before the shared ring
+    /// buffer, so its bursts only drop its own frames (default: none)
+    #[serde(default)]
+    pub channel_capacity: Option<usize>,
+
+    /// Read this interface from a TPACKET_V3 ring buffer (default:
+    /// `capture.ring.enabled`)
+    #[serde(default)]
+    pub ring: Option<bool>,
+
+    /// Clock the frames of this interface are stamped with (default:
+    /// `capture.timestamp_source`)
+    #[serde(default)]
+    pub timestamp_source: Option<TimestampSource>,
+
+    /// Decapsulate the ERSPAN (types I, II and III) traffic mirrored to this
+    /// interface by remote switches
+    #[serde(default)]
+    pub erspan: bool,
+
+    /// Frames to capture on this interface (default: `capture.filter`)
+    #[serde(default)]
+    pub filter: Option<CaptureFilter>,
+}
+
+/// Capture filter, compiled to BPF and run by the kernel
+///
+/// Each list keeps the frames matching one of its entries (`not_` lists:
+/// none of them); frames must pass every list given, and `expr` when set.
+/// `bpf` replaces both with a program compiled by tcpdump.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureFilter {
+    /// VLAN IDs (802.1Q)
+    #[serde(default)]
+    pub vlan: Vec<u16>,
+    #[serde(default)]
+    pub not_vlan: Vec<u16>,
+
+    /// Protocols: arp, ipv4, ipv6, icmp, icmp6, tcp, udp, gre, sctp
+    #[serde(default)]
+    pub proto: Vec<FilterProtocol>,
+    #[serde(default)]
+    pub not_proto: Vec<FilterProtocol>,
+
+    /// IPv4 source or destination addresses
+    #[serde(default)]
+    pub host: Vec<Ipv4Addr>,
+    #[serde(default)]
+    pub not_host: Vec<Ipv4Addr>,
+
+    /// TCP, UDP or SCTP source or destination ports
+    #[serde(default)]
+    pub port: Vec<u16>,
+    #[serde(default)]
+    pub not_port: Vec<u16>,
+
+    /// tcpdump-style expression over VLANs, protocols, IPv4 hosts and ports,
+    /// e.g. `vlan 10 and (tcp port 443 or udp)`
+    #[serde(default)]
+    pub expr: Option<String>,
+
+    /// Classic BPF program in the `tcpdump -ddd` format, e.g. the output of
+    /// `tcpdump -i eth0 -ddd 'vlan 10 or vlan 20'` (default: the lists)
+    #[serde(default)]
+    pub bpf: Option<String>,
+}
+
+impl CaptureFilter {
+    /// Check the values the BPF program can test
+    fn validate(&self) -> Result<()> {
+        let lengths = [
+            ("vlan", self.vlan.len()),
+            ("not_vlan", self.not_vlan.len()),
+            ("proto", self.proto.len()),
+            ("not_proto", self.not_proto.len()),
+            ("host", self.host.len()),
+            ("not_host", self.not_host.len()),
+            ("port", self.port.len()),
+            ("not_port", self.not_port.len()),
+        ];
+        for (name, length) in lengths {
+            if length > MAX_FILTER_ENTRIES {
+                anyhow::bail!("Filter list '{}' has {} entries (maximum {})", name, length, MAX_FILTER_ENTRIES);
+            }
+        }
+        if self.bpf.is_some() && (self.expr.is_some() || lengths.iter().any(|(_, length)| *length > 0)) {
+            anyhow::bail!("Filter 'bpf' replaces the lists and 'expr', they cannot be combined");
+        }
+        if let Some(vlan) = self.vlan.iter().chain(&self.not_vlan).find(|vlan| **vlan > 4095) {
+            anyhow::bail!("Filter VLAN {} must be between 0 and 4095", vlan);
+        }
+        if self.host.iter().chain(&self.not_host).any(|host| host.is_broadcast()) {
+            anyhow::bail!("Filter hosts cannot include the broadcast address");
+        }
+        crate::capture::filter::compile(self)?;
+        Ok(())
+    }
+}
+
+/// ERSPAN session of a remote mirror
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ErspanSessionConfig {
+    /// Session ID configured on the remote switch (0-1023)
+    pub id: u16,
+
+    /// Site the session watches, published instead of `sensor.site`
+    #[serde(default)]
+    pub site: Option<String>,
+
+    /// Segment the session watches, set as the `segment` tag of its frames
+    #[serde(default)]
+    pub segment: Option<String>,
+}
+
+/// What a protocol policy rule does with the frames it matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    /// Send the frames, sparing them from the rules below
+    Allow,
+    /// Drop the frames
+    #[default]
+    Drop,
+}
+
+impl PolicyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+/// Protocol policy rule (`[[capture.policy]]`)
+///
+/// A frame matches when it matches every criterion set, a list matching any
+/// of its values.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct PolicyRule {
+    /// Name the frames of the rule are counted under
+    pub name: String,
+
+    /// What to do with the frames: "allow" or "drop" (default)
+    #[serde(default)]
+    pub action: PolicyAction,
+
+    /// Ethertypes, past the VLAN tags (e.g. 0x86DD for IPv6)
+    #[serde(default)]
+    pub ethertype: Vec<u16>,
+
+    /// Protocols: arp, ipv4, ipv6, icmp, icmp6, tcp, udp, gre, sctp
+    #[serde(default)]
+    pub proto: Vec<FilterProtocol>,
+
+    /// VLAN IDs (inner VLAN if QinQ)
+    #[serde(default)]
+    pub vlan: Vec<u16>,
+
+    /// Whether the destination is a multicast or broadcast address
+    #[serde(default)]
+    pub multicast: Option<bool>,
+}
+
+impl PolicyRule {
+    /// Check the rule matches some frames, not all of them
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.len() > 64 {
+            anyhow::bail!("Policy rule name must be between 1 and 64 characters");
+        }
+        if self.ethertype.is_empty() && self.proto.is_empty() && self.vlan.is_empty() && self.multicast.is_none() {
+            anyhow::bail!("Policy rule '{}' needs an ethertype, proto, vlan or multicast criterion", self.name);
+        }
+        if let Some(vlan) = self.vlan.iter().find(|vlan| **vlan > 4095) {
+            anyhow::bail!("VLAN {} of policy rule '{}' must be between 0 and 4095", vlan, self.name);
+        }
+        Ok(())
+    }
+}
+
+impl InterfaceConfig {
+    /// Snap length of this interface
+    pub fn snap_length(&self, capture: &CaptureConfig) -> usize {
+        self.snap_length.unwrap_or(capture.snap_length)
+    }
+
+    /// Read buffer size of this interface
+    pub fn read_buffer_size(&self, capture: &CaptureConfig) -> usize {
+        self.read_buffer_size.unwrap_or(capture.read_buffer_size)
+    }
+
+    /// Ring buffer of this interface, if read from one
+    pub fn ring<'a>(&self, capture: &'a CaptureConfig) -> Option<&'a RingConfig> {
+        self.ring.unwrap_or(capture.ring.enabled).then_some(&capture.ring)
+    }
+
+    /// Capture filter of this interface
+    pub fn filter<'a>(&'a self, capture: &'a CaptureConfig) -> Option<&'a CaptureFilter> {
+        self.filter.as_ref().or(capture.filter.as_ref())
+    }
+
+    /// Timestamp source of this interface
+    pub fn timestamp_source(&self, capture: &CaptureConfig) -> TimestampSource {
+        self.timestamp_source.unwrap_or(capture.timestamp_source)
+    }
 }
 
 /// Redis configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RedisConfig {
     /// Redis connection URL
     #[serde(default = "default_redis_url")]
@@ -69,10 +530,68 @@ pub struct RedisConfig {
     /// Connection pool size
     #[serde(default = "default_pool_size")]
     pub pool_size: usize,
+
+    /// Optional frame fields to publish, the others left out (default: all)
+    #[serde(default)]
+    pub fields: Option<Vec<FrameField>>,
+
+    /// Optional frame fields left out of the published frames
+    #[serde(default)]
+    pub omit_fields: Vec<FrameField>,
+
+    /// ACL user, over the one of the URL
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password, over the one of the URL
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: default_redis_url(),
+            stream_name: default_stream_name(),
+            max_stream_length: default_max_stream_length(),
+            pool_size: default_pool_size(),
+            fields: None,
+            omit_fields: Vec::new(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+/// Tenant served by the sensor (`[[tenants]]`)
+///
+/// Frames of the tenant's interfaces or VLANs (the outer one of
+/// double-tagged frames) are published to the tenant's own stream, with its
+/// own Redis credentials, rather than to `[redis]`. A frame belongs to the
+/// first tenant it matches.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct TenantConfig {
+    /// Tenant name (`default` stands for the frames of no tenant)
+    pub name: String,
+
+    /// Interfaces of the tenant, or patterns (`*`, `?`), e.g. `acme-*`
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+
+    /// VLAN IDs of the tenant
+    #[serde(default)]
+    pub vlans: Vec<u16>,
+
+    /// Inclusive VLAN ID ranges of the tenant, e.g. `[[100, 199]]`
+    #[serde(default)]
+    pub vlan_ranges: Vec<[u16; 2]>,
+
+    /// Redis stream of the tenant and its credentials
+    pub redis: RedisConfig,
 }
 
 /// Logging configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct LoggingConfig {
     /// Log level
     #[serde(default = "default_log_level")]
@@ -92,7 +611,7 @@ pub struct LoggingConfig {
 }
 
 /// Metrics configuration
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default, JsonSchema)]
 pub struct MetricsConfig {
     /// Enable metrics endpoint
     #[serde(default)]
@@ -107,21 +626,201 @@ pub struct MetricsConfig {
     pub path: String,
 }
 
+/// Local admin socket
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AdminConfig {
+    /// Enable the admin socket
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Unix socket `--log-level` talks to (owner-only permissions)
+    #[serde(default = "default_admin_socket")]
+    pub socket: PathBuf,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket: default_admin_socket(),
+        }
+    }
+}
+
+/// OpenTelemetry configuration
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct TelemetryConfig {
+    /// Export spans and metrics over OTLP
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// OTLP/HTTP collector base URL (`/v1/traces` and `/v1/metrics` are appended)
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+
+    /// Extra headers sent with every export
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// `service.name` resource attribute
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Export spans
+    #[serde(default = "default_true")]
+    pub traces: bool,
+
+    /// Export metrics
+    #[serde(default = "default_true")]
+    pub metrics: bool,
+
+    /// Fraction of traces sampled (0.0 - 1.0)
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+
+    /// Metrics export interval in seconds
+    #[serde(default = "default_telemetry_interval")]
+    pub export_interval_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_otlp_endpoint(),
+            headers: HashMap::new(),
+            service_name: default_service_name(),
+            traces: true,
+            metrics: true,
+            sample_ratio: default_sample_ratio(),
+            export_interval_secs: default_telemetry_interval(),
+        }
+    }
+}
+
+/// Identity stamped on every published frame
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SensorConfig {
+    /// Sensor identifier, unique per capture instance (defaults to the hostname)
+    #[serde(default = "default_sensor_id")]
+    #[schemars(skip_serializing)]
+    pub id: String,
+
+    /// Site the sensor watches; sensors of the same site share one device inventory
+    #[serde(default = "default_site")]
+    pub site: String,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        Self {
+            id: default_sensor_id(),
+            site: default_site(),
+        }
+    }
+}
+
+/// Flow export listeners, used when the capture mode is "flow"
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct FlowInputConfig {
+    /// Address the listeners bind to
+    #[serde(default = "default_flow_bind")]
+    pub bind: IpAddr,
+
+    /// sFlow v5 UDP port (0 disables the listener)
+    #[serde(default = "default_sflow_port")]
+    pub sflow_port: u16,
+
+    /// NetFlow v9 / IPFIX UDP port (0 disables the listener)
+    #[serde(default = "default_netflow_port")]
+    pub netflow_port: u16,
+}
+
+impl Default for FlowInputConfig {
+    fn default() -> Self {
+        Self {
+            bind: default_flow_bind(),
+            sflow_port: default_sflow_port(),
+            netflow_port: default_netflow_port(),
+        }
+    }
+}
+
+/// Scripting hooks run on every decoded frame
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ScriptConfig {
+    /// Run the scripts
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Rhai scripts, evaluated in order on every frame
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+
+    /// Operations a script may run per frame before it is aborted
+    #[serde(default = "default_script_max_operations")]
+    pub max_operations: u64,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            files: Vec::new(),
+            max_operations: default_script_max_operations(),
+        }
+    }
+}
+
 // Default value functions
 fn default_mode() -> String { "mirror".to_string() }
 fn default_ring_buffer_size() -> usize { 8192 }
 fn default_snap_length() -> usize { 1518 }
+fn default_read_buffer_size() -> usize { crate::capture::DEFAULT_READ_BUFFER_SIZE }
 fn default_flush_interval() -> u64 { 100 }
 fn default_batch_size() -> usize { 1000 }
 fn default_redis_url() -> String { "redis://127.0.0.1:6379".to_string() }
 fn default_stream_name() -> String { "netsentinel:frames".to_string() }
 fn default_max_stream_length() -> usize { 100000 }
 fn default_pool_size() -> usize { 4 }
+fn default_script_max_operations() -> u64 { 10_000 }
 fn default_log_level() -> String { "info".to_string() }
 fn default_log_format() -> String { "pretty".to_string() }
 fn default_true() -> bool { true }
+fn default_shed_sample_rate() -> u32 { 10 }
+fn default_shed_recovery() -> u64 { 5 }
+fn default_autotune_interval() -> u64 { 10 }
+fn default_autotune_min_ring_buffer_size() -> usize { 1024 }
+fn default_autotune_max_ring_buffer_size() -> usize { 131072 }
+fn default_autotune_min_batch_size() -> usize { 100 }
+fn default_autotune_max_batch_size() -> usize { 10000 }
+fn default_reassembly_ports() -> Vec<u16> { vec![443, 465, 636, 853, 993, 995, 8443] }
+fn default_reassembly_max_streams() -> usize { 4096 }
+fn default_reassembly_max_stream_bytes() -> usize { 32768 }
+fn default_reassembly_timeout() -> u64 { 30 }
+fn default_ring_block_size() -> usize { 1 << 20 }
+fn default_ring_block_count() -> usize { 64 }
+fn default_ring_block_timeout() -> u32 { 10 }
 fn default_metrics_port() -> u16 { 9100 }
 fn default_metrics_path() -> String { "/metrics".to_string() }
+fn default_admin_socket() -> PathBuf { PathBuf::from("/run/netsentinel/capture.sock") }
+fn default_otlp_endpoint() -> String { "http://localhost:4318".to_string() }
+fn default_service_name() -> String { "netsentinel-capture".to_string() }
+fn default_sample_ratio() -> f64 { 1.0 }
+fn default_telemetry_interval() -> u64 { 60 }
+fn default_flow_bind() -> IpAddr { IpAddr::V4(Ipv4Addr::UNSPECIFIED) }
+fn default_sflow_port() -> u16 { 6343 }
+fn default_netflow_port() -> u16 { 2055 }
+fn default_site() -> String { "default".to_string() }
+
+fn default_sensor_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
 
 impl Config {
     /// Load configuration from a TOML file
@@ -133,23 +832,30 @@ impl Config {
             .with_context(|| "Failed to parse configuration")
     }
 
+    /// Complete default configuration, commented from the field docs
+    pub fn template() -> String {
+        template::render(
+            &schemars::schema_for!(Config),
+            "NetSentinel Capture configuration, generated by `netsentinel-capture \
+             print-default-config`. Optional sections are commented out.",
+        )
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<()> {
         // Validate capture mode
-        if self.capture.mode != "mirror" && self.capture.mode != "bypass" {
-            anyhow::bail!("Invalid capture mode: {}. Must be 'mirror' or 'bypass'", self.capture.mode);
+        if !["mirror", "bypass", "flow"].contains(&self.capture.mode.as_str()) {
+            anyhow::bail!("Invalid capture mode: {}. Must be 'mirror', 'bypass' or 'flow'", self.capture.mode);
         }
 
-        // Validate at least one interface
-        if self.capture.interfaces.is_empty() {
-            anyhow::bail!("At least one capture interface must be configured");
-        }
-
-        // Validate interface names
-        for iface in &self.capture.interfaces {
-            if iface.name.is_empty() {
-                anyhow::bail!("Interface name cannot be empty");
+        if self.capture.mode == "flow" {
+            // Validate at least one flow listener
+            if self.flow_input.sflow_port == 0 && self.flow_input.netflow_port == 0 {
+                anyhow::bail!("Flow mode needs an sFlow or NetFlow port");
             }
+        } else if self.capture.interfaces.is_empty() {
+            // Validate at least one interface
+            anyhow::bail!("At least one capture interface must be configured");
         }
 
         // Validate ring buffer size
@@ -162,6 +868,173 @@ impl Config {
             anyhow::bail!("Snap length must be between 64 and 65535");
         }
 
+        // Validate read buffer size
+        if self.capture.read_buffer_size < 4096 {
+            anyhow::bail!("Read buffer size must be at least 4096 bytes");
+        }
+
+        // Validate shedding
+        if self.capture.shed.sample_rate == 0 {
+            anyhow::bail!("Shed sample rate must be at least 1");
+        }
+
+        // Validate auto-tuning, starting within its bounds
+        let autotune = &self.capture.autotune;
+        if autotune.enabled {
+            if autotune.interval_secs < 1 {
+                anyhow::bail!("Auto-tune interval must be at least 1 second");
+            }
+            if autotune.min_ring_buffer_size < 64
+                || !(autotune.min_ring_buffer_size..=autotune.max_ring_buffer_size).contains(&self.capture.ring_buffer_size)
+            {
+                anyhow::bail!(
+                    "Auto-tune needs 64 <= min_ring_buffer_size <= ring_buffer_size ({}) <= max_ring_buffer_size",
+                    self.capture.ring_buffer_size
+                );
+            }
+            if autotune.min_batch_size < 1
+                || !(autotune.min_batch_size..=autotune.max_batch_size).contains(&self.capture.batch_size)
+            {
+                anyhow::bail!(
+                    "Auto-tune needs 1 <= min_batch_size <= batch_size ({}) <= max_batch_size",
+                    self.capture.batch_size
+                );
+            }
+        }
+
+        // Validate reassembly
+        let reassembly = &self.capture.reassembly;
+        if reassembly.enabled {
+            if reassembly.ports.is_empty() {
+                anyhow::bail!("Reassembly needs at least one port");
+            }
+            if reassembly.max_streams < 1 {
+                anyhow::bail!("Reassembly max streams must be at least 1");
+            }
+            // Room for a whole TLS record (16 KiB plus its header)
+            if reassembly.max_stream_bytes < 16389 {
+                anyhow::bail!("Reassembly max stream bytes must be at least 16389");
+            }
+            if reassembly.timeout_secs < 1 {
+                anyhow::bail!("Reassembly timeout must be at least 1 second");
+            }
+        }
+
+        // Validate the ring buffer, if an interface is read from one
+        let ring = &self.capture.ring;
+        if self.capture.interfaces.iter().any(|iface| iface.ring(&self.capture).is_some()) {
+            // Room for a frame of the largest snap length
+            if ring.block_size < 65536 || !ring.block_size.is_multiple_of(4096) || ring.block_size > u32::MAX as usize {
+                anyhow::bail!("Ring block size must be a multiple of 4096 bytes, at least 65536");
+            }
+            // The kernel fills a block while the capture reads another
+            if ring.block_count < 2 || ring.block_count * ring.block_size > u32::MAX as usize {
+                anyhow::bail!("Ring needs at least 2 blocks, under 4 GiB in all");
+            }
+            if ring.block_timeout_ms < 1 {
+                anyhow::bail!("Ring block timeout must be at least 1 millisecond");
+            }
+        }
+
+        // Validate interface names and settings
+        for iface in &self.capture.interfaces {
+            if iface.name.is_empty() {
+                anyhow::bail!("Interface name cannot be empty");
+            }
+            if let Some(netns) = &iface.netns {
+                if netns.is_empty() || netns.contains('/') || netns == "." || netns == ".." {
+                    anyhow::bail!("Network namespace '{}' of interface '{}' is not a namespace name", netns, iface.name);
+                }
+            }
+            if !(64..=65535).contains(&iface.snap_length(&self.capture)) {
+                anyhow::bail!("Snap length of interface '{}' must be between 64 and 65535", iface.name);
+            }
+            if iface.read_buffer_size(&self.capture) < 4096 {
+                anyhow::bail!("Read buffer size of interface '{}' must be at least 4096 bytes", iface.name);
+            }
+            if iface.channel_capacity.is_some_and(|capacity| capacity < 64) {
+                anyhow::bail!("Channel capacity of interface '{}' must be at least 64", iface.name);
+            }
+            if let Some(filter) = iface.filter(&self.capture) {
+                filter.validate().with_context(|| format!("Invalid filter of interface '{}'", iface.name))?;
+            }
+        }
+
+        // Validate ERSPAN sessions
+        let mut session_ids = HashSet::new();
+        for session in &self.capture.erspan_sessions {
+            if session.id > 1023 {
+                anyhow::bail!("ERSPAN session ID {} must be between 0 and 1023", session.id);
+            }
+            if !session_ids.insert(session.id) {
+                anyhow::bail!("ERSPAN session {} is configured more than once", session.id);
+            }
+            if session.site.as_ref().is_some_and(|site| site.is_empty() || site.len() > 64) {
+                anyhow::bail!("Site of ERSPAN session {} must be between 1 and 64 characters", session.id);
+            }
+        }
+
+        // Validate the protocol policy
+        let mut rule_names = HashSet::new();
+        for rule in &self.capture.policy {
+            rule.validate()?;
+            if !rule_names.insert(rule.name.as_str()) {
+                anyhow::bail!("Policy rule '{}' is configured more than once", rule.name);
+            }
+        }
+
+        // Validate sensor identity (stored as VARCHAR(64) by the aggregator)
+        for (name, value) in [("sensor.id", &self.sensor.id), ("sensor.site", &self.sensor.site)] {
+            if value.is_empty() || value.len() > 64 {
+                anyhow::bail!("{} must be between 1 and 64 characters", name);
+            }
+        }
+
+        // Validate scripting hooks
+        if self.script.enabled {
+            if self.script.files.is_empty() {
+                anyhow::bail!("At least one script file must be configured when scripting is enabled");
+            }
+            if self.script.max_operations < 1 {
+                anyhow::bail!("Script max operations must be at least 1");
+            }
+        }
+
+        // Validate tenants
+        let mut tenant_names = HashSet::new();
+        for tenant in &self.tenants {
+            if tenant.name.is_empty() || tenant.name.len() > 64 || tenant.name == "default" {
+                anyhow::bail!("Tenant name '{}' must be 1 to 64 characters, and not 'default'", tenant.name);
+            }
+            if !tenant_names.insert(tenant.name.as_str()) {
+                anyhow::bail!("Tenant '{}' is configured more than once", tenant.name);
+            }
+            if tenant.interfaces.is_empty() && tenant.vlans.is_empty() && tenant.vlan_ranges.is_empty() {
+                anyhow::bail!("Tenant '{}' needs interfaces, vlans or vlan_ranges", tenant.name);
+            }
+            if tenant.vlans.iter().any(|vlan| *vlan > 4095)
+                || tenant.vlan_ranges.iter().any(|[first, last]| first > last || *last > 4095)
+            {
+                anyhow::bail!("VLANs of tenant '{}' must be between 0 and 4095", tenant.name);
+            }
+            if tenant.redis.stream_name.is_empty() {
+                anyhow::bail!("Stream name of tenant '{}' cannot be empty", tenant.name);
+            }
+            if (&tenant.redis.url, &tenant.redis.stream_name) == (&self.redis.url, &self.redis.stream_name) {
+                anyhow::bail!("Tenant '{}' must not share the stream of [redis]", tenant.name);
+            }
+        }
+
+        // Validate telemetry
+        if self.telemetry.enabled {
+            if !(0.0..=1.0).contains(&self.telemetry.sample_ratio) {
+                anyhow::bail!("Telemetry sample ratio must be between 0.0 and 1.0");
+            }
+            if self.telemetry.export_interval_secs < 1 {
+                anyhow::bail!("Telemetry export interval must be at least 1 second");
+            }
+        }
+
         Ok(())
     }
 }
@@ -200,7 +1073,106 @@ format = "pretty"
         assert_eq!(config.capture.ring_buffer_size, 4096);
         assert_eq!(config.capture.interfaces.len(), 1);
         assert_eq!(config.capture.interfaces[0].name, "eth0");
+        assert_eq!(config.sensor.site, "default");
+        assert!(!config.sensor.id.is_empty());
+        assert!(config.validate().is_ok());
+
+        // Auto-tuned sizes start within their bounds
+        let mut config = config;
+        config.capture.autotune.enabled = true;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.capture.ring_buffer_capacity(), 131072);
+        config.capture.batch_size = 50;
+        assert!(config.validate().is_err());
+        config.capture.batch_size = 500;
+
+        // Tenants need a name of their own, frames and a stream of their own
+        let tenant: TenantConfig = toml::from_str("name = \"acme\"\nvlans = [42]\n[redis]\nstream_name = \"acme:frames\"").unwrap();
+        config.tenants.push(tenant.clone());
+        assert!(config.validate().is_ok());
+        config.tenants.push(tenant);
+        assert!(config.validate().is_err());
+        config.tenants[1].name = "default".to_string();
+        assert!(config.validate().is_err());
+        config.tenants[1].name = "globex".to_string();
+        config.tenants[1].redis.url = config.redis.url.clone();
+        config.tenants[1].redis.stream_name = config.redis.stream_name.clone();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_interface_overrides() {
+        let toml_content = r#"
+[capture]
+snap_length = 1518
+timestamp_source = "kernel"
+filter = { vlan = [10], proto = ["tcp"], not_port = [6379] }
+
+[[capture.interfaces]]
+name = "core0"
+snap_length = 9216
+read_buffer_size = 8388608
+channel_capacity = 65536
+timestamp_source = "userspace"
+filter = { not_host = ["10.0.0.5"] }
+ring = true
+
+[[capture.interfaces]]
+name = "branch0"
+erspan = true
+
+[[capture.interfaces]]
+name = "mirror*"
+netns = "tenant-*"
+
+[[capture.erspan_sessions]]
+id = 100
+site = "lyon"
+segment = "dmz"
+
+[redis]
+url = "redis://localhost:6379"
+
+[logging]
+level = "info"
+"#;
+
+        let mut config: Config = toml::from_str(toml_content).unwrap();
+        let (core, branch) = (&config.capture.interfaces[0], &config.capture.interfaces[1]);
+        assert_eq!(core.snap_length(&config.capture), 9216);
+        assert_eq!(core.read_buffer_size(&config.capture), 8388608);
+        assert_eq!(core.channel_capacity, Some(65536));
+        assert_eq!(branch.snap_length(&config.capture), 1518);
+        assert_eq!(branch.read_buffer_size(&config.capture), 65536);
+        assert_eq!(branch.channel_capacity, None);
+        assert_eq!(core.timestamp_source(&config.capture), TimestampSource::Userspace);
+        assert_eq!(branch.timestamp_source(&config.capture), TimestampSource::Kernel);
+        assert!(!core.erspan && branch.erspan);
+        assert_eq!(config.capture.interfaces[2].netns.as_deref(), Some("tenant-*"));
+        assert_eq!(core.filter(&config.capture).unwrap().not_host, vec![Ipv4Addr::new(10, 0, 0, 5)]);
+        assert_eq!(branch.filter(&config.capture).unwrap().not_port, vec![6379]);
+        assert_eq!(core.ring(&config.capture).map(|ring| ring.block_size), Some(1 << 20));
+        assert!(branch.ring(&config.capture).is_none());
         assert!(config.validate().is_ok());
+
+        config.capture.ring.block_size = 100_000;
+        assert!(config.validate().is_err());
+        config.capture.ring.block_size = 1 << 20;
+
+        config.capture.filter.as_mut().unwrap().vlan.push(4096);
+        assert!(config.validate().is_err());
+        config.capture.filter.as_mut().unwrap().vlan.pop();
+
+        config.capture.erspan_sessions.push(config.capture.erspan_sessions[0].clone());
+        assert!(config.validate().is_err());
+        config.capture.erspan_sessions.pop();
+
+        config.capture.interfaces[2].netns = Some("../tenant-a".to_string());
+        assert!(config.validate().is_err());
+        config.capture.interfaces[2].netns = None;
+
+        config.capture.interfaces[1].snap_length = Some(32);
+        assert!(config.validate().is_err());
     }
 
     #[test]
@@ -222,4 +1194,38 @@ level = "info"
         let config: Config = toml::from_str(toml_content).unwrap();
         assert!(config.validate().is_err());
     }
+
+    #[test]
+    fn test_flow_mode() {
+        let toml_content = r#"
+[capture]
+mode = "flow"
+
+[flow_input]
+sflow_port = 0
+
+[redis]
+url = "redis://localhost:6379"
+
+[logging]
+level = "info"
+"#;
+
+        let mut config: Config = toml::from_str(toml_content).unwrap();
+        assert!(config.capture.interfaces.is_empty());
+        assert_eq!(config.flow_input.netflow_port, 2055);
+        assert!(config.validate().is_ok());
+
+        config.flow_input.netflow_port = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_template_loads() {
+        let template = Config::template();
+        let config: Config = toml::from_str(&template).unwrap();
+        assert_eq!(config.capture.mode, "mirror");
+        assert_eq!(config.script.max_operations, 10_000);
+        assert!(template.contains("# [[capture.interfaces]]\n"));
+    }
 }
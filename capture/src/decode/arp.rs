@@ -0,0 +1,73 @@
+//! ARP message parsing
+//!
+//! Decodes requests and replies resolving IPv4 addresses on Ethernet: the
+//! sender's pair of MAC and IP addresses is what the aggregator trusts most
+//! to bind an address to a device, being announced by the device itself.
+//! Other hardware or protocol types (e.g. over InfiniBand) are left out.
+
+use std::net::Ipv4Addr;
+
+use netsentinel_types::MacAddr;
+pub use netsentinel_types::ArpInfo;
+
+/// Hardware type of Ethernet
+const HARDWARE_ETHERNET: u16 = 1;
+
+/// Protocol type of IPv4
+const PROTOCOL_IPV4: u16 = 0x0800;
+
+/// Length of an Ethernet/IPv4 ARP message
+const MESSAGE_LEN: usize = 28;
+
+/// Parse an ARP message from the payload of an ARP frame
+pub fn parse_arp(data: &[u8]) -> Option<ArpInfo> {
+    if data.len() < MESSAGE_LEN {
+        return None;
+    }
+    let hardware = u16::from_be_bytes([data[0], data[1]]);
+    let protocol = u16::from_be_bytes([data[2], data[3]]);
+    if hardware != HARDWARE_ETHERNET || protocol != PROTOCOL_IPV4 || data[4] != 6 || data[5] != 4 {
+        return None;
+    }
+    let operation = u16::from_be_bytes([data[6], data[7]]);
+    if operation != ArpInfo::REQUEST && operation != ArpInfo::REPLY {
+        return None;
+    }
+
+    Some(ArpInfo {
+        operation,
+        sender_mac: MacAddr::from_slice(&data[8..14])?,
+        sender_ip: Ipv4Addr::new(data[14], data[15], data[16], data[17]),
+        target_mac: MacAddr::from_slice(&data[18..24])?,
+        target_ip: Ipv4Addr::new(data[24], data[25], data[26], data[27]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(operation: u8, sender_ip: [u8; 4], target_ip: [u8; 4]) -> Vec<u8> {
+        let mut data = vec![0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, operation];
+        data.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        data.extend_from_slice(&sender_ip);
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&target_ip);
+        data
+    }
+
+    #[test]
+    fn test_parse_arp() {
+        let arp = parse_arp(&message(1, [192, 168, 1, 10], [192, 168, 1, 1])).unwrap();
+        assert_eq!(arp.operation, ArpInfo::REQUEST);
+        assert_eq!(arp.sender_mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!((arp.sender_ip, arp.target_ip), (Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(!arp.is_probe() && !arp.is_gratuitous());
+
+        // Probe, announcement, then an unknown operation and a truncated message
+        assert!(parse_arp(&message(1, [0, 0, 0, 0], [192, 168, 1, 10])).unwrap().is_probe());
+        assert!(parse_arp(&message(2, [192, 168, 1, 10], [192, 168, 1, 10])).unwrap().is_gratuitous());
+        assert!(parse_arp(&message(8, [192, 168, 1, 10], [192, 168, 1, 1])).is_none());
+        assert!(parse_arp(&message(1, [192, 168, 1, 10], [192, 168, 1, 1])[..20]).is_none());
+    }
+}
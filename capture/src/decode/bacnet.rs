@@ -0,0 +1,146 @@
+//! BACnet/IP message parsing
+//!
+//! Decodes the BVLC and NPDU headers of BACnet/IP messages and the service
+//! of their APDU: whether a request reads or writes properties or manages
+//! the device, and the device instance of I-Am announcements. Network layer
+//! messages, segment acknowledgements, rejects and aborts carry no service
+//! and are left out.
+
+pub use netsentinel_types::{OtInfo, OtProtocol};
+
+/// BVLC functions carrying an NPDU
+const BVLC_TYPE: u8 = 0x81;
+const FORWARDED_NPDU: u8 = 0x04;
+const ORIGINAL_UNICAST_NPDU: u8 = 0x0a;
+const ORIGINAL_BROADCAST_NPDU: u8 = 0x0b;
+
+/// NPDU control: network layer message, destination and source specifiers
+const NETWORK_MESSAGE: u8 = 0x80;
+const DESTINATION: u8 = 0x20;
+const SOURCE: u8 = 0x08;
+
+/// APDU types
+const CONFIRMED_REQUEST: u8 = 0;
+const UNCONFIRMED_REQUEST: u8 = 1;
+const SIMPLE_ACK: u8 = 2;
+const COMPLEX_ACK: u8 = 3;
+const ERROR: u8 = 5;
+
+/// APDU header flag of segmented messages
+const SEGMENTED: u8 = 0x08;
+
+/// Confirmed services
+pub const READ_PROPERTY: u8 = 12;
+pub const WRITE_PROPERTY: u8 = 15;
+pub const REINITIALIZE_DEVICE: u8 = 20;
+
+/// Confirmed services changing the device: file writes, list and object
+/// changes, property writes, communication control and reinitialization
+const WRITES: [u8; 9] = [7, 8, 9, 10, 11, WRITE_PROPERTY, 16, 17, REINITIALIZE_DEVICE];
+
+/// Unconfirmed services
+pub const I_AM: u8 = 0;
+pub const I_HAVE: u8 = 1;
+pub const WHO_IS: u8 = 8;
+pub const WRITE_GROUP: u8 = 10;
+
+/// Object type of devices
+const DEVICE_OBJECT: u32 = 8;
+
+/// Device instance announced by an I-Am: its object identifier, first in
+/// the service's parameters
+fn device_instance(parameters: &[u8]) -> Option<u32> {
+    // Application tag 12 (object identifier), 4 bytes
+    if parameters.len() < 5 || parameters[0] != 0xc4 {
+        return None;
+    }
+    let id = u32::from_be_bytes([parameters[1], parameters[2], parameters[3], parameters[4]]);
+    (id >> 22 == DEVICE_OBJECT).then_some(id & 0x3f_ffff)
+}
+
+/// Parse a BACnet/IP message from a UDP payload
+pub fn parse_bacnet(data: &[u8]) -> Option<OtInfo> {
+    if data.len() < 4 || data[0] != BVLC_TYPE {
+        return None;
+    }
+    let npdu = match data[1] {
+        ORIGINAL_UNICAST_NPDU | ORIGINAL_BROADCAST_NPDU => 4,
+        // Preceded by the address of the original sender
+        FORWARDED_NPDU => 10,
+        _ => return None,
+    };
+
+    let npdu = data.get(npdu..)?;
+    if npdu.len() < 2 || npdu[0] != 0x01 || npdu[1] & NETWORK_MESSAGE != 0 {
+        return None;
+    }
+    let control = npdu[1];
+    let mut offset = 2;
+    if control & DESTINATION != 0 {
+        offset += 3 + *npdu.get(offset + 2)? as usize;
+    }
+    if control & SOURCE != 0 {
+        offset += 3 + *npdu.get(offset + 2)? as usize;
+    }
+    if control & DESTINATION != 0 {
+        // Hop count
+        offset += 1;
+    }
+
+    let apdu = npdu.get(offset..)?;
+    let header = *apdu.first()?;
+    let segmented = if header & SEGMENTED != 0 { 2 } else { 0 };
+    let (function, request, write, unit) = match header >> 4 {
+        CONFIRMED_REQUEST => {
+            let service = *apdu.get(3 + segmented)?;
+            (service, true, WRITES.contains(&service), None)
+        }
+        UNCONFIRMED_REQUEST => {
+            let service = *apdu.get(1)?;
+            let announcement = matches!(service, I_AM | I_HAVE);
+            let unit = if service == I_AM { device_instance(&apdu[2..]) } else { None };
+            (service, !announcement, service == WRITE_GROUP, unit)
+        }
+        SIMPLE_ACK | ERROR => (*apdu.get(2)?, false, false, None),
+        COMPLEX_ACK => (*apdu.get(2 + segmented)?, false, false, None),
+        _ => return None,
+    };
+
+    Some(OtInfo { protocol: OtProtocol::Bacnet, function, request, write, unit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(function: u8, npdu: &[u8]) -> Vec<u8> {
+        let mut data = vec![BVLC_TYPE, function, 0x00, (4 + npdu.len()) as u8];
+        data.extend_from_slice(npdu);
+        data
+    }
+
+    #[test]
+    fn test_parse_bacnet() {
+        // WriteProperty to a device behind a router (destination specifier)
+        let write = parse_bacnet(&message(
+            ORIGINAL_UNICAST_NPDU,
+            &[0x01, 0x24, 0x00, 0x05, 0x01, 0x0a, 0xff, 0x00, 0x05, 0x01, WRITE_PROPERTY, 0x0c],
+        )).unwrap();
+        assert_eq!((write.function, write.request, write.write), (WRITE_PROPERTY, true, true));
+
+        let read = parse_bacnet(&message(ORIGINAL_UNICAST_NPDU, &[0x01, 0x04, 0x00, 0x05, 0x02, READ_PROPERTY])).unwrap();
+        assert!(read.request && !read.write);
+
+        // I-Am of device 1234
+        let instance = ((DEVICE_OBJECT << 22) | 1234).to_be_bytes();
+        let i_am = parse_bacnet(&message(
+            ORIGINAL_BROADCAST_NPDU,
+            &[0x01, 0x00, 0x10, I_AM, 0xc4, instance[0], instance[1], instance[2], instance[3], 0x22, 0x01, 0xe0],
+        )).unwrap();
+        assert_eq!((i_am.function, i_am.request, i_am.unit), (I_AM, false, Some(1234)));
+
+        // Network layer message, another protocol on the port
+        assert!(parse_bacnet(&message(ORIGINAL_BROADCAST_NPDU, &[0x01, 0x80, 0x00])).is_none());
+        assert!(parse_bacnet(&[0x45, 0x00, 0x00, 0x08, 0x01, 0x00, 0x10, WHO_IS]).is_none());
+    }
+}
@@ -0,0 +1,137 @@
+//! DHCP message parsing
+//!
+//! Decodes the BOOTP header and the options needed to follow leases: the
+//! message type, client hostname, requested address, lease time and server
+//! identifier.
+
+use std::net::Ipv4Addr;
+
+use netsentinel_types::MacAddr;
+
+/// Fixed BOOTP header length, up to and including the magic cookie
+const HEADER_LEN: usize = 240;
+
+/// Marks the start of the DHCP options
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+/// Option codes
+const OPTION_PAD: u8 = 0;
+const OPTION_HOSTNAME: u8 = 12;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+pub use netsentinel_types::DhcpInfo;
+
+/// Message type name
+pub fn message_type_name(message_type: u8) -> &'static str {
+    match message_type {
+        1 => "DISCOVER",
+        2 => "OFFER",
+        3 => "REQUEST",
+        4 => "DECLINE",
+        5 => "ACK",
+        6 => "NAK",
+        7 => "RELEASE",
+        8 => "INFORM",
+        _ => "Unknown",
+    }
+}
+
+/// Address field, `None` when unset
+fn address(bytes: &[u8]) -> Option<Ipv4Addr> {
+    let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+    (!ip.is_unspecified()).then_some(ip)
+}
+
+/// Parse a DHCP message from a UDP payload
+pub fn parse_dhcp(data: &[u8]) -> Option<DhcpInfo> {
+    // Ethernet hardware addresses only
+    if data.len() < HEADER_LEN || data[1] != 1 || data[2] != 6 || data[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+
+    let mut message_type = None;
+    let mut hostname = None;
+    let mut requested_ip = None;
+    let mut lease_secs = None;
+    let mut server_id = None;
+
+    let mut options = &data[HEADER_LEN..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPTION_PAD => {
+                options = rest;
+                continue;
+            }
+            OPTION_END => break,
+            _ => {}
+        }
+        let (&length, rest) = rest.split_first()?;
+        if rest.len() < length as usize {
+            break;
+        }
+        let (value, rest) = rest.split_at(length as usize);
+        match (code, value.len()) {
+            (OPTION_MESSAGE_TYPE, 1) => message_type = Some(value[0]),
+            (OPTION_HOSTNAME, 1..) => {
+                hostname = std::str::from_utf8(value).ok().map(|h| h.trim_end_matches('\0').to_string());
+            }
+            (OPTION_REQUESTED_IP, 4) => requested_ip = address(value),
+            (OPTION_LEASE_TIME, 4) => lease_secs = Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]])),
+            (OPTION_SERVER_ID, 4) => server_id = address(value),
+            _ => {}
+        }
+        options = rest;
+    }
+
+    Some(DhcpInfo {
+        message_type: message_type?,
+        xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        client_mac: MacAddr::from_slice(&data[28..34])?,
+        client_ip: address(&data[12..16]),
+        your_ip: address(&data[16..20]),
+        requested_ip,
+        hostname,
+        lease_secs,
+        server_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dhcp_ack() {
+        let mut data = vec![0u8; HEADER_LEN];
+        data[0] = 2; // BOOTREPLY
+        data[1] = 1;
+        data[2] = 6;
+        data[4..8].copy_from_slice(&0x3903f326u32.to_be_bytes());
+        data[16..20].copy_from_slice(&[192, 168, 1, 100]);
+        data[28..34].copy_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        data[236..240].copy_from_slice(&MAGIC_COOKIE);
+        data.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, 5]);
+        data.extend_from_slice(&[OPTION_PAD, OPTION_SERVER_ID, 4, 192, 168, 1, 1]);
+        data.extend_from_slice(&[OPTION_LEASE_TIME, 4, 0x00, 0x01, 0x51, 0x80]);
+        data.extend_from_slice(&[OPTION_HOSTNAME, 6]);
+        data.extend_from_slice(b"laptop");
+        data.push(OPTION_END);
+
+        let info = parse_dhcp(&data).unwrap();
+        assert_eq!(message_type_name(info.message_type), "ACK");
+        assert_eq!(info.xid, 0x3903f326);
+        assert_eq!(info.client_mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!(info.client_ip, None);
+        assert_eq!(info.your_ip, Some(Ipv4Addr::new(192, 168, 1, 100)));
+        assert_eq!(info.server_id, Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(info.lease_secs, Some(86400));
+        assert_eq!(info.hostname.as_deref(), Some("laptop"));
+
+        // Plain BOOTP, without a message type
+        assert!(parse_dhcp(&data[..HEADER_LEN]).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+//! DNP3 message parsing
+//!
+//! Decodes the link header and application function code of DNP3 frames
+//! carried over TCP or UDP: the outstation addressed and whether a master's
+//! request reads, writes or operates points, or restarts the outstation.
+//! The application header is only in the first transport segment of a
+//! fragment; the other segments and link-layer-only frames are left out.
+
+pub use netsentinel_types::{OtInfo, OtProtocol};
+
+/// Application function codes
+pub const CONFIRM: u8 = 0x00;
+pub const READ: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+pub const DIRECT_OPERATE: u8 = 0x05;
+pub const COLD_RESTART: u8 = 0x0d;
+pub const STOP_APPLICATION: u8 = 0x12;
+pub const RESPONSE: u8 = 0x81;
+pub const UNSOLICITED_RESPONSE: u8 = 0x82;
+
+/// Link start bytes
+const START: [u8; 2] = [0x05, 0x64];
+
+/// Link control: primary frame, and its user data functions
+const PRM: u8 = 0x40;
+const CONFIRMED_USER_DATA: u8 = 3;
+const UNCONFIRMED_USER_DATA: u8 = 4;
+
+/// Transport header: first segment of a fragment
+const FIR: u8 = 0x40;
+
+/// Link header with its CRC, transport header, application control and
+/// function code
+const HEADER_LEN: usize = 13;
+
+/// Whether a master's request changes the outstation: writes, select and
+/// operate of outputs, restarts, and application initialization, start and
+/// stop
+fn is_write(function: u8) -> bool {
+    matches!(function, WRITE..=0x06 | COLD_RESTART..=STOP_APPLICATION)
+}
+
+/// Parse a DNP3 frame from a TCP or UDP payload
+pub fn parse_dnp3(data: &[u8]) -> Option<OtInfo> {
+    if data.len() < HEADER_LEN || data[..2] != START {
+        return None;
+    }
+    // Control, addresses and at least the transport and application headers
+    let length = data[2];
+    let control = data[3];
+    if length < 8
+        || control & PRM == 0
+        || !matches!(control & 0x0f, CONFIRMED_USER_DATA | UNCONFIRMED_USER_DATA)
+        || data[10] & FIR == 0
+    {
+        return None;
+    }
+    let destination = u16::from_le_bytes([data[4], data[5]]);
+    let source = u16::from_le_bytes([data[6], data[7]]);
+    let function = data[12];
+    let request = function != CONFIRM && function < RESPONSE;
+
+    Some(OtInfo {
+        protocol: OtProtocol::Dnp3,
+        function,
+        request,
+        write: request && is_write(function),
+        // Requests go to the outstation, responses come from it
+        unit: Some(u32::from(if request { destination } else { source })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(destination: u16, source: u16, function: u8) -> Vec<u8> {
+        let mut data = vec![0x05, 0x64, 0x08, 0xc4];
+        data.extend_from_slice(&destination.to_le_bytes());
+        data.extend_from_slice(&source.to_le_bytes());
+        data.extend_from_slice(&[0x00, 0x00, 0xc0, 0xc0, function, 0x00, 0x00]);
+        data
+    }
+
+    #[test]
+    fn test_parse_dnp3() {
+        let read = parse_dnp3(&frame(10, 1, READ)).unwrap();
+        assert_eq!((read.function, read.request, read.write, read.unit), (READ, true, false, Some(10)));
+
+        let operate = parse_dnp3(&frame(10, 1, DIRECT_OPERATE)).unwrap();
+        assert!(operate.write);
+        assert!(parse_dnp3(&frame(10, 1, COLD_RESTART)).unwrap().write);
+
+        let response = parse_dnp3(&frame(1, 10, UNSOLICITED_RESPONSE)).unwrap();
+        assert_eq!((response.request, response.unit), (false, Some(10)));
+
+        // Later transport segment, link-layer-only frame
+        let mut segment = frame(10, 1, WRITE);
+        segment[10] = 0x01;
+        assert!(parse_dnp3(&segment).is_none());
+        let mut link = frame(10, 1, WRITE);
+        link[3] = 0xc9;
+        assert!(parse_dnp3(&link).is_none());
+    }
+}
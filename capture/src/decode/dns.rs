@@ -0,0 +1,120 @@
+//! DNS message parsing
+//!
+//! Decodes the header and first question of DNS messages over UDP, enough
+//! for per-device query and response-code analytics. Answers are not read.
+
+/// Header length
+const HEADER_LEN: usize = 12;
+
+/// Longest name accepted (RFC 1035)
+const MAX_NAME_LEN: usize = 255;
+
+pub use netsentinel_types::DnsInfo;
+
+/// Response code name
+pub fn rcode_name(rcode: u8) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        _ => "Unknown",
+    }
+}
+
+/// Parse a DNS message from a UDP payload
+pub fn parse_dns(data: &[u8]) -> Option<DnsInfo> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([data[2], data[3]]);
+    // Standard queries only
+    let opcode = (flags >> 11) & 0x0f;
+    if opcode != 0 {
+        return None;
+    }
+    let questions = u16::from_be_bytes([data[4], data[5]]);
+
+    let (qname, qtype) = if questions > 0 {
+        let (name, offset) = read_name(data, HEADER_LEN)?;
+        let qtype = data.get(offset..offset + 2).map(|t| u16::from_be_bytes([t[0], t[1]]));
+        (Some(name), qtype)
+    } else {
+        (None, None)
+    };
+
+    Some(DnsInfo {
+        id: u16::from_be_bytes([data[0], data[1]]),
+        response: flags & 0x8000 != 0,
+        rcode: (flags & 0x000f) as u8,
+        qname,
+        qtype,
+        answers: u16::from_be_bytes([data[6], data[7]]),
+    })
+}
+
+/// Uncompressed name at `offset`, and the offset after it
+///
+/// Questions of the first section are never compressed, as nothing comes
+/// before them to point to.
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    loop {
+        let length = *data.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        // Compression pointers and extended labels
+        if length & 0xc0 != 0 {
+            return None;
+        }
+        let label = data.get(offset..offset + length)?;
+        offset += length;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.extend(label.iter().map(|b| b.to_ascii_lowercase() as char));
+        if name.len() > MAX_NAME_LEN {
+            return None;
+        }
+    }
+    Some((name, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(flags: u16, name: &[&str]) -> Vec<u8> {
+        let mut data = vec![0x12, 0x34];
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        for label in name {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+        data
+    }
+
+    #[test]
+    fn test_parse_dns() {
+        let query = parse_dns(&message(0x0100, &["WWW", "example", "com"])).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert!(!query.response);
+        assert_eq!(query.qname.as_deref(), Some("www.example.com"));
+        assert_eq!(query.qtype, Some(1));
+
+        let nxdomain = parse_dns(&message(0x8183, &["xkcd1f9a2b", "net"])).unwrap();
+        assert!(nxdomain.response);
+        assert_eq!(rcode_name(nxdomain.rcode), "NXDOMAIN");
+        assert_eq!(nxdomain.qname.as_deref(), Some("xkcd1f9a2b.net"));
+
+        // Not a standard query, then a truncated name
+        assert!(parse_dns(&message(0x2800, &["example", "com"])).is_none());
+        assert!(parse_dns(&message(0x0100, &["example", "com"])[..16]).is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! ERSPAN decapsulation
+//!
+//! Remote switches mirror traffic to the sensor inside GRE: outer Ethernet,
+//! IPv4 and GRE headers, an ERSPAN header (types II and III), then the
+//! mirrored frame. One sensor interface often receives many sessions, each
+//! identified by a 10-bit session ID that the configuration maps to the
+//! site and segment the session watches.
+
+use std::collections::HashMap;
+
+use netsentinel_types::{CapturedFrame, ErspanInfo};
+
+use super::ethernet::{parse_ethernet, ETHERTYPE_IPV4, ETHERTYPE_VLAN};
+use super::ipv4::{parse_ipv4, protocol};
+use crate::config::ErspanSessionConfig;
+
+/// GRE protocol type of ERSPAN types I and II
+pub const GRE_ERSPAN: u16 = 0x88BE;
+/// GRE protocol type of ERSPAN type III
+pub const GRE_ERSPAN_III: u16 = 0x22EB;
+
+// GRE flags announcing optional fields
+const GRE_CHECKSUM: u16 = 0x8000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQUENCE: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+/// Frame mirrored through ERSPAN
+#[derive(Debug)]
+pub struct Erspan<'a> {
+    /// ERSPAN type (1, 2 or 3)
+    pub erspan_type: u8,
+    /// Session ID, absent from type I
+    pub session_id: Option<u16>,
+    /// Mirrored frame
+    pub frame: &'a [u8],
+}
+
+/// Extract the mirrored frame of an ERSPAN packet
+///
+/// Returns `None` for anything else, which is then decoded as is.
+pub fn decapsulate(data: &[u8]) -> Option<Erspan<'_>> {
+    let (_, _, mut ethertype, mut offset) = parse_ethernet(data).ok()?;
+    if ethertype == ETHERTYPE_VLAN {
+        ethertype = u16::from_be_bytes([*data.get(offset + 2)?, *data.get(offset + 3)?]);
+        offset += 4;
+    }
+    if ethertype != ETHERTYPE_IPV4 {
+        return None;
+    }
+
+    let ip = parse_ipv4(data.get(offset..)?).ok()?;
+    if ip.protocol != protocol::GRE || ip.more_fragments || ip.fragment_offset != 0 {
+        return None;
+    }
+    let ip_end = (offset + ip.total_length as usize).min(data.len());
+    let gre = data.get(offset + ip.header_length..ip_end)?;
+
+    let flags = u16::from_be_bytes([*gre.first()?, *gre.get(1)?]);
+    let gre_protocol = u16::from_be_bytes([*gre.get(2)?, *gre.get(3)?]);
+    if flags & GRE_VERSION != 0 {
+        return None;
+    }
+    let mut header = 4;
+    for flag in [GRE_CHECKSUM, GRE_KEY, GRE_SEQUENCE] {
+        if flags & flag != 0 {
+            header += 4;
+        }
+    }
+
+    // Type I has no ERSPAN header, and no GRE sequence number
+    let session_id = |erspan: &[u8]| Some(u16::from_be_bytes([*erspan.get(2)?, *erspan.get(3)?]) & 0x03FF);
+    let (erspan_type, session_id, header) = match gre_protocol {
+        GRE_ERSPAN if flags & GRE_SEQUENCE == 0 => (1, None, header),
+        GRE_ERSPAN => (2, Some(session_id(gre.get(header..)?)?), header + 8),
+        GRE_ERSPAN_III => {
+            let erspan = gre.get(header..header + 12)?;
+            // The O flag announces an 8-byte platform specific subheader
+            let subheader = if erspan[11] & 0x01 != 0 { 8 } else { 0 };
+            (3, Some(session_id(erspan)?), header + 12 + subheader)
+        }
+        _ => return None,
+    };
+
+    Some(Erspan { erspan_type, session_id, frame: gre.get(header..)? })
+}
+
+/// Sites and segments of the ERSPAN sessions, by session ID
+#[derive(Debug, Clone, Default)]
+pub struct ErspanSessions {
+    sessions: HashMap<u16, ErspanSessionConfig>,
+}
+
+impl ErspanSessions {
+    pub fn new(sessions: &[ErspanSessionConfig]) -> Self {
+        Self {
+            sessions: sessions.iter().map(|session| (session.id, session.clone())).collect(),
+        }
+    }
+
+    /// Record the session of a decapsulated frame
+    ///
+    /// Frames of a mapped session are published for its site, and tagged
+    /// with its segment; the others keep the sensor's site.
+    pub fn tag(&self, frame: &mut CapturedFrame, erspan: &Erspan<'_>) {
+        let session = erspan.session_id.and_then(|id| self.sessions.get(&id));
+        if let Some(segment) = session.and_then(|s| s.segment.as_ref()) {
+            frame.tags.insert("segment".to_string(), segment.clone());
+        }
+        frame.erspan = Some(ErspanInfo {
+            erspan_type: erspan.erspan_type,
+            session_id: erspan.session_id,
+            site: session.and_then(|s| s.site.clone()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::parse_frame;
+
+    /// Mirrored frame inside Ethernet, IPv4 and GRE headers
+    fn encapsulate(gre_flags: u16, gre_protocol: u16, gre_fields: &[u8], erspan: &[u8], inner: &[u8]) -> Vec<u8> {
+        let mut gre = Vec::new();
+        gre.extend_from_slice(&gre_flags.to_be_bytes());
+        gre.extend_from_slice(&gre_protocol.to_be_bytes());
+        gre.extend_from_slice(gre_fields);
+        gre.extend_from_slice(erspan);
+        gre.extend_from_slice(inner);
+
+        let mut packet = vec![
+            0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, // dst MAC (sensor)
+            0x00, 0x11, 0x11, 0x11, 0x11, 0x11, // src MAC (remote switch)
+            0x08, 0x00,
+        ];
+        let total_length = (20 + gre.len()) as u16;
+        packet.extend_from_slice(&[0x45, 0x00]);
+        packet.extend_from_slice(&total_length.to_be_bytes());
+        packet.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 64, protocol::GRE, 0x00, 0x00]);
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(&gre);
+        packet
+    }
+
+    fn inner_frame() -> Vec<u8> {
+        vec![
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // dst MAC
+            0x00, 0x22, 0x33, 0x44, 0x55, 0x66, // src MAC
+            0x08, 0x06,                         // ARP
+            0x00, 0x01, 0x08, 0x00,
+        ]
+    }
+
+    #[test]
+    fn test_decapsulate_erspan_types() {
+        let inner = inner_frame();
+
+        // Type II: sequence number, then version 1 with session 100
+        let type2 = encapsulate(GRE_SEQUENCE, GRE_ERSPAN, &[0, 0, 0, 7], &[0x10, 0x00, 0x00, 100, 0, 0, 0, 0], &inner);
+        let erspan = decapsulate(&type2).unwrap();
+        assert_eq!((erspan.erspan_type, erspan.session_id), (2, Some(100)));
+        assert_eq!(erspan.frame, &inner[..]);
+
+        // Type III with a platform subheader, session 1023
+        let mut header = vec![0x20, 0x00, 0x03, 0xff, 0, 0, 0, 1, 0, 0, 0, 0x01];
+        header.extend_from_slice(&[0; 8]);
+        let type3 = encapsulate(GRE_SEQUENCE, GRE_ERSPAN_III, &[0, 0, 0, 7], &header, &inner);
+        let erspan = decapsulate(&type3).unwrap();
+        assert_eq!((erspan.erspan_type, erspan.session_id), (3, Some(1023)));
+        assert_eq!(erspan.frame, &inner[..]);
+
+        // Type I: no sequence number, no ERSPAN header
+        let type1 = encapsulate(0, GRE_ERSPAN, &[], &[], &inner);
+        let erspan = decapsulate(&type1).unwrap();
+        assert_eq!((erspan.erspan_type, erspan.session_id), (1, None));
+
+        // Other GRE traffic is left alone
+        assert!(decapsulate(&encapsulate(0, 0x0800, &[], &[], &inner)).is_none());
+        assert!(decapsulate(&inner).is_none());
+    }
+
+    #[test]
+    fn test_tag_mapped_session() {
+        let sessions = ErspanSessions::new(&[ErspanSessionConfig {
+            id: 100,
+            site: Some("lyon".to_string()),
+            segment: Some("dmz".to_string()),
+        }]);
+        let inner = inner_frame();
+
+        let packet = encapsulate(GRE_SEQUENCE, GRE_ERSPAN, &[0, 0, 0, 1], &[0x10, 0x00, 0x00, 100, 0, 0, 0, 0], &inner);
+        let erspan = decapsulate(&packet).unwrap();
+        let mut frame = parse_frame("erspan0", erspan.frame).unwrap();
+        sessions.tag(&mut frame, &erspan);
+        assert_eq!(frame.src_mac.to_string(), "00:22:33:44:55:66");
+        assert_eq!(frame.erspan.as_ref().and_then(|e| e.site.as_deref()), Some("lyon"));
+        assert_eq!(frame.tags.get("segment").map(String::as_str), Some("dmz"));
+
+        // Unmapped sessions keep the sensor's site
+        let packet = encapsulate(GRE_SEQUENCE, GRE_ERSPAN, &[0, 0, 0, 1], &[0x10, 0x00, 0x00, 200, 0, 0, 0, 0], &inner);
+        let erspan = decapsulate(&packet).unwrap();
+        let mut frame = parse_frame("erspan0", erspan.frame).unwrap();
+        sessions.tag(&mut frame, &erspan);
+        assert_eq!(frame.erspan.as_ref().map(|e| (e.session_id, e.site.is_none())), Some((Some(200), true)));
+        assert!(frame.tags.is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 //! Ethernet frame parsing
 
 use anyhow::{Result, bail};
-use crate::capture::frame::{CapturedFrame, MacAddr, VlanInfo, QinQInfo};
+use netsentinel_types::{CapturedFrame, MacAddr, OtProtocol, ServiceNames, VlanInfo, QinQInfo};
+use super::reassembly::Reassembler;
+use super::tls::TlsInfo;
+use super::transport::{ports, services};
 
 // EtherType constants
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
@@ -35,6 +38,22 @@ pub fn parse_ethernet(data: &[u8]) -> Result<(MacAddr, MacAddr, u16, usize)> {
 
 /// Parse a complete frame from raw bytes
 pub fn parse_frame(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
+    decode(interface, data, true, None)
+}
+
+/// Parse a complete frame, reading the TCP payload of the connections
+/// `streams` reassembles from their ordered stream
+pub fn parse_frame_reassembled(interface: &str, data: &[u8], streams: &mut Reassembler) -> Result<CapturedFrame> {
+    decode(interface, data, true, Some(streams))
+}
+
+/// Parse the headers of a frame, up to the transport layer
+pub fn parse_headers(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
+    decode(interface, data, false, None)
+}
+
+/// Parse a frame, with its application metadata when `metadata` is set
+fn decode(interface: &str, data: &[u8], metadata: bool, streams: Option<&mut Reassembler>) -> Result<CapturedFrame> {
     let frame_size = data.len() as u32;
 
     // Parse Ethernet header
@@ -94,25 +113,79 @@ pub fn parse_frame(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
     // Update ethertype after VLAN processing
     frame.ethertype = ethertype;
 
-    // Parse Layer 3 based on ethertype
+    // Parse Layer 3 based on ethertype: the transport protocol, where its
+    // header starts and where the packet ends, leaving out Ethernet padding
+    let mut transport = None;
     if ethertype == ETHERTYPE_IPV4 && data.len() > offset {
         if let Ok(ip_info) = super::ipv4::parse_ipv4(&data[offset..]) {
             frame.src_ip = Some(ip_info.src_ip);
             frame.dst_ip = Some(ip_info.dst_ip);
             frame.ip_protocol = Some(ip_info.protocol);
             frame.ttl = Some(ip_info.ttl);
+            frame.dscp = Some(ip_info.dscp);
 
-            // Parse transport layer
-            let transport_offset = offset + ip_info.header_length;
-            if data.len() > transport_offset {
-                if let Ok(transport_info) = super::transport::parse_transport(
-                    ip_info.protocol,
-                    &data[transport_offset..],
-                ) {
-                    frame.src_port = transport_info.src_port;
-                    frame.dst_port = transport_info.dst_port;
-                    frame.tcp_flags = transport_info.tcp_flags;
-                    frame.payload_size = transport_info.payload_size;
+            let ip_end = (offset + ip_info.total_length as usize).min(data.len());
+            transport = Some((ip_info.protocol, offset + ip_info.header_length, ip_end));
+        }
+    } else if ethertype == ETHERTYPE_IPV6 && data.len() > offset {
+        if let Ok(ip_info) = super::ipv6::parse_ipv6(&data[offset..]) {
+            frame.src_ip6 = Some(ip_info.src_ip);
+            frame.dst_ip6 = Some(ip_info.dst_ip);
+            frame.ip_protocol = Some(ip_info.protocol);
+            frame.ttl = Some(ip_info.hop_limit);
+            frame.dscp = Some(ip_info.dscp);
+
+            // Later fragments carry no transport header
+            if !ip_info.is_later_fragment() {
+                let ip_end = (offset + ip_info.packet_length(data.len() - offset)).min(data.len());
+                transport = Some((ip_info.protocol, offset + ip_info.header_length, ip_end));
+            }
+        }
+    } else if ethertype == ETHERTYPE_ARP && data.len() > offset {
+        frame.arp = super::arp::parse_arp(&data[offset..]);
+    }
+
+    // Parse transport layer
+    if let Some((ip_protocol, transport_offset, ip_end)) = transport.filter(|(_, start, end)| end > start) {
+        if let Ok(transport_info) = super::transport::parse_transport(ip_protocol, &data[transport_offset..ip_end]) {
+            frame.src_port = transport_info.src_port;
+            frame.dst_port = transport_info.dst_port;
+            frame.tcp_flags = transport_info.tcp_flags;
+            frame.tcp_seq = transport_info.tcp_seq;
+            frame.tcp_ack = transport_info.tcp_ack;
+            frame.icmp_type = transport_info.icmp_type;
+            frame.icmp_code = transport_info.icmp_code;
+            frame.icmp_id = transport_info.icmp_id;
+            frame.payload_size = transport_info.payload_size;
+
+            // Application metadata
+            let payload_start = ip_end.saturating_sub(frame.payload_size as usize).max(transport_offset);
+            let payload = &data[payload_start..ip_end];
+            if metadata {
+                if let Some(protocol) = ot_protocol(&frame) {
+                    frame.ot = match protocol {
+                        OtProtocol::Modbus => super::modbus::parse_modbus(payload, frame.dst_port == Some(ports::MODBUS)),
+                        OtProtocol::Dnp3 => super::dnp3::parse_dnp3(payload),
+                        OtProtocol::Bacnet => super::bacnet::parse_bacnet(payload),
+                    };
+                } else if is_sip(&frame) {
+                    frame.sip = super::sip::parse_sip(payload);
+                } else if frame.is_tcp() {
+                    match streams.filter(|streams| streams.tracks(&frame)) {
+                        Some(streams) => frame.tls = reassembled_tls(streams, &frame, payload),
+                        None if !payload.is_empty() => frame.tls = super::tls::parse_tls(payload),
+                        None => {}
+                    }
+                } else if frame.is_udp() && is_dhcp(frame.src_port, frame.dst_port) {
+                    frame.dhcp = super::dhcp::parse_dhcp(payload);
+                } else if frame.is_udp() && is_dns(services(), frame.src_port, frame.dst_port) {
+                    frame.dns = super::dns::parse_dns(payload);
+                } else if frame.is_udp() && is_ntp(frame.src_port, frame.dst_port) {
+                    frame.ntp = super::ntp::parse_ntp(payload);
+                } else if frame.is_udp() && is_snmp(frame.src_port, frame.dst_port) {
+                    frame.snmp = super::snmp::parse_snmp(payload);
+                } else if frame.ip_protocol == Some(super::ipv4::protocol::IGMP) {
+                    frame.igmp = super::igmp::parse_igmp(payload);
                 }
             }
         }
@@ -121,6 +194,60 @@ pub fn parse_frame(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
     Ok(frame)
 }
 
+/// TLS metadata completed by a segment of a reassembled connection
+fn reassembled_tls(streams: &mut Reassembler, frame: &CapturedFrame, payload: &[u8]) -> Option<TlsInfo> {
+    let stream = streams.push(frame, payload)?;
+    let parsed = super::tls::parse_tls_stream(stream.pending());
+    stream.consume(parsed.length);
+    if parsed.done {
+        stream.finish();
+    }
+    parsed.info
+}
+
+/// Whether a UDP datagram goes between DHCP ports
+fn is_dhcp(src_port: Option<u16>, dst_port: Option<u16>) -> bool {
+    let dhcp = |port: Option<u16>| matches!(port, Some(ports::DHCP_SERVER | ports::DHCP_CLIENT));
+    dhcp(src_port) && dhcp(dst_port)
+}
+
+/// Whether a UDP datagram goes to or from the NTP port
+fn is_ntp(src_port: Option<u16>, dst_port: Option<u16>) -> bool {
+    src_port == Some(ports::NTP) || dst_port == Some(ports::NTP)
+}
+
+/// Whether a UDP datagram goes to or from an SNMP agent or trap receiver
+fn is_snmp(src_port: Option<u16>, dst_port: Option<u16>) -> bool {
+    let snmp = |port: Option<u16>| matches!(port, Some(ports::SNMP | ports::SNMP_TRAP));
+    snmp(src_port) || snmp(dst_port)
+}
+
+/// Whether a segment or datagram goes to or from the SIP port
+fn is_sip(frame: &CapturedFrame) -> bool {
+    (frame.is_tcp() || frame.is_udp()) && (frame.src_port == Some(ports::SIP) || frame.dst_port == Some(ports::SIP))
+}
+
+/// Industrial protocol of a segment or datagram to or from its port:
+/// Modbus over TCP, DNP3 over TCP or UDP, BACnet over UDP
+fn ot_protocol(frame: &CapturedFrame) -> Option<OtProtocol> {
+    let port = |port: u16| frame.src_port == Some(port) || frame.dst_port == Some(port);
+    if frame.is_tcp() && port(ports::MODBUS) {
+        Some(OtProtocol::Modbus)
+    } else if (frame.is_tcp() || frame.is_udp()) && port(ports::DNP3) {
+        Some(OtProtocol::Dnp3)
+    } else if frame.is_udp() && port(ports::BACNET) {
+        Some(OtProtocol::Bacnet)
+    } else {
+        None
+    }
+}
+
+/// Whether a UDP datagram goes to or from a port named `dns`: 53, and the
+/// ports named so in `[services]` (e.g. a resolver listening on 5353)
+fn is_dns(services: &ServiceNames, src_port: Option<u16>, dst_port: Option<u16>) -> bool {
+    [src_port, dst_port].into_iter().flatten().any(|port| services.name(port) == Some("dns"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,6 +309,101 @@ mod tests {
         assert_eq!(frame.ethertype, ETHERTYPE_IPV4);
     }
 
+    #[test]
+    fn test_parse_tcp_frame() {
+        let mut data = vec![
+            0x00, 0x66, 0x77, 0x88, 0x99, 0xaa, // dst MAC
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // src MAC
+            0x08, 0x00,                         // EtherType (IPv4)
+            0x45, 0xb8, 0x00, 0x28,             // IPv4, DSCP EF, total length 40
+            0x00, 0x00, 0x40, 0x00,             // ID, DF
+            0x40, 0x06, 0x00, 0x00,             // TTL 64, TCP, checksum
+            10, 0, 0, 5,                        // src IP
+            10, 0, 0, 80,                       // dst IP
+        ];
+        data.extend_from_slice(&[
+            0xc3, 0x50, 0x00, 0x50,             // ports 50000 -> 80
+            0x00, 0x00, 0x10, 0x00,             // seq 4096
+            0x00, 0x00, 0x00, 0x00,             // ack 0
+            0x50, 0x02, 0xff, 0xff,             // data offset 5, SYN, window
+            0x00, 0x00, 0x00, 0x00,             // checksum, urgent pointer
+        ]);
+        // Padding up to the 60-byte Ethernet minimum
+        data.extend_from_slice(&[0; 6]);
+
+        let frame = parse_frame("eth0", &data).unwrap();
+
+        assert_eq!(frame.dst_port, Some(80));
+        assert_eq!(frame.dscp, Some(46));
+        assert!(frame.tcp_flags.unwrap().is_syn_only());
+        assert_eq!(frame.tcp_seq, Some(4096));
+        assert_eq!(frame.tcp_ack, Some(0));
+        assert_eq!(frame.payload_size, 0);
+    }
+
+    #[test]
+    fn test_parse_ipv6_tcp_frame() {
+        let mut data = vec![
+            0x33, 0x33, 0x00, 0x00, 0x00, 0x01, // dst MAC
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // src MAC
+            0x86, 0xdd,                         // EtherType (IPv6)
+            0x60, 0x00, 0x00, 0x00,             // IPv6, traffic class 0
+            0x00, 0x1c, 0x3c, 0x40,             // payload length 28, destination options, hop limit 64
+        ];
+        data.extend_from_slice(&"2001:db8::5".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&"2001:db8::80".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&[0x06, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00]); // TCP next, PadN
+        data.extend_from_slice(&[
+            0xc3, 0x50, 0x01, 0xbb,             // ports 50000 -> 443
+            0x00, 0x00, 0x10, 0x00,             // seq 4096
+            0x00, 0x00, 0x00, 0x00,             // ack 0
+            0x50, 0x02, 0xff, 0xff,             // data offset 5, SYN, window
+            0x00, 0x00, 0x00, 0x00,             // checksum, urgent pointer
+        ]);
+
+        let frame = parse_frame("eth0", &data).unwrap();
+
+        assert_eq!(frame.src_ip6, Some("2001:db8::5".parse().unwrap()));
+        assert_eq!(frame.src_ip, None);
+        assert_eq!((frame.ip_protocol, frame.ttl), (Some(6), Some(64)));
+        assert_eq!((frame.src_port, frame.dst_port), (Some(50000), Some(443)));
+        assert!(frame.tcp_flags.unwrap().is_syn_only());
+    }
+
+    #[test]
+    fn test_parse_arp_frame() {
+        let mut data = vec![
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // dst MAC (broadcast)
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // src MAC
+            0x81, 0x00, 0x00, 0x0a,             // 802.1Q, VLAN ID 10
+            0x08, 0x06,                         // EtherType (ARP)
+            0x00, 0x01, 0x08, 0x00, 6, 4,       // Ethernet, IPv4
+            0x00, 0x01,                         // request
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // sender MAC
+            10, 0, 0, 5,                        // sender IP
+            0, 0, 0, 0, 0, 0,                   // target MAC
+            10, 0, 0, 1,                        // target IP
+        ];
+        // Padding up to the 60-byte Ethernet minimum
+        data.extend_from_slice(&[0; 14]);
+
+        let frame = parse_frame("eth0", &data).unwrap();
+
+        let arp = frame.arp.unwrap();
+        assert_eq!(arp.sender_mac, frame.src_mac);
+        assert_eq!(arp.sender_ip, std::net::Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(frame.src_ip, None);
+    }
+
+    #[test]
+    fn test_dns_ports() {
+        let services = ServiceNames::default().with_name(5353, "dns").with_name(5300, "resolver");
+        assert!(is_dns(&services, Some(50000), Some(53)));
+        assert!(is_dns(&services, Some(5353), Some(50000)));
+        assert!(!is_dns(&services, Some(50000), Some(5300)));
+        assert!(!is_dns(&ServiceNames::default(), Some(50000), Some(5353)));
+    }
+
     #[test]
     fn test_frame_too_short() {
         let data = vec![0xff, 0xff, 0xff]; // Only 3 bytes
@@ -0,0 +1,172 @@
+//! Flow hash of raw frames
+//!
+//! Routing key for spreading frames over decode workers: every frame of a
+//! flow, in both directions, hashes the same, so a flow is always decoded by
+//! the same worker, in capture order, and stateful decoders (TLS handshakes
+//! split over segments, reassembly) keep their per-flow state without
+//! locking. The hash reads the raw headers only, before any decoding.
+//!
+//! Flows are the VLAN tags, the IP addresses, the protocol and, for TCP,
+//! UDP and SCTP, the ports. Fragmented datagrams leave their ports out so
+//! all their fragments go to the same worker; other frames hash their MAC
+//! addresses.
+
+use super::ethernet::{ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_QINQ, ETHERTYPE_QINQ_ALT, ETHERTYPE_VLAN};
+use super::ipv4::protocol;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a, cheap enough to run on every frame of the capture thread
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Hash of the flow of a raw Ethernet frame, the same in both directions
+pub fn flow_hash(data: &[u8]) -> u64 {
+    let mut hash = Fnv(FNV_OFFSET);
+    let Some(ethernet) = data.get(..14) else {
+        hash.write(data);
+        return hash.0;
+    };
+
+    // VLAN tags (up to QinQ) are part of the flow
+    let mut ethertype = u16::from_be_bytes([ethernet[12], ethernet[13]]);
+    let mut offset = 14;
+    for _ in 0..2 {
+        if !matches!(ethertype, ETHERTYPE_VLAN | ETHERTYPE_QINQ | ETHERTYPE_QINQ_ALT) {
+            break;
+        }
+        let Some(tag) = data.get(offset..offset + 4) else { break };
+        hash.write(&[tag[0] & 0x0f, tag[1]]);
+        ethertype = u16::from_be_bytes([tag[2], tag[3]]);
+        offset += 4;
+    }
+
+    let endpoints = match ethertype {
+        ETHERTYPE_IPV4 => ipv4_endpoints(&data[offset.min(data.len())..]),
+        ETHERTYPE_IPV6 => ipv6_endpoints(&data[offset.min(data.len())..]),
+        _ => None,
+    };
+    match endpoints {
+        Some((protocol, a, b)) => {
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            hash.write(&[protocol]);
+            for (address, port) in [low, high] {
+                hash.write(address);
+                hash.write(&port);
+            }
+        }
+        None => {
+            let (dst, src) = (&ethernet[..6], &ethernet[6..12]);
+            let (low, high) = if src <= dst { (src, dst) } else { (dst, src) };
+            hash.write(&ethertype.to_be_bytes());
+            hash.write(low);
+            hash.write(high);
+        }
+    }
+    hash.0
+}
+
+/// Worker, out of `workers`, decoding the frames of the flow of `data`
+pub fn worker_for(data: &[u8], workers: usize) -> usize {
+    (flow_hash(data) % workers.max(1) as u64) as usize
+}
+
+/// Address and port of one end of a flow (port zero when left out)
+type Endpoint<'a> = (&'a [u8], [u8; 2]);
+
+fn endpoint<'a>(address: &'a [u8], port: Option<&[u8]>) -> Endpoint<'a> {
+    (address, port.map_or([0, 0], |port| [port[0], port[1]]))
+}
+
+/// Whether `protocol` carries source and destination ports first
+fn has_ports(protocol: u8) -> bool {
+    matches!(protocol, protocol::TCP | protocol::UDP | protocol::SCTP)
+}
+
+/// Protocol and endpoints of an IPv4 packet
+fn ipv4_endpoints(packet: &[u8]) -> Option<(u8, Endpoint<'_>, Endpoint<'_>)> {
+    let header = packet.get(..20)?;
+    let header_length = (header[0] & 0x0f) as usize * 4;
+    let protocol = header[9];
+    // More fragments, or a fragment offset
+    let fragmented = u16::from_be_bytes([header[6], header[7]]) & 0x3fff != 0;
+    let ports = packet
+        .get(header_length..header_length + 4)
+        .filter(|_| has_ports(protocol) && !fragmented);
+    Some((
+        protocol,
+        endpoint(&header[12..16], ports.map(|p| &p[..2])),
+        endpoint(&header[16..20], ports.map(|p| &p[2..])),
+    ))
+}
+
+/// Protocol and endpoints of an IPv6 packet, without extension headers
+fn ipv6_endpoints(packet: &[u8]) -> Option<(u8, Endpoint<'_>, Endpoint<'_>)> {
+    let header = packet.get(..40)?;
+    let protocol = header[6];
+    let ports = packet.get(40..44).filter(|_| has_ports(protocol));
+    Some((
+        protocol,
+        endpoint(&header[8..24], ports.map(|p| &p[..2])),
+        endpoint(&header[24..40], ports.map(|p| &p[2..])),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TCP frame between 10.0.0.5:50000 and 10.0.0.80:80, optionally reversed
+    fn tcp_frame(reversed: bool, flags_fragment: [u8; 2]) -> Vec<u8> {
+        let (mut macs, mut ips, mut ports) = (
+            [[0x00, 0x66, 0x77, 0x88, 0x99, 0xaa], [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]],
+            [[10, 0, 0, 5], [10, 0, 0, 80]],
+            [[0xc3, 0x50], [0x00, 0x50]],
+        );
+        if reversed {
+            macs.reverse();
+            ips.reverse();
+            ports.reverse();
+        }
+        let mut data = [macs[0], macs[1]].concat();
+        data.extend_from_slice(&[0x08, 0x00, 0x45, 0x00, 0x00, 0x28, 0x00, 0x00]);
+        data.extend_from_slice(&flags_fragment);
+        data.extend_from_slice(&[0x40, 0x06, 0x00, 0x00]);
+        data.extend_from_slice(&[ips[0], ips[1]].concat());
+        data.extend_from_slice(&[ports[0], ports[1]].concat());
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    #[test]
+    fn test_flow_hash_is_symmetric() {
+        let request = tcp_frame(false, [0x40, 0x00]);
+        let response = tcp_frame(true, [0x40, 0x00]);
+        assert_eq!(flow_hash(&request), flow_hash(&response));
+        assert_eq!(worker_for(&request, 8), worker_for(&response, 8));
+
+        // Another port is another flow
+        let mut other = request.clone();
+        other[35] = 0x51;
+        assert_ne!(flow_hash(&request), flow_hash(&other));
+
+        // Fragments of a datagram hash together, whatever their offset
+        let (first, next) = (tcp_frame(false, [0x20, 0x00]), tcp_frame(true, [0x00, 0x10]));
+        assert_eq!(flow_hash(&first), flow_hash(&next));
+
+        // Tagged on another VLAN: another flow
+        let mut tagged = request[..12].to_vec();
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x0a]);
+        tagged.extend_from_slice(&request[12..]);
+        assert_ne!(flow_hash(&request), flow_hash(&tagged));
+        assert!(worker_for(&tagged, 3) < 3);
+    }
+}
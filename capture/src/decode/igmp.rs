@@ -0,0 +1,97 @@
+//! IGMP message parsing
+//!
+//! Decodes the membership reports (IGMPv1, v2 and v3) and leaves hosts send
+//! for the multicast groups they listen to, and the routers' queries.
+//! IGMPv3 records joining a group in exclude mode, or for some sources, are
+//! memberships; an include mode record without sources is a leave.
+
+use std::net::Ipv4Addr;
+
+pub use netsentinel_types::IgmpInfo;
+
+/// Message types
+pub const MEMBERSHIP_QUERY: u8 = 0x11;
+pub const V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const LEAVE_GROUP: u8 = 0x17;
+pub const V3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+/// IGMPv3 group record types
+const MODE_IS_INCLUDE: u8 = 1;
+const MODE_IS_EXCLUDE: u8 = 2;
+const CHANGE_TO_INCLUDE: u8 = 3;
+const CHANGE_TO_EXCLUDE: u8 = 4;
+const ALLOW_NEW_SOURCES: u8 = 5;
+
+fn group(bytes: &[u8]) -> Option<Ipv4Addr> {
+    let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+    ip.is_multicast().then_some(ip)
+}
+
+/// Parse an IGMP message from an IP payload
+pub fn parse_igmp(data: &[u8]) -> Option<IgmpInfo> {
+    if data.len() < 8 {
+        return None;
+    }
+    let message_type = data[0];
+    let mut info = IgmpInfo { message_type, joined: Vec::new(), left: Vec::new() };
+
+    match message_type {
+        MEMBERSHIP_QUERY => {}
+        V1_MEMBERSHIP_REPORT | V2_MEMBERSHIP_REPORT => info.joined.extend(group(&data[4..8])),
+        LEAVE_GROUP => info.left.extend(group(&data[4..8])),
+        V3_MEMBERSHIP_REPORT => {
+            let records = u16::from_be_bytes([data[6], data[7]]);
+            let mut rest = &data[8..];
+            for _ in 0..records {
+                let header = rest.get(..8)?;
+                let (record_type, aux_words, sources) = (header[0], header[1], u16::from_be_bytes([header[2], header[3]]));
+                let length = 8 + sources as usize * 4 + aux_words as usize * 4;
+                let Some(address) = group(&header[4..8]) else {
+                    rest = rest.get(length..)?;
+                    continue;
+                };
+                match record_type {
+                    MODE_IS_INCLUDE | CHANGE_TO_INCLUDE if sources == 0 => info.left.push(address),
+                    MODE_IS_INCLUDE | CHANGE_TO_INCLUDE | MODE_IS_EXCLUDE | CHANGE_TO_EXCLUDE | ALLOW_NEW_SOURCES => {
+                        info.joined.push(address)
+                    }
+                    _ => {}
+                }
+                rest = rest.get(length..)?;
+            }
+        }
+        _ => return None,
+    }
+
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_igmp() {
+        let report = [V2_MEMBERSHIP_REPORT, 0, 0, 0, 239, 1, 2, 3];
+        let info = parse_igmp(&report).unwrap();
+        assert_eq!(info.joined, vec![Ipv4Addr::new(239, 1, 2, 3)]);
+        assert!(info.left.is_empty());
+
+        let leave = [LEAVE_GROUP, 0, 0, 0, 239, 1, 2, 3];
+        assert_eq!(parse_igmp(&leave).unwrap().left, vec![Ipv4Addr::new(239, 1, 2, 3)]);
+
+        // IGMPv3: join in exclude mode, join one source, leave
+        let mut report = vec![V3_MEMBERSHIP_REPORT, 0, 0, 0, 0, 0, 0, 3];
+        report.extend_from_slice(&[CHANGE_TO_EXCLUDE, 0, 0, 0, 239, 0, 0, 1]);
+        report.extend_from_slice(&[MODE_IS_INCLUDE, 0, 0, 1, 232, 1, 1, 1, 10, 0, 0, 9]);
+        report.extend_from_slice(&[CHANGE_TO_INCLUDE, 0, 0, 0, 239, 0, 0, 2]);
+        let info = parse_igmp(&report).unwrap();
+        assert_eq!(info.joined, vec![Ipv4Addr::new(239, 0, 0, 1), Ipv4Addr::new(232, 1, 1, 1)]);
+        assert_eq!(info.left, vec![Ipv4Addr::new(239, 0, 0, 2)]);
+
+        // Truncated record
+        assert!(parse_igmp(&report[..20]).is_none());
+        assert!(parse_igmp(&[0x99, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -212,7 +212,7 @@ mod tests {
     #[test]
     fn test_parse_ipv4_with_options() {
         // IPv4 header with options: IHL=6 (24 bytes)
-        let mut data = vec![
+        let data = vec![
             0x46, 0x00,             // Version + IHL=6
             0x00, 0x2c,             // Total length (44 bytes)
             0x00, 0x01, 0x40, 0x00, // ID, Flags, Fragment
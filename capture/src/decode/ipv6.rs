@@ -0,0 +1,219 @@
+//! IPv6 header parsing
+//!
+//! Reads the fixed header and walks the extension header chain (hop-by-hop
+//! and destination options, routing, fragment, authentication) to the
+//! upper-layer protocol, so TCP and UDP over IPv6 get their ports like over
+//! IPv4. ESP ends the chain: what follows is encrypted.
+
+use std::net::Ipv6Addr;
+use anyhow::{Result, bail};
+
+use super::ipv4::protocol;
+
+/// Length of the fixed header
+pub const HEADER_LENGTH: usize = 40;
+
+/// Extension headers followed at most, bounding the walk on crafted chains
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// Next header values of the extension headers
+pub mod extension {
+    pub const HOP_BY_HOP: u8 = 0;
+    pub const ROUTING: u8 = 43;
+    pub const FRAGMENT: u8 = 44;
+    pub const DESTINATION_OPTIONS: u8 = 60;
+    pub const MOBILITY: u8 = 135;
+    /// No upper-layer header follows
+    pub const NO_NEXT_HEADER: u8 = 59;
+}
+
+/// Parsed IPv6 information
+#[derive(Debug, Clone)]
+pub struct Ipv6Info {
+    /// Traffic class
+    pub traffic_class: u8,
+    /// Differentiated Services Code Point, from the traffic class
+    pub dscp: u8,
+    /// Flow label
+    pub flow_label: u32,
+    /// Length of the packet after the fixed header, extension headers
+    /// included (0 for jumbograms)
+    pub payload_length: u16,
+    /// Hop limit
+    pub hop_limit: u8,
+    /// Upper-layer protocol, after the extension headers
+    pub protocol: u8,
+    /// Extension headers, in chain order
+    pub extensions: Vec<u8>,
+    /// Length of the fixed and extension headers
+    pub header_length: usize,
+    /// Fragment offset in bytes, when the packet is a fragment
+    pub fragment_offset: Option<u16>,
+    /// More fragments flag
+    pub more_fragments: bool,
+    /// Source IP address
+    pub src_ip: Ipv6Addr,
+    /// Destination IP address
+    pub dst_ip: Ipv6Addr,
+}
+
+impl Ipv6Info {
+    /// Whether the packet is a fragment past the first: its payload
+    /// continues a datagram, without upper-layer header
+    pub fn is_later_fragment(&self) -> bool {
+        self.fragment_offset.is_some_and(|offset| offset > 0)
+    }
+
+    /// Length of the whole packet, `available` for jumbograms
+    pub fn packet_length(&self, available: usize) -> usize {
+        match self.payload_length {
+            0 => available,
+            length => HEADER_LENGTH + length as usize,
+        }
+    }
+}
+
+/// Parse an IPv6 header and its extension headers
+///
+/// IPv6 header format:
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |Version| Traffic Class |           Flow Label                  |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |         Payload Length        |  Next Header  |   Hop Limit   |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// +                         Source Address                        +
+/// |                          (128 bits)                           |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// +                      Destination Address                      +
+/// |                          (128 bits)                           |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// Extension headers start with their next header; all but the fragment
+/// header (8 bytes) give their length after it, in 8-byte units beyond the
+/// first 8 bytes (4-byte units beyond the first 8 for AH).
+pub fn parse_ipv6(data: &[u8]) -> Result<Ipv6Info> {
+    if data.len() < HEADER_LENGTH {
+        bail!("Data too short for IPv6 header: {} bytes (minimum {})", data.len(), HEADER_LENGTH);
+    }
+
+    let version = data[0] >> 4;
+    if version != 6 {
+        bail!("Invalid IP version: {} (expected 6)", version);
+    }
+
+    let traffic_class = (data[0] << 4) | (data[1] >> 4);
+    let flow_label = u32::from_be_bytes([0, data[1] & 0x0F, data[2], data[3]]);
+    let payload_length = u16::from_be_bytes([data[4], data[5]]);
+    let hop_limit = data[7];
+
+    let mut src = [0u8; 16];
+    src.copy_from_slice(&data[8..24]);
+    let mut dst = [0u8; 16];
+    dst.copy_from_slice(&data[24..40]);
+
+    let mut info = Ipv6Info {
+        traffic_class,
+        dscp: traffic_class >> 2,
+        flow_label,
+        payload_length,
+        hop_limit,
+        protocol: data[6],
+        extensions: Vec::new(),
+        header_length: HEADER_LENGTH,
+        fragment_offset: None,
+        more_fragments: false,
+        src_ip: Ipv6Addr::from(src),
+        dst_ip: Ipv6Addr::from(dst),
+    };
+
+    while is_extension(info.protocol) {
+        if info.extensions.len() == MAX_EXTENSION_HEADERS {
+            bail!("More than {} IPv6 extension headers", MAX_EXTENSION_HEADERS);
+        }
+        let offset = info.header_length;
+        let Some(header) = data.get(offset..offset + 8) else {
+            bail!("IPv6 extension header {} truncated at byte {}", info.protocol, offset);
+        };
+
+        let length = match info.protocol {
+            extension::FRAGMENT => {
+                let offset_flags = u16::from_be_bytes([header[2], header[3]]);
+                info.fragment_offset = Some(offset_flags & 0xFFF8);
+                info.more_fragments = offset_flags & 0x0001 == 1;
+                8
+            }
+            protocol::AH => (header[1] as usize + 2) * 4,
+            _ => (header[1] as usize + 1) * 8,
+        };
+        if data.len() < offset + length {
+            bail!("IPv6 extension header {} truncated: {} bytes (need {})", info.protocol, data.len() - offset, length);
+        }
+
+        info.extensions.push(info.protocol);
+        info.protocol = header[0];
+        info.header_length += length;
+    }
+
+    Ok(info)
+}
+
+/// Whether a next header value is an extension header to walk past
+fn is_extension(next_header: u8) -> bool {
+    matches!(
+        next_header,
+        extension::HOP_BY_HOP
+            | extension::ROUTING
+            | extension::FRAGMENT
+            | extension::DESTINATION_OPTIONS
+            | extension::MOBILITY
+            | protocol::AH
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed header from fe80::1 to ff02::1:ff00:2, `next_header` first
+    fn header(payload_length: u16, next_header: u8) -> Vec<u8> {
+        let mut data = vec![0x6b, 0x80, 0x00, 0x01];     // Version 6, traffic class 0xb8 (EF), flow label 1
+        data.extend_from_slice(&payload_length.to_be_bytes());
+        data.extend_from_slice(&[next_header, 255]);     // Next header, hop limit
+        data.extend_from_slice(&"fe80::1".parse::<Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&"ff02::1:ff00:2".parse::<Ipv6Addr>().unwrap().octets());
+        data
+    }
+
+    #[test]
+    fn test_parse_ipv6_extensions() {
+        let info = parse_ipv6(&header(0, protocol::TCP)).unwrap();
+        assert_eq!((info.protocol, info.header_length, info.hop_limit), (protocol::TCP, 40, 255));
+        assert_eq!((info.dscp, info.flow_label), (46, 1));
+        assert_eq!(info.src_ip, "fe80::1".parse::<Ipv6Addr>().unwrap());
+
+        // Hop-by-hop options (16 bytes), then the first fragment of a UDP datagram
+        let mut data = header(32, extension::HOP_BY_HOP);
+        data.extend_from_slice(&[extension::FRAGMENT, 1, 0x05, 0x02, 0x01, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[protocol::UDP, 0, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78]);
+        data.extend_from_slice(&[0; 8]);
+        let info = parse_ipv6(&data).unwrap();
+        assert_eq!(info.protocol, protocol::UDP);
+        assert_eq!(info.extensions, vec![extension::HOP_BY_HOP, extension::FRAGMENT]);
+        assert_eq!(info.header_length, 64);
+        assert_eq!((info.fragment_offset, info.more_fragments, info.is_later_fragment()), (Some(0), true, false));
+        assert_eq!(info.packet_length(data.len()), 72);
+
+        // ESP ends the chain; truncated extension, IPv4
+        let info = parse_ipv6(&header(0, protocol::ESP)).unwrap();
+        assert_eq!((info.protocol, info.header_length), (protocol::ESP, 40));
+        assert!(parse_ipv6(&header(0, extension::ROUTING)).is_err());
+        assert!(parse_ipv6(&[0x45; 40]).is_err());
+    }
+}
@@ -1,22 +1,50 @@
 //! Frame decoding module
 //!
-//! Handles parsing of Ethernet frames including VLAN tags,
-//! IPv4 headers, and TCP/UDP ports.
+//! Handles parsing of Ethernet frames including VLAN tags, ARP messages,
+//! IPv4 and IPv6 headers, TCP/UDP ports, TLS handshake, DHCP, DNS, IGMP, NTP and SNMP metadata,
+//! SIP signaling, and Modbus/TCP, DNP3 and BACnet/IP messages.
 
 pub mod ethernet;
 pub mod vlan;
+pub mod arp;
 pub mod ipv4;
+pub mod ipv6;
 pub mod transport;
+pub mod tls;
+pub mod dhcp;
+pub mod dns;
+pub mod igmp;
+pub mod ntp;
+pub mod snmp;
+pub mod modbus;
+pub mod dnp3;
+pub mod bacnet;
+pub mod sip;
+pub mod erspan;
+pub mod flow_hash;
+pub mod reassembly;
 
 use anyhow::Result;
-use crate::capture::frame::CapturedFrame;
+use netsentinel_types::CapturedFrame;
 
 pub use ethernet::parse_ethernet;
 pub use vlan::{parse_vlan, parse_qinq};
 pub use ipv4::parse_ipv4;
+pub use ipv6::parse_ipv6;
 pub use transport::parse_transport;
 
 /// Parse a complete frame from raw bytes
 pub fn parse_frame(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
     ethernet::parse_frame(interface, data)
 }
+
+/// Parse a complete frame, TCP payloads of the reassembled connections read
+/// from their ordered stream
+pub fn parse_frame_reassembled(interface: &str, data: &[u8], streams: &mut reassembly::Reassembler) -> Result<CapturedFrame> {
+    ethernet::parse_frame_reassembled(interface, data, streams)
+}
+
+/// Parse a frame without its TLS, DHCP or DNS metadata
+pub fn parse_headers(interface: &str, data: &[u8]) -> Result<CapturedFrame> {
+    ethernet::parse_headers(interface, data)
+}
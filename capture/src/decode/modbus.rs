@@ -0,0 +1,86 @@
+//! Modbus/TCP message parsing
+//!
+//! Decodes the MBAP header and function code of Modbus/TCP messages: the
+//! unit addressed and whether a request reads or writes coils and registers.
+//! Exceptions (function code with the high bit set) are reported with the
+//! function they answer. Only the first message of a segment is read.
+
+pub use netsentinel_types::{OtInfo, OtProtocol};
+
+/// Function codes
+pub const READ_COILS: u8 = 1;
+pub const READ_HOLDING_REGISTERS: u8 = 3;
+pub const WRITE_SINGLE_COIL: u8 = 5;
+pub const WRITE_SINGLE_REGISTER: u8 = 6;
+pub const WRITE_MULTIPLE_COILS: u8 = 15;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 16;
+pub const MASK_WRITE_REGISTER: u8 = 22;
+pub const READ_WRITE_MULTIPLE_REGISTERS: u8 = 23;
+
+/// Functions writing coils or registers
+const WRITES: [u8; 6] = [
+    WRITE_SINGLE_COIL,
+    WRITE_SINGLE_REGISTER,
+    WRITE_MULTIPLE_COILS,
+    WRITE_MULTIPLE_REGISTERS,
+    MASK_WRITE_REGISTER,
+    READ_WRITE_MULTIPLE_REGISTERS,
+];
+
+/// MBAP header (transaction, protocol, length, unit) and function code
+const HEADER_LEN: usize = 8;
+
+/// Parse a Modbus/TCP message from a TCP payload, a request when sent to the
+/// Modbus port
+pub fn parse_modbus(data: &[u8], request: bool) -> Option<OtInfo> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let protocol_id = u16::from_be_bytes([data[2], data[3]]);
+    // Unit identifier and PDU, at most 253 bytes
+    let length = u16::from_be_bytes([data[4], data[5]]);
+    if protocol_id != 0 || !(2..=254).contains(&length) {
+        return None;
+    }
+    let function = data[7] & 0x7f;
+    if function == 0 {
+        return None;
+    }
+
+    Some(OtInfo {
+        protocol: OtProtocol::Modbus,
+        function,
+        request,
+        write: request && WRITES.contains(&function),
+        unit: Some(data[6] as u32),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(unit: u8, function: u8) -> Vec<u8> {
+        vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x06, unit, function, 0x00, 0x10, 0x00, 0x02]
+    }
+
+    #[test]
+    fn test_parse_modbus() {
+        let read = parse_modbus(&message(1, READ_HOLDING_REGISTERS), true).unwrap();
+        assert_eq!((read.function, read.request, read.write, read.unit), (READ_HOLDING_REGISTERS, true, false, Some(1)));
+
+        let write = parse_modbus(&message(17, WRITE_SINGLE_COIL), true).unwrap();
+        assert!(write.write);
+        assert_eq!(write.unit, Some(17));
+
+        // The response to a write, and an exception
+        assert!(!parse_modbus(&message(17, WRITE_SINGLE_COIL), false).unwrap().write);
+        assert_eq!(parse_modbus(&message(1, 0x80 | WRITE_MULTIPLE_REGISTERS), false).unwrap().function, WRITE_MULTIPLE_REGISTERS);
+
+        // Another protocol on the port, truncated header
+        let mut other = message(1, READ_COILS);
+        other[2] = 0x12;
+        assert!(parse_modbus(&other, true).is_none());
+        assert!(parse_modbus(&message(1, READ_COILS)[..7], true).is_none());
+    }
+}
@@ -0,0 +1,90 @@
+//! NTP message parsing
+//!
+//! Decodes the header of NTP (v1 to v4) messages: the version, the
+//! association mode telling client requests from server responses, and the
+//! server's stratum and reference, which show where its time comes from.
+//! Control (mode 6) and private (mode 7) messages have another layout and
+//! are left out.
+
+pub use netsentinel_types::NtpInfo;
+
+/// Association modes
+pub const MODE_SYMMETRIC_ACTIVE: u8 = 1;
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+pub const MODE_BROADCAST: u8 = 5;
+
+/// Length of the NTP header, without extension fields or authenticator
+const HEADER_LEN: usize = 48;
+
+/// Reference ID of a server: the ASCII name of a primary server's clock
+/// source (or a kiss code), the IPv4 address of the upstream server of the
+/// others (IPv6 upstream servers give a hash, shown as an address as well)
+fn reference_id(stratum: u8, bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|&b| b == 0) {
+        return None;
+    }
+    if stratum <= 1 {
+        let name: String = bytes.iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect();
+        return (!name.is_empty() && name.chars().all(|c| c.is_ascii_graphic())).then_some(name);
+    }
+    Some(format!("{}.{}.{}.{}", bytes[0], bytes[1], bytes[2], bytes[3]))
+}
+
+/// Parse an NTP message from a UDP payload
+pub fn parse_ntp(data: &[u8]) -> Option<NtpInfo> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let version = (data[0] >> 3) & 0x07;
+    let mode = data[0] & 0x07;
+    if !(1..=4).contains(&version) || !(MODE_SYMMETRIC_ACTIVE..=MODE_BROADCAST).contains(&mode) {
+        return None;
+    }
+    let stratum = data[1];
+
+    Some(NtpInfo {
+        version,
+        mode,
+        stratum,
+        reference_id: if mode == MODE_CLIENT { None } else { reference_id(stratum, &data[12..16]) },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(version: u8, mode: u8, stratum: u8, reference: [u8; 4]) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_LEN];
+        data[0] = (version << 3) | mode;
+        data[1] = stratum;
+        data[12..16].copy_from_slice(&reference);
+        data
+    }
+
+    #[test]
+    fn test_parse_ntp() {
+        let request = parse_ntp(&message(4, MODE_CLIENT, 0, [0; 4])).unwrap();
+        assert_eq!((request.version, request.mode, request.stratum), (4, MODE_CLIENT, 0));
+        assert_eq!(request.reference_id, None);
+
+        let primary = parse_ntp(&message(4, MODE_SERVER, 1, *b"GPS\0")).unwrap();
+        assert_eq!(primary.reference_id.as_deref(), Some("GPS"));
+
+        let secondary = parse_ntp(&message(3, MODE_SERVER, 2, [192, 0, 2, 10])).unwrap();
+        assert_eq!(secondary.stratum, 2);
+        assert_eq!(secondary.reference_id.as_deref(), Some("192.0.2.10"));
+    }
+
+    #[test]
+    fn test_parse_ntp_invalid() {
+        // Control message, unknown version, truncated header
+        assert!(parse_ntp(&message(2, 6, 0, [0; 4])).is_none());
+        assert!(parse_ntp(&message(7, MODE_CLIENT, 0, [0; 4])).is_none());
+        assert!(parse_ntp(&message(4, MODE_CLIENT, 0, [0; 4])[..40]).is_none());
+    }
+}
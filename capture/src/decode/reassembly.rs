@@ -0,0 +1,269 @@
+//! TCP stream reassembly
+//!
+//! A TLS ClientHello or certificate chain often spans several TCP segments,
+//! and segments arrive out of order or retransmitted. For the connections
+//! on the configured ports, each direction's payload is put back in
+//! sequence order and decoders read the ordered byte stream instead of
+//! single segments. Decoders consume the complete messages at the start of
+//! the stream, so only a partial message stays buffered.
+//!
+//! Memory is bounded: at most `max_streams` directions are followed, each
+//! buffering at most `max_stream_bytes` (ordered and out-of-order bytes
+//! together). A direction over its budget, idle for `timeout_secs` or whose
+//! decoder is done is no longer buffered. Each capture thread reassembles
+//! the connections of its own interface, without locking.
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+
+use netsentinel_types::CapturedFrame;
+use crate::config::ReassemblyConfig;
+
+/// One direction of a connection: VLAN, source and destination endpoints
+type StreamKey = (Option<u16>, Ipv4Addr, u16, Ipv4Addr, u16);
+
+/// Bytes of one direction of a connection, in sequence order
+#[derive(Debug, Default)]
+pub struct Stream {
+    /// Sequence number of the next byte expected, once known
+    next_seq: Option<u32>,
+    /// Ordered bytes not consumed yet
+    pending: Vec<u8>,
+    /// Segments past a gap, by sequence number
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    /// No longer buffered: over budget or done decoding
+    finished: bool,
+    last_seen: DateTime<Utc>,
+}
+
+impl Stream {
+    /// Ordered bytes not consumed yet
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    /// Drop the first `length` pending bytes, decoded
+    pub fn consume(&mut self, length: usize) {
+        self.pending.drain(..length.min(self.pending.len()));
+    }
+
+    /// Stop buffering this direction, nothing more to decode in it
+    pub fn finish(&mut self) {
+        self.finished = true;
+        self.pending = Vec::new();
+        self.out_of_order = Vec::new();
+    }
+
+    fn buffered(&self) -> usize {
+        self.pending.len() + self.out_of_order.iter().map(|(_, data)| data.len()).sum::<usize>()
+    }
+
+    /// Add the payload of the segment starting at `seq`, returning whether
+    /// ordered bytes were added
+    fn add(&mut self, seq: u32, payload: &[u8], max_bytes: usize) -> bool {
+        let next = *self.next_seq.get_or_insert(seq);
+        if self.buffered() + payload.len() > max_bytes {
+            self.finish();
+            return false;
+        }
+
+        // Past a gap: kept until the gap is filled
+        let ahead = seq.wrapping_sub(next) as i32;
+        if ahead > 0 {
+            self.out_of_order.push((seq, payload.to_vec()));
+            return false;
+        }
+
+        let added = self.append(seq, payload);
+        // Segments the gap was holding back
+        loop {
+            let next = self.next_seq.unwrap_or_default();
+            let Some(index) = self.out_of_order.iter().position(|(seq, _)| seq.wrapping_sub(next) as i32 <= 0) else {
+                break;
+            };
+            let (seq, data) = self.out_of_order.swap_remove(index);
+            self.append(seq, &data);
+        }
+        added
+    }
+
+    /// Append the bytes of a segment not received yet
+    fn append(&mut self, seq: u32, payload: &[u8]) -> bool {
+        let next = self.next_seq.unwrap_or(seq);
+        // Retransmitted bytes are skipped
+        let seen = (next.wrapping_sub(seq) as usize).min(payload.len());
+        let new = &payload[seen..];
+        self.pending.extend_from_slice(new);
+        self.next_seq = Some(next.wrapping_add(new.len() as u32));
+        !new.is_empty()
+    }
+}
+
+/// Reassembled TCP streams of one capture
+#[derive(Debug)]
+pub struct Reassembler {
+    ports: HashSet<u16>,
+    max_streams: usize,
+    max_stream_bytes: usize,
+    timeout: chrono::Duration,
+    streams: HashMap<StreamKey, Stream>,
+    last_sweep: Option<DateTime<Utc>>,
+}
+
+impl Reassembler {
+    pub fn new(config: &ReassemblyConfig) -> Self {
+        Self {
+            ports: config.ports.iter().copied().collect(),
+            max_streams: config.max_streams,
+            max_stream_bytes: config.max_stream_bytes,
+            timeout: chrono::Duration::seconds(config.timeout_secs as i64),
+            streams: HashMap::new(),
+            last_sweep: None,
+        }
+    }
+
+    /// Whether the connection of a TCP frame is reassembled (IPv4 only)
+    pub fn tracks(&self, frame: &CapturedFrame) -> bool {
+        frame.src_ip.is_some() && [frame.src_port, frame.dst_port].iter().flatten().any(|port| self.ports.contains(port))
+    }
+
+    /// Directions followed
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Add a TCP segment with its payload
+    ///
+    /// Returns its direction's stream when the segment added ordered bytes
+    /// to decode.
+    pub fn push(&mut self, frame: &CapturedFrame, payload: &[u8]) -> Option<&mut Stream> {
+        let (Some(src_ip), Some(dst_ip), Some(src_port), Some(dst_port), Some(seq), Some(flags)) =
+            (frame.src_ip, frame.dst_ip, frame.src_port, frame.dst_port, frame.tcp_seq, frame.tcp_flags)
+        else {
+            return None;
+        };
+        let key = (frame.vlan_id(), src_ip, src_port, dst_ip, dst_port);
+        self.sweep(frame.timestamp);
+
+        // The connection is over in this direction
+        if flags.rst || flags.fin {
+            self.streams.remove(&key);
+            return None;
+        }
+        if flags.syn {
+            // The SYN takes a sequence number, data starts after it
+            let stream = Stream { next_seq: Some(seq.wrapping_add(1)), last_seen: frame.timestamp, ..Default::default() };
+            if self.streams.len() < self.max_streams || self.streams.contains_key(&key) {
+                self.streams.insert(key, stream);
+            }
+            return None;
+        }
+        if payload.is_empty() {
+            return None;
+        }
+
+        if !self.streams.contains_key(&key) && self.streams.len() >= self.max_streams {
+            return None;
+        }
+        let max_bytes = self.max_stream_bytes;
+        let stream = self.streams.entry(key).or_default();
+        stream.last_seen = frame.timestamp;
+        if stream.finished || !stream.add(seq, payload, max_bytes) {
+            return None;
+        }
+        Some(stream)
+    }
+
+    /// Forget the directions idle for longer than the timeout, once per
+    /// timeout or, when no more directions can be followed, once a second
+    fn sweep(&mut self, now: DateTime<Utc>) {
+        let elapsed = now - *self.last_sweep.get_or_insert(now);
+        let full = self.streams.len() >= self.max_streams;
+        if elapsed < self.timeout && !(full && elapsed >= chrono::Duration::seconds(1)) {
+            return;
+        }
+        let stale = now - self.timeout;
+        self.streams.retain(|_, stream| stream.last_seen >= stale);
+        self.last_sweep = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netsentinel_types::{MacAddr, TcpFlags};
+
+    fn segment(seq: u32, flags: u8) -> CapturedFrame {
+        let mut frame = CapturedFrame::new("eth0", MacAddr::new([0, 1, 2, 3, 4, 5]), MacAddr::new([0, 1, 2, 3, 4, 6]), 0x0800, 60);
+        frame.src_ip = Some(Ipv4Addr::new(10, 0, 0, 5));
+        frame.dst_ip = Some(Ipv4Addr::new(10, 0, 0, 80));
+        frame.ip_protocol = Some(6);
+        frame.src_port = Some(50000);
+        frame.dst_port = Some(443);
+        frame.tcp_seq = Some(seq);
+        frame.tcp_flags = Some(TcpFlags::from_byte(flags));
+        frame
+    }
+
+    fn reassembler(max_streams: usize, max_stream_bytes: usize) -> Reassembler {
+        Reassembler::new(&ReassemblyConfig {
+            enabled: true,
+            ports: vec![443],
+            max_streams,
+            max_stream_bytes,
+            timeout_secs: 30,
+        })
+    }
+
+    #[test]
+    fn test_reorders_segments() {
+        let mut streams = reassembler(16, 1024);
+        // Sequence numbers wrap around within the connection
+        let isn = u32::MAX - 3;
+        assert!(streams.push(&segment(isn, 0x02), &[]).is_none());
+        let data = isn.wrapping_add(1);
+
+        // Out of order: held back until the gap is filled
+        assert!(streams.push(&segment(data.wrapping_add(5), 0x18), b"world").is_none());
+        let stream = streams.push(&segment(data, 0x18), b"hello").unwrap();
+        assert_eq!(stream.pending(), b"helloworld");
+        stream.consume(5);
+
+        // Retransmitted bytes are skipped
+        let stream = streams.push(&segment(data.wrapping_add(8), 0x18), b"ld!").unwrap();
+        assert_eq!(stream.pending(), b"world!");
+        assert!(streams.push(&segment(data, 0x18), b"hello").is_none());
+
+        // Finished streams are no longer buffered, until the connection ends
+        streams.push(&segment(data.wrapping_add(11), 0x18), b"?").unwrap().finish();
+        assert!(streams.push(&segment(data.wrapping_add(12), 0x18), b"more").is_none());
+        assert!(streams.push(&segment(data.wrapping_add(16), 0x11), &[]).is_none());
+        assert!(streams.is_empty());
+    }
+
+    #[test]
+    fn test_memory_bounds() {
+        let mut streams = reassembler(1, 8);
+        assert!(streams.push(&segment(100, 0x18), b"0123").is_some());
+
+        // Over the stream budget: no longer buffered
+        assert!(streams.push(&segment(104, 0x18), b"456789").is_none());
+        assert!(streams.push(&segment(110, 0x18), b"a").is_none());
+
+        // Over the stream count: other connections are not followed
+        let mut other = segment(500, 0x18);
+        other.src_port = Some(50001);
+        assert!(streams.push(&other, b"data").is_none());
+        assert_eq!(streams.len(), 1);
+
+        // Idle streams make room
+        other.timestamp += chrono::Duration::seconds(31);
+        assert!(streams.push(&other, b"data").is_some());
+        assert_eq!(streams.len(), 1);
+    }
+}
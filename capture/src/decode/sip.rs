@@ -0,0 +1,159 @@
+//! SIP message parsing
+//!
+//! Decodes the start line and the dialog headers of SIP messages over UDP
+//! or TCP: method or status, Call-ID, From and To URIs and the user agent.
+//! An SDP body gives the address and port the sender receives the call's
+//! audio on, which pairs the RTP stream with the call. Only a message
+//! starting the datagram or segment is read.
+
+use std::net::Ipv4Addr;
+
+pub use netsentinel_types::{SipInfo, SipMedia};
+
+/// Longest value kept from a header
+const MAX_FIELD_LEN: usize = 256;
+
+/// Truncate `value` to [`MAX_FIELD_LEN`] bytes, on a character boundary
+fn clip(value: &str) -> String {
+    let mut end = value.len().min(MAX_FIELD_LEN);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+/// URI of a From or To header: between angle brackets when there is a
+/// display name, before the parameters otherwise
+fn uri(value: &str) -> &str {
+    match (value.find('<'), value.find('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value.split(';').next().unwrap_or_default().trim(),
+    }
+}
+
+/// RTP endpoint of the first audio stream of an SDP body, or of its first
+/// stream without audio
+fn sdp_media(body: &str) -> Option<SipMedia> {
+    let mut session = None;
+    let mut media: Option<(u16, Option<Ipv4Addr>, bool)> = None;
+
+    for line in body.lines().map(str::trim_end) {
+        if let Some(connection) = line.strip_prefix("c=IN IP4 ") {
+            // Multicast connections carry a TTL
+            let ip = connection.split('/').next().and_then(|ip| ip.trim().parse().ok());
+            match media.as_mut() {
+                Some((_, media_ip, _)) => *media_ip = ip.or(*media_ip),
+                None => session = ip,
+            }
+        } else if let Some(description) = line.strip_prefix("m=") {
+            if media.is_some_and(|(_, _, audio)| audio) {
+                break;
+            }
+            let mut fields = description.split_whitespace();
+            let audio = fields.next() == Some("audio");
+            // Port 0: stream rejected
+            if let Some(port) = fields.next().and_then(|port| port.parse().ok()).filter(|port| *port != 0) {
+                if media.is_none() || audio {
+                    media = Some((port, None, audio));
+                }
+            }
+        }
+    }
+
+    let (port, ip, _) = media?;
+    Some(SipMedia { ip: ip.or(session)?, port })
+}
+
+/// Parse a SIP message from a UDP datagram or TCP segment
+pub fn parse_sip(data: &[u8]) -> Option<SipInfo> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+    };
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((text, ""));
+    let mut lines = head.split("\r\n");
+
+    let start = lines.next()?;
+    let (mut method, status) = match start.strip_prefix("SIP/2.0 ") {
+        Some(status) => (None, Some(status.get(..3)?.parse::<u16>().ok()?)),
+        None => {
+            let (method, rest) = start.split_once(' ')?;
+            if !rest.ends_with(" SIP/2.0") || method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+                return None;
+            }
+            (Some(method.to_string()), None)
+        }
+    };
+
+    let (mut call_id, mut from, mut to, mut user_agent, mut sdp) = (None, None, None, None, false);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        // Long names, and the compact forms of RFC 3261
+        match name.trim().to_ascii_lowercase().as_str() {
+            "call-id" | "i" => call_id = Some(clip(value)),
+            "from" | "f" => from = Some(clip(uri(value))),
+            "to" | "t" => to = Some(clip(uri(value))),
+            "user-agent" | "server" => user_agent = Some(clip(value)),
+            "content-type" | "c" => sdp = value.to_ascii_lowercase().starts_with("application/sdp"),
+            // A response answers the request of its CSeq
+            "cseq" if method.is_none() => method = value.split_whitespace().nth(1).map(clip),
+            _ => {}
+        }
+    }
+
+    Some(SipInfo {
+        method: method?,
+        status,
+        call_id: call_id?,
+        from: from?,
+        to: to?,
+        user_agent,
+        media: if sdp { sdp_media(body) } else { None },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "INVITE sip:1002@pbx.example.com SIP/2.0\r\n\
+        Via: SIP/2.0/UDP 10.1.0.21:5060;branch=z9hG4bK776asdhds\r\n\
+        From: \"Alice\" <sip:1001@pbx.example.com>;tag=1928301774\r\n\
+        To: <sip:1002@pbx.example.com>\r\n\
+        Call-ID: a84b4c76e66710@10.1.0.21\r\n\
+        CSeq: 314159 INVITE\r\n\
+        User-Agent: Yealink SIP-T46U 108.86.0.20\r\n\
+        Content-Type: application/sdp\r\n\
+        Content-Length: 142\r\n\
+        \r\n\
+        v=0\r\n\
+        o=alice 2890844526 2890844526 IN IP4 10.1.0.21\r\n\
+        c=IN IP4 10.1.0.21\r\n\
+        t=0 0\r\n\
+        m=video 0 RTP/AVP 96\r\n\
+        m=audio 49170 RTP/AVP 0 8\r\n";
+
+    #[test]
+    fn test_parse_sip() {
+        let invite = parse_sip(INVITE.as_bytes()).unwrap();
+        assert_eq!((invite.method.as_str(), invite.status), ("INVITE", None));
+        assert_eq!(invite.call_id, "a84b4c76e66710@10.1.0.21");
+        assert_eq!((invite.from.as_str(), invite.to.as_str()), ("sip:1001@pbx.example.com", "sip:1002@pbx.example.com"));
+        assert_eq!(invite.user_agent.as_deref(), Some("Yealink SIP-T46U 108.86.0.20"));
+        assert_eq!(invite.media, Some(SipMedia { ip: Ipv4Addr::new(10, 1, 0, 21), port: 49170 }));
+
+        // Compact headers, the method of a response from its CSeq
+        let ok = "SIP/2.0 200 OK\r\ni: a84b4c76e66710@10.1.0.21\r\nf: sip:1001@pbx.example.com;tag=1\r\n\
+            t: sip:1002@pbx.example.com;tag=2\r\nCSeq: 314159 INVITE\r\nc: application/sdp\r\n\r\n\
+            c=IN IP4 10.1.0.1\r\nm=audio 10000 RTP/AVP 0\r\nc=IN IP4 10.1.0.22\r\n";
+        let ok = parse_sip(ok.as_bytes()).unwrap();
+        assert_eq!((ok.method.as_str(), ok.status), ("INVITE", Some(200)));
+        assert_eq!(ok.from, "sip:1001@pbx.example.com");
+        assert_eq!(ok.media, Some(SipMedia { ip: Ipv4Addr::new(10, 1, 0, 22), port: 10000 }));
+
+        // Missing dialog headers, another protocol on the port
+        assert!(parse_sip(b"OPTIONS sip:pbx SIP/2.0\r\nCSeq: 1 OPTIONS\r\n\r\n").is_none());
+        assert!(parse_sip(b"GET / HTTP/1.1\r\nHost: pbx\r\n\r\n").is_none());
+    }
+}
@@ -0,0 +1,89 @@
+//! SNMP message parsing
+//!
+//! Reads the version of SNMP messages and, for v1 and v2c, the type of
+//! their PDU. Those versions authenticate with a community string sent in
+//! the clear, which the aggregator flags; the string itself is skipped,
+//! never copied out of the capture.
+
+pub use netsentinel_types::SnmpInfo;
+
+/// BER tags
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+
+/// Context-specific constructed tags of the PDUs, the type in the low bits
+const PDU_CLASS: u8 = 0xA0;
+
+/// Tag of the BER element at `pos`, where its content starts and its length
+fn element(data: &[u8], pos: usize) -> Option<(u8, usize, usize)> {
+    let tag = *data.get(pos)?;
+    let first = *data.get(pos + 1)? as usize;
+    let (start, length) = if first < 0x80 {
+        (pos + 2, first)
+    } else {
+        // Long form: the length in the next 1 to 4 bytes
+        let count = first & 0x7F;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(pos + 2..pos + 2 + count)?;
+        (pos + 2 + count, bytes.iter().fold(0, |length, &b| (length << 8) | b as usize))
+    };
+    Some((tag, start, length))
+}
+
+/// Parse an SNMP message from a UDP payload
+pub fn parse_snmp(data: &[u8]) -> Option<SnmpInfo> {
+    let (tag, start, _) = element(data, 0)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, start, length) = element(data, start)?;
+    if tag != INTEGER || length != 1 {
+        return None;
+    }
+    let version = *data.get(start)?;
+    if version > SnmpInfo::V3 {
+        return None;
+    }
+    if version == SnmpInfo::V3 {
+        return Some(SnmpInfo { version, pdu: None });
+    }
+
+    let (tag, start, length) = element(data, start + 1)?;
+    if tag != OCTET_STRING {
+        return None;
+    }
+    let (tag, _, _) = element(data, start + length)?;
+    if tag & 0xE0 != PDU_CLASS {
+        return None;
+    }
+    Some(SnmpInfo { version, pdu: Some(tag & 0x1F) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_snmp() {
+        // v2c get-request of sysDescr.0 with community "public"
+        let get = [
+            0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c',
+            0xa0, 0x1c, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00,
+            0x30, 0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+        ];
+        let info = parse_snmp(&get).unwrap();
+        assert_eq!(info, SnmpInfo { version: SnmpInfo::V2C, pdu: Some(0) });
+        assert!(info.uses_community());
+
+        // v3 header, long-form length
+        let v3 = [0x30, 0x81, 0x10, 0x02, 0x01, 0x03, 0x30, 0x0d];
+        let info = parse_snmp(&v3).unwrap();
+        assert_eq!((info.version, info.pdu, info.uses_community()), (SnmpInfo::V3, None, false));
+
+        assert!(parse_snmp(&get[..13]).is_none());
+        assert!(parse_snmp(b"not snmp").is_none());
+    }
+}
@@ -0,0 +1,462 @@
+//! TLS handshake metadata
+//!
+//! Extracts the server name (SNI) and JA3 fingerprint from ClientHello
+//! messages, the JA3S fingerprint from ServerHello messages, and the leaf
+//! certificate from Certificate messages (TLS 1.2 and earlier; TLS 1.3
+//! encrypts certificates). Without reassembly, only what a single TCP
+//! segment holds is parsed: a certificate split across segments is skipped.
+//! Reassembled streams are parsed record by record, handshake messages
+//! spanning records included.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// TLS record content type for handshake messages
+const CONTENT_HANDSHAKE: u8 = 0x16;
+
+/// Handshake message types
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+
+/// Extensions read from the hellos
+const EXTENSION_SERVER_NAME: u16 = 0;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 10;
+const EXTENSION_EC_POINT_FORMATS: u16 = 11;
+
+/// DER tags
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_VERSION: u8 = 0xa0;
+
+/// OID 2.5.4.3 (commonName)
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+pub use netsentinel_types::{CertificateInfo, TlsInfo};
+
+/// Parse the TLS records at the start of a TCP payload
+pub fn parse_tls(payload: &[u8]) -> Option<TlsInfo> {
+    if payload.first() != Some(&CONTENT_HANDSHAKE) {
+        return None;
+    }
+
+    let mut info = TlsInfo::default();
+    let mut records = payload;
+    while records.len() >= 5 && records[0] == CONTENT_HANDSHAKE && records[1] == 0x03 {
+        let length = u16::from_be_bytes([records[3], records[4]]) as usize;
+        // Handshake messages are parsed as far as the segment goes
+        let end = (5 + length).min(records.len());
+        parse_handshakes(&records[5..end], &mut info);
+        records = &records[end..];
+    }
+
+    (info != TlsInfo::default()).then_some(info)
+}
+
+/// Handshake metadata read from the start of a reassembled stream
+#[derive(Debug, Default)]
+pub struct TlsStream {
+    pub info: Option<TlsInfo>,
+    /// Bytes of the records parsed, to consume
+    pub length: usize,
+    /// Whether the handshake is over in clear: encrypted, or not TLS
+    pub done: bool,
+}
+
+/// Parse the complete handshake records at the start of a reassembled stream
+///
+/// Records holding the start of a message are left for the next call, once
+/// the records holding its end have arrived.
+pub fn parse_tls_stream(stream: &[u8]) -> TlsStream {
+    let mut parsed = TlsStream::default();
+    let mut info = TlsInfo::default();
+    let mut messages = Vec::new();
+    let mut offset = 0;
+
+    while let Some(header) = stream.get(offset..offset + 5) {
+        if header[0] != CONTENT_HANDSHAKE || header[1] != 0x03 {
+            // Past the hellos and certificates, records are encrypted
+            parsed.done = true;
+            break;
+        }
+        let end = offset + 5 + u16::from_be_bytes([header[3], header[4]]) as usize;
+        let Some(body) = stream.get(offset + 5..end) else { break };
+        messages.extend_from_slice(body);
+        offset = end;
+
+        // Parse once the records end on a message boundary
+        if complete_messages(&messages) {
+            parse_handshakes(&messages, &mut info);
+            messages.clear();
+            parsed.length = offset;
+        }
+    }
+
+    parsed.info = (info != TlsInfo::default()).then_some(info);
+    parsed
+}
+
+/// Whether `data` holds whole handshake messages only
+fn complete_messages(mut data: &[u8]) -> bool {
+    while data.len() >= 4 {
+        let length = 4 + u24(&data[1..4]);
+        if data.len() < length {
+            return false;
+        }
+        data = &data[length..];
+    }
+    data.is_empty()
+}
+
+fn parse_handshakes(mut data: &[u8], info: &mut TlsInfo) {
+    while data.len() >= 4 {
+        let kind = data[0];
+        let length = u24(&data[1..4]);
+        let body = &data[4..];
+        let complete = body.len() >= length;
+        let body = &body[..length.min(body.len())];
+
+        match kind {
+            CLIENT_HELLO if complete => {
+                parse_client_hello(body, info);
+            }
+            SERVER_HELLO if complete => info.ja3s = server_hello_ja3s(body),
+            CERTIFICATE => info.certificate = leaf_certificate(body),
+            _ => {}
+        }
+        if !complete {
+            return;
+        }
+        data = &data[4 + length..];
+    }
+}
+
+fn u24(bytes: &[u8]) -> usize {
+    (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize
+}
+
+/// Split `len` bytes off the front of `data`
+fn take(data: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    (data.len() >= len).then(|| data.split_at(len))
+}
+
+/// Read a big-endian u16 length and split that many bytes off
+fn take_u16_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (length, rest) = take(data, 2)?;
+    take(rest, u16::from_be_bytes([length[0], length[1]]) as usize)
+}
+
+/// Big-endian u16 values of a list
+fn u16_values(data: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    data.chunks_exact(2).map(|v| u16::from_be_bytes([v[0], v[1]]))
+}
+
+/// GREASE values (RFC 8701) are left out of JA3 fingerprints
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Values joined with `-`, GREASE left out
+fn ja3_list(values: impl Iterator<Item = u16>) -> String {
+    values.filter(|v| !is_grease(*v)).map(|v| v.to_string()).collect::<Vec<_>>().join("-")
+}
+
+fn md5_hex(text: &str) -> String {
+    Md5::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Extension type and data of each extension in a block
+fn extensions(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let (header, rest) = take(data, 4)?;
+        let (extension, rest) = take(rest, u16::from_be_bytes([header[2], header[3]]) as usize)?;
+        data = rest;
+        Some((u16::from_be_bytes([header[0], header[1]]), extension))
+    })
+}
+
+/// Server name and JA3 fingerprint of a ClientHello
+fn parse_client_hello(body: &[u8], info: &mut TlsInfo) -> Option<()> {
+    let (version, rest) = take(body, 2)?;
+    let (_, rest) = take(rest, 32)?; // random
+    let (session_len, rest) = take(rest, 1)?;
+    let (_, rest) = take(rest, session_len[0] as usize)?;
+    let (suites, rest) = take_u16_prefixed(rest)?;
+    let (compression_len, rest) = take(rest, 1)?;
+    let (_, rest) = take(rest, compression_len[0] as usize)?;
+    // Hellos without extensions are valid
+    let block = take_u16_prefixed(rest).map_or(&[][..], |(block, _)| block);
+
+    let mut types = Vec::new();
+    let mut groups = String::new();
+    let mut point_formats = String::new();
+    for (kind, extension) in extensions(block) {
+        types.push(kind);
+        match kind {
+            EXTENSION_SERVER_NAME => info.sni = server_name(extension),
+            EXTENSION_SUPPORTED_GROUPS => {
+                groups = take_u16_prefixed(extension).map_or_else(String::new, |(list, _)| ja3_list(u16_values(list)));
+            }
+            EXTENSION_EC_POINT_FORMATS => {
+                point_formats = take(extension, 1)
+                    .and_then(|(length, rest)| take(rest, length[0] as usize))
+                    .map_or_else(String::new, |(list, _)| ja3_list(list.iter().map(|f| *f as u16)));
+            }
+            _ => {}
+        }
+    }
+
+    let ja3 = format!(
+        "{},{},{},{},{}",
+        u16::from_be_bytes([version[0], version[1]]),
+        ja3_list(u16_values(suites)),
+        ja3_list(types.into_iter()),
+        groups,
+        point_formats,
+    );
+    info.ja3 = Some(md5_hex(&ja3));
+    Some(())
+}
+
+/// Host name of a server_name extension
+fn server_name(extension: &[u8]) -> Option<String> {
+    // List length, then entries of type (0: host name) and length
+    let (_, list) = take(extension, 2)?;
+    let (header, rest) = take(list, 3)?;
+    let (name, _) = take(rest, u16::from_be_bytes([header[1], header[2]]) as usize)?;
+    if header[0] != 0 {
+        return None;
+    }
+    let name = std::str::from_utf8(name).ok()?;
+    Some(name.to_ascii_lowercase())
+}
+
+/// JA3S fingerprint of a ServerHello
+fn server_hello_ja3s(body: &[u8]) -> Option<String> {
+    let (version, rest) = take(body, 2)?;
+    let (_, rest) = take(rest, 32)?; // random
+    let (session_len, rest) = take(rest, 1)?;
+    let (_, rest) = take(rest, session_len[0] as usize)?;
+    let (cipher, rest) = take(rest, 2)?;
+    let (_, rest) = take(rest, 1)?; // compression
+    let block = take_u16_prefixed(rest).map_or(&[][..], |(block, _)| block);
+
+    let ja3s = format!(
+        "{},{},{}",
+        u16::from_be_bytes([version[0], version[1]]),
+        u16::from_be_bytes([cipher[0], cipher[1]]),
+        ja3_list(extensions(block).map(|(kind, _)| kind)),
+    );
+    Some(md5_hex(&ja3s))
+}
+
+fn leaf_certificate(body: &[u8]) -> Option<CertificateInfo> {
+    let (_, rest) = take(body, 3)?;
+    let (length, rest) = take(rest, 3)?;
+    let (der, _) = take(rest, u24(length))?;
+    parse_certificate(der)
+}
+
+/// One DER element: tag, contents and what follows
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first & 0x80 == 0 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let (bytes, rest) = take(rest, count)?;
+        (bytes.iter().fold(0usize, |n, b| n << 8 | *b as usize), rest)
+    };
+    let (contents, rest) = take(rest, length)?;
+    Some((tag, contents, rest))
+}
+
+/// Leaf certificate fingerprint, names and validity
+pub fn parse_certificate(der: &[u8]) -> Option<CertificateInfo> {
+    let (TAG_SEQUENCE, certificate, _) = read_tlv(der)? else { return None };
+    let (TAG_SEQUENCE, tbs, _) = read_tlv(certificate)? else { return None };
+
+    let (tag, _, mut rest) = read_tlv(tbs)?;
+    if tag == TAG_VERSION {
+        // Serial number
+        (_, _, rest) = read_tlv(rest)?;
+    }
+    let (_, _, rest) = read_tlv(rest)?; // signature algorithm
+    let (TAG_SEQUENCE, issuer, rest) = read_tlv(rest)? else { return None };
+    let (TAG_SEQUENCE, validity, rest) = read_tlv(rest)? else { return None };
+    let (TAG_SEQUENCE, subject, _) = read_tlv(rest)? else { return None };
+
+    let (before_tag, not_before, rest) = read_tlv(validity)?;
+    let (after_tag, not_after, _) = read_tlv(rest)?;
+
+    Some(CertificateInfo {
+        sha256: Sha256::digest(der).iter().map(|b| format!("{:02x}", b)).collect(),
+        subject: common_name(subject),
+        issuer: common_name(issuer),
+        not_before: parse_time(before_tag, not_before)?,
+        not_after: parse_time(after_tag, not_after)?,
+    })
+}
+
+/// Common name of an X.509 name, if any
+fn common_name(mut name: &[u8]) -> Option<String> {
+    while let Some((TAG_SET, set, rest)) = read_tlv(name) {
+        if let Some((TAG_SEQUENCE, attribute, _)) = read_tlv(set) {
+            if let Some((TAG_OID, OID_COMMON_NAME, value)) = read_tlv(attribute) {
+                let (_, value, _) = read_tlv(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+        name = rest;
+    }
+    None
+}
+
+/// UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`)
+fn parse_time(tag: u8, value: &[u8]) -> Option<DateTime<Utc>> {
+    let text = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        TAG_UTC_TIME => {
+            let year: i32 = text.get(..2)?.parse().ok()?;
+            // RFC 5280: two-digit years 50-99 are 19xx
+            (if year >= 50 { 1900 + year } else { 2000 + year }, text.get(2..)?)
+        }
+        TAG_GENERALIZED_TIME => (text.get(..4)?.parse().ok()?, text.get(4..)?),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<u32> { rest.get(i..i + 2)?.parse().ok() };
+
+    NaiveDate::from_ymd_opt(year, field(0)?, field(2)?)?
+        .and_hms_opt(field(4)?, field(6)?, field(8)?)
+        .map(|t| t.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DER element with a short-form length
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag, contents.len() as u8];
+        out.extend_from_slice(contents);
+        out
+    }
+
+    fn name(cn: &str) -> Vec<u8> {
+        let attribute = [tlv(TAG_OID, OID_COMMON_NAME), tlv(0x0c, cn.as_bytes())].concat();
+        tlv(TAG_SEQUENCE, &tlv(TAG_SET, &tlv(TAG_SEQUENCE, &attribute)))
+    }
+
+    fn certificate() -> Vec<u8> {
+        let tbs = [
+            tlv(TAG_VERSION, &tlv(0x02, &[2])),
+            tlv(0x02, &[0x01, 0x23]),
+            tlv(TAG_SEQUENCE, &tlv(TAG_OID, &[0x2a, 0x86, 0x48])),
+            name("Example CA"),
+            tlv(TAG_SEQUENCE, &[
+                tlv(TAG_UTC_TIME, b"240101000000Z"),
+                tlv(TAG_GENERALIZED_TIME, b"20250101120000Z"),
+            ].concat()),
+            name("app.example.com"),
+        ].concat();
+        tlv(TAG_SEQUENCE, &tlv(TAG_SEQUENCE, &tbs))
+    }
+
+    /// Handshake record holding one message
+    fn record(kind: u8, body: &[u8]) -> Vec<u8> {
+        let length = body.len() as u32;
+        let mut message = vec![kind, (length >> 16) as u8, (length >> 8) as u8, length as u8];
+        message.extend_from_slice(body);
+        let mut out = vec![CONTENT_HANDSHAKE, 0x03, 0x03];
+        out.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        out.extend_from_slice(&message);
+        out
+    }
+
+    #[test]
+    fn test_client_hello_sni() {
+        let host = b"Files.Example.com";
+        let mut server_name = vec![0x00, (host.len() + 3) as u8, 0x00, 0x00, host.len() as u8];
+        server_name.extend_from_slice(host);
+        let mut extensions = vec![
+            0x1a, 0x1a, 0x00, 0x00, // GREASE
+            0x00, 0x0b, 0x00, 0x02, 0x01, 0x00, // ec_point_formats
+            0x00, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x1d, 0x00, 0x17, // supported_groups
+        ];
+        extensions.extend_from_slice(&[0x00, 0x00, 0x00, server_name.len() as u8]);
+        extensions.extend_from_slice(&server_name);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        // No session ID, GREASE and TLS_AES_128_GCM_SHA256, null compression
+        hello.extend_from_slice(&[0x00, 0x00, 0x04, 0x0a, 0x0a, 0x13, 0x01, 0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let info = parse_tls(&record(CLIENT_HELLO, &hello)).unwrap();
+        assert_eq!(info.sni.as_deref(), Some("files.example.com"));
+        // 771,4865,11-10-0,29-23,0
+        assert_eq!(info.ja3.as_deref(), Some("519556fcb9b7c76fcbfaf1d5916417f3"));
+        assert!(parse_tls(b"GET / HTTP/1.1\r\n").is_none());
+    }
+
+    #[test]
+    fn test_certificate() {
+        let der = certificate();
+        let length = der.len() as u32;
+        let mut body = vec![0x00, 0x00, (length + 3) as u8, 0x00, (length >> 8) as u8, length as u8];
+        body.extend_from_slice(&der);
+
+        let mut server_hello = vec![0x03, 0x03];
+        server_hello.extend_from_slice(&[0; 32]);
+        // No session ID, TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, null compression
+        server_hello.extend_from_slice(&[0x00, 0xc0, 0x2f, 0x00, 0x00, 0x0b]);
+        server_hello.extend_from_slice(&[0xff, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0b, 0x00, 0x02, 0x01, 0x00]);
+
+        // ServerHello and Certificate in the same segment
+        let payload = [record(SERVER_HELLO, &server_hello), record(CERTIFICATE, &body)].concat();
+        let info = parse_tls(&payload).unwrap();
+        // 771,49199,65281-11
+        assert_eq!(info.ja3s.as_deref(), Some("303951d4c50efb2e991652225a6f02b1"));
+        let certificate = info.certificate.unwrap();
+        assert_eq!(certificate.subject.as_deref(), Some("app.example.com"));
+        assert_eq!(certificate.issuer.as_deref(), Some("Example CA"));
+        assert_eq!(certificate.not_before.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(certificate.not_after.to_rfc3339(), "2025-01-01T12:00:00+00:00");
+        assert_eq!(certificate.sha256.len(), 64);
+
+        // Cut off mid-certificate
+        assert!(parse_tls(&payload[..payload.len() - 10]).unwrap().certificate.is_none());
+    }
+
+    #[test]
+    fn test_stream_across_records() {
+        let der = certificate();
+        let length = der.len() as u32;
+        let mut body = vec![0x00, 0x00, (length + 3) as u8, 0x00, (length >> 8) as u8, length as u8];
+        body.extend_from_slice(&der);
+
+        // The Certificate message split over two records
+        let message = &record(CERTIFICATE, &body)[5..];
+        let (first, second) = message.split_at(20);
+        let fragment = |data: &[u8]| [&[CONTENT_HANDSHAKE, 0x03, 0x03][..], &(data.len() as u16).to_be_bytes(), data].concat();
+        let stream = [fragment(first), fragment(second), vec![0x17, 0x03, 0x03, 0x00, 0x10]].concat();
+
+        // Its first record alone: nothing to consume yet
+        let partial = parse_tls_stream(&stream[..first.len() + 5]);
+        assert!(partial.info.is_none() && partial.length == 0 && !partial.done);
+
+        let parsed = parse_tls_stream(&stream);
+        assert_eq!(parsed.info.unwrap().certificate.unwrap().subject.as_deref(), Some("app.example.com"));
+        assert_eq!(parsed.length, stream.len() - 5);
+        assert!(parsed.done);
+    }
+}
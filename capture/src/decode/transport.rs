@@ -1,9 +1,25 @@
-//! Transport layer (TCP/UDP) parsing
+//! Transport layer (TCP/UDP/ICMP) parsing
 
 use anyhow::{Result, bail};
-use crate::capture::frame::TcpFlags;
+use netsentinel_types::{ServiceNames, TcpFlags};
+use std::sync::OnceLock;
 use super::ipv4::protocol;
 
+pub use netsentinel_types::service::{ports, service_name};
+
+/// Service names of the configuration, set once at startup
+static SERVICES: OnceLock<ServiceNames> = OnceLock::new();
+
+/// Name ports with `services` (`[services]`), once at startup before decoding
+pub fn set_services(services: ServiceNames) {
+    let _ = SERVICES.set(services);
+}
+
+/// Service names of ports: the configured ones, then the well-known ones
+pub fn services() -> &'static ServiceNames {
+    SERVICES.get_or_init(ServiceNames::default)
+}
+
 /// Parsed transport layer information
 #[derive(Debug, Clone)]
 pub struct TransportInfo {
@@ -19,87 +35,45 @@ pub struct TransportInfo {
     pub tcp_ack: Option<u32>,
     /// TCP window size (if TCP)
     pub tcp_window: Option<u16>,
+    /// ICMP message type (if ICMP)
+    pub icmp_type: Option<u8>,
+    /// ICMP message code (if ICMP)
+    pub icmp_code: Option<u8>,
+    /// ICMP echo identifier (if echo request or reply)
+    pub icmp_id: Option<u16>,
     /// Payload size after transport header
     pub payload_size: u32,
 }
 
-/// Well-known port numbers
-pub mod ports {
-    pub const FTP_DATA: u16 = 20;
-    pub const FTP: u16 = 21;
-    pub const SSH: u16 = 22;
-    pub const TELNET: u16 = 23;
-    pub const SMTP: u16 = 25;
-    pub const DNS: u16 = 53;
-    pub const DHCP_SERVER: u16 = 67;
-    pub const DHCP_CLIENT: u16 = 68;
-    pub const HTTP: u16 = 80;
-    pub const KERBEROS: u16 = 88;
-    pub const POP3: u16 = 110;
-    pub const NTP: u16 = 123;
-    pub const NETBIOS_NS: u16 = 137;
-    pub const NETBIOS_DGM: u16 = 138;
-    pub const NETBIOS_SSN: u16 = 139;
-    pub const IMAP: u16 = 143;
-    pub const SNMP: u16 = 161;
-    pub const SNMP_TRAP: u16 = 162;
-    pub const LDAP: u16 = 389;
-    pub const HTTPS: u16 = 443;
-    pub const SMB: u16 = 445;
-    pub const LDAPS: u16 = 636;
-    pub const IMAPS: u16 = 993;
-    pub const MYSQL: u16 = 3306;
-    pub const RDP: u16 = 3389;
-    pub const POSTGRESQL: u16 = 5432;
-    pub const REDIS: u16 = 6379;
-    pub const HTTP_ALT: u16 = 8080;
-    pub const HTTPS_ALT: u16 = 8443;
-}
-
-/// Get service name from port number
-pub fn service_name(port: u16) -> Option<&'static str> {
-    match port {
-        ports::FTP_DATA => Some("ftp-data"),
-        ports::FTP => Some("ftp"),
-        ports::SSH => Some("ssh"),
-        ports::TELNET => Some("telnet"),
-        ports::SMTP => Some("smtp"),
-        ports::DNS => Some("dns"),
-        ports::DHCP_SERVER | ports::DHCP_CLIENT => Some("dhcp"),
-        ports::HTTP | ports::HTTP_ALT => Some("http"),
-        ports::KERBEROS => Some("kerberos"),
-        ports::POP3 => Some("pop3"),
-        ports::NTP => Some("ntp"),
-        ports::NETBIOS_NS | ports::NETBIOS_DGM | ports::NETBIOS_SSN => Some("netbios"),
-        ports::IMAP => Some("imap"),
-        ports::SNMP | ports::SNMP_TRAP => Some("snmp"),
-        ports::LDAP => Some("ldap"),
-        ports::HTTPS | ports::HTTPS_ALT => Some("https"),
-        ports::SMB => Some("smb"),
-        ports::LDAPS => Some("ldaps"),
-        ports::IMAPS => Some("imaps"),
-        ports::MYSQL => Some("mysql"),
-        ports::RDP => Some("rdp"),
-        ports::POSTGRESQL => Some("postgresql"),
-        ports::REDIS => Some("redis"),
-        _ => None,
-    }
-}
-
 /// Parse transport layer header
 pub fn parse_transport(ip_protocol: u8, data: &[u8]) -> Result<TransportInfo> {
     match ip_protocol {
         protocol::TCP => parse_tcp(data),
         protocol::UDP => parse_udp(data),
+        protocol::ICMP => parse_icmp(data, [ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY]),
+        protocol::ICMPV6 => parse_icmp(data, [ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY]),
         _ => Ok(TransportInfo {
+            payload_size: data.len() as u32,
+            ..TransportInfo::empty()
+        }),
+    }
+}
+
+impl TransportInfo {
+    /// No transport header fields, no payload
+    fn empty() -> Self {
+        Self {
             src_port: None,
             dst_port: None,
             tcp_flags: None,
             tcp_seq: None,
             tcp_ack: None,
             tcp_window: None,
-            payload_size: data.len() as u32,
-        }),
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+            payload_size: 0,
+        }
     }
 }
 
@@ -157,6 +131,7 @@ fn parse_tcp(data: &[u8]) -> Result<TransportInfo> {
         tcp_ack: Some(ack),
         tcp_window: Some(window),
         payload_size,
+        ..TransportInfo::empty()
     })
 }
 
@@ -182,16 +157,53 @@ fn parse_udp(data: &[u8]) -> Result<TransportInfo> {
     let length = u16::from_be_bytes([data[4], data[5]]);
 
     // UDP length includes header (8 bytes)
-    let payload_size = if length > 8 { length - 8 } else { 0 };
+    let payload_size = length.saturating_sub(8);
 
     Ok(TransportInfo {
         src_port: Some(src_port),
         dst_port: Some(dst_port),
-        tcp_flags: None,
-        tcp_seq: None,
-        tcp_ack: None,
-        tcp_window: None,
         payload_size: payload_size as u32,
+        ..TransportInfo::empty()
+    })
+}
+
+/// ICMP and ICMPv6 echo reply and echo request types, whose identifier
+/// tells ping sessions apart
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Parse ICMP or ICMPv6 header, `echo` its echo request and reply types
+///
+/// ICMP has no ports: the type and code, and for echo messages the
+/// identifier, tell exchanges between two hosts apart.
+///
+/// ICMP header format:
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |     Type      |     Code      |          Checksum             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |   Identifier (echo)           |   Sequence Number (echo)      |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+fn parse_icmp(data: &[u8], echo: [u8; 2]) -> Result<TransportInfo> {
+    if data.len() < 8 {
+        bail!("Data too short for ICMP header: {} bytes (minimum 8)", data.len());
+    }
+
+    let icmp_type = data[0];
+    let icmp_id = echo.contains(&icmp_type)
+        .then(|| u16::from_be_bytes([data[4], data[5]]));
+
+    Ok(TransportInfo {
+        icmp_type: Some(icmp_type),
+        icmp_code: Some(data[1]),
+        icmp_id,
+        payload_size: (data.len() - 8) as u32,
+        ..TransportInfo::empty()
     })
 }
 
@@ -242,12 +254,22 @@ mod tests {
     }
 
     #[test]
-    fn test_service_names() {
-        assert_eq!(service_name(80), Some("http"));
-        assert_eq!(service_name(443), Some("https"));
-        assert_eq!(service_name(22), Some("ssh"));
-        assert_eq!(service_name(53), Some("dns"));
-        assert_eq!(service_name(12345), None);
+    fn test_parse_icmp_header() {
+        // Echo request, identifier 0x1234, sequence 1
+        let mut data = vec![0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01];
+        data.extend_from_slice(&[0; 56]);
+
+        let info = parse_transport(protocol::ICMP, &data).unwrap();
+        assert_eq!((info.icmp_type, info.icmp_code, info.icmp_id), (Some(8), Some(0), Some(0x1234)));
+        assert!(info.src_port.is_none() && info.dst_port.is_none());
+        assert_eq!(info.payload_size, 56);
+
+        // Port unreachable: no identifier
+        let data = [0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let info = parse_transport(protocol::ICMP, &data).unwrap();
+        assert_eq!((info.icmp_type, info.icmp_code, info.icmp_id), (Some(3), Some(3), None));
+
+        assert!(parse_transport(protocol::ICMP, &data[..4]).is_err());
     }
 
     #[test]
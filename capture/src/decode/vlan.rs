@@ -1,7 +1,7 @@
 //! VLAN tag parsing (802.1Q and 802.1ad)
 
 use anyhow::{Result, bail};
-use crate::capture::frame::{VlanInfo, QinQInfo};
+use netsentinel_types::{VlanInfo, QinQInfo};
 
 /// Parse a single VLAN tag (802.1Q)
 ///
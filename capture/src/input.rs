@@ -0,0 +1,228 @@
+//! Frame input
+//!
+//! Starts what the configuration asks for, packet capture on the configured
+//! interfaces or the flow export listeners, and sends every frame on one
+//! channel. The capture binary publishes that channel to Redis; the
+//! all-in-one binary hands it straight to the aggregator. When scripting
+//! hooks are enabled, frames go through them on a thread of their own first.
+
+use anyhow::{Context, Result};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::autotune::{TunedSender, Tunables};
+use crate::capture::{filter, netns};
+use crate::capture::{AfPacketCapture, CaptureStats, CaptureStatsSnapshot, CapturedFrame, MultiCapture, Policy};
+use crate::collector::FlowCollector;
+use crate::config::{Config, InterfaceConfig};
+use crate::decode::erspan::ErspanSessions;
+use crate::decode::transport;
+use crate::metrics::{metrics, Channel};
+use crate::script::{ScriptHook, ScriptStats};
+
+/// Running capture or flow listeners
+pub struct Input {
+    capture: MultiCapture,
+    threads: Vec<std::thread::JoinHandle<()>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+    stats: Vec<(String, Arc<CaptureStats>)>,
+    script_stats: Option<Arc<ScriptStats>>,
+    /// Protocol policy of the captures, if any
+    policy: Option<Arc<Policy>>,
+}
+
+impl Input {
+    /// Start the input of `config`, sending frames on `frame_tx`
+    ///
+    /// With `tuning`, captures hold the channel they send to (`frame_tx`, or
+    /// the scripting hooks') to the tuned ring buffer size.
+    pub fn start(
+        config: &Config,
+        frame_tx: mpsc::Sender<CapturedFrame>,
+        tuning: Option<Arc<Tunables>>,
+    ) -> Result<Self> {
+        let mut capture = MultiCapture::new();
+        transport::set_services(config.services.clone());
+
+        // Run the scripting hooks between the input and `frame_tx`
+        let (frame_tx, script_stats) = if config.script.enabled {
+            let hook = ScriptHook::new(&config.script)?;
+            let stats = hook.stats();
+            let (script_tx, mut script_rx) = mpsc::channel::<CapturedFrame>(config.capture.ring_buffer_capacity());
+            metrics().open_channel(Channel::Script, config.capture.ring_buffer_size);
+            std::thread::Builder::new()
+                .name("script-hooks".to_string())
+                .spawn(move || {
+                    while let Some(frame) = script_rx.blocking_recv() {
+                        metrics().sample_channel(Channel::Script, script_rx.len());
+                        let Some(frame) = hook.apply(frame) else { continue };
+                        if frame_tx.blocking_send(frame).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .with_context(|| "Failed to start the script hooks thread")?;
+            info!("Running {} frame script(s)", config.script.files.len());
+            (script_tx, Some(stats))
+        } else {
+            (frame_tx, None)
+        };
+
+        if config.capture.mode == "flow" {
+            let collector = FlowCollector::new(config.flow_input.clone());
+            let stats = collector.stats();
+            let task = tokio::spawn(async move {
+                if let Err(e) = collector.run(frame_tx).await {
+                    error!("Flow collector error: {}", e);
+                }
+            });
+            return Ok(Self { capture, threads: Vec::new(), task: Some(task), stats, script_stats, policy: None });
+        }
+
+        let erspan = Arc::new(ErspanSessions::new(&config.capture.erspan_sessions));
+        let policy = (!config.capture.policy.is_empty()).then(|| Arc::new(Policy::new(&config.capture.policy)));
+        for iface in &config.capture.interfaces {
+            let program = iface
+                .filter(&config.capture)
+                .map(filter::compile)
+                .transpose()
+                .with_context(|| format!("Invalid filter of interface '{}'", iface.name))?;
+            let snap_length = iface.snap_length(&config.capture);
+
+            for (netns, name) in discover(iface) {
+                let opened = match &netns {
+                    Some(netns) => AfPacketCapture::new_in_netns(&name, netns, iface.promiscuous, snap_length),
+                    None => AfPacketCapture::new(&name, iface.promiscuous, snap_length),
+                };
+                match opened {
+                    Ok(interface) => {
+                        let mut interface = interface
+                            .with_read_buffer_size(iface.read_buffer_size(&config.capture))
+                            .with_timestamp_source(iface.timestamp_source(&config.capture));
+                        if let Some(capacity) = iface.channel_capacity {
+                            interface = interface.with_channel_capacity(capacity);
+                        }
+                        if iface.erspan {
+                            interface = interface.with_erspan(Arc::clone(&erspan));
+                        }
+                        if config.capture.shed.budget_us > 0 {
+                            interface = interface.with_shed(config.capture.shed.clone());
+                        }
+                        if let Some(policy) = &policy {
+                            interface = interface.with_policy(Arc::clone(policy));
+                        }
+                        if let Some(program) = &program {
+                            interface = interface.with_filter(program.clone());
+                        }
+                        if config.capture.reassembly.enabled {
+                            interface = interface.with_reassembly(config.capture.reassembly.clone());
+                        }
+                        if let Some(ring) = iface.ring(&config.capture) {
+                            interface = interface.with_ring(ring.clone());
+                        }
+                        capture.add_capture(interface);
+                    }
+                    Err(e) => error!(
+                        "Failed to add interface '{}': {}",
+                        netns::qualified_name(netns.as_deref(), &name), e
+                    ),
+                }
+            }
+        }
+        let stats = capture.interface_stats();
+
+        // Start capture threads, sending straight to frame_tx
+        let threads = match tuning {
+            Some(tunables) => capture.start_with(TunedSender::new(frame_tx, tunables)),
+            None => capture.start_with(frame_tx),
+        }
+        .with_context(|| "Failed to start capture")?;
+
+        info!("Capture started on {} interface(s)", stats.len());
+
+        Ok(Self { capture, threads, task: None, stats, script_stats, policy })
+    }
+
+    /// Statistics of each interface or flow listener, by name
+    pub fn stats(&self) -> Vec<(String, Arc<CaptureStats>)> {
+        self.stats.clone()
+    }
+
+    /// Stop capturing, wait for the capture threads and return the totals
+    pub fn stop(self) -> CaptureStatsSnapshot {
+        self.capture.stop_all();
+        for handle in self.threads {
+            let _ = handle.join();
+        }
+        if let Some(task) = self.task {
+            task.abort();
+        }
+        if let Some(stats) = &self.script_stats {
+            info!(
+                "Script stats: dropped={}, errors={}",
+                stats.frames_dropped.load(Ordering::Relaxed),
+                stats.errors.load(Ordering::Relaxed)
+            );
+        }
+        if let Some(policy) = &self.policy {
+            for (name, action, frames) in policy.counts() {
+                info!("Policy rule '{}' ({}): {} frames", name, action.as_str(), frames);
+            }
+        }
+
+        self.stats.iter().fold(CaptureStatsSnapshot::default(), |mut combined, (_, stats)| {
+            combined.add(&stats.snapshot());
+            combined
+        })
+    }
+}
+
+/// Namespaces and names of the interfaces `iface` captures, its patterns
+/// matched against the namespaces and interfaces present
+fn discover(iface: &InterfaceConfig) -> Vec<(Option<String>, String)> {
+    let namespaces = match &iface.netns {
+        Some(pattern) => match netns::discover(pattern) {
+            Ok(namespaces) => namespaces.into_iter().map(Some).collect(),
+            Err(e) => {
+                error!("Failed to discover network namespaces '{}': {}", pattern, e);
+                return Vec::new();
+            }
+        },
+        None => vec![None],
+    };
+
+    let mut interfaces = Vec::new();
+    for netns in namespaces {
+        if !netns::is_pattern(&iface.name) {
+            interfaces.push((netns, iface.name.clone()));
+            continue;
+        }
+        match netns::discover_interfaces(netns.as_deref(), &iface.name) {
+            Ok(names) => interfaces.extend(names.into_iter().map(|name| (netns.clone(), name))),
+            Err(e) => error!(
+                "Failed to discover interfaces '{}': {}",
+                netns::qualified_name(netns.as_deref(), &iface.name), e
+            ),
+        }
+    }
+
+    if interfaces.is_empty() {
+        warn!(
+            "No interface matches '{}'",
+            netns::qualified_name(iface.netns.as_deref(), &iface.name)
+        );
+    } else if iface.netns.as_deref().is_some_and(netns::is_pattern) || netns::is_pattern(&iface.name) {
+        let names: Vec<String> = interfaces
+            .iter()
+            .map(|(netns, name)| netns::qualified_name(netns.as_deref(), name))
+            .collect();
+        info!(
+            "Interfaces matching '{}': {}",
+            netns::qualified_name(iface.netns.as_deref(), &iface.name),
+            names.join(", ")
+        );
+    }
+    interfaces
+}
@@ -1,11 +1,24 @@
 //! NetSentinel Capture Module
 //!
 //! Passive network packet capture using AF_PACKET for high-performance
-//! zero-copy frame capture on Linux systems.
+//! zero-copy frame capture on Linux systems, or sFlow/NetFlow/IPFIX collection
+//! where no mirror port is available.
+//!
+//! Other services can embed the capture and decoding through
+//! [`CaptureBuilder`], without the Redis output.
 
+pub mod admin;
+pub mod autotune;
 pub mod capture;
+pub mod collector;
 pub mod config;
 pub mod decode;
+pub mod input;
+pub mod logging;
+pub mod metrics;
 pub mod output;
+pub mod script;
+pub mod telemetry;
 
+pub use capture::{CaptureBuilder, CaptureHandle, CapturedFrame};
 pub use config::Config;
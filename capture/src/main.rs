@@ -3,16 +3,22 @@
 //! High-performance packet capture for network monitoring and CMDB generation.
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::mpsc;
-use tracing::{error, info, warn, Level};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, Level};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-use netsentinel_capture::capture::{CapturedFrame, MultiCapture, print_interfaces};
+use netsentinel_capture::admin::{self, AdminRequest, AdminServer};
+use netsentinel_capture::autotune::{self, Tunables};
+use netsentinel_capture::capture::{CapturedFrame, print_interfaces};
 use netsentinel_capture::config::Config;
-use netsentinel_capture::output::RedisOutput;
+use netsentinel_capture::input::Input;
+use netsentinel_capture::logging::LogFilter;
+use netsentinel_capture::metrics;
+use netsentinel_capture::output::{tenant, RedisOutput};
+use netsentinel_capture::telemetry::Telemetry;
 
 /// NetSentinel Passive Network Capture
 #[derive(Parser, Debug)]
@@ -36,12 +42,32 @@ struct Args {
     /// Dry run - capture but don't send to Redis
     #[arg(long)]
     dry_run: bool,
+
+    /// Print the running capture's log filter, or add directives to it
+    /// (`netsentinel_capture::output=debug`, `reset` restores it), and exit
+    #[arg(long, value_name = "FILTER", num_args = 0..=1)]
+    log_level: Option<Option<String>>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a complete default configuration, with every field documented
+    PrintDefaultConfig,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    // Print the configuration template and exit if requested (no config needed)
+    if let Some(Command::PrintDefaultConfig) = args.command {
+        print!("{}", Config::template());
+        return Ok(());
+    }
+
     // List interfaces and exit if requested
     if args.list_interfaces {
         print_interfaces();
@@ -54,70 +80,98 @@ async fn main() -> Result<()> {
 
     config.validate()?;
 
-    // Setup logging
-    setup_logging(&config, args.debug)?;
+    // Change the log filter of the running capture and exit if requested
+    if let Some(filter) = args.log_level {
+        let outcome = admin::send(&config.admin.socket, &AdminRequest::LogLevel { filter }).await?;
+        println!("{}", serde_json::to_string_pretty(&outcome)?);
+        return Ok(());
+    }
+
+    // Setup logging (kept alive so spans and metrics are flushed on exit)
+    let (telemetry, log_filter) = setup_logging(&config, args.debug)?;
 
     info!("NetSentinel Capture starting...");
     info!("Mode: {}", config.capture.mode);
-    info!("Interfaces: {:?}", config.capture.interfaces.iter().map(|i| &i.name).collect::<Vec<_>>());
+    info!("Sensor: {} (site {})", config.sensor.id, config.sensor.site);
+    if config.capture.mode == "flow" {
+        info!(
+            "Flow listeners: sflow={}, netflow={} on {}",
+            config.flow_input.sflow_port, config.flow_input.netflow_port, config.flow_input.bind
+        );
+    } else {
+        info!("Interfaces: {:?}", config.capture.interfaces.iter().map(|i| &i.name).collect::<Vec<_>>());
+    }
 
-    // Create channel for frames
-    let (frame_tx, frame_rx) = mpsc::channel::<CapturedFrame>(config.capture.ring_buffer_size);
+    // Create channel for frames, the one captures send to when not scripted
+    let capacity = if config.script.enabled { config.capture.ring_buffer_size } else { config.capture.ring_buffer_capacity() };
+    let (frame_tx, frame_rx) = mpsc::channel::<CapturedFrame>(capacity);
+    let tuning = config.capture.autotune.enabled.then(|| Arc::new(Tunables::new(&config)));
 
     // Start Redis output (unless dry run)
-    let redis_handle = if !args.dry_run {
-        let redis_output = RedisOutput::new(config.redis.clone());
+    let mut output_stats = None;
+    let redis_handles = if !args.dry_run {
+        let mut redis_output = RedisOutput::new(config.redis.clone()).with_sensor(config.sensor.clone());
+        if let Some(tunables) = &tuning {
+            redis_output = redis_output.with_tuning(Arc::clone(tunables));
+        }
+        output_stats = Some(redis_output.stats());
         let batch_size = config.capture.batch_size;
         let flush_interval = config.capture.flush_interval_ms;
 
-        Some(tokio::spawn(async move {
-            if let Err(e) = redis_output.run(frame_rx, batch_size, flush_interval).await {
-                error!("Redis output error: {}", e);
-            }
-        }))
+        if config.tenants.is_empty() {
+            vec![tokio::spawn(async move {
+                if let Err(e) = redis_output.run(frame_rx, batch_size, flush_interval).await {
+                    error!("Redis output error: {}", e);
+                }
+            })]
+        } else {
+            // Frames of each tenant to its own stream, the others to [redis]
+            tenant::start(&config, frame_rx, redis_output, tuning.clone())
+        }
     } else {
         info!("Dry run mode - frames will not be sent to Redis");
         // Consume frames but don't do anything with them
         let mut rx = frame_rx;
-        Some(tokio::spawn(async move {
+        vec![tokio::spawn(async move {
             let mut count = 0u64;
             while rx.recv().await.is_some() {
                 count += 1;
-                if count % 10000 == 0 {
+                if count.is_multiple_of(10000) {
                     info!("Dry run: {} frames captured", count);
                 }
             }
             info!("Dry run: Total {} frames captured", count);
-        }))
+        })]
     };
 
-    // Setup capture on all interfaces
-    let mut multi_capture = MultiCapture::new();
-    for iface in &config.capture.interfaces {
-        if let Err(e) = multi_capture.add_interface(
-            &iface.name,
-            iface.promiscuous,
-            config.capture.snap_length,
-        ) {
-            error!("Failed to add interface '{}': {}", iface.name, e);
-        }
-    }
+    // Start the input: flow export listeners, or capture on all interfaces
+    let input = Input::start(&config, frame_tx, tuning.clone())?;
 
-    // Start capture threads
-    let (capture_handles, capture_rx): (Vec<std::thread::JoinHandle<()>>, crossbeam_channel::Receiver<CapturedFrame>) = multi_capture
-        .start_all(config.capture.ring_buffer_size)
-        .with_context(|| "Failed to start capture")?;
+    // Adjust the ring buffer and batch sizes to the drops and flushes
+    let autotune_handle = tuning.map(|tunables| {
+        tokio::spawn(autotune::run(config.capture.autotune.clone(), tunables, output_stats.clone()))
+    });
 
-    info!("Capture started on {} interface(s)", capture_handles.len());
+    telemetry.observe(input.stats(), output_stats);
 
-    // Bridge capture channel to frame_tx
-    let bridge_handle = tokio::spawn(async move {
-        while let Ok(frame) = capture_rx.recv() {
-            if frame_tx.send(frame).await.is_err() {
-                warn!("Frame channel closed");
-                break;
+    // Start the local admin socket and the metrics endpoint (optional)
+    let (servers_tx, servers_rx) = watch::channel(true);
+    let admin_handle = config.admin.enabled.then(|| {
+        let server = AdminServer::new(config.admin.clone(), log_filter);
+        let running = servers_rx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.run(running).await {
+                error!("Admin socket error: {:#}", e);
             }
-        }
+        })
+    });
+    let metrics_handle = config.metrics.enabled.then(|| {
+        let metrics_config = config.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics_config, servers_rx).await {
+                error!("Metrics endpoint error: {:#}", e);
+            }
+        })
     });
 
     // Setup signal handling
@@ -136,26 +190,29 @@ async fn main() -> Result<()> {
 
     // Cleanup
     info!("Shutting down...");
-    multi_capture.stop_all();
+    if let Some(h) = autotune_handle {
+        h.abort();
+    }
+    let stats = input.stop();
+    let _ = servers_tx.send(false);
+    for h in [admin_handle, metrics_handle].into_iter().flatten() {
+        let _ = h.await;
+    }
 
     // Print final stats
-    let stats = multi_capture.combined_stats();
     info!(
-        "Final stats: packets={}, bytes={}, dropped={}, errors={}",
+        "Final stats: packets={}, bytes={}, dropped={}, errors={}, shed={}, denied={}, mode changes={}",
         stats.packets_captured,
         stats.bytes_captured,
         stats.packets_dropped,
-        stats.parse_errors
+        stats.parse_errors,
+        stats.frames_shed,
+        stats.frames_denied,
+        stats.mode_changes
     );
 
-    // Wait for capture threads
-    for handle in capture_handles {
-        let _ = handle.join();
-    }
-
-    // Cancel bridge and redis tasks
-    bridge_handle.abort();
-    if let Some(h) = redis_handle {
+    // Cancel the redis tasks
+    for h in redis_handles {
         h.abort();
     }
 
@@ -163,8 +220,11 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Setup logging based on configuration
-fn setup_logging(config: &Config, debug: bool) -> Result<()> {
+/// Setup logging and the optional OpenTelemetry export
+///
+/// Returns the handle changing the log filter at runtime along with the
+/// telemetry providers.
+fn setup_logging(config: &Config, debug: bool) -> Result<(Telemetry, LogFilter)> {
     let level = if debug {
         Level::DEBUG
     } else {
@@ -183,8 +243,11 @@ fn setup_logging(config: &Config, debug: bool) -> Result<()> {
         .add_directive("redis=warn".parse().unwrap())
         .add_directive("hyper=warn".parse().unwrap());
 
+    let (filter, log_filter) = LogFilter::new(filter);
+    let telemetry = Telemetry::new(&config.telemetry)?;
     let subscriber = tracing_subscriber::registry()
-        .with(filter);
+        .with(filter)
+        .with(telemetry.tracer().map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
 
     if config.logging.format == "json" {
         subscriber
@@ -196,5 +259,5 @@ fn setup_logging(config: &Config, debug: bool) -> Result<()> {
             .init();
     }
 
-    Ok(())
+    Ok((telemetry, log_filter))
 }
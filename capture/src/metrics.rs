@@ -0,0 +1,217 @@
+//! Prometheus metrics
+//!
+//! Frames go from the capture threads (through a channel of their own for
+//! interfaces with a `channel_capacity`), through the scripting hooks when
+//! enabled, to the Redis output. Each channel's consumer samples its depth
+//! as it takes a frame, and the output times and sizes each batch it
+//! flushes, so backpressure shows up before frames are dropped.
+
+use anyhow::{Context, Result};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::convert::Infallible;
+use std::sync::{Arc, LazyLock};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::config::MetricsConfig;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Get the process-wide metrics
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Frame channel, labelled by stage and interface or tenant (empty when
+/// shared)
+#[derive(Debug, Clone, Copy)]
+pub enum Channel<'a> {
+    /// Frames of one interface, before the shared channel
+    Interface(&'a str),
+    /// Frames waiting for the scripting hooks
+    Script,
+    /// Frames waiting for the Redis output
+    Output,
+    /// Frames waiting for the Redis output of a tenant (`default`: of the
+    /// frames of no tenant)
+    Tenant(&'a str),
+}
+
+impl Channel<'_> {
+    fn labels(&self) -> [&str; 2] {
+        match self {
+            Self::Interface(name) => ["interface", name],
+            Self::Script => ["script", ""],
+            Self::Output => ["output", ""],
+            Self::Tenant(name) => ["tenant", name],
+        }
+    }
+}
+
+/// Capture metrics
+pub struct Metrics {
+    registry: Registry,
+    /// Frames waiting in each channel
+    pub channel_depth: IntGaugeVec,
+    /// Frames each channel holds before senders wait or drop
+    pub channel_capacity: IntGaugeVec,
+    /// Duration of Redis batch flushes
+    pub flush_duration: Histogram,
+    /// Frames per Redis batch flush
+    pub flush_size: Histogram,
+    /// Frames a Redis batch holds before it is flushed
+    pub batch_size: IntGauge,
+    /// Frames matched by each protocol policy rule
+    pub policy_frames: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("netsentinel_capture".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let channel_depth = IntGaugeVec::new(
+            Opts::new("channel_depth", "Frames waiting in a channel"),
+            &["channel", "interface"],
+        )
+            .expect("valid metric");
+        let channel_capacity = IntGaugeVec::new(
+            Opts::new("channel_capacity", "Frames a channel holds"),
+            &["channel", "interface"],
+        )
+            .expect("valid metric");
+        let flush_duration = Histogram::with_opts(
+            HistogramOpts::new("flush_duration_seconds", "Duration of Redis batch flushes")
+                .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+        )
+            .expect("valid metric");
+        let flush_size = Histogram::with_opts(
+            HistogramOpts::new("flush_batch_frames", "Frames per Redis batch flush")
+                .buckets(vec![1.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0]),
+        )
+            .expect("valid metric");
+        let batch_size = IntGauge::new("batch_size", "Frames a Redis batch holds before it is flushed")
+            .expect("valid metric");
+        let policy_frames = IntCounterVec::new(
+            Opts::new("policy_frames_total", "Frames matched by a protocol policy rule"),
+            &["rule", "action"],
+        )
+            .expect("valid metric");
+
+        for collector in [
+            Box::new(channel_depth.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(channel_capacity.clone()),
+            Box::new(flush_duration.clone()),
+            Box::new(flush_size.clone()),
+            Box::new(batch_size.clone()),
+            Box::new(policy_frames.clone()),
+        ] {
+            registry.register(collector).expect("unique metric names");
+        }
+
+        Self { registry, channel_depth, channel_capacity, flush_duration, flush_size, batch_size, policy_frames }
+    }
+
+    /// Record the capacity of a channel
+    pub fn open_channel(&self, channel: Channel<'_>, capacity: usize) {
+        self.channel_capacity.with_label_values(&channel.labels()).set(capacity as i64);
+        self.channel_depth.with_label_values(&channel.labels()).set(0);
+    }
+
+    /// Record a new capacity of an open channel
+    pub fn resize_channel(&self, channel: Channel<'_>, capacity: usize) {
+        self.channel_capacity.with_label_values(&channel.labels()).set(capacity as i64);
+    }
+
+    /// Record the frames left in a channel after taking one
+    pub fn sample_channel(&self, channel: Channel<'_>, depth: usize) {
+        self.channel_depth.with_label_values(&channel.labels()).set(depth as i64);
+    }
+
+    /// Render all metrics in the text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails on malformed metric families
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Answer scrapes of `path`
+fn respond(request: &Request<Incoming>, path: &str) -> Response<Full<Bytes>> {
+    let status = if request.uri().path() != path {
+        StatusCode::NOT_FOUND
+    } else if request.method() != Method::GET {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else {
+        let mut response = Response::new(Full::new(Bytes::from(metrics().render())));
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(prometheus::TEXT_FORMAT));
+        return response;
+    };
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+/// Serve the metrics endpoint until `running` turns false
+pub async fn serve(config: MetricsConfig, mut running: watch::Receiver<bool>) -> Result<()> {
+    let addr = format!("0.0.0.0:{}", config.port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+
+    info!("Metrics available on http://{}{}", addr, config.path);
+
+    let path: Arc<str> = config.path.into();
+    loop {
+        tokio::select! {
+            _ = running.wait_for(|running| !running) => break,
+            accepted = listener.accept() => {
+                let (stream, _) = match accepted {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!("Metrics accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let path = Arc::clone(&path);
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| {
+                        let response = respond(&request, &path);
+                        async move { Ok::<_, Infallible>(response) }
+                    });
+                    if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                        debug!("Metrics connection failed: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_channels_and_flushes() {
+        metrics().open_channel(Channel::Interface("eth9"), 4096);
+        metrics().sample_channel(Channel::Interface("eth9"), 1200);
+        metrics().flush_size.observe(1000.0);
+
+        let text = metrics().render();
+        assert!(text.contains("netsentinel_capture_channel_depth{channel=\"interface\",interface=\"eth9\"} 1200"));
+        assert!(text.contains("netsentinel_capture_channel_capacity{channel=\"interface\",interface=\"eth9\"} 4096"));
+        assert!(text.contains("netsentinel_capture_flush_batch_frames_bucket{le=\"1000\"}"));
+    }
+}
@@ -1,5 +1,7 @@
 //! Output module for sending captured frames to destinations
 
+pub mod projection;
 pub mod redis;
+pub mod tenant;
 
 pub use redis::RedisOutput;
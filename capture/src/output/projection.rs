@@ -0,0 +1,188 @@
+//! Frame field projection
+//!
+//! Deployments needing inventory-level data only can leave optional fields
+//! out of the frames they publish, cutting the stream's bandwidth: `fields`
+//! publishes only the listed ones, `omit_fields` leaves out the listed ones.
+//! What every frame carries (timestamp, interface, MAC addresses, EtherType,
+//! sizes) and its ERSPAN session, which decides its site, are always
+//! published. The aggregator does without what it is not sent: no DNS
+//! tracking without `dns`, no handshake outcomes without `tcp_flags`.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use netsentinel_types::CapturedFrame;
+use crate::config::RedisConfig;
+
+/// Optional field of a published frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameField {
+    Vlan,
+    Qinq,
+    /// ARP requests and replies
+    Arp,
+    /// IPv4 or IPv6 source address
+    SrcIp,
+    /// IPv4 or IPv6 destination address
+    DstIp,
+    IpProtocol,
+    Ttl,
+    Dscp,
+    SrcPort,
+    DstPort,
+    TcpFlags,
+    TcpSeq,
+    TcpAck,
+    /// ICMP type, code and echo identifier
+    Icmp,
+    Tls,
+    Dhcp,
+    Dns,
+    Igmp,
+    Ntp,
+    /// SNMP versions and PDU types
+    Snmp,
+    /// Modbus, DNP3 and BACnet messages
+    Ot,
+    /// SIP requests and responses
+    Sip,
+    /// Tags of the scripting hooks and ERSPAN segments
+    Tags,
+}
+
+impl FrameField {
+    pub const ALL: [FrameField; 23] = [
+        Self::Vlan,
+        Self::Qinq,
+        Self::Arp,
+        Self::SrcIp,
+        Self::DstIp,
+        Self::IpProtocol,
+        Self::Ttl,
+        Self::Dscp,
+        Self::SrcPort,
+        Self::DstPort,
+        Self::TcpFlags,
+        Self::TcpSeq,
+        Self::TcpAck,
+        Self::Icmp,
+        Self::Tls,
+        Self::Dhcp,
+        Self::Dns,
+        Self::Igmp,
+        Self::Ntp,
+        Self::Snmp,
+        Self::Ot,
+        Self::Sip,
+        Self::Tags,
+    ];
+
+    /// Leave the field out of `frame`
+    fn clear(self, frame: &mut CapturedFrame) {
+        match self {
+            Self::Vlan => frame.vlan = None,
+            Self::Qinq => frame.qinq = None,
+            Self::Arp => frame.arp = None,
+            Self::SrcIp => (frame.src_ip, frame.src_ip6) = (None, None),
+            Self::DstIp => (frame.dst_ip, frame.dst_ip6) = (None, None),
+            Self::IpProtocol => frame.ip_protocol = None,
+            Self::Ttl => frame.ttl = None,
+            Self::Dscp => frame.dscp = None,
+            Self::SrcPort => frame.src_port = None,
+            Self::DstPort => frame.dst_port = None,
+            Self::TcpFlags => frame.tcp_flags = None,
+            Self::TcpSeq => frame.tcp_seq = None,
+            Self::TcpAck => frame.tcp_ack = None,
+            Self::Icmp => (frame.icmp_type, frame.icmp_code, frame.icmp_id) = (None, None, None),
+            Self::Tls => frame.tls = None,
+            Self::Dhcp => frame.dhcp = None,
+            Self::Dns => frame.dns = None,
+            Self::Igmp => frame.igmp = None,
+            Self::Ntp => frame.ntp = None,
+            Self::Snmp => frame.snmp = None,
+            Self::Ot => frame.ot = None,
+            Self::Sip => frame.sip = None,
+            Self::Tags => frame.tags.clear(),
+        }
+    }
+}
+
+/// Fields left out of the published frames
+#[derive(Debug, Clone, Default)]
+pub struct Projection {
+    omitted: Vec<FrameField>,
+}
+
+impl Projection {
+    /// Projection of the `fields` and `omit_fields` of `config`
+    pub fn new(config: &RedisConfig) -> Self {
+        let omitted = FrameField::ALL
+            .into_iter()
+            .filter(|field| {
+                config.omit_fields.contains(field)
+                    || config.fields.as_ref().is_some_and(|fields| !fields.contains(field))
+            })
+            .collect();
+        Self { omitted }
+    }
+
+    /// Whether frames are published whole
+    pub fn is_empty(&self) -> bool {
+        self.omitted.is_empty()
+    }
+
+    /// Fields left out
+    pub fn omitted(&self) -> &[FrameField] {
+        &self.omitted
+    }
+
+    /// Leave the omitted fields out of `frame`
+    pub fn apply(&self, frame: &mut CapturedFrame) {
+        for field in &self.omitted {
+            field.clear(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netsentinel_types::MacAddr;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_projection() {
+        let mut frame = CapturedFrame::new(
+            "eth0",
+            MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            0x0800,
+            64,
+        );
+        frame.src_ip = Some(Ipv4Addr::new(10, 0, 0, 1));
+        frame.dst_port = Some(443);
+        frame.tcp_seq = Some(1000);
+        frame.icmp_type = Some(8);
+
+        assert!(Projection::new(&RedisConfig::default()).is_empty());
+
+        // Everything but the flow fields
+        let config: RedisConfig = toml::from_str(
+            r#"fields = ["src_ip", "dst_ip", "ip_protocol", "src_port", "dst_port", "tcp_flags"]"#,
+        )
+        .unwrap();
+        let mut flow = frame.clone();
+        Projection::new(&config).apply(&mut flow);
+        assert_eq!((flow.src_ip, flow.dst_port, flow.tcp_seq, flow.icmp_type), (frame.src_ip, Some(443), None, None));
+
+        // Only the TCP sequence numbers
+        let config: RedisConfig = toml::from_str(r#"omit_fields = ["tcp_seq", "tcp_ack"]"#).unwrap();
+        let projection = Projection::new(&config);
+        assert_eq!(projection.omitted(), &[FrameField::TcpSeq, FrameField::TcpAck]);
+        projection.apply(&mut frame);
+        assert_eq!((frame.tcp_seq, frame.icmp_type), (None, Some(8)));
+
+        assert!(toml::from_str::<RedisConfig>(r#"omit_fields = ["timestamp"]"#).is_err());
+    }
+}
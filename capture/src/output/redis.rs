@@ -1,16 +1,20 @@
 //! Redis Streams output for captured frames
 
 use anyhow::{Context, Result, bail};
-use redis::{Client, RedisResult};
+use redis::{Client, IntoConnectionInfo, RedisResult};
 use redis::aio::MultiplexedConnection;
+use serde::Serialize;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, instrument};
 
-use crate::capture::frame::CapturedFrame;
-use crate::config::RedisConfig;
+use netsentinel_types::CapturedFrame;
+use crate::autotune::Tunables;
+use crate::config::{RedisConfig, SensorConfig};
+use crate::output::projection::Projection;
+use crate::metrics::{metrics, Channel};
 
 /// Output statistics
 #[derive(Debug, Default)]
@@ -23,23 +27,72 @@ pub struct OutputStats {
     pub send_errors: AtomicU64,
     /// Total bytes sent
     pub bytes_sent: AtomicU64,
+    /// Batches flushed
+    pub flushes: AtomicU64,
+    /// Time spent flushing batches, in microseconds
+    pub flush_time_us: AtomicU64,
+}
+
+/// Frame as published, stamped with the sensor that captured it
+///
+/// Frames of a mapped ERSPAN session are published for the session's site.
+#[derive(Serialize)]
+struct Published<'a> {
+    sensor: &'a str,
+    site: &'a str,
+    #[serde(flatten)]
+    frame: &'a CapturedFrame,
+}
+
+impl<'a> Published<'a> {
+    fn new(sensor: &'a SensorConfig, frame: &'a CapturedFrame) -> Self {
+        let site = frame.erspan.as_ref().and_then(|e| e.site.as_deref()).unwrap_or(&sensor.site);
+        Self { sensor: &sensor.id, site, frame }
+    }
 }
 
 /// Redis Streams output
 pub struct RedisOutput {
     config: RedisConfig,
+    sensor: SensorConfig,
+    projection: Projection,
     stats: Arc<OutputStats>,
+    tuning: Option<Arc<Tunables>>,
+    /// Tenant whose frames are published
+    tenant: Option<String>,
 }
 
 impl RedisOutput {
     /// Create a new Redis output
     pub fn new(config: RedisConfig) -> Self {
         Self {
+            projection: Projection::new(&config),
             config,
+            sensor: SensorConfig::default(),
             stats: Arc::new(OutputStats::default()),
+            tuning: None,
+            tenant: None,
         }
     }
 
+    /// Identify published frames as coming from `sensor`
+    pub fn with_sensor(mut self, sensor: SensorConfig) -> Self {
+        self.sensor = sensor;
+        self
+    }
+
+    /// Follow the batch size (and output channel capacity) of the auto-tuner
+    pub fn with_tuning(mut self, tunables: Arc<Tunables>) -> Self {
+        self.tuning = Some(tunables);
+        self
+    }
+
+    /// Publish the frames of `tenant` (`default`: of no tenant)
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
     /// Get output statistics
     pub fn stats(&self) -> Arc<OutputStats> {
         Arc::clone(&self.stats)
@@ -47,7 +100,16 @@ impl RedisOutput {
 
     /// Connect to Redis and return an async connection
     pub async fn connect(&self) -> Result<MultiplexedConnection> {
-        let client = Client::open(self.config.url.as_str())
+        let mut info = self.config.url.as_str()
+            .into_connection_info()
+            .with_context(|| format!("Invalid Redis URL: {}", self.config.url))?;
+        if let Some(username) = &self.config.username {
+            info.redis.username = Some(username.clone());
+        }
+        if let Some(password) = &self.config.password {
+            info.redis.password = Some(password.clone());
+        }
+        let client = Client::open(info)
             .with_context(|| format!("Failed to create Redis client: {}", self.config.url))?;
 
         let conn = client
@@ -69,26 +131,38 @@ impl RedisOutput {
         let mut conn = self.connect().await?;
         let stream_name = &self.config.stream_name;
         let max_len = self.config.max_stream_length;
+        let sensor = &self.sensor;
         let stats = Arc::clone(&self.stats);
 
         let mut batch: Vec<CapturedFrame> = Vec::with_capacity(batch_size);
         let flush_interval = Duration::from_millis(flush_interval_ms);
         let mut last_flush = std::time::Instant::now();
+        let channel = self.tenant.as_deref().map_or(Channel::Output, Channel::Tenant);
+        let capacity = self.tuning.as_ref().and_then(|tuning| tuning.output_capacity()).filter(|_| self.tenant.is_none());
+        metrics().open_channel(channel, capacity.unwrap_or(frame_rx.max_capacity()));
+        metrics().batch_size.set(batch_size as i64);
 
         info!(
-            "Redis output started: stream={}, batch_size={}, flush_interval={}ms",
-            stream_name, batch_size, flush_interval_ms
+            "Redis output started: stream={}, batch_size={}, flush_interval={}ms{}",
+            stream_name, batch_size, flush_interval_ms,
+            self.tenant.as_ref().map_or(String::new(), |tenant| format!(", tenant={}", tenant))
         );
+        if !self.projection.is_empty() {
+            info!("Frames published without {:?}", self.projection.omitted());
+        }
 
         loop {
             // Try to receive with timeout
             match tokio::time::timeout(flush_interval, frame_rx.recv()).await {
-                Ok(Some(frame)) => {
+                Ok(Some(mut frame)) => {
+                    metrics().sample_channel(channel, frame_rx.len());
+                    self.projection.apply(&mut frame);
                     batch.push(frame);
 
-                    // Flush if batch is full
+                    // Flush if batch is full, at its tuned size when auto-tuned
+                    let batch_size = self.tuning.as_ref().map_or(batch_size, |tuning| tuning.batch_size());
                     if batch.len() >= batch_size {
-                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, &batch, &stats).await {
+                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, sensor, &batch, &stats).await {
                             error!("Failed to flush batch: {}", e);
                         }
                         batch.clear();
@@ -99,7 +173,7 @@ impl RedisOutput {
                     // Channel closed
                     info!("Frame channel closed, flushing remaining frames");
                     if !batch.is_empty() {
-                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, &batch, &stats).await {
+                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, sensor, &batch, &stats).await {
                             error!("Failed to flush final batch: {}", e);
                         }
                     }
@@ -108,7 +182,7 @@ impl RedisOutput {
                 Err(_) => {
                     // Timeout - check if we need to flush
                     if !batch.is_empty() && last_flush.elapsed() >= flush_interval {
-                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, &batch, &stats).await {
+                        if let Err(e) = Self::flush_batch(&mut conn, stream_name, max_len, sensor, &batch, &stats).await {
                             error!("Failed to flush batch on timeout: {}", e);
                         }
                         batch.clear();
@@ -123,10 +197,12 @@ impl RedisOutput {
     }
 
     /// Flush a batch of frames to Redis Stream
+    #[instrument(name = "redis_flush", skip_all, fields(stream = stream_name, frames = batch.len()))]
     async fn flush_batch(
         conn: &mut MultiplexedConnection,
         stream_name: &str,
         max_len: usize,
+        sensor: &SensorConfig,
         batch: &[CapturedFrame],
         stats: &OutputStats,
     ) -> Result<()> {
@@ -134,11 +210,13 @@ impl RedisOutput {
             return Ok(());
         }
 
+        let started = std::time::Instant::now();
+
         // Use pipeline for batch writes
         let mut pipe = redis::pipe();
 
         for frame in batch {
-            let json = serde_json::to_string(frame)
+            let json = serde_json::to_string(&Published::new(sensor, frame))
                 .with_context(|| "Failed to serialize frame")?;
 
             // XADD with MAXLEN ~ for approximate trimming
@@ -155,8 +233,13 @@ impl RedisOutput {
         }
 
         // Execute pipeline
-        let _: Vec<String> = pipe.query_async(conn).await
-            .with_context(|| "Failed to execute Redis pipeline")?;
+        let result: RedisResult<Vec<String>> = pipe.query_async(conn).await;
+        let elapsed = started.elapsed();
+        metrics().flush_duration.observe(elapsed.as_secs_f64());
+        stats.flushes.fetch_add(1, Ordering::Relaxed);
+        stats.flush_time_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        metrics().flush_size.observe(batch.len() as f64);
+        result.with_context(|| "Failed to execute Redis pipeline")?;
 
         stats.frames_sent.fetch_add(batch.len() as u64, Ordering::Relaxed);
 
@@ -167,7 +250,9 @@ impl RedisOutput {
 
     /// Send a single frame to Redis (for testing or low-volume scenarios)
     pub async fn send_frame(&self, conn: &mut MultiplexedConnection, frame: &CapturedFrame) -> Result<String> {
-        let json = serde_json::to_string(frame)
+        let mut frame = frame.clone();
+        self.projection.apply(&mut frame);
+        let json = serde_json::to_string(&Published::new(&self.sensor, &frame))
             .with_context(|| "Failed to serialize frame")?;
 
         let entry_id: String = redis::cmd("XADD")
@@ -247,7 +332,7 @@ pub struct StreamInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::capture::frame::MacAddr;
+    use netsentinel_types::{ErspanInfo, MacAddr};
 
     fn test_frame() -> CapturedFrame {
         CapturedFrame::new(
@@ -267,6 +352,19 @@ mod tests {
         assert!(json.contains("eth0"));
         assert!(json.contains("00:11:22:33:44:55"));
         assert!(json.contains("ff:ff:ff:ff:ff:ff"));
+
+        let sensor = SensorConfig { id: "paris-core-1".to_string(), site: "paris".to_string() };
+        let published: serde_json::Value = serde_json::to_value(Published::new(&sensor, &frame)).unwrap();
+        assert_eq!(published["sensor"], "paris-core-1");
+        assert_eq!(published["site"], "paris");
+        assert_eq!(published["interface"], "eth0");
+
+        // Frames of a mapped ERSPAN session belong to the session's site
+        let mut frame = frame;
+        frame.erspan = Some(ErspanInfo { erspan_type: 2, session_id: Some(100), site: Some("lyon".to_string()) });
+        let published: serde_json::Value = serde_json::to_value(Published::new(&sensor, &frame)).unwrap();
+        assert_eq!(published["site"], "lyon");
+        assert_eq!(published["erspan"]["session_id"], 100);
     }
 
     #[tokio::test]
@@ -277,6 +375,7 @@ mod tests {
             stream_name: "test:frames".to_string(),
             max_stream_length: 1000,
             pool_size: 1,
+            ..Default::default()
         };
 
         let output = RedisOutput::new(config);
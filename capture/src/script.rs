@@ -0,0 +1,184 @@
+//! Frame scripting hooks
+//!
+//! Site-specific filtering too dynamic for static configuration: Rhai
+//! scripts run on every decoded frame, in order, and may drop it, change
+//! its fields or tag it. A script sees the frame as the `frame` map, with
+//! the fields of the published JSON (absent ones read as `()`), and drops
+//! it by evaluating to `false`:
+//!
+//! ```text
+//! if frame.dst_port == 5353 { return false; }   // drop mDNS
+//! if frame.vlan?.id == 30 { frame.tags.zone = "ot"; }
+//! ```
+//!
+//! Tags travel with the frame and end up on the source device in the
+//! aggregator. Each evaluation may run at most `max_operations` operations;
+//! a script that fails or runs out of budget leaves the frame as it was and
+//! is counted as an error, and the following scripts still run.
+
+use anyhow::{anyhow, Context, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::capture::CapturedFrame;
+use crate::config::ScriptConfig;
+
+/// Script hook statistics
+#[derive(Debug, Default)]
+pub struct ScriptStats {
+    /// Frames dropped by a script
+    pub frames_dropped: AtomicU64,
+    /// Failed or aborted script evaluations
+    pub errors: AtomicU64,
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+    errors: AtomicU64,
+}
+
+/// Compiled scripts run on every frame
+pub struct ScriptHook {
+    engine: Engine,
+    scripts: Vec<Script>,
+    stats: Arc<ScriptStats>,
+}
+
+impl ScriptHook {
+    /// Compile the scripts of `config`
+    pub fn new(config: &ScriptConfig) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(config.max_operations);
+        engine.set_max_call_levels(16);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(1024);
+
+        let mut hook = Self { engine, scripts: Vec::new(), stats: Arc::new(ScriptStats::default()) };
+        for path in &config.files {
+            let source = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read script {:?}", path))?;
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            hook.add(&name, &source)?;
+        }
+        Ok(hook)
+    }
+
+    /// Compile `source` and run it after the scripts already added
+    fn add(&mut self, name: &str, source: &str) -> Result<()> {
+        let ast = self.engine
+            .compile(source)
+            .map_err(|e| anyhow!("Failed to compile script {}: {}", name, e))?;
+        self.scripts.push(Script { name: name.to_string(), ast, errors: AtomicU64::new(0) });
+        Ok(())
+    }
+
+    /// Get the hook statistics
+    pub fn stats(&self) -> Arc<ScriptStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Run the scripts on `frame`, returning it as changed, or `None` if dropped
+    pub fn apply(&self, mut frame: CapturedFrame) -> Option<CapturedFrame> {
+        for script in &self.scripts {
+            match self.run(script, &frame) {
+                Ok(Some(changed)) => frame = changed,
+                Ok(None) => {
+                    self.stats.frames_dropped.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                Err(e) => {
+                    self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    if script.errors.fetch_add(1, Ordering::Relaxed) == 0 {
+                        warn!("Script {} failed (further errors logged at debug level): {:#}", script.name, e);
+                    } else {
+                        debug!("Script {} failed: {:#}", script.name, e);
+                    }
+                }
+            }
+        }
+        Some(frame)
+    }
+
+    fn run(&self, script: &Script, frame: &CapturedFrame) -> Result<Option<CapturedFrame>> {
+        let mut fields: Map = rhai::serde::to_dynamic(frame)
+            .map_err(|e| anyhow!("{}", e))?
+            .try_cast()
+            .context("Frame is not a map")?;
+        // Present even when empty, so scripts can add to it
+        fields.entry("tags".into()).or_insert_with(|| Dynamic::from_map(Map::new()));
+        let mut scope = Scope::new();
+        scope.push("frame", fields);
+
+        let result: Dynamic = self.engine
+            .eval_ast_with_scope(&mut scope, &script.ast)
+            .map_err(|e| anyhow!("{}", e))?;
+        if result.as_bool() == Ok(false) {
+            return Ok(None);
+        }
+
+        let changed = scope.get_value::<Dynamic>("frame").context("Script removed the frame variable")?;
+        let changed = rhai::serde::from_dynamic(&changed).map_err(|e| anyhow!("Invalid frame: {}", e))?;
+        Ok(Some(changed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::MacAddr;
+
+    fn hook(scripts: &[&str]) -> ScriptHook {
+        let mut hook = ScriptHook::new(&ScriptConfig::default()).unwrap();
+        for (i, source) in scripts.iter().enumerate() {
+            hook.add(&format!("test{}.rhai", i), source).unwrap();
+        }
+        hook
+    }
+
+    fn frame(dst_port: u16) -> CapturedFrame {
+        let mut frame = CapturedFrame::new(
+            "eth0",
+            MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            MacAddr::new([0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb]),
+            0x0800,
+            90,
+        );
+        frame.ip_protocol = Some(17);
+        frame.dst_port = Some(dst_port);
+        frame
+    }
+
+    #[test]
+    fn test_drop_and_tag() {
+        let hook = hook(&[
+            "if frame.dst_port == 5353 { return false; }",
+            r#"if frame.ip_protocol == 17 { frame.tags.transport = "udp"; frame.dst_port += 1; }"#,
+        ]);
+
+        assert!(hook.apply(frame(5353)).is_none());
+        let tagged = hook.apply(frame(53)).unwrap();
+        assert_eq!(tagged.tags.get("transport").map(String::as_str), Some("udp"));
+        assert_eq!(tagged.dst_port, Some(54));
+        assert_eq!(hook.stats().frames_dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_errors_are_isolated() {
+        let config = ScriptConfig { max_operations: 1000, ..Default::default() };
+        let mut hook = ScriptHook::new(&config).unwrap();
+        hook.add("loop.rhai", "loop { }").unwrap();
+        hook.add("broken.rhai", "frame.src_mac = 42;").unwrap();
+        hook.add("tag.rhai", r#"frame.tags.seen = "yes";"#).unwrap();
+
+        // The looping and broken scripts leave the frame alone; the last one still runs
+        let frame = hook.apply(frame(53)).unwrap();
+        assert_eq!(frame.tags.get("seen").map(String::as_str), Some("yes"));
+        assert_eq!(frame.src_mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!(hook.stats().errors.load(Ordering::Relaxed), 2);
+    }
+}
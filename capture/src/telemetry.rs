@@ -0,0 +1,180 @@
+//! OpenTelemetry export
+//!
+//! Sends spans (Redis batch flushes) and capture metrics to an OTLP/HTTP
+//! collector. Spans come from the `tracing` instrumentation through a
+//! subscriber layer; metrics are the capture and output statistics,
+//! observed at each export.
+
+use anyhow::{Context, Result};
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::capture::{CaptureStats, CaptureStatsSnapshot};
+use crate::config::TelemetryConfig;
+use crate::output::redis::OutputStats;
+
+/// Instrumentation scope of the capture spans and metrics
+const SCOPE: &str = "netsentinel-capture";
+
+/// Timeout of a single export request
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Metric name, description and how to read it from the statistics
+type Metric<T> = (&'static str, &'static str, fn(&T) -> u64);
+
+/// OTLP providers, flushed and shut down when dropped
+#[derive(Default)]
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+impl Telemetry {
+    /// Build the exporters enabled in `config`
+    pub fn new(config: &TelemetryConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+
+        let resource = Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+            .build();
+        let endpoint = config.endpoint.trim_end_matches('/');
+
+        let tracer_provider = if config.traces {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint))
+                .with_headers(config.headers.clone())
+                .with_timeout(EXPORT_TIMEOUT)
+                .build()
+                .with_context(|| "Failed to build OTLP span exporter")?;
+
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+                    .with_resource(resource.clone())
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        let meter_provider = if config.metrics {
+            let exporter = MetricExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/metrics", endpoint))
+                .with_headers(config.headers.clone())
+                .with_timeout(EXPORT_TIMEOUT)
+                .build()
+                .with_context(|| "Failed to build OTLP metric exporter")?;
+            let reader = PeriodicReader::builder(exporter)
+                .with_interval(Duration::from_secs(config.export_interval_secs))
+                .build();
+
+            Some(
+                SdkMeterProvider::builder()
+                    .with_reader(reader)
+                    .with_resource(resource)
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        Ok(Self { tracer_provider, meter_provider })
+    }
+
+    /// Tracer for the `tracing` layer, when spans are exported
+    pub fn tracer(&self) -> Option<SdkTracer> {
+        self.tracer_provider.as_ref().map(|provider| provider.tracer(SCOPE))
+    }
+
+    /// Register the per-interface capture and output statistics
+    pub fn observe(&self, captures: Vec<(String, Arc<CaptureStats>)>, output: Option<Arc<OutputStats>>) {
+        let Some(provider) = &self.meter_provider else {
+            return;
+        };
+        let meter = provider.meter(SCOPE);
+        let captures = Arc::new(captures);
+
+        let capture_counters: [Metric<CaptureStatsSnapshot>; 7] = [
+            ("netsentinel.capture.packets", "Packets captured", |s| s.packets_captured),
+            ("netsentinel.capture.bytes", "Bytes captured", |s| s.bytes_captured),
+            ("netsentinel.capture.dropped", "Packets dropped", |s| s.packets_dropped),
+            ("netsentinel.capture.parse_errors", "Frames that failed to decode", |s| s.parse_errors),
+            ("netsentinel.capture.shed", "Frames dropped undecoded over the latency budget", |s| s.frames_shed),
+            ("netsentinel.capture.denied", "Frames dropped by the protocol policy", |s| s.frames_denied),
+            ("netsentinel.capture.mode_changes", "Switches between full and degraded decoding", |s| s.mode_changes),
+        ];
+        for (name, description, read) in capture_counters {
+            let captures = Arc::clone(&captures);
+            meter.u64_observable_counter(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    for (interface, stats) in captures.iter() {
+                        observer.observe(read(&stats.snapshot()), &[KeyValue::new("interface", interface.clone())]);
+                    }
+                })
+                .build();
+        }
+
+        let capture_gauges: [Metric<CaptureStatsSnapshot>; 2] = [
+            ("netsentinel.capture.degraded", "Whether the capture is degraded (1) or fully decoding (0)", |s| s.degraded as u64),
+            ("netsentinel.capture.frame_latency", "Average decode and enqueue time of a frame, in nanoseconds", |s| s.frame_latency_ns),
+        ];
+        for (name, description, read) in capture_gauges {
+            let captures = Arc::clone(&captures);
+            meter.u64_observable_gauge(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    for (interface, stats) in captures.iter() {
+                        observer.observe(read(&stats.snapshot()), &[KeyValue::new("interface", interface.clone())]);
+                    }
+                })
+                .build();
+        }
+
+        let Some(output) = output else {
+            return;
+        };
+        let output_counters: [Metric<OutputStats>; 3] = [
+            ("netsentinel.output.frames_sent", "Frames written to Redis", |s| s.frames_sent.load(Ordering::Relaxed)),
+            ("netsentinel.output.send_errors", "Failed Redis writes", |s| s.send_errors.load(Ordering::Relaxed)),
+            ("netsentinel.output.bytes_sent", "Bytes written to Redis", |s| s.bytes_sent.load(Ordering::Relaxed)),
+        ];
+        for (name, description, read) in output_counters {
+            let output = Arc::clone(&output);
+            meter.u64_observable_counter(name)
+                .with_description(description)
+                .with_callback(move |observer| observer.observe(read(&output), &[]))
+                .build();
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush spans: {}", e);
+            }
+        }
+        if let Some(provider) = self.meter_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush metrics: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+-- NetSentinel - Alerts
+-- Version: 002
+-- Description: Stores the alerts raised by the aggregator
+
+CREATE TABLE alerts (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    raised_at       TIMESTAMPTZ NOT NULL,
+    severity        VARCHAR(10) NOT NULL,   -- 'info', 'low', 'medium', 'high', 'critical'
+    name            VARCHAR(128) NOT NULL,  -- rule or threshold name
+    message         TEXT NOT NULL,
+    device_id       UUID REFERENCES devices(id) ON DELETE SET NULL,
+    mac_address     MACADDR,
+    ip_address      INET,
+    details         JSONB,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_alerts_raised ON alerts(raised_at DESC);
+CREATE INDEX idx_alerts_device ON alerts(device_id);
+CREATE INDEX idx_alerts_name ON alerts(name);
@@ -0,0 +1,23 @@
+-- NetSentinel - Alert lifecycle
+-- Version: 003
+-- Description: Deduplicates repeated alerts and tracks acknowledge/resolve state
+
+ALTER TABLE alerts
+    ADD COLUMN fingerprint      VARCHAR(64),
+    ADD COLUMN status           VARCHAR(16) NOT NULL DEFAULT 'open',  -- 'open', 'acknowledged', 'resolved'
+    ADD COLUMN occurrences      INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN last_seen        TIMESTAMPTZ,
+    ADD COLUMN last_notified    TIMESTAMPTZ,
+    ADD COLUMN suppressed_by    VARCHAR(128),                         -- maintenance window
+    ADD COLUMN acknowledged_at  TIMESTAMPTZ,
+    ADD COLUMN acknowledged_by  VARCHAR(128),
+    ADD COLUMN resolved_at      TIMESTAMPTZ,
+    ADD COLUMN resolved_by      VARCHAR(128),
+    ADD COLUMN note             TEXT;
+
+UPDATE alerts SET last_seen = raised_at WHERE last_seen IS NULL;
+ALTER TABLE alerts ALTER COLUMN last_seen SET NOT NULL;
+
+-- At most one unresolved alert per fingerprint
+CREATE UNIQUE INDEX idx_alerts_fingerprint_active ON alerts(fingerprint) WHERE status <> 'resolved';
+CREATE INDEX idx_alerts_status ON alerts(status, last_seen DESC);
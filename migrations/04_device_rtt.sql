@@ -0,0 +1,27 @@
+-- NetSentinel - Passive RTT
+-- Version: 004
+-- Description: Rolling TCP handshake round-trip times between device pairs
+
+CREATE TABLE device_rtt (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    client_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    client_mac          MACADDR NOT NULL,
+    server_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    server_mac          MACADDR NOT NULL,
+    total_samples       BIGINT NOT NULL DEFAULT 0,
+    window_samples      INTEGER NOT NULL DEFAULT 0,   -- samples behind the percentiles
+    rtt_min_ms          REAL,
+    rtt_p50_ms          REAL,
+    rtt_p90_ms          REAL,
+    rtt_p99_ms          REAL,
+    rtt_max_ms          REAL,
+    server_p50_ms       REAL,                          -- capture point to server and back
+    client_p50_ms       REAL,                          -- capture point to client and back
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT uq_device_rtt_pair UNIQUE (client_mac, server_mac)
+);
+
+CREATE INDEX idx_device_rtt_client ON device_rtt(client_device_id);
+CREATE INDEX idx_device_rtt_server ON device_rtt(server_device_id);
+CREATE INDEX idx_device_rtt_p50 ON device_rtt(rtt_p50_ms DESC);
@@ -0,0 +1,32 @@
+-- NetSentinel - TCP health
+-- Version: 005
+-- Description: Retransmission and reset counters per flow and device
+
+ALTER TABLE traffic_flows
+    ADD COLUMN tcp_data_segments  BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN tcp_retransmits    BIGINT NOT NULL DEFAULT 0,  -- data segments repeating a sequence range
+    ADD COLUMN tcp_resets         BIGINT NOT NULL DEFAULT 0;
+
+-- Counters of the segments each device sent
+ALTER TABLE devices
+    ADD COLUMN tcp_data_segments  BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN tcp_retransmits    BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN tcp_resets         BIGINT NOT NULL DEFAULT 0;
+
+-- Lossiest paths first
+CREATE VIEW tcp_path_health AS
+SELECT
+    src_mac,
+    src_ip,
+    dst_mac,
+    dst_ip,
+    COUNT(*)                    AS flows,
+    SUM(tcp_data_segments)      AS data_segments,
+    SUM(tcp_retransmits)        AS retransmits,
+    SUM(tcp_resets)             AS resets,
+    SUM(tcp_retransmits)::REAL / NULLIF(SUM(tcp_data_segments), 0) AS retransmit_rate,
+    MAX(last_seen)              AS last_seen
+FROM traffic_flows
+WHERE ip_protocol = 6
+GROUP BY src_mac, src_ip, dst_mac, dst_ip
+ORDER BY retransmit_rate DESC NULLS LAST;
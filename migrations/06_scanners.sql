@@ -0,0 +1,15 @@
+-- NetSentinel - External scanners
+-- Version: 006
+-- Description: External sources identified as probing internal hosts and services
+
+CREATE TABLE scanners (
+    ip_address      INET PRIMARY KEY,
+    first_seen      TIMESTAMPTZ NOT NULL,
+    last_seen       TIMESTAMPTZ NOT NULL,
+    probes          BIGINT NOT NULL DEFAULT 0,
+    hosts_targeted  INTEGER NOT NULL DEFAULT 0,            -- most distinct internal hosts probed
+    services        TEXT[] NOT NULL DEFAULT '{}'           -- e.g. 'tcp/22', 'udp/161', 'icmp'
+);
+
+CREATE INDEX idx_scanners_last_seen ON scanners(last_seen DESC);
+CREATE INDEX idx_scanners_services ON scanners USING GIN (services);
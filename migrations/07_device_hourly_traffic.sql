@@ -0,0 +1,19 @@
+-- NetSentinel - Device traffic composition
+-- Version: 007
+-- Description: Per-device hourly bytes by service (top N, plus 'other')
+
+CREATE TABLE device_hourly_traffic (
+    hour            TIMESTAMPTZ NOT NULL,
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    mac_address     MACADDR NOT NULL,
+    service         VARCHAR(32) NOT NULL,   -- e.g. 'tcp/443', 'udp/53', 'icmp', 'arp', 'other'
+    bytes_sent      BIGINT NOT NULL DEFAULT 0,
+    bytes_received  BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (mac_address, hour, service)
+);
+
+SELECT create_hypertable('device_hourly_traffic', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('device_hourly_traffic', INTERVAL '90 days');
+
+CREATE INDEX idx_device_hourly_traffic_device ON device_hourly_traffic(device_id, hour DESC);
@@ -0,0 +1,26 @@
+-- NetSentinel - TLS inventory
+-- Version: 008
+-- Description: TLS server names and server certificates seen per client device
+
+CREATE TABLE tls_observations (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id           UUID REFERENCES devices(id) ON DELETE CASCADE,
+    client_mac          MACADDR NOT NULL,
+    server_ip           INET NOT NULL,
+    server_port         INTEGER NOT NULL,
+    sni                 VARCHAR(255),                  -- NULL when the ClientHello was not seen
+    cert_sha256         CHAR(64),                      -- NULL for TLS 1.3 (encrypted certificate)
+    cert_subject        TEXT,
+    cert_issuer         TEXT,
+    cert_not_before     TIMESTAMPTZ,
+    cert_not_after      TIMESTAMPTZ,
+    handshakes          BIGINT NOT NULL DEFAULT 0,
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX uq_tls_observation ON tls_observations(client_mac, server_ip, server_port, COALESCE(sni, ''));
+CREATE INDEX idx_tls_observations_device ON tls_observations(device_id);
+CREATE INDEX idx_tls_observations_sni ON tls_observations(sni);
+CREATE INDEX idx_tls_observations_not_after ON tls_observations(cert_not_after) WHERE cert_not_after IS NOT NULL;
+CREATE INDEX idx_tls_observations_last_seen ON tls_observations(last_seen DESC);
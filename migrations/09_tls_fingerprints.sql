@@ -0,0 +1,17 @@
+-- NetSentinel - TLS fingerprints
+-- Version: 009
+-- Description: JA3 (client) and JA3S (server) fingerprints seen per client device
+
+CREATE TABLE tls_fingerprints (
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    mac_address     MACADDR NOT NULL,
+    kind            VARCHAR(4) NOT NULL CHECK (kind IN ('ja3', 'ja3s')),
+    fingerprint     CHAR(32) NOT NULL,          -- MD5, lowercase hex
+    count           BIGINT NOT NULL DEFAULT 0,
+    first_seen      TIMESTAMPTZ NOT NULL,
+    last_seen       TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (mac_address, kind, fingerprint)
+);
+
+CREATE INDEX idx_tls_fingerprints_fingerprint ON tls_fingerprints(fingerprint);
+CREATE INDEX idx_tls_fingerprints_device ON tls_fingerprints(device_id);
@@ -0,0 +1,21 @@
+-- NetSentinel - DHCP leases
+-- Version: 010
+-- Description: History of DHCP lease events (assignments, renewals, releases)
+
+CREATE TABLE dhcp_leases (
+    id              BIGSERIAL PRIMARY KEY,
+    time            TIMESTAMPTZ NOT NULL,
+    event           VARCHAR(8) NOT NULL CHECK (event IN ('ack', 'nak', 'release', 'decline')),
+    device_id       UUID REFERENCES devices(id) ON DELETE SET NULL,
+    mac_address     MACADDR NOT NULL,
+    ip_address      INET,
+    hostname        VARCHAR(255),
+    lease_secs      BIGINT,
+    expires_at      TIMESTAMPTZ,                -- NULL for infinite leases and non-ACK events
+    server_ip       INET,
+    xid             BIGINT NOT NULL
+);
+
+CREATE INDEX idx_dhcp_leases_mac ON dhcp_leases(mac_address, time DESC);
+CREATE INDEX idx_dhcp_leases_ip ON dhcp_leases(ip_address, time DESC);
+CREATE INDEX idx_dhcp_leases_time ON dhcp_leases(time DESC);
@@ -0,0 +1,24 @@
+-- NetSentinel - Device DNS analytics
+-- Version: 011
+-- Description: Per-device hourly DNS queries, NXDOMAIN responses and DGA-like names
+
+CREATE TABLE device_dns_hourly (
+    hour                TIMESTAMPTZ NOT NULL,
+    device_id           UUID REFERENCES devices(id) ON DELETE CASCADE,
+    mac_address         MACADDR NOT NULL,
+    queries             BIGINT NOT NULL DEFAULT 0,
+    responses           BIGINT NOT NULL DEFAULT 0,
+    nxdomain            BIGINT NOT NULL DEFAULT 0,
+    distinct_domains    INTEGER NOT NULL DEFAULT 0,
+    mean_entropy        REAL NOT NULL DEFAULT 0,    -- bits per character of the longest label
+    dga_like_domains    INTEGER NOT NULL DEFAULT 0,
+    top_domains         TEXT[] NOT NULL DEFAULT '{}',
+    top_domain_queries  BIGINT[] NOT NULL DEFAULT '{}',
+    PRIMARY KEY (mac_address, hour)
+);
+
+SELECT create_hypertable('device_dns_hourly', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('device_dns_hourly', INTERVAL '90 days');
+
+CREATE INDEX idx_device_dns_hourly_device ON device_dns_hourly(device_id, hour DESC);
@@ -0,0 +1,60 @@
+-- NetSentinel - Sites and sensors
+-- Version: 012
+-- Description: Key the inventory by site and flows by sensor, so one aggregator
+--              can serve several sites without merging their devices
+
+-- Devices: one row per MAC address and site
+ALTER TABLE devices ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE devices DROP CONSTRAINT devices_mac_address_key;
+ALTER TABLE devices ADD CONSTRAINT uq_device_site_mac UNIQUE (site, mac_address);
+
+-- VLANs: the same VLAN ID is a different network on each site
+ALTER TABLE vlans ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE vlans DROP CONSTRAINT uq_vlan_ids;
+ALTER TABLE vlans ADD CONSTRAINT uq_vlan_ids UNIQUE (site, vlan_id, COALESCE(outer_vlan_id, -1));
+
+-- Flows: one row per tuple and sensor
+ALTER TABLE traffic_flows ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE traffic_flows ADD COLUMN sensor VARCHAR(64) NOT NULL DEFAULT 'unknown';
+DROP INDEX idx_flows_unique_tuple;
+CREATE UNIQUE INDEX idx_flows_unique_tuple ON traffic_flows (
+    sensor,
+    src_mac,
+    COALESCE(src_ip, '0.0.0.0'::inet),
+    COALESCE(src_port, 0),
+    dst_mac,
+    COALESCE(dst_ip, '0.0.0.0'::inet),
+    COALESCE(dst_port, 0),
+    COALESCE(vlan_id, 0),
+    COALESCE(ip_protocol, 0)
+);
+CREATE INDEX idx_flows_site ON traffic_flows(site, last_seen DESC);
+
+-- Per-device tables
+ALTER TABLE device_rtt ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_rtt DROP CONSTRAINT uq_device_rtt_pair;
+ALTER TABLE device_rtt ADD CONSTRAINT uq_device_rtt_pair UNIQUE (site, client_mac, server_mac);
+
+ALTER TABLE device_hourly_traffic ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_hourly_traffic DROP CONSTRAINT device_hourly_traffic_pkey;
+ALTER TABLE device_hourly_traffic ADD PRIMARY KEY (site, mac_address, hour, service);
+
+ALTER TABLE device_dns_hourly ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_dns_hourly DROP CONSTRAINT device_dns_hourly_pkey;
+ALTER TABLE device_dns_hourly ADD PRIMARY KEY (site, mac_address, hour);
+
+ALTER TABLE tls_observations ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+DROP INDEX uq_tls_observation;
+CREATE UNIQUE INDEX uq_tls_observation ON tls_observations(site, client_mac, server_ip, server_port, COALESCE(sni, ''));
+
+ALTER TABLE tls_fingerprints ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE tls_fingerprints DROP CONSTRAINT tls_fingerprints_pkey;
+ALTER TABLE tls_fingerprints ADD PRIMARY KEY (site, mac_address, kind, fingerprint);
+
+ALTER TABLE dhcp_leases ADD COLUMN site VARCHAR(64) NOT NULL DEFAULT 'default';
+DROP INDEX idx_dhcp_leases_mac;
+CREATE INDEX idx_dhcp_leases_mac ON dhcp_leases(site, mac_address, time DESC);
+
+-- Alerts: NULL when not about one site (e.g. external scanners)
+ALTER TABLE alerts ADD COLUMN site VARCHAR(64);
+CREATE INDEX idx_alerts_site ON alerts(site, raised_at DESC);
@@ -0,0 +1,100 @@
+-- NetSentinel - Cross-site device presence
+-- Version: 013
+-- Description: Merge a MAC address seen on several sites (roaming laptops)
+--              into one device, with its presence on each site kept in
+--              device_sites
+
+-- Presence of a device on one site. Counters are the site's own; the
+-- device totals are their sum.
+CREATE TABLE device_sites (
+    device_id               UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    site                    VARCHAR(64) NOT NULL,
+    first_seen              TIMESTAMPTZ NOT NULL,
+    last_seen               TIMESTAMPTZ NOT NULL,
+    total_packets_sent      BIGINT DEFAULT 0,
+    total_packets_received  BIGINT DEFAULT 0,
+    total_bytes_sent        BIGINT DEFAULT 0,
+    total_bytes_received    BIGINT DEFAULT 0,
+    tcp_data_segments       BIGINT DEFAULT 0,
+    tcp_retransmits         BIGINT DEFAULT 0,
+    tcp_resets              BIGINT DEFAULT 0,
+    PRIMARY KEY (device_id, site)
+);
+
+CREATE INDEX idx_device_sites_site ON device_sites(site, last_seen DESC);
+
+INSERT INTO device_sites (device_id, site, first_seen, last_seen,
+                          total_packets_sent, total_packets_received,
+                          total_bytes_sent, total_bytes_received,
+                          tcp_data_segments, tcp_retransmits, tcp_resets)
+SELECT id, site, first_seen, last_seen,
+       total_packets_sent, total_packets_received,
+       total_bytes_sent, total_bytes_received,
+       tcp_data_segments, tcp_retransmits, tcp_resets
+FROM devices;
+
+-- Keep the first seen row of each MAC address and point the others at it
+CREATE TEMPORARY TABLE device_merge AS
+SELECT id AS old_id,
+       FIRST_VALUE(id) OVER (PARTITION BY mac_address ORDER BY first_seen, id) AS new_id
+FROM devices;
+DELETE FROM device_merge WHERE old_id = new_id;
+
+UPDATE device_sites s SET device_id = m.new_id FROM device_merge m WHERE s.device_id = m.old_id;
+
+DELETE FROM device_ips i USING device_merge m
+WHERE i.device_id = m.old_id
+  AND EXISTS (
+      SELECT 1 FROM device_ips k
+      WHERE k.device_id = m.new_id
+        AND k.ip_address = i.ip_address
+        AND COALESCE(k.vlan_id, -1) = COALESCE(i.vlan_id, -1)
+  );
+UPDATE device_ips i SET device_id = m.new_id FROM device_merge m WHERE i.device_id = m.old_id;
+
+UPDATE traffic_flows f SET src_device_id = m.new_id FROM device_merge m WHERE f.src_device_id = m.old_id;
+UPDATE traffic_flows f SET dst_device_id = m.new_id FROM device_merge m WHERE f.dst_device_id = m.old_id;
+UPDATE traffic_metrics t SET device_id = m.new_id FROM device_merge m WHERE t.device_id = m.old_id;
+UPDATE alerts a SET device_id = m.new_id FROM device_merge m WHERE a.device_id = m.old_id;
+UPDATE device_rtt r SET client_device_id = m.new_id FROM device_merge m WHERE r.client_device_id = m.old_id;
+UPDATE device_rtt r SET server_device_id = m.new_id FROM device_merge m WHERE r.server_device_id = m.old_id;
+UPDATE device_hourly_traffic t SET device_id = m.new_id FROM device_merge m WHERE t.device_id = m.old_id;
+UPDATE device_dns_hourly d SET device_id = m.new_id FROM device_merge m WHERE d.device_id = m.old_id;
+UPDATE tls_observations o SET device_id = m.new_id FROM device_merge m WHERE o.device_id = m.old_id;
+UPDATE tls_fingerprints f SET device_id = m.new_id FROM device_merge m WHERE f.device_id = m.old_id;
+UPDATE dhcp_leases l SET device_id = m.new_id FROM device_merge m WHERE l.device_id = m.old_id;
+
+DELETE FROM devices d USING device_merge m WHERE d.id = m.old_id;
+DROP TABLE device_merge;
+
+-- Devices: one row per MAC address again, totals summed over its sites and
+-- `site` now the site it was last seen on
+UPDATE devices d SET
+    site = latest.site,
+    first_seen = s.first_seen,
+    last_seen = s.last_seen,
+    total_packets_sent = s.packets_sent,
+    total_packets_received = s.packets_received,
+    total_bytes_sent = s.bytes_sent,
+    total_bytes_received = s.bytes_received,
+    tcp_data_segments = s.data_segments,
+    tcp_retransmits = s.retransmits,
+    tcp_resets = s.resets
+FROM (
+    SELECT device_id,
+           MIN(first_seen) AS first_seen, MAX(last_seen) AS last_seen,
+           SUM(total_packets_sent) AS packets_sent, SUM(total_packets_received) AS packets_received,
+           SUM(total_bytes_sent) AS bytes_sent, SUM(total_bytes_received) AS bytes_received,
+           SUM(tcp_data_segments) AS data_segments, SUM(tcp_retransmits) AS retransmits,
+           SUM(tcp_resets) AS resets
+    FROM device_sites
+    GROUP BY device_id
+) s, (
+    SELECT DISTINCT ON (device_id) device_id, site
+    FROM device_sites
+    ORDER BY device_id, last_seen DESC
+) latest
+WHERE s.device_id = d.id AND latest.device_id = d.id;
+
+ALTER TABLE devices DROP CONSTRAINT uq_device_site_mac;
+ALTER TABLE devices ADD CONSTRAINT devices_mac_address_key UNIQUE (mac_address);
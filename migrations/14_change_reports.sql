@@ -0,0 +1,18 @@
+-- NetSentinel - Asset change reports
+-- Version: 014
+-- Description: Daily diff of the inventory: new and disappeared devices,
+--              new services and IP address changes
+
+CREATE TABLE change_reports (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    period_start        TIMESTAMPTZ NOT NULL,
+    period_end          TIMESTAMPTZ NOT NULL,
+    new_devices         INTEGER NOT NULL DEFAULT 0,
+    disappeared_devices INTEGER NOT NULL DEFAULT 0,
+    new_services        INTEGER NOT NULL DEFAULT 0,
+    ip_changes          INTEGER NOT NULL DEFAULT 0,
+    report              JSONB NOT NULL,             -- the changes themselves
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_change_reports_period ON change_reports(period_end DESC);
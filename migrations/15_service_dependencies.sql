@@ -0,0 +1,27 @@
+-- NetSentinel - Service dependency map
+-- Version: 015
+-- Description: Flows rolled up into client device -> server device:service
+--              edges, with traffic totals and the hours they were active
+
+CREATE TABLE service_dependencies (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    client_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    client_mac          MACADDR NOT NULL,
+    server_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    server_mac          MACADDR NOT NULL,
+    service             VARCHAR(32) NOT NULL,         -- tcp/443, udp/53
+    flows               BIGINT NOT NULL DEFAULT 0,    -- flows the client opened
+    packets_to_server   BIGINT NOT NULL DEFAULT 0,
+    bytes_to_server     BIGINT NOT NULL DEFAULT 0,
+    packets_to_client   BIGINT NOT NULL DEFAULT 0,
+    bytes_to_client     BIGINT NOT NULL DEFAULT 0,
+    active_hours        INTEGER NOT NULL DEFAULT 0,   -- distinct hours with traffic
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT uq_service_dependency UNIQUE (site, client_mac, server_mac, service)
+);
+
+CREATE INDEX idx_service_dependencies_client ON service_dependencies(client_device_id);
+CREATE INDEX idx_service_dependencies_server ON service_dependencies(server_device_id);
+CREATE INDEX idx_service_dependencies_service ON service_dependencies(service, last_seen DESC);
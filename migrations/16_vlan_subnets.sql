@@ -0,0 +1,19 @@
+-- NetSentinel - VLAN to subnet mapping
+-- Version: 016
+-- Description: IP subnets inferred on each VLAN from the source addresses
+--              of its devices, with confidence and conflicts
+
+CREATE TABLE vlan_subnets (
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    vlan_id             SMALLINT NOT NULL,
+    subnet              CIDR NOT NULL,
+    hosts               INTEGER NOT NULL DEFAULT 0,   -- devices sourcing addresses from the subnet
+    confidence          REAL NOT NULL DEFAULT 0,      -- share of the VLAN's hosts
+    is_primary          BOOLEAN NOT NULL DEFAULT FALSE,
+    conflicts           TEXT[] NOT NULL DEFAULT '{}', -- several_subnets, several_vlans
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, vlan_id, subnet)
+);
+
+CREATE INDEX idx_vlan_subnets_subnet ON vlan_subnets(subnet);
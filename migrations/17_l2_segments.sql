@@ -0,0 +1,26 @@
+-- NetSentinel - Layer 2 segments
+-- Version: 017
+-- Description: Devices grouped by the capture interfaces they are seen on,
+--              i.e. the switch port span they sit behind
+
+CREATE TABLE l2_segments (
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    segment_id          VARCHAR(16) NOT NULL,          -- hash of the capture points
+    capture_points      TEXT[] NOT NULL,               -- sensor/interface
+    device_count        INTEGER NOT NULL DEFAULT 0,
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, segment_id)
+);
+
+-- Segment each device currently belongs to
+CREATE TABLE device_segments (
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address         MACADDR NOT NULL,
+    device_id           UUID REFERENCES devices(id) ON DELETE CASCADE,
+    segment_id          VARCHAR(16) NOT NULL,
+    updated_at          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, mac_address)
+);
+
+CREATE INDEX idx_device_segments_segment ON device_segments(site, segment_id);
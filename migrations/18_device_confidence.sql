@@ -0,0 +1,13 @@
+-- NetSentinel - Device confidence scoring
+-- Version: 018
+-- Description: Kinds of evidence each device was seen with (ARP, DHCP,
+--              hostname, DNS, unicast traffic) and the resulting 0-100
+--              confidence score, used to filter noise out of CMDB exports
+
+-- evidence is a bit set of the kinds seen on any site; confidence the
+-- highest score any site gave the device
+ALTER TABLE devices
+    ADD COLUMN evidence     SMALLINT NOT NULL DEFAULT 0,
+    ADD COLUMN confidence   SMALLINT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_devices_confidence ON devices(confidence);
@@ -0,0 +1,10 @@
+-- NetSentinel - Device tags
+-- Version: 019
+-- Description: Name/value tags set on devices by the aggregator's frame
+--              enrichers (asset database lookups, custom tagging)
+
+-- Tags from every site are merged; a tag set again takes the new value
+ALTER TABLE devices
+    ADD COLUMN tags JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_devices_tags ON devices USING GIN (tags);
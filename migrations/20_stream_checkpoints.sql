@@ -0,0 +1,15 @@
+-- NetSentinel - Stream checkpoints
+-- Version: 020
+-- Description: Last frames stream entry each aggregator consumer has applied
+--              and persisted, to report how far behind the stream head
+--              aggregation is and where to replay from after an incident
+
+CREATE TABLE stream_checkpoints (
+    stream          VARCHAR(255) NOT NULL,
+    consumer_group  VARCHAR(255) NOT NULL,
+    consumer        VARCHAR(255) NOT NULL,
+    -- Stream entry ID (`<milliseconds>-<sequence>`)
+    last_id         VARCHAR(64) NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (stream, consumer_group, consumer)
+);
@@ -0,0 +1,11 @@
+-- NetSentinel - Nanosecond timestamps
+-- Version: 021
+-- Description: Nanoseconds since the epoch next to the TIMESTAMPTZ columns
+--              of lease events and flows, which PostgreSQL rounds to the
+--              microsecond, to sequence events closer together than that
+
+ALTER TABLE dhcp_leases ADD COLUMN time_ns BIGINT;
+ALTER TABLE traffic_flows ADD COLUMN first_seen_ns BIGINT;
+
+-- Rows written before keep their microsecond timestamps, readers fall back
+-- to them when the nanosecond column is NULL
@@ -0,0 +1,25 @@
+-- NetSentinel - ICMP flows
+-- Version: 022
+-- Description: ICMP type, code and echo identifier of ICMP flows, part of
+--              the flow tuple so ping sessions and ICMP errors between two
+--              hosts are separate flows instead of one
+
+ALTER TABLE traffic_flows ADD COLUMN icmp_type SMALLINT;
+ALTER TABLE traffic_flows ADD COLUMN icmp_code SMALLINT;
+ALTER TABLE traffic_flows ADD COLUMN icmp_id INTEGER;
+
+DROP INDEX idx_flows_unique_tuple;
+CREATE UNIQUE INDEX idx_flows_unique_tuple ON traffic_flows (
+    sensor,
+    src_mac,
+    COALESCE(src_ip, '0.0.0.0'::inet),
+    COALESCE(src_port, 0),
+    dst_mac,
+    COALESCE(dst_ip, '0.0.0.0'::inet),
+    COALESCE(dst_port, 0),
+    COALESCE(vlan_id, 0),
+    COALESCE(ip_protocol, 0),
+    COALESCE(icmp_type, -1),
+    COALESCE(icmp_code, -1),
+    COALESCE(icmp_id, -1)
+);
@@ -0,0 +1,12 @@
+-- NetSentinel - Flow service names
+-- Version: 023
+-- Description: Name of the service behind the ports of flows and service
+--              dependencies (https, rdp), from the well-known ports and the
+--              aggregator's [services] table
+
+ALTER TABLE traffic_flows ADD COLUMN service VARCHAR(64);
+ALTER TABLE service_dependencies ADD COLUMN service_name VARCHAR(64);
+
+CREATE INDEX idx_flows_service ON traffic_flows(service, last_seen DESC);
+
+-- Rows written before are named when their flow or edge is next persisted
@@ -0,0 +1,21 @@
+-- NetSentinel - Multicast group inventory
+-- Version: 024
+-- Description: Members (IGMP reports) and senders of each IPv4 multicast
+--              group, with the group's traffic and bandwidth
+
+CREATE TABLE multicast_groups (
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    group_address       INET NOT NULL,
+    members             MACADDR[] NOT NULL DEFAULT '{}',  -- current members
+    senders             MACADDR[] NOT NULL DEFAULT '{}',  -- current senders
+    packets             BIGINT NOT NULL DEFAULT 0,
+    bytes               BIGINT NOT NULL DEFAULT 0,
+    bits_per_sec        DOUBLE PRECISION NOT NULL DEFAULT 0,  -- over the last persist cycle
+    peak_bits_per_sec   DOUBLE PRECISION NOT NULL DEFAULT 0,
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, group_address)
+);
+
+CREATE INDEX idx_multicast_groups_members ON multicast_groups USING GIN (members);
+CREATE INDEX idx_multicast_groups_senders ON multicast_groups USING GIN (senders);
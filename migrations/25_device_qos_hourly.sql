@@ -0,0 +1,23 @@
+-- NetSentinel - Traffic by QoS class
+-- Version: 025
+-- Description: Per-device hourly bytes sent by VLAN and DSCP value, to audit
+--              QoS markings against the policy
+
+CREATE TABLE device_qos_hourly (
+    hour            TIMESTAMPTZ NOT NULL,
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    mac_address     MACADDR NOT NULL,
+    vlan_id         SMALLINT NOT NULL DEFAULT 0,    -- 0 when untagged
+    dscp            SMALLINT NOT NULL,              -- 0-63, e.g. 46 = EF, 34 = AF41
+    packets         BIGINT NOT NULL DEFAULT 0,
+    bytes           BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (site, mac_address, hour, vlan_id, dscp)
+);
+
+SELECT create_hypertable('device_qos_hourly', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('device_qos_hourly', INTERVAL '90 days');
+
+CREATE INDEX idx_device_qos_hourly_device ON device_qos_hourly(device_id, hour DESC);
+CREATE INDEX idx_device_qos_hourly_vlan ON device_qos_hourly(site, vlan_id, dscp, hour DESC);
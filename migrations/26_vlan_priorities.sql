@@ -0,0 +1,7 @@
+-- NetSentinel - 802.1p priority statistics per VLAN
+-- Version: 026
+-- Description: Packets and bytes of each VLAN per 802.1p priority (PCP),
+--              indexed from PCP 0 (best effort) to 7 (network control)
+
+ALTER TABLE vlans ADD COLUMN priority_packets BIGINT[] NOT NULL DEFAULT '{0,0,0,0,0,0,0,0}';
+ALTER TABLE vlans ADD COLUMN priority_bytes BIGINT[] NOT NULL DEFAULT '{0,0,0,0,0,0,0,0}';
@@ -0,0 +1,21 @@
+-- NetSentinel - Inter-subnet traffic matrix
+-- Version: 027
+-- Description: Hourly traffic routed by the detected gateways, per source
+--              and destination subnet (0.0.0.0/0 outside the private ranges)
+
+CREATE TABLE subnet_traffic_hourly (
+    hour            TIMESTAMPTZ NOT NULL,
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    src_subnet      CIDR NOT NULL,
+    dst_subnet      CIDR NOT NULL,
+    packets         BIGINT NOT NULL DEFAULT 0,
+    bytes           BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (site, hour, src_subnet, dst_subnet)
+);
+
+SELECT create_hypertable('subnet_traffic_hourly', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('subnet_traffic_hourly', INTERVAL '90 days');
+
+CREATE INDEX idx_subnet_traffic_hourly_src ON subnet_traffic_hourly(site, src_subnet, hour DESC);
+CREATE INDEX idx_subnet_traffic_hourly_dst ON subnet_traffic_hourly(site, dst_subnet, hour DESC);
@@ -0,0 +1,8 @@
+-- NetSentinel - TCP handshake outcome
+-- Version: 028
+-- Description: How the handshake of TCP flows went, on the flow of the
+--              client: established, rejected, unanswered or reset
+
+ALTER TABLE traffic_flows ADD COLUMN tcp_outcome VARCHAR(16);
+
+CREATE INDEX idx_flows_tcp_outcome ON traffic_flows(tcp_outcome, last_seen DESC) WHERE tcp_outcome IS NOT NULL;
@@ -0,0 +1,120 @@
+-- NetSentinel - Tenants
+-- Version: 029
+-- Description: Row-level tenant of every table, so one aggregator can serve
+--              several isolated customers. A tenant owns sites: rows get the
+--              tenant of their site (or of their device) when inserted, from
+--              tenant_sites, which the aggregator fills in from its
+--              configuration. Rows of no tenant's site, and aggregator-wide
+--              rows, belong to 'default'.
+
+-- Site ownership, replaced by the aggregator on startup
+CREATE TABLE tenant_sites (
+    site        VARCHAR(64) PRIMARY KEY,
+    tenant      VARCHAR(64) NOT NULL
+);
+
+CREATE INDEX idx_tenant_sites_tenant ON tenant_sites(tenant);
+
+CREATE OR REPLACE FUNCTION tenant_of_site(site VARCHAR)
+RETURNS VARCHAR AS $$
+    SELECT COALESCE((SELECT tenant FROM tenant_sites t WHERE t.site = tenant_of_site.site), 'default');
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION set_tenant_from_site()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.tenant = tenant_of_site(NEW.site);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION set_tenant_from_device()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.tenant = COALESCE((SELECT tenant FROM devices WHERE id = NEW.device_id), 'default');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Tables of a site
+ALTER TABLE devices ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_sites ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE vlans ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE traffic_flows ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_rtt ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_hourly_traffic ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_dns_hourly ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_qos_hourly ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE tls_observations ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE tls_fingerprints ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE dhcp_leases ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE alerts ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE service_dependencies ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE vlan_subnets ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE l2_segments ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE device_segments ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE multicast_groups ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE subnet_traffic_hourly ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+
+-- Tables of a device
+ALTER TABLE device_ips ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE traffic_metrics ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+
+-- Aggregator-wide tables, 'default' unless set
+ALTER TABLE protocol_stats ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE scanners ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE change_reports ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE stream_checkpoints ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE config_settings ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE users ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE audit_log ADD COLUMN tenant VARCHAR(64) NOT NULL DEFAULT 'default';
+
+-- Devices: one row per MAC address and tenant, merged over the tenant's sites
+ALTER TABLE devices DROP CONSTRAINT devices_mac_address_key;
+ALTER TABLE devices ADD CONSTRAINT uq_device_tenant_mac UNIQUE (tenant, mac_address);
+
+CREATE TRIGGER set_devices_tenant BEFORE INSERT ON devices
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_sites_tenant BEFORE INSERT ON device_sites
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_vlans_tenant BEFORE INSERT ON vlans
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_traffic_flows_tenant BEFORE INSERT ON traffic_flows
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_rtt_tenant BEFORE INSERT ON device_rtt
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_hourly_traffic_tenant BEFORE INSERT ON device_hourly_traffic
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_dns_hourly_tenant BEFORE INSERT ON device_dns_hourly
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_qos_hourly_tenant BEFORE INSERT ON device_qos_hourly
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_tls_observations_tenant BEFORE INSERT ON tls_observations
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_tls_fingerprints_tenant BEFORE INSERT ON tls_fingerprints
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_dhcp_leases_tenant BEFORE INSERT ON dhcp_leases
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_alerts_tenant BEFORE INSERT ON alerts
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_service_dependencies_tenant BEFORE INSERT ON service_dependencies
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_vlan_subnets_tenant BEFORE INSERT ON vlan_subnets
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_l2_segments_tenant BEFORE INSERT ON l2_segments
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_device_segments_tenant BEFORE INSERT ON device_segments
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_multicast_groups_tenant BEFORE INSERT ON multicast_groups
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+CREATE TRIGGER set_subnet_traffic_hourly_tenant BEFORE INSERT ON subnet_traffic_hourly
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+
+CREATE TRIGGER set_device_ips_tenant BEFORE INSERT ON device_ips
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_device();
+CREATE TRIGGER set_traffic_metrics_tenant BEFORE INSERT ON traffic_metrics
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_device();
+
+CREATE INDEX idx_devices_tenant ON devices(tenant, last_seen DESC);
+CREATE INDEX idx_flows_tenant ON traffic_flows(tenant, last_seen DESC);
+CREATE INDEX idx_alerts_tenant ON alerts(tenant, raised_at DESC);
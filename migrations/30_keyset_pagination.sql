@@ -0,0 +1,11 @@
+-- NetSentinel - Keyset pagination
+-- Version: 030
+-- Description: The API pages devices, flows and alerts from the last row of
+--              the previous page, by last seen time then ID
+
+DROP INDEX IF EXISTS idx_devices_last_seen;
+DROP INDEX IF EXISTS idx_flows_last_seen;
+
+CREATE INDEX idx_devices_last_seen ON devices(last_seen DESC, id DESC);
+CREATE INDEX idx_flows_last_seen ON traffic_flows(last_seen DESC, id DESC);
+CREATE INDEX idx_alerts_last_seen ON alerts(last_seen DESC, id DESC);
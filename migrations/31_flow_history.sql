@@ -0,0 +1,78 @@
+-- NetSentinel - Flow history
+-- Version: 031
+-- Description: Hourly traffic of each flow, so who talked to whom can be
+--              asked of any past window. traffic_flows only keeps running
+--              totals: a trigger records what each upsert adds to them in
+--              the hour the flow was last seen (counters that went back
+--              after an eviction or a restart count from zero). Endpoints
+--              are copied, and the history outlives the flow rows.
+
+CREATE TABLE flow_history (
+    hour            TIMESTAMPTZ NOT NULL,
+    flow_id         UUID NOT NULL,
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    src_mac         MACADDR NOT NULL,
+    src_ip          INET,
+    src_port        INTEGER,
+    dst_mac         MACADDR NOT NULL,
+    dst_ip          INET,
+    dst_port        INTEGER,
+    ip_protocol     SMALLINT,
+    service         VARCHAR(64),
+    packets         BIGINT NOT NULL DEFAULT 0,
+    bytes           BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (flow_id, hour)
+);
+
+SELECT create_hypertable('flow_history', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('flow_history', INTERVAL '90 days');
+
+CREATE INDEX idx_flow_history_src_mac ON flow_history(tenant, src_mac, hour DESC);
+CREATE INDEX idx_flow_history_dst_mac ON flow_history(tenant, dst_mac, hour DESC);
+CREATE INDEX idx_flow_history_src_ip ON flow_history(src_ip, hour DESC);
+CREATE INDEX idx_flow_history_dst_ip ON flow_history(dst_ip, hour DESC);
+
+CREATE OR REPLACE FUNCTION record_flow_history()
+RETURNS TRIGGER AS $$
+DECLARE
+    added_packets BIGINT := NEW.packet_count;
+    added_bytes BIGINT := NEW.byte_count;
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.packet_count >= OLD.packet_count AND NEW.byte_count >= OLD.byte_count THEN
+        added_packets := NEW.packet_count - OLD.packet_count;
+        added_bytes := NEW.byte_count - OLD.byte_count;
+    END IF;
+    IF added_packets = 0 AND added_bytes = 0 THEN
+        RETURN NULL;
+    END IF;
+
+    INSERT INTO flow_history (
+        hour, flow_id, tenant, site, src_mac, src_ip, src_port, dst_mac, dst_ip, dst_port,
+        ip_protocol, service, packets, bytes
+    )
+    VALUES (
+        date_trunc('hour', NEW.last_seen), NEW.id, NEW.tenant, NEW.site, NEW.src_mac, NEW.src_ip, NEW.src_port,
+        NEW.dst_mac, NEW.dst_ip, NEW.dst_port, NEW.ip_protocol, NEW.service, added_packets, added_bytes
+    )
+    ON CONFLICT (flow_id, hour) DO UPDATE SET
+        packets = flow_history.packets + EXCLUDED.packets,
+        bytes = flow_history.bytes + EXCLUDED.bytes,
+        service = EXCLUDED.service;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER record_traffic_flows_history AFTER INSERT OR UPDATE OF packet_count, byte_count ON traffic_flows
+    FOR EACH ROW EXECUTE FUNCTION record_flow_history();
+
+-- Flows stored so far, in the hour they were last seen
+INSERT INTO flow_history (
+    hour, flow_id, tenant, site, src_mac, src_ip, src_port, dst_mac, dst_ip, dst_port,
+    ip_protocol, service, packets, bytes
+)
+SELECT date_trunc('hour', last_seen), id, tenant, site, src_mac, src_ip, src_port, dst_mac, dst_ip, dst_port,
+       ip_protocol, service, packet_count, byte_count
+FROM traffic_flows
+WHERE packet_count > 0 OR byte_count > 0;
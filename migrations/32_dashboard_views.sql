@@ -0,0 +1,100 @@
+-- NetSentinel - Dashboard views
+-- Version: 032
+-- Description: Views shaped for Grafana's PostgreSQL data source, one row
+--              per time bucket and series, with tenant and site columns to
+--              filter on: top devices per hour, protocol mix per hour and
+--              new devices per day. The hourly ones are continuous
+--              aggregates, refreshed every 30 minutes and completed with
+--              the latest rows when queried.
+
+-- Bytes each device sent and received per hour, over every service
+CREATE MATERIALIZED VIEW dashboard_device_traffic_hourly
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT
+    time_bucket('1 hour', hour) AS hour,
+    tenant,
+    site,
+    mac_address,
+    SUM(bytes_sent)             AS bytes_sent,
+    SUM(bytes_received)         AS bytes_received,
+    SUM(bytes_sent + bytes_received) AS bytes
+FROM device_hourly_traffic
+GROUP BY time_bucket('1 hour', hour), tenant, site, mac_address
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('dashboard_device_traffic_hourly',
+    start_offset => INTERVAL '3 days',
+    end_offset => INTERVAL '1 hour',
+    schedule_interval => INTERVAL '30 minutes');
+
+-- Heaviest devices of each tenant and hour first: filter on rank <= 10
+CREATE VIEW dashboard_top_devices_hourly AS
+SELECT
+    t.hour,
+    t.tenant,
+    t.site,
+    t.mac_address,
+    d.device_notes,
+    t.bytes_sent,
+    t.bytes_received,
+    t.bytes,
+    RANK() OVER (PARTITION BY t.tenant, t.hour ORDER BY t.bytes DESC) AS rank
+FROM dashboard_device_traffic_hourly t
+LEFT JOIN devices d ON d.tenant = t.tenant AND d.mac_address = t.mac_address;
+
+-- Traffic per IP protocol and hour, from the flow history
+CREATE MATERIALIZED VIEW dashboard_ip_protocols_hourly
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT
+    time_bucket('1 hour', hour) AS hour,
+    tenant,
+    site,
+    ip_protocol,
+    SUM(packets)                AS packets,
+    SUM(bytes)                  AS bytes
+FROM flow_history
+GROUP BY time_bucket('1 hour', hour), tenant, site, ip_protocol
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('dashboard_ip_protocols_hourly',
+    start_offset => INTERVAL '3 days',
+    end_offset => INTERVAL '1 hour',
+    schedule_interval => INTERVAL '30 minutes');
+
+-- Protocol mix, named as the aggregator's protocol statistics are
+CREATE VIEW dashboard_protocol_mix_hourly AS
+SELECT
+    hour,
+    tenant,
+    site,
+    CASE
+        WHEN ip_protocol IS NULL THEN 'Non-IP'
+        WHEN ip_protocol = 1 THEN 'ICMP'
+        WHEN ip_protocol = 2 THEN 'IGMP'
+        WHEN ip_protocol = 6 THEN 'TCP'
+        WHEN ip_protocol = 17 THEN 'UDP'
+        WHEN ip_protocol = 47 THEN 'GRE'
+        WHEN ip_protocol = 50 THEN 'ESP'
+        WHEN ip_protocol = 51 THEN 'AH'
+        WHEN ip_protocol = 89 THEN 'OSPF'
+        WHEN ip_protocol = 132 THEN 'SCTP'
+        ELSE 'IPv4/Other'
+    END AS protocol,
+    packets,
+    bytes
+FROM dashboard_ip_protocols_hourly;
+
+-- Devices first seen each day
+CREATE VIEW dashboard_new_devices_daily AS
+SELECT
+    date_trunc('day', first_seen) AS day,
+    tenant,
+    site,
+    COUNT(*)                    AS devices,
+    COUNT(*) FILTER (WHERE is_gateway) AS gateways
+FROM devices
+GROUP BY date_trunc('day', first_seen), tenant, site;
+
+-- Fill the aggregates from the history already stored
+CALL refresh_continuous_aggregate('dashboard_device_traffic_hourly', NULL, NULL);
+CALL refresh_continuous_aggregate('dashboard_ip_protocols_hourly', NULL, NULL);
@@ -0,0 +1,31 @@
+-- NetSentinel - Device anomaly scores
+-- Version: 033
+-- Description: Per-device hourly scores of the baseline detectors (outbound
+--              volume, NXDOMAIN responses): how many standard deviations
+--              each period lay from the device's learned baseline, so slow
+--              drifts show before an alert fires.
+
+CREATE TABLE device_anomaly_hourly (
+    hour            TIMESTAMPTZ NOT NULL,
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address     MACADDR NOT NULL,
+    detector        VARCHAR(32) NOT NULL,
+    samples         BIGINT NOT NULL DEFAULT 0,
+    mean_score      DOUBLE PRECISION NOT NULL DEFAULT 0,
+    max_score       DOUBLE PRECISION NOT NULL DEFAULT 0,
+    mean_value      DOUBLE PRECISION NOT NULL DEFAULT 0,    -- bytes or responses per period
+    baseline        DOUBLE PRECISION NOT NULL DEFAULT 0,    -- baseline mean at the last sample
+    PRIMARY KEY (site, mac_address, detector, hour)
+);
+
+SELECT create_hypertable('device_anomaly_hourly', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('device_anomaly_hourly', INTERVAL '90 days');
+
+CREATE INDEX idx_device_anomaly_hourly_device ON device_anomaly_hourly(device_id, hour DESC);
+CREATE INDEX idx_device_anomaly_hourly_tenant ON device_anomaly_hourly(tenant, detector, hour DESC);
+
+CREATE TRIGGER set_device_anomaly_hourly_tenant BEFORE INSERT ON device_anomaly_hourly
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
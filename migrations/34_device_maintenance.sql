@@ -0,0 +1,45 @@
+-- NetSentinel - Device maintenance
+-- Version: 034
+-- Description: Devices and whole VLANs of a site put under maintenance
+--              through the API until a set time. Rules raise no alert about
+--              them, other alerts are recorded without being notified, and
+--              the change report leaves out what changed on them meanwhile.
+--              Ending a maintenance early moves its end, so the changes it
+--              covered stay out of later reports.
+
+CREATE TABLE device_maintenance (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address     MACADDR,
+    vlan_id         SMALLINT,
+    reason          TEXT,
+    created_by      VARCHAR(128),
+    starts_at       TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    until           TIMESTAMPTZ NOT NULL,
+    CONSTRAINT chk_maintenance_target CHECK ((mac_address IS NULL) <> (vlan_id IS NULL)),
+    CONSTRAINT chk_maintenance_period CHECK (until >= starts_at)
+);
+
+CREATE INDEX idx_device_maintenance_until ON device_maintenance(until DESC);
+CREATE INDEX idx_device_maintenance_mac ON device_maintenance(site, mac_address) WHERE mac_address IS NOT NULL;
+CREATE INDEX idx_device_maintenance_vlan ON device_maintenance(site, vlan_id) WHERE vlan_id IS NOT NULL;
+
+CREATE TRIGGER set_device_maintenance_tenant BEFORE INSERT ON device_maintenance
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+
+-- Whether the device `mac` of `site`, or a VLAN it holds addresses on, was
+-- under maintenance at `at`
+CREATE OR REPLACE FUNCTION under_maintenance(site VARCHAR, mac MACADDR, at TIMESTAMPTZ)
+RETURNS BOOLEAN AS $$
+    SELECT EXISTS (
+        SELECT 1 FROM device_maintenance m
+        WHERE m.site = under_maintenance.site
+          AND m.starts_at <= under_maintenance.at AND under_maintenance.at < m.until
+          AND (m.mac_address = under_maintenance.mac
+               OR m.vlan_id IN (SELECT i.vlan_id FROM device_ips i
+                                JOIN devices d ON d.id = i.device_id
+                                WHERE d.tenant = tenant_of_site(under_maintenance.site)
+                                  AND d.mac_address = under_maintenance.mac))
+    );
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,30 @@
+-- NetSentinel - Device NTP servers
+-- Version: 035
+-- Description: Time servers each device queries, from the NTP client
+--              requests and server responses the capture decodes, with the
+--              stratum and reference the servers answer with. Feeds the
+--              compliance checks of the time sources devices sync to.
+
+CREATE TABLE device_ntp_servers (
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address     MACADDR NOT NULL,
+    client_ip       INET,
+    server_ip       INET NOT NULL,
+    version         SMALLINT NOT NULL DEFAULT 0,     -- of the last request
+    stratum         SMALLINT,                        -- of the last response
+    reference_id    VARCHAR(16),                     -- clock source or upstream server
+    requests        BIGINT NOT NULL DEFAULT 0,
+    responses       BIGINT NOT NULL DEFAULT 0,
+    first_seen      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen       TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, mac_address, server_ip)
+);
+
+CREATE INDEX idx_device_ntp_servers_device ON device_ntp_servers(device_id);
+CREATE INDEX idx_device_ntp_servers_server ON device_ntp_servers(tenant, server_ip);
+CREATE INDEX idx_device_ntp_servers_last_seen ON device_ntp_servers(last_seen DESC);
+
+CREATE TRIGGER set_device_ntp_servers_tenant BEFORE INSERT ON device_ntp_servers
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
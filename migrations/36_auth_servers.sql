@@ -0,0 +1,29 @@
+-- NetSentinel - Authentication servers
+-- Version: 036
+-- Description: Devices classified as domain controllers, Kerberos, LDAP or
+--              SMB servers from the clients and flows their authentication
+--              services answer in the service dependencies, refreshed
+--              periodically. A server no longer meeting the thresholds
+--              loses its role.
+
+CREATE TABLE auth_servers (
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    mac_address     MACADDR NOT NULL,
+    role            VARCHAR(32) NOT NULL CHECK (role IN ('domain_controller', 'kerberos', 'ldap', 'smb')),
+    services        VARCHAR(32)[] NOT NULL DEFAULT '{}',  -- tcp/88, tcp/389, ...
+    clients         INTEGER NOT NULL DEFAULT 0,           -- most clients of one of the services
+    flows           BIGINT NOT NULL DEFAULT 0,
+    bytes           BIGINT NOT NULL DEFAULT 0,
+    first_seen      TIMESTAMPTZ NOT NULL,
+    last_seen       TIMESTAMPTZ NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, mac_address, role)
+);
+
+CREATE INDEX idx_auth_servers_device ON auth_servers(device_id);
+CREATE INDEX idx_auth_servers_role ON auth_servers(tenant, role);
+
+CREATE TRIGGER set_auth_servers_tenant BEFORE INSERT ON auth_servers
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
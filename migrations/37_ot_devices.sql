@@ -0,0 +1,52 @@
+-- NetSentinel - Industrial devices
+-- Version: 037
+-- Description: PLCs, RTUs and building controllers found in the Modbus/TCP,
+--              DNP3 and BACnet/IP messages the capture decodes, with the
+--              units they serve, and the sources writing to them with the
+--              write functions they used. Feeds the OT inventory and the
+--              checks of who may change industrial devices.
+
+CREATE TABLE ot_devices (
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address     MACADDR NOT NULL,
+    protocol        VARCHAR(16) NOT NULL CHECK (protocol IN ('modbus', 'dnp3', 'bacnet')),
+    ip_address      INET,
+    units           INTEGER[] NOT NULL DEFAULT '{}',  -- Modbus units, DNP3 addresses, BACnet instances
+    requests        BIGINT NOT NULL DEFAULT 0,        -- received
+    responses       BIGINT NOT NULL DEFAULT 0,        -- sent, announcements included
+    first_seen      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen       TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, mac_address, protocol)
+);
+
+CREATE INDEX idx_ot_devices_device ON ot_devices(device_id);
+CREATE INDEX idx_ot_devices_protocol ON ot_devices(tenant, protocol);
+
+CREATE TRIGGER set_ot_devices_tenant BEFORE INSERT ON ot_devices
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
+
+CREATE TABLE ot_writes (
+    tenant              VARCHAR(64) NOT NULL DEFAULT 'default',
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    protocol            VARCHAR(16) NOT NULL CHECK (protocol IN ('modbus', 'dnp3', 'bacnet')),
+    source_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    source_mac          MACADDR NOT NULL,
+    source_ip           INET,
+    device_id           UUID REFERENCES devices(id) ON DELETE CASCADE,
+    device_mac          MACADDR NOT NULL,
+    device_ip           INET,
+    functions           SMALLINT[] NOT NULL DEFAULT '{}',  -- function codes or services
+    writes              BIGINT NOT NULL DEFAULT 0,
+    first_seen          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, protocol, source_mac, device_mac)
+);
+
+CREATE INDEX idx_ot_writes_source ON ot_writes(source_device_id);
+CREATE INDEX idx_ot_writes_device ON ot_writes(device_id);
+CREATE INDEX idx_ot_writes_last_seen ON ot_writes(last_seen DESC);
+
+CREATE TRIGGER set_ot_writes_tenant BEFORE INSERT ON ot_writes
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
@@ -0,0 +1,35 @@
+-- NetSentinel - VoIP calls
+-- Version: 038
+-- Description: Calls followed from their SIP signaling, between the device
+--              sending the INVITE and the one receiving it, with the RTP
+--              datagrams and bytes sent to the media endpoints announced in
+--              their SDP. Gives the duration and bandwidth of each call.
+
+CREATE TABLE sip_calls (
+    tenant              VARCHAR(64) NOT NULL DEFAULT 'default',
+    site                VARCHAR(64) NOT NULL DEFAULT 'default',
+    call_id             VARCHAR(256) NOT NULL,
+    caller_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    caller_mac          MACADDR NOT NULL,
+    caller_ip           INET,
+    callee_device_id    UUID REFERENCES devices(id) ON DELETE CASCADE,
+    callee_mac          MACADDR NOT NULL,
+    callee_ip           INET,
+    from_uri            VARCHAR(256) NOT NULL,
+    to_uri              VARCHAR(256) NOT NULL,
+    state               VARCHAR(16) NOT NULL CHECK (state IN ('ringing', 'answered', 'ended', 'failed')),
+    started_at          TIMESTAMPTZ NOT NULL,
+    answered_at         TIMESTAMPTZ,
+    ended_at            TIMESTAMPTZ,
+    rtp_packets         BIGINT NOT NULL DEFAULT 0,
+    rtp_bytes           BIGINT NOT NULL DEFAULT 0,
+    last_seen           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (site, call_id)
+);
+
+CREATE INDEX idx_sip_calls_caller ON sip_calls(caller_device_id);
+CREATE INDEX idx_sip_calls_callee ON sip_calls(callee_device_id);
+CREATE INDEX idx_sip_calls_started ON sip_calls(tenant, started_at DESC);
+
+CREATE TRIGGER set_sip_calls_tenant BEFORE INSERT ON sip_calls
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
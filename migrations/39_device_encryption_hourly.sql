@@ -0,0 +1,29 @@
+-- NetSentinel - Encrypted traffic ratio
+-- Version: 039
+-- Description: Per-device hourly bytes sent and received by protocol,
+--              classified as encrypted (TLS, SSH, QUIC, IPsec) or plaintext
+--              (HTTP, telnet, FTP, SNMP), for plaintext protocol usage
+--              compliance reports. Unlisted services are summed as `other`.
+
+CREATE TABLE device_encryption_hourly (
+    hour            TIMESTAMPTZ NOT NULL,
+    device_id       UUID REFERENCES devices(id) ON DELETE CASCADE,
+    tenant          VARCHAR(64) NOT NULL DEFAULT 'default',
+    site            VARCHAR(64) NOT NULL DEFAULT 'default',
+    mac_address     MACADDR NOT NULL,
+    protocol        VARCHAR(32) NOT NULL,   -- https, ssh, telnet, http, tls, other
+    encrypted       BOOLEAN,                -- NULL for other
+    bytes           BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (site, mac_address, hour, protocol)
+);
+
+SELECT create_hypertable('device_encryption_hourly', 'hour', chunk_time_interval => INTERVAL '7 days');
+
+SELECT add_retention_policy('device_encryption_hourly', INTERVAL '90 days');
+
+CREATE INDEX idx_device_encryption_hourly_device ON device_encryption_hourly(device_id, hour DESC);
+CREATE INDEX idx_device_encryption_hourly_protocol ON device_encryption_hourly(tenant, protocol, hour DESC)
+    WHERE encrypted = FALSE;
+
+CREATE TRIGGER set_device_encryption_hourly_tenant BEFORE INSERT ON device_encryption_hourly
+    FOR EACH ROW EXECUTE FUNCTION set_tenant_from_site();
@@ -0,0 +1,11 @@
+-- NetSentinel - ARP address bindings
+-- Version: 040
+-- Description: Last time a device announced an IP address as the sender of
+--              an ARP request or reply. Such bindings come from the device
+--              itself, unlike addresses inferred from IP traffic (which a
+--              router picks up for every host it forwards for); the CMDB
+--              exports prefer them.
+
+ALTER TABLE device_ips ADD COLUMN arp_last_seen TIMESTAMPTZ;
+
+-- Addresses recorded before are bound again by the next ARP message
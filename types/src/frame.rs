@@ -1,107 +1,13 @@
 //! Frame data structures for captured network packets
 
+use std::collections::BTreeMap;
 use std::fmt;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use chrono::{DateTime, Utc};
-use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::{Serialize, Deserialize};
 
-/// MAC address (6 bytes)
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct MacAddr([u8; 6]);
-
-impl MacAddr {
-    /// Create a new MAC address from bytes
-    pub fn new(bytes: [u8; 6]) -> Self {
-        Self(bytes)
-    }
-
-    /// Create from a slice (must be exactly 6 bytes)
-    pub fn from_slice(slice: &[u8]) -> Option<Self> {
-        if slice.len() == 6 {
-            let mut bytes = [0u8; 6];
-            bytes.copy_from_slice(slice);
-            Some(Self(bytes))
-        } else {
-            None
-        }
-    }
-
-    /// Get the raw bytes
-    pub fn as_bytes(&self) -> &[u8; 6] {
-        &self.0
-    }
-
-    /// Check if this is a broadcast address
-    pub fn is_broadcast(&self) -> bool {
-        self.0 == [0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
-    }
-
-    /// Check if this is a multicast address (bit 0 of first byte is 1)
-    pub fn is_multicast(&self) -> bool {
-        self.0[0] & 0x01 == 0x01
-    }
-
-    /// Check if this is a locally administered address
-    pub fn is_local(&self) -> bool {
-        self.0[0] & 0x02 == 0x02
-    }
-
-    /// Get the OUI prefix as a string (XX:XX:XX)
-    pub fn oui_prefix(&self) -> String {
-        format!("{:02X}:{:02X}:{:02X}", self.0[0], self.0[1], self.0[2])
-    }
-
-    /// Get the OUI prefix as bytes
-    pub fn oui_bytes(&self) -> [u8; 3] {
-        [self.0[0], self.0[1], self.0[2]]
-    }
-}
-
-impl fmt::Debug for MacAddr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "MacAddr({})", self)
-    }
-}
-
-impl fmt::Display for MacAddr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
-        )
-    }
-}
-
-impl Serialize for MacAddr {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
-    }
-}
-
-impl<'de> Deserialize<'de> for MacAddr {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() != 6 {
-            return Err(serde::de::Error::custom("Invalid MAC address format"));
-        }
-
-        let mut bytes = [0u8; 6];
-        for (i, part) in parts.iter().enumerate() {
-            bytes[i] = u8::from_str_radix(part, 16)
-                .map_err(|_| serde::de::Error::custom("Invalid MAC address byte"))?;
-        }
-
-        Ok(MacAddr(bytes))
-    }
-}
+use crate::layer7::{DhcpInfo, DnsInfo, IgmpInfo, NtpInfo, OtInfo, SipInfo, SnmpInfo, TlsInfo};
+use crate::mac::MacAddr;
 
 /// VLAN information (802.1Q)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +40,49 @@ pub struct QinQInfo {
     pub inner_vlan: VlanInfo,
 }
 
+/// ERSPAN session a remotely mirrored frame was received from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErspanInfo {
+    /// ERSPAN type (1, 2 or 3)
+    #[serde(rename = "type")]
+    pub erspan_type: u8,
+    /// Session ID (types 2 and 3)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<u16>,
+    /// Site the session is mapped to, replacing the sensor's site
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+}
+
+/// ARP request or reply (Ethernet and IPv4 addresses)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArpInfo {
+    /// Operation (1 = request, 2 = reply)
+    pub operation: u16,
+    /// Sender hardware and protocol addresses
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    /// Target hardware and protocol addresses (hardware zero in requests)
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpInfo {
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+
+    /// Check if this is a probe (RFC 5227): a request from a host without
+    /// address yet, its sender IP unspecified
+    pub fn is_probe(&self) -> bool {
+        self.sender_ip.is_unspecified()
+    }
+
+    /// Check if this is a gratuitous ARP, announcing the sender's own address
+    pub fn is_gratuitous(&self) -> bool {
+        !self.is_probe() && self.sender_ip == self.target_ip
+    }
+}
+
 /// TCP flags
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 pub struct TcpFlags {
@@ -208,7 +157,8 @@ impl fmt::Display for TcpFlags {
 /// Captured frame with all parsed information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CapturedFrame {
-    /// Capture timestamp
+    /// Capture timestamp, written with nanosecond precision
+    #[serde(with = "crate::timestamp")]
     pub timestamp: DateTime<Utc>,
 
     /// Interface name where the frame was captured
@@ -232,6 +182,14 @@ pub struct CapturedFrame {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub qinq: Option<QinQInfo>,
 
+    /// ERSPAN session (if decapsulated from a remote mirror)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erspan: Option<ErspanInfo>,
+
+    /// ARP request or reply (if ARP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arp: Option<ArpInfo>,
+
     // Layer 3 - IP
     /// Source IP address (IPv4)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -241,14 +199,27 @@ pub struct CapturedFrame {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dst_ip: Option<Ipv4Addr>,
 
-    /// IP protocol number (6 = TCP, 17 = UDP, 1 = ICMP, etc.)
+    /// Source IP address (IPv6)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_ip6: Option<Ipv6Addr>,
+
+    /// Destination IP address (IPv6)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_ip6: Option<Ipv6Addr>,
+
+    /// IP protocol number (6 = TCP, 17 = UDP, 1 = ICMP, etc.), after the
+    /// IPv6 extension headers
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ip_protocol: Option<u8>,
 
-    /// Time To Live
+    /// Time To Live, or IPv6 hop limit
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ttl: Option<u8>,
 
+    /// Differentiated Services Code Point (QoS class, 46 = EF)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
+
     // Layer 4 - Transport
     /// Source port (TCP/UDP)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -262,12 +233,69 @@ pub struct CapturedFrame {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tcp_flags: Option<TcpFlags>,
 
+    /// TCP sequence number (if TCP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_seq: Option<u32>,
+
+    /// TCP acknowledgment number (if TCP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_ack: Option<u32>,
+
+    /// ICMP message type (if ICMP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_type: Option<u8>,
+
+    /// ICMP message code (if ICMP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_code: Option<u8>,
+
+    /// ICMP echo identifier (if echo request or reply)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_id: Option<u16>,
+
+    // Layer 7
+    /// TLS handshake metadata (SNI, server certificate)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+
+    /// DHCP message metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<DhcpInfo>,
+
+    /// DNS message metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsInfo>,
+
+    /// IGMP membership report, leave or query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub igmp: Option<IgmpInfo>,
+
+    /// NTP client request or server response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ntp: Option<NtpInfo>,
+
+    /// SNMP request, response or trap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snmp: Option<SnmpInfo>,
+
+    /// Modbus, DNP3 or BACnet message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ot: Option<OtInfo>,
+
+    /// SIP request or response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sip: Option<SipInfo>,
+
     // Metadata
     /// Total frame size in bytes
     pub frame_size: u32,
 
     /// Payload size (after headers)
     pub payload_size: u32,
+
+    /// Tags set by the capture's scripting hooks (name -> value)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
 }
 
 impl CapturedFrame {
@@ -281,15 +309,34 @@ impl CapturedFrame {
             ethertype,
             vlan: None,
             qinq: None,
+            erspan: None,
+            arp: None,
             src_ip: None,
             dst_ip: None,
+            src_ip6: None,
+            dst_ip6: None,
             ip_protocol: None,
             ttl: None,
+            dscp: None,
             src_port: None,
             dst_port: None,
             tcp_flags: None,
+            tcp_seq: None,
+            tcp_ack: None,
+            icmp_type: None,
+            icmp_code: None,
+            icmp_id: None,
+            tls: None,
+            dhcp: None,
+            dns: None,
+            igmp: None,
+            ntp: None,
+            snmp: None,
+            ot: None,
+            sip: None,
             frame_size,
             payload_size: 0,
+            tags: BTreeMap::new(),
         }
     }
 
@@ -332,33 +379,30 @@ impl CapturedFrame {
         }
     }
 
+    /// Get the 802.1p priority of the VLAN tag (inner VLAN if QinQ)
+    pub fn vlan_priority(&self) -> Option<u8> {
+        if let Some(ref qinq) = self.qinq {
+            Some(qinq.inner_vlan.priority)
+        } else {
+            self.vlan.as_ref().map(|v| v.priority)
+        }
+    }
+
     /// Get the outer VLAN ID (for QinQ)
     pub fn outer_vlan_id(&self) -> Option<u16> {
         self.qinq.as_ref().map(|q| q.outer_vlan.id)
     }
+
+    /// Get the TCP flags byte (if TCP)
+    pub fn tcp_flags_byte(&self) -> Option<u8> {
+        self.tcp_flags.map(|f| f.to_byte())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_mac_addr() {
-        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
-        assert_eq!(mac.to_string(), "00:11:22:33:44:55");
-        assert_eq!(mac.oui_prefix(), "00:11:22");
-        assert!(!mac.is_broadcast());
-        assert!(!mac.is_multicast());
-
-        let broadcast = MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
-        assert!(broadcast.is_broadcast());
-        assert!(broadcast.is_multicast());
-
-        let multicast = MacAddr::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]);
-        assert!(!multicast.is_broadcast());
-        assert!(multicast.is_multicast());
-    }
-
     #[test]
     fn test_vlan_info() {
         // TCI: Priority=5, DEI=0, VID=100
@@ -368,6 +412,11 @@ mod tests {
         assert_eq!(vlan.id, 100);
         assert_eq!(vlan.priority, 5);
         assert!(!vlan.dei);
+
+        let mut frame = CapturedFrame::new("eth0", MacAddr::new([0; 6]), MacAddr::new([0xff; 6]), 0x0800, 64);
+        assert_eq!(frame.vlan_priority(), None);
+        frame.qinq = Some(QinQInfo { outer_vlan: VlanInfo::from_tci(0x0ffe), inner_vlan: vlan });
+        assert_eq!((frame.vlan_id(), frame.vlan_priority()), (Some(100), Some(5)));
     }
 
     #[test]
@@ -387,12 +436,28 @@ mod tests {
     }
 
     #[test]
-    fn test_mac_addr_serialization() {
-        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
-        let json = serde_json::to_string(&mac).unwrap();
-        assert_eq!(json, "\"00:11:22:33:44:55\"");
-
-        let parsed: MacAddr = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed, mac);
+    fn test_frame_round_trip() {
+        let mut frame = CapturedFrame::new(
+            "eth0",
+            MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            MacAddr::new([0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb]),
+            0x0800,
+            74,
+        );
+        frame.src_ip = Some(Ipv4Addr::new(192, 168, 1, 10));
+        frame.ttl = Some(64);
+        frame.ip_protocol = Some(6);
+        frame.tcp_flags = Some(TcpFlags::from_byte(0x52));
+
+        let json = serde_json::to_value(&frame).unwrap();
+        assert_eq!(json["src_mac"], "00:11:22:33:44:55");
+        assert!(json.get("dst_ip").is_none());
+        assert!(json.get("icmp_type").is_none());
+
+        let parsed: CapturedFrame = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.ttl, Some(64));
+        assert_eq!(parsed.src_mac, frame.src_mac);
+        assert_eq!(parsed.tcp_flags_byte(), Some(0x52));
+        assert!(parsed.tcp_flags.is_some_and(|f| f.ece));
     }
 }
@@ -0,0 +1,218 @@
+//! Application layer metadata
+//!
+//! What the capture decoders extract from TLS handshakes, DHCP, DNS, IGMP,
+//! NTP, SNMP, SIP and industrial protocol messages. Parsing stays in the capture; these
+//! are only the fields carried with the frame.
+
+use std::net::Ipv4Addr;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::mac::MacAddr;
+
+/// Server certificate seen in a handshake
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    /// SHA-256 of the DER certificate, lowercase hex
+    pub sha256: String,
+    /// Subject common name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Issuer common name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+/// TLS metadata found in a segment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// Server name requested by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+    /// Leaf certificate presented by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<CertificateInfo>,
+    /// JA3 fingerprint of the ClientHello (MD5, lowercase hex)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ja3: Option<String>,
+    /// JA3S fingerprint of the ServerHello (MD5, lowercase hex)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ja3s: Option<String>,
+}
+
+/// DHCP message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DhcpInfo {
+    /// Message type (1 = DISCOVER, 3 = REQUEST, 5 = ACK, 7 = RELEASE, ...)
+    pub message_type: u8,
+    /// Transaction ID
+    pub xid: u32,
+    /// Client hardware address
+    pub client_mac: MacAddr,
+    /// Client's current address (ciaddr)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<Ipv4Addr>,
+    /// Address assigned by the server (yiaddr)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub your_ip: Option<Ipv4Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_ip: Option<Ipv4Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Lease time in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_secs: Option<u32>,
+    /// Server identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<Ipv4Addr>,
+}
+
+/// DNS message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsInfo {
+    /// Transaction ID
+    pub id: u16,
+    /// Response (QR bit set) rather than query
+    pub response: bool,
+    /// Response code (0 = NOERROR, 2 = SERVFAIL, 3 = NXDOMAIN, ...)
+    pub rcode: u8,
+    /// Name of the first question, lowercase without the trailing dot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qname: Option<String>,
+    /// Type of the first question (1 = A, 28 = AAAA, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qtype: Option<u16>,
+    /// Answer records in a response
+    pub answers: u16,
+}
+
+/// IGMP message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IgmpInfo {
+    /// Message type (0x11 = query, 0x12/0x16/0x22 = v1/v2/v3 report, 0x17 = leave)
+    pub message_type: u8,
+    /// Groups the sender joined or stays a member of
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub joined: Vec<Ipv4Addr>,
+    /// Groups the sender left
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left: Vec<Ipv4Addr>,
+}
+
+/// NTP message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NtpInfo {
+    /// Protocol version (3 or 4)
+    pub version: u8,
+    /// Association mode (1/2 = symmetric, 3 = client, 4 = server, 5 = broadcast)
+    pub mode: u8,
+    /// Stratum of the sender (0 = unsynchronized, 1 = primary reference)
+    pub stratum: u8,
+    /// Reference of a server's clock: its source for a primary server
+    /// (`GPS`, `PPS`), the address of its upstream server otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_id: Option<String>,
+}
+
+/// SNMP message metadata
+///
+/// The community string of v1 and v2c messages stays in the capture: only
+/// its use is known downstream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnmpInfo {
+    /// Version as encoded (0 = v1, 1 = v2c, 3 = v3)
+    pub version: u8,
+    /// PDU type of v1 and v2c messages (0 = get, 3 = set, 4 = v1 trap,
+    /// 7 = v2 trap, ...); v3 may encrypt it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pdu: Option<u8>,
+}
+
+impl SnmpInfo {
+    pub const V1: u8 = 0;
+    pub const V2C: u8 = 1;
+    pub const V3: u8 = 3;
+
+    /// Check if the message authenticates with a cleartext community string
+    pub fn uses_community(&self) -> bool {
+        matches!(self.version, Self::V1 | Self::V2C)
+    }
+}
+
+/// SIP message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SipInfo {
+    /// Method of a request (`INVITE`, `REGISTER`, `BYE`, ...), or of the
+    /// request a response answers
+    pub method: String,
+    /// Status code of a response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub call_id: String,
+    /// URIs of the From and To headers, without display name or tag
+    pub from: String,
+    pub to: String,
+    /// User-Agent of a request, Server of a response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Where the sender receives the RTP stream of the call, from the SDP
+    /// offer or answer of the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<SipMedia>,
+}
+
+/// RTP endpoint announced in an SDP body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SipMedia {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+}
+
+/// Industrial protocol of an [`OtInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtProtocol {
+    /// Modbus/TCP (TCP 502)
+    Modbus,
+    /// DNP3 over TCP or UDP (20000)
+    Dnp3,
+    /// BACnet/IP (UDP 47808)
+    Bacnet,
+}
+
+impl OtProtocol {
+    pub const ALL: [OtProtocol; 3] = [Self::Modbus, Self::Dnp3, Self::Bacnet];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Modbus => "modbus",
+            Self::Dnp3 => "dnp3",
+            Self::Bacnet => "bacnet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|protocol| protocol.as_str() == name)
+    }
+}
+
+/// Industrial (OT) protocol message metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OtInfo {
+    pub protocol: OtProtocol,
+    /// Modbus function code, DNP3 application function code or BACnet
+    /// service choice
+    pub function: u8,
+    /// Request of a master or client, rather than a response or an
+    /// announcement (BACnet I-Am)
+    pub request: bool,
+    /// Request changing the device: Modbus writes, DNP3 writes, operates and
+    /// restarts, BACnet property writes and device management
+    pub write: bool,
+    /// Modbus unit identifier, DNP3 outstation address or BACnet device
+    /// instance (from I-Am announcements)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<u32>,
+}
@@ -0,0 +1,17 @@
+//! NetSentinel Frame Schema
+//!
+//! Frames travel from capture to aggregator as JSON on a Redis stream. Both
+//! sides build on the types of this crate, so a field added to the schema
+//! cannot be silently dropped by a reader still holding an older copy.
+
+pub mod frame;
+pub mod layer7;
+pub mod mac;
+pub mod service;
+pub mod template;
+pub mod timestamp;
+
+pub use frame::{ArpInfo, CapturedFrame, ErspanInfo, QinQInfo, TcpFlags, VlanInfo};
+pub use layer7::{CertificateInfo, DhcpInfo, DnsInfo, IgmpInfo, NtpInfo, OtInfo, OtProtocol, SipInfo, SipMedia, SnmpInfo, TlsInfo};
+pub use mac::MacAddr;
+pub use service::ServiceNames;
@@ -0,0 +1,143 @@
+//! MAC addresses
+
+use std::fmt;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+/// MAC address (6 bytes)
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    /// Create a new MAC address from bytes
+    pub fn new(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+
+    /// Create from a slice (must be exactly 6 bytes)
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() == 6 {
+            let mut bytes = [0u8; 6];
+            bytes.copy_from_slice(slice);
+            Some(Self(bytes))
+        } else {
+            None
+        }
+    }
+
+    /// Parse the colon-separated hex form (`00:11:22:33:44:55`)
+    pub fn from_string(s: &str) -> Option<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 6 {
+            return None;
+        }
+
+        let mut bytes = [0u8; 6];
+        for (i, part) in parts.iter().enumerate() {
+            bytes[i] = u8::from_str_radix(part, 16).ok()?;
+        }
+
+        Some(Self(bytes))
+    }
+
+    /// Get the raw bytes
+    pub fn as_bytes(&self) -> &[u8; 6] {
+        &self.0
+    }
+
+    /// Check if this is a broadcast address
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+    }
+
+    /// Check if this is a multicast address (bit 0 of first byte is 1)
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 == 0x01
+    }
+
+    /// Check if this is a locally administered address
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 == 0x02
+    }
+
+    /// Get the OUI prefix as a string (XX:XX:XX)
+    pub fn oui_prefix(&self) -> String {
+        format!("{:02X}:{:02X}:{:02X}", self.0[0], self.0[1], self.0[2])
+    }
+
+    /// Get the OUI prefix as bytes
+    pub fn oui_bytes(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MacAddr({})", self)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        )
+    }
+}
+
+impl Serialize for MacAddr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        Self::from_string(&s).ok_or_else(|| serde::de::Error::custom(format!("Invalid MAC address '{}'", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac_addr() {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert_eq!(mac.to_string(), "00:11:22:33:44:55");
+        assert_eq!(mac.oui_prefix(), "00:11:22");
+        assert!(!mac.is_broadcast());
+        assert!(!mac.is_multicast());
+
+        let broadcast = MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert!(broadcast.is_broadcast());
+        assert!(broadcast.is_multicast());
+
+        let multicast = MacAddr::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]);
+        assert!(!multicast.is_broadcast());
+        assert!(multicast.is_multicast());
+
+        assert_eq!(MacAddr::from_string("00:11:22:33:44:55"), Some(mac));
+        assert_eq!(MacAddr::from_string("00:11:22:33:44"), None);
+        assert_eq!(MacAddr::from_string("00:11:22:33:44:zz"), None);
+    }
+
+    #[test]
+    fn test_mac_addr_serialization() {
+        let mac = MacAddr::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let json = serde_json::to_string(&mac).unwrap();
+        assert_eq!(json, "\"00:11:22:33:44:55\"");
+
+        let parsed: MacAddr = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, mac);
+        assert!(serde_json::from_str::<MacAddr>("\"not-a-mac\"").is_err());
+    }
+}
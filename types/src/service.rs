@@ -0,0 +1,165 @@
+//! Service names of well-known ports
+//!
+//! Capture and aggregator name the services behind ports from the same
+//! table, so flows read `https` or `rdp` rather than a port number wherever
+//! they are shown. Deployments add their own ports (`[services]` in the
+//! configuration), which take precedence over the built-in names.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Well-known port numbers
+pub mod ports {
+    pub const FTP_DATA: u16 = 20;
+    pub const FTP: u16 = 21;
+    pub const SSH: u16 = 22;
+    pub const TELNET: u16 = 23;
+    pub const SMTP: u16 = 25;
+    pub const DNS: u16 = 53;
+    pub const DHCP_SERVER: u16 = 67;
+    pub const DHCP_CLIENT: u16 = 68;
+    pub const HTTP: u16 = 80;
+    pub const KERBEROS: u16 = 88;
+    pub const POP3: u16 = 110;
+    pub const NTP: u16 = 123;
+    pub const NETBIOS_NS: u16 = 137;
+    pub const NETBIOS_DGM: u16 = 138;
+    pub const NETBIOS_SSN: u16 = 139;
+    pub const IMAP: u16 = 143;
+    pub const SNMP: u16 = 161;
+    pub const SNMP_TRAP: u16 = 162;
+    pub const LDAP: u16 = 389;
+    pub const HTTPS: u16 = 443;
+    pub const SMB: u16 = 445;
+    pub const LDAPS: u16 = 636;
+    pub const MODBUS: u16 = 502;
+    pub const IMAPS: u16 = 993;
+    pub const MYSQL: u16 = 3306;
+    pub const RDP: u16 = 3389;
+    pub const SIP: u16 = 5060;
+    pub const POSTGRESQL: u16 = 5432;
+    pub const REDIS: u16 = 6379;
+    pub const HTTP_ALT: u16 = 8080;
+    pub const HTTPS_ALT: u16 = 8443;
+    pub const DNP3: u16 = 20000;
+    pub const BACNET: u16 = 47808;
+}
+
+/// Get service name from port number
+pub fn service_name(port: u16) -> Option<&'static str> {
+    match port {
+        ports::FTP_DATA => Some("ftp-data"),
+        ports::FTP => Some("ftp"),
+        ports::SSH => Some("ssh"),
+        ports::TELNET => Some("telnet"),
+        ports::SMTP => Some("smtp"),
+        ports::DNS => Some("dns"),
+        ports::DHCP_SERVER | ports::DHCP_CLIENT => Some("dhcp"),
+        ports::HTTP | ports::HTTP_ALT => Some("http"),
+        ports::KERBEROS => Some("kerberos"),
+        ports::POP3 => Some("pop3"),
+        ports::NTP => Some("ntp"),
+        ports::NETBIOS_NS | ports::NETBIOS_DGM | ports::NETBIOS_SSN => Some("netbios"),
+        ports::IMAP => Some("imap"),
+        ports::SNMP | ports::SNMP_TRAP => Some("snmp"),
+        ports::LDAP => Some("ldap"),
+        ports::HTTPS | ports::HTTPS_ALT => Some("https"),
+        ports::SMB => Some("smb"),
+        ports::LDAPS => Some("ldaps"),
+        ports::MODBUS => Some("modbus"),
+        ports::IMAPS => Some("imaps"),
+        ports::MYSQL => Some("mysql"),
+        ports::RDP => Some("rdp"),
+        ports::SIP => Some("sip"),
+        ports::POSTGRESQL => Some("postgresql"),
+        ports::REDIS => Some("redis"),
+        ports::DNP3 => Some("dnp3"),
+        ports::BACNET => Some("bacnet"),
+        _ => None,
+    }
+}
+
+/// Service names of ports: the deployment's own (port -> name), then the
+/// well-known ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct ServiceNames {
+    #[serde(deserialize_with = "port_keys")]
+    extra: HashMap<u16, String>,
+}
+
+/// Longest service name, as stored with flows
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Port -> name table, configuration files keying it with strings
+fn port_keys<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<HashMap<u16, String>, D::Error> {
+    use serde::de::Error;
+
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(port, name)| {
+            let port = port.parse().map_err(|_| D::Error::custom(format!("invalid port '{}' in services", port)))?;
+            if name.is_empty() || name.len() > MAX_NAME_LEN {
+                return Err(D::Error::custom(format!(
+                    "service name of port {} must be 1 to {} characters",
+                    port, MAX_NAME_LEN
+                )));
+            }
+            Ok((port, name))
+        })
+        .collect()
+}
+
+impl ServiceNames {
+    /// Name `port` `name`, over its well-known name if any
+    pub fn with_name(mut self, port: u16, name: &str) -> Self {
+        self.extra.insert(port, name.to_string());
+        self
+    }
+
+    /// Name of the service on `port`
+    pub fn name(&self, port: u16) -> Option<&str> {
+        self.extra.get(&port).map(String::as_str).or_else(|| service_name(port))
+    }
+
+    /// Service of a connection: its destination port's, or for replies its
+    /// source port's
+    pub fn of_ports(&self, src_port: Option<u16>, dst_port: Option<u16>) -> Option<&str> {
+        dst_port.and_then(|port| self.name(port)).or_else(|| src_port.and_then(|port| self.name(port)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_names() {
+        assert_eq!(service_name(80), Some("http"));
+        assert_eq!(service_name(443), Some("https"));
+        assert_eq!(service_name(22), Some("ssh"));
+        assert_eq!(service_name(53), Some("dns"));
+        assert_eq!(service_name(12345), None);
+
+        #[derive(Deserialize)]
+        struct Config {
+            services: ServiceNames,
+        }
+        let config: Config = toml::from_str("[services]\n8006 = \"proxmox\"\n8080 = \"unifi\"").unwrap();
+        let names = config.services;
+        assert_eq!(names, ServiceNames::default().with_name(8006, "proxmox").with_name(8080, "unifi"));
+        assert_eq!(names.name(8006), Some("proxmox"));
+        assert_eq!(names.name(8080), Some("unifi"));
+        assert_eq!(names.name(3389), Some("rdp"));
+
+        // Requests name their destination, replies their source
+        assert_eq!(names.of_ports(Some(51000), Some(443)), Some("https"));
+        assert_eq!(names.of_ports(Some(8006), Some(51000)), Some("proxmox"));
+        assert_eq!(names.of_ports(Some(51000), Some(51001)), None);
+        assert_eq!(names.of_ports(None, None), None);
+
+        assert!(toml::from_str::<Config>("[services]\nproxmox = \"8006\"").is_err());
+        assert!(toml::from_str::<Config>("[services]\n8006 = \"\"").is_err());
+    }
+}
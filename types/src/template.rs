@@ -0,0 +1,361 @@
+//! Configuration templates
+//!
+//! Both binaries print their default configuration from the JSON schema
+//! derived from their `Config` types, so the template follows the code: doc
+//! comments become TOML comments and serde defaults become values. Optional
+//! sections and lists of tables are commented out, fields without a default
+//! get a placeholder marked as required. Defaults computed at run time (the
+//! host name...) are hidden with `#[schemars(skip_serializing)]` and the
+//! field is commented out.
+
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec};
+use serde_json::Value;
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+/// Width comments are wrapped at
+const WIDTH: usize = 78;
+
+/// Render `schema` as a commented TOML document, after the `header` comment
+pub fn render(schema: &RootSchema, header: &str) -> String {
+    let mut out = String::new();
+    comment(&mut out, "", header);
+    Renderer { schema }.table(&mut out, "", "", &schema.schema, None);
+    out
+}
+
+struct Renderer<'a> {
+    schema: &'a RootSchema,
+}
+
+/// Property of a table, with its type resolved
+struct Field<'a> {
+    name: &'a str,
+    description: Option<&'a str>,
+    default: Option<&'a Value>,
+    required: bool,
+    nullable: bool,
+    object: &'a SchemaObject,
+}
+
+impl<'a> Renderer<'a> {
+    /// Follow references and `Option` wrappers down to the actual type
+    fn resolve(&self, schema: &'a Schema) -> (&'a SchemaObject, bool) {
+        let Schema::Object(object) = schema else {
+            return (unknown(), false);
+        };
+        let mut object: &'a SchemaObject = object;
+        let mut nullable = false;
+        loop {
+            if let Some(name) = object.reference.as_deref() {
+                let name = name.trim_start_matches("#/definitions/");
+                match self.schema.definitions.get(name) {
+                    Some(Schema::Object(definition)) => object = definition,
+                    _ => return (unknown(), nullable),
+                }
+                continue;
+            }
+            if let Some(subschemas) = object.subschemas.as_deref() {
+                let variants = subschemas.all_of.as_ref().or(subschemas.any_of.as_ref());
+                if let Some(variants) = variants {
+                    let mut inner = variants.iter().filter(|variant| !is_null(variant));
+                    if let (Some(Schema::Object(first)), None) = (inner.next(), inner.next()) {
+                        nullable |= variants.len() > 1;
+                        object = first;
+                        continue;
+                    }
+                }
+            }
+            if let Some(SingleOrVec::Vec(types)) = &object.instance_type {
+                nullable |= types.contains(&InstanceType::Null);
+            }
+            return (object, nullable);
+        }
+    }
+
+    fn fields(&self, object: &'a SchemaObject) -> Vec<Field<'a>> {
+        let Some(validation) = object.object.as_deref() else {
+            return Vec::new();
+        };
+        validation.properties.iter()
+            .map(|(name, schema)| {
+                let (resolved, nullable) = self.resolve(schema);
+                let metadata = match schema {
+                    Schema::Object(object) => object.metadata.as_deref(),
+                    Schema::Bool(_) => None,
+                };
+                Field {
+                    name,
+                    description: metadata.and_then(|m| m.description.as_deref())
+                        .or_else(|| resolved.metadata.as_deref().and_then(|m| m.description.as_deref())),
+                    default: metadata.and_then(|m| m.default.as_ref()).filter(|v| !v.is_null()),
+                    // Defaults computed at run time are left out of the schema
+                    required: validation.required.contains(name)
+                        && !metadata.is_some_and(|m| m.write_only),
+                    nullable,
+                    object: resolved,
+                }
+            })
+            .collect()
+    }
+
+    /// Table whose fields are listed, as opposed to a map of arbitrary keys
+    fn is_table(object: &SchemaObject) -> bool {
+        object.object.as_deref().is_some_and(|o| !o.properties.is_empty())
+    }
+
+    fn item_table(&self, object: &'a SchemaObject) -> Option<&'a SchemaObject> {
+        let Some(SingleOrVec::Single(items)) = object.array.as_deref()?.items.as_ref() else {
+            return None;
+        };
+        let (item, _) = self.resolve(items);
+        Self::is_table(item).then_some(item)
+    }
+
+    /// Render the fields of a table, then its sub-tables
+    ///
+    /// `prefix` comments the whole table out; `defaults` holds the table's
+    /// default value, for fields whose own default is not in the schema.
+    fn table(&self, out: &mut String, prefix: &str, path: &str, object: &'a SchemaObject, defaults: Option<&Value>) {
+        let fields = self.fields(object);
+        let key = |name: &str| if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) };
+
+        for field in fields.iter().filter(|f| !Self::is_table(f.object) && self.item_table(f.object).is_none()) {
+            let default = field.default.or_else(|| defaults.and_then(|d| d.get(field.name)).filter(|v| !v.is_null()));
+            if let Some(description) = field.description {
+                comment(out, prefix, description);
+            }
+            let variants = variants(field.object);
+            if !variants.is_empty() {
+                let _ = writeln!(out, "{}# One of: {}", prefix, variants.join(", "));
+            }
+            match default {
+                Some(value) => {
+                    let _ = writeln!(out, "{}{} = {}", prefix, field.name, format_value(value));
+                }
+                None if field.required && !field.nullable => {
+                    let _ = writeln!(out, "{}{} = {}  # required", prefix, field.name, placeholder(field.object, &variants));
+                }
+                None => {
+                    let _ = writeln!(out, "{}# {} = {}", prefix, field.name, placeholder(field.object, &variants));
+                }
+            }
+        }
+
+        for field in &fields {
+            let heading = if let Some(item) = self.item_table(field.object) {
+                Some((format!("[[{}]]", key(field.name)), item, true))
+            } else if Self::is_table(field.object) {
+                Some((format!("[{}]", key(field.name)), field.object, field.nullable))
+            } else {
+                None
+            };
+            let Some((heading, table, commented)) = heading else {
+                continue;
+            };
+
+            out.push('\n');
+            if let Some(description) = field.description {
+                comment(out, prefix, description);
+            }
+            let prefix = if commented { format!("{}# ", prefix) } else { prefix.to_string() };
+            let _ = writeln!(out, "{}{}", prefix, heading);
+            let defaults = field.default.or_else(|| defaults.and_then(|d| d.get(field.name)));
+            self.table(out, &prefix, &key(field.name), table, defaults);
+        }
+    }
+}
+
+/// Type of schemas the renderer cannot follow, rendered as a plain value
+fn unknown() -> &'static SchemaObject {
+    static UNKNOWN: OnceLock<SchemaObject> = OnceLock::new();
+    UNKNOWN.get_or_init(SchemaObject::default)
+}
+
+fn is_null(schema: &Schema) -> bool {
+    matches!(schema, Schema::Object(SchemaObject { instance_type: Some(SingleOrVec::Single(t)), .. }) if **t == InstanceType::Null)
+}
+
+/// Values of a unit enum, with or without documented variants
+fn variants(object: &SchemaObject) -> Vec<String> {
+    let values = object.enum_values.iter().flatten().chain(
+        object.subschemas.iter()
+            .flat_map(|s| s.one_of.iter().flatten())
+            .filter_map(|variant| match variant {
+                Schema::Object(variant) => variant.enum_values.as_ref(),
+                Schema::Bool(_) => None,
+            })
+            .flatten(),
+    );
+    values.filter_map(|v| v.as_str().map(str::to_string)).collect()
+}
+
+/// Value of a field without default, by type
+fn placeholder(object: &SchemaObject, variants: &[String]) -> String {
+    if let Some(first) = variants.first() {
+        return format!("{:?}", first);
+    }
+    let instance_type = match &object.instance_type {
+        Some(SingleOrVec::Single(t)) => Some(**t),
+        Some(SingleOrVec::Vec(types)) => types.iter().copied().find(|t| *t != InstanceType::Null),
+        None => None,
+    };
+    match (instance_type, object.format.as_deref()) {
+        (Some(InstanceType::String), Some("date-time")) => "\"2024-01-01T00:00:00Z\"".to_string(),
+        (Some(InstanceType::String), Some("partial-date-time")) => "\"00:00:00\"".to_string(),
+        (Some(InstanceType::Boolean), _) => "false".to_string(),
+        (Some(InstanceType::Integer), _) => "0".to_string(),
+        (Some(InstanceType::Number), _) => "0.0".to_string(),
+        (Some(InstanceType::Array), _) => "[]".to_string(),
+        (Some(InstanceType::Object), _) => "{}".to_string(),
+        _ => "\"\"".to_string(),
+    }
+}
+
+/// TOML form of a default value, on a single line
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            format!("[{}]", items.iter().map(format_value).collect::<Vec<_>>().join(", "))
+        }
+        Value::Object(entries) if entries.is_empty() => "{}".to_string(),
+        Value::Object(entries) => {
+            let entries: Vec<String> = entries.iter()
+                .map(|(key, value)| format!("{} = {}", format_key(key), format_value(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        // JSON string escapes are valid in TOML basic strings
+        _ => value.to_string(),
+    }
+}
+
+fn format_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        Value::from(key).to_string()
+    }
+}
+
+/// Write `text` as comment lines, wrapped and with paragraphs kept apart
+fn comment(out: &mut String, prefix: &str, text: &str) {
+    for (i, paragraph) in text.split("\n\n").enumerate() {
+        if i > 0 {
+            let _ = writeln!(out, "{}#", prefix);
+        }
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && prefix.len() + 2 + line.len() + 1 + word.len() > WIDTH {
+                let _ = writeln!(out, "{}# {}", prefix, line);
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        let _ = writeln!(out, "{}# {}", prefix, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    /// Test configuration
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct Config {
+        /// Connection settings
+        server: Server,
+        /// Optional export
+        #[serde(default)]
+        export: Option<Export>,
+        /// Static routes
+        #[serde(default)]
+        routes: Vec<Route>,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct Server {
+        /// Address to connect to
+        address: String,
+        /// Port to connect to
+        #[serde(default = "default_port")]
+        port: u16,
+        /// Connection mode
+        #[serde(default)]
+        mode: Mode,
+        #[serde(default)]
+        tag: Option<String>,
+    }
+
+    #[derive(Default, Serialize, Deserialize, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    enum Mode {
+        #[default]
+        Plain,
+        Tls,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct Export {
+        #[serde(default)]
+        enabled: bool,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct Route {
+        network: String,
+    }
+
+    fn default_port() -> u16 {
+        6379
+    }
+
+    #[test]
+    fn test_render() {
+        let template = render(&schemars::schema_for!(Config), "Test configuration");
+        assert_eq!(template, "\
+# Test configuration
+
+# Connection settings
+[server]
+# Address to connect to
+address = \"\"  # required
+# Port to connect to
+port = 6379
+# Connection mode
+# One of: plain, tls
+mode = \"plain\"
+# tag = \"\"
+
+# Optional export
+# [export]
+# enabled = false
+
+# Static routes
+# [[routes]]
+# network = \"\"  # required
+");
+        // The uncommented part loads as is
+        assert!(toml::from_str::<Config>(&template).is_ok());
+    }
+
+    #[test]
+    fn test_comment_wrapping() {
+        let mut out = String::new();
+        comment(&mut out, "# ", &format!("{}\n\nSecond paragraph", "word ".repeat(20)));
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines.iter().all(|line| line.len() <= WIDTH));
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[2], "# #");
+        assert_eq!(lines[3], "# # Second paragraph");
+    }
+}
@@ -0,0 +1,47 @@
+//! Frame timestamps
+//!
+//! Timestamps are written as RFC 3339 with all nine fractional digits, so
+//! nanosecond capture times survive the stream and every timestamp of the
+//! schema has the same width. Any RFC 3339 precision is accepted on read.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Serialize with nanosecond precision (`#[serde(with = "...")]`)
+pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
+/// Deserialize an RFC 3339 timestamp of any precision
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    DateTime::<Utc>::deserialize(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Stamped {
+        #[serde(with = "crate::timestamp")]
+        at: DateTime<Utc>,
+    }
+
+    #[test]
+    fn test_nanosecond_round_trip() {
+        let at = DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+        let json = serde_json::to_string(&Stamped { at }).unwrap();
+        assert_eq!(json, r#"{"at":"2023-11-14T22:13:20.123456789Z"}"#);
+        assert_eq!(serde_json::from_str::<Stamped>(&json).unwrap().at, at);
+
+        // Whole seconds keep their fractional digits
+        let json = serde_json::to_string(&Stamped { at: DateTime::from_timestamp(1_700_000_000, 0).unwrap() }).unwrap();
+        assert!(json.contains("20.000000000Z"));
+
+        // Older writers' microsecond timestamps still parse
+        let parsed: Stamped = serde_json::from_str(r#"{"at":"2023-11-14T22:13:20.123456Z"}"#).unwrap();
+        assert_eq!(parsed.at.timestamp_subsec_nanos(), 123_456_000);
+    }
+}